@@ -1,4 +1,29 @@
+use std::path::Path;
+use std::process::Command;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::compile_protos("proto/gatehouse.proto")?;
+
+    // the commit the server was built from, for GetServerInfo
+    if let Some(hash) = git_hash() {
+        println!("cargo:rustc-env=GATEHOUSE_GIT_HASH={hash}");
+    }
+    // every commit and checkout is appended to the log of HEAD
+    if Path::new(".git/logs/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/logs/HEAD");
+    }
     Ok(())
 }
+
+/// The short hash of HEAD, if this is a git checkout
+fn git_hash() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()?;
+    let hash = String::from_utf8(output.stdout).ok()?;
+    match output.status.success() && !hash.trim().is_empty() {
+        true => Some(hash.trim().to_string()),
+        false => None,
+    }
+}
@@ -0,0 +1,55 @@
+// Helpers to quickly build requests and make checks against a Gatehouse server.
+//
+// Written by `gatecli sdk go` next to the generated protobuf code.
+package gatehouse
+
+import (
+	"context"
+
+	"google.golang.org/grpc"
+	"google.golang.org/grpc/credentials/insecure"
+)
+
+// Dial connects to a Gatehouse server, e.g. "localhost:6174".
+func Dial(addr string) (GatehouseClient, *grpc.ClientConn, error) {
+	conn, err := grpc.Dial(addr, grpc.WithTransportCredentials(insecure.NewCredentials()))
+	if err != nil {
+		return nil, nil, err
+	}
+	return NewGatehouseClient(conn), conn, nil
+}
+
+// ToAttribs converts a map of attribute names to values into what the server expects.
+func ToAttribs(attribs map[string][]string) map[string]*AttributeValues {
+	out := make(map[string]*AttributeValues, len(attribs))
+	for name, vals := range attribs {
+		out[name] = &AttributeValues{Values: vals}
+	}
+	return out
+}
+
+// NewCheckRequest builds a check of whether an actor can take an action on a target.
+// Append to TargetAction to check several actions at once.
+func NewCheckRequest(actorName, actorType string, actorAttribs map[string][]string,
+	targetName, targetType, action string, envAttribs map[string][]string) *CheckRequest {
+	return &CheckRequest{
+		Actor: &Actor{
+			Name:       actorName,
+			Typestr:    actorType,
+			Attributes: ToAttribs(actorAttribs),
+		},
+		EnvAttributes: ToAttribs(envAttribs),
+		TargetName:    targetName,
+		TargetType:    targetType,
+		TargetAction:  []string{action},
+	}
+}
+
+// IsAllowed makes a check and reports whether the decision was ALLOW.
+func IsAllowed(ctx context.Context, client GatehouseClient, req *CheckRequest) (bool, error) {
+	resp, err := client.Check(ctx, req)
+	if err != nil {
+		return false, err
+	}
+	return resp.GetDecision() == DECIDE_ALLOW, nil
+}
@@ -0,0 +1,50 @@
+"""Helpers to quickly build requests and make checks against a Gatehouse server.
+
+Written by `gatecli sdk python` next to the generated protobuf code.
+"""
+
+import grpc
+
+import actors_pb2
+import common_pb2
+import gatehouse_pb2
+import gatehouse_pb2_grpc
+import policies_pb2
+
+
+def connect(addr="localhost:6174"):
+    """Connect to a Gatehouse server"""
+    return gatehouse_pb2_grpc.GatehouseStub(grpc.insecure_channel(addr))
+
+
+def to_attribs(attribs):
+    """Convert a dict of attribute names to values into what the server expects"""
+    return {
+        name: common_pb2.AttributeValues(values=list(vals))
+        for name, vals in (attribs or {}).items()
+    }
+
+
+def check_request(actor_name, actor_type, target_name, target_type, action,
+                  actor_attribs=None, env_attribs=None):
+    """Build a check of whether an actor can take an action on a target
+
+    `action` can also be a list of actions that must all be allowed.
+    """
+    actions = [action] if isinstance(action, str) else list(action)
+    return gatehouse_pb2.CheckRequest(
+        actor=actors_pb2.Actor(
+            name=actor_name,
+            typestr=actor_type,
+            attributes=to_attribs(actor_attribs),
+        ),
+        env_attributes=to_attribs(env_attribs),
+        target_name=target_name,
+        target_type=target_type,
+        target_action=actions,
+    )
+
+
+def is_allowed(stub, req):
+    """Make a check and report whether the decision was ALLOW"""
+    return stub.check(req).decision == policies_pb2.ALLOW
@@ -0,0 +1,59 @@
+// Helpers to quickly build requests and make checks against a Gatehouse server.
+//
+// Written by `gatecli sdk typescript` next to the code generated by ts-proto.
+import { credentials } from "@grpc/grpc-js";
+
+import { Actor } from "./actors";
+import { AttributeValues } from "./common";
+import { ACTION_MODE, CheckRequest, GatehouseClient } from "./gatehouse";
+import { DECIDE } from "./policies";
+
+/** Connect to a Gatehouse server */
+export function connect(addr = "localhost:6174"): GatehouseClient {
+  return new GatehouseClient(addr, credentials.createInsecure());
+}
+
+/** Convert a map of attribute names to values into what the server expects */
+export function toAttribs(
+  attribs: Record<string, string[]> = {},
+): Record<string, AttributeValues> {
+  const out: Record<string, AttributeValues> = {};
+  for (const [name, values] of Object.entries(attribs)) {
+    out[name] = { values };
+  }
+  return out;
+}
+
+/** Build a check of whether an actor can take an action (or several actions) on a target */
+export function checkRequest(
+  actor: { name: string; typestr: string; attribs?: Record<string, string[]> },
+  target: { name: string; typestr: string; action: string | string[] },
+  envAttribs?: Record<string, string[]>,
+): CheckRequest {
+  const checkActor: Actor = {
+    name: actor.name,
+    typestr: actor.typestr,
+    attributes: toAttribs(actor.attribs),
+  };
+  return {
+    actor: checkActor,
+    envAttributes: toAttribs(envAttribs),
+    targetName: target.name,
+    targetType: target.typestr,
+    targetAction: typeof target.action === "string" ? [target.action] : target.action,
+    actionMode: ACTION_MODE.ALL_ACTIONS,
+  };
+}
+
+/** Make a check and report whether the decision was ALLOW */
+export function isAllowed(client: GatehouseClient, req: CheckRequest): Promise<boolean> {
+  return new Promise((resolve, reject) => {
+    client.check(req, (err, resp) => {
+      if (err) {
+        reject(err);
+      } else {
+        resolve(resp.decision === DECIDE.ALLOW);
+      }
+    });
+  });
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
@@ -16,10 +18,17 @@ pub enum ActorCmds {
 
 #[derive(Args, Debug)]
 pub struct ActorCmdAddArgs {
-    #[arg(help = "Type (case-insensitive)")]
-    pub typestr: String,
-    #[arg(help = "Name (case-insensitive)")]
-    pub name: String,
+    #[arg(help = "Type (case-insensitive)", required_unless_present = "file")]
+    pub typestr: Option<String>,
+    #[arg(help = "Name (case-insensitive)", required_unless_present = "file")]
+    pub name: Option<String>,
+    #[arg(
+        long,
+        short = 'f',
+        conflicts_with_all = ["typestr", "name"],
+        help = "JSON or CSV file of actors to add instead of one"
+    )]
+    pub file: Option<PathBuf>,
     #[arg(
         long,
         short = 't',
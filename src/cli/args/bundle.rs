@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+#[derive(Args, Debug)]
+pub struct BundleKeygenArgs {
+    #[arg(long, short = 'o', help = "File to write the secret key to")]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct BundleExportArgs {
+    #[arg(long, help = "File holding the secret key to sign the bundle with")]
+    pub key: PathBuf,
+    #[arg(long, short = 'o', help = "File to write the signed bundle to")]
+    pub output: PathBuf,
+    #[arg(
+        long,
+        help = "Sequence to sign the bundle with; must be higher than that of the last bundle applied [default: seconds since the epoch]"
+    )]
+    pub sequence: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+pub struct BundleApplyArgs {
+    #[arg(long, short = 'f', help = "Signed bundle to apply")]
+    pub file: PathBuf,
+    #[arg(
+        long,
+        help = "Base64 public key to verify the bundle with before sending it"
+    )]
+    pub public_key: Option<String>,
+    #[arg(
+        long,
+        help = "Show the changes the bundle would make without making them"
+    )]
+    pub dry_run: bool,
+}
@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+#[derive(Args, Debug)]
+pub struct DslImportArgs {
+    #[arg(long, short = 'f', help = "File of policies written in the policy DSL")]
+    pub file: PathBuf,
+    #[arg(long, help = "Validate the compiled policies without adding them")]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DslExportArgs {
+    #[arg(long, help = "Only export the policy with this name")]
+    pub name: Option<String>,
+    #[arg(
+        long,
+        short = 'o',
+        help = "File to write the policies to, instead of stdout"
+    )]
+    pub output: Option<PathBuf>,
+}
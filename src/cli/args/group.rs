@@ -0,0 +1,32 @@
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+pub struct Group {
+    #[clap(subcommand)]
+    pub group_cmds: GroupCmds,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GroupCmds {
+    Search(GroupCmdSearchArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct GroupCmdSearchArgs {
+    #[arg(help = "Name (case-insensitive)", required = false)]
+    pub name: Option<String>,
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["TYPE", "NAME"],
+        help = "Only groups with this member"
+    )]
+    pub member: Option<Vec<String>>,
+    #[arg(long, help = "Only groups granting this role")]
+    pub role: Option<String>,
+    #[arg(
+        long,
+        help = "Leave out the members of each group, showing only their counts by type"
+    )]
+    pub summary: bool,
+}
@@ -1,17 +1,54 @@
 use clap::{Parser, Subcommand};
 
 mod actor;
+mod bundle;
+mod dsl;
+mod group;
+mod policy;
+mod schema;
+mod sdk;
+mod spicedb;
 mod target;
+mod test;
+mod xacml;
 
 pub use actor::*;
+pub use bundle::*;
+pub use dsl::*;
+pub use group::*;
+pub use policy::*;
+pub use schema::*;
+pub use sdk::*;
+pub use spicedb::*;
 pub use target::*;
+pub use test::*;
+pub use xacml::*;
 
 #[derive(Parser, Debug)]
 pub struct Arguments {
-    #[arg(long, default_value_t = String::from("localhost"))]
-    pub host: String,
-    #[arg(long, default_value_t = 6174)]
-    pub port: u32,
+    #[arg(long, help = "Server host [default: the profile's, or localhost]")]
+    pub host: Option<String>,
+    #[arg(long, help = "Server port [default: the profile's, or 6174]")]
+    pub port: Option<u32>,
+    #[arg(
+        long,
+        help = "Profile in ~/.config/gatehouse/config.toml to connect with [default: default]"
+    )]
+    pub profile: Option<String>,
+    #[arg(long, help = "gzip-compress requests")]
+    pub compress: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Print no errors; the exit code tells what went wrong"
+    )]
+    pub quiet: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Print errors to stderr as JSON lines with the error kind, exit code, and message"
+    )]
+    pub json_errors: bool,
 
     #[clap(subcommand)]
     pub command: Commands,
@@ -23,4 +60,73 @@ pub enum Commands {
     Actor(Actor),
     #[clap(name = "targets")]
     Target(Target),
+    #[clap(name = "groups")]
+    Group(Group),
+    #[clap(name = "policies")]
+    Policy(Policy),
+    #[clap(
+        name = "coverage",
+        about = "Replay recorded checks to find unmatched policies"
+    )]
+    Coverage,
+    #[clap(
+        name = "reload-state",
+        about = "Have the server load everything again from its storage backend"
+    )]
+    ReloadState,
+    #[clap(
+        name = "info",
+        about = "Print the server's version, how it was built, and what it holds"
+    )]
+    Info,
+    #[clap(
+        name = "test",
+        about = "Run policy test cases and report which pass or fail"
+    )]
+    Test(TestArgs),
+    #[clap(
+        name = "import-xacml",
+        about = "Convert XACML 3.0 policies into Gatehouse policies and add them"
+    )]
+    ImportXacml(XacmlArgs),
+    #[clap(
+        name = "export-spicedb",
+        about = "Export group memberships and role grants as a SpiceDB schema and relationships"
+    )]
+    ExportSpiceDb(SpiceDbArgs),
+    #[clap(
+        name = "import-dsl",
+        about = "Compile policies written in the policy DSL and add them"
+    )]
+    ImportDsl(DslImportArgs),
+    #[clap(
+        name = "export-dsl",
+        about = "Export policies written in the policy DSL"
+    )]
+    ExportDsl(DslExportArgs),
+    #[clap(
+        name = "bundle-keygen",
+        about = "Generate a key to sign policy bundles with"
+    )]
+    BundleKeygen(BundleKeygenArgs),
+    #[clap(
+        name = "export-bundle",
+        about = "Sign the server's policies into a bundle"
+    )]
+    ExportBundle(BundleExportArgs),
+    #[clap(
+        name = "apply-bundle",
+        about = "Make the server's policies those of a signed bundle"
+    )]
+    ApplyBundle(BundleApplyArgs),
+    #[clap(
+        name = "sdk",
+        about = "Generate a client with helpers for another language"
+    )]
+    Sdk(SdkArgs),
+    #[clap(
+        name = "schemas",
+        about = "List the server's JSON Schemas for documents, or print some of them"
+    )]
+    Schemas(SchemaArgs),
 }
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+pub struct Policy {
+    #[clap(subcommand)]
+    pub policy_cmds: PolicyCmds,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PolicyCmds {
+    #[clap(about = "Show a stored policy")]
+    Show(PolicyCmdShowArgs),
+    #[clap(about = "Show what differs between a stored policy and a local definition of it")]
+    Diff(PolicyCmdDiffArgs),
+    #[clap(about = "List the policies that take longest to evaluate in checks")]
+    Slowest(PolicyCmdSlowestArgs),
+    #[clap(about = "Find policies by their tags, decision, target type, name, or description")]
+    Find(PolicyCmdFindArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct PolicyCmdShowArgs {
+    #[arg(help = "Name of the policy")]
+    pub name: String,
+    #[arg(
+        long,
+        help = "Show the rule as an indented tree instead of YAML in the file storage format"
+    )]
+    pub pretty: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct PolicyCmdSlowestArgs {
+    #[arg(long, default_value_t = 10, help = "How many policies to list")]
+    pub top: u32,
+}
+
+#[derive(Args, Debug)]
+pub struct PolicyCmdFindArgs {
+    #[arg(help = "Words that must all be in the policy's name or description")]
+    pub search: Option<String>,
+    #[arg(
+        long = "tag",
+        help = "Tag the policy must have; can be given more than once"
+    )]
+    pub tags: Vec<String>,
+    #[arg(long, help = "Decision the policy must make, e.g. allow or deny")]
+    pub decision: Option<String>,
+    #[arg(long, help = "Target type the policy must be able to apply to")]
+    pub target_type: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct PolicyCmdDiffArgs {
+    #[arg(help = "Name of the policy")]
+    pub name: String,
+    #[arg(
+        long,
+        short = 'f',
+        help = "JSON or YAML file of the policy, in the file storage format, or a directory of them"
+    )]
+    pub file: PathBuf,
+}
@@ -0,0 +1,7 @@
+use clap::Args;
+
+#[derive(Args, Debug)]
+pub struct SchemaArgs {
+    #[arg(help = "Schemas to print; lists the schemas there are if none are given")]
+    pub names: Vec<String>,
+}
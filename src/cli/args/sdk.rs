@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SdkLang {
+    Go,
+    Python,
+    Typescript,
+}
+
+#[derive(Args, Debug)]
+pub struct SdkArgs {
+    #[arg(value_enum, help = "Language to generate a client for")]
+    pub lang: SdkLang,
+    #[arg(
+        long,
+        short = 'o',
+        default_value = "gatehouse-sdk",
+        help = "Directory to write the client to"
+    )]
+    pub out: PathBuf,
+    #[arg(
+        long,
+        default_value = "gatehouse",
+        help = "Go import path for the generated package"
+    )]
+    pub go_package: String,
+    #[arg(
+        long,
+        help = "Only write the .proto files and helpers, and print the protoc command to run"
+    )]
+    pub no_protoc: bool,
+}
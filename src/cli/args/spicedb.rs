@@ -0,0 +1,13 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+#[derive(Args, Debug)]
+pub struct SpiceDbArgs {
+    #[arg(
+        long,
+        short = 'o',
+        help = "File to write the SpiceDB validation file to, instead of stdout"
+    )]
+    pub output: Option<PathBuf>,
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
@@ -16,10 +18,17 @@ pub enum TargetCmds {
 
 #[derive(Args, Debug)]
 pub struct TargetCmdAddArgs {
-    #[arg(help = "Type (case-insensitive)")]
-    pub typestr: String,
-    #[arg(help = "Name (case-insensitive)")]
-    pub name: String,
+    #[arg(help = "Type (case-insensitive)", required_unless_present = "file")]
+    pub typestr: Option<String>,
+    #[arg(help = "Name (case-insensitive)", required_unless_present = "file")]
+    pub name: Option<String>,
+    #[arg(
+        long,
+        short = 'f',
+        conflicts_with_all = ["typestr", "name"],
+        help = "JSON or CSV file of targets to add instead of one"
+    )]
+    pub file: Option<PathBuf>,
     #[arg(
         long,
         short = 'a',
@@ -34,6 +43,13 @@ pub struct TargetCmdAddArgs {
         help = "Attribute of format '{key}:{val1},{val2},{val3}'"
     )]
     pub attribs: Vec<String>,
+    #[arg(
+        long = "group",
+        short = 'g',
+        required = false,
+        help = "Action group of format '{name}:{action1},{action2},{action3}'"
+    )]
+    pub action_groups: Vec<String>,
 }
 
 #[derive(Args, Debug)]
@@ -66,6 +82,18 @@ pub struct TargetCmdModifyArgs {
         help = "Attributes to remove. Attribute of format '{key}:{val1},{val2},{val3}'"
     )]
     pub remove_attribs: Vec<String>,
+    #[arg(
+        long = "ag",
+        required = false,
+        help = "Action groups to add or replace. Group of format '{name}:{action1},{action2}'"
+    )]
+    pub add_action_groups: Vec<String>,
+    #[arg(
+        long = "rg",
+        required = false,
+        help = "Action groups to remove. Repeat arg to specify multiple groups"
+    )]
+    pub remove_action_groups: Vec<String>,
 }
 
 #[derive(Args, Debug)]
@@ -0,0 +1,18 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+#[derive(Args, Debug)]
+pub struct TestArgs {
+    #[arg(
+        long,
+        short = 'f',
+        help = "Test file, or directory of .yaml test files, to run"
+    )]
+    pub file: PathBuf,
+    #[arg(
+        long,
+        help = "Policy file, or directory of them, to test instead of the server's policies"
+    )]
+    pub policies: Option<PathBuf>,
+}
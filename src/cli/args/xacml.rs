@@ -0,0 +1,15 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+#[derive(Args, Debug)]
+pub struct XacmlArgs {
+    #[arg(
+        long,
+        short = 'f',
+        help = "XACML 3.0 Policy or PolicySet file to import"
+    )]
+    pub file: PathBuf,
+    #[arg(long, help = "Validate the converted policies without adding them")]
+    pub dry_run: bool,
+}
@@ -2,22 +2,85 @@ extern crate clap;
 
 use clap::Parser;
 
-use cmds::{add_actor, get_actors, get_targets, modify_actor, remove_actor};
+use cmds::{
+    add_actor, apply_bundle, bundle_keygen, coverage_report, diff_policy, export_bundle,
+    export_dsl, export_spicedb, find_policies, generate_sdk, get_actors, get_groups, get_schemas,
+    get_targets, import_dsl, import_xacml, modify_actor, reload_state, remove_actor, server_info,
+    show_policy, slowest_policies, test_policies,
+};
+use gatehouse::profile::{self, Profile, DEFAULT_PROFILE};
 use gatehouse::proto::base::gatehouse_client::GatehouseClient;
+use tonic::codec::CompressionEncoding;
+use tonic::transport::Channel;
 
 mod args;
 mod cmds;
+mod error;
 
-use crate::args::{ActorCmds, Arguments, Commands, TargetCmds};
-use crate::cmds::{add_target, modify_target, remove_target};
+use crate::args::{ActorCmds, Arguments, Commands, GroupCmds, PolicyCmds, TargetCmds};
+use crate::cmds::{add_target, modify_target, remove_target, ApiKey};
+use crate::error::{fail, Error, Kind, Output};
 
 #[tokio::main]
 async fn main() {
     let args = Arguments::parse();
+    error::configure(Output {
+        quiet: args.quiet,
+        json: args.json_errors,
+    });
 
-    let mut client = GatehouseClient::connect(format!("http://{}:{}", args.host, args.port))
-        .await
-        .expect("Could not create client");
+    // generating a client doesn't need a server
+    if let Commands::Sdk(args) = args.command {
+        return generate_sdk(args);
+    }
+    // nor does generating a key
+    if let Commands::BundleKeygen(args) = args.command {
+        return bundle_keygen(args);
+    }
+
+    let profile = match load_profile(args.profile.as_deref()) {
+        Ok(profile) => profile,
+        Err(err) => fail(Error::new(Kind::Usage, err)),
+    };
+    if profile.tls {
+        fail(Error::new(
+            Kind::Usage,
+            "gatecli can't connect with TLS yet; set tls = false to connect without it",
+        ));
+    }
+    let host = args
+        .host
+        .or(profile.host)
+        .unwrap_or_else(|| String::from("localhost"));
+    let port = args.port.or(profile.port).unwrap_or(6174);
+    let api_key = match profile.token.map(|token| token.parse()).transpose() {
+        Ok(api_key) => ApiKey(api_key),
+        Err(_) => fail(Error::new(
+            Kind::Usage,
+            "The profile's token can't be sent as metadata",
+        )),
+    };
+
+    let channel = match Channel::from_shared(format!("http://{host}:{port}")) {
+        Ok(endpoint) => endpoint,
+        Err(err) => fail(Error::new(
+            Kind::Usage,
+            format!("Invalid server address: {err}"),
+        )),
+    };
+    let channel = match channel.connect().await {
+        Ok(channel) => channel,
+        Err(err) => fail(Error::new(
+            Kind::Connection,
+            format!("Could not connect to {host}:{port}: {err}"),
+        )),
+    };
+    // responses are taken compressed whenever the server sends them that way
+    let mut client = GatehouseClient::with_interceptor(channel, api_key)
+        .accept_compressed(CompressionEncoding::Gzip);
+    if args.compress {
+        client = client.send_compressed(CompressionEncoding::Gzip);
+    }
 
     match args.command {
         Commands::Target(args) => match args.target_cmds {
@@ -32,5 +95,43 @@ async fn main() {
             ActorCmds::Remove(args) => remove_actor(&mut client, args).await,
             ActorCmds::Search(args) => get_actors(&mut client, args).await,
         },
+        Commands::Group(args) => match args.group_cmds {
+            GroupCmds::Search(args) => get_groups(&mut client, args).await,
+        },
+        Commands::Policy(args) => match args.policy_cmds {
+            PolicyCmds::Show(args) => show_policy(&mut client, args).await,
+            PolicyCmds::Diff(args) => diff_policy(&mut client, args).await,
+            PolicyCmds::Slowest(args) => slowest_policies(&mut client, args).await,
+            PolicyCmds::Find(args) => find_policies(&mut client, args).await,
+        },
+        Commands::Coverage => coverage_report(&mut client).await,
+        Commands::ReloadState => reload_state(&mut client).await,
+        Commands::Info => server_info(&mut client).await,
+        Commands::Test(args) => test_policies(&mut client, args).await,
+        Commands::ImportXacml(args) => import_xacml(&mut client, args).await,
+        Commands::ExportSpiceDb(args) => export_spicedb(&mut client, args).await,
+        Commands::ImportDsl(args) => import_dsl(&mut client, args).await,
+        Commands::ExportDsl(args) => export_dsl(&mut client, args).await,
+        Commands::ExportBundle(args) => export_bundle(&mut client, args).await,
+        Commands::ApplyBundle(args) => apply_bundle(&mut client, args).await,
+        Commands::Schemas(args) => get_schemas(&mut client, args).await,
+        Commands::Sdk(_) | Commands::BundleKeygen(_) => unreachable!(),
+    }
+}
+
+/// The profile asked for, or the default one if the config file has it
+fn load_profile(name: Option<&str>) -> Result<Profile, String> {
+    let path = match profile::default_path() {
+        Some(path) => path,
+        None if name.is_some() => return Err(String::from("Could not find the config file")),
+        None => return Ok(Profile::default()),
+    };
+    let mut profiles = profile::load(&path)?;
+
+    match name {
+        Some(name) => profiles
+            .remove(name)
+            .ok_or_else(|| format!("No profile {name} in {}", path.display())),
+        None => Ok(profiles.remove(DEFAULT_PROFILE).unwrap_or_default()),
     }
 }
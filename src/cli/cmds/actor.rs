@@ -1,54 +1,90 @@
-use tonic::transport::Channel;
+use std::path::Path;
 
-use gatehouse::helpers;
-use gatehouse::proto::base::gatehouse_client::GatehouseClient;
+use gatehouse::bulk::load_actors;
+use gatehouse::helpers::{self, AddActorRequestBuilder, ModifyActorRequestBuilder};
 
 use crate::args::{ActorCmdAddArgs, ActorCmdModifyArgs, ActorCmdRemoveArgs, ActorCmdSearchArgs};
+use crate::error::{fail, report, Error, Kind};
 
-use super::form_attributes;
+use super::{form_attributes, Client};
 
-pub async fn add_actor(client: &mut GatehouseClient<Channel>, args: ActorCmdAddArgs) {
-    let attributes = form_attributes(&args.attribs);
+pub async fn add_actor(client: &mut Client, args: ActorCmdAddArgs) {
+    if let Some(ref file) = args.file {
+        return add_actors(client, file).await;
+    }
+    let (name, typestr) = (
+        args.name.unwrap_or_default(),
+        args.typestr.unwrap_or_default(),
+    );
+    let mut req = AddActorRequestBuilder::new(&typestr, &name);
+    for (key, vals) in form_attributes(&args.attribs) {
+        req = req.attribute(&key, &vals);
+    }
 
-    match helpers::add_actor(client, &args.name, &args.typestr, attributes).await {
+    match helpers::add_actor(client, req.build()).await {
         Ok(actor) => println!("Added {actor}"),
-        Err(err) => eprintln!("Error: {err}"),
+        Err(err) => fail(err),
     }
 }
 
-pub async fn modify_actor(client: &mut GatehouseClient<Channel>, args: ActorCmdModifyArgs) {
-    let add_attributes = form_attributes(&args.add_attribs);
-    let remove_attributes = form_attributes(&args.remove_attribs);
-
-    match helpers::modify_actor(
-        client,
-        &args.name,
-        &args.typestr,
-        add_attributes,
-        remove_attributes,
-    )
-    .await
-    {
+/// Add the actors in a file, reporting the ones that couldn't be added
+async fn add_actors(client: &mut Client, file: &Path) {
+    let actors = match load_actors(file) {
+        Ok(actors) => actors,
+        Err(err) => fail(Error::new(Kind::Usage, err)),
+    };
+    let names: Vec<String> = actors
+        .iter()
+        .map(|actor| format!("{}/{}", actor.typestr, actor.name))
+        .collect();
+
+    let results = match helpers::add_actors(client, actors).await {
+        Ok(results) => results,
+        Err(err) => fail(err),
+    };
+    let mut failed = 0;
+    for (name, result) in names.iter().zip(&results) {
+        if !result.error.is_empty() {
+            report(format!("Could not add {name}: {}", result.error));
+            failed += 1;
+        }
+    }
+    println!("Added {} of {} actors", results.len() - failed, names.len());
+    if failed > 0 {
+        Kind::Failed.exit();
+    }
+}
+
+pub async fn modify_actor(client: &mut Client, args: ActorCmdModifyArgs) {
+    let mut req = ModifyActorRequestBuilder::new(&args.typestr, &args.name);
+    for (key, vals) in form_attributes(&args.add_attribs) {
+        req = req.add_attribute(&key, &vals);
+    }
+    for (key, vals) in form_attributes(&args.remove_attribs) {
+        req = req.remove_attribute(&key, &vals);
+    }
+
+    match helpers::modify_actor(client, req.build()).await {
         Ok(actor) => println!("Updated {actor}"),
-        Err(err) => eprintln!("Error: {err}"),
+        Err(err) => fail(err),
     }
 }
 
-pub async fn get_actors(client: &mut GatehouseClient<Channel>, args: ActorCmdSearchArgs) {
-    match helpers::get_actors(client, args.name, args.typestr).await {
+pub async fn get_actors(client: &mut Client, args: ActorCmdSearchArgs) {
+    match helpers::get_actors(client, args.typestr, args.name).await {
         Ok(actors) => {
             println!("Got {} actors:", actors.len());
             for actor in actors {
                 println!("{actor}");
             }
         }
-        Err(err) => println!("Error: {err}"),
+        Err(err) => fail(err),
     }
 }
 
-pub async fn remove_actor(client: &mut GatehouseClient<Channel>, args: ActorCmdRemoveArgs) {
-    match helpers::remove_actor(client, &args.name, &args.typestr).await {
+pub async fn remove_actor(client: &mut Client, args: ActorCmdRemoveArgs) {
+    match helpers::remove_actor(client, &args.typestr, &args.name).await {
         Ok(actor) => println!("Removed {actor}"),
-        Err(err) => eprintln!("Error: {err}"),
+        Err(err) => fail(err),
     }
 }
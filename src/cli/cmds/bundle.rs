@@ -0,0 +1,99 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+
+use gatehouse::bundle;
+use gatehouse::proto::base::ApplyBundleRequest;
+use gatehouse::proto::policies::{GetPoliciesRequest, SignedBundle};
+
+use crate::args::{BundleApplyArgs, BundleExportArgs, BundleKeygenArgs};
+
+use super::Client;
+use crate::error::{fail, Error, Kind};
+
+/// Generate a key to sign bundles with, writing the secret key to a file and printing the
+/// public key
+pub fn bundle_keygen(args: BundleKeygenArgs) {
+    let mut secret = [0u8; 32];
+    if let Err(err) = File::open("/dev/urandom").and_then(|mut file| file.read_exact(&mut secret)) {
+        fail(format!("Could not generate a key: {err}"));
+    }
+
+    if let Err(err) = fs::write(&args.output, format!("{}\n", base64::encode(secret))) {
+        fail(format!("Could not write {}: {err}", args.output.display()));
+    }
+    println!("Public key: {}", bundle::public_key(&secret));
+}
+
+/// Sign the server's policies and write them to a bundle file
+pub async fn export_bundle(client: &mut Client, args: BundleExportArgs) {
+    let secret = match fs::read_to_string(&args.key)
+        .map_err(|err| format!("Could not read {}: {err}", args.key.display()))
+        .and_then(|text| bundle::parse_secret_key(&text))
+    {
+        Ok(secret) => secret,
+        Err(err) => fail(Error::new(Kind::Usage, err)),
+    };
+
+    let rules = match client.get_policies(GetPoliciesRequest::default()).await {
+        Ok(resp) => resp.into_inner().rules,
+        Err(err) => fail(Error::status("Could not get policies", err)),
+    };
+
+    // seconds since the epoch only go up, so bundles signed later are newer
+    let sequence = args.sequence.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default()
+    });
+
+    let count = rules.len();
+    let signed = bundle::sign(rules, sequence, &secret);
+    if let Err(err) = fs::write(&args.output, signed.encode_to_vec()) {
+        fail(format!("Could not write {}: {err}", args.output.display()));
+    }
+    println!(
+        "Signed {count} policies into {} with sequence {sequence}",
+        args.output.display()
+    );
+}
+
+/// Apply a signed bundle, verifying it first if given the public key
+pub async fn apply_bundle(client: &mut Client, args: BundleApplyArgs) {
+    let signed = match fs::read(&args.file)
+        .map_err(|err| format!("Could not read {}: {err}", args.file.display()))
+        .and_then(|bytes| {
+            SignedBundle::decode(bytes.as_slice()).map_err(|err| format!("Bad bundle: {err}"))
+        }) {
+        Ok(signed) => signed,
+        Err(err) => fail(Error::new(Kind::Usage, err)),
+    };
+
+    if let Some(ref key) = args.public_key {
+        if let Err(err) =
+            bundle::parse_public_key(key).and_then(|key| bundle::verify(&signed, &key))
+        {
+            fail(Error::new(Kind::Invalid, err));
+        }
+    }
+
+    let req = ApplyBundleRequest {
+        bundle: Some(signed),
+        dry_run: args.dry_run,
+    };
+    let changes = match client.apply_bundle(req).await {
+        Ok(resp) => resp.into_inner().changes,
+        Err(err) => fail(Error::status("Could not apply bundle", err)),
+    };
+
+    for change in &changes {
+        println!("{} {} {}", change.op, change.kind, change.name);
+    }
+    match args.dry_run {
+        true => println!("Would make {} changes", changes.len()),
+        false => println!("Made {} changes", changes.len()),
+    }
+}
@@ -0,0 +1,27 @@
+use gatehouse::helpers;
+
+use super::Client;
+use crate::error::fail;
+
+pub async fn coverage_report(client: &mut Client) {
+    match helpers::coverage_report(client).await {
+        Ok(report) => {
+            println!("Replayed {} recorded checks", report.requests);
+            println!(
+                "{} policies never matched:",
+                report.unmatched_policies.len()
+            );
+            for name in report.unmatched_policies {
+                println!("  policy[{name}]");
+            }
+            println!(
+                "{} checks only hit the default decision:",
+                report.default_decisions.len()
+            );
+            for req in report.default_decisions {
+                println!("  {req}");
+            }
+        }
+        Err(err) => fail(err),
+    }
+}
@@ -0,0 +1,67 @@
+use std::fs;
+
+use gatehouse::dsl;
+use gatehouse::proto::policies::{AddPolicyRequest, GetPoliciesRequest};
+
+use crate::args::{DslExportArgs, DslImportArgs};
+
+use super::{warn_unknown_actions, Client};
+use crate::error::{fail, Error, Kind};
+
+/// Compile policies written in the policy DSL and add them, stopping at the first one the server
+/// rejects
+pub async fn import_dsl(client: &mut Client, args: DslImportArgs) {
+    let rules = match fs::read_to_string(&args.file)
+        .map_err(|err| format!("Could not read {}: {err}", args.file.display()))
+        .and_then(|text| dsl::parse(&text))
+    {
+        Ok(rules) => rules,
+        Err(err) => fail(Error::new(Kind::Usage, err)),
+    };
+
+    for rule in rules {
+        let name = rule.name.clone();
+        let req = AddPolicyRequest {
+            rule: Some(rule),
+            dry_run: args.dry_run,
+        };
+        match client.add_policy(req).await {
+            Ok(resp) => {
+                match args.dry_run {
+                    true => println!("Would add policy {name}"),
+                    false => println!("Added policy {name}"),
+                }
+                warn_unknown_actions(&name, resp.get_ref());
+            }
+            Err(err) => fail(Error::status(format!("Could not add policy {name}"), err)),
+        }
+    }
+}
+
+/// Export policies written in the policy DSL, warning about those that can't be
+pub async fn export_dsl(client: &mut Client, args: DslExportArgs) {
+    let req = GetPoliciesRequest {
+        name: args.name,
+        ..Default::default()
+    };
+    let printed = match client.print_policies(req).await {
+        Ok(resp) => resp.into_inner(),
+        Err(err) => fail(Error::status("Could not get policies", err)),
+    };
+    for err in &printed.unprintable {
+        eprintln!("Skipped: {err}");
+    }
+
+    let text = format!("{}\n", printed.text);
+    let result = match args.output {
+        Some(ref file) => fs::write(file, text)
+            .map_err(|err| format!("Could not write {}: {err}", file.display())),
+        None => {
+            print!("{text}");
+            Ok(())
+        }
+    };
+    if let Err(err) = result {
+        fail(err);
+    }
+}
@@ -0,0 +1,32 @@
+use gatehouse::helpers;
+
+use crate::args::GroupCmdSearchArgs;
+use crate::error::fail;
+
+use super::Client;
+
+pub async fn get_groups(client: &mut Client, args: GroupCmdSearchArgs) {
+    // clap takes exactly two values for --member: its type, then its name
+    let member = args
+        .member
+        .as_deref()
+        .map(|member| (member[0].as_str(), member[1].as_str()));
+
+    match helpers::get_groups(
+        client,
+        args.name.as_deref(),
+        member,
+        args.role.as_deref(),
+        args.summary,
+    )
+    .await
+    {
+        Ok(groups) => {
+            println!("Got {} groups:", groups.len());
+            for group in groups {
+                println!("{group}");
+            }
+        }
+        Err(err) => fail(err),
+    }
+}
@@ -1,8 +1,57 @@
 mod actor;
+mod bundle;
+mod coverage;
+mod dsl;
+mod group;
+mod policy;
+mod schema;
+mod sdk;
+mod server;
+mod spicedb;
 mod target;
+mod test;
+mod xacml;
 
 pub use actor::*;
+pub use bundle::*;
+pub use coverage::*;
+pub use dsl::*;
+pub use group::*;
+pub use policy::*;
+pub use schema::*;
+pub use sdk::*;
+pub use server::*;
+pub use spicedb::*;
 pub use target::*;
+pub use test::*;
+pub use xacml::*;
+
+use gatehouse::apikey::API_KEY_METADATA_KEY;
+use gatehouse::proto::base::gatehouse_client::GatehouseClient;
+use gatehouse::proto::policies::PolicyResponse;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+
+/// a client of the server
+pub type Client = GatehouseClient<InterceptedService<Channel, ApiKey>>;
+
+/// Sends the profile's token, if it has one, as the API key of every call
+#[derive(Clone)]
+pub struct ApiKey(pub Option<AsciiMetadataValue>);
+
+impl Interceptor for ApiKey {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(ref key) = self.0 {
+            request
+                .metadata_mut()
+                .insert(API_KEY_METADATA_KEY, key.clone());
+        }
+        Ok(request)
+    }
+}
 
 /// convert attributes passed into what the helper expects
 fn form_attributes(attr_args: &[String]) -> Vec<(String, Vec<&str>)> {
@@ -17,3 +66,16 @@ fn form_attributes(attr_args: &[String]) -> Vec<(String, Vec<&str>)> {
 
     attrs
 }
+
+/// warn about actions an added policy names that no registered target has
+fn warn_unknown_actions(name: &str, resp: &PolicyResponse) {
+    let Some(impact) = &resp.impact else {
+        return;
+    };
+    if !impact.unknown_actions.is_empty() {
+        eprintln!(
+            "Warning: no registered target has the actions {} named in policy {name}",
+            impact.unknown_actions.join(", ")
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use gatehouse::policytest::load_policies;
+use gatehouse::proto::base::GetPolicyStatsRequest;
+use gatehouse::proto::policies::{Decide, GetPoliciesRequest, PolicyRule};
+use gatehouse::render;
+
+use crate::args::{PolicyCmdDiffArgs, PolicyCmdFindArgs, PolicyCmdShowArgs, PolicyCmdSlowestArgs};
+
+use super::Client;
+use crate::error::{fail, Error, Kind};
+
+/// Fetch a stored policy by name, exiting if there isn't one
+async fn get_policy(client: &mut Client, name: &str) -> PolicyRule {
+    let req = GetPoliciesRequest {
+        name: Some(name.to_string()),
+        ..Default::default()
+    };
+    let rules = match client.get_policies(req).await {
+        Ok(resp) => resp.into_inner().rules,
+        Err(err) => fail(Error::status(format!("Could not get policy {name}"), err)),
+    };
+    match rules.into_iter().next() {
+        Some(rule) => rule,
+        None => fail(Error::new(
+            Kind::NotFound,
+            format!("No policy named {name}"),
+        )),
+    }
+}
+
+/// Show a stored policy, as YAML or as a tree
+pub async fn show_policy(client: &mut Client, args: PolicyCmdShowArgs) {
+    let rule = get_policy(client, &args.name).await;
+    if args.pretty {
+        print!("{}", render::pretty(&rule));
+        return;
+    }
+    match render::yaml(&rule) {
+        Ok(yaml) => print!("{yaml}"),
+        Err(err) => fail(err),
+    }
+}
+
+/// Show what differs between a stored policy and a local definition of it; the path can be a
+/// directory of them, as long as one has the name or it is the only one
+pub async fn diff_policy(client: &mut Client, args: PolicyCmdDiffArgs) {
+    let mut local = match load_policies(&args.file) {
+        Ok(rules) => rules,
+        Err(err) => fail(Error::new(Kind::Usage, err)),
+    };
+    let local = match local
+        .iter()
+        .position(|rule| rule.name.eq_ignore_ascii_case(&args.name))
+    {
+        Some(pos) => local.swap_remove(pos),
+        None if local.len() == 1 => local.remove(0),
+        None => fail(Error::new(
+            Kind::Usage,
+            format!("No policy named {} in {}", args.name, args.file.display()),
+        )),
+    };
+
+    let stored = get_policy(client, &args.name).await;
+    match render::diff(&stored, &local).as_str() {
+        "" => println!("No differences"),
+        diff => print!("{diff}"),
+    }
+}
+
+/// List the policies with the given tags, decision, and target type, and the words searched for
+pub async fn find_policies(client: &mut Client, args: PolicyCmdFindArgs) {
+    let decision = args.decision.map(|decision| {
+        match Decide::from_str_name(&decision.to_ascii_uppercase().replace('-', "_")) {
+            Some(decision) => decision as i32,
+            None => fail(Error::new(
+                Kind::Usage,
+                format!("Unknown decision {decision}"),
+            )),
+        }
+    });
+    let req = GetPoliciesRequest {
+        tags: args.tags,
+        decision,
+        target_type: args.target_type,
+        search: args.search,
+        ..Default::default()
+    };
+    let rules = match client.get_policies(req).await {
+        Ok(resp) => resp.into_inner().rules,
+        Err(err) => fail(Error::status("Could not find policies", err)),
+    };
+
+    if rules.is_empty() {
+        println!("No policies found");
+        return;
+    }
+    for rule in rules {
+        print!("policy[{}]: {}", rule.name, rule.decision());
+        if !rule.tags.is_empty() {
+            print!(" [{}]", rule.tags.join(", "));
+        }
+        match rule.desc {
+            Some(desc) => println!(" {}", desc.lines().next().unwrap_or_default()),
+            None => println!(),
+        }
+    }
+}
+
+/// List the policies that take longest to evaluate in checks
+pub async fn slowest_policies(client: &mut Client, args: PolicyCmdSlowestArgs) {
+    let req = GetPolicyStatsRequest { top: args.top };
+    let stats = match client.get_policy_stats(req).await {
+        Ok(resp) => resp.into_inner(),
+        Err(err) => fail(Error::status("Could not get policy stats", err)),
+    };
+
+    if stats.policies.is_empty() {
+        println!("No policies have been evaluated in checks");
+        return;
+    }
+    for policy in stats.policies {
+        print!(
+            "policy[{}]: {:.1}us average, {}us max, {} evaluations",
+            policy.name, policy.average_micros, policy.max_micros, policy.evaluations
+        );
+        match policy.over_budget {
+            0 => println!(),
+            over => println!(", {over} over the {}us budget", stats.budget_micros),
+        }
+    }
+}
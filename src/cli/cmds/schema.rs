@@ -0,0 +1,29 @@
+use gatehouse::proto::base::GetSchemasRequest;
+
+use crate::args::SchemaArgs;
+
+use super::Client;
+use crate::error::{fail, Error};
+
+/// List the server's JSON Schemas, or print the ones asked for
+pub async fn get_schemas(client: &mut Client, args: SchemaArgs) {
+    let listing = args.names.is_empty();
+    let req = GetSchemasRequest { names: args.names };
+    let schemas = match client.get_schemas(req).await {
+        Ok(resp) => resp.into_inner().schemas,
+        Err(err) => fail(Error::status("Could not get schemas", err)),
+    };
+
+    for schema in schemas {
+        match listing {
+            true => println!("{}: {}", schema.name, schema.description),
+            false => match serde_json::from_str::<serde_json::Value>(&schema.schema) {
+                Ok(json) => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&json).unwrap_or(schema.schema)
+                ),
+                Err(_) => println!("{}", schema.schema),
+            },
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::args::{SdkArgs, SdkLang};
+use crate::error::fail;
+
+/// the protos the server is built from, so the CLI can hand them out
+const PROTOS: &[(&str, &str)] = &[
+    ("actors.proto", include_str!("../../../proto/actors.proto")),
+    ("common.proto", include_str!("../../../proto/common.proto")),
+    (
+        "gatehouse.proto",
+        include_str!("../../../proto/gatehouse.proto"),
+    ),
+    ("groups.proto", include_str!("../../../proto/groups.proto")),
+    (
+        "policies.proto",
+        include_str!("../../../proto/policies.proto"),
+    ),
+    ("roles.proto", include_str!("../../../proto/roles.proto")),
+    (
+        "targets.proto",
+        include_str!("../../../proto/targets.proto"),
+    ),
+    (
+        "webhooks.proto",
+        include_str!("../../../proto/webhooks.proto"),
+    ),
+];
+
+const GO_HELPERS: &str = include_str!("../../../sdk/go/helpers.go");
+const PYTHON_HELPERS: &str = include_str!("../../../sdk/python/gatehouse_helpers.py");
+const TYPESCRIPT_HELPERS: &str = include_str!("../../../sdk/typescript/helpers.ts");
+
+pub fn generate_sdk(args: SdkArgs) {
+    if let Err(err) = write_sdk(&args) {
+        fail(err);
+    }
+}
+
+fn write_sdk(args: &SdkArgs) -> Result<(), String> {
+    let proto_dir = args.out.join("proto");
+    for (name, contents) in PROTOS {
+        write_file(&proto_dir.join(name), contents)?;
+    }
+    println!("Wrote {} protos to {}", PROTOS.len(), proto_dir.display());
+
+    let (code_dir, helpers_file, helpers) = match args.lang {
+        SdkLang::Go => (args.out.join("gatehouse"), "helpers.go", GO_HELPERS),
+        SdkLang::Python => (args.out.clone(), "gatehouse_helpers.py", PYTHON_HELPERS),
+        SdkLang::Typescript => (args.out.clone(), "helpers.ts", TYPESCRIPT_HELPERS),
+    };
+    write_file(&code_dir.join(helpers_file), helpers)?;
+    println!("Wrote helpers to {}", code_dir.join(helpers_file).display());
+
+    let mut cmd = protoc_command(args, &proto_dir, &code_dir);
+    if args.no_protoc {
+        println!("Generate the client with:\n  {}", describe(&cmd));
+        return Ok(());
+    }
+
+    let status = cmd
+        .status()
+        .map_err(|err| format!("Could not run {}: {err}", describe(&cmd)))?;
+    if !status.success() {
+        return Err(format!("{} failed with {status}", describe(&cmd)));
+    }
+
+    println!("Generated client in {}", code_dir.display());
+    Ok(())
+}
+
+/// the protoc invocation that generates the client for a language
+fn protoc_command(args: &SdkArgs, proto_dir: &Path, code_dir: &Path) -> Command {
+    let mut cmd = match args.lang {
+        SdkLang::Python => {
+            let mut cmd = Command::new("python3");
+            cmd.args(["-m", "grpc_tools.protoc"]);
+            cmd
+        }
+        _ => Command::new("protoc"),
+    };
+    cmd.arg(format!("-I{}", proto_dir.display()));
+
+    let code_dir = code_dir.display();
+    match args.lang {
+        SdkLang::Go => {
+            cmd.arg(format!("--go_out={code_dir}"))
+                .arg("--go_opt=paths=source_relative")
+                .arg(format!("--go-grpc_out={code_dir}"))
+                .arg("--go-grpc_opt=paths=source_relative");
+            // put every proto in the one Go package alongside the helpers
+            for (name, _) in PROTOS {
+                cmd.arg(format!("--go_opt=M{name}={};gatehouse", args.go_package))
+                    .arg(format!(
+                        "--go-grpc_opt=M{name}={};gatehouse",
+                        args.go_package
+                    ));
+            }
+        }
+        SdkLang::Python => {
+            cmd.arg(format!("--python_out={code_dir}"))
+                .arg(format!("--grpc_python_out={code_dir}"));
+        }
+        SdkLang::Typescript => {
+            cmd.arg(format!("--ts_proto_out={code_dir}"))
+                .arg("--ts_proto_opt=outputServices=grpc-js,esModuleInterop=true");
+        }
+    }
+
+    for (name, _) in PROTOS {
+        cmd.arg(proto_dir.join(name));
+    }
+
+    cmd
+}
+
+/// the command as it would be typed into a shell
+fn describe(cmd: &Command) -> String {
+    let mut parts = vec![cmd.get_program().to_string_lossy().to_string()];
+    for arg in cmd.get_args().map(|arg| arg.to_string_lossy()) {
+        if arg.contains([';', ' ']) {
+            parts.push(format!("'{arg}'"));
+        } else {
+            parts.push(arg.to_string());
+        }
+    }
+    parts.join(" ")
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| format!("Could not create {}: {err}", dir.display()))?;
+    }
+    fs::write(path, contents).map_err(|err| format!("Could not write {}: {err}", path.display()))
+}
@@ -0,0 +1,36 @@
+use gatehouse::helpers;
+
+use super::Client;
+use crate::error::fail;
+
+pub async fn server_info(client: &mut Client) {
+    let info = match helpers::get_server_info(client).await {
+        Ok(info) => info,
+        Err(err) => fail(err),
+    };
+    let or_none = |val: &str| match val.is_empty() {
+        true => String::from("none"),
+        false => val.to_string(),
+    };
+
+    println!("Version:      {}", info.version);
+    println!("Commit:       {}", or_none(&info.git_hash));
+    println!("Storage:      {}", info.storage);
+    println!("Uptime:       {}s", info.uptime_secs);
+    println!("Features:     {}", or_none(&info.features.join(", ")));
+    println!("Targets:      {}", info.targets);
+    println!("Actors:       {}", info.actors);
+    println!("Roles:        {}", info.roles);
+    println!("Groups:       {}", info.groups);
+    println!("Policies:     {}", info.policies);
+    println!("Policy sets:  {}", info.policy_sets);
+    println!("Delegations:  {}", info.delegations);
+    println!("Webhooks:     {}", info.webhooks);
+}
+
+pub async fn reload_state(client: &mut Client) {
+    match helpers::reload_state(client).await {
+        Ok(changed) => println!("Reloaded from the storage backend; {changed} entities changed"),
+        Err(err) => fail(err),
+    }
+}
@@ -0,0 +1,29 @@
+use std::fs;
+
+use gatehouse::compat::spicedb;
+use gatehouse::proto::base::SyncRequest;
+
+use crate::args::SpiceDbArgs;
+
+use super::Client;
+use crate::error::{fail, Error};
+
+/// Export group memberships and role grants as a SpiceDB schema and relationships
+pub async fn export_spicedb(client: &mut Client, args: SpiceDbArgs) {
+    let yaml = match client.sync(SyncRequest {}).await {
+        Ok(resp) => spicedb::export(resp.get_ref()).and_then(|export| export.to_yaml()),
+        Err(err) => fail(Error::status("Could not get state", err)),
+    };
+    let result = yaml.and_then(|yaml| match args.output {
+        Some(ref file) => fs::write(file, yaml)
+            .map_err(|err| format!("Could not write {}: {err}", file.display())),
+        None => {
+            print!("{yaml}");
+            Ok(())
+        }
+    });
+
+    if let Err(err) = result {
+        fail(err);
+    }
+}
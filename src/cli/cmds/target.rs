@@ -1,66 +1,110 @@
-use tonic::transport::Channel;
+use std::path::Path;
 
-use gatehouse::helpers;
-use gatehouse::proto::base::gatehouse_client::GatehouseClient;
+use gatehouse::bulk::load_targets;
+use gatehouse::helpers::{self, AddTargetRequestBuilder, ModifyTargetRequestBuilder};
 
 use crate::args::{
     TargetCmdAddArgs, TargetCmdModifyArgs, TargetCmdRemoveArgs, TargetCmdSearchArgs,
 };
 
-use super::form_attributes;
+use super::{form_attributes, Client};
+use crate::error::{fail, report, Error, Kind};
 
-pub async fn add_target(client: &mut GatehouseClient<Channel>, args: TargetCmdAddArgs) {
-    let attributes = form_attributes(&args.attribs);
+pub async fn add_target(client: &mut Client, args: TargetCmdAddArgs) {
+    if let Some(ref file) = args.file {
+        return add_targets(client, file).await;
+    }
+    let (name, typestr) = (
+        args.name.unwrap_or_default(),
+        args.typestr.unwrap_or_default(),
+    );
+    let actions: Vec<&str> = args.actions.iter().map(AsRef::as_ref).collect();
+    let mut req = AddTargetRequestBuilder::new(&typestr, &name).actions(&actions);
+    for (key, vals) in form_attributes(&args.attribs) {
+        req = req.attribute(&key, &vals);
+    }
+    for (group, actions) in form_attributes(&args.action_groups) {
+        req = req.action_group(&group, &actions);
+    }
 
-    match helpers::add_target(
-        client,
-        &args.name,
-        &args.typestr,
-        args.actions.iter().map(AsRef::as_ref).collect(),
-        attributes,
-    )
-    .await
-    {
+    match helpers::add_target(client, req.build()).await {
         Ok(target) => println!("Added {target}"),
-        Err(err) => eprintln!("Error: {err}"),
+        Err(err) => fail(err),
+    }
+}
+
+/// Add the targets in a file, reporting the ones that couldn't be added
+async fn add_targets(client: &mut Client, file: &Path) {
+    let targets = match load_targets(file) {
+        Ok(targets) => targets,
+        Err(err) => fail(Error::new(Kind::Usage, err)),
+    };
+    let names: Vec<String> = targets
+        .iter()
+        .map(|target| format!("{}/{}", target.typestr, target.name))
+        .collect();
+
+    let results = match helpers::add_targets(client, targets).await {
+        Ok(results) => results,
+        Err(err) => fail(err),
+    };
+    let mut failed = 0;
+    for (name, result) in names.iter().zip(&results) {
+        if !result.error.is_empty() {
+            report(format!("Could not add {name}: {}", result.error));
+            failed += 1;
+        }
+    }
+    println!(
+        "Added {} of {} targets",
+        results.len() - failed,
+        names.len()
+    );
+    if failed > 0 {
+        Kind::Failed.exit();
     }
 }
 
-pub async fn modify_target(client: &mut GatehouseClient<Channel>, args: TargetCmdModifyArgs) {
-    let add_attributes = form_attributes(&args.add_attribs);
-    let remove_attributes = form_attributes(&args.remove_attribs);
+pub async fn modify_target(client: &mut Client, args: TargetCmdModifyArgs) {
+    let mut req = ModifyTargetRequestBuilder::new(&args.typestr, &args.name)
+        .add_actions(&strs(&args.add_actions))
+        .remove_actions(&strs(&args.remove_actions))
+        .remove_action_groups(&strs(&args.remove_action_groups));
+    for (key, vals) in form_attributes(&args.add_attribs) {
+        req = req.add_attribute(&key, &vals);
+    }
+    for (key, vals) in form_attributes(&args.remove_attribs) {
+        req = req.remove_attribute(&key, &vals);
+    }
+    for (group, actions) in form_attributes(&args.add_action_groups) {
+        req = req.add_action_group(&group, &actions);
+    }
 
-    match helpers::modify_target(
-        client,
-        &args.name,
-        &args.typestr,
-        args.add_actions.iter().map(AsRef::as_ref).collect(),
-        add_attributes,
-        args.remove_actions.iter().map(AsRef::as_ref).collect(),
-        remove_attributes,
-    )
-    .await
-    {
+    match helpers::modify_target(client, req.build()).await {
         Ok(target) => println!("Updated {target}"),
-        Err(err) => eprintln!("Error: {err}"),
+        Err(err) => fail(err),
     }
 }
 
-pub async fn get_targets(client: &mut GatehouseClient<Channel>, args: TargetCmdSearchArgs) {
-    match helpers::get_targets(client, args.name, args.typestr).await {
+pub async fn get_targets(client: &mut Client, args: TargetCmdSearchArgs) {
+    match helpers::get_targets(client, args.typestr, args.name).await {
         Ok(targets) => {
             println!("Got {} targets:", targets.len());
             for target in targets {
                 println!("{target}");
             }
         }
-        Err(err) => println!("Error: {err}"),
+        Err(err) => fail(err),
     }
 }
 
-pub async fn remove_target(client: &mut GatehouseClient<Channel>, args: TargetCmdRemoveArgs) {
-    match helpers::remove_target(client, &args.name, &args.typestr).await {
+pub async fn remove_target(client: &mut Client, args: TargetCmdRemoveArgs) {
+    match helpers::remove_target(client, &args.typestr, &args.name).await {
         Ok(target) => println!("Removed {target}"),
-        Err(err) => eprintln!("Error: {err}"),
+        Err(err) => fail(err),
     }
 }
+
+fn strs(vals: &[String]) -> Vec<&str> {
+    vals.iter().map(AsRef::as_ref).collect()
+}
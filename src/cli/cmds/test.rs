@@ -0,0 +1,41 @@
+use gatehouse::helpers;
+use gatehouse::policytest::{load_policies, load_tests};
+
+use crate::args::TestArgs;
+use crate::error::{fail, Error, Kind};
+
+use super::Client;
+
+/// Run policy tests, exiting with an error if any fail so CI can gate on them
+pub async fn test_policies(client: &mut Client, args: TestArgs) {
+    let cases = match load_tests(&args.file) {
+        Ok(cases) => cases,
+        Err(err) => fail(Error::new(Kind::Usage, err)),
+    };
+    let policies = match args.policies.as_deref().map(load_policies) {
+        Some(Ok(policies)) => policies,
+        Some(Err(err)) => fail(Error::new(Kind::Usage, err)),
+        None => vec![],
+    };
+
+    match helpers::test_policies(client, cases, policies).await {
+        Ok(report) => {
+            for result in &report.results {
+                match result.passed {
+                    true => println!("PASS {}", result.name),
+                    false => println!(
+                        "FAIL {}: expected {:?}, got {:?}",
+                        result.name,
+                        result.expected(),
+                        result.actual()
+                    ),
+                }
+            }
+            println!("{} passed, {} failed", report.passed, report.failed);
+            if report.failed > 0 {
+                Kind::Failed.exit();
+            }
+        }
+        Err(err) => fail(err),
+    }
+}
@@ -0,0 +1,38 @@
+use std::fs;
+
+use gatehouse::compat::xacml;
+use gatehouse::proto::policies::AddPolicyRequest;
+
+use crate::args::XacmlArgs;
+
+use super::{warn_unknown_actions, Client};
+use crate::error::{fail, Error, Kind};
+
+/// Convert XACML policies and add them, stopping at the first one the server rejects
+pub async fn import_xacml(client: &mut Client, args: XacmlArgs) {
+    let rules = match fs::read_to_string(&args.file)
+        .map_err(|err| format!("Could not read {}: {err}", args.file.display()))
+        .and_then(|xml| xacml::import(&xml))
+    {
+        Ok(rules) => rules,
+        Err(err) => fail(Error::new(Kind::Usage, err)),
+    };
+
+    for rule in rules {
+        let name = rule.name.clone();
+        let req = AddPolicyRequest {
+            rule: Some(rule),
+            dry_run: args.dry_run,
+        };
+        match client.add_policy(req).await {
+            Ok(resp) => {
+                match args.dry_run {
+                    true => println!("Would add policy {name}"),
+                    false => println!("Added policy {name}"),
+                }
+                warn_unknown_actions(&name, resp.get_ref());
+            }
+            Err(err) => fail(Error::status(format!("Could not add policy {name}"), err)),
+        }
+    }
+}
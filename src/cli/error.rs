@@ -0,0 +1,154 @@
+//! How gatecli reports errors, and the exit code each kind of error ends it with
+
+use std::fmt::Display;
+use std::process;
+use std::sync::OnceLock;
+
+use gatehouse::helpers::CallError;
+use serde_json::json;
+use tonic::{Code, Status};
+
+/// What went wrong; each kind has its own exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// anything else, including policy tests that fail (1)
+    Failed,
+    /// bad arguments, settings, or input files (2, as clap uses for bad arguments)
+    Usage,
+    /// what was asked for doesn't exist (3)
+    NotFound,
+    /// the server rejected the request as invalid (4)
+    Invalid,
+    /// the server couldn't be reached (5)
+    Connection,
+    /// the server refused the caller (6)
+    Permission,
+}
+
+impl Kind {
+    /// the exit code for this kind of error
+    pub fn code(self) -> i32 {
+        match self {
+            Kind::Failed => 1,
+            Kind::Usage => 2,
+            Kind::NotFound => 3,
+            Kind::Invalid => 4,
+            Kind::Connection => 5,
+            Kind::Permission => 6,
+        }
+    }
+
+    /// the name of this kind of error in JSON errors
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Failed => "failed",
+            Kind::Usage => "usage",
+            Kind::NotFound => "not_found",
+            Kind::Invalid => "invalid",
+            Kind::Connection => "connection",
+            Kind::Permission => "permission",
+        }
+    }
+
+    /// end gatecli with this kind's exit code, without reporting anything
+    pub fn exit(self) -> ! {
+        process::exit(self.code())
+    }
+}
+
+impl From<Code> for Kind {
+    fn from(code: Code) -> Self {
+        match code {
+            Code::NotFound => Kind::NotFound,
+            Code::InvalidArgument
+            | Code::FailedPrecondition
+            | Code::AlreadyExists
+            | Code::OutOfRange => Kind::Invalid,
+            Code::Unavailable | Code::DeadlineExceeded => Kind::Connection,
+            Code::PermissionDenied | Code::Unauthenticated => Kind::Permission,
+            _ => Kind::Failed,
+        }
+    }
+}
+
+/// An error to report
+#[derive(Debug)]
+pub struct Error {
+    kind: Kind,
+    message: String,
+}
+
+impl Error {
+    /// an error of a kind
+    pub fn new(kind: Kind, message: impl Display) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    /// an error the server answered a call with, described by what the call was for
+    pub fn status(context: impl Display, status: Status) -> Self {
+        Self::new(
+            status.code().into(),
+            format!("{context}: {}", status.message()),
+        )
+    }
+}
+
+impl From<CallError> for Error {
+    fn from(err: CallError) -> Self {
+        Self::new(err.code.into(), err.message)
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Self::new(Kind::Failed, message)
+    }
+}
+
+/// How errors are printed, as set by the global flags
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Output {
+    /// print nothing; only the exit code tells what went wrong
+    pub quiet: bool,
+    /// print each error as a line of JSON
+    pub json: bool,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+/// Set how errors are printed; only the first call counts
+pub fn configure(output: Output) {
+    let _ = OUTPUT.set(output);
+}
+
+/// Print an error to stderr, and carry on
+pub fn report(err: impl Into<Error>) {
+    let err = err.into();
+    let output = OUTPUT.get().copied().unwrap_or_default();
+    if output.quiet {
+        return;
+    }
+
+    match output.json {
+        true => eprintln!(
+            "{}",
+            json!({
+                "error": err.kind.name(),
+                "code": err.kind.code(),
+                "message": err.message,
+            })
+        ),
+        false => eprintln!("Error: {}", err.message),
+    }
+}
+
+/// Print an error to stderr and end gatecli with its exit code
+pub fn fail(err: impl Into<Error>) -> ! {
+    let err = err.into();
+    let kind = err.kind;
+    report(err);
+    kind.exit()
+}
@@ -5,18 +5,29 @@
 use core::hash::Hash;
 use fasthash::metro;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::sync::Arc;
 
+use crate::attribute::{self, AttributeMap};
+use crate::intern;
 use crate::proto::actors::Actor;
 use crate::proto::common::AttributeValues;
 use crate::proto::groups::GroupMember;
+use crate::timestamps::Timestamps;
 
 #[derive(Debug, Clone, Eq, Serialize, Deserialize)]
 pub(crate) struct RegisteredActor {
     pub name: String,
-    pub typestr: String,
-    pub attributes: HashMap<String, HashSet<String>>,
+    /// interned, so actors of a type share one copy of it
+    #[serde(deserialize_with = "intern::deserialize")]
+    pub typestr: Arc<str>,
+    /// shared, so checks can use them without a copy
+    #[serde(deserialize_with = "attribute::deserialize")]
+    pub attributes: Arc<AttributeMap>,
+    /// when the actor was added and last changed
+    #[serde(flatten)]
+    pub timestamps: Timestamps,
 }
 
 /// Two registered actors are equivalent if the name and typestr are identical
@@ -35,15 +46,20 @@ impl Hash for RegisteredActor {
 
 impl From<Actor> for RegisteredActor {
     fn from(tgt: Actor) -> Self {
-        let mut attributes = HashMap::new();
-        for (key, val) in tgt.attributes {
-            attributes.insert(key, HashSet::from_iter(val.values));
-        }
+        let attributes = attribute::from_request(tgt.attributes);
+
+        let (mut name, mut typestr) = (tgt.name, tgt.typestr);
+        name.make_ascii_lowercase();
+        typestr.make_ascii_lowercase();
 
         Self {
-            name: tgt.name.to_ascii_lowercase(),
-            typestr: tgt.typestr.to_ascii_lowercase(),
-            attributes,
+            name,
+            typestr: intern::intern(&typestr),
+            attributes: Arc::new(attributes),
+            timestamps: Timestamps {
+                created_at: tgt.created_at,
+                updated_at: tgt.updated_at,
+            },
         }
     }
 }
@@ -51,9 +67,9 @@ impl From<Actor> for RegisteredActor {
 impl From<RegisteredActor> for Actor {
     fn from(actor: RegisteredActor) -> Self {
         let mut attributes = HashMap::new();
-        for (key, val) in actor.attributes {
+        for (key, val) in actor.attributes.iter() {
             attributes.insert(
-                key,
+                key.to_string(),
                 AttributeValues {
                     values: val.iter().map(|v| v.to_string()).collect(),
                 },
@@ -62,8 +78,10 @@ impl From<RegisteredActor> for Actor {
 
         Self {
             name: actor.name,
-            typestr: actor.typestr,
+            typestr: actor.typestr.to_string(),
             attributes,
+            created_at: actor.timestamps.created_at,
+            updated_at: actor.timestamps.updated_at,
         }
     }
 }
@@ -72,7 +90,7 @@ impl From<RegisteredActor> for GroupMember {
     fn from(actor: RegisteredActor) -> Self {
         Self {
             name: actor.name,
-            typestr: actor.typestr,
+            typestr: actor.typestr.to_string(),
         }
     }
 }
@@ -99,15 +117,22 @@ impl Display for RegisteredActor {
 }
 
 impl RegisteredActor {
-    pub(crate) fn new(
-        name: &str,
-        typestr: &str,
-        attributes: HashMap<String, HashSet<String>>,
-    ) -> Self {
+    /// The actor a check is about, with the attributes it brings, which aren't interned
+    pub(crate) fn from_check(actor: &Actor) -> Self {
+        RegisteredActor {
+            name: actor.name.to_ascii_lowercase(),
+            typestr: Arc::from(actor.typestr.to_ascii_lowercase()),
+            attributes: Arc::new(attribute::from_check(&actor.attributes)),
+            timestamps: Timestamps::default(),
+        }
+    }
+
+    pub(crate) fn new(name: &str, typestr: &str, attributes: AttributeMap) -> Self {
         RegisteredActor {
             name: name.to_string(),
-            typestr: typestr.to_string(),
-            attributes,
+            typestr: intern::intern(typestr),
+            attributes: Arc::new(attributes),
+            timestamps: Timestamps::default(),
         }
     }
 
@@ -0,0 +1,1028 @@
+#![warn(missing_docs)]
+
+//! A versioned HTTP/JSON admin API, meant for tools like a Terraform provider
+//!
+//! Every target, actor, role, group, and policy is a resource under `/admin/v1`:
+//!
+//! * `/admin/v1/targets/{type}/{name}` and `/admin/v1/actors/{type}/{name}`
+//! * `/admin/v1/roles/{name}`, `/admin/v1/groups/{name}`, and `/admin/v1/policies/{name}`
+//!
+//! `GET` returns the resource as a JSON document along with an `ETag`. `PUT` creates the resource
+//! or replaces it with the document given, and `DELETE` removes it. Both take `If-Match` with the
+//! ETag of the version they were planned against, and are refused with 412 if the resource
+//! has changed since; `PUT` with `If-None-Match: *` only creates. Adding `?dry_run=true`
+//! validates the change and returns what the resource would become, without making the change.
+//!
+//! Documents list everything in sorted order, so the same resource always gives the same
+//! document and ETag. Within `v1`, fields are only ever added, and only as optional ones. Fields a
+//! server doesn't know are refused rather than ignored, so nothing is silently dropped by an older
+//! server. Policies use the format the file storage backend keeps them in. Changes are made through
+//! the same calls as over gRPC, so request hooks and policy approvals apply to them too.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use fasthash::metro;
+use hyper::header::{HeaderValue, ETAG, IF_MATCH, IF_NONE_MATCH};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Status};
+
+use crate::policy::RegisteredPolicyRule;
+use crate::proto::actors::{
+    Actor, AddActorRequest, GetActorsRequest, ModifyActorRequest, RemoveActorRequest,
+};
+use crate::proto::base::gatehouse_server::Gatehouse;
+use crate::proto::common::AttributeValues;
+use crate::proto::groups::{
+    AddGroupRequest, GetGroupsRequest, Group, GroupMember, ModifyGroupRequest, RemoveGroupRequest,
+};
+use crate::proto::policies::{
+    AddPolicyRequest, GetPoliciesRequest, ModifyPolicyRequest, PolicyRule, RemovePolicyRequest,
+};
+use crate::proto::roles::{
+    AddRoleRequest, GetRolesRequest, ModifyRoleRequest, RemoveRoleRequest, Role,
+};
+use crate::proto::targets::{
+    ActionGroup, AddTargetRequest, GetTargetsRequest, ModifyTargetRequest, RemoveTargetRequest,
+    Target,
+};
+use crate::svc::GatehouseSvc;
+
+/// the prefix of every path in this version of the API
+const PREFIX: &str = "/admin/v1/";
+
+/// A resource named by a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Resource {
+    Target(String, String),
+    Actor(String, String),
+    Role(String),
+    Group(String),
+    Policy(String),
+}
+
+impl Resource {
+    /// The resource a path names, if any
+    fn parse(path: &str) -> Option<Self> {
+        let parts: Vec<String> = path
+            .strip_prefix(PREFIX)?
+            .split('/')
+            .map(|part| percent_decode_str(part).decode_utf8_lossy().to_lowercase())
+            .collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            return None;
+        }
+
+        match parts.as_slice() {
+            [kind, typestr, name] if kind == "targets" => {
+                Some(Self::Target(typestr.clone(), name.clone()))
+            }
+            [kind, typestr, name] if kind == "actors" => {
+                Some(Self::Actor(typestr.clone(), name.clone()))
+            }
+            [kind, name] if kind == "roles" => Some(Self::Role(name.clone())),
+            [kind, name] if kind == "groups" => Some(Self::Group(name.clone())),
+            [kind, name] if kind == "policies" => Some(Self::Policy(name.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// A target as a document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TargetDoc {
+    #[serde(rename = "type")]
+    typestr: String,
+    name: String,
+    #[serde(default)]
+    actions: BTreeSet<String>,
+    #[serde(default)]
+    attributes: BTreeMap<String, BTreeSet<String>>,
+    #[serde(default)]
+    action_groups: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// An actor as a document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ActorDoc {
+    #[serde(rename = "type")]
+    typestr: String,
+    name: String,
+    #[serde(default)]
+    attributes: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// A role as a document; roles are granted to groups in the group documents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoleDoc {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+/// A group as a document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupDoc {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default)]
+    members: BTreeSet<MemberDoc>,
+    #[serde(default)]
+    roles: BTreeSet<String>,
+}
+
+/// A group member as a document
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MemberDoc {
+    #[serde(rename = "type")]
+    typestr: String,
+    name: String,
+}
+
+/// Any resource as a document
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+enum Document {
+    Target(TargetDoc),
+    Actor(ActorDoc),
+    Role(RoleDoc),
+    Group(GroupDoc),
+    Policy(Box<RegisteredPolicyRule>),
+}
+
+impl Document {
+    /// The ETag of this version of the resource
+    fn etag(&self) -> String {
+        let json = serde_json::to_string(self).unwrap_or_default();
+        format!("\"{:016x}\"", metro::hash64(json))
+    }
+}
+
+/// Turn attributes into a document's, dropping those with no values
+fn doc_attributes(attrs: HashMap<String, AttributeValues>) -> BTreeMap<String, BTreeSet<String>> {
+    attrs
+        .into_iter()
+        .filter(|(_, vals)| !vals.values.is_empty())
+        .map(|(key, vals)| (key, vals.values.into_iter().collect()))
+        .collect()
+}
+
+/// Turn a document's attributes into those of a request
+fn attributes(attrs: &BTreeMap<String, BTreeSet<String>>) -> HashMap<String, AttributeValues> {
+    attrs
+        .iter()
+        .map(|(key, vals)| {
+            let values = vals.iter().cloned().collect();
+            (key.clone(), AttributeValues { values })
+        })
+        .collect()
+}
+
+/// The attribute values in one set of attributes and not the other
+fn missing(
+    from: &BTreeMap<String, BTreeSet<String>>,
+    other: &BTreeMap<String, BTreeSet<String>>,
+) -> BTreeMap<String, BTreeSet<String>> {
+    from.iter()
+        .filter_map(|(key, vals)| {
+            let others = other.get(key);
+            let vals: BTreeSet<String> = vals
+                .iter()
+                .filter(|val| !others.is_some_and(|others| others.contains(*val)))
+                .cloned()
+                .collect();
+            (!vals.is_empty()).then(|| (key.clone(), vals))
+        })
+        .collect()
+}
+
+/// A description as a document's, where an empty one is none
+fn description(desc: Option<String>) -> Option<String> {
+    desc.filter(|desc| !desc.is_empty())
+}
+
+impl From<Target> for TargetDoc {
+    fn from(target: Target) -> Self {
+        Self {
+            typestr: target.typestr,
+            name: target.name,
+            actions: target.actions.into_iter().collect(),
+            attributes: doc_attributes(target.attributes),
+            action_groups: target
+                .action_groups
+                .into_iter()
+                .map(|(name, group)| (name, group.actions.into_iter().collect()))
+                .collect(),
+        }
+    }
+}
+
+impl From<Actor> for ActorDoc {
+    fn from(actor: Actor) -> Self {
+        Self {
+            typestr: actor.typestr,
+            name: actor.name,
+            attributes: doc_attributes(actor.attributes),
+        }
+    }
+}
+
+impl From<Role> for RoleDoc {
+    fn from(role: Role) -> Self {
+        Self {
+            name: role.name,
+            description: description(role.desc),
+        }
+    }
+}
+
+impl From<Group> for GroupDoc {
+    fn from(group: Group) -> Self {
+        Self {
+            name: group.name,
+            description: description(group.desc),
+            members: group
+                .members
+                .into_iter()
+                .map(|member| MemberDoc {
+                    typestr: member.typestr,
+                    name: member.name,
+                })
+                .collect(),
+            roles: group.roles.into_iter().collect(),
+        }
+    }
+}
+
+impl From<MemberDoc> for GroupMember {
+    fn from(member: MemberDoc) -> Self {
+        Self {
+            name: member.name,
+            typestr: member.typestr,
+        }
+    }
+}
+
+/// Serve the admin API until the server fails
+pub async fn serve(addr: SocketAddr, svc: Arc<GatehouseSvc>) -> Result<(), hyper::Error> {
+    // writes are made one at a time, so If-Match can't be checked against a stale version
+    let writes = Arc::new(Mutex::new(()));
+
+    let make_svc = make_service_fn(move |_| {
+        let svc = svc.clone();
+        let writes = writes.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let svc = svc.clone();
+                let writes = writes.clone();
+                async move { Ok::<_, Infallible>(handle(&svc, &writes, req).await) }
+            }))
+        }
+    });
+
+    Server::bind(&addr).serve(make_svc).await
+}
+
+/// Handle a single HTTP request
+async fn handle(svc: &GatehouseSvc, writes: &Mutex<()>, req: Request<Body>) -> Response<Body> {
+    let resource = match Resource::parse(req.uri().path()) {
+        Some(resource) => resource,
+        None => return error(StatusCode::NOT_FOUND, "Not found"),
+    };
+    let dry_run = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .any(|param| param == "dry_run=true");
+
+    let (parts, body) = req.into_parts();
+    let result = match parts.method {
+        Method::GET => get(svc, &resource, &parts.headers).await,
+        Method::PUT => {
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(err) => return error(StatusCode::BAD_REQUEST, &err.to_string()),
+            };
+            let _write = writes.lock().await;
+            put(svc, &resource, &parts.headers, &body, dry_run).await
+        }
+        Method::DELETE => {
+            let _write = writes.lock().await;
+            delete(svc, &resource, &parts.headers, dry_run).await
+        }
+        _ => Err(error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Use GET, PUT, or DELETE",
+        )),
+    };
+
+    result.unwrap_or_else(|resp| resp)
+}
+
+/// Return a resource
+async fn get(
+    svc: &GatehouseSvc,
+    resource: &Resource,
+    headers: &HeaderMap,
+) -> Result<Response<Body>, Response<Body>> {
+    match read(svc, resource, headers).await.map_err(status_error)? {
+        Some(doc) => Ok(document(StatusCode::OK, &doc)),
+        None => Err(error(StatusCode::NOT_FOUND, "Not found")),
+    }
+}
+
+/// Create or replace a resource with a document
+async fn put(
+    svc: &GatehouseSvc,
+    resource: &Resource,
+    headers: &HeaderMap,
+    body: &[u8],
+    dry_run: bool,
+) -> Result<Response<Body>, Response<Body>> {
+    let current = read(svc, resource, headers).await.map_err(status_error)?;
+    precondition(headers, current.as_ref())
+        .map_err(|err| error(StatusCode::PRECONDITION_FAILED, err))?;
+    let exists = current.is_some();
+
+    let bad_request = |err: serde_json::Error| error(StatusCode::BAD_REQUEST, &err.to_string());
+    let (doc, proposal_id) = match resource {
+        Resource::Target(typestr, name) => {
+            let doc: TargetDoc = serde_json::from_slice(body).map_err(bad_request)?;
+            check_names(&[(typestr, &doc.typestr), (name, &doc.name)])
+                .map_err(|err| error(StatusCode::BAD_REQUEST, &err))?;
+            let current = match current {
+                Some(Document::Target(current)) => Some(current),
+                _ => None,
+            };
+            (put_target(svc, headers, doc, current, dry_run).await, 0)
+        }
+        Resource::Actor(typestr, name) => {
+            let doc: ActorDoc = serde_json::from_slice(body).map_err(bad_request)?;
+            check_names(&[(typestr, &doc.typestr), (name, &doc.name)])
+                .map_err(|err| error(StatusCode::BAD_REQUEST, &err))?;
+            let current = match current {
+                Some(Document::Actor(current)) => Some(current),
+                _ => None,
+            };
+            (put_actor(svc, headers, doc, current, dry_run).await, 0)
+        }
+        Resource::Role(name) => {
+            let doc: RoleDoc = serde_json::from_slice(body).map_err(bad_request)?;
+            check_names(&[(name, &doc.name)])
+                .map_err(|err| error(StatusCode::BAD_REQUEST, &err))?;
+            (put_role(svc, headers, doc, exists, dry_run).await, 0)
+        }
+        Resource::Group(name) => {
+            let doc: GroupDoc = serde_json::from_slice(body).map_err(bad_request)?;
+            check_names(&[(name, &doc.name)])
+                .map_err(|err| error(StatusCode::BAD_REQUEST, &err))?;
+            let current = match current {
+                Some(Document::Group(current)) => Some(current),
+                _ => None,
+            };
+            (put_group(svc, headers, doc, current, dry_run).await, 0)
+        }
+        Resource::Policy(name) => {
+            let rule: RegisteredPolicyRule = serde_json::from_slice(body).map_err(bad_request)?;
+            check_names(&[(name, &rule.name)])
+                .map_err(|err| error(StatusCode::BAD_REQUEST, &err))?;
+            match put_policy(svc, headers, rule, exists, dry_run).await {
+                Ok((doc, proposal_id)) => (Ok(doc), proposal_id),
+                Err(status) => (Err(status), 0),
+            }
+        }
+    };
+    let doc = doc.map_err(status_error)?;
+
+    if proposal_id != 0 {
+        return Ok(json_response(
+            StatusCode::ACCEPTED,
+            json!({ "proposal_id": proposal_id }),
+        ));
+    }
+    match exists {
+        true => Ok(document(StatusCode::OK, &doc)),
+        false => Ok(document(StatusCode::CREATED, &doc)),
+    }
+}
+
+/// Remove a resource
+async fn delete(
+    svc: &GatehouseSvc,
+    resource: &Resource,
+    headers: &HeaderMap,
+    dry_run: bool,
+) -> Result<Response<Body>, Response<Body>> {
+    let current = read(svc, resource, headers).await.map_err(status_error)?;
+    if current.is_none() {
+        return Err(error(StatusCode::NOT_FOUND, "Not found"));
+    }
+    precondition(headers, current.as_ref())
+        .map_err(|err| error(StatusCode::PRECONDITION_FAILED, err))?;
+
+    let metadata = || MetadataMap::from_headers(headers.clone());
+    let proposal_id = match resource.clone() {
+        Resource::Target(typestr, name) => {
+            let req = RemoveTargetRequest {
+                name,
+                typestr,
+                dry_run,
+                ..Default::default()
+            };
+            svc.remove_target(request(req, metadata())).await.map(|_| 0)
+        }
+        Resource::Actor(typestr, name) => {
+            let req = RemoveActorRequest {
+                name,
+                typestr,
+                dry_run,
+                ..Default::default()
+            };
+            svc.remove_actor(request(req, metadata())).await.map(|_| 0)
+        }
+        Resource::Role(name) => {
+            let req = RemoveRoleRequest { name, dry_run };
+            svc.remove_role(request(req, metadata())).await.map(|_| 0)
+        }
+        Resource::Group(name) => {
+            let req = RemoveGroupRequest { name, dry_run };
+            svc.remove_group(request(req, metadata())).await.map(|_| 0)
+        }
+        Resource::Policy(name) => {
+            let req = RemovePolicyRequest { name, dry_run };
+            svc.remove_policy(request(req, metadata()))
+                .await
+                .map(|resp| resp.into_inner().proposal_id)
+        }
+    }
+    .map_err(status_error)?;
+
+    match proposal_id {
+        0 => Ok(empty(StatusCode::NO_CONTENT)),
+        _ => Ok(json_response(
+            StatusCode::ACCEPTED,
+            json!({ "proposal_id": proposal_id }),
+        )),
+    }
+}
+
+/// Read a resource as a document, if it exists
+async fn read(
+    svc: &GatehouseSvc,
+    resource: &Resource,
+    headers: &HeaderMap,
+) -> Result<Option<Document>, Status> {
+    let metadata = MetadataMap::from_headers(headers.clone());
+    let doc = match resource.clone() {
+        Resource::Target(typestr, name) => {
+            let req = GetTargetsRequest {
+                name: Some(name),
+                typestr: Some(typestr),
+                ..Default::default()
+            };
+            let targets = svc.get_targets(request(req, metadata)).await?.into_inner();
+            targets
+                .targets
+                .into_iter()
+                .next()
+                .map(|target| Document::Target(target.into()))
+        }
+        Resource::Actor(typestr, name) => {
+            let req = GetActorsRequest {
+                name: Some(name),
+                typestr: Some(typestr),
+                ..Default::default()
+            };
+            let actors = svc.get_actors(request(req, metadata)).await?.into_inner();
+            actors
+                .actors
+                .into_iter()
+                .next()
+                .map(|actor| Document::Actor(actor.into()))
+        }
+        Resource::Role(name) => {
+            let req = GetRolesRequest {
+                name: Some(name),
+                ..Default::default()
+            };
+            let roles = svc.get_roles(request(req, metadata)).await?.into_inner();
+            roles
+                .roles
+                .into_iter()
+                .next()
+                .map(|role| Document::Role(role.into()))
+        }
+        Resource::Group(name) => {
+            let req = GetGroupsRequest {
+                name: Some(name),
+                ..Default::default()
+            };
+            let groups = svc.get_groups(request(req, metadata)).await?.into_inner();
+            groups
+                .groups
+                .into_iter()
+                .next()
+                .map(|group| Document::Group(group.into()))
+        }
+        Resource::Policy(name) => {
+            let req = GetPoliciesRequest {
+                name: Some(name),
+                ..Default::default()
+            };
+            let policies = svc.get_policies(request(req, metadata)).await?.into_inner();
+            policies
+                .rules
+                .into_iter()
+                .next()
+                .map(|rule| Document::Policy(Box::new(rule.into())))
+        }
+    };
+
+    Ok(doc)
+}
+
+/// Create a target, or change it to match a document
+async fn put_target(
+    svc: &GatehouseSvc,
+    headers: &HeaderMap,
+    doc: TargetDoc,
+    current: Option<TargetDoc>,
+    dry_run: bool,
+) -> Result<Document, Status> {
+    let metadata = MetadataMap::from_headers(headers.clone());
+    let action_groups = |groups: &BTreeMap<String, BTreeSet<String>>| {
+        groups
+            .iter()
+            .map(|(name, actions)| {
+                let actions = actions.iter().cloned().collect();
+                (name.clone(), ActionGroup { actions })
+            })
+            .collect()
+    };
+
+    let resp = match current {
+        None => {
+            let req = AddTargetRequest {
+                name: doc.name,
+                typestr: doc.typestr,
+                actions: doc.actions.into_iter().collect(),
+                attributes: attributes(&doc.attributes),
+                action_groups: action_groups(&doc.action_groups),
+                dry_run,
+            };
+            svc.add_target(request(req, metadata)).await?
+        }
+        Some(current) => {
+            let changed_groups: BTreeMap<String, BTreeSet<String>> = doc
+                .action_groups
+                .iter()
+                .filter(|(name, actions)| current.action_groups.get(*name) != Some(*actions))
+                .map(|(name, actions)| (name.clone(), actions.clone()))
+                .collect();
+            let req = ModifyTargetRequest {
+                name: doc.name,
+                typestr: doc.typestr,
+                add_actions: doc.actions.difference(&current.actions).cloned().collect(),
+                remove_actions: current.actions.difference(&doc.actions).cloned().collect(),
+                add_attributes: attributes(&missing(&doc.attributes, &current.attributes)),
+                remove_attributes: attributes(&missing(&current.attributes, &doc.attributes)),
+                add_action_groups: action_groups(&changed_groups),
+                remove_action_groups: current
+                    .action_groups
+                    .keys()
+                    .filter(|name| !doc.action_groups.contains_key(*name))
+                    .cloned()
+                    .collect(),
+                dry_run,
+                ..Default::default()
+            };
+            svc.modify_target(request(req, metadata)).await?
+        }
+    };
+
+    let target = resp.into_inner().target.unwrap_or_default();
+    Ok(Document::Target(target.into()))
+}
+
+/// Create an actor, or change it to match a document
+async fn put_actor(
+    svc: &GatehouseSvc,
+    headers: &HeaderMap,
+    doc: ActorDoc,
+    current: Option<ActorDoc>,
+    dry_run: bool,
+) -> Result<Document, Status> {
+    let metadata = MetadataMap::from_headers(headers.clone());
+    let resp = match current {
+        None => {
+            let req = AddActorRequest {
+                name: doc.name,
+                typestr: doc.typestr,
+                attributes: attributes(&doc.attributes),
+                dry_run,
+            };
+            svc.add_actor(request(req, metadata)).await?
+        }
+        Some(current) => {
+            let req = ModifyActorRequest {
+                name: doc.name,
+                typestr: doc.typestr,
+                add_attributes: attributes(&missing(&doc.attributes, &current.attributes)),
+                remove_attributes: attributes(&missing(&current.attributes, &doc.attributes)),
+                dry_run,
+                ..Default::default()
+            };
+            svc.modify_actor(request(req, metadata)).await?
+        }
+    };
+
+    let actor = resp.into_inner().actor.unwrap_or_default();
+    Ok(Document::Actor(actor.into()))
+}
+
+/// Create a role, or change it to match a document
+async fn put_role(
+    svc: &GatehouseSvc,
+    headers: &HeaderMap,
+    doc: RoleDoc,
+    exists: bool,
+    dry_run: bool,
+) -> Result<Document, Status> {
+    let metadata = MetadataMap::from_headers(headers.clone());
+    let resp = match exists {
+        false => {
+            let req = AddRoleRequest {
+                name: doc.name,
+                desc: doc.description,
+                dry_run,
+                ..Default::default()
+            };
+            svc.add_role(request(req, metadata)).await?
+        }
+        true => {
+            // an empty description clears it
+            let req = ModifyRoleRequest {
+                name: doc.name,
+                desc: Some(doc.description.unwrap_or_default()),
+                dry_run,
+                ..Default::default()
+            };
+            svc.modify_role(request(req, metadata)).await?
+        }
+    };
+
+    let role = resp.into_inner().role.unwrap_or_default();
+    Ok(Document::Role(role.into()))
+}
+
+/// Create a group, or change it to match a document
+async fn put_group(
+    svc: &GatehouseSvc,
+    headers: &HeaderMap,
+    doc: GroupDoc,
+    current: Option<GroupDoc>,
+    dry_run: bool,
+) -> Result<Document, Status> {
+    let metadata = MetadataMap::from_headers(headers.clone());
+    let members = |members: BTreeSet<&MemberDoc>| {
+        members
+            .into_iter()
+            .cloned()
+            .map(GroupMember::from)
+            .collect()
+    };
+
+    let resp = match current {
+        None => {
+            let req = AddGroupRequest {
+                name: doc.name,
+                desc: doc.description,
+                members: members(doc.members.iter().collect()),
+                roles: doc.roles.into_iter().collect(),
+                dry_run,
+            };
+            svc.add_group(request(req, metadata)).await?
+        }
+        Some(current) => {
+            // an empty description clears it
+            let req = ModifyGroupRequest {
+                name: doc.name,
+                desc: Some(doc.description.unwrap_or_default()),
+                add_members: members(doc.members.difference(&current.members).collect()),
+                remove_members: members(current.members.difference(&doc.members).collect()),
+                add_roles: doc.roles.difference(&current.roles).cloned().collect(),
+                remove_roles: current.roles.difference(&doc.roles).cloned().collect(),
+                dry_run,
+                ..Default::default()
+            };
+            svc.modify_group(request(req, metadata)).await?
+        }
+    };
+
+    let group = resp.into_inner().group.unwrap_or_default();
+    Ok(Document::Group(group.into()))
+}
+
+/// Create a policy, or replace it; returns the proposal made instead, if it needs approval
+async fn put_policy(
+    svc: &GatehouseSvc,
+    headers: &HeaderMap,
+    rule: RegisteredPolicyRule,
+    exists: bool,
+    dry_run: bool,
+) -> Result<(Document, u64), Status> {
+    let metadata = MetadataMap::from_headers(headers.clone());
+    let rule = Some(PolicyRule::from(rule));
+    let resp = match exists {
+        false => {
+            let req = AddPolicyRequest { rule, dry_run };
+            svc.add_policy(request(req, metadata)).await?
+        }
+        true => {
+            let req = ModifyPolicyRequest { rule, dry_run };
+            svc.modify_policy(request(req, metadata)).await?
+        }
+    };
+
+    let resp = resp.into_inner();
+    let rule = resp.rule.unwrap_or_default();
+    Ok((Document::Policy(Box::new(rule.into())), resp.proposal_id))
+}
+
+/// Refuse a change unless the resource is the version the request was planned against
+fn precondition(headers: &HeaderMap, current: Option<&Document>) -> Result<(), &'static str> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|val: &HeaderValue| val.to_str().ok())
+    };
+    let etag = current.map(Document::etag);
+
+    if let Some(tags) = header(IF_MATCH) {
+        let matches = match etag {
+            Some(ref etag) => tags
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag == etag),
+            None => false,
+        };
+        if !matches {
+            return Err("Resource has changed since it was read");
+        }
+    }
+
+    if header(IF_NONE_MATCH).is_some_and(|tags| tags.trim() == "*") && current.is_some() {
+        return Err("Resource already exists");
+    }
+
+    Ok(())
+}
+
+/// Refuse a document whose names don't match those in the path
+fn check_names(names: &[(&String, &String)]) -> Result<(), String> {
+    match names
+        .iter()
+        .find(|(path, doc)| !path.eq_ignore_ascii_case(doc))
+    {
+        Some((path, doc)) => Err(format!("Document names {doc}, but the path names {path}")),
+        None => Ok(()),
+    }
+}
+
+/// A request to the service, with the metadata of the HTTP request
+fn request<T>(req: T, metadata: MetadataMap) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(req);
+    *request.metadata_mut() = metadata;
+    request
+}
+
+/// Build an error response for a failed call
+fn status_error(status: Status) -> Response<Body> {
+    let code = match status.code() {
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error(code, status.message())
+}
+
+/// Build a response with a document and its ETag
+fn document(code: StatusCode, doc: &Document) -> Response<Body> {
+    let body = serde_json::to_value(doc).unwrap_or_default();
+    let mut resp = json_response(code, body);
+    if let Ok(etag) = HeaderValue::from_str(&doc.etag()) {
+        resp.headers_mut().insert(ETAG, etag);
+    }
+    resp
+}
+
+/// Build an error response
+fn error(code: StatusCode, msg: &str) -> Response<Body> {
+    json_response(code, json!({ "error": msg }))
+}
+
+/// Build a response with no body
+fn empty(code: StatusCode) -> Response<Body> {
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = code;
+    resp
+}
+
+/// Build a JSON response
+fn json_response(code: StatusCode, body: Value) -> Response<Body> {
+    let mut resp = Response::new(Body::from(body.to_string()));
+    *resp.status_mut() = code;
+    resp.headers_mut()
+        .insert("content-type", HeaderValue::from_static("application/json"));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::test;
+
+    use crate::StorageType;
+
+    use super::*;
+
+    async fn call(
+        svc: &GatehouseSvc,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> (StatusCode, Option<String>, Value) {
+        let mut req = Request::builder().method(method).uri(uri);
+        for (name, val) in headers {
+            req = req.header(*name, *val);
+        }
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let req = req.body(Body::from(body)).unwrap();
+
+        let resp = handle(svc, &Mutex::new(()), req).await;
+        let code = resp.status();
+        let etag = resp
+            .headers()
+            .get(ETAG)
+            .map(|etag| etag.to_str().unwrap().to_string());
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (
+            code,
+            etag,
+            serde_json::from_slice(&body).unwrap_or_default(),
+        )
+    }
+
+    #[test]
+    async fn test_admin_api() {
+        let svc = GatehouseSvc::new(&StorageType::Nil).await;
+        let uri = "/admin/v1/targets/database/maindb";
+        let target = json!({
+            "type": "database",
+            "name": "maindb",
+            "actions": ["write", "read"],
+            "attributes": { "env": ["prod"] },
+        });
+
+        let (code, _, _) = call(&svc, Method::GET, uri, &[], None).await;
+        assert_eq!(code, StatusCode::NOT_FOUND);
+
+        // a dry run changes nothing
+        let dry_run = format!("{uri}?dry_run=true");
+        let (code, _, _) = call(&svc, Method::PUT, &dry_run, &[], Some(target.clone())).await;
+        assert_eq!(code, StatusCode::CREATED);
+        let (code, _, _) = call(&svc, Method::GET, uri, &[], None).await;
+        assert_eq!(code, StatusCode::NOT_FOUND);
+
+        let create = [("if-none-match", "*")];
+        let (code, etag, body) = call(&svc, Method::PUT, uri, &create, Some(target.clone())).await;
+        assert_eq!(code, StatusCode::CREATED);
+        assert_eq!(body["actions"], json!(["read", "write"]));
+        let etag = etag.unwrap();
+
+        let (code, _, _) = call(&svc, Method::PUT, uri, &create, Some(target)).await;
+        assert_eq!(code, StatusCode::PRECONDITION_FAILED);
+
+        // reading gives the same document and ETag
+        let (code, read_etag, _) = call(&svc, Method::GET, uri, &[], None).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(read_etag.unwrap(), etag);
+
+        // replace it with what was read
+        let replacement = json!({
+            "type": "database",
+            "name": "maindb",
+            "actions": ["read"],
+            "attributes": { "env": ["dev"], "team": ["data"] },
+        });
+        let planned = [("if-match", etag.as_str())];
+        let (code, new_etag, body) =
+            call(&svc, Method::PUT, uri, &planned, Some(replacement.clone())).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["actions"], json!(["read"]));
+        assert_eq!(
+            body["attributes"],
+            json!({ "env": ["dev"], "team": ["data"] })
+        );
+        assert_ne!(new_etag.unwrap(), etag);
+
+        // the version that was planned against is gone
+        let (code, _, _) = call(&svc, Method::PUT, uri, &planned, Some(replacement)).await;
+        assert_eq!(code, StatusCode::PRECONDITION_FAILED);
+        let (code, _, _) = call(&svc, Method::DELETE, uri, &planned, None).await;
+        assert_eq!(code, StatusCode::PRECONDITION_FAILED);
+
+        // the document has to match the path, and can't have fields we don't know
+        let other = json!({ "type": "database", "name": "otherdb" });
+        let (code, _, _) = call(&svc, Method::PUT, uri, &[], Some(other)).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        let unknown = json!({ "type": "database", "name": "maindb", "owner": "me" });
+        let (code, _, _) = call(&svc, Method::PUT, uri, &[], Some(unknown)).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+
+        // groups, with members and roles
+        let (code, _, _) = call(
+            &svc,
+            Method::PUT,
+            "/admin/v1/roles/reader",
+            &[],
+            Some(json!({ "name": "reader", "description": "reads" })),
+        )
+        .await;
+        assert_eq!(code, StatusCode::CREATED);
+        let group = json!({
+            "name": "readers",
+            "members": [{ "type": "user", "name": "kaitlyn" }],
+            "roles": ["reader"],
+        });
+        let (code, _, body) = call(
+            &svc,
+            Method::PUT,
+            "/admin/v1/groups/readers",
+            &[],
+            Some(group),
+        )
+        .await;
+        assert_eq!(code, StatusCode::CREATED);
+        assert_eq!(body["roles"], json!(["reader"]));
+        let (code, _, body) = call(
+            &svc,
+            Method::PUT,
+            "/admin/v1/groups/readers",
+            &[],
+            Some(json!({ "name": "readers", "description": "people who read" })),
+        )
+        .await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["members"], json!([]));
+        assert_eq!(body["roles"], json!([]));
+        assert_eq!(body["description"], "people who read");
+
+        // policies use the file backend's format
+        let policy = json!({
+            "name": "allow-all",
+            "desc": null,
+            "actor_check": null,
+            "env_attributes": [],
+            "target_check": null,
+            "decision": "Allow",
+        });
+        let (code, _, body) = call(
+            &svc,
+            Method::PUT,
+            "/admin/v1/policies/allow-all",
+            &[],
+            Some(policy),
+        )
+        .await;
+        assert_eq!(code, StatusCode::CREATED);
+        assert_eq!(body["decision"], "Allow");
+
+        let (code, _, _) = call(&svc, Method::DELETE, uri, &[], None).await;
+        assert_eq!(code, StatusCode::NO_CONTENT);
+        let (code, _, _) = call(&svc, Method::DELETE, uri, &[], None).await;
+        assert_eq!(code, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,243 @@
+#![warn(missing_docs)]
+
+//! API keys that callers authenticate with
+//!
+//! A key is `ghk_<id>_<secret>`, sent in the `x-gatehouse-api-key` metadata. Only a SHA-256
+//! hash of the secret is stored, so the key is handed out once, when it is created. Each key has
+//! a scope that limits the calls it may make, and can be limited to some namespaces. Each key is
+//! also bound to a principal, who every call made with it is made by.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::proto::base::{ApiKey, ApiKeyScope};
+
+/// the metadata that carries the API key
+pub const API_KEY_METADATA_KEY: &str = "x-gatehouse-api-key";
+
+/// what every key starts with
+const KEY_PREFIX: &str = "ghk_";
+
+/// calls that only ask for decisions
+const CHECK_CALLS: [&str; 6] = [
+    "check",
+    "trace_check",
+    "check_ssh_cert",
+    "request_grant",
+    "verify_grant",
+    "health",
+];
+
+/// calls only replication keys may make, whether or not API keys are required
+pub(crate) const REPLICATION_CALLS: [&str; 1] = ["replicate"];
+
+/// calls, other than `get_` ones, that only read
+const READ_CALLS: [&str; 10] = [
+    "list_proposals",
+    "compile_policies",
+    "print_policies",
+    "find_unused",
+    "coverage_report",
+    "what_if",
+    "test_policies",
+    "sync",
+    "watch",
+    "stream_changes",
+];
+
+/// What an API key may be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Scope {
+    CheckOnly,
+    ReadOnly,
+    Admin,
+    Replication,
+}
+
+impl From<ApiKeyScope> for Scope {
+    fn from(scope: ApiKeyScope) -> Self {
+        match scope {
+            ApiKeyScope::CheckOnly => Self::CheckOnly,
+            ApiKeyScope::ReadOnly => Self::ReadOnly,
+            ApiKeyScope::Admin => Self::Admin,
+            ApiKeyScope::Replication => Self::Replication,
+        }
+    }
+}
+
+impl From<Scope> for ApiKeyScope {
+    fn from(scope: Scope) -> Self {
+        match scope {
+            Scope::CheckOnly => Self::CheckOnly,
+            Scope::ReadOnly => Self::ReadOnly,
+            Scope::Admin => Self::Admin,
+            Scope::Replication => Self::Replication,
+        }
+    }
+}
+
+impl Scope {
+    /// Whether a key with this scope may make a call
+    pub fn allows(&self, method: &str) -> bool {
+        let is_check = CHECK_CALLS.contains(&method);
+        let is_read = is_check
+            || READ_CALLS.contains(&method)
+            || (method.starts_with("get_") && method != "get_api_keys");
+
+        let is_replication = REPLICATION_CALLS.contains(&method);
+
+        match self {
+            Self::CheckOnly => is_check,
+            Self::ReadOnly => is_read,
+            Self::Admin => !is_replication,
+            Self::Replication => is_replication,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RegisteredApiKey {
+    pub id: String,
+    pub name: String,
+    pub scope: Scope,
+    pub namespaces: Vec<String>,
+    /// hex encoded SHA-256 hash of the secret
+    pub hash: String,
+    pub created: u64,
+    /// who calls made with the key are made by; keys issued before principals have none
+    #[serde(default)]
+    pub principal: String,
+}
+
+/// The details of a key; the hash is never handed out
+impl From<RegisteredApiKey> for ApiKey {
+    fn from(key: RegisteredApiKey) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            scope: ApiKeyScope::from(key.scope).into(),
+            namespaces: key.namespaces,
+            created: key.created as i64,
+            principal: key.principal,
+        }
+    }
+}
+
+impl RegisteredApiKey {
+    /// Issue a new key, returning it along with the key to hand out
+    pub fn issue(
+        name: String,
+        scope: Scope,
+        namespaces: Vec<String>,
+        principal: String,
+        created: u64,
+    ) -> Result<(Self, String), String> {
+        let mut random = [0u8; 40];
+        getrandom::getrandom(&mut random)
+            .map_err(|err| format!("Could not generate a key: {err}"))?;
+
+        let id = hex::encode(&random[..8]);
+        let secret = hex::encode(&random[8..]);
+        let key = Self {
+            id: id.clone(),
+            name,
+            scope,
+            namespaces: namespaces
+                .into_iter()
+                .map(|ns| ns.to_ascii_lowercase())
+                .collect(),
+            hash: hash(&secret),
+            created,
+            principal,
+        };
+        Ok((key, format!("{KEY_PREFIX}{id}_{secret}")))
+    }
+
+    /// The `ADMIN` key the server is configured to start with, so the first calls can be made
+    pub fn bootstrap(key: &str, principal: &str, created: u64) -> Result<Self, String> {
+        let (id, secret) =
+            parse(key).ok_or("Not an API key, which looks like ghk_<id>_<secret>")?;
+        if id.is_empty() || secret.is_empty() {
+            return Err(String::from("The key's id and secret cannot be empty"));
+        }
+
+        Ok(Self {
+            id: id.to_string(),
+            name: String::from("bootstrap"),
+            scope: Scope::Admin,
+            namespaces: Vec::new(),
+            hash: hash(secret),
+            created,
+            principal: principal.to_string(),
+        })
+    }
+
+    /// Whether a secret is this key's, without giving away how much of its hash matches
+    pub fn matches(&self, secret: &str) -> bool {
+        hash(secret).as_bytes().ct_eq(self.hash.as_bytes()).into()
+    }
+
+    /// Whether the key may be used in a namespace, or the default one if none
+    pub fn allows_namespace(&self, namespace: Option<&str>) -> bool {
+        match namespace {
+            _ if self.namespaces.is_empty() => true,
+            Some(namespace) => self.namespaces.iter().any(|ns| ns == namespace),
+            None => false,
+        }
+    }
+}
+
+/// The id and secret of a key, if it looks like one
+pub(crate) fn parse(key: &str) -> Option<(&str, &str)> {
+    key.strip_prefix(KEY_PREFIX)?.split_once('_')
+}
+
+/// The hex encoded SHA-256 hash a secret is stored as
+fn hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_key() {
+        let (key, secret) = RegisteredApiKey::issue(
+            String::from("ci"),
+            Scope::ReadOnly,
+            vec![String::from("Staging")],
+            String::from("ci-bot"),
+            0,
+        )
+        .unwrap();
+
+        let (id, secret) = parse(&secret).unwrap();
+        assert_eq!(id, key.id);
+        assert!(key.matches(secret));
+        assert!(!key.matches("wrong"));
+        assert!(parse("not-a-key").is_none());
+
+        assert!(key.allows_namespace(Some("staging")));
+        assert!(!key.allows_namespace(Some("prod")));
+        assert!(!key.allows_namespace(None));
+
+        assert!(Scope::CheckOnly.allows("check"));
+        assert!(!Scope::CheckOnly.allows("get_targets"));
+        assert!(Scope::ReadOnly.allows("get_targets"));
+        assert!(!Scope::ReadOnly.allows("add_target"));
+        assert!(!Scope::ReadOnly.allows("get_api_keys"));
+        assert!(Scope::Admin.allows("create_api_key"));
+        assert!(!Scope::Admin.allows("replicate"));
+        assert!(Scope::Replication.allows("replicate"));
+        assert!(!Scope::Replication.allows("check"));
+
+        let key = RegisteredApiKey::bootstrap("ghk_0123_secret", "admin", 0).unwrap();
+        assert_eq!(key.id, "0123");
+        assert_eq!(key.scope, Scope::Admin);
+        assert!(key.matches("secret"));
+        assert!(RegisteredApiKey::bootstrap("secret", "admin", 0).is_err());
+        assert!(RegisteredApiKey::bootstrap("ghk__secret", "admin", 0).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+#![warn(missing_docs)]
+
+//! The attribute maps of actors, targets, and the environment of checks
+//!
+//! Attribute maps hash with aHash rather than the standard SipHash, which costs a lot for the
+//! short keys checks hash. aHash's keys are picked at random, so callers who choose attribute
+//! names and values still can't predict collisions.
+//!
+//! The names and values of stored attributes are interned, so every entity that has an attribute
+//! or a value shares a single copy of it. Those a check brings are only kept for the check, and
+//! often unique, like request ids, so they aren't.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::intern::intern;
+use crate::proto::common::AttributeValues;
+use crate::risk::RISK_ATTRIBUTE;
+
+/// the actor attribute listing the groups the actor is a member of
+pub const MEMBER_OF: &str = "member-of";
+
+/// the actor attribute listing the roles the actor's groups give it
+pub const HAS_ROLE: &str = "has-role";
+
+/// The actor attributes Gatehouse sets itself, which callers can't register or claim
+pub const SYSTEM_ATTRIBUTES: &[&str] = &[MEMBER_OF, HAS_ROLE, RISK_ATTRIBUTE];
+
+/// Whether Gatehouse sets an actor attribute itself
+pub fn is_system(key: &str) -> bool {
+    SYSTEM_ATTRIBUTES.contains(&key)
+}
+
+/// The environment attributes Gatehouse sets itself, which are dropped from checks
+pub const SYSTEM_ENV_ATTRIBUTES: &[&str] = &[RISK_ATTRIBUTE];
+
+/// Whether Gatehouse sets an environment attribute itself
+pub fn is_system_env(key: &str) -> bool {
+    SYSTEM_ENV_ATTRIBUTES.contains(&key)
+}
+
+/// A map using aHash
+pub type FastMap<K, V> = HashMap<K, V, ahash::RandomState>;
+
+/// A set using aHash
+pub type FastSet<T> = HashSet<T, ahash::RandomState>;
+
+/// An attribute's values
+pub type Values = FastSet<Arc<str>>;
+
+/// Attributes, by interned name
+pub type AttributeMap = FastMap<Arc<str>, Values>;
+
+/// A set of interned values to store, sized for them up front
+pub fn values<I, S>(vals: I) -> Values
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let vals = vals.into_iter();
+    let mut set = Values::with_capacity_and_hasher(vals.size_hint().0, Default::default());
+    set.extend(vals.map(|val| intern(val.as_ref())));
+    set
+}
+
+/// A set of values only kept for a check, which aren't interned
+pub fn check_values<I, S>(vals: I) -> Values
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let vals = vals.into_iter();
+    let mut set = Values::with_capacity_and_hasher(vals.size_hint().0, Default::default());
+    set.extend(vals.map(|val| Arc::from(val.as_ref())));
+    set
+}
+
+/// Attributes from a request to store, interned
+pub fn from_request(attributes: HashMap<String, AttributeValues>) -> AttributeMap {
+    let mut map = AttributeMap::with_capacity_and_hasher(attributes.len(), Default::default());
+    for (name, vals) in &attributes {
+        map.insert(intern(name), values(&vals.values));
+    }
+    map
+}
+
+/// Attributes a check brings, which aren't interned
+pub fn from_check(attributes: &HashMap<String, AttributeValues>) -> AttributeMap {
+    let mut map = AttributeMap::with_capacity_and_hasher(attributes.len(), Default::default());
+    for (name, vals) in attributes {
+        map.insert(Arc::from(name.as_str()), check_values(&vals.values));
+    }
+    map
+}
+
+/// Deserialize attributes with their names and values interned, for `#[serde(deserialize_with)]`
+pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Arc<AttributeMap>, D::Error> {
+    let attributes = HashMap::<String, Vec<String>>::deserialize(deserializer)?;
+    Ok(Arc::new(
+        attributes
+            .iter()
+            .map(|(name, vals)| (intern(name), values(vals)))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interned() {
+        let team = intern("team");
+        assert!(Arc::ptr_eq(&team, &intern("team")));
+        assert_eq!(&*intern("env"), "env");
+
+        let attributes = from_request(HashMap::from([
+            (
+                String::from("team"),
+                AttributeValues {
+                    values: vec![String::from("b"), String::from("a")],
+                },
+            ),
+            (String::from("empty"), AttributeValues { values: vec![] }),
+        ]));
+        assert_eq!(attributes.len(), 2);
+        let (name, vals) = attributes.get_key_value("team").unwrap();
+        assert!(Arc::ptr_eq(name, &team));
+        assert!(vals.contains("a") && vals.contains("b"));
+        assert!(Arc::ptr_eq(vals.get("a").unwrap(), &intern("a")));
+
+        // a check's attributes get their own copies
+        let checked = from_check(&HashMap::from([(
+            String::from("team"),
+            AttributeValues {
+                values: vec![String::from("a")],
+            },
+        )]));
+        let (name, vals) = checked.get_key_value("team").unwrap();
+        assert!(!Arc::ptr_eq(name, &team));
+        assert!(!Arc::ptr_eq(vals.get("a").unwrap(), &intern("a")));
+
+        // loaded attributes share the same copies
+        let loaded: Arc<AttributeMap> = deserialize(&mut serde_json::Deserializer::from_str(
+            r#"{"team": ["a"]}"#,
+        ))
+        .unwrap();
+        let (name, vals) = loaded.get_key_value("team").unwrap();
+        assert!(Arc::ptr_eq(name, &team));
+        assert!(Arc::ptr_eq(vals.get("a").unwrap(), &intern("a")));
+    }
+}
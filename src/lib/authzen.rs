@@ -0,0 +1,226 @@
+#![warn(missing_docs)]
+
+//! An HTTP/JSON endpoint compatible with the AuthZEN (OpenID AuthZ API) evaluation API
+//!
+//! `POST /access/v1/evaluation` requests are mapped onto Gatehouse checks: the subject becomes
+//! the actor, the resource becomes the target, the action name becomes the target action, and
+//! the context becomes the environment attributes.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::header::HeaderValue;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tonic::metadata::MetadataMap;
+use tonic::Code;
+
+use crate::oidc::claim_values;
+use crate::proto::actors::Actor;
+use crate::proto::base::gatehouse_server::Gatehouse;
+use crate::proto::base::CheckRequest;
+use crate::proto::common::AttributeValues;
+use crate::proto::policies::Decide;
+use crate::svc::GatehouseSvc;
+
+/// the path of the evaluation endpoint
+const EVALUATION_PATH: &str = "/access/v1/evaluation";
+
+/// An AuthZEN access evaluation request
+#[derive(Debug, Deserialize)]
+struct EvaluationRequest {
+    subject: Entity,
+    resource: Entity,
+    action: Action,
+    #[serde(default)]
+    context: Map<String, Value>,
+}
+
+/// A subject or resource in an evaluation request
+#[derive(Debug, Deserialize)]
+struct Entity {
+    #[serde(rename = "type")]
+    typestr: String,
+    id: String,
+    #[serde(default)]
+    properties: Map<String, Value>,
+}
+
+/// The action in an evaluation request
+#[derive(Debug, Deserialize)]
+struct Action {
+    name: String,
+}
+
+impl From<EvaluationRequest> for CheckRequest {
+    fn from(req: EvaluationRequest) -> Self {
+        CheckRequest {
+            actor: Some(Actor {
+                name: req.subject.id,
+                typestr: req.subject.typestr,
+                attributes: attributes(&req.subject.properties),
+                ..Default::default()
+            }),
+            env_attributes: attributes(&req.context),
+            target_name: req.resource.id,
+            target_type: req.resource.typestr,
+            target_action: vec![req.action.name],
+            ..Default::default()
+        }
+    }
+}
+
+/// Turn a JSON object into attributes
+fn attributes(props: &Map<String, Value>) -> HashMap<String, AttributeValues> {
+    props
+        .iter()
+        .map(|(key, val)| {
+            let values = claim_values(val);
+            (key.clone(), AttributeValues { values })
+        })
+        .collect()
+}
+
+/// Serve the evaluation endpoint until the server fails
+pub async fn serve(addr: SocketAddr, svc: Arc<GatehouseSvc>) -> Result<(), hyper::Error> {
+    let make_svc = make_service_fn(move |_| {
+        let svc = svc.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let svc = svc.clone();
+                async move { Ok::<_, Infallible>(handle(&svc, req).await) }
+            }))
+        }
+    });
+
+    Server::bind(&addr).serve(make_svc).await
+}
+
+/// Handle a single HTTP request
+async fn handle(svc: &GatehouseSvc, req: Request<Body>) -> Response<Body> {
+    let request_id = req.headers().get("x-request-id").cloned();
+
+    let mut resp = match (req.method(), req.uri().path()) {
+        (&Method::POST, EVALUATION_PATH) => evaluate(svc, req).await,
+        (_, EVALUATION_PATH) => error(StatusCode::METHOD_NOT_ALLOWED, "Use POST"),
+        _ => error(StatusCode::NOT_FOUND, "Not found"),
+    };
+
+    // AuthZEN asks that the request id be echoed back
+    if let Some(request_id) = request_id {
+        resp.headers_mut().insert("x-request-id", request_id);
+    }
+
+    resp
+}
+
+/// Run an evaluation request as a check
+async fn evaluate(svc: &GatehouseSvc, req: Request<Body>) -> Response<Body> {
+    let (parts, body) = req.into_parts();
+
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => return error(StatusCode::BAD_REQUEST, &err.to_string()),
+    };
+    let eval: EvaluationRequest = match serde_json::from_slice(&body) {
+        Ok(eval) => eval,
+        Err(err) => return error(StatusCode::BAD_REQUEST, &err.to_string()),
+    };
+
+    // pass the headers along so bearer tokens can be introspected
+    let mut check = tonic::Request::new(CheckRequest::from(eval));
+    *check.metadata_mut() = MetadataMap::from_headers(parts.headers);
+
+    match svc.check(check).await {
+        Ok(resp) => {
+            let decision = resp.into_inner().decision == i32::from(Decide::Allow);
+            json_response(StatusCode::OK, json!({ "decision": decision }))
+        }
+        Err(status) => {
+            let code = match status.code() {
+                Code::InvalidArgument | Code::FailedPrecondition => StatusCode::BAD_REQUEST,
+                Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            error(code, status.message())
+        }
+    }
+}
+
+/// Build an error response
+fn error(code: StatusCode, msg: &str) -> Response<Body> {
+    json_response(code, json!({ "error": msg }))
+}
+
+/// Build a JSON response
+fn json_response(code: StatusCode, body: Value) -> Response<Body> {
+    let mut resp = Response::new(Body::from(body.to_string()));
+    *resp.status_mut() = code;
+    resp.headers_mut()
+        .insert("content-type", HeaderValue::from_static("application/json"));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::test;
+
+    use crate::proto::policies::{AddPolicyRequest, PolicyRule};
+    use crate::StorageType;
+
+    use super::*;
+
+    async fn post(svc: &GatehouseSvc, body: Value) -> (StatusCode, Value) {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(EVALUATION_PATH)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let resp = handle(svc, req).await;
+        let code = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (code, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    async fn test_evaluation() {
+        let svc = GatehouseSvc::new(&StorageType::Nil).await;
+
+        let eval = json!({
+            "subject": { "type": "user", "id": "alice@example.com", "properties": { "dept": "eng" } },
+            "resource": { "type": "document", "id": "plans" },
+            "action": { "name": "read" },
+            "context": { "time": "morning" },
+        });
+
+        // nothing allows it yet
+        let (code, body) = post(&svc, eval.clone()).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, json!({ "decision": false }));
+
+        let rule = PolicyRule {
+            name: String::from("allow-all"),
+            decision: Decide::Allow.into(),
+            ..Default::default()
+        };
+        svc.add_policy(tonic::Request::new(AddPolicyRequest {
+            rule: Some(rule),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+
+        let (code, body) = post(&svc, eval).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, json!({ "decision": true }));
+
+        // the subject, resource, and action are required
+        let (code, _) = post(&svc, json!({ "subject": { "type": "user", "id": "bob" } })).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+    }
+}
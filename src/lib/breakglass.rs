@@ -0,0 +1,90 @@
+#![warn(missing_docs)]
+
+//! Emergency access that ends on its own
+//!
+//! Break glasses are stored and replicated like everything else, so emergency access outlives a
+//! restart and replicas decide checks the way their primary does.
+
+use serde::{Deserialize, Serialize};
+
+use crate::proto::base::BreakGlass;
+
+/// A break glass registered with Gatehouse
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RegisteredBreakGlass {
+    /// identifies the break glass
+    pub id: u64,
+    /// who broke the glass
+    pub caller: String,
+    /// why the glass was broken
+    pub reason: String,
+    /// the type of the actor allowed everything; empty if a policy is bypassed
+    pub actor_type: String,
+    /// the name of the actor allowed everything; empty if a policy is bypassed
+    pub actor_name: String,
+    /// if set, the only target type the actor is allowed everything on
+    pub target_type: String,
+    /// the deny policy that no longer applies; empty if an actor is elevated
+    pub policy: String,
+    /// when the glass was broken, in seconds since the epoch
+    pub created_at: u64,
+    /// when the access ends, in seconds since the epoch
+    pub expires_at: u64,
+}
+
+impl RegisteredBreakGlass {
+    /// The key the break glass is stored under
+    pub fn key(&self) -> String {
+        self.id.to_string()
+    }
+
+    /// Make sure the break glass names either an actor or a policy, and ends after it starts
+    pub fn validate(&self) -> Result<(), String> {
+        if self.id == 0 {
+            return Err(String::from("Break glass must have an id"));
+        }
+        let actor = !self.actor_type.is_empty() && !self.actor_name.is_empty();
+        let policy = !self.policy.is_empty();
+        if actor == policy {
+            return Err(String::from(
+                "Break glass must name either an actor or a policy",
+            ));
+        }
+        if self.expires_at <= self.created_at {
+            return Err(String::from("Break glass must end after it starts"));
+        }
+        Ok(())
+    }
+}
+
+impl From<BreakGlass> for RegisteredBreakGlass {
+    fn from(break_glass: BreakGlass) -> Self {
+        Self {
+            id: break_glass.id,
+            caller: break_glass.caller,
+            reason: break_glass.reason,
+            actor_type: break_glass.actor_type.to_ascii_lowercase(),
+            actor_name: break_glass.actor_name.to_ascii_lowercase(),
+            target_type: break_glass.target_type,
+            policy: break_glass.policy.to_ascii_lowercase(),
+            created_at: break_glass.created_at,
+            expires_at: break_glass.expires_at,
+        }
+    }
+}
+
+impl From<RegisteredBreakGlass> for BreakGlass {
+    fn from(break_glass: RegisteredBreakGlass) -> Self {
+        Self {
+            id: break_glass.id,
+            caller: break_glass.caller,
+            reason: break_glass.reason,
+            actor_type: break_glass.actor_type,
+            actor_name: break_glass.actor_name,
+            target_type: break_glass.target_type,
+            policy: break_glass.policy,
+            created_at: break_glass.created_at,
+            expires_at: break_glass.expires_at,
+        }
+    }
+}
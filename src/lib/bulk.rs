@@ -0,0 +1,232 @@
+#![warn(missing_docs)]
+
+//! Actors and targets to add in bulk, read from JSON or CSV
+//!
+//! A JSON file holds a list of entities:
+//!
+//! ```json
+//! [
+//!   {"type": "user", "name": "kaitlyn", "attributes": {"team": ["eng"], "level": "3"}},
+//!   {"type": "database", "name": "maindb", "actions": ["read", "write"],
+//!    "action_groups": {"read-ops": ["read"]}}
+//! ]
+//! ```
+//!
+//! A CSV file starts with a header row naming its columns. `type` and `name` are required,
+//! targets can have an `actions` column, and every other column is an attribute. Several values
+//! in a cell are separated by `|`, and an empty cell means the attribute isn't set:
+//!
+//! ```text
+//! type,name,team,level
+//! user,kaitlyn,eng|ops,3
+//! ```
+//!
+//! Fields can be quoted, so values can contain commas, e.g. `"Smith, Jo"`. Action groups can
+//! only be given in JSON.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::proto::actors::AddActorRequest;
+use crate::proto::common::AttributeValues;
+use crate::proto::targets::{ActionGroup, AddTargetRequest};
+
+/// the most actors or targets one `AddActors` or `AddTargets` call can add
+pub const MAX_BULK_ADD: usize = 1_000;
+
+/// An actor or target in a JSON file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entity {
+    #[serde(rename = "type")]
+    typestr: String,
+    name: String,
+    #[serde(default)]
+    attributes: HashMap<String, Values>,
+    #[serde(default)]
+    actions: Vec<String>,
+    #[serde(default)]
+    action_groups: HashMap<String, Vec<String>>,
+}
+
+/// The values of an attribute, which can be written as a single one
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Values {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<Values> for AttributeValues {
+    fn from(vals: Values) -> Self {
+        let values = match vals {
+            Values::One(val) => vec![val],
+            Values::Many(vals) => vals,
+        };
+        AttributeValues { values }
+    }
+}
+
+/// Load the actors in a JSON or CSV file
+pub fn load_actors(path: &Path) -> Result<Vec<AddActorRequest>, String> {
+    let mut actors = Vec::new();
+    for entity in load(path, false)? {
+        if !entity.actions.is_empty() || !entity.action_groups.is_empty() {
+            return Err(format!(
+                "Actor {}/{} can't have actions",
+                entity.typestr, entity.name
+            ));
+        }
+        actors.push(AddActorRequest {
+            name: entity.name,
+            typestr: entity.typestr,
+            attributes: attributes(entity.attributes),
+            dry_run: false,
+        });
+    }
+    Ok(actors)
+}
+
+/// Load the targets in a JSON or CSV file
+pub fn load_targets(path: &Path) -> Result<Vec<AddTargetRequest>, String> {
+    let entities = load(path, true)?;
+    Ok(entities
+        .into_iter()
+        .map(|entity| AddTargetRequest {
+            name: entity.name,
+            typestr: entity.typestr,
+            actions: entity.actions,
+            attributes: attributes(entity.attributes),
+            action_groups: entity
+                .action_groups
+                .into_iter()
+                .map(|(name, actions)| (name, ActionGroup { actions }))
+                .collect(),
+            dry_run: false,
+        })
+        .collect())
+}
+
+/// Load the entities in a file, by its extension
+fn load(path: &Path, targets: bool) -> Result<Vec<Entity>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("Could not read {}: {err}", path.display()))?;
+    let entities = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&contents).map_err(|err| err.to_string()),
+        Some("csv") => parse_csv(&contents, targets),
+        _ => Err(String::from("The file must be .json or .csv")),
+    };
+    entities.map_err(|err| format!("{}: {err}", path.display()))
+}
+
+/// Parse the rows of a CSV file into entities
+fn parse_csv(text: &str, targets: bool) -> Result<Vec<Entity>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes());
+    let header = reader.headers().map_err(|err| err.to_string())?;
+    if header.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !(header.iter().any(|col| col == "type") && header.iter().any(|col| col == "name")) {
+        return Err(String::from("The header must have type and name columns"));
+    }
+
+    let values = |cell: &str| -> Vec<String> {
+        cell.split('|')
+            .map(str::trim)
+            .filter(|val| !val.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    let mut entities = Vec::new();
+    for row in reader.deserialize::<HashMap<String, String>>() {
+        let mut row = row.map_err(|err| err.to_string())?;
+        let actions = match targets {
+            true => row.remove("actions"),
+            false => None,
+        };
+        entities.push(Entity {
+            typestr: row.remove("type").unwrap_or_default(),
+            name: row.remove("name").unwrap_or_default(),
+            attributes: row
+                .into_iter()
+                .filter(|(_, cell)| !cell.is_empty())
+                .map(|(key, cell)| (key, Values::Many(values(&cell))))
+                .collect(),
+            actions: actions.map(|cell| values(&cell)).unwrap_or_default(),
+            action_groups: HashMap::new(),
+        });
+    }
+    Ok(entities)
+}
+
+/// Turn the attributes of an entity into attributes of a request
+fn attributes(attrs: HashMap<String, Values>) -> HashMap<String, AttributeValues> {
+    attrs
+        .into_iter()
+        .map(|(key, vals)| (key, vals.into()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("gatehouse-bulk-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let csv = dir.join("actors.csv");
+        fs::write(
+            &csv,
+            "type,name,team,level\nuser,kaitlyn,eng|ops,3\nuser,rob,,\n",
+        )
+        .unwrap();
+        let actors = load_actors(&csv).unwrap();
+        assert_eq!(actors.len(), 2);
+        assert_eq!(actors[0].name, "kaitlyn");
+        assert_eq!(actors[0].attributes["team"].values, vec!["eng", "ops"]);
+        assert_eq!(actors[0].attributes["level"].values, vec!["3"]);
+        assert!(actors[1].attributes.is_empty());
+
+        let json = dir.join("targets.json");
+        fs::write(
+            &json,
+            r#"[{"type": "database", "name": "maindb", "attributes": {"env": "prod"},
+                 "actions": ["read", "write"], "action_groups": {"read-ops": ["read"]}}]"#,
+        )
+        .unwrap();
+        let targets = load_targets(&json).unwrap();
+        assert_eq!(targets[0].actions, vec!["read", "write"]);
+        assert_eq!(targets[0].attributes["env"].values, vec!["prod"]);
+        assert_eq!(targets[0].action_groups["read-ops"].actions, vec!["read"]);
+        assert!(load_actors(&json).is_err());
+
+        let bad = dir.join("bad.csv");
+        fs::write(&bad, "type,name\nuser,kaitlyn,extra\n").unwrap();
+        assert!(load_actors(&bad)
+            .unwrap_err()
+            .contains("found record with 3 fields"));
+
+        // quoted fields can hold commas
+        let quoted = dir.join("quoted.csv");
+        fs::write(
+            &quoted,
+            "type,name,title\nuser,\"smith, jo\",\"Director, Eng|VP\"\n",
+        )
+        .unwrap();
+        let actors = load_actors(&quoted).unwrap();
+        assert_eq!(actors[0].name, "smith, jo");
+        assert_eq!(
+            actors[0].attributes["title"].values,
+            vec!["Director, Eng", "VP"]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+#![warn(missing_docs)]
+
+//! Signed policy bundles
+//!
+//! A bundle is the complete set of policies, encoded and signed with an Ed25519 key. Applying
+//! one makes the server's policies match it. A server given the public key verifies every
+//! bundle before applying it, and can be made to refuse policy changes made any other way, so
+//! the policies it enforces are always ones the key holder signed.
+//!
+//! Every bundle is signed with a sequence number, and the server stores the sequence of the last
+//! one it applied, so an older bundle can't be applied again to undo a newer one.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::proto::policies::{PolicyBundle, PolicyRule, SignedBundle};
+
+/// Read a base64 encoded 32 byte key
+fn parse_key(kind: &str, text: &str) -> Result<[u8; 32], String> {
+    base64::decode(text.trim())
+        .map_err(|err| format!("Bad {kind} key: {err}"))?
+        .try_into()
+        .map_err(|_| format!("Bad {kind} key: it must be 32 bytes"))
+}
+
+/// Read a base64 encoded secret (signing) key
+pub fn parse_secret_key(text: &str) -> Result<[u8; 32], String> {
+    parse_key("secret", text)
+}
+
+/// Read a base64 encoded public (verifying) key
+pub fn parse_public_key(text: &str) -> Result<[u8; 32], String> {
+    parse_key("public", text)
+}
+
+/// The base64 encoded public key for a secret key
+pub fn public_key(secret: &[u8; 32]) -> String {
+    base64::encode(SigningKey::from_bytes(secret).verifying_key().to_bytes())
+}
+
+/// The last bundle a server applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AppliedBundle {
+    /// the sequence it was signed with
+    pub sequence: u64,
+    /// when it was applied, in seconds since the epoch
+    pub applied_at: u64,
+}
+
+impl AppliedBundle {
+    /// The key the applied bundle is stored under; there is only ever one
+    pub fn key(&self) -> String {
+        String::from("applied")
+    }
+}
+
+/// Bundle and sign a set of policies; the sequence must be higher than that of any bundle
+/// signed before it
+pub fn sign(rules: Vec<PolicyRule>, sequence: u64, secret: &[u8; 32]) -> SignedBundle {
+    let bundle = PolicyBundle { rules, sequence }.encode_to_vec();
+    let signature = SigningKey::from_bytes(secret)
+        .sign(&bundle)
+        .to_bytes()
+        .to_vec();
+    SignedBundle { bundle, signature }
+}
+
+/// The bundle, if it was signed by the holder of the public key
+pub fn verify(signed: &SignedBundle, public: &[u8; 32]) -> Result<PolicyBundle, String> {
+    let signature: [u8; 64] = signed
+        .signature
+        .as_slice()
+        .try_into()
+        .map_err(|_| String::from("Bundle signature must be 64 bytes"))?;
+    VerifyingKey::from_bytes(public)
+        .map_err(|err| format!("Bad public key: {err}"))?
+        .verify_strict(&signed.bundle, &Signature::from_bytes(&signature))
+        .map_err(|_| String::from("Bundle signature does not match"))?;
+
+    PolicyBundle::decode(signed.bundle.as_slice()).map_err(|err| format!("Bad bundle: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let secret = [7u8; 32];
+        let public = parse_public_key(&public_key(&secret)).unwrap();
+        let rules = vec![PolicyRule {
+            name: String::from("readers"),
+            ..Default::default()
+        }];
+
+        let signed = sign(rules.clone(), 3, &secret);
+        let bundle = verify(&signed, &public).unwrap();
+        assert_eq!(bundle.rules, rules);
+        assert_eq!(bundle.sequence, 3);
+
+        // the sequence is signed too
+        let mut replayed = signed.clone();
+        replayed.bundle = PolicyBundle { rules, sequence: 4 }.encode_to_vec();
+        assert!(verify(&replayed, &public).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.bundle.push(0);
+        assert!(verify(&tampered, &public).is_err());
+
+        let other = parse_public_key(&public_key(&[8u8; 32])).unwrap();
+        assert!(verify(&signed, &other).is_err());
+
+        assert!(parse_secret_key("c2hvcnQ=").is_err());
+
+        // RFC 8032 test vector 1
+        let secret = parse_secret_key("nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=").unwrap();
+        assert_eq!(
+            public_key(&secret),
+            "11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="
+        );
+    }
+}
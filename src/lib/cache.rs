@@ -0,0 +1,265 @@
+#![warn(missing_docs)]
+
+//! A client wrapper that caches check decisions
+//!
+//! Decisions are cached for as long as the server allows (see `GATEDECISIONTTL`), keyed by
+//! everything in the check request that can change its decision. Attribute values are treated
+//! as sets, so the order they were given in doesn't matter. Requests that carry metadata, such
+//! as bearer tokens, should not be made through the cache since the metadata is not part of
+//! the key.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use tonic::transport::Channel;
+
+use crate::proto::base::gatehouse_client::GatehouseClient;
+use crate::proto::base::{CheckRequest, CheckResponse};
+use crate::proto::common::AttributeValues;
+
+/// How well the cache is doing
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    /// checks answered from the cache
+    pub hits: u64,
+    /// checks that went to the server
+    pub misses: u64,
+    /// decisions currently cached
+    pub entries: usize,
+}
+
+impl CacheStats {
+    /// the fraction of checks answered from the cache
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}% hit rate), {} entries",
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0,
+            self.entries
+        )
+    }
+}
+
+/// A Gatehouse client that caches check decisions
+pub struct CachingClient {
+    client: GatehouseClient<Channel>,
+    max_entries: usize,
+    /// cached responses by request, along with when they expire
+    entries: HashMap<RequestKey, (CheckResponse, Instant)>,
+    stats: CacheStats,
+}
+
+impl CachingClient {
+    /// Wrap a client, caching at most `max_entries` decisions
+    pub fn new(client: GatehouseClient<Channel>, max_entries: usize) -> Self {
+        Self {
+            client,
+            max_entries,
+            entries: HashMap::new(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Make a check, answering from the cache if we can
+    pub async fn check(&mut self, req: CheckRequest) -> Result<CheckResponse, String> {
+        let key = RequestKey::new(&req);
+        let now = Instant::now();
+
+        match self.entries.get(&key) {
+            Some((resp, expires)) if *expires > now => {
+                self.stats.hits += 1;
+                // the server never saw this check, so it has no correlation id
+                return Ok(CheckResponse {
+                    correlation_id: String::new(),
+                    ..resp.clone()
+                });
+            }
+            Some(_) => {
+                self.entries.remove(&key);
+            }
+            None => {}
+        }
+        self.stats.misses += 1;
+
+        let resp = self
+            .client
+            .check(req)
+            .await
+            .map_err(|err| format!("Failed to check: {err}"))?
+            .into_inner();
+
+        if resp.cache_ttl > 0 && self.max_entries > 0 {
+            self.make_room(now);
+            let expires = now + Duration::from_secs(resp.cache_ttl.into());
+            self.entries.insert(key, (resp.clone(), expires));
+        }
+
+        Ok(resp)
+    }
+
+    /// Forget every cached decision, e.g. after changing policies
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// How well the cache is doing
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            ..self.stats
+        }
+    }
+
+    /// Get the wrapped client for calls that aren't cached
+    pub fn client(&mut self) -> &mut GatehouseClient<Channel> {
+        &mut self.client
+    }
+
+    /// Drop expired decisions and, if we are still full, the one closest to expiring
+    fn make_room(&mut self, now: Instant) {
+        if self.entries.len() < self.max_entries {
+            return;
+        }
+
+        self.entries.retain(|_, (_, expires)| *expires > now);
+
+        if self.entries.len() >= self.max_entries {
+            if let Some(key) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, expires))| *expires)
+                .map(|(key, _)| key.clone())
+            {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+/// A check request reduced to what can change its decision
+///
+/// Attributes and their values are kept sorted and without duplicates, so requests that only
+/// list them in a different order are equal. The correlation id is left out.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct RequestKey {
+    actor: Option<(String, String, Attributes)>,
+    env_attributes: Attributes,
+    target_name: String,
+    target_type: String,
+    target_action: Vec<String>,
+    action_mode: i32,
+    strict: bool,
+    approval_id: u64,
+}
+
+type Attributes = BTreeMap<String, BTreeSet<String>>;
+
+impl RequestKey {
+    /// The key of a check request
+    pub fn new(req: &CheckRequest) -> Self {
+        Self {
+            actor: req.actor.as_ref().map(|actor| {
+                (
+                    actor.name.clone(),
+                    actor.typestr.clone(),
+                    sorted_attributes(&actor.attributes),
+                )
+            }),
+            env_attributes: sorted_attributes(&req.env_attributes),
+            target_name: req.target_name.clone(),
+            target_type: req.target_type.clone(),
+            target_action: req.target_action.clone(),
+            action_mode: req.action_mode,
+            strict: req.strict,
+            approval_id: req.approval_id,
+        }
+    }
+}
+
+/// Attributes in sorted order, with their values as sets
+fn sorted_attributes(attributes: &HashMap<String, AttributeValues>) -> Attributes {
+    attributes
+        .iter()
+        .map(|(key, vals)| (key.clone(), vals.values.iter().cloned().collect()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::actors::Actor;
+
+    use super::*;
+
+    fn attribs(vals: &[(&str, &[&str])]) -> HashMap<String, AttributeValues> {
+        vals.iter()
+            .map(|(key, vals)| {
+                let values = vals.iter().map(|v| v.to_string()).collect();
+                (key.to_string(), AttributeValues { values })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_request_key() {
+        let req = CheckRequest {
+            actor: Some(Actor {
+                name: String::from("jdoe"),
+                typestr: String::from("user"),
+                attributes: attribs(&[("team", &["db", "infra"]), ("region", &["us"])]),
+                ..Default::default()
+            }),
+            target_name: String::from("db"),
+            target_type: String::from("database"),
+            target_action: vec![String::from("read")],
+            ..Default::default()
+        };
+
+        // the order of attribute values doesn't matter
+        let mut reordered = req.clone();
+        reordered.actor.as_mut().unwrap().attributes =
+            attribs(&[("region", &["us"]), ("team", &["infra", "db"])]);
+        assert_eq!(RequestKey::new(&req), RequestKey::new(&reordered));
+
+        // nor does the correlation id
+        let mut other = req.clone();
+        other.correlation_id = String::from("abc123");
+        assert_eq!(RequestKey::new(&req), RequestKey::new(&other));
+
+        // but everything else does
+        let mut other = req.clone();
+        other.target_action = vec![String::from("write")];
+        assert_ne!(RequestKey::new(&req), RequestKey::new(&other));
+
+        let mut other = req.clone();
+        other.env_attributes = attribs(&[("team", &["db", "infra"])]);
+        assert_ne!(RequestKey::new(&req), RequestKey::new(&other));
+
+        let mut other = req.clone();
+        other.strict = true;
+        assert_ne!(RequestKey::new(&req), RequestKey::new(&other));
+
+        let mut other = req.clone();
+        other.approval_id = 7;
+        assert_ne!(RequestKey::new(&req), RequestKey::new(&other));
+
+        let stats = CacheStats {
+            hits: 3,
+            misses: 1,
+            entries: 1,
+        };
+        assert_eq!(stats.hit_rate(), 0.75);
+        assert_eq!(CacheStats::default().hit_rate(), 0.0);
+    }
+}
@@ -0,0 +1,114 @@
+#![warn(missing_docs)]
+
+//! Canary checks run against the loaded policies at startup
+//!
+//! Canaries are policy test cases (see [`crate::policytest`]) read from a YAML file or a
+//! directory of them. They run once the default store has loaded its data, so a broken policy
+//! bundle shows up before the server takes traffic. Each result is logged and reported by the
+//! `Health` RPC, and if the canaries are required, a failure keeps the server from reporting
+//! itself ready.
+
+use std::path::Path;
+
+use flume::Sender;
+use tokio::sync::oneshot::channel;
+
+use crate::msgs::{DsRequest, DsResponse};
+use crate::policytest::load_tests;
+use crate::proto::base::{CanaryStatus, PolicyTestCase, PolicyTestResult, TestPoliciesRequest};
+
+/// Run the canaries in a file or directory against the datastore's current policies
+pub(crate) async fn run(source: &str, dstx: &Sender<DsRequest>) -> CanaryStatus {
+    let mut status = CanaryStatus {
+        source: source.to_string(),
+        ..Default::default()
+    };
+
+    let outcome = match load_tests(Path::new(source)) {
+        Ok(cases) => test(cases, dstx).await,
+        Err(err) => Err(err),
+    };
+    match outcome {
+        Ok(results) => status.results = results,
+        Err(err) => status.error = err,
+    }
+    status.passed = status.error.is_empty() && status.results.iter().all(|r| r.passed);
+
+    for result in &status.results {
+        match result.passed {
+            true => println!("Canary {}: passed", result.name),
+            false => eprintln!(
+                "Canary {}: FAILED, expected {} but got {}",
+                result.name,
+                result.expected(),
+                result.actual()
+            ),
+        }
+    }
+    if !status.error.is_empty() {
+        eprintln!("Canaries from {source} could not run: {}", status.error);
+    }
+    status
+}
+
+/// Check the canaries as policy tests
+async fn test(
+    cases: Vec<PolicyTestCase>,
+    dstx: &Sender<DsRequest>,
+) -> Result<Vec<PolicyTestResult>, String> {
+    let (tx, rx) = channel::<DsResponse>();
+    let req = TestPoliciesRequest {
+        cases,
+        policies: vec![],
+    };
+    dstx.send_async(DsRequest::TestPolicies(req, tx))
+        .await
+        .map_err(|err| err.to_string())?;
+
+    match rx.await {
+        Ok(DsResponse::PolicyTestResults(resp)) => Ok(resp.results),
+        Ok(DsResponse::Error(status)) => Err(status.message().to_string()),
+        Ok(_) => Err(String::from("Got unexpected answer from datastore")),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::config::Config;
+    use crate::ds::Datastore;
+    use crate::StorageType;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run() {
+        let (dstx, _, _) = Datastore::create(&StorageType::Nil, Config::default()).await;
+
+        let dir = std::env::temp_dir().join(format!("gatehouse-canary-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("canaries.yaml");
+        fs::write(
+            &file,
+            "- name: nobody gets in\n  actor: { name: kaitlyn, type: user }\n  \
+             target: { name: db, type: database }\n  actions: [read]\n  expect: deny\n\
+             - name: everybody gets in\n  actor: { name: kaitlyn, type: user }\n  \
+             target: { name: db, type: database }\n  actions: [read]\n  expect: allow\n",
+        )
+        .unwrap();
+
+        let status = run(file.to_str().unwrap(), &dstx).await;
+        assert!(status.error.is_empty());
+        let passed: Vec<bool> = status.results.iter().map(|r| r.passed).collect();
+        assert_eq!(passed, vec![true, false]);
+        assert!(!status.passed);
+
+        let status = run(dir.join("missing.yaml").to_str().unwrap(), &dstx).await;
+        assert!(!status.error.is_empty());
+        assert!(!status.passed);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
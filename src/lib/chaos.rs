@@ -0,0 +1,424 @@
+#![warn(missing_docs)]
+
+//! Fault injection into the storage backend, for testing
+//!
+//! With `GATECHAOS` set, every call to the storage backend goes through a wrapper that can slow
+//! it down and make it fail, so the datastore's handling of partial failures and the circuit
+//! breaker can be tested against any backend, in CI as well as by hand. It is only meant for
+//! testing: a server running with it loses changes on purpose.
+//!
+//! The setting is a comma-separated list of `key=value` pairs:
+//!
+//! * `latency`: milliseconds to delay each affected call by (default 0)
+//! * `fail`: the fraction of affected calls that fail, from 0 to 1 (default 0)
+//! * `every`: make every nth affected call fail as well, for failures that land in the same
+//!   place on every run (default 0, none)
+//! * `ops`: the calls affected, from `save`, `remove`, and `load`, joined with `+` (default all)
+//! * `seed`: the seed for which calls fail (default 1); the same seed fails the same calls, in
+//!   the order they are made
+//!
+//! Calls that persist several changes at once are made one change at a time under the wrapper,
+//! so faults can land partway through them. Pings aren't affected, so the breaker still closes
+//! once calls succeed again.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tonic::async_trait;
+
+use crate::actor::RegisteredActor;
+use crate::apikey::RegisteredApiKey;
+use crate::breakglass::RegisteredBreakGlass;
+use crate::bundle::AppliedBundle;
+use crate::delegation::RegisteredDelegation;
+use crate::group::RegisteredGroup;
+use crate::migrate::Upgraded;
+use crate::policy::RegisteredPolicyRule;
+use crate::policyset::RegisteredPolicySet;
+use crate::proposal::RegisteredProposal;
+use crate::role::RegisteredRole;
+use crate::shard::Typed;
+use crate::storage::{Leadership, Storage};
+use crate::target::RegisteredTarget;
+use crate::webhook::RegisteredWebhook;
+
+/// A kind of call to the storage backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// saving an entity
+    Save,
+    /// removing an entity
+    Remove,
+    /// loading every entity of a kind
+    Load,
+}
+
+impl Op {
+    fn parse(val: &str) -> Result<Self, String> {
+        match val.trim() {
+            "save" => Ok(Self::Save),
+            "remove" => Ok(Self::Remove),
+            "load" => Ok(Self::Load),
+            _ => Err(format!("Unknown storage call: {val}")),
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Save => write!(f, "save"),
+            Self::Remove => write!(f, "remove"),
+            Self::Load => write!(f, "load"),
+        }
+    }
+}
+
+/// The faults to inject into the storage backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosConfig {
+    /// how long to delay each affected call by
+    pub latency: Duration,
+    /// the fraction of affected calls that fail
+    pub fail_rate: f64,
+    /// if not 0, every nth affected call fails too
+    pub every: u64,
+    /// the calls affected
+    pub ops: Vec<Op>,
+    /// the seed for which calls fail
+    pub seed: u64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            fail_rate: 0.0,
+            every: 0,
+            ops: vec![Op::Save, Op::Remove, Op::Load],
+            seed: 1,
+        }
+    }
+}
+
+impl ChaosConfig {
+    /// Parse the faults from a list like `latency=50,fail=0.1,ops=save+remove`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        for pair in spec.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (key, val) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key=value in chaos setting: {pair}"))?;
+            let val = val.trim();
+            let number = |val: &str| {
+                val.parse::<u64>()
+                    .map_err(|_| format!("Bad number for {}: {val}", key.trim()))
+            };
+            match key.trim() {
+                "latency" => config.latency = Duration::from_millis(number(val)?),
+                "fail" => {
+                    config.fail_rate = val
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|rate| (0.0..=1.0).contains(rate))
+                        .ok_or_else(|| format!("Failure rate should be from 0 to 1: {val}"))?
+                }
+                "every" => config.every = number(val)?,
+                "ops" => {
+                    config.ops = val.split('+').map(Op::parse).collect::<Result<_, _>>()?;
+                }
+                "seed" => config.seed = number(val)?,
+                key => return Err(format!("Unknown chaos setting: {key}")),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl Display for ChaosConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ops: Vec<String> = self.ops.iter().map(Op::to_string).collect();
+        write!(
+            f,
+            "{} calls delayed {}ms, {}% failing",
+            ops.join("/"),
+            self.latency.as_millis(),
+            self.fail_rate * 100.0
+        )?;
+        if self.every > 0 {
+            write!(f, " and every {}th", self.every)?;
+        }
+        write!(f, " (seed {})", self.seed)
+    }
+}
+
+/// A storage backend that delays and fails calls as configured
+pub(crate) struct ChaosStorage {
+    inner: Box<dyn Storage + Send + Sync>,
+    config: ChaosConfig,
+    /// the state of the generator deciding which calls fail
+    state: AtomicU64,
+    /// how many affected calls there have been
+    calls: AtomicU64,
+}
+
+impl ChaosStorage {
+    pub(crate) fn new(inner: Box<dyn Storage + Send + Sync>, config: ChaosConfig) -> Self {
+        // spread the seed's bits out, and keep the state odd since xorshift gets stuck at 0
+        let state = AtomicU64::new(config.seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1);
+        Self {
+            inner,
+            config,
+            state,
+            calls: AtomicU64::new(0),
+        }
+    }
+
+    /// The next number from the generator, from 0 to 1
+    fn next_random(&self) -> f64 {
+        let step = |mut x: u64| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        let prev = self
+            .state
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+            .unwrap_or_default();
+        (step(prev) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Delay a call, then decide whether it fails
+    async fn inject(&self, op: Op, call: &str) -> Result<(), String> {
+        if !self.config.ops.contains(&op) {
+            return Ok(());
+        }
+        if !self.config.latency.is_zero() {
+            sleep(self.config.latency).await;
+        }
+
+        let count = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        let every = self.config.every > 0 && count.is_multiple_of(self.config.every);
+        let random = self.config.fail_rate > 0.0 && self.next_random() < self.config.fail_rate;
+        match every || random {
+            true => Err(format!("Injected failure of {call}")),
+            false => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for ChaosStorage {
+    async fn save_target(&self, tgt: &RegisteredTarget) -> Result<(), String> {
+        self.inject(Op::Save, "save_target").await?;
+        self.inner.save_target(tgt).await
+    }
+    async fn remove_target(&self, typestr: &str, name: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_target").await?;
+        self.inner.remove_target(typestr, name).await
+    }
+    async fn load_targets(&self) -> Result<Typed<RegisteredTarget>, String> {
+        self.inject(Op::Load, "load_targets").await?;
+        self.inner.load_targets().await
+    }
+    async fn save_actor(&self, tgt: &RegisteredActor) -> Result<(), String> {
+        self.inject(Op::Save, "save_actor").await?;
+        self.inner.save_actor(tgt).await
+    }
+    async fn remove_actor(&self, typestr: &str, name: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_actor").await?;
+        self.inner.remove_actor(typestr, name).await
+    }
+    async fn load_actors(&self) -> Result<Typed<RegisteredActor>, String> {
+        self.inject(Op::Load, "load_actors").await?;
+        self.inner.load_actors().await
+    }
+    async fn save_role(&self, role: &RegisteredRole) -> Result<(), String> {
+        self.inject(Op::Save, "save_role").await?;
+        self.inner.save_role(role).await
+    }
+    async fn remove_role(&self, name: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_role").await?;
+        self.inner.remove_role(name).await
+    }
+    async fn load_roles(&self) -> Result<HashMap<String, RegisteredRole>, String> {
+        self.inject(Op::Load, "load_roles").await?;
+        self.inner.load_roles().await
+    }
+    async fn save_group(&self, group: &RegisteredGroup) -> Result<(), String> {
+        self.inject(Op::Save, "save_group").await?;
+        self.inner.save_group(group).await
+    }
+    async fn remove_group(&self, name: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_group").await?;
+        self.inner.remove_group(name).await
+    }
+    async fn load_groups(&self) -> Result<HashMap<String, RegisteredGroup>, String> {
+        self.inject(Op::Load, "load_groups").await?;
+        self.inner.load_groups().await
+    }
+    async fn save_policy(&self, policy: &RegisteredPolicyRule) -> Result<(), String> {
+        self.inject(Op::Save, "save_policy").await?;
+        self.inner.save_policy(policy).await
+    }
+    async fn remove_policy(&self, name: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_policy").await?;
+        self.inner.remove_policy(name).await
+    }
+    async fn load_policies(&self) -> Result<HashMap<String, RegisteredPolicyRule>, String> {
+        self.inject(Op::Load, "load_policies").await?;
+        self.inner.load_policies().await
+    }
+    async fn save_policy_set(&self, set: &RegisteredPolicySet) -> Result<(), String> {
+        self.inject(Op::Save, "save_policy_set").await?;
+        self.inner.save_policy_set(set).await
+    }
+    async fn remove_policy_set(&self, name: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_policy_set").await?;
+        self.inner.remove_policy_set(name).await
+    }
+    async fn load_policy_sets(&self) -> Result<HashMap<String, RegisteredPolicySet>, String> {
+        self.inject(Op::Load, "load_policy_sets").await?;
+        self.inner.load_policy_sets().await
+    }
+    async fn save_webhook(&self, hook: &RegisteredWebhook) -> Result<(), String> {
+        self.inject(Op::Save, "save_webhook").await?;
+        self.inner.save_webhook(hook).await
+    }
+    async fn remove_webhook(&self, name: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_webhook").await?;
+        self.inner.remove_webhook(name).await
+    }
+    async fn load_webhooks(&self) -> Result<HashMap<String, RegisteredWebhook>, String> {
+        self.inject(Op::Load, "load_webhooks").await?;
+        self.inner.load_webhooks().await
+    }
+    async fn save_api_key(&self, key: &RegisteredApiKey) -> Result<(), String> {
+        self.inject(Op::Save, "save_api_key").await?;
+        self.inner.save_api_key(key).await
+    }
+    async fn remove_api_key(&self, id: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_api_key").await?;
+        self.inner.remove_api_key(id).await
+    }
+    async fn load_api_keys(&self) -> Result<HashMap<String, RegisteredApiKey>, String> {
+        self.inject(Op::Load, "load_api_keys").await?;
+        self.inner.load_api_keys().await
+    }
+    async fn save_delegation(&self, delegation: &RegisteredDelegation) -> Result<(), String> {
+        self.inject(Op::Save, "save_delegation").await?;
+        self.inner.save_delegation(delegation).await
+    }
+    async fn remove_delegation(&self, name: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_delegation").await?;
+        self.inner.remove_delegation(name).await
+    }
+    async fn load_delegations(&self) -> Result<HashMap<String, RegisteredDelegation>, String> {
+        self.inject(Op::Load, "load_delegations").await?;
+        self.inner.load_delegations().await
+    }
+    async fn save_proposal(&self, proposal: &RegisteredProposal) -> Result<(), String> {
+        self.inject(Op::Save, "save_proposal").await?;
+        self.inner.save_proposal(proposal).await
+    }
+    async fn remove_proposal(&self, id: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_proposal").await?;
+        self.inner.remove_proposal(id).await
+    }
+    async fn load_proposals(&self) -> Result<HashMap<String, RegisteredProposal>, String> {
+        self.inject(Op::Load, "load_proposals").await?;
+        self.inner.load_proposals().await
+    }
+    async fn save_break_glass(&self, break_glass: &RegisteredBreakGlass) -> Result<(), String> {
+        self.inject(Op::Save, "save_break_glass").await?;
+        self.inner.save_break_glass(break_glass).await
+    }
+    async fn remove_break_glass(&self, id: &str) -> Result<(), String> {
+        self.inject(Op::Remove, "remove_break_glass").await?;
+        self.inner.remove_break_glass(id).await
+    }
+    async fn load_break_glasses(&self) -> Result<HashMap<String, RegisteredBreakGlass>, String> {
+        self.inject(Op::Load, "load_break_glasses").await?;
+        self.inner.load_break_glasses().await
+    }
+    async fn save_bundle(&self, bundle: &AppliedBundle) -> Result<(), String> {
+        self.inject(Op::Save, "save_bundle").await?;
+        self.inner.save_bundle(bundle).await
+    }
+    async fn load_bundle(&self) -> Result<Option<AppliedBundle>, String> {
+        self.inject(Op::Load, "load_bundle").await?;
+        self.inner.load_bundle().await
+    }
+    async fn ping(&self) -> Result<(), String> {
+        self.inner.ping().await
+    }
+    fn upgraded(&self) -> Vec<Upgraded> {
+        self.inner.upgraded()
+    }
+    fn leadership(&self) -> watch::Receiver<Leadership> {
+        self.inner.leadership()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::role::RegisteredRole;
+    use crate::storage::nil::NilStorage;
+    use crate::storage::BackendUpdate;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_chaos() {
+        assert_eq!(ChaosConfig::parse("").unwrap(), ChaosConfig::default());
+        let config = ChaosConfig::parse("latency=5, fail=0.5, ops=save+remove, seed=7").unwrap();
+        assert_eq!(config.latency, Duration::from_millis(5));
+        assert_eq!(config.ops, vec![Op::Save, Op::Remove]);
+        assert!(ChaosConfig::parse("fail=2").is_err());
+        assert!(ChaosConfig::parse("ops=truncate").is_err());
+        assert!(ChaosConfig::parse("latency").is_err());
+
+        // affected calls are delayed, and fail at about the rate asked for
+        let chaos = ChaosStorage::new(Box::new(NilStorage), config.clone());
+        let role = RegisteredRole::new("admin", None);
+        let started = Instant::now();
+        let mut failures = Vec::new();
+        for _ in 0..100 {
+            failures.push(chaos.save_role(&role).await.is_err());
+        }
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        let failed = failures.iter().filter(|failed| **failed).count();
+        assert!((30..=70).contains(&failed), "{failed} failed");
+        assert!(chaos.load_roles().await.is_ok());
+
+        // the same seed fails the same calls
+        let again = ChaosStorage::new(
+            Box::new(NilStorage),
+            ChaosConfig {
+                latency: Duration::ZERO,
+                ..config
+            },
+        );
+        for failed in failures {
+            assert_eq!(again.save_role(&role).await.is_err(), failed);
+        }
+
+        // changes are persisted one at a time, so failures can land partway through
+        let config = ChaosConfig::parse("every=2").unwrap();
+        let chaos = ChaosStorage::new(Box::new(NilStorage), config);
+        let updates = vec![
+            BackendUpdate::PutRole(role.clone()),
+            BackendUpdate::DeleteRole(String::from("reader")),
+        ];
+        let err = chaos.persist_changes(&updates).await.unwrap_err();
+        assert_eq!(err.saved, 1);
+        assert_eq!(err.err, "Injected failure of remove_role");
+    }
+}
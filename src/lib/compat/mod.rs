@@ -0,0 +1,6 @@
+#![warn(missing_docs)]
+
+//! Conversion between Gatehouse and other authorization systems
+
+pub mod spicedb;
+pub mod xacml;
@@ -0,0 +1,196 @@
+#![warn(missing_docs)]
+
+//! Export of group memberships and role grants as a SpiceDB schema and relationships
+//!
+//! Gatehouse has no relationship tuples of its own, but its groups and roles are relations:
+//! actors are members of groups, and roles are granted to groups. These become a `group`
+//! definition with a `member` relation, and a `role` definition with a `granted` relation to
+//! group members and a `has` permission. Each actor type becomes a definition named
+//! `actor_<type>`, so SpiceDB can be asked, for instance, whether `actor_user:kaitlyn` has
+//! permission `has` on `role:admin`, and the answer compared with the `has-role` attributes
+//! Gatehouse gives the actor. Policies decide on attributes, which SpiceDB schemas can't express,
+//! so they are not exported.
+//!
+//! Names are kept as they are where SpiceDB allows, and any other character is written as `=`
+//! followed by its UTF-8 bytes in hex, so `kaitlyn@example.com` becomes
+//! `kaitlyn=40example=2ecom`. The export is a validation file, which `zed validate` checks and `zed import` loads.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::proto::base::SyncResponse;
+
+/// A SpiceDB schema and the relationships written against it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SpiceDbExport {
+    /// the schema, in the SpiceDB schema language
+    pub schema: String,
+    /// one relationship per line, e.g. `group:admins#member@actor_user:kaitlyn`
+    pub relationships: String,
+}
+
+impl SpiceDbExport {
+    /// The export as a SpiceDB validation file
+    pub fn to_yaml(&self) -> Result<String, String> {
+        serde_yaml::to_string(self).map_err(|err| err.to_string())
+    }
+}
+
+/// Export the groups and roles of a full state, as returned by `Sync`
+pub fn export(state: &SyncResponse) -> Result<SpiceDbExport, String> {
+    // the definition each actor type becomes, and which type it came from
+    let mut types: BTreeMap<String, String> = BTreeMap::new();
+    let mut relationships = BTreeSet::new();
+
+    for group in &state.groups {
+        let group_id = object_id(&group.name);
+        for member in &group.members {
+            let definition = actor_definition(&member.typestr)?;
+            if let Some(other) = types.insert(definition.clone(), member.typestr.clone()) {
+                if !other.eq_ignore_ascii_case(&member.typestr) {
+                    return Err(format!(
+                        "Actor types {other} and {} would both be exported as {definition}",
+                        member.typestr
+                    ));
+                }
+            }
+            relationships.insert(format!(
+                "group:{group_id}#member@{definition}:{}",
+                object_id(&member.name)
+            ));
+        }
+        for role in &group.roles {
+            relationships.insert(format!(
+                "role:{}#granted@group:{group_id}#member",
+                object_id(role)
+            ));
+        }
+    }
+
+    let mut schema = String::new();
+    for definition in types.keys() {
+        schema.push_str(&format!("definition {definition} {{}}\n\n"));
+    }
+    let members = match types.is_empty() {
+        // a relation needs at least one type, even with no members to relate
+        true => String::from("group#member"),
+        false => types.keys().cloned().collect::<Vec<_>>().join(" | "),
+    };
+    schema.push_str(&format!(
+        "definition group {{\n    relation member: {members}\n}}\n\n"
+    ));
+    schema.push_str(
+        "definition role {\n    relation granted: group#member\n    permission has = granted\n}\n",
+    );
+
+    let relationships = relationships
+        .into_iter()
+        .map(|rel| rel + "\n")
+        .collect::<String>();
+
+    Ok(SpiceDbExport {
+        schema,
+        relationships,
+    })
+}
+
+/// The definition an actor type becomes
+fn actor_definition(typestr: &str) -> Result<String, String> {
+    let name: String = typestr
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .collect();
+    let name = name.trim_matches('_');
+    if name.is_empty() || name.len() > 56 {
+        return Err(format!("Actor type {typestr} can't be exported"));
+    }
+    Ok(format!("actor_{name}"))
+}
+
+/// A name as a SpiceDB object id
+fn object_id(name: &str) -> String {
+    let mut id = String::new();
+    for c in name.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '/' | '_' | '|' | '-' | '+' => id.push(c),
+            _ => {
+                let mut buf = [0; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    id.push_str(&format!("={byte:02x}"));
+                }
+            }
+        }
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::groups::{Group, GroupMember};
+
+    fn member(name: &str, typestr: &str) -> GroupMember {
+        GroupMember {
+            name: name.to_string(),
+            typestr: typestr.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let state = SyncResponse {
+            groups: vec![
+                Group {
+                    name: "admins".to_string(),
+                    members: vec![
+                        member("kaitlyn@example.com", "user"),
+                        member("deployer", "Service Account"),
+                    ],
+                    roles: vec!["admin".to_string()],
+                    ..Default::default()
+                },
+                Group {
+                    name: "readers".to_string(),
+                    members: vec![member("kaitlyn@example.com", "user")],
+                    roles: vec!["reader".to_string()],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let exported = export(&state).unwrap();
+        assert!(exported.schema.contains("definition actor_user {}"));
+        assert!(exported
+            .schema
+            .contains("relation member: actor_service_account | actor_user"));
+        assert_eq!(
+            exported.relationships,
+            "group:admins#member@actor_service_account:deployer\n\
+             group:admins#member@actor_user:kaitlyn=40example=2ecom\n\
+             group:readers#member@actor_user:kaitlyn=40example=2ecom\n\
+             role:admin#granted@group:admins#member\n\
+             role:reader#granted@group:readers#member\n"
+        );
+        assert!(exported.to_yaml().unwrap().starts_with("schema: |"));
+
+        // two types that can't be told apart once exported
+        let state = SyncResponse {
+            groups: vec![Group {
+                name: "admins".to_string(),
+                members: vec![
+                    member("a", "service-account"),
+                    member("b", "service_account"),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(export(&state).is_err());
+    }
+}
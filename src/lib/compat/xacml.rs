@@ -0,0 +1,534 @@
+#![warn(missing_docs)]
+
+//! Import of XACML 3.0 policies
+//!
+//! Each XACML rule becomes a Gatehouse policy rule named after its policy and rule ids, which
+//! allows or denies as its effect says, and which checks everything the targets of the rule and
+//! of the policies and policy sets around it match on. Only what Gatehouse can express is
+//! supported, and anything else is refused rather than imported with a different meaning:
+//!
+//! * rule and policy combining must be deny-overrides, which is how Gatehouse combines policies
+//! * matches must be `string-equal`; an `AnyOf` with several `AllOf`s must match the same
+//!   attribute in each, so it becomes a check for one of several values
+//! * conditions can combine with `and` the functions `string-equal` (of `string-one-and-only`),
+//!   `string-is-in`, `any-of` with `string-equal`, and `string-at-least-one-member-of`
+//! * attributes of the access subject, resource, action, and environment are supported; the
+//!   standard `subject-id`, `resource-id`, and `action-id` become the actor name, target name,
+//!   and action, `resource-type` becomes the target type, and anything else becomes an
+//!   attribute named after the last part of its id
+//! * obligations, variables, references, and attribute selectors are not supported
+//!
+//! Advice is ignored, since it never changes a decision.
+
+use std::collections::HashSet;
+
+use roxmltree::{Document, Node};
+
+use crate::proto::policies::{
+    ActorCheck, Decide, Kv, KvCheck, PolicyRule, Set, StringCheck, TargetCheck,
+};
+
+const SUBJECT_ID: &str = "urn:oasis:names:tc:xacml:1.0:subject:subject-id";
+const RESOURCE_ID: &str = "urn:oasis:names:tc:xacml:1.0:resource:resource-id";
+const ACTION_ID: &str = "urn:oasis:names:tc:xacml:1.0:action:action-id";
+
+/// Convert XACML policies, in a `Policy` or `PolicySet` document, into policy rules
+pub fn import(xml: &str) -> Result<Vec<PolicyRule>, String> {
+    let doc = Document::parse(xml).map_err(|err| format!("Invalid XML: {err}"))?;
+    let root = doc.root_element();
+
+    let mut rules = Vec::new();
+    match root.tag_name().name() {
+        "PolicySet" => policy_set(root, &Checks::default(), &mut rules)?,
+        "Policy" => policy(root, &Checks::default(), &mut rules)?,
+        other => return Err(format!("Expected a Policy or PolicySet, not {other}")),
+    }
+
+    let mut names = HashSet::new();
+    for rule in &rules {
+        if !names.insert(rule.name.to_ascii_lowercase()) {
+            return Err(format!("More than one rule would be named {}", rule.name));
+        }
+    }
+
+    Ok(rules)
+}
+
+/// What a rule checks, gathered from its target and condition and those of its parents
+#[derive(Debug, Clone, Default)]
+struct Checks {
+    actor: ActorCheck,
+    target: TargetCheck,
+    env: Vec<KvCheck>,
+}
+
+/// What an attribute designator refers to
+#[derive(Debug, Clone, PartialEq)]
+enum Attribute {
+    ActorName,
+    Actor(String),
+    TargetName,
+    TargetType,
+    Target(String),
+    Action,
+    Env(String),
+}
+
+impl Checks {
+    /// Require an attribute to have one of the values
+    fn add(&mut self, attribute: Attribute, vals: Vec<String>) -> Result<(), String> {
+        let has = |key: String| KvCheck {
+            key,
+            op: Kv::Has.into(),
+            vals: vals.clone(),
+            ..Default::default()
+        };
+
+        match attribute {
+            Attribute::ActorName => one_of(&mut self.actor.name, vals, "subject-id"),
+            Attribute::TargetName => one_of(&mut self.target.name, vals, "resource-id"),
+            Attribute::TargetType => one_of(&mut self.target.typestr, vals, "resource-type"),
+            Attribute::Action => one_of(&mut self.target.action, vals, "action-id"),
+            Attribute::Actor(key) => {
+                self.actor.attributes.push(has(key));
+                Ok(())
+            }
+            Attribute::Target(key) => {
+                self.target.attributes.push(has(key));
+                Ok(())
+            }
+            Attribute::Env(key) => {
+                self.env.push(has(key));
+                Ok(())
+            }
+        }
+    }
+
+    /// A policy rule with these checks
+    fn rule(self, name: String, desc: Option<String>, decision: Decide) -> PolicyRule {
+        let actor_check = match self.actor == ActorCheck::default() {
+            true => None,
+            false => Some(self.actor),
+        };
+        let target_check = match self.target == TargetCheck::default() {
+            true => None,
+            false => Some(self.target),
+        };
+
+        PolicyRule {
+            name,
+            desc,
+            actor_check,
+            env_attributes: self.env,
+            target_check,
+            decision: decision.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Require a string to be one of the values, along with whatever was required before
+fn one_of(check: &mut Option<StringCheck>, vals: Vec<String>, what: &str) -> Result<(), String> {
+    let vals = match check.take() {
+        Some(existing) => {
+            let both: Vec<String> = existing
+                .vals
+                .into_iter()
+                .filter(|val| vals.contains(val))
+                .collect();
+            if both.is_empty() {
+                return Err(format!("Matches on {what} can never all be true"));
+            }
+            both
+        }
+        None => vals,
+    };
+
+    *check = Some(StringCheck {
+        val_cmp: Set::Has.into(),
+        vals,
+    });
+    Ok(())
+}
+
+/// The child elements with a name
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// The element children of a node
+fn elements<'a, 'input>(node: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(|child| child.is_element())
+}
+
+/// An attribute of an element, which must be there
+fn attr<'a>(node: Node<'a, '_>, name: &str) -> Result<&'a str, String> {
+    node.attribute(name)
+        .ok_or_else(|| format!("{} is missing its {name} attribute", node.tag_name().name()))
+}
+
+/// The last part of a URN or URL, e.g. `role` for `urn:example:subject:role`
+fn short(id: &str) -> &str {
+    id.rsplit([':', '/', '#']).next().unwrap_or(id)
+}
+
+/// A name for a policy rule made of ids, with anything but letters, numbers, `-`, `_` and `.`
+/// replaced so it can be stored as a file name
+fn rule_name(policy_id: &str, rule_id: &str) -> String {
+    let clean = |id: &str| -> String {
+        id.chars()
+            .map(|c| match c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                true => c,
+                false => '-',
+            })
+            .collect()
+    };
+    format!("{}.{}", clean(policy_id), clean(rule_id))
+}
+
+/// Only deny-overrides combines the way Gatehouse does
+fn check_combining(node: Node, name: &str) -> Result<(), String> {
+    let alg = attr(node, name)?;
+    match short(alg) {
+        "deny-overrides" | "ordered-deny-overrides" => Ok(()),
+        _ => Err(format!(
+            "{} uses {alg}; only deny-overrides is supported",
+            node.tag_name().name()
+        )),
+    }
+}
+
+/// Refuse what we can't import without changing its meaning
+fn check_supported(node: Node) -> Result<(), String> {
+    for child in elements(node) {
+        let name = child.tag_name().name();
+        if matches!(
+            name,
+            "ObligationExpressions"
+                | "VariableDefinition"
+                | "PolicyIdReference"
+                | "PolicySetIdReference"
+                | "CombinerParameters"
+                | "RuleCombinerParameters"
+                | "PolicyCombinerParameters"
+                | "PolicySetCombinerParameters"
+        ) {
+            return Err(format!("{name} is not supported"));
+        }
+    }
+    Ok(())
+}
+
+/// The text of the description of an element, if it has one
+fn description(node: Node) -> Option<String> {
+    children(node, "Description")
+        .next()
+        .and_then(|desc| desc.text())
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+fn policy_set(node: Node, parent: &Checks, rules: &mut Vec<PolicyRule>) -> Result<(), String> {
+    check_combining(node, "PolicyCombiningAlgId")?;
+    check_supported(node)?;
+
+    let mut checks = parent.clone();
+    target(node, &mut checks)?;
+
+    for child in elements(node) {
+        match child.tag_name().name() {
+            "PolicySet" => policy_set(child, &checks, rules)?,
+            "Policy" => policy(child, &checks, rules)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn policy(node: Node, parent: &Checks, rules: &mut Vec<PolicyRule>) -> Result<(), String> {
+    let policy_id = attr(node, "PolicyId")?;
+    check_combining(node, "RuleCombiningAlgId")?;
+    check_supported(node)?;
+
+    let mut checks = parent.clone();
+    target(node, &mut checks)?;
+
+    for rule in children(node, "Rule") {
+        let rule_id = attr(rule, "RuleId")?;
+        let decision = match attr(rule, "Effect")? {
+            "Permit" => Decide::Allow,
+            "Deny" => Decide::Deny,
+            other => return Err(format!("Rule {rule_id} has an unknown effect: {other}")),
+        };
+        check_supported(rule)?;
+
+        let mut checks = checks.clone();
+        target(rule, &mut checks).map_err(|err| format!("Rule {rule_id}: {err}"))?;
+        if let Some(condition) = children(rule, "Condition").next() {
+            for expr in elements(condition) {
+                apply(expr, &mut checks).map_err(|err| format!("Rule {rule_id}: {err}"))?;
+            }
+        }
+
+        let name = rule_name(policy_id, rule_id);
+        rules.push(checks.rule(name, description(rule), decision));
+    }
+    Ok(())
+}
+
+/// Add what the target of a policy set, policy, or rule matches on
+fn target(node: Node, checks: &mut Checks) -> Result<(), String> {
+    let target = match children(node, "Target").next() {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+
+    for any_of in children(target, "AnyOf") {
+        let all_ofs: Vec<Node> = children(any_of, "AllOf").collect();
+
+        // every match in a single AllOf has to hold
+        if all_ofs.len() == 1 {
+            for m in children(all_ofs[0], "Match") {
+                let (attribute, val) = match_of(m)?;
+                checks.add(attribute, vec![val])?;
+            }
+            continue;
+        }
+
+        // any of several AllOfs can hold, so they must be values of one attribute
+        let mut found: Option<Attribute> = None;
+        let mut vals = Vec::new();
+        for all_of in all_ofs {
+            let matches: Vec<Node> = children(all_of, "Match").collect();
+            if matches.len() != 1 {
+                return Err(String::from(
+                    "An AnyOf with several AllOfs can only have one Match in each",
+                ));
+            }
+            let (attribute, val) = match_of(matches[0])?;
+            if found.as_ref().is_some_and(|found| *found != attribute) {
+                return Err(String::from(
+                    "An AnyOf with several AllOfs must match the same attribute in each",
+                ));
+            }
+            found = Some(attribute);
+            vals.push(val);
+        }
+        if let Some(attribute) = found {
+            checks.add(attribute, vals)?;
+        }
+    }
+    Ok(())
+}
+
+/// The attribute and value of a `string-equal` match
+fn match_of(node: Node) -> Result<(Attribute, String), String> {
+    let function = attr(node, "MatchId")?;
+    if short(function) != "string-equal" {
+        return Err(format!("Match function {function} is not supported"));
+    }
+
+    let val = children(node, "AttributeValue")
+        .next()
+        .ok_or("Match has no AttributeValue")?;
+    let designator = children(node, "AttributeDesignator")
+        .next()
+        .ok_or("Match has no AttributeDesignator")?;
+    Ok((attribute(designator)?, value(val)))
+}
+
+/// Add what a condition expression requires
+fn apply(node: Node, checks: &mut Checks) -> Result<(), String> {
+    if node.tag_name().name() != "Apply" {
+        return Err(format!(
+            "{} is not supported in a condition",
+            node.tag_name().name()
+        ));
+    }
+
+    let function = attr(node, "FunctionId")?;
+    let args: Vec<Node> = elements(node)
+        .filter(|arg| arg.tag_name().name() != "Description")
+        .collect();
+    let arg = |name: &str| {
+        args.iter()
+            .find(|arg| arg.tag_name().name() == name)
+            .copied()
+    };
+    let unsupported = || format!("{function} is only supported on a value and an attribute");
+
+    match short(function) {
+        "and" => {
+            for arg in args {
+                apply(arg, checks)?;
+            }
+            Ok(())
+        }
+        "string-is-in" | "any-of" => {
+            if short(function) == "any-of" {
+                let compare = arg("Function").ok_or_else(unsupported)?;
+                if short(attr(compare, "FunctionId")?) != "string-equal" {
+                    return Err(String::from("any-of is only supported with string-equal"));
+                }
+            }
+            let val = arg("AttributeValue").ok_or_else(unsupported)?;
+            let designator = arg("AttributeDesignator").ok_or_else(unsupported)?;
+            checks.add(attribute(designator)?, vec![value(val)])
+        }
+        "string-equal" => {
+            let val = arg("AttributeValue").ok_or_else(unsupported)?;
+            let one = arg("Apply")
+                .filter(|one| {
+                    one.attribute("FunctionId")
+                        .is_some_and(|id| short(id) == "string-one-and-only")
+                })
+                .ok_or_else(unsupported)?;
+            let designator = children(one, "AttributeDesignator")
+                .next()
+                .ok_or_else(unsupported)?;
+            checks.add(attribute(designator)?, vec![value(val)])
+        }
+        "string-at-least-one-member-of" => {
+            let bag = arg("Apply")
+                .filter(|bag| {
+                    bag.attribute("FunctionId")
+                        .is_some_and(|id| short(id) == "string-bag")
+                })
+                .ok_or_else(unsupported)?;
+            let designator = arg("AttributeDesignator").ok_or_else(unsupported)?;
+            let vals = children(bag, "AttributeValue").map(value).collect();
+            checks.add(attribute(designator)?, vals)
+        }
+        _ => Err(format!("Condition function {function} is not supported")),
+    }
+}
+
+/// The trimmed text of an attribute value
+fn value(node: Node) -> String {
+    node.text().unwrap_or_default().trim().to_string()
+}
+
+/// What an attribute designator refers to
+fn attribute(node: Node) -> Result<Attribute, String> {
+    let category = attr(node, "Category")?;
+    let id = attr(node, "AttributeId")?;
+
+    let attribute = match short(category) {
+        "access-subject" => match id {
+            SUBJECT_ID => Attribute::ActorName,
+            _ => Attribute::Actor(short(id).to_string()),
+        },
+        "resource" => match (id, short(id)) {
+            (RESOURCE_ID, _) => Attribute::TargetName,
+            (_, "resource-type") => Attribute::TargetType,
+            _ => Attribute::Target(short(id).to_string()),
+        },
+        "action" => match id {
+            ACTION_ID => Attribute::Action,
+            _ => return Err(format!("Action attribute {id} is not supported")),
+        },
+        "environment" => Attribute::Env(short(id).to_string()),
+        _ => return Err(format!("Attribute category {category} is not supported")),
+    };
+    Ok(attribute)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+<PolicySet xmlns="urn:oasis:names:tc:xacml:3.0:core:schema:wd-17" PolicySetId="urn:example:docs"
+    PolicyCombiningAlgId="urn:oasis:names:tc:xacml:3.0:policy-combining-algorithm:deny-overrides">
+  <Target>
+    <AnyOf><AllOf>
+      <Match MatchId="urn:oasis:names:tc:xacml:1.0:function:string-equal">
+        <AttributeValue DataType="http://www.w3.org/2001/XMLSchema#string">document</AttributeValue>
+        <AttributeDesignator Category="urn:oasis:names:tc:xacml:3.0:attribute-category:resource"
+            AttributeId="urn:example:resource:resource-type"/>
+      </Match>
+    </AllOf></AnyOf>
+  </Target>
+  <Policy PolicyId="docs"
+      RuleCombiningAlgId="urn:oasis:names:tc:xacml:3.0:rule-combining-algorithm:deny-overrides">
+    <Rule RuleId="readers" Effect="Permit">
+      <Description>Readers can read and list</Description>
+      <Target>
+        <AnyOf>
+          <AllOf>
+            <Match MatchId="urn:oasis:names:tc:xacml:1.0:function:string-equal">
+              <AttributeValue>read</AttributeValue>
+              <AttributeDesignator Category="urn:oasis:names:tc:xacml:3.0:attribute-category:action"
+                  AttributeId="urn:oasis:names:tc:xacml:1.0:action:action-id"/>
+            </Match>
+          </AllOf>
+          <AllOf>
+            <Match MatchId="urn:oasis:names:tc:xacml:1.0:function:string-equal">
+              <AttributeValue>list</AttributeValue>
+              <AttributeDesignator Category="urn:oasis:names:tc:xacml:3.0:attribute-category:action"
+                  AttributeId="urn:oasis:names:tc:xacml:1.0:action:action-id"/>
+            </Match>
+          </AllOf>
+        </AnyOf>
+      </Target>
+      <Condition>
+        <Apply FunctionId="urn:oasis:names:tc:xacml:1.0:function:string-at-least-one-member-of">
+          <Apply FunctionId="urn:oasis:names:tc:xacml:1.0:function:string-bag">
+            <AttributeValue>reader</AttributeValue>
+            <AttributeValue>editor</AttributeValue>
+          </Apply>
+          <AttributeDesignator Category="urn:oasis:names:tc:xacml:1.0:subject-category:access-subject"
+              AttributeId="urn:example:subject:role"/>
+        </Apply>
+      </Condition>
+    </Rule>
+    <Rule RuleId="off-network" Effect="Deny">
+      <Condition>
+        <Apply FunctionId="urn:oasis:names:tc:xacml:1.0:function:string-is-in">
+          <AttributeValue>external</AttributeValue>
+          <AttributeDesignator Category="urn:oasis:names:tc:xacml:3.0:attribute-category:environment"
+              AttributeId="urn:example:environment:network"/>
+        </Apply>
+      </Condition>
+    </Rule>
+  </Policy>
+</PolicySet>
+"#;
+
+    #[test]
+    fn test_import() {
+        let rules = import(POLICY).unwrap();
+        assert_eq!(rules.len(), 2);
+
+        let readers = &rules[0];
+        assert_eq!(readers.name, "docs.readers");
+        assert_eq!(readers.desc.as_deref(), Some("Readers can read and list"));
+        assert_eq!(readers.decision(), Decide::Allow);
+        let target = readers.target_check.as_ref().unwrap();
+        assert_eq!(target.typestr.as_ref().unwrap().vals, vec!["document"]);
+        assert_eq!(target.action.as_ref().unwrap().vals, vec!["read", "list"]);
+        let roles = &readers.actor_check.as_ref().unwrap().attributes[0];
+        assert_eq!(roles.key, "role");
+        assert_eq!(roles.vals, vec!["reader", "editor"]);
+
+        let off_network = &rules[1];
+        assert_eq!(off_network.decision(), Decide::Deny);
+        assert!(off_network.actor_check.is_none());
+        assert_eq!(off_network.env_attributes[0].key, "network");
+        assert_eq!(off_network.env_attributes[0].vals, vec!["external"]);
+
+        // other combining algorithms would mean something else here
+        let permit = POLICY.replace(
+            "rule-combining-algorithm:deny-overrides",
+            "rule-combining-algorithm:permit-overrides",
+        );
+        assert!(import(&permit).unwrap_err().contains("only deny-overrides"));
+
+        let regex = POLICY.replacen("function:string-equal", "function:string-regexp-match", 1);
+        assert!(import(&regex).unwrap_err().contains("not supported"));
+    }
+}
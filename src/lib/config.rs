@@ -0,0 +1,609 @@
+#![warn(missing_docs)]
+
+//! Configuration of the Gatehouse server
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use tokio::runtime::Handle;
+use tonic::codec::CompressionEncoding;
+
+use crate::chaos::ChaosConfig;
+use crate::fallback::UnavailableChecks;
+use crate::limits::ServerLimits;
+use crate::merge::MergePolicy;
+use crate::migrate::MigrationMode;
+use crate::oidc::OidcConfig;
+use crate::proto::base::StartupMode;
+use crate::quota::{EvalLimits, Quotas};
+use crate::region::RegionConfig;
+use crate::risk::RiskConfig;
+use crate::runtime::RuntimeConfig;
+use crate::secrets::{Secret, Secrets};
+use crate::streak::DenyStreakConfig;
+use crate::sync::ldap::LdapConfig;
+use crate::validation::AttributeRules;
+
+/// Options that control how the Gatehouse server behaves
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// limits on how much data can be registered
+    pub quotas: Quotas,
+    /// the limits of namespaces that have their own, in place of `quotas`
+    pub namespace_quotas: HashMap<String, Quotas>,
+    /// limits on how much work deciding a check can take
+    pub eval_limits: EvalLimits,
+    /// how many microseconds a single policy may take to evaluate in a check before a warning is
+    /// logged; 0 never warns
+    pub policy_budget_us: u64,
+    /// when actors that keep getting denied are reported, and whether they are locked out
+    pub deny_streaks: DenyStreakConfig,
+    /// how many recent check requests to record for coverage analysis; 0 disables recording
+    pub recorded_checks: usize,
+    /// how many shards actors and targets are split into by type, each with its own lock; 0 or
+    /// 1 keeps them together
+    pub shards: usize,
+    /// if set, how to sync groups from an LDAP server
+    pub ldap: Option<LdapConfig>,
+    /// if set, how to resolve bearer tokens in check requests with an OIDC provider
+    pub oidc: Option<OidcConfig>,
+    /// if set, the directory to load WASM modules for custom policy conditions from
+    pub wasm_dir: Option<String>,
+    /// how many seconds clients may cache check decisions; 0 disables caching
+    pub decision_ttl: u32,
+    /// if set, the address of a primary server to replicate; only checks are served
+    pub replica_of: Option<String>,
+    /// the API key a replica calls its primary with
+    pub primary_api_key: Option<Secret>,
+    /// if set, the name to campaign under for leadership of the etcd backend
+    pub election: Option<String>,
+    /// if set, the key prefix to keep data under in etcd instead of `/gatehouse`
+    pub etcd_prefix: Option<String>,
+    /// if set, the user to authenticate to etcd as
+    pub etcd_user: Option<String>,
+    /// the password of the etcd user
+    pub etcd_password: Option<Secret>,
+    /// if set, the environment this server belongs to; each one keeps its etcd data apart
+    pub environment: Option<String>,
+    /// whether the file backend syncs every write to disk before reporting it done
+    pub fsync: bool,
+    /// how bad data found in storage at startup is dealt with
+    pub startup_mode: StartupMode,
+    /// whether entities stored in an older schema are saved back in the current one
+    pub migrations: MigrationMode,
+    /// callers who can change policies directly and approve proposals; if empty, anyone can
+    /// change policies and nothing needs approval
+    pub approvers: Vec<String>,
+    /// whether checks of unregistered targets or unknown actions are rejected instead of being
+    /// evaluated with no target attributes
+    pub strict_checks: bool,
+    /// whether strict checks of unregistered targets or unknown actions are decided
+    /// INDETERMINATE instead of being rejected
+    pub strict_indeterminate: bool,
+    /// the names of stores to serve besides the default one, each with its own storage; calls
+    /// pick one with the `x-gatehouse-namespace` request metadata
+    pub namespaces: Vec<String>,
+    /// if set, where to replicate changes to in another region
+    pub region: Option<RegionConfig>,
+    /// how many days an entity nothing refers to can go unused before it is flagged; 0 disables
+    /// looking for unused entities
+    pub unused_days: u32,
+    /// whether unused entities are removed rather than only flagged
+    pub remove_unused: bool,
+    /// how many seconds between repairs of drift between roles and the groups that grant them; 0
+    /// disables them
+    pub drift_secs: u32,
+    /// record check hits for one in this many checks; 0 or 1 records every check
+    pub check_sample_rate: u32,
+    /// if set, the file recent uses of actions are saved to for rate checks, so they are
+    /// roughly kept across restarts
+    pub rates_file: Option<String>,
+    /// if set, the file every request to the datastore is recorded to, to be replayed with
+    /// `gatehouse-replay`
+    pub record_requests: Option<String>,
+    /// if set, the file a sample of checks and their decisions is written to
+    pub sample_decisions: Option<String>,
+    /// sample one in this many checks; 0 or 1 samples every check
+    pub decision_sample_rate: u32,
+    /// if set, how to score the risk of checks
+    pub risk: Option<RiskConfig>,
+    /// if set, the rules values of actor and target attributes must follow
+    pub attribute_rules: Option<AttributeRules>,
+    /// how many seconds a check can wait for approval, and an approved check is then allowed
+    /// for; 0 uses an hour
+    pub approval_ttl: u32,
+    /// if set, the secret grant tokens are signed with; grants can't be requested without one
+    pub grant_secret: Option<Secret>,
+    /// how many seconds grants can be valid for; 0 uses five minutes
+    pub grant_ttl: u32,
+    /// if set, the role whose members can break the glass in an emergency; nobody can otherwise
+    pub break_glass_role: Option<String>,
+    /// what checks get when the datastore doesn't answer them in time
+    pub unavailable_checks: UnavailableChecks,
+    /// how many milliseconds the datastore has to answer a check; 0 uses 30 seconds
+    pub check_timeout_ms: u32,
+    /// if set, the policy test file or directory of them to run as canary checks at startup
+    pub canaries: Option<String>,
+    /// whether the server only reports itself ready if every canary passes
+    pub canaries_required: bool,
+    /// the public key that policy bundles must be signed with; bundles are refused without one
+    pub bundle_key: Option<[u8; 32]>,
+    /// whether policies can only be changed by applying a signed bundle
+    pub bundle_only: bool,
+    /// whether every call must carry an API key
+    pub api_keys_required: bool,
+    /// if set, an `ADMIN` API key registered at startup, so the first calls can be made with it
+    pub bootstrap_api_key: Option<Secret>,
+    /// who calls made with the bootstrap API key are made by
+    pub bootstrap_principal: String,
+    /// how many threads checks and storage calls run on
+    pub runtime: RuntimeConfig,
+    /// if set, the runtime storage calls run on instead of the one checks run on
+    pub storage_runtime: Option<Handle>,
+    /// how the messages sent to clients are compressed
+    pub compression: Compression,
+    /// the limits on connections and the size of messages
+    pub limits: ServerLimits,
+    /// how the actor attributes a check brings merge with the registered ones
+    pub attribute_merge: MergePolicy,
+    /// if set, the faults to inject into calls to the storage backend, for testing
+    pub chaos: Option<ChaosConfig>,
+}
+
+impl Config {
+    /// Build the configuration from environment variables, failing if one can't be read
+    ///
+    /// * `GATERECORDCHECKS`: number of recent check requests to record (default 0)
+    /// * `GATESHARDS`: number of shards to split actors and targets into by type (default 1)
+    /// * `GATEPOLICYBUDGETUS`: microseconds a single policy may take to evaluate before a warning
+    ///   is logged (default 0, never)
+    /// * `GATELDAPCONFIG`: path to a JSON file describing groups to sync from LDAP
+    /// * `GATEOIDCCONFIG`: path to a JSON file describing the OIDC provider and claims to inject
+    /// * `GATEWASMDIR`: directory of WASM modules that policies can use as custom conditions
+    /// * `GATEDECISIONTTL`: seconds clients may cache check decisions for (default 0)
+    /// * `GATEELECTION`: name to campaign under for leadership when using etcd storage
+    /// * `GATEETCDPREFIX`: key prefix to keep data under in etcd (default `/gatehouse`)
+    /// * `GATEETCDUSER`: user to authenticate to etcd as
+    /// * `GATEETCDPASSWORD`: password of the etcd user
+    /// * `GATEENVIRONMENT`: environment name, appended to the etcd prefix
+    /// * `GATEFSYNC`: set to `true` to sync every write to disk when using file storage
+    /// * `GATESTARTUPMODE`: `strict` (default), `lenient`, or `repair`; see [`StartupMode`]
+    /// * `GATEMIGRATIONS`: `apply` (default) to save entities stored in an older schema back in
+    ///   the current one, or `dry-run` to only report them
+    /// * `GATEAPPROVERS`: comma-separated callers who approve policy changes made by others
+    /// * `GATESTRICTCHECKS`: set to `true` to reject checks of unregistered targets or actions,
+    ///   or to `indeterminate` to decide them INDETERMINATE instead
+    /// * `GATENAMESPACES`: comma-separated names of stores to serve besides the default one
+    /// * `GATEUNUSEDDAYS`: days after which unused actors, targets, and roles are flagged (default
+    ///   0, disabled)
+    /// * `GATEREMOVEUNUSED`: set to `true` to remove unused entities instead of only flagging them
+    /// * `GATEDRIFTSECS`: seconds between repairs of drift between roles and groups (default 300,
+    ///   0 disables them)
+    /// * `GATECHECKSAMPLERATE`: record check hits for one in this many checks (default 1)
+    /// * `GATERATESFILE`: file to save recent uses of actions to for rate checks
+    /// * `GATERECORDREQUESTS`: file to record every request to the datastore to, for replaying
+    /// * `GATESAMPLEDECISIONS`: file to write a sample of checks and their decisions to, as JSON
+    ///   lines
+    /// * `GATEDECISIONSAMPLERATE`: sample one in this many checks (default 100)
+    /// * `GATERISKCONFIG`: path to a JSON file describing the signals to score check risk with
+    /// * `GATEATTRIBUTERULES`: path to a JSON file of the rules actor and target attribute values
+    ///   must follow; see [`validation`](crate::validation)
+    /// * `GATEAPPROVALTTL`: seconds a check waits for approval and is then allowed for (default
+    ///   3600)
+    /// * `GATEGRANTSECRET`: secret to sign grant tokens with, shared with the services that
+    ///   verify them
+    /// * `GATEGRANTTTL`: seconds grant tokens can be valid for (default 300)
+    /// * `GATEBREAKGLASSROLE`: role whose members can break the glass in an emergency
+    /// * `GATEUNAVAILABLECHECKS`: `error` (default), `deny`, `allow`, or `cached`; what checks get
+    ///   when the datastore doesn't answer them in time
+    /// * `GATECHECKTIMEOUTMS`: milliseconds the datastore has to answer a check (default 30000)
+    /// * `GATECANARIES`: policy test file, or directory of them, to run as canaries at startup
+    /// * `GATECANARIESREQUIRED`: set to `true` to only report ready if every canary passes
+    /// * `GATEBUNDLEKEY`: base64 Ed25519 public key that policy bundles must be signed with
+    /// * `GATEBUNDLEONLY`: set to `true` to refuse policy changes that don't come from a bundle
+    /// * `GATEAPIKEYS`: set to `true` to require an API key on every call
+    /// * `GATEBOOTSTRAPKEY`: an API key, as `ghk_<id>_<secret>`, to register as an `ADMIN` key
+    ///   at startup
+    /// * `GATEBOOTSTRAPPRINCIPAL`: who calls made with the bootstrap key are made by (default
+    ///   `admin`)
+    /// * `GATEATTRIBUTEMERGE`: how actor attributes from checks merge with registered ones, as
+    ///   `key=merge` pairs separated by commas, where the key `*` sets the default and a merge is
+    ///   `union`, `registered-wins` (the default), or `caller-ignored`
+    /// * `GATECHAOS`: faults to inject into calls to the storage backend, for testing only; see
+    ///   [`chaos`](crate::chaos)
+    /// * `GATECOMPRESSION`: `gzip` to compress the messages sent to clients that accept it, or
+    ///   `none` (the default)
+    /// * `GATEPRIMARYAPIKEY`: the API key a replica calls its primary with
+    ///
+    /// See [`Quotas::from_env`] for the quota variables, [`EvalLimits::from_env`] for the limits
+    /// on deciding checks, [`DenyStreakConfig::from_env`] for deny streaks,
+    /// [`RegionConfig::from_env`] for replication to another region,
+    /// [`RuntimeConfig::from_env`] for the threads the server runs on, and
+    /// [`ServerLimits::from_env`] for the limits on connections and message sizes.
+    ///
+    /// `GATEGRANTSECRET`, `GATEBOOTSTRAPKEY`, `GATEREPLICATEAPIKEY`, `GATEPRIMARYAPIKEY`,
+    /// `GATEETCDPASSWORD`, the LDAP bind password, and the OIDC client secret in their config
+    /// files can be given as is or as a reference to where they are kept, such as
+    /// `file:///run/secrets/grant` or `vault://secret/gatehouse#grant`; see
+    /// [`secrets`](crate::secrets).
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_env_with(&Secrets::default())
+    }
+
+    /// Build the configuration from environment variables, reading secrets from some providers
+    pub fn from_env_with(secrets: &Secrets) -> Result<Self, String> {
+        let quotas = Quotas::from_env()?;
+        let namespaces = namespaces_from_env()?;
+        let mut namespace_quotas = HashMap::new();
+        for namespace in &namespaces {
+            let own = quotas.for_namespace_from_env(namespace)?;
+            if own != quotas {
+                namespace_quotas.insert(namespace.clone(), own);
+            }
+        }
+
+        Ok(Self {
+            quotas,
+            namespace_quotas,
+            eval_limits: EvalLimits::from_env()?,
+            policy_budget_us: number_from_env("GATEPOLICYBUDGETUS")?.unwrap_or(0) as u64,
+            deny_streaks: DenyStreakConfig::from_env()?,
+            recorded_checks: number_from_env("GATERECORDCHECKS")?.unwrap_or(0),
+            shards: number_from_env("GATESHARDS")?.unwrap_or(1),
+            ldap: std::env::var("GATELDAPCONFIG")
+                .ok()
+                .map(|path| {
+                    let mut ldap = LdapConfig::from_file(&path)?;
+                    ldap.bind_password =
+                        resolve_secret(secrets, "LDAP bind password", ldap.bind_password)?;
+                    Ok::<_, String>(ldap)
+                })
+                .transpose()?,
+            oidc: std::env::var("GATEOIDCCONFIG")
+                .ok()
+                .map(|path| {
+                    let mut oidc = OidcConfig::from_file(&path)?;
+                    oidc.client_secret =
+                        resolve_secret(secrets, "OIDC client secret", oidc.client_secret)?;
+                    Ok::<_, String>(oidc)
+                })
+                .transpose()?,
+            wasm_dir: std::env::var("GATEWASMDIR").ok(),
+            decision_ttl: number_from_env("GATEDECISIONTTL")?
+                .map(|ttl| u32::try_from(ttl).unwrap_or(u32::MAX))
+                .unwrap_or(0),
+            replica_of: None,
+            primary_api_key: secret_from_env(secrets, "GATEPRIMARYAPIKEY")?,
+            election: std::env::var("GATEELECTION").ok(),
+            etcd_prefix: std::env::var("GATEETCDPREFIX").ok(),
+            etcd_user: std::env::var("GATEETCDUSER")
+                .ok()
+                .filter(|user| !user.is_empty()),
+            etcd_password: secret_from_env(secrets, "GATEETCDPASSWORD")?,
+            environment: std::env::var("GATEENVIRONMENT").ok(),
+            fsync: matches!(std::env::var("GATEFSYNC").as_deref(), Ok("true") | Ok("1")),
+            startup_mode: startup_mode_from_env()?,
+            migrations: migrations_from_env()?,
+            approvers: std::env::var("GATEAPPROVERS")
+                .map(|val| {
+                    val.split(',')
+                        .map(|approver| approver.trim().to_string())
+                        .filter(|approver| !approver.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            strict_checks: matches!(
+                std::env::var("GATESTRICTCHECKS").as_deref(),
+                Ok("true") | Ok("1") | Ok("reject") | Ok("indeterminate")
+            ),
+            strict_indeterminate: matches!(
+                std::env::var("GATESTRICTCHECKS").as_deref(),
+                Ok("indeterminate")
+            ),
+            namespaces,
+            region: match RegionConfig::from_env()? {
+                Some(region) => Some(RegionConfig {
+                    api_key: secret_from_env(secrets, "GATEREPLICATEAPIKEY")?,
+                    ..region
+                }),
+                None => None,
+            },
+            unused_days: number_from_env("GATEUNUSEDDAYS")?
+                .map(|days| u32::try_from(days).unwrap_or(u32::MAX))
+                .unwrap_or(0),
+            remove_unused: matches!(
+                std::env::var("GATEREMOVEUNUSED").as_deref(),
+                Ok("true") | Ok("1")
+            ),
+            drift_secs: number_from_env("GATEDRIFTSECS")?
+                .map(|secs| u32::try_from(secs).unwrap_or(u32::MAX))
+                .unwrap_or(300),
+            check_sample_rate: number_from_env("GATECHECKSAMPLERATE")?
+                .map(|rate| u32::try_from(rate).unwrap_or(u32::MAX))
+                .unwrap_or(1),
+            rates_file: std::env::var("GATERATESFILE").ok(),
+            record_requests: std::env::var("GATERECORDREQUESTS").ok(),
+            sample_decisions: std::env::var("GATESAMPLEDECISIONS")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            decision_sample_rate: number_from_env("GATEDECISIONSAMPLERATE")?
+                .map(|rate| u32::try_from(rate).unwrap_or(u32::MAX))
+                .unwrap_or(100),
+            risk: std::env::var("GATERISKCONFIG")
+                .ok()
+                .map(|path| RiskConfig::from_file(&path))
+                .transpose()?,
+            attribute_rules: std::env::var("GATEATTRIBUTERULES")
+                .ok()
+                .map(|path| AttributeRules::from_file(&path))
+                .transpose()?,
+            approval_ttl: number_from_env("GATEAPPROVALTTL")?
+                .map(|ttl| u32::try_from(ttl).unwrap_or(u32::MAX))
+                .unwrap_or(0),
+            grant_secret: secret_from_env(secrets, "GATEGRANTSECRET")?,
+            grant_ttl: number_from_env("GATEGRANTTTL")?
+                .map(|ttl| u32::try_from(ttl).unwrap_or(u32::MAX))
+                .unwrap_or(0),
+            break_glass_role: std::env::var("GATEBREAKGLASSROLE")
+                .ok()
+                .map(|role| role.trim().to_string())
+                .filter(|role| !role.is_empty()),
+            unavailable_checks: unavailable_checks_from_env()?,
+            check_timeout_ms: number_from_env("GATECHECKTIMEOUTMS")?
+                .map(|ms| u32::try_from(ms).unwrap_or(u32::MAX))
+                .unwrap_or(0),
+            canaries: std::env::var("GATECANARIES")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            canaries_required: matches!(
+                std::env::var("GATECANARIESREQUIRED").as_deref(),
+                Ok("true") | Ok("1")
+            ),
+            bundle_key: bundle_key_from_env()?,
+            bundle_only: bundle_only_from_env()?,
+            api_keys_required: matches!(
+                std::env::var("GATEAPIKEYS").as_deref(),
+                Ok("true") | Ok("1")
+            ),
+            bootstrap_api_key: bootstrap_key_from_env(secrets)?,
+            bootstrap_principal: std::env::var("GATEBOOTSTRAPPRINCIPAL")
+                .map(|principal| principal.trim().to_string())
+                .ok()
+                .filter(|principal| !principal.is_empty())
+                .unwrap_or_else(|| String::from("admin")),
+            runtime: RuntimeConfig::from_env()?,
+            storage_runtime: None,
+            compression: compression_from_env()?,
+            limits: ServerLimits::from_env()?,
+            attribute_merge: attribute_merge_from_env()?,
+            chaos: chaos_from_env()?,
+        })
+    }
+
+    /// The configuration of the store for a namespace
+    ///
+    /// Etcd keeps the namespace apart as part of the environment, and rate counts and recorded
+    /// requests are saved to files of their own. LDAP sync, replication from a primary or to
+    /// another region, and the bootstrap API key only apply to the default store.
+    pub(crate) fn for_namespace(&self, namespace: &str) -> Self {
+        let environment = match self.environment {
+            Some(ref env) => format!("{env}-{namespace}"),
+            None => namespace.to_string(),
+        };
+
+        Self {
+            rates_file: self
+                .rates_file
+                .as_ref()
+                .map(|path| format!("{path}.{namespace}")),
+            record_requests: self
+                .record_requests
+                .as_ref()
+                .map(|path| format!("{path}.{namespace}")),
+            sample_decisions: self
+                .sample_decisions
+                .as_ref()
+                .map(|path| format!("{path}.{namespace}")),
+            environment: Some(environment),
+            quotas: self
+                .namespace_quotas
+                .get(namespace)
+                .unwrap_or(&self.quotas)
+                .clone(),
+            namespace_quotas: HashMap::new(),
+            ldap: None,
+            replica_of: None,
+            primary_api_key: None,
+            namespaces: vec![],
+            region: None,
+            bootstrap_api_key: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// read the namespaces from the environment, failing if one isn't a plain name
+fn namespaces_from_env() -> Result<Vec<String>, String> {
+    let val = std::env::var("GATENAMESPACES").unwrap_or_default();
+    let mut namespaces = Vec::new();
+    for namespace in val.split(',').map(str::trim).filter(|ns| !ns.is_empty()) {
+        if !namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "GATENAMESPACES must be names of letters, numbers, - and _: {namespace}"
+            ));
+        }
+        namespaces.push(namespace.to_ascii_lowercase());
+    }
+    Ok(namespaces)
+}
+
+/// read the startup mode from the environment, failing if it is not one we know
+fn startup_mode_from_env() -> Result<StartupMode, String> {
+    let val = match std::env::var("GATESTARTUPMODE") {
+        Ok(val) => val,
+        Err(_) => return Ok(StartupMode::Strict),
+    };
+    match val.to_ascii_lowercase().as_str() {
+        "strict" => Ok(StartupMode::Strict),
+        "lenient" => Ok(StartupMode::Lenient),
+        "repair" => Ok(StartupMode::Repair),
+        _ => Err(format!(
+            "GATESTARTUPMODE must be strict, lenient, or repair: {val}"
+        )),
+    }
+}
+
+/// read whether upgraded entities are saved, failing if it is not a mode we know
+fn migrations_from_env() -> Result<MigrationMode, String> {
+    match std::env::var("GATEMIGRATIONS") {
+        Ok(val) => MigrationMode::parse(&val)
+            .map_err(|_| format!("GATEMIGRATIONS must be apply or dry-run: {val}")),
+        Err(_) => Ok(MigrationMode::Apply),
+    }
+}
+
+/// read how check attributes merge with registered ones, failing if it can't be read
+fn attribute_merge_from_env() -> Result<MergePolicy, String> {
+    let val = std::env::var("GATEATTRIBUTEMERGE").unwrap_or_default();
+    MergePolicy::parse(&val)
+        .map_err(|err| format!("GATEATTRIBUTEMERGE is not a list of key=merge: {err}"))
+}
+
+/// read the faults to inject into storage calls, failing if they can't be parsed
+fn chaos_from_env() -> Result<Option<ChaosConfig>, String> {
+    match std::env::var("GATECHAOS") {
+        Ok(val) => ChaosConfig::parse(&val)
+            .map(Some)
+            .map_err(|err| format!("GATECHAOS is not a list of faults: {err}")),
+        Err(_) => Ok(None),
+    }
+}
+
+/// read what checks get when the datastore can't answer them, failing if it is not one we know
+fn unavailable_checks_from_env() -> Result<UnavailableChecks, String> {
+    match std::env::var("GATEUNAVAILABLECHECKS") {
+        Ok(val) => UnavailableChecks::parse(&val).map_err(|_| {
+            format!("GATEUNAVAILABLECHECKS must be error, deny, allow, or cached: {val}")
+        }),
+        Err(_) => Ok(UnavailableChecks::Error),
+    }
+}
+
+/// read the bundle signing public key from the environment, failing if it is not a key
+fn bundle_key_from_env() -> Result<Option<[u8; 32]>, String> {
+    match std::env::var("GATEBUNDLEKEY") {
+        Ok(val) => crate::bundle::parse_public_key(&val)
+            .map(Some)
+            .map_err(|err| format!("GATEBUNDLEKEY: {err}")),
+        Err(_) => Ok(None),
+    }
+}
+
+/// read whether only bundles can change policies, failing if there is no key to verify them
+fn bundle_only_from_env() -> Result<bool, String> {
+    let bundle_only = matches!(
+        std::env::var("GATEBUNDLEONLY").as_deref(),
+        Ok("true") | Ok("1")
+    );
+    if bundle_only && std::env::var("GATEBUNDLEKEY").is_err() {
+        return Err(String::from(
+            "GATEBUNDLEONLY needs GATEBUNDLEKEY to verify bundles with",
+        ));
+    }
+    Ok(bundle_only)
+}
+
+/// read the bootstrap API key from the environment, failing if it is not a key
+fn bootstrap_key_from_env(secrets: &Secrets) -> Result<Option<Secret>, String> {
+    let key = match secret_from_env(secrets, "GATEBOOTSTRAPKEY")? {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    crate::apikey::RegisteredApiKey::bootstrap(key.expose(), "", 0)
+        .map_err(|err| format!("GATEBOOTSTRAPKEY: {err}"))?;
+    Ok(Some(key))
+}
+
+/// read a secret from the environment, failing if it can't be read
+fn secret_from_env(secrets: &Secrets, var: &str) -> Result<Option<Secret>, String> {
+    secrets.from_env(var)
+}
+
+/// read the secret a config file gives, failing if it can't be read
+fn resolve_secret(
+    secrets: &Secrets,
+    name: &str,
+    secret: Option<Secret>,
+) -> Result<Option<Secret>, String> {
+    match secret {
+        Some(secret) => secrets
+            .resolve(secret.expose())
+            .map(Some)
+            .map_err(|err| format!("Could not read {name}: {err}")),
+        None => Ok(None),
+    }
+}
+
+/// read a single number from the environment, failing if it is not a number
+pub(crate) fn number_from_env(var: &str) -> Result<Option<usize>, String> {
+    match std::env::var(var) {
+        Ok(val) => val
+            .parse::<usize>()
+            .map(Some)
+            .map_err(|_| format!("{var} must be a positive number: {val}")),
+        Err(_) => Ok(None),
+    }
+}
+
+/// How the messages sent to clients are compressed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// messages are sent as they are
+    #[default]
+    None,
+    /// messages are gzip-compressed for clients that accept gzip
+    Gzip,
+}
+
+impl Compression {
+    /// Parse a compression name
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_lowercase().as_str() {
+            "none" | "identity" | "" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            // tonic only compresses with zstd from 0.10 on
+            "zstd" => Err(String::from(
+                "zstd compression isn't supported yet; use gzip",
+            )),
+            other => Err(format!("Unknown compression {other}; use gzip or none")),
+        }
+    }
+
+    /// The encoding tonic compresses messages with, if they are
+    pub fn encoding(&self) -> Option<CompressionEncoding> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some(CompressionEncoding::Gzip),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "disabled"),
+            Self::Gzip => write!(f, "gzip"),
+        }
+    }
+}
+
+/// read how the messages sent to clients are compressed, failing if it is not a way we know
+fn compression_from_env() -> Result<Compression, String> {
+    match std::env::var("GATECOMPRESSION") {
+        Ok(val) => Compression::parse(&val).map_err(|err| format!("GATECOMPRESSION: {err}")),
+        Err(_) => Ok(Compression::None),
+    }
+}
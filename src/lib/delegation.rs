@@ -0,0 +1,161 @@
+#![warn(missing_docs)]
+
+//! Delegations of access from one actor to another
+//!
+//! A delegation lends the delegate some of the delegator's access: specific actions on targets of
+//! one type, or on some named targets of it, until it expires. When a check doesn't allow the
+//! delegate an action a delegation covers, the action is decided again as the delegator, so the
+//! delegate can never get more than the delegator has.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::actor::RegisteredActor;
+use crate::intern::intern;
+use crate::proto::actors::Delegation;
+
+/// A delegation registered with Gatehouse
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RegisteredDelegation {
+    /// the name of this delegation
+    pub name: String,
+    /// the type of the actor lending its access
+    pub delegator_type: String,
+    /// the name of the actor lending its access
+    pub delegator_name: String,
+    /// the type of the actor the access is lent to
+    pub delegate_type: String,
+    /// the name of the actor the access is lent to
+    pub delegate_name: String,
+    /// the type of the targets covered
+    pub target_type: String,
+    /// the names of the targets covered, or every target of the type if empty
+    pub target_names: Vec<String>,
+    /// the actions covered
+    pub actions: Vec<String>,
+    /// when the delegation stops applying, in seconds since the epoch
+    pub expires_at: u64,
+    /// when the delegation was made, in seconds since the epoch
+    pub created_at: u64,
+}
+
+impl RegisteredDelegation {
+    /// Make sure the delegation says who lends what to whom, and for how long
+    pub fn validate(&self, now: u64) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err(String::from("Delegation name cannot be empty"));
+        }
+        if self.delegator_type.is_empty() || self.delegator_name.is_empty() {
+            return Err(String::from("Delegation must name its delegator"));
+        }
+        if self.delegate_type.is_empty() || self.delegate_name.is_empty() {
+            return Err(String::from("Delegation must name its delegate"));
+        }
+        if self.delegator_type == self.delegate_type && self.delegator_name == self.delegate_name {
+            return Err(String::from("An actor cannot delegate to itself"));
+        }
+        if self.target_type.is_empty() {
+            return Err(String::from("Delegation must name a target type"));
+        }
+        if self.actions.is_empty() {
+            return Err(String::from("Delegation must cover at least one action"));
+        }
+        if self.expires_at <= now {
+            return Err(String::from("Delegation must expire in the future"));
+        }
+        Ok(())
+    }
+
+    /// Whether the delegation lends an action on a target to an actor right now
+    pub fn covers(
+        &self,
+        actor: &RegisteredActor,
+        target_type: &str,
+        target_name: &str,
+        action: &str,
+        now: u64,
+    ) -> bool {
+        self.expires_at > now
+            && *self.delegate_type == *actor.typestr
+            && self.delegate_name == actor.name
+            && self.target_type.eq_ignore_ascii_case(target_type)
+            && (self.target_names.is_empty()
+                || self
+                    .target_names
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(target_name)))
+            && self
+                .actions
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(action))
+    }
+
+    /// Whether an actor lends or is lent access by the delegation
+    pub fn involves(&self, actor_type: Option<&str>, actor_name: Option<&str>) -> bool {
+        let is = |typestr: &str, name: &str| {
+            actor_type.is_none_or(|t| t.eq_ignore_ascii_case(typestr))
+                && actor_name.is_none_or(|n| n.eq_ignore_ascii_case(name))
+        };
+        is(&self.delegator_type, &self.delegator_name)
+            || is(&self.delegate_type, &self.delegate_name)
+    }
+
+    /// The actor lending its access, without any attributes yet
+    pub fn delegator(&self) -> RegisteredActor {
+        RegisteredActor {
+            name: self.delegator_name.clone(),
+            typestr: intern(&self.delegator_type),
+            attributes: Default::default(),
+            timestamps: Default::default(),
+        }
+    }
+}
+
+impl From<Delegation> for RegisteredDelegation {
+    fn from(delegation: Delegation) -> Self {
+        Self {
+            name: delegation.name.to_ascii_lowercase(),
+            delegator_type: delegation.delegator_type.to_ascii_lowercase(),
+            delegator_name: delegation.delegator_name.to_ascii_lowercase(),
+            delegate_type: delegation.delegate_type.to_ascii_lowercase(),
+            delegate_name: delegation.delegate_name.to_ascii_lowercase(),
+            target_type: delegation.target_type,
+            target_names: delegation.target_names,
+            actions: delegation.actions,
+            expires_at: delegation.expires_at,
+            created_at: delegation.created_at,
+        }
+    }
+}
+
+impl From<RegisteredDelegation> for Delegation {
+    fn from(delegation: RegisteredDelegation) -> Self {
+        Self {
+            name: delegation.name,
+            delegator_type: delegation.delegator_type,
+            delegator_name: delegation.delegator_name,
+            delegate_type: delegation.delegate_type,
+            delegate_name: delegation.delegate_name,
+            target_type: delegation.target_type,
+            target_names: delegation.target_names,
+            actions: delegation.actions,
+            expires_at: delegation.expires_at,
+            created_at: delegation.created_at,
+        }
+    }
+}
+
+impl Display for RegisteredDelegation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "delegation[{}]: {}/{} -> {}/{}",
+            self.name,
+            self.delegator_type,
+            self.delegator_name,
+            self.delegate_type,
+            self.delegate_name
+        )
+    }
+}
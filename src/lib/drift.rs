@@ -0,0 +1,44 @@
+#![warn(missing_docs)]
+
+//! A background job that repairs drift between roles and the groups that grant them
+//!
+//! A role lists the groups that grant it, and each group lists the roles it grants, so changes
+//! to either update both. If the server stops or the backend fails partway through one, the two
+//! can drift apart. Every so often, the job has the datastore bring roles back in step with the
+//! groups, which are taken as the authority, and logs what it repaired. How much drift was found
+//! and repaired is in the server stats.
+
+use flume::Sender;
+use tokio::sync::oneshot::channel;
+use tokio::time::{sleep, Duration};
+
+use crate::msgs::{DsRequest, DsResponse};
+use crate::replica::call;
+
+/// Repair drift in the background every interval until the datastore goes away
+pub(crate) fn spawn(interval: Duration, dstx: Sender<DsRequest>) {
+    tokio::spawn(async move {
+        while !dstx.is_disconnected() {
+            sleep(interval).await;
+
+            match repair(&dstx).await {
+                Ok(repaired) if repaired.is_empty() => {}
+                Ok(repaired) => println!(
+                    "Repaired drift between roles and groups: {}",
+                    repaired.join(", ")
+                ),
+                Err(err) => eprintln!("Repairing drift between roles and groups failed: {err}"),
+            }
+        }
+    });
+}
+
+/// Have the datastore repair drift, returning the updates that did
+async fn repair(dstx: &Sender<DsRequest>) -> Result<Vec<String>, String> {
+    let (tx, rx) = channel::<DsResponse>();
+
+    match call(dstx, DsRequest::RepairDrift(tx), rx).await? {
+        DsResponse::DriftRepaired(repaired) => Ok(repaired),
+        _ => Err(String::from("Got unexpected answer from datastore")),
+    }
+}
@@ -2,132 +2,526 @@
 
 //! The datastore holds all the policies, targets, and internal PIP data
 
+use fasthash::metro;
 use flume::Receiver;
-use std::collections::{HashMap, HashSet};
+use prost::Message;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::oneshot::Sender;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot::{channel, Sender};
 use tokio::sync::RwLock;
-use tonic::Status;
+use tokio::sync::{broadcast, watch};
+use tonic::{Code, Status};
 
 use crate::actor::RegisteredActor;
-use crate::group::{RegisteredGroup, RegisteredGroupMember};
+use crate::attribute::{self, AttributeMap};
+use crate::breakglass::RegisteredBreakGlass;
+use crate::bulk::MAX_BULK_ADD;
+use crate::bundle::AppliedBundle;
+use crate::chaos::ChaosStorage;
+use crate::config::Config;
+use crate::delegation::RegisteredDelegation;
+use crate::expansion::{Expansions, Versioned};
+use crate::group::{RegisteredGroup, RegisteredGroupMember, GROUP_MEMBER_TYPE};
+use crate::intern::intern;
 use crate::msgs::{DsRequest, DsResponse};
-use crate::policy::{Decide, RegisteredPolicyRule};
-use crate::proto::base::CheckRequest;
+use crate::policy::{
+    decide_actions, lowercase, Cidr, Decide, Mode, PolicyStore, RateCheck, RegisteredPolicyRule,
+    StringCheck, TargetAction,
+};
+use crate::policyset::RegisteredPolicySet;
+use crate::proposal::RegisteredProposal;
+use crate::proto::base::mutation::Op;
+use crate::proto::base::watch_event::Change as WatchChange;
+use crate::proto::base::{
+    ActionDecision, ActionMode, ApiKey, ApplyTransactionRequest, Approval, BreakGlass,
+    BreakGlassRequest, CheckRequest, CheckResponse, ConflictPolicy, CoverageReportRequest,
+    CoverageReportResponse, DecisionChange, DecisionSource, EntityChange, FindUnusedRequest,
+    FindUnusedResponse, GetPolicyStatsRequest, GetPolicyStatsResponse, GetReferencesRequest,
+    GetReferencesResponse, GetServerInfoResponse, GetServerStatsRequest, GetServerStatsResponse,
+    Mutation, PolicyTestResult, ReplicateRequest, ReplicateResponse, ServingRole, StartupIssue,
+    StartupMode, SyncResponse, TestPoliciesRequest, TestPoliciesResponse, TraceCheckResponse,
+    UnusedEntity, WatchEvent, WhatIfRequest, WhatIfResponse,
+};
+use crate::quota::EvalBudget;
+use crate::replay::Recorder;
+use crate::replica::watch_change;
+use crate::risk::{RiskInput, RiskScore, RiskScorer, RISK_ATTRIBUTE};
 use crate::StorageType;
 
+use crate::apikey::{self, RegisteredApiKey};
+use crate::latency::{self, PolicyTimings};
+use crate::migrate::{MigrationMode, Upgraded};
 use crate::proto::actors::{
-    Actor, AddActorRequest, GetActorsRequest, ModifyActorRequest, RemoveActorRequest,
+    Actor, ActorMembershipsResponse, AddActorRequest, AddActorResult, AddActorsRequest,
+    AddDelegationRequest, Delegation, GetActorMembershipsRequest, GetActorsRequest,
+    GetDelegationsRequest, ModifyActorRequest, RemoveActorRequest, RemoveDelegationRequest,
+};
+use crate::proto::common::{
+    AttributeValues, AttributeViolation, Attributes, PartialFailure, References, SortBy,
+    SortDirection,
 };
 use crate::proto::groups::{
-    AddGroupRequest, GetGroupsRequest, ModifyGroupRequest, RemoveGroupRequest,
+    AddGroupRequest, BulkModifyMembershipsRequest, GetGroupMembersRequest, GetGroupsRequest, Group,
+    GroupMember, GroupMembersResponse, ModifyGroupRequest, RemoveGroupRequest,
 };
+use crate::proto::policies::proposal::Change as ProposedChange;
 use crate::proto::policies::{
-    AddPolicyRequest, GetPoliciesRequest, ModifyPolicyRequest, PolicyRule, RemovePolicyRequest,
+    AddPolicyRequest, AddPolicySetRequest, GetPoliciesRequest, GetPolicySetsRequest,
+    ModifyPolicyRequest, ModifyPolicySetRequest, PolicyBundle, PolicyImpact, PolicyRule, PolicySet,
+    Proposal, RemovePolicyRequest, RemovePolicySetRequest,
 };
 use crate::proto::roles::{
     AddRoleRequest, GetRolesRequest, ModifyRoleRequest, RemoveRoleRequest, Role,
 };
 use crate::proto::targets::{
-    AddTargetRequest, GetTargetsRequest, ModifyTargetRequest, RemoveTargetRequest, Target,
+    AddTargetRequest, AddTargetResult, AddTargetsRequest, GetTargetsRequest, ModifyTargetRequest,
+    RemoveTargetRequest, Target,
+};
+use crate::proto::webhooks::{
+    AddWebhookRequest, GetDeliveriesRequest, GetWebhooksRequest, RemoveWebhookRequest, Webhook,
 };
 use crate::role::RegisteredRole;
+use crate::sampling::{self, DecisionSampler};
+use crate::shard::{Sharded, Typed};
+use crate::storage::dedicated::DedicatedStorage;
 use crate::storage::etcd::EtcdStorage;
 use crate::storage::file::FileStorage;
+use crate::storage::health::{MonitoredStorage, StorageHealth};
+use crate::storage::log::LogStorage;
 use crate::storage::nil::NilStorage;
-use crate::storage::{BackendUpdate, Storage};
-use crate::target::RegisteredTarget;
+use crate::storage::{BackendUpdate, Leadership, PersistError, Storage};
+use crate::streak::DenyStreaks;
+use crate::target::{action_groups, RegisteredTarget};
+use crate::timestamps::Timestamps;
+use crate::usage::Usage;
+use crate::validation::{self, AttributeRules};
+use crate::velocity::Velocity;
+use crate::wasm::WasmModules;
+use crate::webhook::deliver::Dispatcher;
+use crate::webhook::{Event, RegisteredWebhook};
+
+/// how many watch events a slow watcher can fall behind before it is dropped
+const WATCH_BUFFER: usize = 1024;
+
+/// how many of the most recent changes are kept so change streams can resume
+const CHANGELOG_SIZE: usize = 10_000;
+
+/// how long checks wait for approval, and are allowed for once approved, unless configured
+const DEFAULT_APPROVAL_TTL: u64 = 60 * 60;
+
+/// the most checks that can wait for approval at once
+const MAX_APPROVALS: usize = 10_000;
 
+/// how often old uses of actions are forgotten and the rest saved, if there is a file for them
+const RATES_INTERVAL: Duration = Duration::from_secs(30);
+
+/// the longest emergency access can last, in minutes
+const MAX_BREAK_GLASS_MINUTES: u32 = 24 * 60;
+
+/// the most emergency access that can be granted at once
+const MAX_BREAK_GLASSES: usize = 1_000;
+
+/// how often emergency access that has run out is ended and announced
+const BREAK_GLASS_INTERVAL: Duration = Duration::from_secs(1);
 pub struct Datastore {
     rx: flume::Receiver<DsRequest>,
     storage: Box<dyn Storage + Send + Sync>,
 
-    /// HashMap from type string to HashMap of name to registered target
-    targets: Arc<RwLock<HashMap<String, HashMap<String, RegisteredTarget>>>>,
+    /// The backend the storage was opened with
+    backend: StorageType,
+
+    /// How the storage backend has been doing
+    storage_health: Arc<StorageHealth>,
+
+    /// Problems found with the stored data at startup, and what was done about them
+    startup_issues: RwLock<Vec<StartupIssue>>,
+
+    /// The role and group updates drift between them needed when last looked for
+    drift: RwLock<HashSet<String>>,
+    /// How many updates have repaired drift since we started
+    drift_repairs: AtomicU64,
+
+    /// Server configuration, including quotas
+    config: Config,
+
+    /// HashMap from type string to HashMap of name to registered target, in shards by type
+    targets: Arc<Sharded<RegisteredTarget>>,
 
-    /// HashMap from type string to HashMap of name to registered actor
-    actors: Arc<RwLock<HashMap<String, HashMap<String, RegisteredActor>>>>,
+    /// HashMap from type string to HashMap of name to registered actor, in shards by type
+    actors: Arc<Sharded<RegisteredActor>>,
 
     /// HashMap of name to registered roles
     roles: Arc<RwLock<HashMap<String, RegisteredRole>>>,
 
-    /// HashMap of name to registered group
-    groups: Arc<RwLock<HashMap<String, RegisteredGroup>>>,
+    /// HashMap of name to registered group, versioned so expanded actors know when it changes
+    groups: Arc<Versioned<HashMap<String, RegisteredGroup>>>,
+
+    /// The attributes of actors checked, with their groups and roles added
+    expansions: Arc<Expansions>,
+
+    /// Registered policies by name, also bucketed by target type
+    policies: Arc<RwLock<PolicyStore>>,
+
+    /// HashMap of name to registered policy set
+    policy_sets: Arc<RwLock<HashMap<String, RegisteredPolicySet>>>,
+
+    /// HashMap of name to registered webhook
+    webhooks: Arc<RwLock<HashMap<String, RegisteredWebhook>>>,
+
+    /// HashMap of id to issued API key
+    api_keys: Arc<RwLock<HashMap<String, RegisteredApiKey>>>,
+
+    /// HashMap of name to registered delegation
+    delegations: Arc<RwLock<HashMap<String, RegisteredDelegation>>>,
+
+    /// Sends events to the webhooks
+    dispatcher: Arc<Dispatcher>,
+
+    /// WASM modules that policies can use for custom conditions
+    wasm: Arc<WasmModules>,
+
+    /// Recent uses of actions, for rate checks
+    rates: Arc<Velocity>,
+
+    /// Recent denies of each actor, to find the ones that keep getting denied
+    deny_streaks: Arc<DenyStreaks>,
+
+    /// How long policies take to evaluate in checks
+    policy_timings: Arc<PolicyTimings>,
+
+    /// Scores the risk of checks
+    risk: Arc<RiskScorer>,
+
+    /// Where every request is recorded to as it arrives, if anywhere
+    recorder: Option<Recorder>,
+
+    /// Where a sample of checks and their decisions is written to, if anywhere
+    sampler: Option<DecisionSampler>,
+
+    /// Ring buffer of the most recent check requests, with anonymized actors
+    recorded_checks: Arc<RwLock<VecDeque<CheckRequest>>>,
+
+    /// Policy changes waiting for approval, by id
+    proposals: RwLock<BTreeMap<u64, Proposal>>,
 
-    /// HashMap of name to registered policy
-    policies: Arc<RwLock<HashMap<String, RegisteredPolicyRule>>>,
+    /// The id of the most recent proposal
+    next_proposal: AtomicU64,
+
+    /// Checks waiting for approval, or approved, by id
+    approvals: RwLock<BTreeMap<u64, Approval>>,
+
+    /// The id of the most recent approval
+    next_approval: AtomicU64,
+
+    /// Emergency access granted with break glass, by id, until it runs out
+    break_glasses: RwLock<BTreeMap<u64, BreakGlass>>,
+
+    /// The id of the most recent break glass
+    next_break_glass: AtomicU64,
+
+    /// The sequence of the last bundle applied; only newer bundles are
+    bundle_sequence: AtomicU64,
+
+    /// Counts the changes made, so replicas can tell which watch events are newer than a sync
+    revision: AtomicU64,
+
+    /// Sends every change to the watchers
+    watchers: broadcast::Sender<WatchEvent>,
+
+    /// Identifies this run of the server, since revisions start over when it restarts
+    epoch: u64,
+
+    /// The most recent changes, oldest first, for change streams to resume from
+    changelog: RwLock<VecDeque<WatchEvent>>,
+
+    /// When we started, in seconds since the epoch; entities count as used then
+    started: u64,
+
+    /// How often registered actors, targets, and roles have been checked
+    usage: Usage,
 }
 
 impl Datastore {
     async fn new(
         backend: &StorageType,
+        config: Config,
         req_tx: flume::Sender<DsRequest>,
         req_rx: Receiver<DsRequest>,
     ) -> Self {
-        let backend: Box<dyn Storage + Send + Sync> = match backend {
-            StorageType::Etcd(url) => Box::new(EtcdStorage::new(url, req_tx).await),
-            StorageType::FileSystem(path) => Box::new(FileStorage::new(path).await),
-            StorageType::Nil => Box::new(NilStorage {}),
+        let backend_type = backend.clone();
+        let backend: Box<dyn Storage + Send + Sync> = match config.storage_runtime {
+            // opened on its own runtime, so its connections and background tasks run there too
+            Some(ref runtime) => {
+                let opened = runtime
+                    .spawn(open_storage(backend.clone(), req_tx, config.clone()))
+                    .await
+                    .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+                Box::new(DedicatedStorage::new(opened, runtime.clone()))
+            }
+            None => open_storage(backend.clone(), req_tx, config.clone()).await,
+        };
+        // faults go in under the monitoring, so they trip the breaker like real ones
+        let backend = match config.chaos {
+            Some(ref chaos) => Box::new(ChaosStorage::new(backend, chaos.clone())),
+            None => backend,
         };
+        let backend = MonitoredStorage::new(backend);
+        let storage_health = backend.health();
+
+        // in strict mode, anything we can't load stops us from starting
+        let mode = config.startup_mode;
+        let mut issues = Vec::new();
+        let targets = startup_load("targets", backend.load_targets().await, mode, &mut issues);
+        let actors = startup_load("actors", backend.load_actors().await, mode, &mut issues);
+        let roles = startup_load("roles", backend.load_roles().await, mode, &mut issues);
+        let groups = startup_load("groups", backend.load_groups().await, mode, &mut issues);
+        let policies = startup_load("policies", backend.load_policies().await, mode, &mut issues);
+        let policy_sets = startup_load(
+            "policy sets",
+            backend.load_policy_sets().await,
+            mode,
+            &mut issues,
+        );
+        let webhooks = startup_load("webhooks", backend.load_webhooks().await, mode, &mut issues);
+        let api_keys = startup_load("API keys", backend.load_api_keys().await, mode, &mut issues);
+        let delegations = startup_load(
+            "delegations",
+            backend.load_delegations().await,
+            mode,
+            &mut issues,
+        );
+        let proposals = startup_load(
+            "proposals",
+            backend.load_proposals().await,
+            mode,
+            &mut issues,
+        );
+        let proposals = decode_proposals(proposals, &mut issues);
+        let break_glasses: BTreeMap<u64, BreakGlass> = startup_load(
+            "break glasses",
+            backend.load_break_glasses().await,
+            mode,
+            &mut issues,
+        )
+        .into_values()
+        .map(|break_glass| (break_glass.id, break_glass.into()))
+        .collect();
+        let bundle = startup_load(
+            "the applied bundle",
+            backend.load_bundle().await,
+            mode,
+            &mut issues,
+        );
+        let upgraded = backend.upgraded();
 
-        let targets = backend
-            .load_targets()
-            .await
-            .expect("Could not load targets from backend");
+        let wasm = match config.wasm_dir {
+            Some(ref dir) => WasmModules::load(dir).expect("Could not load WASM modules"),
+            None => WasmModules::default(),
+        };
 
-        let actors = backend
-            .load_actors()
-            .await
-            .expect("Could not load actors from backend");
+        // the counts are only approximate, so losing them is no reason not to start
+        let rates = match config.rates_file {
+            Some(ref path) => Velocity::load(path).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                Velocity::default()
+            }),
+            None => Velocity::default(),
+        };
+        let risk = RiskScorer::new(config.risk.clone().unwrap_or_default());
+        let recorder = config.record_requests.as_ref().map(|path| {
+            Recorder::open(path).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(1);
+            })
+        });
+        let sampler = config.sample_decisions.as_ref().map(|path| {
+            DecisionSampler::open(path, config.decision_sample_rate).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(1);
+            })
+        });
+        let deny_streaks = DenyStreaks::new(config.deny_streaks.clone());
+        let policy_timings = PolicyTimings::new(config.policy_budget_us);
+        let targets = Sharded::new(config.shards, targets);
+        let actors = Sharded::new(config.shards, actors);
 
-        let roles = backend
-            .load_roles()
-            .await
-            .expect("Could not load roles from backend");
+        let ds = Datastore {
+            rx: req_rx,
+            storage: Box::new(backend),
+            backend: backend_type,
+            storage_health,
+            startup_issues: RwLock::new(Vec::new()),
+            drift: RwLock::new(HashSet::new()),
+            drift_repairs: AtomicU64::new(0),
+            recorded_checks: Arc::new(RwLock::new(VecDeque::with_capacity(config.recorded_checks))),
+            recorder,
+            sampler,
+            usage: Usage::new(config.check_sample_rate),
+            config,
+            targets: Arc::new(targets),
+            actors: Arc::new(actors),
+            roles: Arc::new(RwLock::new(roles)),
+            groups: Arc::new(Versioned::new(groups)),
+            expansions: Arc::new(Expansions::default()),
+            policies: Arc::new(RwLock::new(PolicyStore::from(policies))),
+            policy_sets: Arc::new(RwLock::new(policy_sets)),
+            webhooks: Arc::new(RwLock::new(webhooks)),
+            api_keys: Arc::new(RwLock::new(api_keys)),
+            delegations: Arc::new(RwLock::new(delegations)),
+            dispatcher: Arc::new(Dispatcher::new()),
+            wasm: Arc::new(wasm),
+            rates: Arc::new(rates),
+            deny_streaks: Arc::new(deny_streaks),
+            policy_timings: Arc::new(policy_timings),
+            risk: Arc::new(risk),
+            next_proposal: AtomicU64::new(proposals.keys().max().copied().unwrap_or_default()),
+            proposals: RwLock::new(proposals),
+            approvals: RwLock::new(BTreeMap::new()),
+            next_approval: AtomicU64::new(0),
+            next_break_glass: AtomicU64::new(
+                break_glasses.keys().max().copied().unwrap_or_default(),
+            ),
+            break_glasses: RwLock::new(break_glasses),
+            bundle_sequence: AtomicU64::new(
+                bundle.map(|bundle| bundle.sequence).unwrap_or_default(),
+            ),
+            revision: AtomicU64::new(0),
+            watchers: broadcast::channel(WATCH_BUFFER).0,
+            epoch: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_millis() as u64)
+                .unwrap_or_default(),
+            changelog: RwLock::new(VecDeque::new()),
+            started: now(),
+        };
 
-        let groups = backend
-            .load_groups()
-            .await
-            .expect("Could not load groups from backend");
+        ds.migrate_upgraded(upgraded, &mut issues).await;
+        ds.resolve_startup_issues(issues).await;
+        ds.bootstrap_api_key().await;
+        ds
+    }
 
-        let policies = backend
-            .load_policies()
-            .await
-            .expect("Could not load policies from backend");
+    /// Register the API key the configuration starts us with, unless it already is
+    async fn bootstrap_api_key(&self) {
+        let Some(ref key) = self.config.bootstrap_api_key else {
+            return;
+        };
+        let key =
+            RegisteredApiKey::bootstrap(key.expose(), &self.config.bootstrap_principal, now())
+                .unwrap_or_else(|err| panic!("Bootstrap API key: {err}"));
 
-        Datastore {
-            rx: req_rx,
-            storage: backend,
-            targets: Arc::new(RwLock::new(targets)),
-            actors: Arc::new(RwLock::new(actors)),
-            roles: Arc::new(RwLock::new(roles)),
-            groups: Arc::new(RwLock::new(groups)),
-            policies: Arc::new(RwLock::new(policies)),
+        if let Some(existing) = self.api_keys.read().await.get(&key.id) {
+            if existing.hash == key.hash && existing.principal == key.principal {
+                return;
+            }
+        }
+
+        let txn = vec![BackendUpdate::PutApiKey(key.clone())];
+        if let Err(err) = self.storage.persist_changes(&txn).await {
+            eprintln!("Could not register the bootstrap API key: {err}");
+            return;
+        }
+        for update in txn {
+            self.update(update).await;
         }
+        println!(
+            "Registered bootstrap API key {} for {}",
+            key.id, key.principal
+        );
     }
 
-    /// How the datastore is actually created, returning only the sender channel
-    pub(crate) async fn create(backend: &StorageType) -> flume::Sender<DsRequest> {
+    /// How the datastore is actually created, returning only the sender channel, a way to
+    /// follow whether we are the leader, and the health of the storage backend
+    pub(crate) async fn create(
+        backend: &StorageType,
+        config: Config,
+    ) -> (
+        flume::Sender<DsRequest>,
+        watch::Receiver<Leadership>,
+        Arc<StorageHealth>,
+    ) {
         let (req_tx, req_rx) = flume::unbounded();
-        let ds = Self::new(backend, req_tx.clone(), req_rx).await;
+        let ds = Self::new(backend, config, req_tx.clone(), req_rx).await;
+        let leadership = ds.storage.leadership();
+        let storage_health = ds.storage_health.clone();
 
         let arc_ds = Arc::new(ds);
+        let me = arc_ds.clone();
+        tokio::spawn(async move {
+            me.watch_storage_health().await;
+        });
+        let me = arc_ds.clone();
+        tokio::spawn(async move {
+            me.maintain_rates().await;
+        });
+        let me = arc_ds.clone();
+        tokio::spawn(async move {
+            me.maintain_break_glasses().await;
+        });
         tokio::spawn(async move {
             arc_ds.run().await;
         });
 
-        req_tx
+        (req_tx, leadership, storage_health)
+    }
+
+    /// Tell the webhooks whenever the storage backend goes down or recovers
+    async fn watch_storage_health(&self) {
+        let mut available = self.storage_health.subscribe();
+
+        while available.changed().await.is_ok() {
+            let status = self.storage_health.status();
+            let data = json!({
+                "available": status.available,
+                "last_error": status.last_error,
+            });
+            self.notify(Event::StorageHealth, data).await;
+        }
+    }
+
+    /// Forget uses of actions and denies too old to count every so often, saving the uses left if
+    /// there is a file for them
+    async fn maintain_rates(&self) {
+        let mut interval = tokio::time::interval(RATES_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.rates.prune();
+            self.deny_streaks.prune();
+            if let Some(ref path) = self.config.rates_file {
+                if let Err(err) = self.rates.save(path) {
+                    eprintln!("{err}");
+                }
+            }
+        }
+    }
+
+    /// End emergency access as it runs out
+    async fn maintain_break_glasses(&self) {
+        let mut interval = tokio::time::interval(BREAK_GLASS_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.end_break_glasses().await;
+        }
     }
 
     /// Our main run loop.  We listen to incoming messages from the server and respond accordingly
     async fn run(self: Arc<Self>) {
         while let Ok(msg) = self.rx.recv_async().await {
+            if let Some(ref recorder) = self.recorder {
+                recorder.record(&msg);
+            }
             let me = Arc::clone(&self);
             match msg {
                 // TARGETS
                 DsRequest::AddTarget(req, tx) => {
                     tokio::spawn(async move { me.add_target(req, tx).await });
                 }
+                DsRequest::AddTargets(req, tx) => {
+                    tokio::spawn(async move { me.add_targets(req, tx).await });
+                }
                 DsRequest::ModifyTarget(req, tx) => {
                     tokio::spawn(async move { me.modify_target(req, tx).await });
                 }
@@ -141,6 +535,9 @@ impl Datastore {
                 DsRequest::AddActor(req, tx) => {
                     tokio::spawn(async move { me.add_actor(req, tx).await });
                 }
+                DsRequest::AddActors(req, tx) => {
+                    tokio::spawn(async move { me.add_actors(req, tx).await });
+                }
                 DsRequest::ModifyActor(req, tx) => {
                     tokio::spawn(async move { me.modify_actor(req, tx).await });
                 }
@@ -150,6 +547,18 @@ impl Datastore {
                 DsRequest::GetActors(req, tx) => {
                     tokio::spawn(async move { me.get_actors(req, tx).await });
                 }
+                DsRequest::GetActorMemberships(req, tx) => {
+                    tokio::spawn(async move { me.get_actor_memberships(req, tx).await });
+                }
+                DsRequest::AddDelegation(req, tx) => {
+                    tokio::spawn(async move { me.add_delegation(req, tx).await });
+                }
+                DsRequest::RemoveDelegation(req, tx) => {
+                    tokio::spawn(async move { me.remove_delegation(req, tx).await });
+                }
+                DsRequest::GetDelegations(req, tx) => {
+                    tokio::spawn(async move { me.get_delegations(req, tx).await });
+                }
                 // ROLES
                 DsRequest::AddRole(req, tx) => {
                     tokio::spawn(async move { me.add_role(req, tx).await });
@@ -170,12 +579,33 @@ impl Datastore {
                 DsRequest::ModifyGroup(req, tx) => {
                     tokio::spawn(async move { me.modify_group(req, tx).await });
                 }
+                DsRequest::BulkModifyMemberships(req, tx) => {
+                    tokio::spawn(async move { me.bulk_modify_memberships(req, tx).await });
+                }
                 DsRequest::RemoveGroup(req, tx) => {
                     tokio::spawn(async move { me.remove_group(req, tx).await });
                 }
+                DsRequest::CloneGroup(req, tx) => {
+                    tokio::spawn(async move {
+                        me.copy_group(req.name, req.new_name, false, req.dry_run, tx)
+                            .await
+                    });
+                }
+                DsRequest::RenameGroup(req, tx) => {
+                    tokio::spawn(async move {
+                        me.copy_group(req.name, req.new_name, true, req.dry_run, tx)
+                            .await
+                    });
+                }
                 DsRequest::GetGroups(req, tx) => {
                     tokio::spawn(async move { me.get_groups(req, tx).await });
                 }
+                DsRequest::GetGroupMembers(req, tx) => {
+                    tokio::spawn(async move { me.get_group_members(req, tx).await });
+                }
+                DsRequest::SyncGroups(source, groups, tx) => {
+                    tokio::spawn(async move { me.sync_groups(source, groups, tx).await });
+                }
                 // POLICIES
                 DsRequest::AddPolicy(req, tx) => {
                     tokio::spawn(async move { me.add_policy(req, tx).await });
@@ -186,17 +616,157 @@ impl Datastore {
                 DsRequest::RemovePolicy(req, tx) => {
                     tokio::spawn(async move { me.remove_policy(req, tx).await });
                 }
+                DsRequest::ClonePolicy(req, tx) => {
+                    tokio::spawn(async move {
+                        me.copy_policy(req.name, req.new_name, false, req.dry_run, tx)
+                            .await
+                    });
+                }
+                DsRequest::RenamePolicy(req, tx) => {
+                    tokio::spawn(async move {
+                        me.copy_policy(req.name, req.new_name, true, req.dry_run, tx)
+                            .await
+                    });
+                }
                 DsRequest::GetPolicies(req, tx) => {
                     tokio::spawn(async move { me.get_policies(req, tx).await });
                 }
+                DsRequest::AddPolicySet(req, tx) => {
+                    tokio::spawn(async move { me.add_policy_set(req, tx).await });
+                }
+                DsRequest::ModifyPolicySet(req, tx) => {
+                    tokio::spawn(async move { me.modify_policy_set(req, tx).await });
+                }
+                DsRequest::RemovePolicySet(req, tx) => {
+                    tokio::spawn(async move { me.remove_policy_set(req, tx).await });
+                }
+                DsRequest::GetPolicySets(req, tx) => {
+                    tokio::spawn(async move { me.get_policy_sets(req, tx).await });
+                }
+                DsRequest::Propose(proposal, tx) => {
+                    tokio::spawn(async move { me.propose(proposal, tx).await });
+                }
+                DsRequest::ListProposals(tx) => {
+                    tokio::spawn(async move { me.list_proposals(tx).await });
+                }
+                DsRequest::ApproveProposal(id, approver, tx) => {
+                    tokio::spawn(async move { me.approve_proposal(id, approver, tx).await });
+                }
+                DsRequest::RejectProposal(id, tx) => {
+                    tokio::spawn(async move { me.reject_proposal(id, tx).await });
+                }
+                // WEBHOOKS
+                DsRequest::AddWebhook(req, tx) => {
+                    tokio::spawn(async move { me.add_webhook(req, tx).await });
+                }
+                DsRequest::RemoveWebhook(req, tx) => {
+                    tokio::spawn(async move { me.remove_webhook(req, tx).await });
+                }
+                DsRequest::GetWebhooks(req, tx) => {
+                    tokio::spawn(async move { me.get_webhooks(req, tx).await });
+                }
+                DsRequest::AddApiKey(key, tx) => {
+                    tokio::spawn(async move { me.add_api_key(key, tx).await });
+                }
+                DsRequest::RevokeApiKey(id, tx) => {
+                    tokio::spawn(async move { me.revoke_api_key(id, tx).await });
+                }
+                DsRequest::GetApiKeys(tx) => {
+                    tokio::spawn(async move { me.get_api_keys(tx).await });
+                }
+                DsRequest::VerifyApiKey(key, tx) => {
+                    tokio::spawn(async move { me.verify_api_key(key, tx).await });
+                }
+                DsRequest::GetWebhookDeliveries(req, tx) => {
+                    tokio::spawn(async move { me.get_webhook_deliveries(req, tx).await });
+                }
                 // CHECKS
                 DsRequest::Check(req, tx) => {
                     tokio::spawn(async move { me.check(req, tx).await });
                 }
+                DsRequest::Approve(id, approver, tx) => {
+                    tokio::spawn(async move { me.approve(id, approver, tx).await });
+                }
+                DsRequest::GetApprovals(tx) => {
+                    tokio::spawn(async move { me.get_approvals(tx).await });
+                }
+                DsRequest::BreakGlass(req, caller, tx) => {
+                    tokio::spawn(async move { me.break_glass(req, caller, tx).await });
+                }
+                DsRequest::GetBreakGlass(tx) => {
+                    tokio::spawn(async move { me.get_break_glass(tx).await });
+                }
+                DsRequest::TraceCheck(req, tx) => {
+                    tokio::spawn(async move { me.trace_check(req, tx).await });
+                }
+                DsRequest::CoverageReport(req, tx) => {
+                    tokio::spawn(async move { me.coverage_report(req, tx).await });
+                }
+                DsRequest::WhatIf(req, tx) => {
+                    tokio::spawn(async move { me.what_if(req, tx).await });
+                }
+                DsRequest::TestPolicies(req, tx) => {
+                    tokio::spawn(async move { me.test_policies(req, tx).await });
+                }
+                DsRequest::GetServerStats(req, tx) => {
+                    tokio::spawn(async move { me.get_server_stats(req, tx).await });
+                }
+                DsRequest::GetPolicyStats(req, tx) => {
+                    tokio::spawn(async move { me.get_policy_stats(req, tx).await });
+                }
+                DsRequest::GetServerInfo(tx) => {
+                    tokio::spawn(async move { me.get_server_info(tx).await });
+                }
                 // UPDATES FROM BACKEND
                 DsRequest::Update(req) => {
                     tokio::spawn(async move { me.update(req).await });
                 }
+                DsRequest::Reconcile(loaded, tx) => {
+                    tokio::spawn(async move { me.reconcile(loaded, tx).await });
+                }
+                DsRequest::ApplyTransaction(req, tx) => {
+                    tokio::spawn(async move { me.apply_transaction(req, tx).await });
+                }
+                DsRequest::ApplyBundle(bundle, dry_run, tx) => {
+                    tokio::spawn(async move { me.apply_bundle(bundle, dry_run, tx).await });
+                }
+                DsRequest::GetReferences(req, tx) => {
+                    tokio::spawn(async move { me.get_references(req, tx).await });
+                }
+                DsRequest::FindUnused(req, tx) => {
+                    tokio::spawn(async move { me.find_unused(req, tx).await });
+                }
+                DsRequest::RemoveUnused(req, tx) => {
+                    tokio::spawn(async move { me.remove_unused(req, tx).await });
+                }
+                DsRequest::RepairDrift(tx) => {
+                    tokio::spawn(async move { me.repair_drift(tx).await });
+                }
+                DsRequest::ReloadState(tx) => {
+                    tokio::spawn(async move { me.reload_state(tx).await });
+                }
+                // REPLICATION
+                DsRequest::Sync(tx) => {
+                    tokio::spawn(async move { me.sync(tx).await });
+                }
+                DsRequest::Watch(tx) => {
+                    let _ = tx.send(DsResponse::Watcher(me.watchers.subscribe()));
+                }
+                DsRequest::StreamChanges(token, tx) => {
+                    tokio::spawn(async move { me.stream_changes(token, tx).await });
+                }
+                DsRequest::Load(state, tx) => {
+                    tokio::spawn(async move { me.load(*state, tx).await });
+                }
+                DsRequest::Apply(req, tx) => {
+                    tokio::spawn(async move {
+                        me.update(req).await;
+                        let _ = tx.send(DsResponse::Applied);
+                    });
+                }
+                DsRequest::Replicate(req, tx) => {
+                    tokio::spawn(async move { me.replicate(req, tx).await });
+                }
             }
         }
 
@@ -205,13 +775,24 @@ impl Datastore {
 
     /// Add a new target
     async fn add_target(&self, req: AddTargetRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
+        let violations = self.attribute_violations(
+            AttributeRules::check_target,
+            &[("attributes", &req.attributes)],
+        );
+        if !violations.is_empty() {
+            let _ = tx.send(DsResponse::Error(validation::violations_status(violations)));
+            return;
+        }
+
         // add to the local cache
         let name = req.name.to_ascii_lowercase();
         let typestr = req.typestr.to_ascii_lowercase();
 
         // get or create the hashmap for this "type" of target
-        let mut targets = self.targets.write().await;
-        let typed_targets = targets.entry(typestr.clone()).or_insert_with(HashMap::new);
+        let mut targets = self.targets.shard(&typestr).write().await;
+        let typed_targets = targets.entry(intern(&typestr)).or_insert_with(HashMap::new);
 
         // if target already exists, return an error
         if typed_targets.contains_key(&name) {
@@ -226,37 +807,194 @@ impl Datastore {
         drop(targets);
 
         // convert the attributes to a hashmap
-        let mut attributes = HashMap::new();
-        for attrib in req.attributes {
-            attributes.insert(attrib.0, HashSet::from_iter(attrib.1.values));
+        let attributes = attribute::from_request(req.attributes);
+
+        if let Err(err) = check_action_groups(req.action_groups.keys()) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
         }
 
-        let new_target = RegisteredTarget::new(&name, &typestr, req.actions, attributes);
+        let mut new_target =
+            RegisteredTarget::new(&name, &typestr, req.actions, attributes, req.action_groups);
+        new_target.timestamps.touch(now());
 
-        match self.storage.save_target(&new_target).await {
-            Ok(_) => {
-                self.update(BackendUpdate::PutTarget(new_target.clone()))
-                    .await;
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
+        if !dry_run {
+            match self.storage.save_target(&new_target).await {
+                Ok(_) => {
+                    let update = BackendUpdate::PutTarget(new_target.clone());
+                    self.notify_changes(std::slice::from_ref(&update)).await;
+                    self.update(update).await;
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
             }
         }
 
         // TODO! -- do something with error
-        let _ = tx.send(DsResponse::SingleTarget(new_target.into()));
+        let _ = tx.send(DsResponse::SingleTarget(new_target.into(), Vec::new()));
+    }
+
+    /// Add many targets, persisting them together
+    ///
+    /// Each target is checked on its own, so one that can't be added doesn't stop the rest.
+    async fn add_targets(&self, req: AddTargetsRequest, tx: Sender<DsResponse>) {
+        if req.targets.len() > MAX_BULK_ADD {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(format!(
+                "At most {MAX_BULK_ADD} targets can be added at once"
+            ))));
+            return;
+        }
+
+        let mut added = Vec::with_capacity(req.targets.len());
+        {
+            let targets = self.targets.read().await;
+            let mut seen = HashSet::new();
+            for item in req.targets {
+                let name = item.name.to_ascii_lowercase();
+                let typestr = item.typestr.to_ascii_lowercase();
+                let exists = targets
+                    .get(&typestr)
+                    .is_some_and(|typed| typed.contains_key(&name));
+                let violations = self.attribute_violations(
+                    AttributeRules::check_target,
+                    &[("attributes", &item.attributes)],
+                );
+
+                added.push(if name.is_empty() || typestr.is_empty() {
+                    Err(String::from("Name and typestr cannot be null"))
+                } else if exists || !seen.insert((typestr.clone(), name.clone())) {
+                    Err(String::from("Target already exists"))
+                } else if let Err(err) = check_action_groups(item.action_groups.keys()) {
+                    Err(err)
+                } else if !violations.is_empty() {
+                    Err(validation::describe(&violations))
+                } else {
+                    let attributes = attribute::from_request(item.attributes);
+                    Ok(RegisteredTarget::new(
+                        &name,
+                        &typestr,
+                        item.actions,
+                        attributes,
+                        item.action_groups,
+                    ))
+                });
+            }
+        }
+
+        let added = self
+            .persist_added(
+                added,
+                |target| BackendUpdate::PutTarget(target.clone()),
+                |target| &mut target.timestamps,
+                req.dry_run,
+            )
+            .await;
+        let results = added
+            .into_iter()
+            .map(|result| match result {
+                Ok(target) => AddTargetResult {
+                    target: Some(target.into()),
+                    error: String::new(),
+                },
+                Err(error) => AddTargetResult {
+                    target: None,
+                    error,
+                },
+            })
+            .collect();
+        let _ = tx.send(DsResponse::AddedTargets(results));
+    }
+
+    /// Persist the entities of a bulk add that could be added, all together, unless it is a dry
+    /// run; if that fails, only those the backend saved before failing were added
+    async fn persist_added<T>(
+        &self,
+        mut added: Vec<Result<T, String>>,
+        put: impl Fn(&T) -> BackendUpdate,
+        timestamps: impl Fn(&mut T) -> &mut Timestamps,
+        dry_run: bool,
+    ) -> Vec<Result<T, String>> {
+        let now = now();
+        for entity in added.iter_mut().flatten() {
+            timestamps(entity).touch(now);
+        }
+        let mut txn: Vec<BackendUpdate> = added.iter().flatten().map(put).collect();
+        if dry_run || txn.is_empty() {
+            return added;
+        }
+
+        let (saved, err) = match self.persist(&mut txn, now).await {
+            Ok(_) => (txn.len(), None),
+            Err(err) => (err.saved.min(txn.len()), Some(err.err)),
+        };
+        txn.truncate(saved);
+        self.notify_changes(&txn).await;
+        for update in txn {
+            self.update(update).await;
+        }
+
+        let Some(err) = err else {
+            return added;
+        };
+        let mut persisted = 0;
+        added
+            .into_iter()
+            .map(|result| {
+                result.and_then(|entity| {
+                    persisted += 1;
+                    match persisted <= saved {
+                        true => Ok(entity),
+                        false => Err(err.clone()),
+                    }
+                })
+            })
+            .collect()
     }
 
     /// Modify and existing target
     async fn modify_target(&self, req: ModifyTargetRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
+        if let Err(err) = check_replace(&[
+            (
+                "actions",
+                req.set_actions.is_some(),
+                !req.add_actions.is_empty() || !req.remove_actions.is_empty(),
+            ),
+            (
+                "attributes",
+                req.set_attributes.is_some(),
+                !req.add_attributes.is_empty() || !req.remove_attributes.is_empty(),
+            ),
+            (
+                "action_groups",
+                req.set_action_groups.is_some(),
+                !req.add_action_groups.is_empty() || !req.remove_action_groups.is_empty(),
+            ),
+        ]) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
+        }
+
+        let mut written = vec![("add_attributes", &req.add_attributes)];
+        if let Some(set) = &req.set_attributes {
+            written.push(("set_attributes", &set.attributes));
+        }
+        let violations = self.attribute_violations(AttributeRules::check_target, &written);
+        if !violations.is_empty() {
+            let _ = tx.send(DsResponse::Error(validation::violations_status(violations)));
+            return;
+        }
+
         let name = req.name.to_ascii_lowercase();
         let typestr = req.typestr.to_ascii_lowercase();
 
-        let targets = self.targets.read().await;
+        let targets = self.targets.shard(&typestr).read().await;
 
-        if !targets.contains_key(&typestr) {
+        if !targets.contains_key(typestr.as_str()) {
             // TODO! -- do something with error
             let _ = tx.send(DsResponse::Error(Status::not_found(
                 "Could not find target by type",
@@ -264,7 +1002,7 @@ impl Datastore {
             return;
         }
 
-        let typed_targets = targets.get(&typestr).unwrap();
+        let typed_targets = targets.get(typestr.as_str()).unwrap();
         if !(typed_targets.contains_key(&name)) {
             // TODO! -- do something with error
             let _ = tx.send(DsResponse::Error(Status::not_found(
@@ -280,6 +1018,9 @@ impl Datastore {
         drop(targets);
 
         // update actions
+        if let Some(set) = req.set_actions {
+            updated_target.actions = set.actions.iter().map(|a| a.to_ascii_lowercase()).collect();
+        }
         for action in req.add_actions {
             updated_target.actions.insert(action.to_ascii_lowercase());
         }
@@ -287,52 +1028,94 @@ impl Datastore {
             updated_target.actions.remove(&action.to_ascii_lowercase());
         }
 
+        // update action groups
+        let set_groups = req.set_action_groups.map(|set| set.groups);
+        if let Err(err) = check_action_groups(
+            req.add_action_groups
+                .keys()
+                .chain(set_groups.iter().flat_map(|groups| groups.keys())),
+        ) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
+        }
+        if let Some(groups) = set_groups {
+            updated_target.action_groups = action_groups(groups);
+        }
+        for group in req.remove_action_groups {
+            updated_target
+                .action_groups
+                .remove(&group.to_ascii_lowercase());
+        }
+        updated_target
+            .action_groups
+            .extend(action_groups(req.add_action_groups));
+
         // update attributes
+        let attributes = Arc::make_mut(&mut updated_target.attributes);
+        if let Some(set) = req.set_attributes {
+            *attributes = replaced_attributes(set);
+        }
         for attrib in req.add_attributes {
             let key = attrib.0;
             let values = attrib.1.values;
 
-            let attrib_entry = updated_target.attributes.entry(key).or_default();
-            attrib_entry.extend(values);
+            let attrib_entry = attributes.entry(intern(&key)).or_default();
+            attrib_entry.extend(values.iter().map(|value| intern(value)));
         }
         for attrib in req.remove_attributes {
             let key = attrib.0;
             let values = attrib.1.values;
 
-            if let Some(current_values) = updated_target.attributes.get_mut(&key) {
+            if let Some(current_values) = attributes.get_mut(key.as_str()) {
                 for value in values {
-                    current_values.remove(&value);
+                    current_values.remove(value.as_str());
                 }
 
                 if current_values.is_empty() {
-                    updated_target.attributes.remove(&key);
+                    attributes.remove(key.as_str());
                 }
             }
         }
 
-        match self.storage.save_target(&updated_target).await {
-            Ok(_) => {
-                self.update(BackendUpdate::PutTarget(updated_target.clone()))
-                    .await;
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
+        updated_target.timestamps.touch(now());
+
+        if !dry_run {
+            match self.storage.save_target(&updated_target).await {
+                Ok(_) => {
+                    let update = BackendUpdate::PutTarget(updated_target.clone());
+                    self.notify_changes(std::slice::from_ref(&update)).await;
+                    self.update(update).await;
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
             }
         }
 
         // TODO! -- do something with error
-        let _ = tx.send(DsResponse::SingleTarget(updated_target.clone().into()));
+        let _ = tx.send(DsResponse::SingleTarget(
+            updated_target.clone().into(),
+            Vec::new(),
+        ));
     }
 
     /// Remove an existing target
     async fn remove_target(&self, req: RemoveTargetRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
         let name = req.name.to_ascii_lowercase();
         let typestr = req.typestr.to_ascii_lowercase();
 
         // make sure the target type exists
-        if !self.targets.read().await.contains_key(&typestr) {
+        if !self
+            .targets
+            .shard(&typestr)
+            .read()
+            .await
+            .contains_key(typestr.as_str())
+        {
             // TODO! -- do something with error
             let _ = tx.send(DsResponse::Error(Status::not_found(
                 "Could not find target by type",
@@ -341,8 +1124,8 @@ impl Datastore {
         }
 
         // make sure the target exists
-        let targets = self.targets.read().await;
-        let typed_targets = targets.get(&typestr).unwrap();
+        let targets = self.targets.shard(&typestr).read().await;
+        let typed_targets = targets.get(typestr.as_str()).unwrap();
         if !(typed_targets.contains_key(&name)) {
             // TODO! -- do something with error
             let _ = tx.send(DsResponse::Error(Status::not_found(
@@ -357,39 +1140,73 @@ impl Datastore {
         // explicitly drop targets to release lock
         drop(targets);
 
-        // try to remove the target from backend before persisting
-        match self
-            .storage
-            .remove_target(&existing_target.typestr, &existing_target.name)
-            .await
-        {
-            Ok(_) => {
-                self.update(BackendUpdate::DeleteTarget(
-                    existing_target.typestr.clone(),
-                    existing_target.name.clone(),
-                ))
-                .await
+        // find the policies that name this target, and what they would be without it
+        let mut references = Vec::new();
+        let mut txn = Vec::new();
+        for rule in self.policies.read().await.values() {
+            if !rule.refers_to_target(&typestr, &name) {
+                continue;
             }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
+
+            references.push(format!("policy/{}", rule.name));
+            txn.push(match rule.without_target(&name) {
+                Some(rule) => BackendUpdate::PutPolicyRule(Box::new(rule)),
+                None => BackendUpdate::DeletePolicyRule(rule.name.clone()),
+            });
+        }
+        references.sort_unstable();
+
+        match req.references() {
+            References::Leave => txn.clear(),
+            References::Refuse if !references.is_empty() => {
+                let _ = tx.send(DsResponse::Error(Status::failed_precondition(format!(
+                    "Target is still referenced by {}",
+                    references.join(", ")
+                ))));
                 return;
             }
+            References::Refuse | References::Cascade => {}
+        }
+
+        txn.push(BackendUpdate::DeleteTarget(
+            existing_target.typestr.to_string(),
+            existing_target.name.clone(),
+        ));
+
+        // persist and run updates locally
+        if !dry_run {
+            match self.persist(&mut txn, now()).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
+            }
         }
 
         // TODO! -- do something with error
-        let _ = tx.send(DsResponse::SingleTarget(existing_target.clone().into()));
+        let _ = tx.send(DsResponse::SingleTarget(
+            existing_target.clone().into(),
+            references,
+        ));
     }
 
     /// Get all targets, optionally filtered by type
     async fn get_targets(&self, req: GetTargetsRequest, tx: Sender<DsResponse>) {
-        let typestr = req.typestr.map(|t| t.to_ascii_lowercase());
-        let name = req.name.map(|t| t.to_ascii_lowercase());
-        let mut found_targets: Vec<Target> = Vec::new();
+        let typestr = req.typestr.as_ref().map(|t| t.to_ascii_lowercase());
+        let name = req.name.as_ref().map(|t| t.to_ascii_lowercase());
+        let mut found: Vec<(Target, _)> = Vec::new();
+        let usage = self.usage.read().await;
 
         for typemap in self.targets.read().await.iter() {
             if let Some(ref filter_type) = typestr {
-                if typemap.0.as_str() != filter_type {
+                if **typemap.0 != *filter_type {
                     continue;
                 }
             }
@@ -399,21 +1216,45 @@ impl Datastore {
                         continue;
                     }
                 }
-                found_targets.push(target.1.clone().into());
+                let key = Usage::key("target", typemap.0, target.0);
+                found.push((
+                    Target::from(target.1.clone()),
+                    usage.get(&key).copied().unwrap_or_default().into(),
+                ));
             }
         }
 
-        let _ = tx.send(DsResponse::MultipleTargets(found_targets));
+        sort_list(&mut found, req.sort_by(), req.direction(), |(target, _)| {
+            (
+                &target.typestr,
+                &target.name,
+                target.created_at,
+                target.updated_at,
+            )
+        });
+        let (found_targets, hits) = found.into_iter().unzip();
+        let _ = tx.send(DsResponse::MultipleTargets(found_targets, hits));
     }
 
     /// Add a new actor
     async fn add_actor(&self, req: AddActorRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
+        let violations = self.attribute_violations(
+            AttributeRules::check_actor,
+            &[("attributes", &req.attributes)],
+        );
+        if !violations.is_empty() {
+            let _ = tx.send(DsResponse::Error(validation::violations_status(violations)));
+            return;
+        }
+
         let name = req.name.to_ascii_lowercase();
         let typestr = req.typestr.to_ascii_lowercase();
 
-        // get or create the hashmap for this "type" of target
-        let mut actors = self.actors.write().await;
-        let typed_actors = actors.entry(typestr.clone()).or_insert_with(HashMap::new);
+        // get or create the hashmap for this "type" of target; the quota counts every shard
+        let (mut actors, count) = self.actors.write_counted(&typestr).await;
+        let typed_actors = actors.entry(intern(&typestr)).or_insert_with(HashMap::new);
 
         // if actor already exists, return an error
         if typed_actors.contains_key(&name) {
@@ -426,41 +1267,140 @@ impl Datastore {
             return;
         }
 
+        // make sure we have room for another actor
+        if let Some(max_actors) = self.config.quotas.max_actors {
+            if count >= max_actors {
+                let _ = tx.send(DsResponse::Error(Status::resource_exhausted(format!(
+                    "Actor limit of {max_actors} reached"
+                ))));
+                return;
+            }
+        }
+
         // drop the lock
         drop(actors);
 
         // convert the attributes to a hashmap
-        let mut attributes = HashMap::new();
-        for (key, vals) in req.attributes {
-            attributes.insert(key, HashSet::from_iter(vals.values));
-        }
+        let attributes = attribute::from_request(req.attributes);
 
-        let new_actor = RegisteredActor::new(&name, &typestr, attributes);
+        let mut new_actor = RegisteredActor::new(&name, &typestr, attributes);
+        new_actor.timestamps.touch(now());
 
-        match self.storage.save_actor(&new_actor).await {
-            Ok(_) => {
-                self.update(BackendUpdate::PutActor(new_actor.clone()))
-                    .await
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
+        if !dry_run {
+            match self.storage.save_actor(&new_actor).await {
+                Ok(_) => {
+                    self.update(BackendUpdate::PutActor(new_actor.clone()))
+                        .await
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
             }
         }
 
         // TODO! -- do something with error
-        let _ = tx.send(DsResponse::SingleActor(new_actor.into()));
+        let _ = tx.send(DsResponse::SingleActor(new_actor.into(), Vec::new()));
+    }
+
+    /// Add many actors, persisting them together
+    ///
+    /// Each actor is checked on its own, so one that can't be added doesn't stop the rest.
+    async fn add_actors(&self, req: AddActorsRequest, tx: Sender<DsResponse>) {
+        if req.actors.len() > MAX_BULK_ADD {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(format!(
+                "At most {MAX_BULK_ADD} actors can be added at once"
+            ))));
+            return;
+        }
+
+        let mut added = Vec::with_capacity(req.actors.len());
+        {
+            let actors = self.actors.read().await;
+            let mut count: usize = actors.values().map(|typed| typed.len()).sum();
+            let mut seen = HashSet::new();
+            for item in req.actors {
+                let name = item.name.to_ascii_lowercase();
+                let typestr = item.typestr.to_ascii_lowercase();
+                let exists = actors
+                    .get(&typestr)
+                    .is_some_and(|typed| typed.contains_key(&name));
+                let violations = self.attribute_violations(
+                    AttributeRules::check_actor,
+                    &[("attributes", &item.attributes)],
+                );
+
+                added.push(match self.config.quotas.max_actors {
+                    _ if name.is_empty() || typestr.is_empty() => {
+                        Err(String::from("Name and typestr cannot be null"))
+                    }
+                    _ if exists || !seen.insert((typestr.clone(), name.clone())) => {
+                        Err(String::from("Actor already exists"))
+                    }
+                    _ if !violations.is_empty() => Err(validation::describe(&violations)),
+                    Some(max_actors) if count >= max_actors => {
+                        Err(format!("Actor limit of {max_actors} reached"))
+                    }
+                    _ => {
+                        count += 1;
+                        let attributes = attribute::from_request(item.attributes);
+                        Ok(RegisteredActor::new(&name, &typestr, attributes))
+                    }
+                });
+            }
+        }
+
+        let added = self
+            .persist_added(
+                added,
+                |actor| BackendUpdate::PutActor(actor.clone()),
+                |actor| &mut actor.timestamps,
+                req.dry_run,
+            )
+            .await;
+        let results = added
+            .into_iter()
+            .map(|result| match result {
+                Ok(actor) => AddActorResult {
+                    actor: Some(actor.into()),
+                    error: String::new(),
+                },
+                Err(error) => AddActorResult { actor: None, error },
+            })
+            .collect();
+        let _ = tx.send(DsResponse::AddedActors(results));
     }
 
     /// Modify and existing actor
     async fn modify_actor(&self, req: ModifyActorRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
+        if let Err(err) = check_replace(&[(
+            "attributes",
+            req.set_attributes.is_some(),
+            !req.add_attributes.is_empty() || !req.remove_attributes.is_empty(),
+        )]) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
+        }
+
+        let mut written = vec![("add_attributes", &req.add_attributes)];
+        if let Some(set) = &req.set_attributes {
+            written.push(("set_attributes", &set.attributes));
+        }
+        let violations = self.attribute_violations(AttributeRules::check_actor, &written);
+        if !violations.is_empty() {
+            let _ = tx.send(DsResponse::Error(validation::violations_status(violations)));
+            return;
+        }
+
         let name = req.name.to_ascii_lowercase();
         let typestr = req.typestr.to_ascii_lowercase();
 
-        let actors = self.actors.read().await;
+        let actors = self.actors.shard(&typestr).read().await;
 
-        if !actors.contains_key(&typestr) {
+        if !actors.contains_key(typestr.as_str()) {
             // TODO! -- do something with error
             let _ = tx.send(DsResponse::Error(Status::not_found(
                 "Could not find actor by type",
@@ -468,7 +1408,7 @@ impl Datastore {
             return;
         }
 
-        let typed_actors = actors.get(&typestr).unwrap();
+        let typed_actors = actors.get(typestr.as_str()).unwrap();
         if !(typed_actors.contains_key(&name)) {
             // TODO! -- do something with error
             let _ = tx.send(DsResponse::Error(Status::not_found(
@@ -484,55 +1424,71 @@ impl Datastore {
         drop(actors);
 
         // update attributes
+        let attributes = Arc::make_mut(&mut updated_actor.attributes);
+        if let Some(set) = req.set_attributes {
+            *attributes = replaced_attributes(set);
+        }
         for attrib in req.add_attributes {
             let key = attrib.0;
             let values = attrib.1.values;
 
-            let attrib_entry = updated_actor.attributes.entry(key).or_default();
-            attrib_entry.extend(values);
+            let attrib_entry = attributes.entry(intern(&key)).or_default();
+            attrib_entry.extend(values.iter().map(|value| intern(value)));
         }
         for attrib in req.remove_attributes {
             let key = attrib.0;
             let values = attrib.1.values;
 
-            if let Some(current_values) = updated_actor.attributes.get_mut(&key) {
+            if let Some(current_values) = attributes.get_mut(key.as_str()) {
                 for value in values {
-                    current_values.remove(&value);
+                    current_values.remove(value.as_str());
                 }
 
                 if current_values.is_empty() {
-                    updated_actor.attributes.remove(&key);
+                    attributes.remove(key.as_str());
                 }
             }
         }
 
-        match self.storage.save_actor(&updated_actor).await {
-            Ok(_) => {
-                self.update(BackendUpdate::PutActor(updated_actor.clone()))
-                    .await;
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
+        updated_actor.timestamps.touch(now());
+
+        if !dry_run {
+            match self.storage.save_actor(&updated_actor).await {
+                Ok(_) => {
+                    let update = BackendUpdate::PutActor(updated_actor.clone());
+                    self.notify_changes(std::slice::from_ref(&update)).await;
+                    self.update(update).await;
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
             }
         }
 
-        let expanded_actor = self.expand_groups_and_roles(updated_actor.clone()).await;
+        let expanded_actor = self
+            .expand_groups_and_roles(updated_actor.clone(), &EvalBudget::unlimited())
+            .await;
 
         // TODO! -- do something with error
-        let _ = tx.send(DsResponse::SingleActor(expanded_actor.clone().into()));
+        let _ = tx.send(DsResponse::SingleActor(
+            expanded_actor.clone().into(),
+            Vec::new(),
+        ));
     }
 
     /// Remove an existing actor
     async fn remove_actor(&self, req: RemoveActorRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
         let name = req.name.to_ascii_lowercase();
         let typestr = req.typestr.to_ascii_lowercase();
 
-        let actors = self.actors.read().await;
+        let actors = self.actors.shard(&typestr).read().await;
 
         // make sure the target type exists
-        if !actors.contains_key(&typestr) {
+        if !actors.contains_key(typestr.as_str()) {
             // TODO! -- do something with error
             let _ = tx.send(DsResponse::Error(Status::not_found(
                 "Could not find actor by type",
@@ -541,7 +1497,7 @@ impl Datastore {
         }
 
         // make sure the target exists
-        let typed_actors = actors.get(&typestr).unwrap();
+        let typed_actors = actors.get(typestr.as_str()).unwrap();
         if !(typed_actors.contains_key(&name)) {
             // TODO! -- do something with error
             let _ = tx.send(DsResponse::Error(Status::not_found(
@@ -556,41 +1512,87 @@ impl Datastore {
         // drop the lock
         drop(actors);
 
-        // try to persist the new target to the backend and if that succeeds, update it in memory
-        match self
-            .storage
-            .remove_actor(&existing_actor.typestr, &existing_actor.name)
-            .await
-        {
-            Ok(_) => {
-                self.update(BackendUpdate::DeleteActor(
-                    existing_actor.typestr.clone(),
-                    existing_actor.name.clone(),
-                ))
-                .await
+        // find the groups this actor is a member of, and what they would be without it
+        let member = RegisteredGroupMember {
+            name: name.clone(),
+            typestr: intern(&typestr),
+        };
+        let mut references = Vec::new();
+        let mut txn = Vec::new();
+        for group in self.groups.read().await.values() {
+            if !group.members.contains(&member) {
+                continue;
             }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
+
+            references.push(format!("group/{}", group.name));
+            let mut group = group.clone();
+            group.members.remove(&member);
+            txn.push(BackendUpdate::PutGroup(group));
+        }
+        references.sort_unstable();
+
+        match req.references() {
+            References::Leave => txn.clear(),
+            References::Refuse if !references.is_empty() => {
+                let _ = tx.send(DsResponse::Error(Status::failed_precondition(format!(
+                    "Actor is still referenced by {}",
+                    references.join(", ")
+                ))));
                 return;
             }
+            References::Refuse | References::Cascade => {
+                for update in txn.iter() {
+                    if let BackendUpdate::PutGroup(group) = update {
+                        if let Err(err) = check_group_editable(group) {
+                            let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+
+        txn.push(BackendUpdate::DeleteActor(
+            existing_actor.typestr.to_string(),
+            existing_actor.name.clone(),
+        ));
+
+        // persist and run updates locally
+        if !dry_run {
+            match self.persist(&mut txn, now()).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
+            }
         }
 
         // TODO! -- do something with error
-        let _ = tx.send(DsResponse::SingleActor(existing_actor.clone().into()));
+        let _ = tx.send(DsResponse::SingleActor(
+            existing_actor.clone().into(),
+            references,
+        ));
     }
 
     /// Get all actors, optionally filtered by type
     async fn get_actors(&self, req: GetActorsRequest, tx: Sender<DsResponse>) {
-        let type_filter = req.typestr.map(|t| t.to_ascii_lowercase());
-        let name_filter = req.name.map(|t| t.to_ascii_lowercase());
-        let mut found_actors: Vec<Actor> = Vec::new();
+        let type_filter = req.typestr.as_ref().map(|t| t.to_ascii_lowercase());
+        let name_filter = req.name.as_ref().map(|t| t.to_ascii_lowercase());
+        let mut found: Vec<(Actor, _)> = Vec::new();
 
         let actors = self.actors.read().await;
+        let usage = self.usage.read().await;
 
         for (typestr, actors_of_type) in actors.iter() {
             if let Some(ref filter_type) = type_filter {
-                if typestr.as_str() != filter_type {
+                if **typestr != *filter_type {
                     continue;
                 }
             }
@@ -600,16 +1602,160 @@ impl Datastore {
                         continue;
                     }
                 }
-                let expanded_actor = self.expand_groups_and_roles(actor.clone()).await;
-                found_actors.push(expanded_actor.into());
+                let expanded_actor = self
+                    .expand_groups_and_roles(actor.clone(), &EvalBudget::unlimited())
+                    .await;
+                let key = Usage::key("actor", typestr, actor_name);
+                found.push((
+                    Actor::from(expanded_actor),
+                    usage.get(&key).copied().unwrap_or_default().into(),
+                ));
+            }
+        }
+
+        sort_list(&mut found, req.sort_by(), req.direction(), |(actor, _)| {
+            (
+                &actor.typestr,
+                &actor.name,
+                actor.created_at,
+                actor.updated_at,
+            )
+        });
+        let (found_actors, hits) = found.into_iter().unzip();
+        let _ = tx.send(DsResponse::MultipleActors(found_actors, hits));
+    }
+
+    /// Get the groups an actor belongs to and the roles they convey
+    ///
+    /// The actor is extended just as it is for a check, so this is exactly what policies see.
+    async fn get_actor_memberships(&self, req: GetActorMembershipsRequest, tx: Sender<DsResponse>) {
+        let actor = RegisteredActor::from_check(&Actor {
+            name: req.name,
+            typestr: req.typestr,
+            attributes: HashMap::new(),
+            ..Default::default()
+        });
+        let actor = self.extend_actor(actor, &EvalBudget::unlimited()).await;
+
+        let sorted = |key: &str| {
+            let mut vals: Vec<String> = actor
+                .attributes
+                .get(key)
+                .map(|vals| vals.iter().map(|val| val.to_string()).collect())
+                .unwrap_or_default();
+            vals.sort_unstable();
+            vals
+        };
+
+        let _ = tx.send(DsResponse::ActorMemberships(ActorMembershipsResponse {
+            groups: sorted("member-of"),
+            roles: sorted("has-role"),
+        }));
+    }
+
+    /// Add a delegation of access from one actor to another
+    async fn add_delegation(&self, req: AddDelegationRequest, tx: Sender<DsResponse>) {
+        let mut delegation: RegisteredDelegation = match req.delegation {
+            Some(delegation) => delegation.into(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                    "No delegation in request",
+                )));
+                return;
+            }
+        };
+        delegation.created_at = now();
+
+        if let Err(err) = delegation.validate(delegation.created_at) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
+        }
+
+        if self.delegations.read().await.contains_key(&delegation.name) {
+            let _ = tx.send(DsResponse::Error(Status::already_exists(
+                "Delegation already exists",
+            )));
+            return;
+        }
+
+        let txn = vec![BackendUpdate::PutDelegation(delegation.clone())];
+
+        // persist and run updates locally
+        if !req.dry_run {
+            match self.storage.persist_changes(&txn).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
+            }
+        }
+
+        let _ = tx.send(DsResponse::SingleDelegation(delegation.into()));
+    }
+
+    /// Remove a delegation
+    async fn remove_delegation(&self, req: RemoveDelegationRequest, tx: Sender<DsResponse>) {
+        let name = req.name.to_ascii_lowercase();
+
+        let existing = match self.delegations.read().await.get(&name) {
+            Some(delegation) => delegation.clone(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::not_found(
+                    "Delegation does not exist",
+                )));
+                return;
+            }
+        };
+
+        let txn = vec![BackendUpdate::DeleteDelegation(name)];
+
+        // persist and run updates locally
+        if !req.dry_run {
+            match self.storage.persist_changes(&txn).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
             }
         }
 
-        let _ = tx.send(DsResponse::MultipleActors(found_actors));
+        let _ = tx.send(DsResponse::SingleDelegation(existing.into()));
+    }
+
+    /// Get delegations, or just those from or to an actor, sorted by name
+    async fn get_delegations(&self, req: GetDelegationsRequest, tx: Sender<DsResponse>) {
+        let mut delegations: Vec<Delegation> = self
+            .delegations
+            .read()
+            .await
+            .values()
+            .filter(|delegation| {
+                delegation.involves(req.actor_type.as_deref(), req.actor_name.as_deref())
+            })
+            .cloned()
+            .map(Delegation::from)
+            .collect();
+        delegations.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let _ = tx.send(DsResponse::MultipleDelegations(delegations));
     }
 
     /// Add a role
     async fn add_role(&self, req: AddRoleRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
         let role = req.name.to_ascii_lowercase();
 
         let mut new_role = RegisteredRole::new(&role, req.desc);
@@ -640,6 +1786,10 @@ impl Datastore {
                 return;
             }
             let mut modified_group = groups.get(group_name).unwrap().clone();
+            if let Err(err) = check_group_editable(&modified_group) {
+                let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+                return;
+            }
             modified_group.roles.insert(role.clone());
             new_role.groups.insert(group_name.to_owned());
 
@@ -647,29 +1797,38 @@ impl Datastore {
             txn.push(BackendUpdate::PutGroup(modified_group));
         }
 
+        let now = now();
+        new_role.timestamps.touch(now);
         txn.push(BackendUpdate::PutRole(new_role.clone()));
 
         // drop the lock
         drop(groups);
 
+        let affected = updated_groups(&txn);
+
         // try to persist the new role and updated groups to the backend and if that succeeds, update it in memory
-        match self.storage.persist_changes(&txn).await {
-            Ok(_) => {
-                for update in txn {
-                    self.update(update).await;
+        if !dry_run {
+            match self.persist(&mut txn, now).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
                 }
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
             }
         }
 
-        let _ = tx.send(DsResponse::SingleRole(new_role.into()));
+        let _ = tx.send(DsResponse::SingleRole(new_role.into(), affected));
     }
 
     async fn modify_role(&self, req: ModifyRoleRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
         let role = req.name.to_ascii_lowercase();
 
         // verify the role exists
@@ -684,6 +1843,10 @@ impl Datastore {
             }
         };
 
+        if let Some(desc) = req.desc {
+            existing_role.desc = Some(desc);
+        }
+
         let mut txn = Vec::new();
 
         // verify the groups we want to add exist and create updated versions of them
@@ -697,6 +1860,10 @@ impl Datastore {
                 }
                 Some(group) => group.clone(),
             };
+            if let Err(err) = check_group_editable(&updated_group) {
+                let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+                return;
+            }
             updated_group.roles.insert(role.clone());
             txn.push(BackendUpdate::PutGroup(updated_group));
 
@@ -714,33 +1881,46 @@ impl Datastore {
                 }
                 Some(group) => group.clone(),
             };
+            if let Err(err) = check_group_editable(&updated_group) {
+                let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+                return;
+            }
             updated_group.roles.remove(&role);
             txn.push(BackendUpdate::PutGroup(updated_group));
 
             existing_role.groups.remove(remove_group);
         }
 
+        let now = now();
+        existing_role.timestamps.touch(now);
         txn.push(BackendUpdate::PutRole(existing_role.clone()));
 
+        let affected = updated_groups(&txn);
+
         // try to persist the new role and updated groups to the backend and if that succeeds, update it in memory
-        match self.storage.persist_changes(&txn).await {
-            Ok(_) => {
-                for update in txn {
-                    self.update(update).await;
+        if !dry_run {
+            match self.persist(&mut txn, now).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
                 }
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
             }
         }
 
-        let _ = tx.send(DsResponse::SingleRole(existing_role.into()));
+        let _ = tx.send(DsResponse::SingleRole(existing_role.into(), affected));
     }
 
     /// Remove a role
     async fn remove_role(&self, req: RemoveRoleRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
         let role = req.name.to_ascii_lowercase();
 
         if !self.roles.read().await.contains_key(&role) {
@@ -765,44 +1945,55 @@ impl Datastore {
 
         txn.push(BackendUpdate::DeleteRole(role));
 
+        let affected = updated_groups(&txn);
+
         // persist and run updates locally
-        match self.storage.persist_changes(&txn).await {
-            Ok(_) => {
-                for update in txn {
-                    self.update(update).await;
+        if !dry_run {
+            match self.persist(&mut txn, now()).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(DsResponse::Error(self.partial_failure(txn, err).await));
+                    return;
                 }
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
             }
         }
 
-        let _ = tx.send(DsResponse::SingleRole(existing_role.into()));
+        let _ = tx.send(DsResponse::SingleRole(existing_role.into(), affected));
     }
 
     /// Get all roles
     async fn get_roles(&self, req: GetRolesRequest, tx: Sender<DsResponse>) {
         let mut roles: Vec<Role> = Vec::new();
 
-        if req.name.is_none() {
+        if let Some(ref name) = req.name {
+            if let Some(role) = self.roles.read().await.get(name) {
+                roles = vec![role.to_owned().into()];
+            }
+        } else {
             roles = self
                 .roles
                 .read()
                 .await
-                .iter()
-                .map(|(_, r)| r.to_owned().into())
+                .values()
+                .map(|r| r.to_owned().into())
                 .collect();
-        } else if let Some(role) = self.roles.read().await.get(&req.name.unwrap()) {
-            roles = vec![role.to_owned().into()];
         }
+        sort_list(&mut roles, req.sort_by(), req.direction(), |role| {
+            ("", &role.name, role.created_at, role.updated_at)
+        });
         let _ = tx.send(DsResponse::MultipleRoles(roles));
     }
 
     /// Add group. We cross reference role membership in the registered roles but not in actors
     /// because it is perfectly legal to have members of groups that will be expressed externally
     async fn add_group(&self, req: AddGroupRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
         let name = req.name.to_ascii_lowercase();
 
         // if group already exists, return an error
@@ -819,6 +2010,11 @@ impl Datastore {
         let members: HashSet<RegisteredGroupMember> =
             req.members.iter().map(|m| m.clone().into()).collect();
 
+        if let Err(err) = self.check_group_size(members.len()) {
+            let _ = tx.send(DsResponse::Error(Status::resource_exhausted(err)));
+            return;
+        }
+
         let mut roles = HashSet::new();
         let mut txn = Vec::new();
 
@@ -845,28 +2041,53 @@ impl Datastore {
             txn.push(BackendUpdate::PutRole(cloned_role));
         }
 
-        let new_group = RegisteredGroup::new(&name, req.desc, members, roles);
+        let now = now();
+        let mut new_group = RegisteredGroup::new(&name, req.desc, members, roles);
+        new_group.timestamps.touch(now);
         txn.push(BackendUpdate::PutGroup(new_group.clone()));
 
+        let affected = updated_roles(&txn);
+
         // persist and run updates locally
-        match self.storage.persist_changes(&txn).await {
-            Ok(_) => {
-                for update in txn {
-                    self.update(update).await;
+        if !dry_run {
+            match self.persist(&mut txn, now).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
                 }
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
             }
         }
 
-        let _ = tx.send(DsResponse::SingleGroup(new_group.into()));
+        let _ = tx.send(DsResponse::SingleGroup(new_group.into(), affected));
     }
 
     /// Modify a group
     async fn modify_group(&self, req: ModifyGroupRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
+        if let Err(err) = check_replace(&[
+            (
+                "members",
+                req.set_members.is_some(),
+                !req.add_members.is_empty() || !req.remove_members.is_empty(),
+            ),
+            (
+                "roles",
+                req.set_roles.is_some(),
+                !req.add_roles.is_empty() || !req.remove_roles.is_empty(),
+            ),
+        ]) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
+        }
+
         let name = req.name.to_ascii_lowercase();
 
         if !self.groups.read().await.contains_key(&name) {
@@ -878,12 +2099,19 @@ impl Datastore {
         }
 
         let mut updated_group = self.groups.read().await.get(&name).unwrap().clone();
+        if let Err(err) = check_group_editable(&updated_group) {
+            let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+            return;
+        }
 
         if let Some(desc) = req.desc {
             updated_group.desc = Some(desc);
         }
 
-        // add new members
+        // replace the members, or add new ones
+        if let Some(set) = req.set_members {
+            updated_group.members = set.members.into_iter().map(Into::into).collect();
+        }
         for member in req.add_members {
             updated_group.members.insert(member.into());
         }
@@ -893,10 +2121,28 @@ impl Datastore {
             updated_group.members.remove(&member.into());
         }
 
+        if let Err(err) = self.check_group_size(updated_group.members.len()) {
+            let _ = tx.send(DsResponse::Error(Status::resource_exhausted(err)));
+            return;
+        }
+
         let mut txn = Vec::new();
 
+        // replacing the roles adds the missing ones and removes the rest
+        let (add_roles, remove_roles) = match req.set_roles {
+            Some(set) => {
+                let wanted: HashSet<String> =
+                    set.roles.iter().map(|r| r.to_ascii_lowercase()).collect();
+                (
+                    wanted.difference(&updated_group.roles).cloned().collect(),
+                    updated_group.roles.difference(&wanted).cloned().collect(),
+                )
+            }
+            None => (req.add_roles, req.remove_roles),
+        };
+
         // find existing roles that are being added to this group
-        for role_req in req.add_roles {
+        for role_req in add_roles {
             let role_req_name = role_req.to_ascii_lowercase();
             let known_roles = self.roles.read().await;
             let found_role = known_roles.get(&role_req_name);
@@ -919,7 +2165,7 @@ impl Datastore {
         }
 
         // find existing roles that are being removed from this group
-        for role_req in req.remove_roles {
+        for role_req in remove_roles {
             let role_req_name = role_req.to_ascii_lowercase();
             let known_roles = self.roles.read().await;
             let found_role = known_roles.get(&role_req_name);
@@ -941,50 +2187,153 @@ impl Datastore {
             updated_group.roles.remove(&role_req_name);
         }
 
+        let now = now();
+        updated_group.timestamps.touch(now);
         txn.push(BackendUpdate::PutGroup(updated_group.clone()));
 
+        let affected = updated_roles(&txn);
+
         // persist and run updates locally
-        match self.storage.persist_changes(&txn).await {
-            Ok(_) => {
-                for update in txn {
-                    self.update(update).await;
+        if !dry_run {
+            match self.persist(&mut txn, now).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(DsResponse::Error(self.partial_failure(txn, err).await));
+                    return;
                 }
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
             }
         }
 
-        let _ = tx.send(DsResponse::SingleGroup(updated_group.into()));
+        let _ = tx.send(DsResponse::SingleGroup(updated_group.into(), affected));
     }
 
-    /// Remove an existing group
-    async fn remove_group(&self, req: RemoveGroupRequest, tx: Sender<DsResponse>) {
-        let name = req.name.to_ascii_lowercase();
-
-        if !self.groups.read().await.contains_key(&name) {
-            println!("Group does not exists: {}", name);
-
-            // TODO! -- do something with error
-            let _ = tx.send(DsResponse::Error(Status::not_found("Group not found")));
+    /// Add and remove members across several groups
+    ///
+    /// Every group is checked before anything is changed, and the changes are persisted
+    /// together, so either every group is updated or none are.
+    async fn bulk_modify_memberships(
+        &self,
+        req: BulkModifyMembershipsRequest,
+        tx: Sender<DsResponse>,
+    ) {
+        let dry_run = req.dry_run;
+
+        if req.groups.is_empty() {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "At least one group is required",
+            )));
             return;
         }
 
-        let existing_group = self.groups.read().await.get(&name).unwrap().clone();
+        let mut names: Vec<String> = req.groups.iter().map(|g| g.to_ascii_lowercase()).collect();
+        names.sort_unstable();
+        names.dedup();
 
+        let add_members: Vec<RegisteredGroupMember> =
+            req.add_members.into_iter().map(|m| m.into()).collect();
+        let remove_members: Vec<RegisteredGroupMember> =
+            req.remove_members.into_iter().map(|m| m.into()).collect();
+
+        let now = now();
         let mut txn = Vec::new();
+        {
+            let groups = self.groups.read().await;
+
+            for name in &names {
+                let mut updated_group = match groups.get(name) {
+                    Some(group) => group.clone(),
+                    None => {
+                        let _ = tx.send(DsResponse::Error(Status::not_found(format!(
+                            "Group {name} not found"
+                        ))));
+                        return;
+                    }
+                };
+                if let Err(err) = check_group_editable(&updated_group) {
+                    let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+                    return;
+                }
 
-        // find existing roles that have been granted to this group
-        for role_name in existing_group.roles.iter() {
-            let known_roles = self.roles.read().await;
-            let found_role = known_roles.get(role_name);
-            if found_role.is_none() {
-                // unexpected but not the end of the world
-                eprintln!(
-                    "When removing group {}, the role {} didn't actually exist",
-                    name, role_name
+                updated_group.members.extend(add_members.iter().cloned());
+                for member in &remove_members {
+                    updated_group.members.remove(member);
+                }
+
+                if let Err(err) = self.check_group_size(updated_group.members.len()) {
+                    let _ = tx.send(DsResponse::Error(Status::resource_exhausted(format!(
+                        "Group {name}: {err}"
+                    ))));
+                    return;
+                }
+
+                updated_group.timestamps.touch(now);
+                txn.push(BackendUpdate::PutGroup(updated_group));
+            }
+        }
+
+        let updated_groups: Vec<Group> = txn
+            .iter()
+            .filter_map(|update| match update {
+                BackendUpdate::PutGroup(group) => Some(group.clone().into()),
+                _ => None,
+            })
+            .collect();
+
+        // persist and run updates locally
+        if !dry_run {
+            match self.persist(&mut txn, now).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
+            }
+        }
+
+        let _ = tx.send(DsResponse::MultipleGroups(updated_groups));
+    }
+
+    /// Remove an existing group
+    async fn remove_group(&self, req: RemoveGroupRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
+        let name = req.name.to_ascii_lowercase();
+
+        if !self.groups.read().await.contains_key(&name) {
+            println!("Group does not exists: {}", name);
+
+            // TODO! -- do something with error
+            let _ = tx.send(DsResponse::Error(Status::not_found("Group not found")));
+            return;
+        }
+
+        let existing_group = self.groups.read().await.get(&name).unwrap().clone();
+        if let Err(err) = check_group_editable(&existing_group) {
+            let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+            return;
+        }
+
+        let mut txn = Vec::new();
+
+        // find existing roles that have been granted to this group
+        for role_name in existing_group.roles.iter() {
+            let known_roles = self.roles.read().await;
+            let found_role = known_roles.get(role_name);
+            if found_role.is_none() {
+                // unexpected but not the end of the world
+                eprintln!(
+                    "When removing group {}, the role {} didn't actually exist",
+                    name, role_name
                 );
             }
 
@@ -996,9 +2345,237 @@ impl Datastore {
 
         txn.push(BackendUpdate::DeleteGroup(name.clone()));
 
+        let affected = updated_roles(&txn);
+
         // persist and run updates locally
-        match self.storage.persist_changes(&txn).await {
+        if !dry_run {
+            match self.persist(&mut txn, now()).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
+            }
+        }
+
+        let _ = tx.send(DsResponse::SingleGroup(existing_group.into(), affected));
+    }
+
+    /// Copy a group under a new name, or rename it if `rename` is set
+    ///
+    /// The roles granted to the group are granted to the copy too. When renaming, the roles and
+    /// the policies that check for the group in `member-of` are moved over to the new name.
+    async fn copy_group(
+        &self,
+        name: String,
+        new_name: String,
+        rename: bool,
+        dry_run: bool,
+        tx: Sender<DsResponse>,
+    ) {
+        let name = name.to_ascii_lowercase();
+        let new_name = new_name.to_ascii_lowercase();
+
+        if new_name.is_empty() {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "New name cannot be empty",
+            )));
+            return;
+        }
+
+        let groups = self.groups.read().await;
+        let existing_group = match groups.get(&name) {
+            Some(group) => group.clone(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::not_found("Group not found")));
+                return;
+            }
+        };
+        if groups.contains_key(&new_name) {
+            let _ = tx.send(DsResponse::Error(Status::already_exists(
+                "Group already exists",
+            )));
+            return;
+        }
+        drop(groups);
+
+        if rename {
+            if let Err(err) = check_group_editable(&existing_group) {
+                let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+                return;
+            }
+        }
+
+        let mut txn = Vec::new();
+
+        // grant the roles to the new name
+        let known_roles = self.roles.read().await;
+        for role_name in existing_group.roles.iter() {
+            if let Some(role) = known_roles.get(role_name) {
+                let mut cloned_role = role.clone();
+                if rename {
+                    cloned_role.groups.remove(&name);
+                }
+                cloned_role.groups.insert(new_name.clone());
+                txn.push(BackendUpdate::PutRole(cloned_role));
+            }
+        }
+        drop(known_roles);
+
+        // policies keep checking for the group under its new name
+        if rename {
+            for rule in self.policies.read().await.values() {
+                if rule.refers_to_attribute("member-of", &name) {
+                    let renamed = rule.with_attribute_renamed("member-of", &name, &new_name);
+                    txn.push(BackendUpdate::PutPolicyRule(Box::new(renamed)));
+                }
+            }
+        }
+
+        // a copy of a managed group is ours to edit, and is added now; a renamed group isn't
+        let mut new_group = existing_group;
+        new_group.name = new_name;
+        new_group.managed_by = None;
+        if !rename {
+            new_group.timestamps = Timestamps::default();
+        }
+        let now = now();
+        new_group.timestamps.touch(now);
+        txn.push(BackendUpdate::PutGroup(new_group.clone()));
+        if rename {
+            txn.push(BackendUpdate::DeleteGroup(name));
+        }
+
+        let affected = updated_roles(&txn);
+
+        // persist and run updates locally
+        if !dry_run {
+            match self.persist(&mut txn, now).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
+            }
+        }
+
+        let _ = tx.send(DsResponse::SingleGroup(new_group.into(), affected));
+    }
+
+    /// Reconcile the groups managed by an external source
+    ///
+    /// `groups` is the complete set of groups the source manages. Groups are added or updated to
+    /// match it, and groups the source managed before that are no longer present are removed.
+    /// Groups created through the API are never touched, and roles that don't exist are skipped.
+    async fn sync_groups(
+        &self,
+        source: String,
+        groups: Vec<RegisteredGroup>,
+        tx: Sender<DsResponse>,
+    ) {
+        let existing_groups = self.groups.read().await.clone();
+        let mut roles = self.roles.read().await.clone();
+        let mut changed_roles = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut txn = Vec::new();
+
+        for mut group in groups {
+            group.managed_by = Some(source.clone());
+            seen.insert(group.name.clone());
+
+            let existing = existing_groups.get(&group.name);
+            if existing.is_some_and(|e| e.managed_by.as_ref() != Some(&source)) {
+                eprintln!(
+                    "Not syncing group {} from {source}: group is not managed by {source}",
+                    group.name
+                );
+                continue;
+            }
+
+            if let Err(err) = self.check_group_size(group.members.len()) {
+                eprintln!("Not syncing group {} from {source}: {err}", group.name);
+                continue;
+            }
+
+            group.roles.retain(|role| {
+                let known = roles.contains_key(role);
+                if !known {
+                    eprintln!(
+                        "Not granting unknown role {role} to group {} from {source}",
+                        group.name
+                    );
+                }
+                known
+            });
+
+            // revoke roles that are no longer granted, or skip the group if nothing changed
+            if let Some(existing) = existing {
+                if existing.desc == group.desc
+                    && existing.members == group.members
+                    && existing.roles == group.roles
+                {
+                    continue;
+                }
+
+                for role_name in existing.roles.difference(&group.roles) {
+                    if let Some(role) = roles.get_mut(role_name) {
+                        role.groups.remove(&group.name);
+                        changed_roles.insert(role_name.clone());
+                    }
+                }
+                group.timestamps = existing.timestamps;
+            }
+
+            for role_name in &group.roles {
+                if let Some(role) = roles.get_mut(role_name) {
+                    if role.groups.insert(group.name.clone()) {
+                        changed_roles.insert(role_name.clone());
+                    }
+                }
+            }
+
+            txn.push(BackendUpdate::PutGroup(group));
+        }
+
+        // remove groups the source no longer has
+        for (name, existing) in existing_groups.iter() {
+            if existing.managed_by.as_ref() != Some(&source) || seen.contains(name) {
+                continue;
+            }
+
+            for role_name in &existing.roles {
+                if let Some(role) = roles.get_mut(role_name) {
+                    role.groups.remove(name);
+                    changed_roles.insert(role_name.clone());
+                }
+            }
+
+            txn.push(BackendUpdate::DeleteGroup(name.clone()));
+        }
+
+        let changed = txn.len();
+        for role_name in changed_roles {
+            if let Some(role) = roles.remove(&role_name) {
+                txn.push(BackendUpdate::PutRole(role));
+            }
+        }
+
+        // persist and run updates locally
+        match self.persist(&mut txn, now()).await {
             Ok(_) => {
+                self.notify_changes(&txn).await;
                 for update in txn {
                     self.update(update).await;
                 }
@@ -1010,16 +2587,18 @@ impl Datastore {
             }
         }
 
-        let _ = tx.send(DsResponse::SingleGroup(existing_group.into()));
+        let _ = tx.send(DsResponse::GroupsSynced(changed));
     }
 
     /// Get groups based on filter
     async fn get_groups(&self, req: GetGroupsRequest, tx: Sender<DsResponse>) {
+        let (sort_by, direction) = (req.sort_by(), req.direction());
         let name_filter = req.name;
         let member_filter = req.member;
         let role_filter = req.role;
+        let summary = req.summary;
 
-        let mut found_groups = Vec::new();
+        let mut found_groups: Vec<Group> = Vec::new();
 
         for (name, group) in self.groups.read().await.iter() {
             if let Some(ref filter) = name_filter {
@@ -1041,14 +2620,82 @@ impl Datastore {
                 }
             }
 
-            found_groups.push(group.clone().into());
+            found_groups.push(match summary {
+                true => group.summary(),
+                false => group.clone().into(),
+            });
         }
 
+        sort_list(&mut found_groups, sort_by, direction, |group| {
+            ("", &group.name, group.created_at, group.updated_at)
+        });
         let _ = tx.send(DsResponse::MultipleGroups(found_groups));
     }
 
+    /// Get the effective members of a group, a page at a time
+    ///
+    /// Members are sorted by type and then name, and the page token is the last member of the
+    /// previous page, so pages stay consistent while members come and go.
+    async fn get_group_members(&self, req: GetGroupMembersRequest, tx: Sender<DsResponse>) {
+        let name = req.name.to_ascii_lowercase();
+
+        let groups = self.groups.read().await;
+        let members = match groups.get(&name) {
+            Some(group) => self.effective_members(group, &groups),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::not_found("Group not found")));
+                return;
+            }
+        };
+
+        let after = match req.page_token.as_str() {
+            "" => None,
+            token => match decode_page_token(token) {
+                Ok(after) => Some(after),
+                Err(err) => {
+                    let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+                    return;
+                }
+            },
+        };
+
+        let total = members.len() as u32;
+        let page_size = match req.page_size {
+            0 => members.len(),
+            size => size as usize,
+        };
+
+        let mut page: Vec<&RegisteredGroupMember> = members
+            .iter()
+            .filter(|member| match after {
+                Some((ref typestr, ref name)) => {
+                    (&*member.typestr, &member.name) > (typestr.as_str(), name)
+                }
+                None => true,
+            })
+            .take(page_size + 1)
+            .collect();
+
+        // we took one more than a page to know if there is another page
+        let mut next_page_token = String::new();
+        if page.len() > page_size {
+            page.truncate(page_size);
+            if let Some(last) = page.last() {
+                next_page_token = encode_page_token(last);
+            }
+        }
+
+        let _ = tx.send(DsResponse::GroupMembers(GroupMembersResponse {
+            members: page.into_iter().cloned().map(GroupMember::from).collect(),
+            next_page_token,
+            total,
+        }));
+    }
+
     /// Add a policy if new
     async fn add_policy(&self, req: AddPolicyRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
         let rule = match req.rule {
             None => {
                 let _ = tx.send(DsResponse::Error(Status::invalid_argument(
@@ -1072,26 +2719,62 @@ impl Datastore {
             return;
         }
 
-        let new_policy: RegisteredPolicyRule = rule.clone().into();
+        // make sure we have room for another policy
+        if let Some(max_policies) = self.config.quotas.max_policies {
+            if self.policies.read().await.len() >= max_policies {
+                let _ = tx.send(DsResponse::Error(Status::resource_exhausted(format!(
+                    "Policy limit of {max_policies} reached"
+                ))));
+                return;
+            }
+        }
+
+        if let Err(err) = check_decision(&rule)
+            .and_then(|_| check_cidr_blocks(&rule))
+            .and_then(|_| check_rate_checks(&rule))
+        {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
+        }
+
+        // the policy is added now, whatever times the request gave
+        let mut new_policy: RegisteredPolicyRule = rule.clone().into();
+        new_policy.timestamps = Timestamps::default();
+        new_policy.timestamps.touch(now());
+
+        if let Err(err) = self.check_wasm_module(&new_policy) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
+        }
 
         // try to persist the new policy to the backend and if that succeeds, update it in memory
-        match self.storage.save_policy(&new_policy).await {
-            Ok(_) => {
-                self.update(BackendUpdate::PutPolicyRule(new_policy.clone()))
-                    .await;
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
+        if !dry_run {
+            match self.storage.save_policy(&new_policy).await {
+                Ok(_) => {
+                    let update = BackendUpdate::PutPolicyRule(Box::new(new_policy.clone()));
+                    self.notify_changes(std::slice::from_ref(&update)).await;
+                    self.update(update).await;
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
             }
         }
 
-        let _ = tx.send(DsResponse::SinglePolicy(Box::new(new_policy.into())));
+        let mut impact = self.policy_impact(&[&new_policy]).await;
+        impact.unknown_actions = self.unknown_actions(&new_policy).await;
+        let _ = tx.send(DsResponse::SinglePolicy(
+            Box::new(new_policy.into()),
+            Some(impact),
+        ));
     }
 
     /// Update an existing policy
     async fn modify_policy(&self, req: ModifyPolicyRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
         let rule = match req.rule {
             None => {
                 let _ = tx.send(DsResponse::Error(Status::invalid_argument(
@@ -1105,7 +2788,7 @@ impl Datastore {
         let name = rule.name.to_ascii_lowercase();
 
         // if policy rule does not exist, return an error
-        if !self.policies.read().await.contains_key(&name) {
+        let Some(existing_policy) = self.policies.read().await.get(&name).cloned() else {
             println!("Policy rule not found: {}", name);
 
             // TODO! -- do something with error
@@ -1113,28 +2796,55 @@ impl Datastore {
                 "Policy rule does not exist",
             )));
             return;
+        };
+
+        if let Err(err) = check_decision(&rule)
+            .and_then(|_| check_cidr_blocks(&rule))
+            .and_then(|_| check_rate_checks(&rule))
+        {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
         }
 
-        let updated_policy: RegisteredPolicyRule = rule.clone().into();
+        let mut updated_policy: RegisteredPolicyRule = rule.clone().into();
+        updated_policy.timestamps = existing_policy.timestamps;
+        updated_policy.timestamps.touch(now());
+
+        if let Err(err) = self.check_wasm_module(&updated_policy) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
+        }
 
         // try to persist the new policy to the backend and if that succeeds, update it in memory
-        match self.storage.save_policy(&updated_policy).await {
-            Ok(_) => {
-                self.update(BackendUpdate::PutPolicyRule(updated_policy.clone()))
-                    .await;
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
+        if !dry_run {
+            match self.storage.save_policy(&updated_policy).await {
+                Ok(_) => {
+                    let update = BackendUpdate::PutPolicyRule(Box::new(updated_policy.clone()));
+                    self.notify_changes(std::slice::from_ref(&update)).await;
+                    self.update(update).await;
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
             }
         }
 
-        let _ = tx.send(DsResponse::SinglePolicy(Box::new(updated_policy.into())));
+        let mut impact = self
+            .policy_impact(&[&existing_policy, &updated_policy])
+            .await;
+        impact.unknown_actions = self.unknown_actions(&updated_policy).await;
+        let _ = tx.send(DsResponse::SinglePolicy(
+            Box::new(updated_policy.into()),
+            Some(impact),
+        ));
     }
 
     /// Remove an existing policy
     async fn remove_policy(&self, req: RemovePolicyRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
         let name = req.name.to_ascii_lowercase();
 
         // if policy rule does not exist, return an error
@@ -1151,303 +2861,8798 @@ impl Datastore {
         let existing_policy = self.policies.read().await.get(&name).unwrap().to_owned();
 
         // try to remove the policy from backend before updating memory
-        match self.storage.remove_policy(&name).await {
-            Ok(_) => {
-                self.update(BackendUpdate::DeletePolicyRule(name.clone()))
-                    .await;
-            }
-            Err(err) => {
-                // TODO! -- do something with error
-                let _ = tx.send(DsResponse::Error(Status::internal(err)));
-                return;
+        if !dry_run {
+            match self.storage.remove_policy(&name).await {
+                Ok(_) => {
+                    let update = BackendUpdate::DeletePolicyRule(name.clone());
+                    self.notify_changes(std::slice::from_ref(&update)).await;
+                    self.update(update).await;
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
             }
         }
 
-        let _ = tx.send(DsResponse::SinglePolicy(Box::new(existing_policy.into())));
+        let impact = self.policy_impact(&[&existing_policy]).await;
+        let _ = tx.send(DsResponse::SinglePolicy(
+            Box::new(existing_policy.into()),
+            Some(impact),
+        ));
     }
 
-    /// Get policies based on filters
-    async fn get_policies(&self, req: GetPoliciesRequest, tx: Sender<DsResponse>) {
-        let mut policies: Vec<PolicyRule> = Vec::new();
+    /// Copy a policy under a new name, or rename it if `rename` is set
+    async fn copy_policy(
+        &self,
+        name: String,
+        new_name: String,
+        rename: bool,
+        dry_run: bool,
+        tx: Sender<DsResponse>,
+    ) {
+        let name = name.to_ascii_lowercase();
+        let new_name = new_name.to_ascii_lowercase();
+
+        if new_name.is_empty() {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "New name cannot be empty",
+            )));
+            return;
+        }
 
-        let req_name = req.name.map(|n| n.to_ascii_lowercase());
-        for (name, policy) in self.policies.read().await.iter() {
-            // see if name matches if a name filter was given
-            if let Some(ref req_name) = req_name {
-                if name != req_name {
-                    continue;
-                }
+        let policies = self.policies.read().await;
+        let mut new_policy = match policies.get(&name) {
+            Some(policy) => policy.clone(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::not_found(
+                    "Policy rule does not exist",
+                )));
+                return;
             }
-
-            policies.push(policy.to_owned().into());
+        };
+        if policies.contains_key(&new_name) {
+            let _ = tx.send(DsResponse::Error(Status::already_exists(
+                "Policy rule already exists",
+            )));
+            return;
         }
 
-        let _ = tx.send(DsResponse::MultiplePolicies(policies));
-    }
+        // a copy needs room for another policy
+        if let Some(max_policies) = self.config.quotas.max_policies {
+            if !rename && policies.len() >= max_policies {
+                let _ = tx.send(DsResponse::Error(Status::resource_exhausted(format!(
+                    "Policy limit of {max_policies} reached"
+                ))));
+                return;
+            }
+        }
+        drop(policies);
 
-    /// Update data directly
-    ///
-    /// We get these updates from the backend when working in a distributed model so we
-    /// want to update the data in the memory store when getting these updates
-    /// TODO -- we should only update the memory when we get this call from the backend
-    ///         rather than doing it directly on an update request. And we should do it
-    ///         in transactions when changing multiple entities at once, like when adding
-    ///         roles to groups
-    async fn update(&self, req: BackendUpdate) {
-        match req {
-            BackendUpdate::PutActor(actor) => {
-                println!("backend => add actor {}/{}", actor.typestr, actor.name);
-                let mut actors = self.actors.write().await;
-                let typed_actors = actors
-                    .entry(actor.typestr.clone())
-                    .or_insert_with(HashMap::new);
-                typed_actors.insert(actor.name.clone(), actor);
-            }
-            BackendUpdate::PutGroup(group) => {
-                println!("backend => add group {}", group.name);
-                let mut groups = self.groups.write().await;
-                groups.insert(group.name.clone(), group);
-            }
-            BackendUpdate::PutPolicyRule(policyrule) => {
-                println!("backend => add policy {}", policyrule.name);
-                let mut policies = self.policies.write().await;
-                policies.insert(policyrule.name.clone(), policyrule);
-            }
-            BackendUpdate::PutRole(role) => {
-                println!("backend => add role {}", role.name);
-                let mut roles = self.roles.write().await;
-                roles.insert(role.name.clone(), role);
-            }
-            BackendUpdate::PutTarget(target) => {
-                println!("backend => add target {}", target.name);
-                let mut targets = self.targets.write().await;
-                let type_targets = targets
-                    .entry(target.typestr.clone())
-                    .or_insert_with(HashMap::new);
-                type_targets.insert(target.name.clone(), target);
-            }
-            BackendUpdate::DeleteActor(typestr, name) => {
-                println!("backend => delete {}/{}", typestr, name);
-                let mut actors = self.actors.write().await;
-                if let Some(typed_actors) = actors.get_mut(&typestr) {
-                    typed_actors.remove(&name);
+        // a copy is added now; a renamed policy isn't
+        new_policy.name = new_name.clone();
+        if !rename {
+            new_policy.timestamps = Timestamps::default();
+        }
+        let now = now();
+        new_policy.timestamps.touch(now);
+        let mut txn = vec![BackendUpdate::PutPolicyRule(Box::new(new_policy.clone()))];
+        if rename {
+            // a renamed policy stays in its set
+            for set in self.policy_sets.read().await.values() {
+                if set.policies.contains(&name) {
+                    let mut renamed = set.clone();
+                    for member in renamed.policies.iter_mut().filter(|m| **m == name) {
+                        *member = new_name.clone();
+                    }
+                    txn.push(BackendUpdate::PutPolicySet(renamed));
                 }
             }
-            BackendUpdate::DeleteGroup(name) => {
-                println!("backend => delete group {}", name);
-                let mut groups = self.groups.write().await;
-                groups.remove(&name);
-            }
-            BackendUpdate::DeletePolicyRule(name) => {
-                println!("backend => delete policy rule {}", name);
-                let mut policies = self.policies.write().await;
-                policies.remove(&name);
-            }
-            BackendUpdate::DeleteRole(name) => {
-                println!("backend => delete role {}", name);
-                let mut roles = self.roles.write().await;
-                roles.remove(&name);
-            }
-            BackendUpdate::DeleteTarget(typestr, name) => {
-                println!("backend => delete target {}/{}", typestr, name);
-                let mut targets = self.targets.write().await;
-                if let Some(typed_targets) = targets.get_mut(&typestr) {
-                    typed_targets.remove(&name);
+            txn.push(BackendUpdate::DeletePolicyRule(name));
+        }
+
+        // persist and run updates locally
+        if !dry_run {
+            match self.persist(&mut txn, now).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
                 }
             }
         }
+
+        let _ = tx.send(DsResponse::SinglePolicy(Box::new(new_policy.into()), None));
     }
 
-    /// Perform a check
+    /// What a change to policies can affect: the registered actors and targets any of the rules
+    /// before or after it can apply to
     ///
-    /// We will receive an actor (type and name) and a list of attributes that the policy
-    /// enforcement point (PEP) wants to share about the actor. PEP may also share environment
-    /// attributes in the form of key/val pairs (vals are a list). Finally, the PEP will specify
-    /// the target (name and type) and action to be checked against the policies. DS will evaluate
-    /// against known policy rules and decide on a ALLOW/DENY decision.
+    /// Only the checks of the actor and target themselves are judged, not the environment, the
+    /// action, or other conditions, so the counts are the most the change can affect.
+    async fn policy_impact(&self, rules: &[&RegisteredPolicyRule]) -> PolicyImpact {
+        let mut target_types = BTreeSet::new();
+        let mut targets = 0;
+        for typed in self.targets.read().await.values() {
+            for target in typed.values() {
+                if rules.iter().any(|rule| rule.can_match_target(target)) {
+                    target_types.insert(target.typestr.to_string());
+                    targets += 1;
+                }
+            }
+        }
+
+        // actors are judged with their groups and roles, as they are in checks
+        let registered: Vec<RegisteredActor> = self
+            .actors
+            .read()
+            .await
+            .values()
+            .flat_map(|typed| typed.values().cloned())
+            .collect();
+        let groups = self.groups.read().await;
+        let actors = registered
+            .into_iter()
+            .map(|actor| add_groups_and_roles(actor, &groups, &EvalBudget::unlimited()))
+            .filter(|actor| rules.iter().any(|rule| rule.can_match_actor(actor)))
+            .count();
+
+        PolicyImpact {
+            target_types: target_types.into_iter().collect(),
+            targets,
+            actors: actors as u64,
+            ..Default::default()
+        }
+    }
+
+    /// Actions a policy names that no registered target has as an action or action group
     ///
-    /// The actor may match a registered actor, in which case, we'll add some more attributes if
-    /// we have them. The actor may also belong to a group which has been granted some roles.
-    /// In that case, we will add "member-of" attributes for each group, and "has-role" attributes
-    /// for each role. Lastly, we will determine a bucket (between 0-99) using the murmur3 algo
-    async fn check(&self, req: CheckRequest, tx: Sender<DsResponse>) {
-        let actor = self
-            .extend_actor(RegisteredActor::from(req.actor.unwrap()))
-            .await;
+    /// A policy that checks for `raed` is accepted, but never matches a real check. With no
+    /// targets registered there is nothing to judge by, so nothing is reported.
+    async fn unknown_actions(&self, rule: &RegisteredPolicyRule) -> Vec<String> {
+        let Some(target_check) = &rule.target_check else {
+            return Vec::new();
+        };
+        let mut named: BTreeSet<String> = target_check.action_except.iter().cloned().collect();
+        if let Some(StringCheck::OneOf(actions) | StringCheck::NotOneOf(actions)) =
+            &target_check.action
+        {
+            named.extend(actions.iter().map(|action| action.to_ascii_lowercase()));
+        }
+        named.remove("*");
+
+        let mut registered = false;
+        for typed in self.targets.read().await.values() {
+            for target in typed.values() {
+                registered = true;
+                named.retain(|action| {
+                    !target.actions.contains(action) && !target.action_groups.contains_key(action)
+                });
+            }
+        }
 
-        let mut env_attributes = HashMap::new();
-        for (key, vals) in req.env_attributes {
-            env_attributes.insert(key, HashSet::from_iter(vals.values));
+        match registered {
+            true => named.into_iter().collect(),
+            false => Vec::new(),
         }
+    }
 
-        // get any known attributes about the target
-        let target_attributes = self
-            .get_target_attributes(&req.target_name, &req.target_type)
-            .await;
+    /// Get policies based on filters
+    async fn get_policies(&self, req: GetPoliciesRequest, tx: Sender<DsResponse>) {
+        let mut policies: Vec<PolicyRule> = Vec::new();
 
-        // TODO -- refactor the policy store to make applicable polices quicker to find
-        // Examine every policy -- if the actor check, environment check, and target check's pass
-        // then we can make a determination. If we get an explicit DENY from any rule, we exit
-        // immediately.
-        let mut decision = Decide::Deny;
-        for (_, policy) in self.policies.read().await.iter() {
-            if let Some(ref actor_check) = policy.actor_check {
-                if !actor_check.check(&actor) {
-                    // this actor check does not apply to this request
+        let req_name = req.name.as_ref().map(|n| n.to_ascii_lowercase());
+        let tags: Vec<String> = req.tags.iter().map(|t| t.to_ascii_lowercase()).collect();
+        for (name, policy) in self.policies.read().await.iter() {
+            // see if name matches if a name filter was given
+            if let Some(ref req_name) = req_name {
+                if name != req_name {
                     continue;
                 }
             }
-
-            // perform environment check
-            if !policy
-                .env_attributes
-                .iter()
-                .all(|ea| ea.check(&env_attributes))
-            {
-                // these environment checks do not match
+            if !tags.iter().all(|tag| policy.tags.contains(tag)) {
                 continue;
             }
-
-            if let Some(ref target_check) = policy.target_check {
-                if !target_check.check(
-                    &req.target_name,
-                    &req.target_type,
-                    &target_attributes,
-                    &req.target_action,
-                    &actor.attributes,
-                    &env_attributes,
-                ) {
-                    // this target does not match
+            let decision = crate::proto::policies::Decide::from(policy.decision.clone());
+            if req.decision.is_some_and(|req| req != decision as i32) {
+                continue;
+            }
+            if let Some(ref target_type) = req.target_type {
+                if !policy.can_target_type(target_type) {
+                    continue;
+                }
+            }
+            if let Some(ref search) = req.search {
+                if !policy.mentions(search) {
                     continue;
                 }
             }
 
-            // all conditions must match; take decision
-            decision = policy.decision.clone();
-            if let Decide::Deny = decision {
-                break;
+            policies.push(policy.to_owned().into());
+        }
+
+        sort_list(&mut policies, req.sort_by(), req.direction(), |rule| {
+            ("", &rule.name, rule.created_at, rule.updated_at)
+        });
+        let _ = tx.send(DsResponse::MultiplePolicies(policies));
+    }
+
+    /// Add a new policy set
+    async fn add_policy_set(&self, req: AddPolicySetRequest, tx: Sender<DsResponse>) {
+        let mut set: RegisteredPolicySet = match req.set {
+            Some(set) => set.into(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                    "No policy set in request",
+                )));
+                return;
             }
+        };
+
+        if self.policy_sets.read().await.contains_key(&set.name) {
+            let _ = tx.send(DsResponse::Error(Status::already_exists(
+                "Policy set already exists",
+            )));
+            return;
         }
 
-        let _ = tx.send(DsResponse::CheckResult(decision.into()));
+        // the set is added now, whatever times the request gave
+        set.timestamps = Timestamps::default();
+        self.save_policy_set(set, req.dry_run, tx).await;
     }
 
-    /** HELPERS */
-    /// Extend a given actor with additional attributes
-    ///
-    /// Given a RegisteredActor created just from a gRPC call,
-    /// update it with any additional attributes from a
-    /// known actor, as well as any group/roles we might have.
-    async fn extend_actor(&self, mut actor: RegisteredActor) -> RegisteredActor {
-        let actors = self.actors.read().await;
-        let typed_actors = actors.get(&actor.typestr);
+    /// Replace an existing policy set
+    async fn modify_policy_set(&self, req: ModifyPolicySetRequest, tx: Sender<DsResponse>) {
+        let mut set: RegisteredPolicySet = match req.set {
+            Some(set) => set.into(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                    "No policy set in request",
+                )));
+                return;
+            }
+        };
 
-        // extend attributes if we know about this actor
-        if let Some(typed_actors) = typed_actors {
-            if let Some(found_actor) = typed_actors.get(&actor.name).map(|e| e.to_owned()) {
-                actor.attributes.extend(found_actor.attributes.into_iter());
+        match self.policy_sets.read().await.get(&set.name) {
+            Some(existing) => set.timestamps = existing.timestamps,
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::not_found(
+                    "Policy set does not exist",
+                )));
+                return;
             }
         }
 
-        actor = self.expand_groups_and_roles(actor).await;
-
-        actor
+        self.save_policy_set(set, req.dry_run, tx).await;
     }
 
-    async fn expand_groups_and_roles(&self, mut actor: RegisteredActor) -> RegisteredActor {
-        // create a representation of this actor as a RegisteredGroupMember so we can search for it
-        let actor_as_member = RegisteredGroupMember::from(&actor);
+    /// Validate and save a new or changed policy set
+    ///
+    /// Every policy in the set must exist and may not already belong to another set.
+    async fn save_policy_set(
+        &self,
+        mut set: RegisteredPolicySet,
+        dry_run: bool,
+        tx: Sender<DsResponse>,
+    ) {
+        if let Err(status) = self.check_policy_set(&set).await {
+            let _ = tx.send(DsResponse::Error(status));
+            return;
+        }
+
+        let now = now();
+        set.timestamps.touch(now);
+        let mut txn = vec![BackendUpdate::PutPolicySet(set.clone())];
 
-        let groups = self.groups.read().await;
+        // persist and run updates locally
+        if !dry_run {
+            match self.persist(&mut txn, now).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
+            }
+        }
 
-        for group in groups.values() {
-            if group.members.contains(&actor_as_member) {
-                actor
-                    .attributes
-                    .entry("member-of".to_string())
-                    .or_default()
-                    .insert(group.name.clone());
+        let _ = tx.send(DsResponse::SinglePolicySet(set.into()));
+    }
 
-                actor
-                    .attributes
-                    .entry("has-role".to_string())
-                    .or_default()
-                    .extend(group.roles.clone());
+    /// Make sure a policy set is named, and its policies exist and belong to no other set
+    async fn check_policy_set(&self, set: &RegisteredPolicySet) -> Result<(), Status> {
+        if set.name.is_empty() {
+            return Err(Status::invalid_argument("Policy set name cannot be empty"));
+        }
+
+        let policies = self.policies.read().await;
+        if let Some(missing) = set.policies.iter().find(|p| !policies.contains_key(*p)) {
+            return Err(Status::not_found(format!(
+                "Policy rule {missing} does not exist"
+            )));
+        }
+        drop(policies);
+
+        for other in self.policy_sets.read().await.values() {
+            if other.name == set.name {
+                continue;
+            }
+            if let Some(shared) = set.policies.iter().find(|p| other.policies.contains(p)) {
+                return Err(Status::failed_precondition(format!(
+                    "Policy rule {shared} already belongs to policy set {}",
+                    other.name
+                )));
             }
         }
 
-        actor
+        Ok(())
     }
 
-    /// Return attributes for a target if known
-    async fn get_target_attributes(
-        &self,
-        name: &str,
-        typestr: &str,
-    ) -> HashMap<String, HashSet<String>> {
-        let targets = self.targets.read().await;
-        let typed_targets = targets.get(typestr);
+    /// Remove a policy set; its policies are then evaluated on their own again
+    async fn remove_policy_set(&self, req: RemovePolicySetRequest, tx: Sender<DsResponse>) {
+        let name = req.name.to_ascii_lowercase();
 
-        if let Some(typed_targets) = typed_targets {
-            if let Some(found_target) = typed_targets.get(name) {
-                return found_target.attributes.clone();
+        let existing_set = match self.policy_sets.read().await.get(&name) {
+            Some(set) => set.clone(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::not_found(
+                    "Policy set does not exist",
+                )));
+                return;
+            }
+        };
+
+        let txn = vec![BackendUpdate::DeletePolicySet(name)];
+
+        // persist and run updates locally
+        if !req.dry_run {
+            match self.storage.persist_changes(&txn).await {
+                Ok(_) => {
+                    self.notify_changes(&txn).await;
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
             }
         }
 
-        HashMap::new()
+        let _ = tx.send(DsResponse::SinglePolicySet(existing_set.into()));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use tokio::sync::oneshot::channel;
-    use tokio::test;
+    /// Get policy sets, or just the one named
+    async fn get_policy_sets(&self, req: GetPolicySetsRequest, tx: Sender<DsResponse>) {
+        let req_name = req.name.as_ref().map(|n| n.to_ascii_lowercase());
 
-    use crate::proto::common::AttributeValues;
+        let mut sets: Vec<PolicySet> = self
+            .policy_sets
+            .read()
+            .await
+            .values()
+            .filter(|set| req_name.as_ref().is_none_or(|name| *name == set.name))
+            .cloned()
+            .map(PolicySet::from)
+            .collect();
+
+        sort_list(&mut sets, req.sort_by(), req.direction(), |set| {
+            ("", &set.name, set.created_at, set.updated_at)
+        });
+        let _ = tx.send(DsResponse::MultiplePolicySets(sets));
+    }
 
-    use super::*;
+    /// Hold a policy change until it is approved
+    async fn propose(&self, mut proposal: Proposal, tx: Sender<DsResponse>) {
+        if proposal.change.is_none() {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "A proposal needs a change",
+            )));
+            return;
+        }
 
-    fn str(val: &str) -> String {
-        String::from(val)
+        proposal.id = self.next_proposal.fetch_add(1, Ordering::SeqCst) + 1;
+        proposal.proposed_at = now();
+
+        let txn = vec![BackendUpdate::PutProposal(RegisteredProposal::from(
+            &proposal,
+        ))];
+        if let Err(err) = self.storage.persist_changes(&txn).await {
+            let _ = tx.send(DsResponse::Error(Status::internal(err)));
+            return;
+        }
+        for update in txn {
+            self.update(update).await;
+        }
+
+        let _ = tx.send(DsResponse::SingleProposal(Box::new(proposal)));
     }
 
-    #[test]
-    async fn test_targets() {
-        let (req_tx, req_rx) = flume::unbounded();
-        let (tx, _) = channel::<DsResponse>();
-        let ds = Datastore::new(&StorageType::Nil, req_tx, req_rx).await;
+    /// Get the policy changes waiting for approval, oldest first
+    async fn list_proposals(&self, tx: Sender<DsResponse>) {
+        let proposals = self.proposals.read().await.values().cloned().collect();
 
-        let mut map: HashMap<String, AttributeValues> = HashMap::new();
-        map.insert(
-            str("role"),
-            AttributeValues {
-                values: vec![str("main"), str("backup")],
-            },
-        );
-        map.insert(
-            str("env"),
-            AttributeValues {
-                values: vec![str("test")],
-            },
-        );
+        let _ = tx.send(DsResponse::MultipleProposals(proposals));
+    }
 
-        let req = AddTargetRequest {
-            name: str("test"),
-            typestr: str("typetest"),
-            actions: vec![str("action1"), str("action2")],
-            attributes: map,
+    /// Make the change of a proposal and drop it
+    ///
+    /// Nobody can approve their own proposal. If the change can't be made, for instance
+    /// because the policy was removed in the meantime, the proposal is kept.
+    async fn approve_proposal(&self, id: u64, approver: String, tx: Sender<DsResponse>) {
+        // hold the lock while the change is made so it is only made once
+        let mut proposals = self.proposals.write().await;
+
+        let proposal = match proposals.get(&id) {
+            Some(proposal) => proposal.clone(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::not_found(format!(
+                    "Proposal {id} not found"
+                ))));
+                return;
+            }
         };
-        ds.add_target(req, tx).await;
+        if proposal.proposer == approver {
+            let _ = tx.send(DsResponse::Error(Status::permission_denied(
+                "A proposal must be approved by someone other than its proposer",
+            )));
+            return;
+        }
+
+        let (change_tx, change_rx) = channel::<DsResponse>();
+        match proposal.change.clone() {
+            Some(ProposedChange::Add(req)) => self.add_policy(req, change_tx).await,
+            Some(ProposedChange::Modify(req)) => self.modify_policy(req, change_tx).await,
+            Some(ProposedChange::Remove(req)) => self.remove_policy(req, change_tx).await,
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::internal(
+                    "Proposal has no change",
+                )));
+                return;
+            }
+        }
+
+        match change_rx.await {
+            Ok(DsResponse::SinglePolicy(..)) => {
+                // the change is made, so a proposal left in storage would only be listed again
+                let txn = vec![BackendUpdate::DeleteProposal(id.to_string())];
+                if let Err(err) = self.storage.persist_changes(&txn).await {
+                    eprintln!("Could not remove approved proposal {id}: {err}");
+                }
+                proposals.remove(&id);
+                let _ = tx.send(DsResponse::SingleProposal(Box::new(proposal)));
+            }
+            Ok(DsResponse::Error(status)) => {
+                let _ = tx.send(DsResponse::Error(status));
+            }
+            _ => {
+                let _ = tx.send(DsResponse::Error(Status::internal(
+                    "Got unexpected answer making the change",
+                )));
+            }
+        }
+    }
+
+    /// Drop a proposal without making its change
+    async fn reject_proposal(&self, id: u64, tx: Sender<DsResponse>) {
+        let proposal = match self.proposals.read().await.get(&id) {
+            Some(proposal) => proposal.clone(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::not_found(format!(
+                    "Proposal {id} not found"
+                ))));
+                return;
+            }
+        };
+
+        let txn = vec![BackendUpdate::DeleteProposal(id.to_string())];
+        if let Err(err) = self.storage.persist_changes(&txn).await {
+            let _ = tx.send(DsResponse::Error(Status::internal(err)));
+            return;
+        }
+        for update in txn {
+            self.update(update).await;
+        }
+
+        let _ = tx.send(DsResponse::SingleProposal(Box::new(proposal)));
+    }
+
+    /// Add a webhook
+    async fn add_webhook(&self, req: AddWebhookRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
+        let hook = match req.webhook {
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                    "No webhook in request",
+                )));
+                return;
+            }
+            Some(hook) => RegisteredWebhook::from(hook),
+        };
+
+        if self.webhooks.read().await.contains_key(&hook.name) {
+            // TODO! -- do something with error
+            let _ = tx.send(DsResponse::Error(Status::already_exists(
+                "Webhook already exists",
+            )));
+            return;
+        }
+
+        if !hook.url.starts_with("http://") && !hook.url.starts_with("https://") {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "Webhook url must be an http:// or https:// url",
+            )));
+            return;
+        }
+
+        if hook.events.is_empty() {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "Webhook must subscribe to at least one event",
+            )));
+            return;
+        }
+
+        let txn = vec![BackendUpdate::PutWebhook(hook.clone())];
+
+        // persist and run updates locally
+        if !dry_run {
+            match self.storage.persist_changes(&txn).await {
+                Ok(_) => {
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
+            }
+        }
+
+        let _ = tx.send(DsResponse::SingleWebhook(hook.into()));
+    }
+
+    /// Remove a webhook
+    async fn remove_webhook(&self, req: RemoveWebhookRequest, tx: Sender<DsResponse>) {
+        let dry_run = req.dry_run;
+
+        let name = req.name.to_ascii_lowercase();
+
+        let existing_hook = match self.webhooks.read().await.get(&name) {
+            Some(hook) => hook.clone(),
+            None => {
+                // TODO! -- do something with error
+                let _ = tx.send(DsResponse::Error(Status::not_found("Webhook not found")));
+                return;
+            }
+        };
+
+        let txn = vec![BackendUpdate::DeleteWebhook(name)];
+
+        // persist and run updates locally
+        if !dry_run {
+            match self.storage.persist_changes(&txn).await {
+                Ok(_) => {
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                }
+                Err(err) => {
+                    // TODO! -- do something with error
+                    let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                    return;
+                }
+            }
+        }
+
+        let _ = tx.send(DsResponse::SingleWebhook(existing_hook.into()));
+    }
+
+    /// Get webhooks, optionally filtered by name
+    async fn get_webhooks(&self, req: GetWebhooksRequest, tx: Sender<DsResponse>) {
+        let req_name = req.name.map(|n| n.to_ascii_lowercase());
+
+        let hooks: Vec<Webhook> = self
+            .webhooks
+            .read()
+            .await
+            .values()
+            .filter(|hook| req_name.as_ref().is_none_or(|name| &hook.name == name))
+            .map(|hook| hook.clone().into())
+            .collect();
+
+        let _ = tx.send(DsResponse::MultipleWebhooks(hooks));
+    }
+
+    /** API KEYS */
+    /// Store a newly issued API key
+    async fn add_api_key(&self, key: RegisteredApiKey, tx: Sender<DsResponse>) {
+        if key.name.is_empty() {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "API key name cannot be empty",
+            )));
+            return;
+        }
+
+        let txn = vec![BackendUpdate::PutApiKey(key.clone())];
+        match self.storage.persist_changes(&txn).await {
+            Ok(_) => {
+                for update in txn {
+                    self.update(update).await;
+                }
+            }
+            Err(err) => {
+                let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                return;
+            }
+        }
+
+        let _ = tx.send(DsResponse::SingleApiKey(key.into()));
+    }
+
+    /// Revoke an API key
+    async fn revoke_api_key(&self, id: String, tx: Sender<DsResponse>) {
+        let existing_key = match self.api_keys.read().await.get(&id) {
+            Some(key) => key.clone(),
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::not_found("API key not found")));
+                return;
+            }
+        };
+
+        let txn = vec![BackendUpdate::DeleteApiKey(id)];
+        match self.storage.persist_changes(&txn).await {
+            Ok(_) => {
+                for update in txn {
+                    self.update(update).await;
+                }
+            }
+            Err(err) => {
+                let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                return;
+            }
+        }
+
+        let _ = tx.send(DsResponse::SingleApiKey(existing_key.into()));
+    }
+
+    /// Get the issued API keys, oldest first
+    async fn get_api_keys(&self, tx: Sender<DsResponse>) {
+        let mut keys: Vec<RegisteredApiKey> =
+            self.api_keys.read().await.values().cloned().collect();
+        keys.sort_by(|a, b| (a.created, &a.id).cmp(&(b.created, &b.id)));
+
+        let _ = tx.send(DsResponse::MultipleApiKeys(
+            keys.into_iter().map(ApiKey::from).collect(),
+        ));
+    }
+
+    /// Find the key an API key is; a call without a known key is refused, even before any keys
+    /// have been issued
+    async fn verify_api_key(&self, key: Option<String>, tx: Sender<DsResponse>) {
+        let api_keys = self.api_keys.read().await;
+        let verified = key
+            .as_deref()
+            .and_then(apikey::parse)
+            .and_then(|(id, secret)| api_keys.get(id).filter(|key| key.matches(secret)));
+        let _ = match (key, verified) {
+            (_, Some(verified)) => tx.send(DsResponse::VerifiedApiKey(verified.clone())),
+            (None, None) => tx.send(DsResponse::Error(Status::unauthenticated(
+                "An API key is required",
+            ))),
+            (Some(_), None) => tx.send(DsResponse::Error(Status::unauthenticated(
+                "Unknown or revoked API key",
+            ))),
+        };
+    }
+
+    /// Get the status of recent webhook deliveries
+    async fn get_webhook_deliveries(&self, req: GetDeliveriesRequest, tx: Sender<DsResponse>) {
+        let name = req.webhook.map(|n| n.to_ascii_lowercase());
+        let deliveries = self.dispatcher.deliveries(name.as_deref()).await;
+
+        let _ = tx.send(DsResponse::Deliveries(deliveries));
+    }
+
+    /// Update data directly
+    ///
+    /// We get these updates from the backend when working in a distributed model so we
+    /// want to update the data in the memory store when getting these updates
+    /// TODO -- we should only update the memory when we get this call from the backend
+    ///         rather than doing it directly on an update request. And we should do it
+    ///         in transactions when changing multiple entities at once, like when adding
+    ///         roles to groups
+    async fn update(&self, req: BackendUpdate) {
+        let change = watch_change(&req);
+
+        // a new or changed entity hasn't had a chance to be used yet
+        match req {
+            BackendUpdate::PutActor(ref actor) => {
+                let key = Usage::key("actor", &actor.typestr, &actor.name);
+                self.usage.changed(key).await;
+            }
+            BackendUpdate::PutTarget(ref target) => {
+                let key = Usage::key("target", &target.typestr, &target.name);
+                self.usage.changed(key).await;
+            }
+            BackendUpdate::PutRole(ref role) => {
+                self.usage.changed(Usage::key("role", "", &role.name)).await;
+            }
+            BackendUpdate::DeleteActor(ref typestr, ref name) => {
+                self.usage
+                    .removed(&Usage::key("actor", typestr, name))
+                    .await;
+            }
+            BackendUpdate::DeleteTarget(ref typestr, ref name) => {
+                self.usage
+                    .removed(&Usage::key("target", typestr, name))
+                    .await;
+            }
+            BackendUpdate::DeleteRole(ref name) => {
+                self.usage.removed(&Usage::key("role", "", name)).await;
+            }
+            _ => {}
+        }
+
+        match req {
+            BackendUpdate::PutActor(actor) => {
+                println!("backend => add actor {}/{}", actor.typestr, actor.name);
+                let mut actors = self.actors.shard(&actor.typestr).write().await;
+                let typed_actors = actors
+                    .entry(actor.typestr.clone())
+                    .or_insert_with(HashMap::new);
+                typed_actors.insert(actor.name.clone(), actor);
+            }
+            BackendUpdate::PutGroup(group) => {
+                println!("backend => add group {}", group.name);
+                let mut groups = self.groups.write().await;
+                groups.insert(group.name.clone(), group);
+            }
+            BackendUpdate::PutPolicyRule(policyrule) => {
+                println!("backend => add policy {}", policyrule.name);
+                let mut policies = self.policies.write().await;
+                policies.insert(policyrule.name.clone(), *policyrule);
+            }
+            BackendUpdate::PutPolicySet(set) => {
+                println!("backend => add policy set {}", set.name);
+                let mut sets = self.policy_sets.write().await;
+                sets.insert(set.name.clone(), set);
+            }
+            BackendUpdate::PutRole(role) => {
+                println!("backend => add role {}", role.name);
+                let mut roles = self.roles.write().await;
+                roles.insert(role.name.clone(), role);
+            }
+            BackendUpdate::PutTarget(target) => {
+                println!("backend => add target {}", target.name);
+                let mut targets = self.targets.shard(&target.typestr).write().await;
+                let type_targets = targets
+                    .entry(target.typestr.clone())
+                    .or_insert_with(HashMap::new);
+                type_targets.insert(target.name.clone(), target);
+            }
+            BackendUpdate::DeleteActor(typestr, name) => {
+                println!("backend => delete {}/{}", typestr, name);
+                let mut actors = self.actors.shard(&typestr).write().await;
+                if let Some(typed_actors) = actors.get_mut(typestr.as_str()) {
+                    typed_actors.remove(&name);
+                }
+            }
+            BackendUpdate::DeleteGroup(name) => {
+                println!("backend => delete group {}", name);
+                let mut groups = self.groups.write().await;
+                groups.remove(&name);
+            }
+            BackendUpdate::DeletePolicyRule(name) => {
+                println!("backend => delete policy rule {}", name);
+                let mut policies = self.policies.write().await;
+                policies.remove(&name);
+            }
+            BackendUpdate::DeletePolicySet(name) => {
+                println!("backend => delete policy set {}", name);
+                let mut sets = self.policy_sets.write().await;
+                sets.remove(&name);
+            }
+            BackendUpdate::DeleteRole(name) => {
+                println!("backend => delete role {}", name);
+                let mut roles = self.roles.write().await;
+                roles.remove(&name);
+            }
+            BackendUpdate::DeleteTarget(typestr, name) => {
+                println!("backend => delete target {}/{}", typestr, name);
+                let mut targets = self.targets.shard(&typestr).write().await;
+                if let Some(typed_targets) = targets.get_mut(typestr.as_str()) {
+                    typed_targets.remove(&name);
+                }
+            }
+            BackendUpdate::PutWebhook(hook) => {
+                println!("backend => add webhook {}", hook.name);
+                let mut webhooks = self.webhooks.write().await;
+                webhooks.insert(hook.name.clone(), hook);
+            }
+            BackendUpdate::DeleteWebhook(name) => {
+                println!("backend => delete webhook {}", name);
+                let mut webhooks = self.webhooks.write().await;
+                webhooks.remove(&name);
+            }
+            BackendUpdate::PutApiKey(key) => {
+                println!("backend => add API key {}", key.id);
+                let mut api_keys = self.api_keys.write().await;
+                api_keys.insert(key.id.clone(), key);
+            }
+            BackendUpdate::DeleteApiKey(id) => {
+                println!("backend => delete API key {}", id);
+                let mut api_keys = self.api_keys.write().await;
+                api_keys.remove(&id);
+            }
+            BackendUpdate::PutDelegation(delegation) => {
+                println!("backend => add delegation {}", delegation.name);
+                let mut delegations = self.delegations.write().await;
+                delegations.insert(delegation.name.clone(), delegation);
+            }
+            BackendUpdate::DeleteDelegation(name) => {
+                println!("backend => delete delegation {}", name);
+                let mut delegations = self.delegations.write().await;
+                delegations.remove(&name);
+            }
+            BackendUpdate::PutProposal(proposal) => {
+                println!("backend => add proposal {}", proposal.id);
+                match Proposal::try_from(&proposal) {
+                    Ok(decoded) => {
+                        self.next_proposal.fetch_max(proposal.id, Ordering::SeqCst);
+                        self.proposals.write().await.insert(proposal.id, decoded);
+                    }
+                    Err(err) => eprintln!("{err}"),
+                }
+            }
+            BackendUpdate::DeleteProposal(id) => {
+                println!("backend => delete proposal {}", id);
+                if let Ok(id) = id.parse::<u64>() {
+                    self.proposals.write().await.remove(&id);
+                }
+            }
+            BackendUpdate::PutBreakGlass(break_glass) => {
+                println!("backend => add break glass {}", break_glass.id);
+                self.next_break_glass
+                    .fetch_max(break_glass.id, Ordering::SeqCst);
+                let mut break_glasses = self.break_glasses.write().await;
+                break_glasses.insert(break_glass.id, break_glass.into());
+            }
+            BackendUpdate::DeleteBreakGlass(id) => {
+                println!("backend => delete break glass {}", id);
+                if let Ok(id) = id.parse::<u64>() {
+                    self.break_glasses.write().await.remove(&id);
+                }
+            }
+            BackendUpdate::PutBundle(bundle) => {
+                println!("backend => applied bundle {}", bundle.sequence);
+                self.bundle_sequence
+                    .fetch_max(bundle.sequence, Ordering::SeqCst);
+            }
+        }
+
+        // webhooks aren't replicated, so they don't count as a change
+        if let Some(change) = change {
+            // logged and sent under the lock, so a change stream opening now gets each once
+            let mut changelog = self.changelog.write().await;
+            let revision = self.revision.fetch_add(1, Ordering::SeqCst) + 1;
+            let event = WatchEvent {
+                revision,
+                change: Some(change),
+            };
+            if changelog.len() == CHANGELOG_SIZE {
+                changelog.pop_front();
+            }
+            changelog.push_back(event.clone());
+            let _ = self.watchers.send(event);
+        }
+    }
+
+    /// A target, as the put that would recreate it, if it exists
+    async fn target_entity(&self, typestr: &str, name: &str) -> Option<BackendUpdate> {
+        let targets = self.targets.shard(typestr).read().await;
+        targets
+            .get(typestr)
+            .and_then(|typed| typed.get(name))
+            .cloned()
+            .map(BackendUpdate::PutTarget)
+    }
+
+    /// An actor, as the put that would recreate it, if it exists
+    async fn actor_entity(&self, typestr: &str, name: &str) -> Option<BackendUpdate> {
+        let actors = self.actors.shard(typestr).read().await;
+        actors
+            .get(typestr)
+            .and_then(|typed| typed.get(name))
+            .cloned()
+            .map(BackendUpdate::PutActor)
+    }
+
+    /// The entity an update is for, as the put that would recreate it, if it exists
+    async fn entity(&self, update: &BackendUpdate) -> Option<BackendUpdate> {
+        match update {
+            BackendUpdate::PutTarget(RegisteredTarget { typestr, name, .. }) => {
+                self.target_entity(typestr, name).await
+            }
+            BackendUpdate::DeleteTarget(typestr, name) => self.target_entity(typestr, name).await,
+            BackendUpdate::PutActor(RegisteredActor { typestr, name, .. }) => {
+                self.actor_entity(typestr, name).await
+            }
+            BackendUpdate::DeleteActor(typestr, name) => self.actor_entity(typestr, name).await,
+            BackendUpdate::PutRole(RegisteredRole { name, .. })
+            | BackendUpdate::DeleteRole(name) => self
+                .roles
+                .read()
+                .await
+                .get(name)
+                .cloned()
+                .map(BackendUpdate::PutRole),
+            BackendUpdate::PutGroup(RegisteredGroup { name, .. })
+            | BackendUpdate::DeleteGroup(name) => self
+                .groups
+                .read()
+                .await
+                .get(name)
+                .cloned()
+                .map(BackendUpdate::PutGroup),
+            BackendUpdate::PutPolicyRule(rule) => self
+                .policies
+                .read()
+                .await
+                .get(&rule.name)
+                .cloned()
+                .map(|rule| BackendUpdate::PutPolicyRule(Box::new(rule))),
+            BackendUpdate::DeletePolicyRule(name) => self
+                .policies
+                .read()
+                .await
+                .get(name)
+                .cloned()
+                .map(|rule| BackendUpdate::PutPolicyRule(Box::new(rule))),
+            BackendUpdate::PutPolicySet(RegisteredPolicySet { name, .. })
+            | BackendUpdate::DeletePolicySet(name) => self
+                .policy_sets
+                .read()
+                .await
+                .get(name)
+                .cloned()
+                .map(BackendUpdate::PutPolicySet),
+            BackendUpdate::PutDelegation(RegisteredDelegation { name, .. })
+            | BackendUpdate::DeleteDelegation(name) => self
+                .delegations
+                .read()
+                .await
+                .get(name)
+                .cloned()
+                .map(BackendUpdate::PutDelegation),
+            BackendUpdate::PutWebhook(RegisteredWebhook { name, .. })
+            | BackendUpdate::DeleteWebhook(name) => self
+                .webhooks
+                .read()
+                .await
+                .get(name)
+                .cloned()
+                .map(BackendUpdate::PutWebhook),
+            BackendUpdate::PutApiKey(RegisteredApiKey { id, .. })
+            | BackendUpdate::DeleteApiKey(id) => self
+                .api_keys
+                .read()
+                .await
+                .get(id)
+                .cloned()
+                .map(BackendUpdate::PutApiKey),
+            BackendUpdate::PutProposal(RegisteredProposal { id, .. }) => self
+                .proposals
+                .read()
+                .await
+                .get(id)
+                .map(|proposal| BackendUpdate::PutProposal(proposal.into())),
+            BackendUpdate::DeleteProposal(id) => {
+                let id = id.parse::<u64>().ok()?;
+                self.proposals
+                    .read()
+                    .await
+                    .get(&id)
+                    .map(|proposal| BackendUpdate::PutProposal(proposal.into()))
+            }
+            BackendUpdate::PutBreakGlass(RegisteredBreakGlass { id, .. }) => self
+                .break_glasses
+                .read()
+                .await
+                .get(id)
+                .map(|break_glass| BackendUpdate::PutBreakGlass(break_glass.clone().into())),
+            BackendUpdate::DeleteBreakGlass(id) => {
+                let id = id.parse::<u64>().ok()?;
+                self.break_glasses
+                    .read()
+                    .await
+                    .get(&id)
+                    .map(|break_glass| BackendUpdate::PutBreakGlass(break_glass.clone().into()))
+            }
+            // only the sequence of the applied bundle is kept
+            BackendUpdate::PutBundle(_) => None,
+        }
+    }
+
+    /// Everything we hold, as the puts that would recreate it
+    async fn snapshot(&self) -> Vec<BackendUpdate> {
+        let mut state = Vec::new();
+        for typed in self.targets.read().await.values() {
+            state.extend(typed.values().cloned().map(BackendUpdate::PutTarget));
+        }
+        for typed in self.actors.read().await.values() {
+            state.extend(typed.values().cloned().map(BackendUpdate::PutActor));
+        }
+        let roles = self.roles.read().await;
+        state.extend(roles.values().cloned().map(BackendUpdate::PutRole));
+        let groups = self.groups.read().await;
+        state.extend(groups.values().cloned().map(BackendUpdate::PutGroup));
+        let policies = self.policies.read().await;
+        state.extend(
+            policies
+                .values()
+                .cloned()
+                .map(|p| BackendUpdate::PutPolicyRule(Box::new(p))),
+        );
+        let policy_sets = self.policy_sets.read().await;
+        state.extend(
+            policy_sets
+                .values()
+                .cloned()
+                .map(BackendUpdate::PutPolicySet),
+        );
+        let delegations = self.delegations.read().await;
+        state.extend(
+            delegations
+                .values()
+                .cloned()
+                .map(BackendUpdate::PutDelegation),
+        );
+        let webhooks = self.webhooks.read().await;
+        state.extend(webhooks.values().cloned().map(BackendUpdate::PutWebhook));
+        let api_keys = self.api_keys.read().await;
+        state.extend(api_keys.values().cloned().map(BackendUpdate::PutApiKey));
+        let proposals = self.proposals.read().await;
+        state.extend(
+            proposals
+                .values()
+                .map(|proposal| BackendUpdate::PutProposal(proposal.into())),
+        );
+        let break_glasses = self.break_glasses.read().await;
+        state.extend(
+            break_glasses
+                .values()
+                .map(|break_glass| BackendUpdate::PutBreakGlass(break_glass.clone().into())),
+        );
+        state
+    }
+
+    /// Bring memory in line with a full reload of the backend, e.g. after missing changes
+    ///
+    /// Only what differs from the reload is put or deleted, through `update`, so watchers and
+    /// webhooks hear about it like any other change. Replies with how many entities changed.
+    async fn reconcile(&self, loaded: Vec<BackendUpdate>, tx: Sender<DsResponse>) {
+        let txn = changes(self.snapshot().await, loaded);
+
+        let changed = txn.len();
+        println!("backend => reconciled with a full reload: {changed} changes");
+
+        self.notify_changes(&txn).await;
+        for update in txn {
+            self.update(update).await;
+        }
+
+        let _ = tx.send(DsResponse::Reconciled(changed));
+    }
+
+    /// Load everything again from the backend and make memory match it, e.g. after the backend
+    /// was edited directly or memory is suspected to have drifted from it
+    ///
+    /// Nothing changes unless everything loads. What differs is changed through `reconcile`.
+    async fn reload_state(&self, tx: Sender<DsResponse>) {
+        // the nil backend keeps nothing to load again
+        if matches!(self.backend, StorageType::Nil) {
+            let _ = tx.send(DsResponse::Error(Status::failed_precondition(
+                "There is no storage backend to reload from",
+            )));
+            return;
+        }
+
+        match self.load_stored().await {
+            Ok(loaded) => self.reconcile(loaded, tx).await,
+            Err(err) => {
+                let _ = tx.send(DsResponse::Error(Status::unavailable(format!(
+                    "Could not reload from the storage backend: {err}"
+                ))));
+            }
+        }
+    }
+
+    /// Everything in the backend, as the puts that would recreate it
+    async fn load_stored(&self) -> Result<Vec<BackendUpdate>, String> {
+        let mut loaded = Vec::new();
+        for typed in self.storage.load_targets().await?.into_values() {
+            loaded.extend(typed.into_values().map(BackendUpdate::PutTarget));
+        }
+        for typed in self.storage.load_actors().await?.into_values() {
+            loaded.extend(typed.into_values().map(BackendUpdate::PutActor));
+        }
+        let roles = self.storage.load_roles().await?;
+        loaded.extend(roles.into_values().map(BackendUpdate::PutRole));
+        let groups = self.storage.load_groups().await?;
+        loaded.extend(groups.into_values().map(BackendUpdate::PutGroup));
+        let policies = self.storage.load_policies().await?;
+        loaded.extend(
+            policies
+                .into_values()
+                .map(|p| BackendUpdate::PutPolicyRule(Box::new(p))),
+        );
+        let policy_sets = self.storage.load_policy_sets().await?;
+        loaded.extend(policy_sets.into_values().map(BackendUpdate::PutPolicySet));
+        let delegations = self.storage.load_delegations().await?;
+        loaded.extend(delegations.into_values().map(BackendUpdate::PutDelegation));
+        let webhooks = self.storage.load_webhooks().await?;
+        loaded.extend(webhooks.into_values().map(BackendUpdate::PutWebhook));
+        let api_keys = self.storage.load_api_keys().await?;
+        loaded.extend(api_keys.into_values().map(BackendUpdate::PutApiKey));
+        let proposals = self.storage.load_proposals().await?;
+        loaded.extend(proposals.into_values().map(BackendUpdate::PutProposal));
+        let break_glasses = self.storage.load_break_glasses().await?;
+        loaded.extend(
+            break_glasses
+                .into_values()
+                .map(BackendUpdate::PutBreakGlass),
+        );
+        Ok(loaded)
+    }
+
+    /// Make changes replicated from a server in another region
+    ///
+    /// A change conflicts if the entity here is neither as it was before the change nor as it is
+    /// after, i.e. it was also changed here. Changes that were already made are skipped, so a
+    /// batch can be sent again after a failure. Each change is persisted on its own.
+    async fn replicate(&self, req: ReplicateRequest, tx: Sender<DsResponse>) {
+        let conflict_policy = req.conflicts();
+        let mut result = ReplicateResponse::default();
+
+        for change in req.changes {
+            let update = match change.event.and_then(|event| event.change) {
+                Some(change) => match self.check_replicated(&change).await {
+                    Ok(_) => BackendUpdate::from(change),
+                    Err(status) => {
+                        let _ = tx.send(DsResponse::Error(status));
+                        return;
+                    }
+                },
+                None => {
+                    let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                        "Replicated change cannot be null",
+                    )));
+                    return;
+                }
+            };
+            let previous: Vec<BackendUpdate> = change
+                .previous
+                .and_then(|event| event.change)
+                .map(BackendUpdate::from)
+                .into_iter()
+                .collect();
+            let after: Vec<BackendUpdate> = match update {
+                BackendUpdate::PutActor(_)
+                | BackendUpdate::PutGroup(_)
+                | BackendUpdate::PutPolicyRule(_)
+                | BackendUpdate::PutPolicySet(_)
+                | BackendUpdate::PutDelegation(_)
+                | BackendUpdate::PutRole(_)
+                | BackendUpdate::PutTarget(_)
+                | BackendUpdate::PutWebhook(_)
+                | BackendUpdate::PutApiKey(_)
+                | BackendUpdate::PutBreakGlass(_) => vec![update.clone()],
+                _ => vec![],
+            };
+            let current: Vec<BackendUpdate> = self.entity(&update).await.into_iter().collect();
+
+            if changes(current.clone(), after).is_empty() {
+                result.unchanged += 1;
+                continue;
+            }
+            if !changes(current, previous).is_empty() {
+                result.conflicts += 1;
+                if let Some((_, kind, name)) = describe_change(&update) {
+                    println!(
+                        "Replicated change to {kind} {name} conflicts with a change made here"
+                    );
+                }
+                if conflict_policy == ConflictPolicy::TargetWins {
+                    continue;
+                }
+            }
+
+            let txn = vec![update];
+            if let Err(err) = self.storage.persist_changes(&txn).await {
+                let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                return;
+            }
+            self.notify_changes(&txn).await;
+            for update in txn {
+                self.update(update).await;
+            }
+            result.applied += 1;
+        }
+
+        let _ = tx.send(DsResponse::Replicated(result));
+    }
+
+    /// Make sure a replicated change passes the checks a change made here would
+    ///
+    /// Quotas only count against entities that are new here. Removals aren't checked.
+    async fn check_replicated(&self, change: &WatchChange) -> Result<(), Status> {
+        match change {
+            WatchChange::PutTarget(target) => {
+                if target.name.is_empty() || target.typestr.is_empty() {
+                    return Err(Status::invalid_argument("Name and typestr cannot be null"));
+                }
+                check_action_groups(target.action_groups.keys())
+                    .map_err(Status::invalid_argument)?;
+                let violations = self.attribute_violations(
+                    AttributeRules::check_target,
+                    &[("attributes", &target.attributes)],
+                );
+                if !violations.is_empty() {
+                    return Err(validation::violations_status(violations));
+                }
+            }
+            WatchChange::PutActor(actor) => {
+                if actor.name.is_empty() || actor.typestr.is_empty() {
+                    return Err(Status::invalid_argument("Name and typestr cannot be null"));
+                }
+                let violations = self.attribute_violations(
+                    AttributeRules::check_actor,
+                    &[("attributes", &actor.attributes)],
+                );
+                if !violations.is_empty() {
+                    return Err(validation::violations_status(violations));
+                }
+                if let Some(max_actors) = self.config.quotas.max_actors {
+                    let typestr = actor.typestr.to_ascii_lowercase();
+                    let actors = self.actors.read().await;
+                    let exists = actors
+                        .get(&typestr)
+                        .is_some_and(|typed| typed.contains_key(&actor.name.to_ascii_lowercase()));
+                    let count: usize = actors.values().map(|typed| typed.len()).sum();
+                    if !exists && count >= max_actors {
+                        return Err(Status::resource_exhausted(format!(
+                            "Actor limit of {max_actors} reached"
+                        )));
+                    }
+                }
+            }
+            WatchChange::PutGroup(group) => {
+                self.check_group_size(group.members.len())
+                    .map_err(Status::resource_exhausted)?;
+            }
+            WatchChange::PutPolicy(rule) => {
+                check_decision(rule)
+                    .and_then(|_| check_cidr_blocks(rule))
+                    .and_then(|_| check_rate_checks(rule))
+                    .and_then(|_| self.check_wasm_module(&rule.clone().into()))
+                    .map_err(Status::invalid_argument)?;
+                if let Some(max_policies) = self.config.quotas.max_policies {
+                    let policies = self.policies.read().await;
+                    let exists = policies.contains_key(&rule.name.to_ascii_lowercase());
+                    if !exists && policies.len() >= max_policies {
+                        return Err(Status::resource_exhausted(format!(
+                            "Policy limit of {max_policies} reached"
+                        )));
+                    }
+                }
+            }
+            WatchChange::PutPolicySet(set) => {
+                self.check_policy_set(&set.clone().into()).await?;
+            }
+            WatchChange::PutDelegation(delegation) => {
+                let delegation = RegisteredDelegation::from(delegation.clone());
+                delegation
+                    .validate(delegation.created_at)
+                    .map_err(Status::invalid_argument)?;
+            }
+            WatchChange::PutBreakGlass(break_glass) => {
+                RegisteredBreakGlass::from(break_glass.clone())
+                    .validate()
+                    .map_err(Status::invalid_argument)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /** UNUSED ENTITIES */
+    /// Find the actors, targets, and roles nothing uses
+    ///
+    /// An actor is unused if it is in no group, no policy names it, and it hasn't been checked or
+    /// changed in the given number of days. The same goes for targets, leaving out groups, and
+    /// for roles, which are unused if no group or actor has them and no policy asks for them.
+    /// Usage is only tracked in memory, so everything counts as used when the server started.
+    async fn unused(&self, days: u32) -> Vec<UnusedEntity> {
+        let cutoff = now().saturating_sub(u64::from(days) * 24 * 60 * 60);
+        let usage = self.usage.read().await;
+        let policies = self.policies.read().await;
+        let groups = self.groups.read().await;
+
+        let mut unused = Vec::new();
+        let mut stale = |kind: &str, typestr: &str, name: &str| {
+            let used = usage
+                .get(&Usage::key(kind, typestr, name))
+                .map(|hits| hits.last_used);
+            if used.unwrap_or(self.started) < cutoff {
+                unused.push(UnusedEntity {
+                    kind: kind.to_string(),
+                    name: name.to_string(),
+                    typestr: typestr.to_string(),
+                    last_used: used.unwrap_or_default(),
+                });
+            }
+        };
+
+        let actors = self.actors.read().await;
+        let mut granted: HashSet<&str> = HashSet::new();
+        for actor in actors.values().flat_map(|typed| typed.values()) {
+            granted.extend(
+                actor
+                    .attributes
+                    .get("has-role")
+                    .into_iter()
+                    .flatten()
+                    .map(|role| role.as_ref()),
+            );
+
+            let member = RegisteredGroupMember::from(actor);
+            if groups.values().any(|group| group.members.contains(&member))
+                || policies
+                    .values()
+                    .any(|rule| rule.refers_to_actor(&actor.typestr, &actor.name))
+            {
+                continue;
+            }
+            stale("actor", &actor.typestr, &actor.name);
+        }
+
+        for target in self
+            .targets
+            .read()
+            .await
+            .values()
+            .flat_map(|typed| typed.values())
+        {
+            if policies
+                .values()
+                .any(|rule| rule.refers_to_target(&target.typestr, &target.name))
+            {
+                continue;
+            }
+            stale("target", &target.typestr, &target.name);
+        }
+
+        for role in self.roles.read().await.values() {
+            if !role.groups.is_empty()
+                || granted.contains(role.name.as_str())
+                || policies
+                    .values()
+                    .any(|rule| rule.refers_to_attribute("has-role", &role.name))
+            {
+                continue;
+            }
+            stale("role", "", &role.name);
+        }
+
+        unused.sort_by(|a, b| (&a.kind, &a.typestr, &a.name).cmp(&(&b.kind, &b.typestr, &b.name)));
+        unused
+    }
+
+    /// Report the actors, targets, and roles nothing uses, without removing them
+    async fn find_unused(&self, req: FindUnusedRequest, tx: Sender<DsResponse>) {
+        if req.days == 0 {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "Days must be at least 1",
+            )));
+            return;
+        }
+
+        let entities = self.unused(req.days).await;
+        let _ = tx.send(DsResponse::Unused(FindUnusedResponse { entities }));
+    }
+
+    /// Remove the actors, targets, and roles nothing uses, returning what was removed
+    async fn remove_unused(&self, req: FindUnusedRequest, tx: Sender<DsResponse>) {
+        if req.days == 0 {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "Days must be at least 1",
+            )));
+            return;
+        }
+
+        let entities = self.unused(req.days).await;
+        let txn: Vec<BackendUpdate> = entities
+            .iter()
+            .map(|entity| match entity.kind.as_str() {
+                "actor" => BackendUpdate::DeleteActor(entity.typestr.clone(), entity.name.clone()),
+                "target" => {
+                    BackendUpdate::DeleteTarget(entity.typestr.clone(), entity.name.clone())
+                }
+                _ => BackendUpdate::DeleteRole(entity.name.clone()),
+            })
+            .collect();
+
+        if !txn.is_empty() {
+            if let Err(err) = self.storage.persist_changes(&txn).await {
+                let _ = tx.send(DsResponse::Error(Status::internal(err)));
+                return;
+            }
+            self.notify_changes(&txn).await;
+            for update in txn {
+                self.update(update).await;
+            }
+        }
+
+        let _ = tx.send(DsResponse::Unused(FindUnusedResponse { entities }));
+    }
+
+    /** TRANSACTIONS */
+    /// Make several changes at once; either all of them are saved or none are
+    ///
+    /// The changes are made one after another on a scratch copy of the datastore, so each one
+    /// sees those before it and is validated just like a single request. Whatever then differs
+    /// between the copy and what we started from is persisted together.
+    async fn apply_transaction(&self, req: ApplyTransactionRequest, tx: Sender<DsResponse>) {
+        let _ = match self.transact(req, Vec::new()).await {
+            Ok(changed) => tx.send(DsResponse::TransactionApplied(changed)),
+            Err(status) => tx.send(DsResponse::Error(status)),
+        };
+    }
+
+    /// Make the changes of a transaction, persisting `also` along with them, and return what
+    /// changed
+    async fn transact(
+        &self,
+        req: ApplyTransactionRequest,
+        also: Vec<BackendUpdate>,
+    ) -> Result<Vec<EntityChange>, Status> {
+        let dry_run = req.dry_run;
+        let scratch = self.scratch().await;
+        let before = scratch.snapshot().await;
+
+        for (index, mutation) in req.mutations.into_iter().enumerate() {
+            let (change_tx, change_rx) = channel::<DsResponse>();
+            match mutation.op {
+                Some(Op::AddTarget(req)) => scratch.add_target(req, change_tx).await,
+                Some(Op::ModifyTarget(req)) => scratch.modify_target(req, change_tx).await,
+                Some(Op::RemoveTarget(req)) => scratch.remove_target(req, change_tx).await,
+                Some(Op::AddActor(req)) => scratch.add_actor(req, change_tx).await,
+                Some(Op::ModifyActor(req)) => scratch.modify_actor(req, change_tx).await,
+                Some(Op::RemoveActor(req)) => scratch.remove_actor(req, change_tx).await,
+                Some(Op::AddRole(req)) => scratch.add_role(req, change_tx).await,
+                Some(Op::ModifyRole(req)) => scratch.modify_role(req, change_tx).await,
+                Some(Op::RemoveRole(req)) => scratch.remove_role(req, change_tx).await,
+                Some(Op::AddGroup(req)) => scratch.add_group(req, change_tx).await,
+                Some(Op::ModifyGroup(req)) => scratch.modify_group(req, change_tx).await,
+                Some(Op::BulkModifyMemberships(req)) => {
+                    scratch.bulk_modify_memberships(req, change_tx).await
+                }
+                Some(Op::RemoveGroup(req)) => scratch.remove_group(req, change_tx).await,
+                Some(Op::AddPolicy(req)) => scratch.add_policy(req, change_tx).await,
+                Some(Op::ModifyPolicy(req)) => scratch.modify_policy(req, change_tx).await,
+                Some(Op::RemovePolicy(req)) => scratch.remove_policy(req, change_tx).await,
+                None => {
+                    return Err(Status::invalid_argument(format!(
+                        "Mutation {index} has no change"
+                    )));
+                }
+            }
+
+            match change_rx.await {
+                Ok(DsResponse::Error(status)) => {
+                    return Err(Status::new(
+                        status.code(),
+                        format!("Mutation {index} failed: {}", status.message()),
+                    ));
+                }
+                Ok(_) => {}
+                Err(err) => return Err(Status::internal(err.to_string())),
+            }
+        }
+
+        let mut txn = changes(before, scratch.snapshot().await);
+        let changed = txn
+            .iter()
+            .filter_map(describe_change)
+            .map(|(op, kind, name)| EntityChange {
+                op: op.to_string(),
+                kind: kind.to_string(),
+                name,
+            })
+            .collect();
+
+        if !dry_run {
+            txn.extend(also);
+            if let Err(err) = self.persist(&mut txn, now()).await {
+                return Err(self.partial_failure(txn, err).await);
+            }
+            self.notify_changes(&txn).await;
+            for update in txn {
+                self.update(update).await;
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Make the policies those of a bundle, in one transaction
+    ///
+    /// Policies in the bundle are added or modified, and those that aren't are removed. Only a
+    /// bundle newer than the last one applied is, and its sequence is saved with the policies.
+    async fn apply_bundle(&self, bundle: PolicyBundle, dry_run: bool, tx: Sender<DsResponse>) {
+        let sequence = bundle.sequence;
+        // claimed before the changes are made, so two bundles applied at once can't both pass
+        let last = match dry_run {
+            true => self.bundle_sequence.load(Ordering::SeqCst),
+            false => self.bundle_sequence.fetch_max(sequence, Ordering::SeqCst),
+        };
+        if sequence <= last {
+            let _ = tx.send(DsResponse::Error(Status::failed_precondition(format!(
+                "Bundle {sequence} is not newer than bundle {last}, the last one applied"
+            ))));
+            return;
+        }
+
+        let mut existing: HashSet<String> = self.policies.read().await.keys().cloned().collect();
+
+        let mut mutations = Vec::new();
+        for rule in bundle.rules {
+            let op = match existing.remove(&rule.name.to_ascii_lowercase()) {
+                true => Op::ModifyPolicy(ModifyPolicyRequest {
+                    rule: Some(rule),
+                    ..Default::default()
+                }),
+                false => Op::AddPolicy(AddPolicyRequest {
+                    rule: Some(rule),
+                    ..Default::default()
+                }),
+            };
+            mutations.push(Mutation { op: Some(op) });
+        }
+        let mut removed: Vec<String> = existing.into_iter().collect();
+        removed.sort();
+        for name in removed {
+            mutations.push(Mutation {
+                op: Some(Op::RemovePolicy(RemovePolicyRequest {
+                    name,
+                    ..Default::default()
+                })),
+            });
+        }
+
+        let applied = AppliedBundle {
+            sequence,
+            applied_at: now(),
+        };
+        let req = ApplyTransactionRequest { mutations, dry_run };
+        let _ = match self
+            .transact(req, vec![BackendUpdate::PutBundle(applied)])
+            .await
+        {
+            Ok(changed) => tx.send(DsResponse::TransactionApplied(changed)),
+            Err(status) => {
+                // a bundle that wasn't applied doesn't count
+                if !dry_run {
+                    let _ = self.bundle_sequence.compare_exchange(
+                        sequence,
+                        last,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    );
+                }
+                tx.send(DsResponse::Error(status))
+            }
+        };
+    }
+
+    /// A copy of the datastore that changes can be tried on without saving or announcing them
+    ///
+    /// Webhooks aren't copied, so the copy has nobody to tell about its changes.
+    async fn scratch(&self) -> Datastore {
+        Datastore {
+            rx: flume::unbounded().1,
+            storage: Box::new(NilStorage {}),
+            backend: StorageType::Nil,
+            storage_health: self.storage_health.clone(),
+            startup_issues: RwLock::new(Vec::new()),
+            drift: RwLock::new(HashSet::new()),
+            drift_repairs: AtomicU64::new(0),
+            config: self.config.clone(),
+            targets: Arc::new(Sharded::new(
+                self.config.shards,
+                self.targets.read().await.to_map(),
+            )),
+            actors: Arc::new(Sharded::new(
+                self.config.shards,
+                self.actors.read().await.to_map(),
+            )),
+            roles: Arc::new(RwLock::new(self.roles.read().await.clone())),
+            groups: Arc::new(Versioned::new(self.groups.read().await.clone())),
+            expansions: Arc::new(Expansions::default()),
+            policies: Arc::new(RwLock::new(self.policies.read().await.clone())),
+            policy_sets: Arc::new(RwLock::new(self.policy_sets.read().await.clone())),
+            delegations: Arc::new(RwLock::new(self.delegations.read().await.clone())),
+            webhooks: Arc::new(RwLock::new(HashMap::new())),
+            api_keys: Arc::new(RwLock::new(HashMap::new())),
+            dispatcher: Arc::new(Dispatcher::new()),
+            wasm: self.wasm.clone(),
+            rates: self.rates.clone(),
+            deny_streaks: self.deny_streaks.clone(),
+            policy_timings: self.policy_timings.clone(),
+            risk: self.risk.clone(),
+            recorded_checks: Arc::new(RwLock::new(VecDeque::new())),
+            recorder: None,
+            sampler: None,
+            proposals: RwLock::new(BTreeMap::new()),
+            next_proposal: AtomicU64::new(0),
+            approvals: RwLock::new(BTreeMap::new()),
+            next_approval: AtomicU64::new(0),
+            break_glasses: RwLock::new(BTreeMap::new()),
+            next_break_glass: AtomicU64::new(0),
+            bundle_sequence: AtomicU64::new(0),
+            revision: AtomicU64::new(0),
+            watchers: broadcast::channel(1).0,
+            epoch: self.epoch,
+            changelog: RwLock::new(VecDeque::new()),
+            started: self.started,
+            usage: Usage::new(1),
+        }
+    }
+
+    /** REFERENCES */
+    /// Find the policies, groups, and roles that refer to an actor, target, role, or group
+    ///
+    /// Policies refer to actors and targets through the names in their checks, and to roles and
+    /// groups through the `has-role` and `member-of` actor attributes.
+    async fn get_references(&self, req: GetReferencesRequest, tx: Sender<DsResponse>) {
+        let kind = req.kind.to_ascii_lowercase();
+        let name = req.name.to_ascii_lowercase();
+        let typestr = req.typestr.to_ascii_lowercase();
+
+        let exists = match kind.as_str() {
+            "actor" => self
+                .actors
+                .read()
+                .await
+                .get(&typestr)
+                .is_some_and(|typed| typed.contains_key(&name)),
+            "target" => self
+                .targets
+                .read()
+                .await
+                .get(&typestr)
+                .is_some_and(|typed| typed.contains_key(&name)),
+            "role" => self.roles.read().await.contains_key(&name),
+            "group" => self.groups.read().await.contains_key(&name),
+            _ => {
+                let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                    "Kind must be one of actor, target, role, or group",
+                )));
+                return;
+            }
+        };
+        if !exists {
+            let _ = tx.send(DsResponse::Error(Status::not_found(format!(
+                "Could not find {kind} by name"
+            ))));
+            return;
+        }
+
+        let mut policies: Vec<String> = self
+            .policies
+            .read()
+            .await
+            .values()
+            .filter(|rule| match kind.as_str() {
+                "actor" => rule.refers_to_actor(&typestr, &name),
+                "target" => rule.refers_to_target(&typestr, &name),
+                "role" => rule.refers_to_attribute("has-role", &name),
+                _ => rule.refers_to_attribute("member-of", &name),
+            })
+            .map(|rule| rule.name.clone())
+            .collect();
+        policies.sort_unstable();
+
+        let member = RegisteredGroupMember {
+            name: name.clone(),
+            typestr: intern(&typestr),
+        };
+        let mut groups: Vec<String> = self
+            .groups
+            .read()
+            .await
+            .values()
+            .filter(|group| match kind.as_str() {
+                "actor" => group.members.contains(&member),
+                "role" => group.roles.contains(&name),
+                _ => false,
+            })
+            .map(|group| group.name.clone())
+            .collect();
+        groups.sort_unstable();
+
+        let mut roles: Vec<String> = self
+            .roles
+            .read()
+            .await
+            .values()
+            .filter(|role| kind == "group" && role.groups.contains(&name))
+            .map(|role| role.name.clone())
+            .collect();
+        roles.sort_unstable();
+
+        let _ = tx.send(DsResponse::References(GetReferencesResponse {
+            policies,
+            groups,
+            roles,
+        }));
+    }
+
+    /** REPLICATION */
+    /// Get everything a replica needs to make decisions
+    ///
+    /// The revision is read before the state, so a change that lands while we copy is also
+    /// sent to watchers with a higher revision. Replaying it on top of the copy is harmless.
+    /// The same goes for a change stream resumed with the token returned.
+    async fn sync(&self, tx: Sender<DsResponse>) {
+        let revision = self.revision.load(Ordering::SeqCst);
+
+        let targets = self
+            .targets
+            .read()
+            .await
+            .values()
+            .flat_map(|typed| typed.values().cloned().map(Target::from))
+            .collect();
+        let actors = self
+            .actors
+            .read()
+            .await
+            .values()
+            .flat_map(|typed| typed.values().cloned().map(Actor::from))
+            .collect();
+        let roles = self
+            .roles
+            .read()
+            .await
+            .values()
+            .cloned()
+            .map(Role::from)
+            .collect();
+        let groups = self
+            .groups
+            .read()
+            .await
+            .values()
+            .cloned()
+            .map(Group::from)
+            .collect();
+        let policies = self
+            .policies
+            .read()
+            .await
+            .values()
+            .cloned()
+            .map(PolicyRule::from)
+            .collect();
+        let policy_sets = self
+            .policy_sets
+            .read()
+            .await
+            .values()
+            .cloned()
+            .map(PolicySet::from)
+            .collect();
+        let delegations = self
+            .delegations
+            .read()
+            .await
+            .values()
+            .cloned()
+            .map(Delegation::from)
+            .collect();
+        let break_glasses = self.break_glasses.read().await.values().cloned().collect();
+
+        let _ = tx.send(DsResponse::SyncResult(Box::new(SyncResponse {
+            revision,
+            resume_token: resume_token(self.epoch, revision),
+            targets,
+            actors,
+            roles,
+            groups,
+            policies,
+            policy_sets,
+            delegations,
+            break_glasses,
+        })));
+    }
+
+    /// Open a change stream, along with the changes made since the resume token, if any
+    ///
+    /// The changelog lock is held while subscribing, so no change falls between those already
+    /// logged and those yet to be sent.
+    async fn stream_changes(&self, token: String, tx: Sender<DsResponse>) {
+        let after = match token.is_empty() {
+            true => None,
+            false => match parse_resume_token(&token) {
+                Ok(after) => Some(after),
+                Err(err) => {
+                    let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+                    return;
+                }
+            },
+        };
+
+        let changelog = self.changelog.read().await;
+        let events = self.watchers.subscribe();
+        let revision = self.revision.load(Ordering::SeqCst);
+
+        let backlog = match after {
+            None => vec![],
+            Some((epoch, _)) if epoch != self.epoch => {
+                let _ = tx.send(DsResponse::Error(Status::out_of_range(
+                    "Resume token is from before the server restarted; sync again",
+                )));
+                return;
+            }
+            Some((_, after)) if after > revision => {
+                let _ = tx.send(DsResponse::Error(Status::invalid_argument(format!(
+                    "Resume token is ahead of the server at revision {revision}"
+                ))));
+                return;
+            }
+            Some((_, after)) => {
+                let oldest = changelog.front().map(|event| event.revision);
+                if oldest.is_some_and(|oldest| after + 1 < oldest) {
+                    let _ = tx.send(DsResponse::Error(Status::out_of_range(
+                        "Resume token is older than the changes kept; sync again",
+                    )));
+                    return;
+                }
+                changelog
+                    .iter()
+                    .filter(|event| event.revision > after)
+                    .cloned()
+                    .collect()
+            }
+        };
+
+        let after = after.map(|(_, after)| after).unwrap_or(revision);
+        let _ = tx.send(DsResponse::Changes(self.epoch, after, backlog, events));
+    }
+
+    /// Replace everything but the webhooks with the state synced from another server
+    async fn load(&self, state: SyncResponse, tx: Sender<DsResponse>) {
+        let mut targets: Typed<RegisteredTarget> = HashMap::new();
+        for target in state.targets.into_iter().map(RegisteredTarget::from) {
+            targets
+                .entry(target.typestr.clone())
+                .or_default()
+                .insert(target.name.clone(), target);
+        }
+
+        let mut actors: Typed<RegisteredActor> = HashMap::new();
+        for actor in state.actors.into_iter().map(RegisteredActor::from) {
+            actors
+                .entry(actor.typestr.clone())
+                .or_default()
+                .insert(actor.name.clone(), actor);
+        }
+
+        let roles = state
+            .roles
+            .into_iter()
+            .map(|role| (role.name.to_ascii_lowercase(), RegisteredRole::from(role)))
+            .collect();
+        let groups = state
+            .groups
+            .into_iter()
+            .map(|group| {
+                (
+                    group.name.to_ascii_lowercase(),
+                    RegisteredGroup::from(group),
+                )
+            })
+            .collect();
+        let policies = state
+            .policies
+            .into_iter()
+            .map(|rule| (rule.name.clone(), RegisteredPolicyRule::from(rule)))
+            .collect();
+        let policy_sets = state
+            .policy_sets
+            .into_iter()
+            .map(|set| {
+                (
+                    set.name.to_ascii_lowercase(),
+                    RegisteredPolicySet::from(set),
+                )
+            })
+            .collect();
+        let delegations = state
+            .delegations
+            .into_iter()
+            .map(RegisteredDelegation::from)
+            .map(|delegation| (delegation.name.clone(), delegation))
+            .collect();
+        let break_glasses: BTreeMap<u64, BreakGlass> = state
+            .break_glasses
+            .into_iter()
+            .map(|break_glass| (break_glass.id, break_glass))
+            .collect();
+        let last_break_glass = break_glasses.keys().max().copied().unwrap_or_default();
+
+        self.targets.replace(targets).await;
+        self.actors.replace(actors).await;
+        *self.roles.write().await = roles;
+        *self.groups.write().await = groups;
+        *self.policies.write().await = policies;
+        *self.policy_sets.write().await = policy_sets;
+        *self.delegations.write().await = delegations;
+        *self.break_glasses.write().await = break_glasses;
+        self.next_break_glass
+            .fetch_max(last_break_glass, Ordering::SeqCst);
+
+        println!("backend => loaded state at revision {}", state.revision);
+        let _ = tx.send(DsResponse::Loaded(state.revision));
+    }
+
+    /// Perform a check
+    ///
+    /// We will receive an actor (type and name) and a list of attributes that the policy
+    /// enforcement point (PEP) wants to share about the actor. PEP may also share environment
+    /// attributes in the form of key/val pairs (vals are a list). Finally, the PEP will specify
+    /// the target (name and type) and action to be checked against the policies. DS will evaluate
+    /// against known policy rules and decide on a ALLOW/DENY decision.
+    ///
+    /// The actor may match a registered actor, in which case, we'll add some more attributes if
+    /// we have them. The actor may also belong to a group which has been granted some roles.
+    /// In that case, we will add "member-of" attributes for each group, and "has-role" attributes
+    /// for each role. Lastly, we will determine a bucket (between 0-99) using the murmur3 algo
+    ///
+    /// When several actions are checked, the decision is ALLOW only if every one of them is
+    /// allowed. With `EACH_ACTION`, the decision on each action is returned as well.
+    ///
+    /// If a matching rule asks for approval and nothing denies, the decision is PENDING and an
+    /// approval is asked for. Once it is co-signed, checks that name it are allowed, unless
+    /// something denies them by then.
+    ///
+    /// A strict `EACH_ACTION` check of a registered target that names some actions the target
+    /// doesn't have is decided on the rest, with the unknown actions INDETERMINATE.
+    async fn check(&self, mut req: CheckRequest, tx: Sender<DsResponse>) {
+        // the actions asked for, and whether the target has each, if only some are evaluated
+        let mut partial = None;
+        if let Err(err) = self.check_strict(&req).await {
+            let known = match (self.config.strict_indeterminate, req.action_mode()) {
+                (true, ActionMode::EachAction) => self
+                    .known_actions(&req)
+                    .await
+                    .filter(|known| known.contains(&true)),
+                _ => None,
+            };
+            match (known, self.config.strict_indeterminate) {
+                (Some(known), _) => {
+                    println!("Indeterminate, {err}: {req}");
+                    let requested = std::mem::take(&mut req.target_action);
+                    req.target_action = requested
+                        .iter()
+                        .zip(&known)
+                        .filter(|(_, known)| **known)
+                        .map(|(action, _)| action.clone())
+                        .collect();
+                    partial = Some((requested, known));
+                }
+                (None, true) => return decide_unregistered(req, err, tx),
+                (None, false) => {
+                    let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+                    return;
+                }
+            }
+        }
+
+        // a check that would take too much work to decide is given the configured decision
+        // instead; the budget is spent on expanding groups and on every rule evaluated
+        let limits = &self.config.eval_limits;
+        let budget = EvalBudget::new(limits);
+
+        let (actor, mut env_attributes, target_attributes) =
+            self.prepare_check(&req, &budget).await;
+        let risk = self.score_risk(&req, &actor, &mut env_attributes, true);
+        let each_action = self.resolve_each_action(&req).await;
+        self.record_check(&req, &actor).await;
+        self.record_usage(&req, &actor).await;
+        let delegators = self.delegators(&req, &actor, &each_action, &budget).await;
+        // an actor locked out after a streak of denies is denied everything until it ends
+        let locked_out = self.deny_streaks.locked_out(&actor);
+
+        let approval = self.find_approval(&req, &actor).await;
+        // how many approvers co-signed the check, if enough did for the approval it asked for
+        let cosigned = approval
+            .as_ref()
+            .filter(|approval| approval.approved_at > 0)
+            .map_or(0, |approval| approval.approvers.len());
+
+        let policies = self.policies.read().await;
+        let policy_sets = self.policy_sets.read().await;
+
+        // in an emergency, deny policies may be bypassed, and an actor may be allowed everything
+        let (elevated, bypassed) = self.break_glass_for(&req, &actor, &policies).await;
+        let bypassing = match bypassed.is_empty() {
+            true => None,
+            false => {
+                let mut enforced = policies.clone();
+                for break_glass in &bypassed {
+                    enforced.remove(&break_glass.policy);
+                }
+                Some(enforced)
+            }
+        };
+        let enforced = bypassing.as_ref().unwrap_or(&policies);
+        let mut broken: Vec<&BreakGlass> = Vec::new();
+
+        // TODO -- refactor the policy store to make applicable polices quicker to find
+        // Examine every policy -- if the actor check, environment check, and target check's pass
+        // then we can make a determination.
+        let per_action = req.action_mode() == ActionMode::EachAction;
+        let mut decision = Decide::Allow;
+        let mut action_decisions = Vec::new();
+        let mut allowed = Vec::new();
+        let mut delegated: Vec<&RegisteredDelegation> = Vec::new();
+        for (name, actions) in &each_action {
+            if locked_out.is_some() || !budget.in_time() {
+                break;
+            }
+
+            let action_decision = match decide_actions(
+                enforced,
+                &policy_sets,
+                &actor,
+                &env_attributes,
+                &req.target_name,
+                &req.target_type,
+                &target_attributes,
+                actions,
+                &self.wasm,
+                &self.rates,
+                Some(&self.policy_timings),
+                &budget,
+            ) {
+                Decide::AllowWithApproval(required) if cosigned >= required as usize => {
+                    Decide::Allow
+                }
+                action_decision => action_decision,
+            };
+
+            let action_decision = match (action_decision, &elevated) {
+                (Decide::Allow, _) => Decide::Allow,
+                (_, Some(break_glass)) => {
+                    println!("!!! BREAK GLASS {} allowed {name}: {req}", break_glass.id);
+                    broken.push(break_glass);
+                    Decide::Allow
+                }
+                (own, None) => own,
+            };
+
+            // what no policy decides for the actor itself may have been lent to them; it is
+            // decided as the delegator, so they never get more than the delegator has, and a
+            // policy that denies the actor still does
+            let action_decision = match action_decision {
+                Decide::NotApplicable => match delegators.iter().find(|(delegation, delegator)| {
+                    delegation
+                        .actions
+                        .iter()
+                        .any(|a| a.eq_ignore_ascii_case(name))
+                        && decide_actions(
+                            enforced,
+                            &policy_sets,
+                            delegator,
+                            &env_attributes,
+                            &req.target_name,
+                            &req.target_type,
+                            &target_attributes,
+                            actions,
+                            &self.wasm,
+                            &self.rates,
+                            Some(&self.policy_timings),
+                            &budget,
+                        ) == Decide::Allow
+                }) {
+                    Some((delegation, _)) => {
+                        println!("Delegated decision: {delegation} allowed {name}: {req}");
+                        delegated.push(delegation);
+                        Decide::Allow
+                    }
+                    None => Decide::NotApplicable,
+                },
+                own => own,
+            };
+
+            if action_decision == Decide::Allow {
+                allowed.extend(actions);
+            }
+            decision = decision.strictest(action_decision.clone());
+
+            if per_action {
+                action_decisions.push(ActionDecision {
+                    action: name.clone(),
+                    decision: decided(action_decision),
+                });
+            } else if decision.denies() {
+                // no need to look at the rest
+                break;
+            }
+        }
+
+        // only the actions that are allowed get used
+        if !per_action && decision != Decide::Allow {
+            allowed.clear();
+        }
+
+        let over_limit = budget.exceeded();
+        let mut source = DecisionSource::Evaluated;
+        if let Some(ref err) = over_limit {
+            (decision, source) = match limits.fail_open {
+                true => (Decide::Allow, DecisionSource::FailedOpen),
+                false => (Decide::Deny, DecisionSource::FailedClosed),
+            };
+            if per_action {
+                action_decisions = each_action
+                    .iter()
+                    .map(|(name, _)| ActionDecision {
+                        action: name.clone(),
+                        decision: decided(decision.clone()),
+                    })
+                    .collect();
+            }
+            allowed.clear();
+
+            println!(
+                "Evaluation limit {}: {err}: {req}",
+                crate::proto::policies::Decide::from(decision.clone())
+            );
+            let data = json!({
+                "actor": {"name": actor.name, "typestr": actor.typestr},
+                "target": {"name": req.target_name, "typestr": req.target_type},
+                "action": req.target_action.join(","),
+                "decision": crate::proto::policies::Decide::from(decision.clone()).as_str_name(),
+                "limit": err,
+                "correlation_id": req.correlation_id,
+            });
+            self.notify(Event::EvaluationLimit, data).await;
+        }
+
+        if let Some(left) = locked_out {
+            (decision, source) = (Decide::Deny, DecisionSource::LockedOut);
+            if per_action {
+                action_decisions = each_action
+                    .iter()
+                    .map(|(name, _)| ActionDecision {
+                        action: name.clone(),
+                        decision: decided(decision.clone()),
+                    })
+                    .collect();
+            }
+            allowed.clear();
+            println!("Locked out for {left}s: {req}");
+        }
+
+        // actions the target doesn't have can't be allowed, whatever the others are
+        if partial.is_some() {
+            decision = decision.strictest(Decide::Indeterminate);
+            if source == DecisionSource::Evaluated {
+                source = DecisionSource::Unregistered;
+            }
+        }
+
+        // a bypassed policy is only worth telling about when it would have denied something
+        for break_glass in &bypassed {
+            let bypassed_policy = match policies.get(&break_glass.policy) {
+                Some(policy) => policy,
+                None => continue,
+            };
+            let denies = each_action
+                .iter()
+                .flat_map(|(_, actions)| actions)
+                .any(|action| {
+                    bypassed_policy.matches(
+                        &actor,
+                        &env_attributes,
+                        &req.target_name,
+                        &req.target_type,
+                        &target_attributes,
+                        action,
+                        &self.wasm,
+                        &self.rates,
+                    )
+                });
+            if denies {
+                println!(
+                    "!!! BREAK GLASS {} bypassed policy[{}]: {req}",
+                    break_glass.id, break_glass.policy
+                );
+                broken.push(break_glass);
+            }
+        }
+        self.record_rates(&policies, &actor, &allowed);
+
+        // shadow policies never affect the decision, but we log what they would have decided;
+        // a check over its limits has done enough work already
+        for (_, policy) in policies
+            .for_type(&req.target_type)
+            .filter(|(_, p)| p.mode == Mode::Shadow && over_limit.is_none())
+        {
+            for action in each_action.iter().flat_map(|(_, actions)| actions) {
+                if policy.matches(
+                    &actor,
+                    &env_attributes,
+                    &req.target_name,
+                    &req.target_type,
+                    &target_attributes,
+                    action,
+                    &self.wasm,
+                    &self.rates,
+                ) {
+                    println!(
+                        "Shadow policy[{}] would {} {}: {req}",
+                        policy.name,
+                        crate::proto::policies::Decide::from(policy.decision.clone()),
+                        action.name
+                    );
+                }
+            }
+        }
+
+        if let Some(ref risk) = risk {
+            if risk.score > 0 {
+                println!(
+                    "Risk {risk} {}: {req}",
+                    crate::proto::policies::Decide::from(decision.clone())
+                );
+            }
+        }
+
+        if decision.denies() {
+            let data = json!({
+                "actor": {"name": actor.name, "typestr": actor.typestr},
+                "target": {"name": req.target_name, "typestr": req.target_type},
+                "action": req.target_action.join(","),
+                "decision": crate::proto::policies::Decide::from(decision.clone()).as_str_name(),
+                "correlation_id": req.correlation_id,
+                "risk": risk.as_ref().map(|risk| risk.score),
+            });
+            self.notify(Event::DenyDecision, data).await;
+        }
+
+        // denies while locked out don't count toward another streak
+        let streak = match locked_out {
+            Some(_) => None,
+            None => self.deny_streaks.record(&actor, decision.denies()),
+        };
+        if let Some(streak) = streak {
+            match streak.locked_until {
+                Some(until) => println!(
+                    "Deny streak of {}, locked out until {until}: {req}",
+                    streak.denies
+                ),
+                None => println!("Deny streak of {}: {req}", streak.denies),
+            }
+            let data = json!({
+                "actor": {"name": actor.name, "typestr": actor.typestr},
+                "target": {"name": req.target_name, "typestr": req.target_type},
+                "action": req.target_action.join(","),
+                "denies": streak.denies,
+                "locked_until": streak.locked_until,
+                "correlation_id": req.correlation_id,
+            });
+            self.notify(Event::DenyStreak, data).await;
+        }
+
+        // every decision a delegation took part in is audited, whatever the decision on the check
+        delegated.sort_by(|a, b| a.name.cmp(&b.name));
+        delegated.dedup_by(|a, b| a.name == b.name);
+        let mut cache_ttl = self.config.decision_ttl;
+        if !delegated.is_empty() {
+            let data = json!({
+                "actor": {"name": actor.name, "typestr": actor.typestr},
+                "target": {"name": req.target_name, "typestr": req.target_type},
+                "action": req.target_action.join(","),
+                "decision": crate::proto::policies::Decide::from(decision.clone()).as_str_name(),
+                "delegations": delegated.iter().map(|delegation| json!({
+                    "name": delegation.name,
+                    "delegator": {
+                        "name": delegation.delegator_name,
+                        "typestr": delegation.delegator_type,
+                    },
+                    "expires_at": delegation.expires_at,
+                })).collect::<Vec<_>>(),
+                "correlation_id": req.correlation_id,
+            });
+            self.notify(Event::DelegatedDecision, data).await;
+
+            // a cached decision shouldn't outlive the delegations it relied on
+            let now = now();
+            for delegation in &delegated {
+                let left = delegation.expires_at.saturating_sub(now);
+                cache_ttl = cache_ttl.min(u32::try_from(left).unwrap_or(u32::MAX));
+            }
+        }
+
+        // a decision made without evaluating the policies shouldn't be cached at all
+        if over_limit.is_some() {
+            cache_ttl = 0;
+        }
+
+        // a lockout only lasts so long, and so does a decision it made
+        if let Some(left) = locked_out {
+            cache_ttl = cache_ttl.min(u32::try_from(left).unwrap_or(u32::MAX));
+        }
+
+        // nor should it outlive the emergency access it relied on
+        broken.sort_by_key(|break_glass| break_glass.id);
+        broken.dedup_by_key(|break_glass| break_glass.id);
+        let now = now();
+        for break_glass in &broken {
+            let left = break_glass.expires_at.saturating_sub(now);
+            cache_ttl = cache_ttl.min(u32::try_from(left).unwrap_or(u32::MAX));
+        }
+
+        // nor should one that named actions that may yet be registered
+        if partial.is_some() {
+            cache_ttl = 0;
+        }
+
+        // a check that needs approval waits for it, asking for it the first time
+        let mut approval_id = 0;
+        if let Decide::AllowWithApproval(required) = decision {
+            approval_id = match approval {
+                Some(approval) => approval.id,
+                None => match self.request_approval(&req, &actor, required).await {
+                    Ok(id) => id,
+                    Err(status) => {
+                        let _ = tx.send(DsResponse::Error(status));
+                        return;
+                    }
+                },
+            };
+            cache_ttl = 0;
+        }
+
+        if let Some(ref sampler) = self.sampler {
+            if sampler.sample() {
+                sampler.write(json!({
+                    "correlation_id": req.correlation_id,
+                    "actor": {
+                        "typestr": actor.typestr,
+                        "name": actor.name,
+                        "attributes": sampling::attributes_json(&actor.attributes),
+                    },
+                    "target": {
+                        "typestr": req.target_type,
+                        "name": req.target_name,
+                        "attributes": sampling::attributes_json(&target_attributes),
+                    },
+                    "env": sampling::attributes_json(&env_attributes),
+                    "actions": req.target_action,
+                    "decision": crate::proto::policies::Decide::from(decision.clone()).as_str_name(),
+                    "source": source.as_str_name(),
+                    "action_decisions": action_decisions.iter().map(|action| json!({
+                        "action": action.action,
+                        "decision": action.decision().as_str_name(),
+                    })).collect::<Vec<_>>(),
+                    "risk": risk.as_ref().map(|risk| risk.score),
+                }));
+            }
+        }
+
+        // the unknown actions go back in among the evaluated ones, in the order they were asked for
+        if let Some((requested, known)) = partial {
+            let mut evaluated = action_decisions.into_iter();
+            action_decisions = requested
+                .into_iter()
+                .zip(known)
+                .filter_map(|(action, known)| match known {
+                    true => evaluated.next(),
+                    false => Some(ActionDecision {
+                        action,
+                        decision: decided(Decide::Indeterminate),
+                    }),
+                })
+                .collect();
+        }
+
+        let _ = tx.send(DsResponse::CheckResult(CheckResponse {
+            decision: decided(decision),
+            action_decisions,
+            cache_ttl,
+            correlation_id: req.correlation_id,
+            approval_id,
+            delegations: delegated
+                .iter()
+                .map(|delegation| delegation.name.clone())
+                .collect(),
+            break_glasses: broken.iter().map(|break_glass| break_glass.id).collect(),
+            source: source.into(),
+        }));
+    }
+
+    /// The delegations that may lend the actor an action on the target being checked, with
+    /// their delegators as checks see them
+    async fn delegators(
+        &self,
+        req: &CheckRequest,
+        actor: &RegisteredActor,
+        each_action: &[(String, Vec<TargetAction>)],
+        budget: &EvalBudget,
+    ) -> Vec<(RegisteredDelegation, RegisteredActor)> {
+        let now = now();
+        let delegations: Vec<RegisteredDelegation> = self
+            .delegations
+            .read()
+            .await
+            .values()
+            .filter(|delegation| {
+                each_action.iter().any(|(name, _)| {
+                    delegation.covers(actor, &req.target_type, &req.target_name, name, now)
+                })
+            })
+            .cloned()
+            .collect();
+
+        let mut delegators = Vec::new();
+        for delegation in delegations {
+            let delegator = self.extend_actor(delegation.delegator(), budget).await;
+            delegators.push((delegation, delegator));
+        }
+        delegators
+    }
+
+    /// The approval a check names, if it is for the same check and can still be used
+    async fn find_approval(&self, req: &CheckRequest, actor: &RegisteredActor) -> Option<Approval> {
+        if req.approval_id == 0 {
+            return None;
+        }
+
+        let approvals = self.approvals.read().await;
+        let approval = approvals.get(&req.approval_id)?;
+        let same = approval.actor_type.eq_ignore_ascii_case(&actor.typestr)
+            && approval.actor_name.eq_ignore_ascii_case(&actor.name)
+            && approval.target_type.eq_ignore_ascii_case(&req.target_type)
+            && approval.target_name.eq_ignore_ascii_case(&req.target_name)
+            && approval.actions == approval_actions(req);
+
+        match same && approval.expires_at > now() {
+            true => Some(approval.clone()),
+            false => None,
+        }
+    }
+
+    /// Ask for approval of a check, returning the id of the approval
+    async fn request_approval(
+        &self,
+        req: &CheckRequest,
+        actor: &RegisteredActor,
+        required: u32,
+    ) -> Result<u64, Status> {
+        let mut approvals = self.approvals.write().await;
+        let now = now();
+        approvals.retain(|_, approval| approval.expires_at > now);
+        if approvals.len() >= MAX_APPROVALS {
+            return Err(Status::resource_exhausted(
+                "Too many checks are waiting for approval",
+            ));
+        }
+
+        let approval = Approval {
+            id: self.next_approval.fetch_add(1, Ordering::SeqCst) + 1,
+            actor_type: actor.typestr.to_string(),
+            actor_name: actor.name.clone(),
+            target_name: req.target_name.clone(),
+            target_type: req.target_type.clone(),
+            actions: approval_actions(req),
+            required,
+            approvers: vec![],
+            created_at: now,
+            approved_at: 0,
+            expires_at: now + self.approval_ttl(),
+        };
+        println!(
+            "Approval {} asks for {required} approvers: {req}",
+            approval.id
+        );
+
+        let id = approval.id;
+        approvals.insert(id, approval);
+        Ok(id)
+    }
+
+    /// How long checks wait for approval, and are allowed for once approved, in seconds
+    fn approval_ttl(&self) -> u64 {
+        match self.config.approval_ttl {
+            0 => DEFAULT_APPROVAL_TTL,
+            ttl => u64::from(ttl),
+        }
+    }
+
+    /// Co-sign an approval; once enough approvers have, checks that name it are allowed
+    ///
+    /// Nobody can approve their own check, or approve the same check twice.
+    async fn approve(&self, id: u64, approver: String, tx: Sender<DsResponse>) {
+        let mut approvals = self.approvals.write().await;
+        let now = now();
+
+        let approval = match approvals.get_mut(&id) {
+            Some(approval) if approval.expires_at > now => approval,
+            _ => {
+                let _ = tx.send(DsResponse::Error(Status::not_found(format!(
+                    "Approval {id} not found"
+                ))));
+                return;
+            }
+        };
+        if approval.actor_name.eq_ignore_ascii_case(&approver) {
+            let _ = tx.send(DsResponse::Error(Status::permission_denied(
+                "A check must be approved by someone other than its actor",
+            )));
+            return;
+        }
+        if approval.approvers.contains(&approver) {
+            let _ = tx.send(DsResponse::Error(Status::already_exists(format!(
+                "{approver} already approved {id}"
+            ))));
+            return;
+        }
+
+        approval.approvers.push(approver);
+        if approval.approved_at == 0 && approval.approvers.len() >= approval.required as usize {
+            approval.approved_at = now;
+            approval.expires_at = now + self.approval_ttl();
+        }
+
+        let _ = tx.send(DsResponse::SingleApproval(Box::new(approval.clone())));
+    }
+
+    /// Get the approvals that are pending or can still be used, oldest first
+    async fn get_approvals(&self, tx: Sender<DsResponse>) {
+        let now = now();
+        let approvals = self
+            .approvals
+            .read()
+            .await
+            .values()
+            .filter(|approval| approval.expires_at > now)
+            .cloned()
+            .collect();
+
+        let _ = tx.send(DsResponse::MultipleApprovals(approvals));
+    }
+
+    /// Grant emergency access: allow an actor everything, or bypass a deny policy, for some
+    /// minutes
+    ///
+    /// Only members of a group granted the break glass role can, named by their type and name as
+    /// `type/name`, and every break glass is announced to the webhooks when it starts and when it
+    /// ends.
+    async fn break_glass(&self, req: BreakGlassRequest, caller: String, tx: Sender<DsResponse>) {
+        let role = match self.config.break_glass_role {
+            Some(ref role) => role,
+            None => {
+                let _ = tx.send(DsResponse::Error(Status::failed_precondition(
+                    "Break glass is not configured",
+                )));
+                return;
+            }
+        };
+        let Some((typestr, name)) = caller.split_once('/') else {
+            let _ = tx.send(DsResponse::Error(Status::permission_denied(
+                "Breaking the glass needs a caller named as type/name",
+            )));
+            return;
+        };
+        // the caller gets the role the way an actor in a check would
+        let caller_actor = self
+            .expand_groups_and_roles(
+                RegisteredActor::new(name, typestr, AttributeMap::default()),
+                &EvalBudget::unlimited(),
+            )
+            .await;
+        let may = caller_actor
+            .attributes
+            .get(attribute::HAS_ROLE)
+            .is_some_and(|roles| roles.iter().any(|r| r.eq_ignore_ascii_case(role)));
+        if !may {
+            let _ = tx.send(DsResponse::Error(Status::permission_denied(format!(
+                "Only members of the {role} role can break the glass"
+            ))));
+            return;
+        }
+
+        if let Err(err) = validate_break_glass(&req) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(err)));
+            return;
+        }
+        let policy = req.policy.to_ascii_lowercase();
+        if !policy.is_empty() {
+            match self.policies.read().await.get(&policy) {
+                Some(rule) if rule.decision == Decide::Deny => (),
+                Some(_) => {
+                    let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                        "Only a deny policy can be bypassed",
+                    )));
+                    return;
+                }
+                None => {
+                    let _ = tx.send(DsResponse::Error(Status::not_found(
+                        "Policy does not exist",
+                    )));
+                    return;
+                }
+            }
+        }
+
+        if self.break_glasses.read().await.len() >= MAX_BREAK_GLASSES {
+            let _ = tx.send(DsResponse::Error(Status::resource_exhausted(
+                "Too much emergency access is in use",
+            )));
+            return;
+        }
+
+        let now = now();
+        let break_glass = BreakGlass {
+            id: self.next_break_glass.fetch_add(1, Ordering::SeqCst) + 1,
+            caller,
+            reason: req.reason,
+            actor_type: req.actor_type.to_ascii_lowercase(),
+            actor_name: req.actor_name.to_ascii_lowercase(),
+            target_type: req.target_type,
+            policy,
+            created_at: now,
+            expires_at: now + u64::from(req.minutes) * 60,
+        };
+
+        let txn = vec![BackendUpdate::PutBreakGlass(break_glass.clone().into())];
+        if let Err(err) = self.storage.persist_changes(&txn).await {
+            let _ = tx.send(DsResponse::Error(Status::internal(err)));
+            return;
+        }
+        for update in txn {
+            self.update(update).await;
+        }
+
+        println!(
+            "!!! BREAK GLASS {} by {} for {} minutes: {}: {}",
+            break_glass.id,
+            break_glass.caller,
+            req.minutes,
+            break_glass_what(&break_glass),
+            break_glass.reason
+        );
+        self.notify(Event::BreakGlass, break_glass_data("started", &break_glass))
+            .await;
+
+        let _ = tx.send(DsResponse::SingleBreakGlass(Box::new(break_glass)));
+    }
+
+    /// Get the emergency access that hasn't ended, oldest first
+    async fn get_break_glass(&self, tx: Sender<DsResponse>) {
+        let now = now();
+        let break_glasses = self
+            .break_glasses
+            .read()
+            .await
+            .values()
+            .filter(|break_glass| break_glass.expires_at > now)
+            .cloned()
+            .collect();
+
+        let _ = tx.send(DsResponse::MultipleBreakGlasses(break_glasses));
+    }
+
+    /// End the emergency access that has run out, announcing each; checks already ignore it
+    ///
+    /// Standbys leave this to the leader, whose removals reach them like any other change. One
+    /// that can't be removed from the backend is tried again next time.
+    async fn end_break_glasses(&self) {
+        if self.storage.leadership().borrow().role == ServingRole::Standby {
+            return;
+        }
+        let now = now();
+        let ended: Vec<BreakGlass> = self
+            .break_glasses
+            .read()
+            .await
+            .values()
+            .filter(|break_glass| break_glass.expires_at <= now)
+            .cloned()
+            .collect();
+
+        for break_glass in ended {
+            let txn = vec![BackendUpdate::DeleteBreakGlass(break_glass.id.to_string())];
+            if let Err(err) = self.storage.persist_changes(&txn).await {
+                eprintln!("Could not end break glass {}: {err}", break_glass.id);
+                continue;
+            }
+            for update in txn {
+                self.update(update).await;
+            }
+
+            println!(
+                "!!! BREAK GLASS {} ended: {}",
+                break_glass.id,
+                break_glass_what(&break_glass)
+            );
+            self.notify(Event::BreakGlass, break_glass_data("ended", &break_glass))
+                .await;
+        }
+    }
+
+    /// The emergency access that applies to a check: a break glass allowing the actor
+    /// everything, if any, and those bypassing policies that can apply to the target
+    async fn break_glass_for(
+        &self,
+        req: &CheckRequest,
+        actor: &RegisteredActor,
+        policies: &PolicyStore,
+    ) -> (Option<BreakGlass>, Vec<BreakGlass>) {
+        let now = now();
+        let break_glasses = self.break_glasses.read().await;
+        let active = break_glasses
+            .values()
+            .filter(|break_glass| break_glass.expires_at > now);
+
+        let mut elevated = None;
+        let mut bypassed = Vec::new();
+        for break_glass in active {
+            if break_glass.policy.is_empty() {
+                if elevated.is_none()
+                    && break_glass.actor_type.eq_ignore_ascii_case(&actor.typestr)
+                    && break_glass.actor_name.eq_ignore_ascii_case(&actor.name)
+                    && (break_glass.target_type.is_empty()
+                        || break_glass
+                            .target_type
+                            .eq_ignore_ascii_case(&req.target_type))
+                {
+                    elevated = Some(break_glass.clone());
+                }
+            } else if policies
+                .for_type(&req.target_type)
+                .any(|(name, _)| *name == break_glass.policy)
+            {
+                bypassed.push(break_glass.clone());
+            }
+        }
+        (elevated, bypassed)
+    }
+
+    /// Perform a check, tracing every policy rule
+    ///
+    /// Unlike a normal check, we do not stop at the first DENY. Every policy is evaluated and
+    /// every sub-check is recorded so rule interactions can be debugged. The policies are
+    /// returned sorted by name.
+    async fn trace_check(&self, req: CheckRequest, tx: Sender<DsResponse>) {
+        if let Err(err) = self.check_strict(&req).await {
+            let _ = tx.send(DsResponse::Error(Status::failed_precondition(err)));
+            return;
+        }
+
+        let (actor, mut env_attributes, target_attributes) =
+            self.prepare_check(&req, &EvalBudget::unlimited()).await;
+        self.score_risk(&req, &actor, &mut env_attributes, false);
+        let actions = self.resolve_actions(&req).await;
+
+        let policies = self.policies.read().await;
+
+        // when the action stands for several actions, each policy is traced once per action
+        let mut traces = Vec::new();
+        for action in &actions {
+            for policy in policies.values() {
+                traces.push(policy.trace(
+                    &actor,
+                    &env_attributes,
+                    &req.target_name,
+                    &req.target_type,
+                    &target_attributes,
+                    action,
+                    &self.wasm,
+                    &self.rates,
+                ));
+            }
+        }
+        traces.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // the decision takes policy sets into account just like a normal check
+        let decision = decide_actions(
+            &policies,
+            &*self.policy_sets.read().await,
+            &actor,
+            &env_attributes,
+            &req.target_name,
+            &req.target_type,
+            &target_attributes,
+            &actions,
+            &self.wasm,
+            &self.rates,
+            None,
+            &EvalBudget::unlimited(),
+        );
+
+        let _ = tx.send(DsResponse::TraceResult(TraceCheckResponse {
+            decision: crate::proto::policies::Decide::from(decision).into(),
+            policies: traces,
+        }));
+    }
+
+    /// Replay the recorded check requests against the current policies
+    ///
+    /// Requests are replayed with the actor attributes seen when they were recorded, but against
+    /// the current policies and target attributes. Because actor names are anonymized, rules
+    /// that check the actor's name or bucket will not match as they did originally.
+    async fn coverage_report(&self, _req: CoverageReportRequest, tx: Sender<DsResponse>) {
+        let recorded_checks = self.recorded_checks.read().await;
+        let policies = self.policies.read().await;
+
+        let mut unmatched: HashSet<&String> = policies.keys().collect();
+        let mut default_decisions = Vec::new();
+        for req in recorded_checks.iter() {
+            let (actor, env_attributes, target_attributes) = self.prepare_replay(req).await;
+            let actions = self.resolve_actions(req).await;
+
+            let mut matched = false;
+            for (name, policy) in policies.iter() {
+                for action in &actions {
+                    if policy.matches(
+                        &actor,
+                        &env_attributes,
+                        &req.target_name,
+                        &req.target_type,
+                        &target_attributes,
+                        action,
+                        &self.wasm,
+                        &self.rates,
+                    ) {
+                        matched = matched || policy.mode == Mode::Enforce;
+                        unmatched.remove(name);
+                    }
+                }
+            }
+
+            if !matched {
+                default_decisions.push(req.clone());
+            }
+        }
+
+        let mut unmatched_policies: Vec<String> = unmatched.into_iter().cloned().collect();
+        unmatched_policies.sort();
+
+        let _ = tx.send(DsResponse::CoverageResult(CoverageReportResponse {
+            requests: recorded_checks.len() as u32,
+            unmatched_policies,
+            default_decisions,
+        }));
+    }
+
+    /// Compare the decisions of a candidate policy set against the current policies
+    ///
+    /// The sample requests in the request are checked as they would be normally. If no samples
+    /// are given, the recorded check requests are replayed instead (see `coverage_report`).
+    async fn what_if(&self, req: WhatIfRequest, tx: Sender<DsResponse>) {
+        let candidates: PolicyStore = req
+            .policies
+            .into_iter()
+            .map(RegisteredPolicyRule::from)
+            .map(|rule| (rule.name.to_ascii_lowercase(), rule))
+            .collect();
+
+        let (samples, recorded) = if req.requests.is_empty() {
+            (
+                self.recorded_checks.read().await.iter().cloned().collect(),
+                true,
+            )
+        } else {
+            (req.requests, false)
+        };
+
+        if samples.iter().any(|s| s.actor.is_none()) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "Actor cannot be null",
+            )));
+            return;
+        }
+
+        let mut changes = Vec::new();
+        for sample in &samples {
+            let (actor, env_attributes, target_attributes) = if recorded {
+                self.prepare_replay(sample).await
+            } else {
+                self.prepare_check(sample, &EvalBudget::unlimited()).await
+            };
+
+            let actions = self.resolve_actions(sample).await;
+
+            let policy_sets = self.policy_sets.read().await;
+            let current = decide_actions(
+                &*self.policies.read().await,
+                &policy_sets,
+                &actor,
+                &env_attributes,
+                &sample.target_name,
+                &sample.target_type,
+                &target_attributes,
+                &actions,
+                &self.wasm,
+                &self.rates,
+                None,
+                &EvalBudget::unlimited(),
+            );
+            let candidate = decide_actions(
+                &candidates,
+                &policy_sets,
+                &actor,
+                &env_attributes,
+                &sample.target_name,
+                &sample.target_type,
+                &target_attributes,
+                &actions,
+                &self.wasm,
+                &self.rates,
+                None,
+                &EvalBudget::unlimited(),
+            );
+
+            if current != candidate {
+                changes.push(DecisionChange {
+                    request: Some(sample.clone()),
+                    current: crate::proto::policies::Decide::from(current).into(),
+                    candidate: crate::proto::policies::Decide::from(candidate).into(),
+                });
+            }
+        }
+
+        let _ = tx.send(DsResponse::WhatIfResult(WhatIfResponse {
+            requests: samples.len() as u32,
+            changes,
+        }));
+    }
+
+    /// Run policy test cases against the current policies, or the given ones instead
+    ///
+    /// Each case is checked as it would be normally, except that a target given by the case
+    /// brings its own attributes.
+    async fn test_policies(&self, req: TestPoliciesRequest, tx: Sender<DsResponse>) {
+        if req.cases.iter().any(|case| {
+            case.check
+                .as_ref()
+                .is_none_or(|check| check.actor.is_none())
+        }) {
+            let _ = tx.send(DsResponse::Error(Status::invalid_argument(
+                "Actor cannot be null",
+            )));
+            return;
+        }
+
+        let given: Option<PolicyStore> = match req.policies.is_empty() {
+            true => None,
+            false => Some(
+                req.policies
+                    .into_iter()
+                    .map(RegisteredPolicyRule::from)
+                    .map(|rule| (rule.name.to_ascii_lowercase(), rule))
+                    .collect(),
+            ),
+        };
+
+        let mut results = Vec::new();
+        for case in req.cases {
+            let expected = case.expect();
+            let check = case.check.unwrap();
+
+            let (actor, env_attributes, mut target_attributes) =
+                self.prepare_check(&check, &EvalBudget::unlimited()).await;
+            if let Some(target) = case.target {
+                target_attributes = RegisteredTarget::from(target).attributes;
+            }
+            let actions = self.resolve_actions(&check).await;
+
+            let current = self.policies.read().await;
+            let actual = decide_actions(
+                given.as_ref().unwrap_or(&current),
+                &*self.policy_sets.read().await,
+                &actor,
+                &env_attributes,
+                &check.target_name,
+                &check.target_type,
+                &target_attributes,
+                &actions,
+                &self.wasm,
+                &self.rates,
+                None,
+                &EvalBudget::unlimited(),
+            );
+            let actual = crate::proto::policies::Decide::from(actual);
+
+            // a check nothing applies to is denied as well
+            let passed = actual == expected
+                || (expected == crate::proto::policies::Decide::Deny
+                    && actual == crate::proto::policies::Decide::NotApplicable);
+            results.push(PolicyTestResult {
+                name: case.name,
+                passed,
+                expected: expected.into(),
+                actual: actual.into(),
+            });
+        }
+
+        let passed = results.iter().filter(|result| result.passed).count() as u32;
+        let _ = tx.send(DsResponse::PolicyTestResults(TestPoliciesResponse {
+            failed: results.len() as u32 - passed,
+            passed,
+            results,
+        }));
+    }
+
+    /// Get statistics about the server
+    async fn get_server_stats(&self, _req: GetServerStatsRequest, tx: Sender<DsResponse>) {
+        let count = |len: usize| len as u64;
+        let (rate_counters, rate_events) = self.rates.stats();
+        let (deny_streaks, locked_out_actors) = self.deny_streaks.stats();
+
+        let stats = GetServerStatsResponse {
+            startup_mode: self.config.startup_mode.into(),
+            startup_issues: self.startup_issues.read().await.clone(),
+            targets: count(self.targets.read().await.values().map(HashMap::len).sum()),
+            actors: count(self.actors.read().await.values().map(HashMap::len).sum()),
+            roles: count(self.roles.read().await.len()),
+            groups: count(self.groups.read().await.len()),
+            policies: count(self.policies.read().await.len()),
+            webhooks: count(self.webhooks.read().await.len()),
+            checks: self.usage.checks(),
+            check_sample_rate: self.usage.sample_rate(),
+            tracked_entities: count(self.usage.read().await.len()),
+            rate_counters,
+            rate_events,
+            deny_streaks,
+            locked_out_actors,
+            drift: count(self.drift.read().await.len()),
+            drift_repairs: self.drift_repairs.load(Ordering::Relaxed),
+        };
+
+        let _ = tx.send(DsResponse::ServerStats(stats));
+    }
+
+    /// Get the version of the server, how it was built, and what it holds
+    async fn get_server_info(&self, tx: Sender<DsResponse>) {
+        let count = |len: usize| len as u64;
+        let mut features = Vec::new();
+        if cfg!(feature = "wasm") {
+            features.push(String::from("wasm"));
+        }
+
+        let info = GetServerInfoResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_hash: option_env!("GATEHOUSE_GIT_HASH")
+                .unwrap_or_default()
+                .to_string(),
+            storage: self.backend.kind().to_string(),
+            targets: count(self.targets.read().await.values().map(HashMap::len).sum()),
+            actors: count(self.actors.read().await.values().map(HashMap::len).sum()),
+            roles: count(self.roles.read().await.len()),
+            groups: count(self.groups.read().await.len()),
+            policies: count(self.policies.read().await.len()),
+            policy_sets: count(self.policy_sets.read().await.len()),
+            delegations: count(self.delegations.read().await.len()),
+            webhooks: count(self.webhooks.read().await.len()),
+            uptime_secs: now().saturating_sub(self.started),
+            features,
+        };
+
+        let _ = tx.send(DsResponse::ServerInfo(info));
+    }
+
+    /// Get the policies that take longest to evaluate in checks
+    async fn get_policy_stats(&self, req: GetPolicyStatsRequest, tx: Sender<DsResponse>) {
+        let top = match req.top {
+            0 => latency::DEFAULT_TOP,
+            top => top as usize,
+        };
+        let policies = self.policies.read().await;
+        let stats = GetPolicyStatsResponse {
+            policies: self
+                .policy_timings
+                .slowest(top, |name| policies.contains_key(name)),
+            budget_micros: self.policy_timings.budget_micros(),
+        };
+
+        let _ = tx.send(DsResponse::PolicyStats(stats));
+    }
+
+    /// Save what was upgraded from an older schema back in the current one, or in dry-run mode
+    /// only report it
+    ///
+    /// Upgrades aren't problems with the data, so they are saved whatever the startup mode is.
+    async fn migrate_upgraded(&self, upgraded: Vec<Upgraded>, issues: &mut Vec<StartupIssue>) {
+        let mut migrations = Vec::new();
+        for upgraded in upgraded {
+            let kind = match upgraded.kind.as_str() {
+                "policysets" => "policy sets",
+                "apikeys" => "API keys",
+                kind => kind,
+            };
+            let issue = StartupIssue {
+                kind: kind.to_string(),
+                name: upgraded.key.clone(),
+                problem: format!("Stored in schema version {}", upgraded.from),
+                action: String::from("would migrate"),
+            };
+            // anything no longer loaded is left as it was stored
+            if let Some(update) = self.stored(&upgraded.kind, &upgraded.key).await {
+                migrations.push((issue, update));
+            }
+        }
+        if migrations.is_empty() {
+            return;
+        }
+
+        let (mut migrated, txn): (Vec<StartupIssue>, Vec<BackendUpdate>) =
+            migrations.into_iter().unzip();
+        if self.config.migrations == MigrationMode::Apply {
+            let action = match self.storage.persist_changes(&txn).await {
+                Ok(_) => "migrated",
+                Err(err) => {
+                    eprintln!("Could not save migrations: {err}");
+                    "migrated in memory"
+                }
+            };
+            for issue in &mut migrated {
+                issue.action = action.to_string();
+            }
+        }
+        issues.extend(migrated);
+    }
+
+    /// What would save a loaded entity as it is, by the kind and key its backend stores it under
+    async fn stored(&self, kind: &str, key: &str) -> Option<BackendUpdate> {
+        let typed = || key.split_once('/');
+        let update = match kind {
+            "targets" => {
+                let (typestr, name) = typed()?;
+                let targets = self.targets.read().await;
+                BackendUpdate::PutTarget(targets.get(typestr)?.get(name)?.clone())
+            }
+            "actors" => {
+                let (typestr, name) = typed()?;
+                let actors = self.actors.read().await;
+                BackendUpdate::PutActor(actors.get(typestr)?.get(name)?.clone())
+            }
+            "roles" => BackendUpdate::PutRole(self.roles.read().await.get(key)?.clone()),
+            "groups" => BackendUpdate::PutGroup(self.groups.read().await.get(key)?.clone()),
+            "policies" => {
+                BackendUpdate::PutPolicyRule(Box::new(self.policies.read().await.get(key)?.clone()))
+            }
+            "policysets" => {
+                BackendUpdate::PutPolicySet(self.policy_sets.read().await.get(key)?.clone())
+            }
+            "webhooks" => BackendUpdate::PutWebhook(self.webhooks.read().await.get(key)?.clone()),
+            "apikeys" => BackendUpdate::PutApiKey(self.api_keys.read().await.get(key)?.clone()),
+            "proposals" => {
+                let proposals = self.proposals.read().await;
+                BackendUpdate::PutProposal(proposals.get(&key.parse().ok()?)?.into())
+            }
+            "breakglasses" => {
+                let break_glasses = self.break_glasses.read().await;
+                BackendUpdate::PutBreakGlass(break_glasses.get(&key.parse().ok()?)?.clone().into())
+            }
+            "delegations" => {
+                BackendUpdate::PutDelegation(self.delegations.read().await.get(key)?.clone())
+            }
+            _ => return None,
+        };
+        Some(update)
+    }
+
+    /// Deal with problems in the stored data as the startup mode says, and remember them
+    ///
+    /// Strict mode refuses to start. Otherwise records we can't use are left out of memory, and
+    /// records that refer to things that don't exist are reported or, in repair mode, corrected
+    /// in storage too.
+    async fn resolve_startup_issues(&self, mut issues: Vec<StartupIssue>) {
+        let found = self.find_startup_issues().await;
+        let mode = self.config.startup_mode;
+
+        if mode == StartupMode::Strict && !found.is_empty() {
+            for (issue, _) in &found {
+                eprintln!("{} {}: {}", issue.kind, issue.name, issue.problem);
+            }
+            eprintln!("Refusing to start with bad data; set GATESTARTUPMODE to lenient or repair");
+            std::process::exit(1);
+        }
+
+        let mut repairs = Vec::new();
+        for (mut issue, fix) in found {
+            match fix {
+                StartupFix::Skip(update) => {
+                    issue.action = String::from("skipped");
+                    self.update(update).await;
+                    issues.push(issue);
+                }
+                StartupFix::Repair(update) if mode == StartupMode::Repair => {
+                    repairs.push((issue, update));
+                }
+                StartupFix::Repair(_) => {
+                    issue.action = String::from("reported");
+                    issues.push(issue);
+                }
+            }
+        }
+
+        if !repairs.is_empty() {
+            let (repaired, txn): (Vec<StartupIssue>, Vec<BackendUpdate>) =
+                repairs.into_iter().unzip();
+            let action = match self.storage.persist_changes(&txn).await {
+                Ok(_) => {
+                    for update in txn {
+                        self.update(update).await;
+                    }
+                    "repaired"
+                }
+                Err(err) => {
+                    eprintln!("Could not save repairs: {err}");
+                    "reported"
+                }
+            };
+            for mut issue in repaired {
+                issue.action = action.to_string();
+                issues.push(issue);
+            }
+        }
+
+        for issue in &issues {
+            println!(
+                "Startup: {} {} {}: {}",
+                issue.action, issue.kind, issue.name, issue.problem
+            );
+        }
+        *self.startup_issues.write().await = issues;
+    }
+
+    /// Look for stored data we can't use or that refers to things that don't exist, along with
+    /// what would fix it
+    async fn find_startup_issues(&self) -> Vec<(StartupIssue, StartupFix)> {
+        let issue = |kind: &str, name: &str, problem: String| StartupIssue {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            problem,
+            action: String::new(),
+        };
+        let mut found = Vec::new();
+
+        for target in self.targets.read().await.values().flat_map(HashMap::values) {
+            if let Err(err) = check_action_groups(target.action_groups.keys()) {
+                let mut fixed = target.clone();
+                fixed.action_groups.remove("*");
+                let name = format!("{}/{}", target.typestr, target.name);
+                found.push((
+                    issue("targets", &name, err),
+                    StartupFix::Repair(BackendUpdate::PutTarget(fixed)),
+                ));
+            }
+        }
+
+        // a policy we can't evaluate properly is left out rather than guessed at
+        for policy in self.policies.read().await.values() {
+            if let Err(err) = self.check_wasm_module(policy) {
+                found.push((
+                    issue("policies", &policy.name, err),
+                    StartupFix::Skip(BackendUpdate::DeletePolicyRule(policy.name.clone())),
+                ));
+            }
+        }
+
+        let roles = self.roles.read().await;
+        let groups = self.groups.read().await;
+        for group in groups.values() {
+            let mut missing: Vec<&str> = group
+                .roles
+                .iter()
+                .filter(|role| !roles.contains_key(*role))
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                missing.sort_unstable();
+                let mut fixed = group.clone();
+                fixed.roles.retain(|role| roles.contains_key(role));
+                found.push((
+                    issue(
+                        "groups",
+                        &group.name,
+                        format!("Grants missing roles: {}", missing.join(", ")),
+                    ),
+                    StartupFix::Repair(BackendUpdate::PutGroup(fixed)),
+                ));
+            }
+        }
+        for role in roles.values() {
+            let mut missing: Vec<&str> = role
+                .groups
+                .iter()
+                .filter(|group| !groups.contains_key(*group))
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                missing.sort_unstable();
+                let mut fixed = role.clone();
+                fixed.groups.retain(|group| groups.contains_key(group));
+                found.push((
+                    issue(
+                        "roles",
+                        &role.name,
+                        format!("Granted by missing groups: {}", missing.join(", ")),
+                    ),
+                    StartupFix::Repair(BackendUpdate::PutRole(fixed)),
+                ));
+            }
+        }
+
+        found
+    }
+
+    /// Repair drift between roles and the groups that grant them
+    ///
+    /// Groups are the authority: a role's groups are made those that grant it, and roles that are
+    /// gone are dropped from groups. Drift is only repaired once it is seen twice in a row, so
+    /// changes still being applied aren't mistaken for it. Standbys leave repairs to the leader.
+    async fn repair_drift(&self, tx: Sender<DsResponse>) {
+        let found = self.find_drift().await;
+        let mut drift: HashSet<String> = describe_updates(&found).into_iter().collect();
+        let standby = self.storage.leadership().borrow().role == ServingRole::Standby;
+
+        let mut previous = self.drift.write().await;
+        let txn: Vec<BackendUpdate> = match standby {
+            true => Vec::new(),
+            false => found
+                .into_iter()
+                .filter(|update| {
+                    describe_change(update)
+                        .map(|(op, kind, name)| previous.contains(&format!("{op} {kind} {name}")))
+                        .unwrap_or(false)
+                })
+                .collect(),
+        };
+        if txn.is_empty() {
+            *previous = drift;
+            let _ = tx.send(DsResponse::DriftRepaired(Vec::new()));
+            return;
+        }
+
+        let repaired = describe_updates(&txn);
+        match self.storage.persist_changes(&txn).await {
+            Ok(_) => {
+                self.notify_changes(&txn).await;
+                for update in txn {
+                    self.update(update).await;
+                }
+                for update in &repaired {
+                    drift.remove(update);
+                }
+                *previous = drift;
+                self.drift_repairs
+                    .fetch_add(repaired.len() as u64, Ordering::Relaxed);
+                let _ = tx.send(DsResponse::DriftRepaired(repaired));
+            }
+            Err(err) => {
+                *previous = drift;
+                drop(previous);
+                let _ = tx.send(DsResponse::Error(self.partial_failure(txn, err).await));
+            }
+        }
+    }
+
+    /// The updates that would bring roles and the groups that grant them back in step
+    async fn find_drift(&self) -> Vec<BackendUpdate> {
+        let roles = self.roles.read().await;
+        let groups = self.groups.read().await;
+        let mut found = Vec::new();
+
+        let mut granted: HashMap<&str, HashSet<String>> = HashMap::new();
+        for group in groups.values() {
+            for role in &group.roles {
+                granted
+                    .entry(role.as_str())
+                    .or_default()
+                    .insert(group.name.clone());
+            }
+            if group.roles.iter().any(|role| !roles.contains_key(role)) {
+                let mut fixed = group.clone();
+                fixed.roles.retain(|role| roles.contains_key(role));
+                found.push(BackendUpdate::PutGroup(fixed));
+            }
+        }
+        for role in roles.values() {
+            let groups = granted.remove(role.name.as_str()).unwrap_or_default();
+            if role.groups != groups {
+                let mut fixed = role.clone();
+                fixed.groups = groups;
+                found.push(BackendUpdate::PutRole(fixed));
+            }
+        }
+
+        found
+    }
+
+    /** HELPERS */
+    /// Make sure the WASM module a policy refers to is loaded
+    fn check_wasm_module(&self, policy: &RegisteredPolicyRule) -> Result<(), String> {
+        match policy.wasm_module {
+            Some(ref module) if !self.wasm.contains(module) => {
+                Err(format!("WASM module {module} is not loaded"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Send an event to the webhooks subscribed to it
+    async fn notify(&self, event: Event, data: serde_json::Value) {
+        let webhooks = self.webhooks.read().await;
+        if webhooks.is_empty() {
+            return;
+        }
+
+        self.dispatcher
+            .dispatch(webhooks.values(), event, data)
+            .await;
+    }
+
+    /// Persist changes made at `now`, recording that time on every entity they put
+    async fn persist(&self, txn: &mut [BackendUpdate], now: u64) -> Result<(), PersistError> {
+        for update in txn.iter_mut() {
+            update.touch(now);
+        }
+        self.storage.persist_changes(txn).await
+    }
+
+    /// Tell the webhooks about entities changed by a set of updates
+    async fn notify_changes(&self, txn: &[BackendUpdate]) {
+        let changes: Vec<serde_json::Value> = txn
+            .iter()
+            .filter_map(describe_change)
+            .map(|(op, kind, name)| json!({"op": op, "kind": kind, "name": name}))
+            .collect();
+
+        if !changes.is_empty() {
+            self.notify(Event::EntityChanged, json!({ "changes": changes }))
+                .await;
+        }
+    }
+
+    /// The attribute values given in the fields of a write that break their validation rules
+    fn attribute_violations(
+        &self,
+        check: fn(
+            &AttributeRules,
+            &str,
+            &HashMap<String, AttributeValues>,
+        ) -> Vec<AttributeViolation>,
+        fields: &[(&str, &HashMap<String, AttributeValues>)],
+    ) -> Vec<AttributeViolation> {
+        // without rules, actors are still kept from being given the attributes Gatehouse sets
+        let no_rules = AttributeRules::default();
+        let rules = self.config.attribute_rules.as_ref().unwrap_or(&no_rules);
+        fields
+            .iter()
+            .flat_map(|(field, attributes)| check(rules, field, attributes))
+            .collect()
+    }
+
+    /// The error for a change to several entities that failed partway through persisting
+    ///
+    /// The updates that were persisted are applied, so the datastore matches its backend, and the
+    /// error's details say which updates were saved and which need repair.
+    async fn partial_failure(&self, mut txn: Vec<BackendUpdate>, err: PersistError) -> Status {
+        if err.saved == 0 {
+            return Status::internal(err);
+        }
+
+        let unsaved = txn.split_off(err.saved.min(txn.len()));
+        let failure = PartialFailure {
+            error: err.err,
+            saved: describe_updates(&txn),
+            unsaved: describe_updates(&unsaved),
+        };
+        self.notify_changes(&txn).await;
+        for update in txn {
+            self.update(update).await;
+        }
+
+        let message = format!(
+            "Failed partway through: {}; saved {}; not saved, needs repair: {}",
+            failure.error,
+            failure.saved.join(", "),
+            failure.unsaved.join(", ")
+        );
+        eprintln!("{message}");
+        Status::with_details(Code::Internal, message, failure.encode_to_vec().into())
+    }
+
+    /// Record a check request for coverage analysis, if enabled
+    ///
+    /// The actor is stored with all the attributes we extended it with and its name replaced by
+    /// a hash, so recorded traffic does not reveal who made the request.
+    async fn record_check(&self, req: &CheckRequest, actor: &RegisteredActor) {
+        if self.config.recorded_checks == 0 {
+            return;
+        }
+
+        let mut anonymized = actor.clone();
+        anonymized.name = format!("{:016x}", metro::hash64(&actor.name));
+
+        let mut recorded = req.clone();
+        recorded.actor = Some(anonymized.into());
+        recorded.correlation_id.clear();
+
+        let mut recorded_checks = self.recorded_checks.write().await;
+        if recorded_checks.len() >= self.config.recorded_checks {
+            recorded_checks.pop_front();
+        }
+        recorded_checks.push_back(recorded);
+    }
+
+    /// Record that the actor, the target, and the actor's roles were in a check, if it is sampled
+    ///
+    /// Only registered entities are tracked, so unknown actors can't grow the usage without bound.
+    async fn record_usage(&self, req: &CheckRequest, actor: &RegisteredActor) {
+        if !self.usage.sample() {
+            return;
+        }
+
+        let target_type = lowercase(&req.target_type);
+        let target_name = lowercase(&req.target_name);
+
+        let mut entities = Vec::new();
+        if self
+            .actors
+            .shard(&actor.typestr)
+            .read()
+            .await
+            .get(&actor.typestr)
+            .is_some_and(|typed| typed.contains_key(&actor.name))
+        {
+            entities.push(("actor", actor.typestr.as_ref(), actor.name.as_str()));
+        }
+        if self
+            .targets
+            .shard(&target_type)
+            .read()
+            .await
+            .get(target_type.as_ref())
+            .is_some_and(|typed| typed.contains_key(target_name.as_ref()))
+        {
+            entities.push(("target", target_type.as_ref(), target_name.as_ref()));
+        }
+        let roles = self.roles.read().await;
+        for role in actor.attributes.get("has-role").into_iter().flatten() {
+            if roles.contains_key(role.as_ref()) {
+                entities.push(("role", "", role.as_ref()));
+            }
+        }
+        drop(roles);
+
+        self.usage.checked(&entities).await;
+    }
+
+    /// Score the risk of a check and set it as the `risk` environment attribute, if risk is scored
+    ///
+    /// Only real checks let the signals learn from them, so tracing a check doesn't change the
+    /// score of the next one.
+    fn score_risk(
+        &self,
+        req: &CheckRequest,
+        actor: &RegisteredActor,
+        env_attributes: &mut AttributeMap,
+        observe: bool,
+    ) -> Option<RiskScore> {
+        if !self.risk.is_enabled() {
+            return None;
+        }
+
+        let input = RiskInput {
+            actor_type: &actor.typestr,
+            actor_name: &actor.name,
+            actor_attributes: &actor.attributes,
+            env_attributes,
+            target_type: &req.target_type,
+            target_name: &req.target_name,
+            actions: &req.target_action,
+        };
+        let risk = self.risk.score(&input, observe);
+        env_attributes.insert(
+            intern(RISK_ATTRIBUTE),
+            attribute::check_values([risk.score.to_string()]),
+        );
+        Some(risk)
+    }
+
+    /// Record the uses of actions that some policy's rate checks count
+    fn record_rates(
+        &self,
+        policies: &PolicyStore,
+        actor: &RegisteredActor,
+        actions: &[&TargetAction],
+    ) {
+        for action in actions {
+            if policies
+                .values()
+                .flat_map(|policy| &policy.rate_checks)
+                .any(|rc| rc.counts(&action.name))
+            {
+                self.rates.record(actor, &action.name);
+            }
+        }
+    }
+
+    /// Gather the actor, environment attributes, and target attributes for a recorded check
+    ///
+    /// Recorded actors were already extended when recorded and their names are anonymized, so
+    /// they are used as they are.
+    async fn prepare_replay(
+        &self,
+        req: &CheckRequest,
+    ) -> (RegisteredActor, AttributeMap, Arc<AttributeMap>) {
+        let actor = RegisteredActor::from_check(req.actor.as_ref().unwrap());
+
+        let env_attributes = attribute::from_check(&req.env_attributes);
+
+        let target_attributes = self
+            .get_target_attributes(&req.target_name, &req.target_type)
+            .await;
+
+        (actor, env_attributes, target_attributes)
+    }
+
+    /// Gather the extended actor, environment attributes, and target attributes for a check
+    async fn prepare_check(
+        &self,
+        req: &CheckRequest,
+        budget: &EvalBudget,
+    ) -> (RegisteredActor, AttributeMap, Arc<AttributeMap>) {
+        let actor = self
+            .extend_actor(
+                RegisteredActor::from_check(req.actor.as_ref().unwrap()),
+                budget,
+            )
+            .await;
+
+        // a PEP can't claim a risk score, whether or not we score checks ourselves
+        let mut env_attributes = attribute::from_check(&req.env_attributes);
+        env_attributes.retain(|key, _| !attribute::is_system_env(key));
+
+        // get any known attributes about the target
+        let target_attributes = self
+            .get_target_attributes(&req.target_name, &req.target_type)
+            .await;
+
+        (actor, env_attributes, target_attributes)
+    }
+
+    /// Extend a given actor with additional attributes
+    ///
+    /// Given a RegisteredActor created just from a gRPC call,
+    /// update it with any additional attributes from a
+    /// known actor, as well as any group/roles we might have. Groups are only followed as deep
+    /// as the budget allows.
+    async fn extend_actor(
+        &self,
+        mut actor: RegisteredActor,
+        budget: &EvalBudget,
+    ) -> RegisteredActor {
+        // the attributes of a known actor, when the check brought none of its own
+        let mut registered = None;
+        {
+            let actors = self.actors.shard(&actor.typestr).read().await;
+            let typed_actors = actors.get(&actor.typestr);
+
+            // extend attributes if we know about this actor, sharing the known attributes unless
+            // the check brought its own to merge them with
+            let merge = &self.config.attribute_merge;
+            match typed_actors.and_then(|typed| typed.get(&actor.name)) {
+                Some(found_actor) if actor.attributes.is_empty() => {
+                    actor.attributes = Arc::clone(&found_actor.attributes);
+                    registered = Some(Arc::clone(&found_actor.attributes));
+                }
+                Some(found_actor) => merge.merge(
+                    Arc::make_mut(&mut actor.attributes),
+                    Some(&found_actor.attributes),
+                ),
+                None if merge.ignores_any(&actor.attributes) => {
+                    merge.merge(Arc::make_mut(&mut actor.attributes), None)
+                }
+                None => {}
+            }
+        }
+
+        let Some(registered) = registered else {
+            return self.expand_groups_and_roles(actor, budget).await;
+        };
+
+        // the version can't change while the groups are locked for reading
+        let groups = self.groups.read().await;
+        let version = self.groups.version();
+        let (typestr, name) = (&actor.typestr, &actor.name);
+        if let Some(expanded) = self.expansions.get(typestr, name, &registered, version) {
+            actor.attributes = expanded;
+            return actor;
+        }
+
+        actor = add_groups_and_roles(actor, &groups, budget);
+        // groups left out for going over a limit mustn't be remembered as all of them
+        if budget.exceeded().is_none() {
+            let expanded = Arc::clone(&actor.attributes);
+            self.expansions
+                .keep(&actor.typestr, &actor.name, registered, version, expanded);
+        }
+        actor
+    }
+
+    async fn expand_groups_and_roles(
+        &self,
+        actor: RegisteredActor,
+        budget: &EvalBudget,
+    ) -> RegisteredActor {
+        add_groups_and_roles(actor, &*self.groups.read().await, budget)
+    }
+
+    /// Everyone who is effectively a member of a group, sorted by type and then name
+    ///
+    /// This is where nested groups and other ways of belonging to a group are resolved, so
+    /// everything that lists members sees the same ones. A group that is a member is replaced by
+    /// its own members, as deep as the group nesting limit allows.
+    fn effective_members(
+        &self,
+        group: &RegisteredGroup,
+        groups: &HashMap<String, RegisteredGroup>,
+    ) -> Vec<RegisteredGroupMember> {
+        let max_group_depth = self.config.eval_limits.max_group_depth;
+        let mut members = HashSet::new();
+        let mut seen = HashSet::from([group.name.as_str()]);
+        let mut level = vec![group];
+        let mut depth = 1;
+        while !level.is_empty() && max_group_depth.is_none_or(|max| depth <= max) {
+            let mut nested = Vec::new();
+            for group in level {
+                for member in &group.members {
+                    if &*member.typestr != GROUP_MEMBER_TYPE {
+                        members.insert(member.clone());
+                    } else if let Some(group) = groups.get(&member.name) {
+                        if seen.insert(group.name.as_str()) {
+                            nested.push(group);
+                        }
+                    }
+                }
+            }
+            level = nested;
+            depth += 1;
+        }
+
+        let mut members: Vec<RegisteredGroupMember> = members.into_iter().collect();
+        members.sort_by(|a, b| (&a.typestr, &a.name).cmp(&(&b.typestr, &b.name)));
+        members
+    }
+
+    /// Make sure a group with this many members stays within our quota
+    fn check_group_size(&self, size: usize) -> Result<(), String> {
+        match self.config.quotas.max_group_size {
+            Some(max_group_size) if size > max_group_size => Err(format!(
+                "Group size limit of {max_group_size} members exceeded"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// In strict mode, make sure a check names a registered target and only its actions
+    ///
+    /// Otherwise a PEP that misspells a target or action is silently evaluated with no target
+    /// attributes. Actions can also be `*` or one of the target's action groups.
+    async fn check_strict(&self, req: &CheckRequest) -> Result<(), String> {
+        if !self.config.strict_checks && !req.strict {
+            return Ok(());
+        }
+
+        let typestr = req.target_type.to_ascii_lowercase();
+        let name = req.target_name.to_ascii_lowercase();
+        let known = self
+            .known_actions(req)
+            .await
+            .ok_or_else(|| format!("Target {typestr}/{name} is not registered"))?;
+
+        if req.target_action.is_empty() {
+            return Err(String::from("No target action given"));
+        }
+        match req
+            .target_action
+            .iter()
+            .zip(known)
+            .find(|(_, known)| !known)
+        {
+            Some((action, _)) => Err(format!(
+                "{action} is not an action of target {typestr}/{name}"
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the target of a check has each of its actions, or `None` if the target isn't
+    /// registered
+    ///
+    /// Actions can also be `*` or one of the target's action groups.
+    async fn known_actions(&self, req: &CheckRequest) -> Option<Vec<bool>> {
+        let typestr = req.target_type.to_ascii_lowercase();
+        let targets = self.targets.shard(&typestr).read().await;
+        let target = targets
+            .get(typestr.as_str())
+            .and_then(|typed_targets| typed_targets.get(&req.target_name.to_ascii_lowercase()))?;
+
+        let known = req
+            .target_action
+            .iter()
+            .map(|action| {
+                let lowered = action.to_ascii_lowercase();
+                lowered == "*"
+                    || target.actions.contains(&lowered)
+                    || target.action_groups.contains_key(&lowered)
+            })
+            .collect();
+        Some(known)
+    }
+
+    /// Resolve every action of a check against the target's action groups
+    async fn resolve_actions(&self, req: &CheckRequest) -> Vec<TargetAction> {
+        self.resolve_each_action(req)
+            .await
+            .into_iter()
+            .flat_map(|(_, actions)| actions)
+            .collect()
+    }
+
+    /// Resolve each action of a check against the target's action groups
+    ///
+    /// Every action named in the check comes with the actions it stands for (see
+    /// `RegisteredTarget::resolve_action`). Unknown targets have no action groups. A check
+    /// without any actions is a check of an empty action, as it always has been.
+    async fn resolve_each_action(&self, req: &CheckRequest) -> Vec<(String, Vec<TargetAction>)> {
+        let typestr = lowercase(&req.target_type);
+        let targets = self.targets.shard(&typestr).read().await;
+        let target = targets
+            .get(typestr.as_ref())
+            .and_then(|typed_targets| typed_targets.get(lowercase(&req.target_name).as_ref()));
+
+        let names: &[String] = match req.target_action.is_empty() {
+            true => &[String::new()],
+            false => &req.target_action,
+        };
+
+        names
+            .iter()
+            .map(|name| {
+                let actions = match target {
+                    Some(target) => target.resolve_action(name),
+                    None => vec![TargetAction::new(name)],
+                };
+                (name.clone(), actions)
+            })
+            .collect()
+    }
+
+    /// Return attributes for a target if known
+    ///
+    /// Targets are registered in lowercase, so they are looked up that way whatever the case of
+    /// the check.
+    async fn get_target_attributes(&self, name: &str, typestr: &str) -> Arc<AttributeMap> {
+        let typestr = lowercase(typestr);
+        let targets = self.targets.shard(&typestr).read().await;
+        let typed_targets = targets.get(typestr.as_ref());
+
+        if let Some(typed_targets) = typed_targets {
+            if let Some(found_target) = typed_targets.get(lowercase(name).as_ref()) {
+                return Arc::clone(&found_target.attributes);
+            }
+        }
+
+        Arc::default()
+    }
+}
+
+/// Add the groups an actor is a member of, and the roles those give it, to its attributes
+///
+/// An actor in a group is also in every group that group is a member of, and so on, as deep as
+/// the budget allows; the groups past its limit are left out.
+fn add_groups_and_roles(
+    mut actor: RegisteredActor,
+    groups: &HashMap<String, RegisteredGroup>,
+    budget: &EvalBudget,
+) -> RegisteredActor {
+    // lend this actor's name and type to a RegisteredGroupMember so we can search for it
+    let actor_as_member = RegisteredGroupMember {
+        name: std::mem::take(&mut actor.name),
+        typestr: std::mem::take(&mut actor.typestr),
+    };
+    let mut level: Vec<&RegisteredGroup> = groups
+        .values()
+        .filter(|group| group.members.contains(&actor_as_member))
+        .collect();
+    actor.name = actor_as_member.name;
+    actor.typestr = actor_as_member.typestr;
+
+    // groups in a cycle are only followed once
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut depth = 1;
+    while !level.is_empty() && budget.allows_depth(depth) {
+        for group in &level {
+            seen.insert(&group.name);
+            // the actor's attributes are only copied once it turns out to be in a group
+            let attributes = Arc::make_mut(&mut actor.attributes);
+            attributes
+                .entry(intern(attribute::MEMBER_OF))
+                .or_default()
+                .insert(intern(&group.name));
+
+            attributes
+                .entry(intern(attribute::HAS_ROLE))
+                .or_default()
+                .extend(group.roles.iter().map(|role| intern(role)));
+        }
+
+        let as_members: Vec<RegisteredGroupMember> = level
+            .iter()
+            .map(|group| RegisteredGroupMember::group(&group.name))
+            .collect();
+        level = groups
+            .values()
+            .filter(|group| !seen.contains(group.name.as_str()))
+            .filter(|group| as_members.iter().any(|m| group.members.contains(m)))
+            .collect();
+        depth += 1;
+    }
+
+    actor
+}
+
+/// What would fix a problem found with the stored data at startup
+enum StartupFix {
+    /// leave the record out of memory
+    Skip(BackendUpdate),
+    /// replace the record with a corrected one
+    Repair(BackendUpdate),
+}
+
+/// Open a storage backend; owns what it is given so it can be opened on another runtime
+async fn open_storage(
+    backend: StorageType,
+    req_tx: flume::Sender<DsRequest>,
+    config: Config,
+) -> Box<dyn Storage + Send + Sync> {
+    match backend {
+        StorageType::Etcd(urls) => Box::new(EtcdStorage::new(&urls, req_tx, &config).await),
+        StorageType::FileSystem(path) => Box::new(FileStorage::new(&path, config.fsync).await),
+        StorageType::Log(path) => Box::new(LogStorage::new(&path, config.fsync).await),
+        StorageType::Nil => Box::new(NilStorage {}),
+    }
+}
+
+/// Take what was loaded from the backend or, if that failed, refuse to start in strict mode and
+/// otherwise carry on without any of this kind
+fn startup_load<T: Default>(
+    kind: &str,
+    loaded: Result<T, String>,
+    mode: StartupMode,
+    issues: &mut Vec<StartupIssue>,
+) -> T {
+    match loaded {
+        Ok(loaded) => loaded,
+        Err(err) if mode == StartupMode::Strict => {
+            eprintln!("Could not load {kind} from backend: {err}");
+            std::process::exit(1);
+        }
+        Err(err) => {
+            issues.push(StartupIssue {
+                kind: kind.to_string(),
+                name: String::from("*"),
+                problem: err,
+                action: String::from("skipped"),
+            });
+            T::default()
+        }
+    }
+}
+
+/// The proposals loaded from the backend, by id; any that don't decode are skipped
+fn decode_proposals(
+    stored: HashMap<String, RegisteredProposal>,
+    issues: &mut Vec<StartupIssue>,
+) -> BTreeMap<u64, Proposal> {
+    let mut proposals = BTreeMap::new();
+    for stored in stored.into_values() {
+        match Proposal::try_from(&stored) {
+            Ok(proposal) => {
+                proposals.insert(proposal.id, proposal);
+            }
+            Err(err) => issues.push(StartupIssue {
+                kind: String::from("proposals"),
+                name: stored.key(),
+                problem: err,
+                action: String::from("skipped"),
+            }),
+        }
+    }
+    proposals
+}
+
+/// What an update does to which entity, as (op, kind, name); webhook, API key, proposal and
+/// break glass changes aren't described
+fn describe_change(update: &BackendUpdate) -> Option<(&'static str, &'static str, String)> {
+    let change = match update {
+        BackendUpdate::PutActor(a) => ("put", "actor", format!("{}/{}", a.typestr, a.name)),
+        BackendUpdate::PutGroup(g) => ("put", "group", g.name.clone()),
+        BackendUpdate::PutPolicyRule(p) => ("put", "policy", p.name.clone()),
+        BackendUpdate::PutPolicySet(s) => ("put", "policyset", s.name.clone()),
+        BackendUpdate::PutDelegation(d) => ("put", "delegation", d.name.clone()),
+        BackendUpdate::PutRole(r) => ("put", "role", r.name.clone()),
+        BackendUpdate::PutTarget(t) => ("put", "target", format!("{}/{}", t.typestr, t.name)),
+        BackendUpdate::DeleteActor(typestr, name) => {
+            ("delete", "actor", format!("{typestr}/{name}"))
+        }
+        BackendUpdate::DeleteGroup(name) => ("delete", "group", name.clone()),
+        BackendUpdate::DeletePolicyRule(name) => ("delete", "policy", name.clone()),
+        BackendUpdate::DeletePolicySet(name) => ("delete", "policyset", name.clone()),
+        BackendUpdate::DeleteDelegation(name) => ("delete", "delegation", name.clone()),
+        BackendUpdate::DeleteRole(name) => ("delete", "role", name.clone()),
+        BackendUpdate::DeleteTarget(typestr, name) => {
+            ("delete", "target", format!("{typestr}/{name}"))
+        }
+        BackendUpdate::PutWebhook(_)
+        | BackendUpdate::DeleteWebhook(_)
+        | BackendUpdate::PutApiKey(_)
+        | BackendUpdate::DeleteApiKey(_)
+        | BackendUpdate::PutProposal(_)
+        | BackendUpdate::DeleteProposal(_)
+        | BackendUpdate::PutBreakGlass(_)
+        | BackendUpdate::DeleteBreakGlass(_)
+        | BackendUpdate::PutBundle(_) => return None,
+    };
+    Some(change)
+}
+
+/// Describe updates, such as "put group admins"
+fn describe_updates(txn: &[BackendUpdate]) -> Vec<String> {
+    txn.iter()
+        .filter_map(describe_change)
+        .map(|(op, kind, name)| format!("{op} {kind} {name}"))
+        .collect()
+}
+
+/// Everything in a datastore, keyed for diffing
+#[derive(Default)]
+struct Keyed {
+    targets: HashMap<(Arc<str>, String), RegisteredTarget>,
+    actors: HashMap<(Arc<str>, String), RegisteredActor>,
+    roles: HashMap<String, RegisteredRole>,
+    groups: HashMap<String, RegisteredGroup>,
+    policies: HashMap<String, RegisteredPolicyRule>,
+    policy_sets: HashMap<String, RegisteredPolicySet>,
+    delegations: HashMap<String, RegisteredDelegation>,
+    webhooks: HashMap<String, RegisteredWebhook>,
+    api_keys: HashMap<String, RegisteredApiKey>,
+    proposals: HashMap<String, RegisteredProposal>,
+    break_glasses: HashMap<String, RegisteredBreakGlass>,
+}
+
+impl From<Vec<BackendUpdate>> for Keyed {
+    fn from(state: Vec<BackendUpdate>) -> Self {
+        let mut keyed = Keyed::default();
+        for update in state {
+            match update {
+                BackendUpdate::PutTarget(t) => {
+                    keyed.targets.insert((t.typestr.clone(), t.name.clone()), t);
+                }
+                BackendUpdate::PutActor(a) => {
+                    keyed.actors.insert((a.typestr.clone(), a.name.clone()), a);
+                }
+                BackendUpdate::PutRole(r) => {
+                    keyed.roles.insert(r.name.clone(), r);
+                }
+                BackendUpdate::PutGroup(g) => {
+                    keyed.groups.insert(g.name.clone(), g);
+                }
+                BackendUpdate::PutPolicyRule(p) => {
+                    keyed.policies.insert(p.name.clone(), *p);
+                }
+                BackendUpdate::PutPolicySet(s) => {
+                    keyed.policy_sets.insert(s.name.clone(), s);
+                }
+                BackendUpdate::PutDelegation(d) => {
+                    keyed.delegations.insert(d.name.clone(), d);
+                }
+                BackendUpdate::PutWebhook(w) => {
+                    keyed.webhooks.insert(w.name.clone(), w);
+                }
+                BackendUpdate::PutApiKey(k) => {
+                    keyed.api_keys.insert(k.id.clone(), k);
+                }
+                BackendUpdate::PutProposal(p) => {
+                    keyed.proposals.insert(p.key(), p);
+                }
+                BackendUpdate::PutBreakGlass(b) => {
+                    keyed.break_glasses.insert(b.key(), b);
+                }
+                // a snapshot only has what exists
+                _ => {}
+            }
+        }
+        keyed
+    }
+}
+
+/// The puts and deletes that turn one snapshot of the state into another
+fn changes(current: Vec<BackendUpdate>, wanted: Vec<BackendUpdate>) -> Vec<BackendUpdate> {
+    let current = Keyed::from(current);
+    let wanted = Keyed::from(wanted);
+
+    let mut txn = Vec::new();
+    txn.extend(diff(
+        current.targets,
+        wanted.targets,
+        |a, b| {
+            a.actions == b.actions
+                && a.attributes == b.attributes
+                && a.action_groups == b.action_groups
+        },
+        BackendUpdate::PutTarget,
+        |(typestr, name)| BackendUpdate::DeleteTarget(typestr.to_string(), name),
+    ));
+    txn.extend(diff(
+        current.actors,
+        wanted.actors,
+        |a, b| a.attributes == b.attributes,
+        BackendUpdate::PutActor,
+        |(typestr, name)| BackendUpdate::DeleteActor(typestr.to_string(), name),
+    ));
+    txn.extend(diff(
+        current.roles,
+        wanted.roles,
+        |a, b| a.desc == b.desc && a.groups == b.groups,
+        BackendUpdate::PutRole,
+        BackendUpdate::DeleteRole,
+    ));
+    txn.extend(diff(
+        current.groups,
+        wanted.groups,
+        |a, b| {
+            a.desc == b.desc
+                && a.members == b.members
+                && a.roles == b.roles
+                && a.managed_by == b.managed_by
+        },
+        BackendUpdate::PutGroup,
+        BackendUpdate::DeleteGroup,
+    ));
+    txn.extend(diff(
+        current.policies,
+        wanted.policies,
+        |a, b| a == b,
+        |p| BackendUpdate::PutPolicyRule(Box::new(p)),
+        BackendUpdate::DeletePolicyRule,
+    ));
+    txn.extend(diff(
+        current.policy_sets,
+        wanted.policy_sets,
+        |a, b| a == b,
+        BackendUpdate::PutPolicySet,
+        BackendUpdate::DeletePolicySet,
+    ));
+    txn.extend(diff(
+        current.delegations,
+        wanted.delegations,
+        |a, b| a == b,
+        BackendUpdate::PutDelegation,
+        BackendUpdate::DeleteDelegation,
+    ));
+    txn.extend(diff(
+        current.webhooks,
+        wanted.webhooks,
+        |a, b| a.url == b.url && a.events == b.events && a.secret == b.secret,
+        BackendUpdate::PutWebhook,
+        BackendUpdate::DeleteWebhook,
+    ));
+    txn.extend(diff(
+        current.api_keys,
+        wanted.api_keys,
+        |a, b| a == b,
+        BackendUpdate::PutApiKey,
+        BackendUpdate::DeleteApiKey,
+    ));
+    txn.extend(diff(
+        current.proposals,
+        wanted.proposals,
+        |a, b| a == b,
+        BackendUpdate::PutProposal,
+        BackendUpdate::DeleteProposal,
+    ));
+    txn.extend(diff(
+        current.break_glasses,
+        wanted.break_glasses,
+        |a, b| a == b,
+        BackendUpdate::PutBreakGlass,
+        BackendUpdate::DeleteBreakGlass,
+    ));
+    txn
+}
+
+/// The puts and deletes that turn what we have into what was loaded
+fn diff<K: Eq + std::hash::Hash, V>(
+    mut current: HashMap<K, V>,
+    loaded: HashMap<K, V>,
+    same: impl Fn(&V, &V) -> bool,
+    put: impl Fn(V) -> BackendUpdate,
+    delete: impl Fn(K) -> BackendUpdate,
+) -> Vec<BackendUpdate> {
+    let mut txn = Vec::new();
+    for (key, val) in loaded {
+        match current.remove(&key) {
+            Some(ref existing) if same(existing, &val) => {}
+            _ => txn.push(put(val)),
+        }
+    }
+    // whatever is left is gone from the backend
+    txn.extend(current.into_keys().map(delete));
+    txn
+}
+
+/// Sort a list the way a Get request asked, by the type, name, and when it was added and last
+/// changed that `key` gives for each entry; ties are broken by name and then type, so the order
+/// doesn't change from one call to the next
+fn sort_list<T>(
+    list: &mut [T],
+    sort_by: SortBy,
+    direction: SortDirection,
+    key: impl Fn(&T) -> (&str, &str, u64, u64),
+) {
+    list.sort_by(|a, b| {
+        let (a_type, a_name, a_created, a_updated) = key(a);
+        let (b_type, b_name, b_created, b_updated) = key(b);
+        let by_name = (a_name, a_type).cmp(&(b_name, b_type));
+        match sort_by {
+            SortBy::Name => by_name,
+            SortBy::Type => (a_type, a_name).cmp(&(b_type, b_name)),
+            SortBy::CreatedAt => a_created.cmp(&b_created).then(by_name),
+            SortBy::UpdatedAt => a_updated.cmp(&b_updated).then(by_name),
+        }
+    });
+    if direction == SortDirection::Descending {
+        list.reverse();
+    }
+}
+
+/// A modify request can replace a field or add to and remove from it, but not both; each field
+/// is given as its name, whether it is replaced, and whether it is added to or removed from
+fn check_replace(fields: &[(&str, bool, bool)]) -> Result<(), String> {
+    match fields.iter().find(|(_, set, changed)| *set && *changed) {
+        Some((field, _, _)) => Err(format!(
+            "set_{field} can't be combined with add_{field} or remove_{field}"
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The attributes to replace an entity's with
+fn replaced_attributes(set: Attributes) -> AttributeMap {
+    set.attributes
+        .into_iter()
+        .filter(|(_, vals)| !vals.values.is_empty())
+        .map(|(key, vals)| (intern(&key), attribute::values(vals.values)))
+        .collect()
+}
+
+/// Turn the last member of a page into the token for the next page
+fn encode_page_token(member: &RegisteredGroupMember) -> String {
+    base64::encode(json!([member.typestr, member.name]).to_string())
+}
+
+/// Get the last member of the previous page back from a page token
+fn decode_page_token(token: &str) -> Result<(String, String), String> {
+    base64::decode(token)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| String::from("Invalid page token"))
+}
+
+/// Groups managed by an external source can only be changed by that source
+fn check_group_editable(group: &RegisteredGroup) -> Result<(), String> {
+    match group.managed_by {
+        Some(ref source) => Err(format!(
+            "Group {} is managed by {source} and is read-only",
+            group.name
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The groups a change also updates, such as when a role is granted to them
+fn updated_groups(txn: &[BackendUpdate]) -> Vec<String> {
+    let mut names: Vec<String> = txn
+        .iter()
+        .filter_map(|update| match update {
+            BackendUpdate::PutGroup(group) => Some(group.name.clone()),
+            _ => None,
+        })
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+/// The roles a change also updates, such as when they are granted to a group
+fn updated_roles(txn: &[BackendUpdate]) -> Vec<String> {
+    let mut names: Vec<String> = txn
+        .iter()
+        .filter_map(|update| match update {
+            BackendUpdate::PutRole(role) => Some(role.name.clone()),
+            _ => None,
+        })
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+/// the time `now` gives while a recording is replayed, or 0 for the real time
+static PINNED_CLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// The current time in seconds since the epoch
+pub(crate) fn now() -> u64 {
+    match PINNED_CLOCK.load(Ordering::Relaxed) {
+        0 => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default(),
+        pinned => pinned,
+    }
+}
+
+/// Make `now` give a fixed time, in seconds since the epoch, or the real time again with 0
+pub(crate) fn pin_clock(secs: u64) {
+    PINNED_CLOCK.store(secs, Ordering::Relaxed);
+}
+
+/// A token that resumes a change stream after a revision of a run of the server
+pub(crate) fn resume_token(epoch: u64, revision: u64) -> String {
+    format!("{epoch:x}.{revision}")
+}
+
+/// The run of the server and the revision a resume token is for
+fn parse_resume_token(token: &str) -> Result<(u64, u64), String> {
+    let invalid = || format!("Invalid resume token: {token}");
+    let (epoch, revision) = token.split_once('.').ok_or_else(invalid)?;
+    let epoch = u64::from_str_radix(epoch, 16).map_err(|_| invalid())?;
+    let revision = revision.parse().map_err(|_| invalid())?;
+    Ok((epoch, revision))
+}
+
+/// Action groups can't shadow the `*` wildcard
+fn check_action_groups<'a>(mut names: impl Iterator<Item = &'a String>) -> Result<(), String> {
+    match names.find(|name| name.as_str() == "*") {
+        Some(_) => Err(String::from("An action group cannot be named *")),
+        None => Ok(()),
+    }
+}
+
+/// Make sure every CIDR block in a policy rule can be parsed
+fn check_cidr_blocks(rule: &PolicyRule) -> Result<(), String> {
+    for block in rule.cidr_checks.iter().flat_map(|cc| &cc.blocks) {
+        block.parse::<Cidr>()?;
+    }
+    Ok(())
+}
+
+/// The decision a check returns; one that needs approval is PENDING until it gets it
+fn decided(decision: Decide) -> i32 {
+    match decision {
+        Decide::AllowWithApproval(_) => crate::proto::policies::Decide::Pending.into(),
+        decision => crate::proto::policies::Decide::from(decision).into(),
+    }
+}
+
+/// Decide a strict check of an unregistered target or action INDETERMINATE
+///
+/// Nothing is evaluated or recorded, and the decision isn't cached, so registering the target or
+/// action takes effect on the next check.
+fn decide_unregistered(req: CheckRequest, err: String, tx: Sender<DsResponse>) {
+    println!("Indeterminate, {err}: {req}");
+    let indeterminate = decided(Decide::Indeterminate);
+    let action_decisions = match req.action_mode() == ActionMode::EachAction {
+        true => req
+            .target_action
+            .iter()
+            .map(|action| ActionDecision {
+                action: action.clone(),
+                decision: indeterminate,
+            })
+            .collect(),
+        false => Vec::new(),
+    };
+    let _ = tx.send(DsResponse::CheckResult(CheckResponse {
+        decision: indeterminate,
+        action_decisions,
+        cache_ttl: 0,
+        correlation_id: req.correlation_id,
+        source: DecisionSource::Unregistered.into(),
+        ..Default::default()
+    }));
+}
+
+/// The actions of a check as an approval lists them
+fn approval_actions(req: &CheckRequest) -> Vec<String> {
+    let mut actions: Vec<String> = req
+        .target_action
+        .iter()
+        .map(|action| action.to_ascii_lowercase())
+        .collect();
+    actions.sort_unstable();
+    actions.dedup();
+    actions
+}
+
+/// Make sure a break glass names either an actor or a policy, and says why and for how long
+fn validate_break_glass(req: &BreakGlassRequest) -> Result<(), String> {
+    let actor = !req.actor_type.is_empty() || !req.actor_name.is_empty();
+    match (actor, req.policy.is_empty()) {
+        (true, false) | (false, true) => {
+            return Err(String::from(
+                "Break glass must name either an actor or a policy",
+            ))
+        }
+        (true, true) if req.actor_type.is_empty() || req.actor_name.is_empty() => {
+            return Err(String::from(
+                "Break glass must name the actor's type and name",
+            ))
+        }
+        (false, false) if !req.target_type.is_empty() => {
+            return Err(String::from(
+                "Only an actor's break glass can name a target type",
+            ))
+        }
+        _ => (),
+    }
+    if req.minutes == 0 || req.minutes > MAX_BREAK_GLASS_MINUTES {
+        return Err(format!(
+            "Break glass must last from 1 to {MAX_BREAK_GLASS_MINUTES} minutes"
+        ));
+    }
+    if req.reason.trim().is_empty() {
+        return Err(String::from("Break glass must give a reason"));
+    }
+    Ok(())
+}
+
+/// What a break glass allows, for the logs
+fn break_glass_what(break_glass: &BreakGlass) -> String {
+    match (
+        break_glass.policy.is_empty(),
+        break_glass.target_type.is_empty(),
+    ) {
+        (false, _) => format!("bypass policy[{}]", break_glass.policy),
+        (true, true) => format!(
+            "allow {}/{} everything",
+            break_glass.actor_type, break_glass.actor_name
+        ),
+        (true, false) => format!(
+            "allow {}/{} everything on {}",
+            break_glass.actor_type, break_glass.actor_name, break_glass.target_type
+        ),
+    }
+}
+
+/// The webhook data for a break glass starting or ending
+fn break_glass_data(state: &str, break_glass: &BreakGlass) -> serde_json::Value {
+    json!({
+        "state": state,
+        "id": break_glass.id,
+        "caller": break_glass.caller,
+        "reason": break_glass.reason,
+        "actor": {"name": break_glass.actor_name, "typestr": break_glass.actor_type},
+        "target_type": break_glass.target_type,
+        "policy": break_glass.policy,
+        "created_at": break_glass.created_at,
+        "expires_at": break_glass.expires_at,
+    })
+}
+
+/// Make sure a policy rule decides something a rule can decide
+fn check_decision(rule: &PolicyRule) -> Result<(), String> {
+    use crate::proto::policies::Decide;
+
+    match rule.decision() {
+        decision @ (Decide::Pending | Decide::NotApplicable | Decide::Indeterminate) => {
+            Err(format!("A policy cannot decide {decision}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Make sure every rate check in a policy rule can be made
+fn check_rate_checks(rule: &PolicyRule) -> Result<(), String> {
+    for rc in &rule.rate_checks {
+        RateCheck::from(rc.clone()).validate()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use tokio::sync::oneshot::channel;
+    use tokio::test;
+
+    use crate::chaos::ChaosConfig;
+    use crate::policy::{ActorCheck, StringCheck};
+    use crate::proto::base::watch_event::Change;
+    use crate::proto::base::{Mutation, ReplicatedChange, WatchEvent};
+    use crate::proto::common::AttributeValues;
+    use crate::proto::groups::{GroupMembers, GroupRoles};
+    use crate::proto::targets::{ActionGroup, Actions};
+    use crate::quota::{EvalLimits, Quotas};
+    use crate::streak::DenyStreakConfig;
+
+    use super::*;
+
+    fn str(val: &str) -> String {
+        String::from(val)
+    }
+
+    #[test]
+    async fn test_targets() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let (tx, _) = channel::<DsResponse>();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let mut map: HashMap<String, AttributeValues> = HashMap::new();
+        map.insert(
+            str("role"),
+            AttributeValues {
+                values: vec![str("main"), str("backup")],
+            },
+        );
+        map.insert(
+            str("env"),
+            AttributeValues {
+                values: vec![str("test")],
+            },
+        );
+
+        let req = AddTargetRequest {
+            name: str("test"),
+            typestr: str("typetest"),
+            actions: vec![str("action1"), str("action2")],
+            attributes: map,
+            action_groups: HashMap::new(),
+            ..Default::default()
+        };
+        ds.add_target(req, tx).await;
+
+        assert_eq!(ds.targets.read().await.iter().count(), 1);
+        assert!(ds.targets.read().await.get("typetest").is_some());
+        assert!(ds
+            .targets
+            .read()
+            .await
+            .get("typetest")
+            .unwrap()
+            .contains_key("test"));
+    }
+
+    #[test]
+    async fn test_bulk_add() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let mut config = Config::default();
+        config.quotas.max_actors = Some(3);
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+        let (tx, _) = channel::<DsResponse>();
+        let req = AddActorRequest {
+            name: str("kaitlyn"),
+            typestr: str("user"),
+            ..Default::default()
+        };
+        ds.add_actor(req, tx).await;
+
+        let actor = |name: &str| AddActorRequest {
+            name: str(name),
+            typestr: str("user"),
+            ..Default::default()
+        };
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddActorsRequest {
+            actors: ["rob", "Kaitlyn", "", "ROB", "maya", "jo"]
+                .into_iter()
+                .map(actor)
+                .collect(),
+            dry_run: false,
+        };
+        ds.add_actors(req, tx).await;
+        let errors: Vec<String> = match rx.await {
+            Ok(DsResponse::AddedActors(results)) => {
+                results.into_iter().map(|result| result.error).collect()
+            }
+            _ => panic!("Expected results"),
+        };
+        assert_eq!(
+            errors,
+            vec![
+                "",
+                "Actor already exists",
+                "Name and typestr cannot be null",
+                "Actor already exists",
+                "",
+                "Actor limit of 3 reached",
+            ]
+        );
+        assert_eq!(ds.actors.read().await["user"].len(), 3);
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddTargetsRequest {
+            targets: vec![AddTargetRequest {
+                name: str("maindb"),
+                typestr: str("database"),
+                ..Default::default()
+            }],
+            dry_run: true,
+        };
+        ds.add_targets(req, tx).await;
+        assert!(
+            matches!(rx.await, Ok(DsResponse::AddedTargets(results)) if results[0].target.is_some())
+        );
+        assert_eq!(ds.targets.read().await.iter().count(), 0);
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddTargetsRequest {
+            targets: vec![AddTargetRequest::default(); MAX_BULK_ADD + 1],
+            dry_run: false,
+        };
+        ds.add_targets(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(_))));
+    }
+
+    #[test]
+    async fn test_replace() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+        let values = |vals: &[&str]| AttributeValues {
+            values: vals.iter().map(|val| str(val)).collect(),
+        };
+
+        let (tx, _) = channel::<DsResponse>();
+        let req = AddActorRequest {
+            name: str("kaitlyn"),
+            typestr: str("user"),
+            attributes: HashMap::from([
+                (str("team"), values(&["eng", "ops"])),
+                (str("level"), values(&["3"])),
+            ]),
+            ..Default::default()
+        };
+        ds.add_actor(req, tx).await;
+
+        let (tx, _) = channel::<DsResponse>();
+        let req = ModifyActorRequest {
+            name: str("kaitlyn"),
+            typestr: str("user"),
+            set_attributes: Some(Attributes {
+                attributes: HashMap::from([(str("team"), values(&["sre"]))]),
+            }),
+            ..Default::default()
+        };
+        ds.modify_actor(req, tx).await;
+        assert_eq!(
+            *ds.actors.read().await["user"]["kaitlyn"].attributes,
+            AttributeMap::from_iter([(str("team").into(), attribute::values([str("sre")]))])
+        );
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = ModifyActorRequest {
+            name: str("kaitlyn"),
+            typestr: str("user"),
+            set_attributes: Some(Attributes::default()),
+            add_attributes: HashMap::from([(str("level"), values(&["4"]))]),
+            ..Default::default()
+        };
+        ds.modify_actor(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(status))
+            if status.message() == "set_attributes can't be combined with add_attributes or remove_attributes"));
+
+        let (tx, _) = channel::<DsResponse>();
+        let req = AddTargetRequest {
+            name: str("maindb"),
+            typestr: str("database"),
+            actions: vec![str("read"), str("write")],
+            ..Default::default()
+        };
+        ds.add_target(req, tx).await;
+
+        let (tx, _) = channel::<DsResponse>();
+        let req = ModifyTargetRequest {
+            name: str("maindb"),
+            typestr: str("database"),
+            set_actions: Some(Actions {
+                actions: vec![str("Read"), str("delete")],
+            }),
+            ..Default::default()
+        };
+        ds.modify_target(req, tx).await;
+        assert_eq!(
+            ds.targets.read().await["database"]["maindb"].actions,
+            HashSet::from([str("read"), str("delete")])
+        );
+
+        for role in ["reader", "writer", "admin"] {
+            let (tx, _) = channel::<DsResponse>();
+            let req = AddRoleRequest {
+                name: str(role),
+                ..Default::default()
+            };
+            ds.add_role(req, tx).await;
+        }
+        let (tx, _) = channel::<DsResponse>();
+        let req = AddGroupRequest {
+            name: str("eng"),
+            members: vec![GroupMember {
+                name: str("rob"),
+                typestr: str("user"),
+            }],
+            roles: vec![str("reader"), str("writer")],
+            ..Default::default()
+        };
+        ds.add_group(req, tx).await;
+
+        let (tx, _) = channel::<DsResponse>();
+        let req = ModifyGroupRequest {
+            name: str("eng"),
+            set_members: Some(GroupMembers {
+                members: vec![GroupMember {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                }],
+            }),
+            set_roles: Some(GroupRoles {
+                roles: vec![str("writer"), str("admin")],
+            }),
+            ..Default::default()
+        };
+        ds.modify_group(req, tx).await;
+        let groups = ds.groups.read().await;
+        let members: Vec<&str> = groups["eng"]
+            .members
+            .iter()
+            .map(|member| member.name.as_str())
+            .collect();
+        assert_eq!(members, vec!["kaitlyn"]);
+        assert_eq!(
+            groups["eng"].roles,
+            HashSet::from([str("writer"), str("admin")])
+        );
+        let roles = ds.roles.read().await;
+        assert!(roles["reader"].groups.is_empty());
+        assert!(roles["admin"].groups.contains("eng"));
+    }
+
+    #[test]
+    async fn test_sorted_lists() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        for (typestr, name) in [("queue", "b"), ("database", "c"), ("queue", "a")] {
+            let (tx, _) = channel::<DsResponse>();
+            let req = AddTargetRequest {
+                name: str(name),
+                typestr: str(typestr),
+                ..Default::default()
+            };
+            ds.add_target(req, tx).await;
+        }
+
+        for (sort_by, direction, expected) in [
+            (
+                SortBy::Name,
+                SortDirection::Ascending,
+                ["queue/a", "queue/b", "database/c"],
+            ),
+            (
+                SortBy::Type,
+                SortDirection::Ascending,
+                ["database/c", "queue/a", "queue/b"],
+            ),
+            (
+                SortBy::Type,
+                SortDirection::Descending,
+                ["queue/b", "queue/a", "database/c"],
+            ),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = GetTargetsRequest {
+                sort_by: sort_by.into(),
+                direction: direction.into(),
+                ..Default::default()
+            };
+            ds.get_targets(req, tx).await;
+            let targets = match rx.await {
+                Ok(DsResponse::MultipleTargets(targets, hits)) => {
+                    assert_eq!(hits.len(), targets.len());
+                    targets
+                }
+                _ => panic!("Expected targets"),
+            };
+            let names: Vec<String> = targets
+                .iter()
+                .map(|t| format!("{}/{}", t.typestr, t.name))
+                .collect();
+            assert_eq!(names, expected);
+        }
+    }
+
+    #[test]
+    async fn test_timestamps() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        for name in ["a", "b", "c"] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = AddRoleRequest {
+                name: str(name),
+                ..Default::default()
+            };
+            ds.add_role(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::SingleRole(role, _)) => {
+                    assert!(role.created_at > 0);
+                    assert_eq!(role.created_at, role.updated_at);
+                }
+                _ => panic!("Expected a role"),
+            }
+        }
+
+        // pretend the roles were added and changed at different times
+        for (name, created_at, updated_at) in [("a", 200, 300), ("b", 300, 350), ("c", 100, 400)] {
+            ds.roles.write().await.get_mut(name).unwrap().timestamps = Timestamps {
+                created_at,
+                updated_at,
+            };
+        }
+
+        // a change keeps when the role was added
+        let (tx, rx) = channel::<DsResponse>();
+        let req = ModifyRoleRequest {
+            name: str("b"),
+            desc: Some(str("changed")),
+            ..Default::default()
+        };
+        ds.modify_role(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::SingleRole(role, _)) => {
+                assert_eq!(role.created_at, 300);
+                assert!(role.updated_at > 400);
+            }
+            _ => panic!("Expected a role"),
+        }
+
+        for (sort_by, direction, expected) in [
+            (SortBy::CreatedAt, SortDirection::Ascending, ["c", "a", "b"]),
+            (SortBy::UpdatedAt, SortDirection::Ascending, ["a", "c", "b"]),
+            (
+                SortBy::UpdatedAt,
+                SortDirection::Descending,
+                ["b", "c", "a"],
+            ),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = GetRolesRequest {
+                sort_by: sort_by.into(),
+                direction: direction.into(),
+                ..Default::default()
+            };
+            ds.get_roles(req, tx).await;
+            let roles = match rx.await {
+                Ok(DsResponse::MultipleRoles(roles)) => roles,
+                _ => panic!("Expected roles"),
+            };
+            let names: Vec<&str> = roles.iter().map(|r| r.name.as_str()).collect();
+            assert_eq!(names, expected);
+        }
+
+        // a new policy is added now, whatever times the request claims
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddPolicyRequest {
+            rule: Some(PolicyRule {
+                name: str("backdated"),
+                created_at: 1,
+                updated_at: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::SinglePolicy(rule, _)) => assert!(rule.created_at > 1),
+            _ => panic!("Expected a policy"),
+        }
+        assert!(ds.policies.read().await["backdated"].timestamps.created_at > 1);
+    }
+
+    #[test]
+    async fn test_find_policies() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let rules = [
+            (
+                "eng-read",
+                "Engineers can read",
+                "allow",
+                vec!["eng"],
+                vec!["database"],
+            ),
+            (
+                "eng-deploy",
+                "Engineers can deploy",
+                "allow",
+                vec!["Eng", "ci"],
+                vec![],
+            ),
+            (
+                "no-prod",
+                "Nobody writes to prod",
+                "deny",
+                vec!["sox"],
+                vec!["queue"],
+            ),
+        ];
+        for (name, desc, decision, tags, target_types) in rules {
+            let (tx, _rx) = channel::<DsResponse>();
+            let decision = match decision {
+                "deny" => crate::proto::policies::Decide::Deny,
+                _ => crate::proto::policies::Decide::Allow,
+            };
+            let rule = PolicyRule {
+                name: str(name),
+                desc: Some(str(desc)),
+                decision: decision.into(),
+                tags: tags.into_iter().map(str).collect(),
+                target_types: target_types.into_iter().map(str).collect(),
+                ..Default::default()
+            };
+            let req = AddPolicyRequest {
+                rule: Some(rule),
+                ..Default::default()
+            };
+            ds.add_policy(req, tx).await;
+        }
+
+        let find = |req: GetPoliciesRequest| async {
+            let (tx, rx) = channel::<DsResponse>();
+            ds.get_policies(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::MultiplePolicies(rules)) => {
+                    rules.into_iter().map(|rule| rule.name).collect::<Vec<_>>()
+                }
+                _ => panic!("Expected policies"),
+            }
+        };
+
+        let req = GetPoliciesRequest {
+            tags: vec![str("ENG")],
+            ..Default::default()
+        };
+        assert_eq!(find(req).await, vec!["eng-deploy", "eng-read"]);
+        let req = GetPoliciesRequest {
+            tags: vec![str("eng"), str("ci")],
+            ..Default::default()
+        };
+        assert_eq!(find(req).await, vec!["eng-deploy"]);
+        let req = GetPoliciesRequest {
+            decision: Some(crate::proto::policies::Decide::Deny.into()),
+            ..Default::default()
+        };
+        assert_eq!(find(req).await, vec!["no-prod"]);
+
+        // a rule with no target types can apply to any
+        let req = GetPoliciesRequest {
+            target_type: Some(str("Database")),
+            ..Default::default()
+        };
+        assert_eq!(find(req).await, vec!["eng-deploy", "eng-read"]);
+
+        let req = GetPoliciesRequest {
+            search: Some(str("engineers READ")),
+            ..Default::default()
+        };
+        assert_eq!(find(req).await, vec!["eng-read"]);
+        let req = GetPoliciesRequest {
+            search: Some(str("prod")),
+            decision: Some(crate::proto::policies::Decide::Allow.into()),
+            ..Default::default()
+        };
+        assert!(find(req).await.is_empty());
+    }
+
+    #[test]
+    async fn test_policy_impact() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+        for (typestr, name) in [
+            ("database", "maindb"),
+            ("database", "testdb"),
+            ("webapp", "site"),
+        ] {
+            let (tx, _rx) = channel::<DsResponse>();
+            let req = AddTargetRequest {
+                name: str(name),
+                typestr: str(typestr),
+                actions: vec![str("read"), str("write")],
+                ..Default::default()
+            };
+            ds.add_target(req, tx).await;
+        }
+        for (typestr, name) in [("user", "alice"), ("user", "bob"), ("service", "builder")] {
+            let (tx, _rx) = channel::<DsResponse>();
+            let req = AddActorRequest {
+                name: str(name),
+                typestr: str(typestr),
+                ..Default::default()
+            };
+            ds.add_actor(req, tx).await;
+        }
+        let bob = RegisteredGroupMember {
+            name: str("bob"),
+            typestr: intern("user"),
+        };
+        let group = RegisteredGroup::new("admins", None, HashSet::from([bob]), HashSet::new());
+        ds.groups.write().await.insert(str("admins"), group);
+
+        let impact = |resp: Result<DsResponse, _>| match resp {
+            Ok(DsResponse::SinglePolicy(_, Some(impact))) => impact,
+            _ => panic!("expected a policy with its impact"),
+        };
+
+        // actors are judged with their groups
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddPolicyRequest {
+            rule: Some(PolicyRule {
+                name: str("admins"),
+                actor_check: Some(crate::proto::policies::ActorCheck {
+                    attributes: vec![crate::proto::policies::KvCheck {
+                        key: str("member-of"),
+                        op: crate::proto::policies::Kv::Has.into(),
+                        vals: vec![str("admins")],
+                        count: None,
+                    }],
+                    ..Default::default()
+                }),
+                target_types: vec![str("database")],
+                ..Default::default()
+            }),
+            dry_run: false,
+        };
+        ds.add_policy(req, tx).await;
+        let added = impact(rx.await);
+        assert_eq!(added.actors, 1);
+        assert_eq!(added.targets, 2);
+        assert_eq!(added.target_types, vec![str("database")]);
+        assert!(added.unknown_actions.is_empty());
+
+        // a modify can affect what the policy applied to before and applies to after
+        let (tx, rx) = channel::<DsResponse>();
+        let req = ModifyPolicyRequest {
+            rule: Some(PolicyRule {
+                name: str("admins"),
+                target_types: vec![str("webapp")],
+                // actions no registered target has are reported, as they are likely typos
+                target_check: Some(crate::proto::policies::TargetCheck {
+                    action: Some(crate::proto::policies::StringCheck {
+                        val_cmp: crate::proto::policies::Set::Has.into(),
+                        vals: vec![str("read"), str("Raed"), str("*")],
+                    }),
+                    action_except: vec![str("write"), str("drop")],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            dry_run: false,
+        };
+        ds.modify_policy(req, tx).await;
+        let modified = impact(rx.await);
+        assert_eq!(modified.actors, 3);
+        assert_eq!(modified.targets, 3);
+        assert_eq!(modified.target_types, vec![str("database"), str("webapp")]);
+        assert_eq!(modified.unknown_actions, vec![str("drop"), str("raed")]);
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = RemovePolicyRequest {
+            name: str("admins"),
+            dry_run: true,
+        };
+        ds.remove_policy(req, tx).await;
+        let removed = impact(rx.await);
+        assert_eq!(removed.actors, 3);
+        assert_eq!(removed.target_types, vec![str("webapp")]);
+    }
+
+    #[test]
+    async fn test_action_except_ignores_case() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddTargetRequest {
+            name: str("db"),
+            typestr: str("database"),
+            actions: vec![str("read"), str("delete")],
+            ..Default::default()
+        };
+        ds.add_target(req, tx).await;
+
+        // allow everything but deletes
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddPolicyRequest {
+            rule: Some(PolicyRule {
+                name: str("no-deletes"),
+                target_check: Some(crate::proto::policies::TargetCheck {
+                    action: Some(crate::proto::policies::StringCheck {
+                        val_cmp: crate::proto::policies::Set::Has.into(),
+                        vals: vec![str("*")],
+                    }),
+                    action_except: vec![str("Delete")],
+                    ..Default::default()
+                }),
+                decision: crate::proto::policies::Decide::Allow.into(),
+                ..Default::default()
+            }),
+            dry_run: false,
+        };
+        ds.add_policy(req, tx).await;
+
+        // a check can't get around the exception by changing the case of the action
+        for (action, expected) in [
+            ("read", crate::proto::policies::Decide::Allow),
+            ("READ", crate::proto::policies::Decide::Allow),
+            ("delete", crate::proto::policies::Decide::NotApplicable),
+            ("DELETE", crate::proto::policies::Decide::NotApplicable),
+            ("DeLeTe", crate::proto::policies::Decide::NotApplicable),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str(action)],
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::CheckResult(resp)) => assert_eq!(resp.decision(), expected),
+                _ => panic!("expected a check result"),
+            }
+        }
+    }
+
+    #[test]
+    async fn test_quotas() {
+        // the quota is on every actor, whichever shard their type is in
+        for shards in [1, 8] {
+            let (req_tx, req_rx) = flume::unbounded();
+            let config = Config {
+                quotas: Quotas {
+                    max_actors: Some(1),
+                    max_policies: None,
+                    max_group_size: None,
+                },
+                shards,
+                ..Default::default()
+            };
+            let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+            let (tx, rx) = channel::<DsResponse>();
+            let req = AddActorRequest {
+                name: str("first"),
+                typestr: str("user"),
+                attributes: HashMap::new(),
+                ..Default::default()
+            };
+            ds.add_actor(req, tx).await;
+            assert!(matches!(rx.await, Ok(DsResponse::SingleActor(..))));
+
+            for typestr in ["user", "service", "bot", "team", "device"] {
+                let (tx, rx) = channel::<DsResponse>();
+                let req = AddActorRequest {
+                    name: str("second"),
+                    typestr: str(typestr),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                };
+                ds.add_actor(req, tx).await;
+                match rx.await {
+                    Ok(DsResponse::Error(status)) => {
+                        assert_eq!(status.code(), tonic::Code::ResourceExhausted)
+                    }
+                    _ => panic!("expected the actor quota to be enforced"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    async fn test_coverage() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let config = Config {
+            recorded_checks: 2,
+            ..Default::default()
+        };
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+        for (name, typestr) in [("users", "user"), ("services", "service")] {
+            ds.policies.write().await.insert(
+                str(name),
+                RegisteredPolicyRule {
+                    name: str(name),
+                    desc: None,
+                    actor_check: Some(ActorCheck {
+                        name: None,
+                        typestr: Some(StringCheck::OneOf(vec![str(typestr)])),
+                        attributes: vec![],
+                        bucket: None,
+                    }),
+                    env_attributes: vec![],
+                    target_check: None,
+                    decision: Decide::Allow,
+                    mode: Mode::Enforce,
+                    wasm_module: None,
+                    compare_checks: vec![],
+                    cidr_checks: vec![],
+                    target_types: vec![],
+                    rate_checks: vec![],
+                    risk: None,
+                    tags: vec![],
+                    timestamps: Timestamps::default(),
+                },
+            );
+        }
+
+        // the first check should fall out of the ring buffer
+        for (name, typestr) in [("old", "service"), ("kaitlyn", "user"), ("robot", "bot")] {
+            let (tx, _) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str(name),
+                    typestr: str(typestr),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                env_attributes: HashMap::new(),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str("read")],
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+        }
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.coverage_report(CoverageReportRequest {}, tx).await;
+        match rx.await {
+            Ok(DsResponse::CoverageResult(report)) => {
+                assert_eq!(report.requests, 2);
+                assert_eq!(report.unmatched_policies, vec![str("services")]);
+                assert_eq!(report.default_decisions.len(), 1);
+
+                let actor = report.default_decisions[0].actor.clone().unwrap();
+                assert_eq!(actor.typestr, "bot");
+                assert_ne!(actor.name, "robot");
+            }
+            _ => panic!("expected a coverage report"),
+        }
+    }
+
+    #[test]
+    async fn test_shadow_policy() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        for (name, decision, mode) in [
+            ("allow-all", Decide::Allow, Mode::Enforce),
+            ("deny-all", Decide::Deny, Mode::Shadow),
+        ] {
+            ds.policies.write().await.insert(
+                str(name),
+                RegisteredPolicyRule {
+                    name: str(name),
+                    desc: None,
+                    actor_check: None,
+                    env_attributes: vec![],
+                    target_check: None,
+                    decision,
+                    mode,
+                    wasm_module: None,
+                    compare_checks: vec![],
+                    cidr_checks: vec![],
+                    target_types: vec![],
+                    rate_checks: vec![],
+                    risk: None,
+                    tags: vec![],
+                    timestamps: Timestamps::default(),
+                },
+            );
+        }
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = CheckRequest {
+            actor: Some(Actor {
+                name: str("kaitlyn"),
+                typestr: str("user"),
+                attributes: HashMap::new(),
+                ..Default::default()
+            }),
+            env_attributes: HashMap::new(),
+            target_name: str("db"),
+            target_type: str("database"),
+            target_action: vec![str("read")],
+            ..Default::default()
+        };
+        ds.check(req, tx).await;
+
+        match rx.await {
+            Ok(DsResponse::CheckResult(resp)) => {
+                assert_eq!(resp.decision(), crate::proto::policies::Decide::Allow)
+            }
+            _ => panic!("expected a check result"),
+        }
+    }
+
+    #[test]
+    async fn test_eval_limits() {
+        for (fail_open, actions, expected) in [
+            (false, vec!["read"], crate::proto::policies::Decide::Allow),
+            (
+                false,
+                vec!["read", "write"],
+                crate::proto::policies::Decide::Deny,
+            ),
+            (
+                true,
+                vec!["read", "write"],
+                crate::proto::policies::Decide::Allow,
+            ),
+        ] {
+            let (req_tx, req_rx) = flume::unbounded();
+            let config = Config {
+                eval_limits: EvalLimits {
+                    max_policy_evals: Some(2),
+                    fail_open,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+            for name in ["allow-all", "allow-everyone"] {
+                ds.policies.write().await.insert(
+                    str(name),
+                    RegisteredPolicyRule {
+                        name: str(name),
+                        desc: None,
+                        actor_check: None,
+                        env_attributes: vec![],
+                        target_check: None,
+                        decision: Decide::Allow,
+                        mode: Mode::Enforce,
+                        wasm_module: None,
+                        compare_checks: vec![],
+                        cidr_checks: vec![],
+                        target_types: vec![],
+                        rate_checks: vec![],
+                        risk: None,
+                        tags: vec![],
+                        timestamps: Timestamps::default(),
+                    },
+                );
+            }
+
+            // two rules for each action, so only checks of a single action stay within the limit
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: actions.into_iter().map(str).collect(),
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::CheckResult(resp)) => assert_eq!(resp.decision(), expected),
+                _ => panic!("expected a check result"),
+            }
+        }
+    }
+
+    #[test]
+    async fn test_group_depth_limit() {
+        for (max_group_depth, expected) in [
+            (None, crate::proto::policies::Decide::Allow),
+            (Some(3), crate::proto::policies::Decide::Allow),
+            (Some(2), crate::proto::policies::Decide::Deny),
+        ] {
+            let (req_tx, req_rx) = flume::unbounded();
+            let config = Config {
+                eval_limits: EvalLimits {
+                    max_group_depth,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+            // kaitlyn is in the team, the team is in the department, and the department is in
+            // the org, which is in the team again
+            let kaitlyn = RegisteredGroupMember {
+                name: str("kaitlyn"),
+                typestr: intern("user"),
+            };
+            for (name, member) in [
+                ("team", kaitlyn),
+                ("dept", RegisteredGroupMember::group("team")),
+                ("org", RegisteredGroupMember::group("dept")),
+            ] {
+                let members = HashSet::from([member, RegisteredGroupMember::group("org")]);
+                let group = RegisteredGroup::new(name, None, members, HashSet::new());
+                ds.groups.write().await.insert(str(name), group);
+            }
+
+            let (tx, _) = channel::<DsResponse>();
+            let req = AddPolicyRequest {
+                rule: Some(PolicyRule {
+                    name: str("org"),
+                    actor_check: Some(crate::proto::policies::ActorCheck {
+                        attributes: vec![crate::proto::policies::KvCheck {
+                            key: str("member-of"),
+                            op: crate::proto::policies::Kv::Has.into(),
+                            vals: vec![str("org")],
+                            count: None,
+                        }],
+                        ..Default::default()
+                    }),
+                    decision: crate::proto::policies::Decide::Allow.into(),
+                    ..Default::default()
+                }),
+                dry_run: false,
+            };
+            ds.add_policy(req, tx).await;
+
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str("read")],
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::CheckResult(resp)) => assert_eq!(resp.decision(), expected),
+                _ => panic!("expected a check result"),
+            }
+
+            // the org's members are whoever its groups have, as deep as the limit allows
+            let (tx, rx) = channel::<DsResponse>();
+            let req = GetGroupMembersRequest {
+                name: str("org"),
+                ..Default::default()
+            };
+            ds.get_group_members(req, tx).await;
+            let members = match rx.await {
+                Ok(DsResponse::GroupMembers(page)) => page.members,
+                _ => panic!("expected the members"),
+            };
+            assert_eq!(members.len(), usize::from(max_group_depth != Some(2)));
+        }
+    }
+
+    #[test]
+    async fn test_kept_expansions() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let (tx, _) = channel::<DsResponse>();
+        let req = AddActorRequest {
+            name: str("kaitlyn"),
+            typestr: str("user"),
+            attributes: HashMap::from([(
+                str("team"),
+                AttributeValues {
+                    values: vec![str("storage")],
+                },
+            )]),
+            ..Default::default()
+        };
+        ds.add_actor(req, tx).await;
+        let (tx, _) = channel::<DsResponse>();
+        let req = AddGroupRequest {
+            name: str("storage"),
+            members: vec![GroupMember {
+                name: str("kaitlyn"),
+                typestr: str("user"),
+            }],
+            ..Default::default()
+        };
+        ds.add_group(req, tx).await;
+
+        // the groups kept for the actor are forgotten once the groups change
+        let actor = RegisteredActor::new("kaitlyn", "user", AttributeMap::default());
+        let extended = ds
+            .extend_actor(actor.clone(), &EvalBudget::unlimited())
+            .await;
+        assert!(extended.attributes.contains_key("member-of"));
+        ds.groups.write().await.clear();
+        let extended = ds.extend_actor(actor, &EvalBudget::unlimited()).await;
+        assert!(!extended.attributes.contains_key("member-of"));
+        assert!(extended.attributes.contains_key("team"));
+    }
+
+    #[test]
+    async fn test_interned_entities() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        for name in ["kaitlyn", "ada"] {
+            let (tx, _) = channel::<DsResponse>();
+            let req = AddActorRequest {
+                name: str(name),
+                typestr: str("User"),
+                attributes: HashMap::from([(
+                    str("team"),
+                    AttributeValues {
+                        values: vec![str("storage")],
+                    },
+                )]),
+                ..Default::default()
+            };
+            ds.add_actor(req, tx).await;
+        }
+        let (tx, _) = channel::<DsResponse>();
+        let req = AddGroupRequest {
+            name: str("storage"),
+            members: vec![GroupMember {
+                name: str("ada"),
+                typestr: str("user"),
+            }],
+            ..Default::default()
+        };
+        ds.add_group(req, tx).await;
+
+        // actors of a type share its name, their attribute names, and their values
+        let actors = ds.actors.read().await;
+        let (typestr, users) = actors.iter().next().unwrap();
+        let kaitlyn = users.get("kaitlyn").unwrap();
+        let ada = users.get("ada").unwrap();
+        assert!(Arc::ptr_eq(typestr, &kaitlyn.typestr));
+        assert!(Arc::ptr_eq(&kaitlyn.typestr, &ada.typestr));
+        let (name, vals) = kaitlyn.attributes.get_key_value("team").unwrap();
+        let (other_name, other_vals) = ada.attributes.get_key_value("team").unwrap();
+        assert!(Arc::ptr_eq(name, other_name));
+        assert!(Arc::ptr_eq(
+            vals.get("storage").unwrap(),
+            other_vals.get("storage").unwrap()
+        ));
+
+        // and so do the group members
+        let groups = ds.groups.read().await;
+        let member = groups["storage"].members.iter().next().unwrap();
+        assert!(Arc::ptr_eq(&member.typestr, &ada.typestr));
+    }
+
+    #[test]
+    async fn test_deny_streak_lockout() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let config = Config {
+            deny_streaks: DenyStreakConfig {
+                threshold: Some(2),
+                window: 60,
+                lockout: 60,
+            },
+            ..Default::default()
+        };
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+        ds.policies.write().await.insert(
+            str("deny-all"),
+            RegisteredPolicyRule {
+                name: str("deny-all"),
+                desc: None,
+                actor_check: None,
+                env_attributes: vec![],
+                target_check: None,
+                decision: Decide::Deny,
+                mode: Mode::Enforce,
+                wasm_module: None,
+                compare_checks: vec![],
+                cidr_checks: vec![],
+                target_types: vec![],
+                rate_checks: vec![],
+                risk: None,
+                tags: vec![],
+                timestamps: Timestamps::default(),
+            },
+        );
+
+        // the second deny makes a streak, and the checks after it are denied by the lockout
+        let mut sources = vec![];
+        for _ in 0..3 {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str("read")],
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::CheckResult(resp)) => {
+                    assert_eq!(resp.decision(), crate::proto::policies::Decide::Deny);
+                    sources.push(resp.source());
+                }
+                _ => panic!("expected a check result"),
+            }
+        }
+        assert_eq!(
+            sources,
+            vec![
+                DecisionSource::Evaluated,
+                DecisionSource::Evaluated,
+                DecisionSource::LockedOut
+            ]
+        );
+        assert_eq!(ds.deny_streaks.stats(), (1, 1));
+    }
+
+    #[test]
+    async fn test_policy_stats() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddPolicyRequest {
+            rule: Some(crate::proto::policies::PolicyRule {
+                name: str("allow-all"),
+                decision: crate::proto::policies::Decide::Allow.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SinglePolicy(..))));
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = CheckRequest {
+            actor: Some(Actor {
+                name: str("kaitlyn"),
+                typestr: str("user"),
+                attributes: HashMap::new(),
+                ..Default::default()
+            }),
+            target_name: str("db"),
+            target_type: str("database"),
+            target_action: vec![str("read"), str("write")],
+            action_mode: ActionMode::EachAction.into(),
+            ..Default::default()
+        };
+        ds.check(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::CheckResult(_))));
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.get_policy_stats(GetPolicyStatsRequest { top: 0 }, tx)
+            .await;
+        match rx.await {
+            Ok(DsResponse::PolicyStats(stats)) => {
+                assert_eq!(stats.policies.len(), 1);
+                assert_eq!(stats.policies[0].name, "allow-all");
+                assert_eq!(stats.policies[0].evaluations, 2);
+            }
+            _ => panic!("expected policy stats"),
+        }
+    }
+
+    #[test]
+    async fn test_strict_checks() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let config = Config {
+            strict_checks: true,
+            ..Default::default()
+        };
+        let mut ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddTargetRequest {
+            name: str("db"),
+            typestr: str("database"),
+            actions: vec![str("read"), str("write")],
+            ..Default::default()
+        };
+        ds.add_target(req, tx).await;
+
+        for (name, typestr, actions, allowed) in [
+            ("db", "database", vec![str("read")], true),
+            // the target is found whatever the case of the check
+            ("DB", "Database", vec![str("READ"), str("*")], true),
+            ("web", "database", vec![str("read")], false),
+            ("db", "database", vec![str("delete")], false),
+            ("db", "database", vec![], false),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str(name),
+                target_type: str(typestr),
+                target_action: actions,
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::CheckResult(_)) => assert!(allowed),
+                Ok(DsResponse::Error(status)) => {
+                    assert!(!allowed);
+                    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+                }
+                _ => panic!("expected a check result"),
+            }
+        }
+
+        // checks that would be rejected can be decided INDETERMINATE instead, and not cached
+        ds.config.strict_indeterminate = true;
+        ds.config.decision_ttl = 60;
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddPolicyRequest {
+            rule: Some(crate::proto::policies::PolicyRule {
+                name: str("allow-all"),
+                decision: crate::proto::policies::Decide::Allow.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SinglePolicy(..))));
+
+        let indeterminate = crate::proto::policies::Decide::Indeterminate;
+        let allow = crate::proto::policies::Decide::Allow;
+        for (name, actions, decisions) in [
+            // an unregistered target leaves nothing to evaluate
+            (
+                "web",
+                vec![str("read"), str("write")],
+                vec![indeterminate, indeterminate],
+            ),
+            // the target's actions are evaluated, and only the unknown one is INDETERMINATE
+            (
+                "db",
+                vec![str("raed"), str("read"), str("write")],
+                vec![indeterminate, allow, allow],
+            ),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str(name),
+                target_type: str("database"),
+                target_action: actions.clone(),
+                action_mode: ActionMode::EachAction.into(),
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::CheckResult(resp)) => {
+                    assert_eq!(resp.decision(), indeterminate);
+                    assert_eq!(resp.source(), DecisionSource::Unregistered);
+                    assert_eq!(resp.cache_ttl, 0);
+                    let got: Vec<(String, crate::proto::policies::Decide)> = resp
+                        .action_decisions
+                        .iter()
+                        .map(|action| (action.action.clone(), action.decision()))
+                        .collect();
+                    let expected: Vec<(String, crate::proto::policies::Decide)> =
+                        actions.into_iter().zip(decisions).collect();
+                    assert_eq!(got, expected);
+                }
+                _ => panic!("expected a check result"),
+            }
+        }
+    }
+
+    #[test]
+    async fn test_rate_checks() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        // allow everything, but no more than two deletes a minute
+        let rate_check = |window_seconds| crate::proto::policies::RateCheck {
+            actions: vec![str("delete")],
+            limit: 2,
+            window_seconds,
+        };
+        let deletes = crate::proto::policies::TargetCheck {
+            action: Some(crate::proto::policies::StringCheck {
+                val_cmp: crate::proto::policies::Set::Has.into(),
+                vals: vec![str("delete")],
+            }),
+            ..Default::default()
+        };
+        for (name, decision, target_check, rate_checks) in [
+            ("allow-all", Decide::Allow, None, vec![]),
+            (
+                "slow-deletes",
+                Decide::Deny,
+                Some(deletes),
+                vec![rate_check(60)],
+            ),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let rule = PolicyRule {
+                name: str(name),
+                decision: crate::proto::policies::Decide::from(decision).into(),
+                target_check,
+                rate_checks,
+                ..Default::default()
+            };
+            let req = AddPolicyRequest {
+                rule: Some(rule),
+                ..Default::default()
+            };
+            ds.add_policy(req, tx).await;
+            assert!(matches!(rx.await, Ok(DsResponse::SinglePolicy(..))));
+        }
+
+        let check = |action: &str| {
+            let ds = &ds;
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str(action)],
+                ..Default::default()
+            };
+            async move {
+                let (tx, rx) = channel::<DsResponse>();
+                ds.check(req, tx).await;
+                match rx.await {
+                    Ok(DsResponse::CheckResult(resp)) => resp.decision,
+                    _ => panic!("expected a check result"),
+                }
+            }
+        };
+        let allow = i32::from(crate::proto::policies::Decide::Allow);
+        let deny = i32::from(crate::proto::policies::Decide::Deny);
+
+        assert_eq!(check("delete").await, allow);
+        assert_eq!(check("delete").await, allow);
+        assert_eq!(check("delete").await, deny);
+        // other actions aren't limited, and denied deletes don't count
+        assert_eq!(check("read").await, allow);
+        assert_eq!(ds.rates.stats(), (1, 2));
+
+        // a window longer than a day can't be counted
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddPolicyRequest {
+            rule: Some(PolicyRule {
+                name: str("slow-forever"),
+                rate_checks: vec![rate_check(24 * 60 * 60 + 1)],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(_))));
+    }
+
+    #[test]
+    async fn test_risk() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let config = Config {
+            risk: Some(crate::risk::RiskConfig {
+                signals: vec![crate::risk::SignalConfig::UnusualValue {
+                    key: str("country"),
+                    usual: vec![str("us")],
+                    weight: 30,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+        // allow everything, unless it is risky
+        let risky = crate::proto::policies::NumberCheck {
+            op: crate::proto::policies::Num::MoreThan.into(),
+            val: 20,
+        };
+        for (name, decision, risk) in [
+            ("allow-all", Decide::Allow, None),
+            ("deny-risky", Decide::Deny, Some(risky)),
+        ] {
+            let (tx, _rx) = channel::<DsResponse>();
+            let rule = PolicyRule {
+                name: str(name),
+                decision: crate::proto::policies::Decide::from(decision).into(),
+                risk,
+                ..Default::default()
+            };
+            let req = AddPolicyRequest {
+                rule: Some(rule),
+                ..Default::default()
+            };
+            ds.add_policy(req, tx).await;
+        }
+
+        for (country, risk, allowed) in [("us", "0", true), ("fr", "0", false)] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                // the PEP can't lower the score
+                env_attributes: HashMap::from([
+                    (
+                        str("country"),
+                        AttributeValues {
+                            values: vec![str(country)],
+                        },
+                    ),
+                    (
+                        str("risk"),
+                        AttributeValues {
+                            values: vec![str(risk)],
+                        },
+                    ),
+                ]),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str("read")],
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::CheckResult(resp)) => {
+                    assert_eq!(
+                        resp.decision == crate::proto::policies::Decide::Allow as i32,
+                        allowed
+                    )
+                }
+                _ => panic!("expected a check result"),
+            }
+        }
+    }
+
+    #[test]
+    async fn test_risk_unscored() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        // allow what isn't risky, with no risk scoring configured
+        let (tx, _rx) = channel::<DsResponse>();
+        let rule = PolicyRule {
+            name: str("allow-safe"),
+            decision: crate::proto::policies::Decide::Allow.into(),
+            risk: Some(crate::proto::policies::NumberCheck {
+                op: crate::proto::policies::Num::LessThan.into(),
+                val: 10,
+            }),
+            ..Default::default()
+        };
+        let req = AddPolicyRequest {
+            rule: Some(rule),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+
+        // the PEP can't claim a score of its own
+        let (tx, rx) = channel::<DsResponse>();
+        let req = CheckRequest {
+            actor: Some(Actor {
+                name: str("kaitlyn"),
+                typestr: str("user"),
+                attributes: HashMap::new(),
+                ..Default::default()
+            }),
+            env_attributes: HashMap::from([(
+                str("risk"),
+                AttributeValues {
+                    values: vec![str("0")],
+                },
+            )]),
+            target_name: str("db"),
+            target_type: str("database"),
+            target_action: vec![str("read")],
+            ..Default::default()
+        };
+        ds.check(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::CheckResult(resp)) => {
+                assert_ne!(resp.decision(), crate::proto::policies::Decide::Allow)
+            }
+            _ => panic!("expected a check result"),
+        }
+    }
+
+    #[test]
+    async fn test_approvals() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        // deletes need two approvers
+        let (tx, _rx) = channel::<DsResponse>();
+        let rule = PolicyRule {
+            name: str("two-person-delete"),
+            decision: crate::proto::policies::Decide::AllowWithApproval.into(),
+            approvals: 2,
+            ..Default::default()
+        };
+        let req = AddPolicyRequest {
+            rule: Some(rule),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+
+        let check = |approval_id: u64| CheckRequest {
+            actor: Some(Actor {
+                name: str("kaitlyn"),
+                typestr: str("user"),
+                attributes: HashMap::new(),
+                ..Default::default()
+            }),
+            target_name: str("db"),
+            target_type: str("database"),
+            target_action: vec![str("delete")],
+            approval_id,
+            ..Default::default()
+        };
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.check(check(0), tx).await;
+        let id = match rx.await {
+            Ok(DsResponse::CheckResult(resp)) => {
+                assert_eq!(
+                    resp.decision,
+                    crate::proto::policies::Decide::Pending as i32
+                );
+                assert_eq!(resp.cache_ttl, 0);
+                resp.approval_id
+            }
+            _ => panic!("expected a check result"),
+        };
+        assert!(id > 0);
+
+        // nobody approves their own check, or approves it twice
+        for (approver, ok) in [("kaitlyn", false), ("maria", true), ("maria", false)] {
+            let (tx, rx) = channel::<DsResponse>();
+            ds.approve(id, str(approver), tx).await;
+            assert_eq!(
+                matches!(rx.await, Ok(DsResponse::SingleApproval(_))),
+                ok,
+                "{approver}"
+            );
+        }
+
+        // one approver isn't enough
+        let (tx, rx) = channel::<DsResponse>();
+        ds.check(check(id), tx).await;
+        match rx.await {
+            Ok(DsResponse::CheckResult(resp)) => {
+                assert_eq!(resp.approval_id, id);
+                assert_eq!(
+                    resp.decision,
+                    crate::proto::policies::Decide::Pending as i32
+                );
+            }
+            _ => panic!("expected a check result"),
+        }
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.approve(id, str("jorge"), tx).await;
+        match rx.await {
+            Ok(DsResponse::SingleApproval(approval)) => assert!(approval.approved_at > 0),
+            _ => panic!("expected an approval"),
+        }
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.check(check(id), tx).await;
+        match rx.await {
+            Ok(DsResponse::CheckResult(resp)) => {
+                assert_eq!(resp.decision, crate::proto::policies::Decide::Allow as i32)
+            }
+            _ => panic!("expected a check result"),
+        }
+
+        // the approval is only good for the check it was asked for
+        let (tx, rx) = channel::<DsResponse>();
+        let mut other = check(id);
+        other.target_name = str("other-db");
+        ds.check(other, tx).await;
+        match rx.await {
+            Ok(DsResponse::CheckResult(resp)) => assert_ne!(resp.approval_id, id),
+            _ => panic!("expected a check result"),
+        }
+    }
+
+    #[test]
+    async fn test_break_glass() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let config = Config {
+            break_glass_role: Some(str("oncall")),
+            ..Default::default()
+        };
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+        // maria is on call; everyone can read and write, but nobody can write during the freeze
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddRoleRequest {
+            name: str("oncall"),
+            ..Default::default()
+        };
+        ds.add_role(req, tx).await;
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddGroupRequest {
+            name: str("sre"),
+            members: vec![GroupMember {
+                name: str("maria"),
+                typestr: str("user"),
+            }],
+            roles: vec![str("oncall")],
+            ..Default::default()
+        };
+        ds.add_group(req, tx).await;
+        for (name, decision, actions) in [
+            ("staff", Decide::Allow, vec![str("read"), str("write")]),
+            ("freeze", Decide::Deny, vec![str("write")]),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let rule = PolicyRule {
+                name: str(name),
+                decision: crate::proto::policies::Decide::from(decision).into(),
+                target_check: Some(crate::proto::policies::TargetCheck {
+                    action: Some(crate::proto::policies::StringCheck {
+                        val_cmp: crate::proto::policies::Set::Has.into(),
+                        vals: actions,
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let req = AddPolicyRequest {
+                rule: Some(rule),
+                ..Default::default()
+            };
+            ds.add_policy(req, tx).await;
+            assert!(matches!(rx.await, Ok(DsResponse::SinglePolicy(..))));
+        }
+
+        // the actor is checked in another case than the glass was broken for
+        let check = |target_type: &str, action: &str| {
+            let ds = &ds;
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("KAITLYN"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str("db"),
+                target_type: str(target_type),
+                target_action: vec![str(action)],
+                ..Default::default()
+            };
+            async move {
+                let (tx, rx) = channel::<DsResponse>();
+                ds.check(req, tx).await;
+                match rx.await {
+                    Ok(DsResponse::CheckResult(resp)) => (resp.decision(), resp.break_glasses),
+                    _ => panic!("expected a check result"),
+                }
+            }
+        };
+        let break_glass = |caller: &str, req: BreakGlassRequest| {
+            let ds = &ds;
+            let caller = str(caller);
+            async move {
+                let (tx, rx) = channel::<DsResponse>();
+                ds.break_glass(req, caller, tx).await;
+                match rx.await {
+                    Ok(DsResponse::SingleBreakGlass(break_glass)) => Ok(break_glass.id),
+                    Ok(DsResponse::Error(status)) => Err(status.code()),
+                    _ => panic!("expected a break glass"),
+                }
+            }
+        };
+        let elevate = BreakGlassRequest {
+            actor_type: str("user"),
+            actor_name: str("Kaitlyn"),
+            target_type: str("database"),
+            minutes: 30,
+            reason: str("outage"),
+            ..Default::default()
+        };
+        let bypass = |policy: &str| BreakGlassRequest {
+            policy: str(policy),
+            minutes: 30,
+            reason: str("hotfix"),
+            ..Default::default()
+        };
+
+        assert_eq!(check("database", "write").await.0, Decide::Deny.into());
+        assert_eq!(
+            check("database", "delete").await.0,
+            Decide::NotApplicable.into()
+        );
+
+        // only the on call role can break the glass, and only for good reasons; the caller is
+        // matched by type and name
+        for caller in ["user/kaitlyn", "maria", "bot/maria"] {
+            assert_eq!(
+                break_glass(caller, elevate.clone()).await,
+                Err(tonic::Code::PermissionDenied)
+            );
+        }
+        for req in [
+            BreakGlassRequest {
+                minutes: 0,
+                ..elevate.clone()
+            },
+            BreakGlassRequest {
+                reason: str(""),
+                ..elevate.clone()
+            },
+            BreakGlassRequest {
+                policy: str("freeze"),
+                ..elevate.clone()
+            },
+            bypass("staff"),
+        ] {
+            assert_eq!(
+                break_glass("user/maria", req).await,
+                Err(tonic::Code::InvalidArgument)
+            );
+        }
+
+        // the freeze no longer applies
+        let bypassed = break_glass("user/maria", bypass("freeze")).await.unwrap();
+        let (decision, break_glasses) = check("database", "write").await;
+        assert_eq!(decision, Decide::Allow.into());
+        assert_eq!(break_glasses, vec![bypassed]);
+        assert!(check("database", "read").await.1.is_empty());
+
+        // kaitlyn can do anything to databases, but nothing more elsewhere
+        let elevated = break_glass("user/maria", elevate).await.unwrap();
+        let (decision, break_glasses) = check("database", "delete").await;
+        assert_eq!(decision, Decide::Allow.into());
+        assert_eq!(break_glasses, vec![elevated]);
+        assert_eq!(
+            check("queue", "delete").await.0,
+            Decide::NotApplicable.into()
+        );
+
+        // once the time is up, everything is as it was
+        for break_glass in ds.break_glasses.write().await.values_mut() {
+            break_glass.expires_at = now();
+        }
+        assert_eq!(check("database", "write").await.0, Decide::Deny.into());
+        assert_eq!(
+            check("database", "delete").await.0,
+            Decide::NotApplicable.into()
+        );
+        ds.end_break_glasses().await;
+        assert!(ds.break_glasses.read().await.is_empty());
+    }
+
+    #[test]
+    async fn test_delegations() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        // only the manager can read or write the database
+        let (tx, rx) = channel::<DsResponse>();
+        let rule = PolicyRule {
+            name: str("managers"),
+            actor_check: Some(crate::proto::policies::ActorCheck {
+                name: Some(crate::proto::policies::StringCheck {
+                    val_cmp: crate::proto::policies::Set::Has.into(),
+                    vals: vec![str("manager")],
+                }),
+                ..Default::default()
+            }),
+            decision: crate::proto::policies::Decide::Allow.into(),
+            ..Default::default()
+        };
+        let req = AddPolicyRequest {
+            rule: Some(rule),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SinglePolicy(..))));
+
+        let delegation = |name: &str, delegate_name: &str, expires_at: u64| Delegation {
+            name: str(name),
+            delegator_type: str("user"),
+            delegator_name: str("manager"),
+            delegate_type: str("user"),
+            delegate_name: str(delegate_name),
+            target_type: str("database"),
+            target_names: vec![str("db")],
+            actions: vec![str("read")],
+            expires_at,
+            ..Default::default()
+        };
+        for (delegation, ok) in [
+            (delegation("cover", "kaitlyn", now() + 60), true),
+            (delegation("cover", "kaitlyn", now() + 60), false),
+            (delegation("self", "manager", now() + 60), false),
+            (delegation("expired", "kaitlyn", now() - 1), false),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = AddDelegationRequest {
+                delegation: Some(delegation),
+                dry_run: false,
+            };
+            ds.add_delegation(req, tx).await;
+            assert_eq!(matches!(rx.await, Ok(DsResponse::SingleDelegation(_))), ok);
+        }
+
+        let check = |name: &str, target_name: &str, action: &str| {
+            let ds = &ds;
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str(name),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str(target_name),
+                target_type: str("database"),
+                target_action: vec![str(action)],
+                ..Default::default()
+            };
+            async move {
+                let (tx, rx) = channel::<DsResponse>();
+                ds.check(req, tx).await;
+                match rx.await {
+                    Ok(DsResponse::CheckResult(resp)) => (resp.decision(), resp.delegations),
+                    _ => panic!("expected a check result"),
+                }
+            }
+        };
+
+        assert_eq!(
+            check("kaitlyn", "db", "read").await,
+            (Decide::Allow.into(), vec![str("cover")])
+        );
+        assert_eq!(
+            check("kaitlyn", "db", "write").await.0,
+            Decide::NotApplicable.into()
+        );
+        assert_eq!(
+            check("kaitlyn", "other-db", "read").await.0,
+            Decide::NotApplicable.into()
+        );
+        assert_eq!(
+            check("maria", "db", "read").await.0,
+            Decide::NotApplicable.into()
+        );
+        assert!(check("manager", "db", "write").await.1.is_empty());
+
+        // a delegation never lends what a policy denies the actor
+        let rule: RegisteredPolicyRule = PolicyRule {
+            name: str("no-kaitlyn"),
+            actor_check: Some(crate::proto::policies::ActorCheck {
+                name: Some(crate::proto::policies::StringCheck {
+                    val_cmp: crate::proto::policies::Set::Has.into(),
+                    vals: vec![str("kaitlyn")],
+                }),
+                ..Default::default()
+            }),
+            decision: crate::proto::policies::Decide::Deny.into(),
+            ..Default::default()
+        }
+        .into();
+        ds.policies.write().await.insert(str("no-kaitlyn"), rule);
+        assert_eq!(
+            check("kaitlyn", "db", "read").await,
+            (Decide::Deny.into(), vec![])
+        );
+        ds.policies.write().await.remove("no-kaitlyn");
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = GetDelegationsRequest {
+            actor_type: None,
+            actor_name: Some(str("Kaitlyn")),
+        };
+        ds.get_delegations(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::MultipleDelegations(delegations)) => assert_eq!(delegations.len(), 1),
+            _ => panic!("expected delegations"),
+        }
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = RemoveDelegationRequest {
+            name: str("cover"),
+            dry_run: false,
+        };
+        ds.remove_delegation(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleDelegation(_))));
+        assert_eq!(
+            check("kaitlyn", "db", "read").await.0,
+            Decide::NotApplicable.into()
+        );
+    }
+
+    #[test]
+    async fn test_decision_kinds() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        async fn decision(ds: &Datastore) -> crate::proto::policies::Decide {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str("read")],
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::CheckResult(resp)) => resp.decision(),
+                _ => panic!("expected a check result"),
+            }
+        }
+        assert_eq!(
+            decision(&ds).await,
+            crate::proto::policies::Decide::NotApplicable
+        );
+
+        // a rule whose module can't be run keeps even another rule's ALLOW from counting
+        for (name, decision, wasm_module) in [
+            ("allow-all", Decide::Allow, None),
+            ("broken", Decide::Allow, Some(str("missing"))),
+        ] {
+            let mut rule: RegisteredPolicyRule = PolicyRule {
+                name: str(name),
+                decision: crate::proto::policies::Decide::from(decision).into(),
+                ..Default::default()
+            }
+            .into();
+            rule.wasm_module = wasm_module;
+            ds.policies.write().await.insert(str(name), rule);
+        }
+        assert_eq!(
+            decision(&ds).await,
+            crate::proto::policies::Decide::Indeterminate
+        );
+
+        // but an explicit DENY still wins
+        let rule: RegisteredPolicyRule = PolicyRule {
+            name: str("deny-all"),
+            decision: crate::proto::policies::Decide::Deny.into(),
+            ..Default::default()
+        }
+        .into();
+        ds.policies.write().await.insert(str("deny-all"), rule);
+        assert_eq!(decision(&ds).await, crate::proto::policies::Decide::Deny);
+
+        // only checks decide these
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddPolicyRequest {
+            rule: Some(PolicyRule {
+                name: str("nothing"),
+                decision: crate::proto::policies::Decide::NotApplicable.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(status))
+            if status.message() == "A policy cannot decide NOT_APPLICABLE"));
+    }
+
+    #[test]
+    async fn test_policy_sets() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        for (name, decision) in [("allow-all", Decide::Allow), ("deny-all", Decide::Deny)] {
+            let rule: RegisteredPolicyRule = PolicyRule {
+                name: str(name),
+                decision: crate::proto::policies::Decide::from(decision).into(),
+                ..Default::default()
+            }
+            .into();
+            ds.policies.write().await.insert(str(name), rule);
+        }
+
+        async fn decision(ds: &Datastore) -> crate::proto::policies::Decide {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str("read")],
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::CheckResult(resp)) => resp.decision(),
+                _ => panic!("expected a check result"),
+            }
+        }
+
+        // on their own, the deny wins
+        assert_eq!(decision(&ds).await, crate::proto::policies::Decide::Deny);
+
+        let mut set = PolicySet {
+            name: str("Lenient"),
+            combine: crate::proto::policies::Combine::AllowOverrides.into(),
+            enabled: true,
+            policies: vec![str("allow-all"), str("deny-all")],
+            ..Default::default()
+        };
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddPolicySetRequest {
+            set: Some(set.clone()),
+            dry_run: false,
+        };
+        ds.add_policy_set(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SinglePolicySet(s)) if s.name == "lenient"));
+        assert_eq!(decision(&ds).await, crate::proto::policies::Decide::Allow);
+
+        // a disabled set decides nothing, and its policies don't count on their own
+        set.enabled = false;
+        let (tx, rx) = channel::<DsResponse>();
+        let req = ModifyPolicySetRequest {
+            set: Some(set),
+            dry_run: false,
+        };
+        ds.modify_policy_set(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SinglePolicySet(s)) if !s.enabled));
+        assert_eq!(
+            decision(&ds).await,
+            crate::proto::policies::Decide::NotApplicable
+        );
+
+        // a policy belongs to one set at most, and it has to exist
+        for (policies, code) in [
+            (vec![str("allow-all")], tonic::Code::FailedPrecondition),
+            (vec![str("missing")], tonic::Code::NotFound),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = AddPolicySetRequest {
+                set: Some(PolicySet {
+                    name: str("other"),
+                    policies,
+                    ..Default::default()
+                }),
+                dry_run: false,
+            };
+            ds.add_policy_set(req, tx).await;
+            assert!(matches!(rx.await, Ok(DsResponse::Error(s)) if s.code() == code));
+        }
+
+        // renaming a policy keeps it in its set
+        let (tx, _rx) = channel::<DsResponse>();
+        ds.copy_policy(str("allow-all"), str("allow"), true, false, tx)
+            .await;
+        assert_eq!(
+            ds.policy_sets.read().await["lenient"].policies,
+            vec![str("allow"), str("deny-all")]
+        );
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = RemovePolicySetRequest {
+            name: str("lenient"),
+            dry_run: false,
+        };
+        ds.remove_policy_set(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SinglePolicySet(_))));
+        assert!(ds.policy_sets.read().await.is_empty());
+    }
+
+    #[test]
+    async fn test_action_groups() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddTargetRequest {
+            name: str("db"),
+            typestr: str("database"),
+            actions: vec![str("get"), str("list"), str("delete")],
+            attributes: HashMap::new(),
+            action_groups: HashMap::from([(
+                str("read-ops"),
+                ActionGroup {
+                    actions: vec![str("get"), str("list")],
+                },
+            )]),
+            ..Default::default()
+        };
+        ds.add_target(req, tx).await;
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let rule = PolicyRule {
+            name: str("readers"),
+            decision: crate::proto::policies::Decide::Allow.into(),
+            target_check: Some(crate::proto::policies::TargetCheck {
+                action: Some(crate::proto::policies::StringCheck {
+                    val_cmp: crate::proto::policies::Set::Has.into(),
+                    vals: vec![str("read-ops")],
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        ds.add_policy(
+            AddPolicyRequest {
+                rule: Some(rule),
+                ..Default::default()
+            },
+            tx,
+        )
+        .await;
+
+        // a group expands to its actions and `*` to every action of the target
+        for (action, expected) in [
+            ("get", Decide::Allow),
+            ("delete", Decide::NotApplicable),
+            ("read-ops", Decide::Allow),
+            ("*", Decide::NotApplicable),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                env_attributes: HashMap::new(),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str(action)],
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+
+            match rx.await {
+                Ok(DsResponse::CheckResult(resp)) => {
+                    assert_eq!(resp.decision(), expected.into(), "checking {action}")
+                }
+                _ => panic!("expected a check result"),
+            }
+        }
+
+        // several actions can be checked at once, with a decision on each
+        let (tx, rx) = channel::<DsResponse>();
+        let req = CheckRequest {
+            actor: Some(Actor {
+                name: str("kaitlyn"),
+                typestr: str("user"),
+                attributes: HashMap::new(),
+                ..Default::default()
+            }),
+            target_name: str("db"),
+            target_type: str("database"),
+            target_action: vec![str("list"), str("delete")],
+            action_mode: ActionMode::EachAction.into(),
+            ..Default::default()
+        };
+        ds.check(req, tx).await;
+
+        match rx.await {
+            Ok(DsResponse::CheckResult(resp)) => {
+                assert_eq!(
+                    resp.decision(),
+                    crate::proto::policies::Decide::NotApplicable
+                );
+                let decisions: Vec<(&str, crate::proto::policies::Decide)> = resp
+                    .action_decisions
+                    .iter()
+                    .map(|ad| (ad.action.as_str(), ad.decision()))
+                    .collect();
+                assert_eq!(
+                    decisions,
+                    vec![
+                        ("list", crate::proto::policies::Decide::Allow),
+                        ("delete", crate::proto::policies::Decide::NotApplicable),
+                    ]
+                );
+            }
+            _ => panic!("expected a check result"),
+        }
+    }
+
+    #[test]
+    async fn test_what_if() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let allow_all = RegisteredPolicyRule {
+            name: str("allow-all"),
+            desc: None,
+            actor_check: None,
+            env_attributes: vec![],
+            target_check: None,
+            decision: Decide::Allow,
+            mode: Mode::Enforce,
+            wasm_module: None,
+            compare_checks: vec![],
+            cidr_checks: vec![],
+            target_types: vec![],
+            rate_checks: vec![],
+            risk: None,
+            tags: vec![],
+            timestamps: Timestamps::default(),
+        };
+        ds.policies
+            .write()
+            .await
+            .insert(str("allow-all"), allow_all.clone());
+
+        let deny_bots = RegisteredPolicyRule {
+            name: str("deny-bots"),
+            actor_check: Some(ActorCheck {
+                name: None,
+                typestr: Some(StringCheck::OneOf(vec![str("bot")])),
+                attributes: vec![],
+                bucket: None,
+            }),
+            decision: Decide::Deny,
+            ..allow_all.clone()
+        };
+
+        let requests = [("kaitlyn", "user"), ("robot", "bot")]
+            .into_iter()
+            .map(|(name, typestr)| CheckRequest {
+                actor: Some(Actor {
+                    name: str(name),
+                    typestr: str(typestr),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                env_attributes: HashMap::new(),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str("read")],
+                ..Default::default()
+            })
+            .collect();
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = WhatIfRequest {
+            policies: vec![allow_all.into(), deny_bots.into()],
+            requests,
+        };
+        ds.what_if(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::WhatIfResult(result)) => {
+                assert_eq!(result.requests, 2);
+                assert_eq!(result.changes.len(), 1);
+
+                let change = &result.changes[0];
+                assert_eq!(
+                    change
+                        .request
+                        .as_ref()
+                        .unwrap()
+                        .actor
+                        .as_ref()
+                        .unwrap()
+                        .name,
+                    "robot"
+                );
+                assert_eq!(change.current(), crate::proto::policies::Decide::Allow);
+                assert_eq!(change.candidate(), crate::proto::policies::Decide::Deny);
+            }
+            _ => panic!("expected a what-if result"),
+        }
+    }
+
+    #[test]
+    async fn test_policy_tests() {
+        use crate::proto::policies as protos;
+
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let allow_all = protos::PolicyRule {
+            name: str("allow-all"),
+            decision: protos::Decide::Allow.into(),
+            ..Default::default()
+        };
+        ds.policies
+            .write()
+            .await
+            .insert(str("allow-all"), allow_all.clone().into());
+
+        let deny_prod = protos::PolicyRule {
+            name: str("deny-prod"),
+            target_check: Some(protos::TargetCheck {
+                attributes: vec![protos::KvCheck {
+                    key: str("env"),
+                    op: protos::Kv::Has.into(),
+                    vals: vec![str("prod")],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            decision: protos::Decide::Deny.into(),
+            ..Default::default()
+        };
+
+        let cases = crate::policytest::parse_tests(
+            r#"
+- name: anyone can read
+  actor: { name: kaitlyn, type: user }
+  target: { name: db, type: database }
+  actions: [read]
+  expect: allow
+- name: prod is off limits
+  actor: { name: kaitlyn, type: user }
+  target: { name: db, type: database, attributes: { env: prod } }
+  actions: [read]
+  expect: deny
+"#,
+        )
+        .unwrap();
+
+        let run = |policies: Vec<protos::PolicyRule>| {
+            let ds = &ds;
+            let cases = cases.clone();
+            async move {
+                let (tx, rx) = channel::<DsResponse>();
+                ds.test_policies(TestPoliciesRequest { cases, policies }, tx)
+                    .await;
+                match rx.await {
+                    Ok(DsResponse::PolicyTestResults(result)) => result,
+                    _ => panic!("expected policy test results"),
+                }
+            }
+        };
+
+        // the current policies allow everything
+        let result = run(vec![]).await;
+        assert_eq!((result.passed, result.failed), (1, 1));
+        assert!(!result.results[1].passed);
+        assert_eq!(result.results[1].actual(), protos::Decide::Allow);
+
+        // the given policies deny the target the test gives
+        let result = run(vec![allow_all, deny_prod]).await;
+        assert_eq!((result.passed, result.failed), (2, 0));
+    }
+
+    #[test]
+    async fn test_sync_groups() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        ds.roles
+            .write()
+            .await
+            .insert(str("admin"), RegisteredRole::new("admin", None));
+
+        let members = HashSet::from([RegisteredGroupMember {
+            name: str("kaitlyn"),
+            typestr: intern("user"),
+        }]);
+        let group = RegisteredGroup::new(
+            "ldap-admins",
+            None,
+            members,
+            HashSet::from([str("admin"), str("unknown")]),
+        );
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.sync_groups(str("ldap"), vec![group], tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::GroupsSynced(1))));
+
+        let synced = ds.groups.read().await.get("ldap-admins").unwrap().clone();
+        assert_eq!(synced.managed_by, Some(str("ldap")));
+        assert_eq!(synced.roles, HashSet::from([str("admin")]));
+        assert!(ds.roles.read().await["admin"]
+            .groups
+            .contains("ldap-admins"));
+
+        // synced groups are read-only through the API
+        let (tx, rx) = channel::<DsResponse>();
+        let req = RemoveGroupRequest {
+            name: str("ldap-admins"),
+            ..Default::default()
+        };
+        ds.remove_group(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::Error(status)) => {
+                assert_eq!(status.code(), tonic::Code::FailedPrecondition)
+            }
+            _ => panic!("expected a synced group to be read-only"),
+        }
+
+        // groups no longer in the source are removed
+        let (tx, rx) = channel::<DsResponse>();
+        ds.sync_groups(str("ldap"), vec![], tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::GroupsSynced(1))));
+        assert!(ds.groups.read().await.is_empty());
+        assert!(ds.roles.read().await["admin"].groups.is_empty());
+    }
+
+    #[test]
+    async fn test_group_members() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let members = ["erin", "carol", "alice", "dave", "bob"]
+            .iter()
+            .map(|name| RegisteredGroupMember {
+                name: str(name),
+                typestr: intern("user"),
+            })
+            .collect();
+        let group = RegisteredGroup::new("staff", None, members, HashSet::new());
+        ds.groups.write().await.insert(str("staff"), group);
+
+        // page through two at a time
+        let mut names = Vec::new();
+        let mut page_token = String::new();
+        let mut pages = 0;
+        loop {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = GetGroupMembersRequest {
+                name: str("Staff"),
+                page_size: 2,
+                page_token,
+            };
+            ds.get_group_members(req, tx).await;
+            let page = match rx.await {
+                Ok(DsResponse::GroupMembers(page)) => page,
+                _ => panic!("expected a page of members"),
+            };
+            assert_eq!(page.total, 5);
+            names.extend(page.members.into_iter().map(|m| m.name));
+            pages += 1;
+
+            if page.next_page_token.is_empty() {
+                break;
+            }
+            page_token = page.next_page_token;
+        }
+        assert_eq!(pages, 3);
+        assert_eq!(names, vec!["alice", "bob", "carol", "dave", "erin"]);
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = GetGroupMembersRequest {
+            name: str("staff"),
+            page_token: str("not a token"),
+            ..Default::default()
+        };
+        ds.get_group_members(req, tx).await;
+        assert!(
+            matches!(rx.await, Ok(DsResponse::Error(s)) if s.code() == tonic::Code::InvalidArgument)
+        );
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = GetGroupMembersRequest {
+            name: str("nobody"),
+            ..Default::default()
+        };
+        ds.get_group_members(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(s)) if s.code() == tonic::Code::NotFound));
+    }
+
+    #[test]
+    async fn test_dry_run() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let group = RegisteredGroup::new("staff", None, HashSet::new(), HashSet::new());
+        ds.groups.write().await.insert(str("staff"), group);
+
+        // the would-be role comes back along with the groups it would change
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddRoleRequest {
+            name: str("reader"),
+            granted_to: vec![str("staff")],
+            dry_run: true,
+            ..Default::default()
+        };
+        ds.add_role(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::SingleRole(role, affected)) => {
+                assert_eq!(role.name, "reader");
+                assert_eq!(affected, vec!["staff"]);
+            }
+            _ => panic!("expected a role"),
+        }
+
+        // but nothing was changed
+        assert!(ds.roles.read().await.is_empty());
+        assert!(ds.groups.read().await["staff"].roles.is_empty());
+
+        // and the change is still validated
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddRoleRequest {
+            name: str("reader"),
+            granted_to: vec![str("nobody")],
+            dry_run: true,
+            ..Default::default()
+        };
+        ds.add_role(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(s)) if s.code() == tonic::Code::NotFound));
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = RemoveGroupRequest {
+            name: str("staff"),
+            dry_run: true,
+        };
+        ds.remove_group(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleGroup(g, _)) if g.name == "staff"));
+        assert!(ds.groups.read().await.contains_key("staff"));
+    }
+
+    #[test]
+    async fn test_remove_references() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        for name in ["db", "cache"] {
+            let (tx, _rx) = channel::<DsResponse>();
+            let req = AddTargetRequest {
+                name: str(name),
+                typestr: str("database"),
+                actions: vec![str("read")],
+                ..Default::default()
+            };
+            ds.add_target(req, tx).await;
+        }
+
+        let policies = [
+            ("db-only", vec![str("db")]),
+            ("both", vec![str("db"), str("cache")]),
+        ];
+        for (name, vals) in policies {
+            let (tx, _rx) = channel::<DsResponse>();
+            let rule = PolicyRule {
+                name: str(name),
+                decision: crate::proto::policies::Decide::Allow.into(),
+                target_check: Some(crate::proto::policies::TargetCheck {
+                    name: Some(crate::proto::policies::StringCheck {
+                        val_cmp: crate::proto::policies::Set::Has.into(),
+                        vals,
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let req = AddPolicyRequest {
+                rule: Some(rule),
+                ..Default::default()
+            };
+            ds.add_policy(req, tx).await;
+        }
+
+        // refusing names the policies and leaves the target in place
+        let remove_db = |references: References| RemoveTargetRequest {
+            name: str("db"),
+            typestr: str("database"),
+            references: references.into(),
+            ..Default::default()
+        };
+        let (tx, rx) = channel::<DsResponse>();
+        ds.remove_target(remove_db(References::Refuse), tx).await;
+        match rx.await {
+            Ok(DsResponse::Error(s)) => {
+                assert_eq!(s.code(), tonic::Code::FailedPrecondition);
+                assert!(s.message().contains("policy/both, policy/db-only"));
+            }
+            _ => panic!("expected the removal to be refused"),
+        }
+        assert!(ds.targets.read().await["database"].contains_key("db"));
+
+        // cascading drops the policy that was only about the target and trims the other
+        let (tx, rx) = channel::<DsResponse>();
+        ds.remove_target(remove_db(References::Cascade), tx).await;
+        match rx.await {
+            Ok(DsResponse::SingleTarget(_, references)) => {
+                assert_eq!(references, vec!["policy/both", "policy/db-only"]);
+            }
+            _ => panic!("expected a target"),
+        }
+        let policies = ds.policies.read().await;
+        assert!(!policies.contains_key("db-only"));
+        assert!(!policies["both"].refers_to_target("database", "db"));
+        assert!(policies["both"].refers_to_target("database", "cache"));
+        drop(policies);
+
+        // the same goes for actors and the groups they are members of
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddActorRequest {
+            name: str("alice"),
+            typestr: str("user"),
+            ..Default::default()
+        };
+        ds.add_actor(req, tx).await;
+
+        let alice = RegisteredGroupMember {
+            name: str("alice"),
+            typestr: intern("user"),
+        };
+        let group = RegisteredGroup::new("staff", None, HashSet::from([alice]), HashSet::new());
+        ds.groups.write().await.insert(str("staff"), group);
+
+        let remove_alice = |references: References| RemoveActorRequest {
+            name: str("alice"),
+            typestr: str("user"),
+            references: references.into(),
+            ..Default::default()
+        };
+        let (tx, rx) = channel::<DsResponse>();
+        ds.remove_actor(remove_alice(References::Refuse), tx).await;
+        assert!(
+            matches!(rx.await, Ok(DsResponse::Error(s)) if s.message().contains("group/staff"))
+        );
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.remove_actor(remove_alice(References::Cascade), tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleActor(_, r)) if r == vec!["group/staff"]));
+        assert!(ds.groups.read().await["staff"].members.is_empty());
+        assert!(!ds.actors.read().await["user"].contains_key("alice"));
+    }
+
+    #[test]
+    async fn test_unused() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        for name in ["alice", "bob"] {
+            let (tx, _rx) = channel::<DsResponse>();
+            let req = AddActorRequest {
+                name: str(name),
+                typestr: str("user"),
+                ..Default::default()
+            };
+            ds.add_actor(req, tx).await;
+        }
+        for name in ["db", "cache"] {
+            let (tx, _rx) = channel::<DsResponse>();
+            let req = AddTargetRequest {
+                name: str(name),
+                typestr: str("database"),
+                actions: vec![str("read")],
+                ..Default::default()
+            };
+            ds.add_target(req, tx).await;
+        }
+
+        let alice = RegisteredGroupMember {
+            name: str("alice"),
+            typestr: intern("user"),
+        };
+        let group = RegisteredGroup::new("staff", None, HashSet::from([alice]), HashSet::new());
+        ds.groups.write().await.insert(str("staff"), group);
+        for (name, granted_to) in [("reader", vec![str("staff")]), ("writer", vec![])] {
+            let (tx, _rx) = channel::<DsResponse>();
+            let req = AddRoleRequest {
+                name: str(name),
+                granted_to,
+                ..Default::default()
+            };
+            ds.add_role(req, tx).await;
+        }
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let rule = PolicyRule {
+            name: str("db-only"),
+            target_check: Some(crate::proto::policies::TargetCheck {
+                name: Some(crate::proto::policies::StringCheck {
+                    val_cmp: crate::proto::policies::Set::Has.into(),
+                    vals: vec![str("db")],
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let req = AddPolicyRequest {
+            rule: Some(rule),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+
+        let unused = || async {
+            let (tx, rx) = channel::<DsResponse>();
+            ds.find_unused(FindUnusedRequest { days: 1 }, tx).await;
+            match rx.await {
+                Ok(DsResponse::Unused(resp)) => resp
+                    .entities
+                    .into_iter()
+                    .map(|entity| format!("{} {}", entity.kind, entity.name))
+                    .collect::<Vec<_>>(),
+                _ => panic!("expected unused entities"),
+            }
+        };
+
+        // everything was just added, so counts as used
+        assert!(unused().await.is_empty());
+
+        ds.usage.backdate(1).await;
+        assert_eq!(
+            unused().await,
+            vec!["actor bob", "role writer", "target cache"]
+        );
+
+        // a check uses the actor and the target
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = CheckRequest {
+            actor: Some(Actor {
+                name: str("bob"),
+                typestr: str("user"),
+                ..Default::default()
+            }),
+            target_name: str("cache"),
+            target_type: str("database"),
+            target_action: vec![str("read")],
+            ..Default::default()
+        };
+        ds.check(req, tx).await;
+        assert_eq!(unused().await, vec!["role writer"]);
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.remove_unused(FindUnusedRequest { days: 1 }, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Unused(resp)) if resp.entities.len() == 1));
+        assert!(!ds.roles.read().await.contains_key("writer"));
+        assert!(unused().await.is_empty());
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.find_unused(FindUnusedRequest { days: 0 }, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(_))));
+    }
+
+    #[test]
+    async fn test_get_references() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddActorRequest {
+            name: str("alice"),
+            typestr: str("user"),
+            ..Default::default()
+        };
+        ds.add_actor(req, tx).await;
+
+        let alice = RegisteredGroupMember {
+            name: str("alice"),
+            typestr: intern("user"),
+        };
+        let group = RegisteredGroup::new("staff", None, HashSet::from([alice]), HashSet::new());
+        ds.groups.write().await.insert(str("staff"), group);
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddRoleRequest {
+            name: str("reader"),
+            granted_to: vec![str("staff")],
+            ..Default::default()
+        };
+        ds.add_role(req, tx).await;
+
+        // one policy names alice, the other the role
+        let string_check = |vals: Vec<String>| crate::proto::policies::StringCheck {
+            val_cmp: crate::proto::policies::Set::Has.into(),
+            vals,
+        };
+        let rules = [
+            crate::proto::policies::ActorCheck {
+                name: Some(string_check(vec![str("Alice")])),
+                ..Default::default()
+            },
+            crate::proto::policies::ActorCheck {
+                attributes: vec![crate::proto::policies::KvCheck {
+                    key: str("has-role"),
+                    op: crate::proto::policies::Kv::Has.into(),
+                    vals: vec![str("reader")],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        ];
+        for (name, actor_check) in ["alice-only", "readers"].into_iter().zip(rules) {
+            let (tx, _rx) = channel::<DsResponse>();
+            let rule = PolicyRule {
+                name: str(name),
+                actor_check: Some(actor_check),
+                ..Default::default()
+            };
+            let req = AddPolicyRequest {
+                rule: Some(rule),
+                ..Default::default()
+            };
+            ds.add_policy(req, tx).await;
+        }
+
+        let references = |kind: &str, name: &str, typestr: &str| GetReferencesRequest {
+            kind: str(kind),
+            name: str(name),
+            typestr: str(typestr),
+        };
+        for (req, expected) in [
+            (
+                references("actor", "alice", "user"),
+                (vec!["alice-only"], vec!["staff"], vec![]),
+            ),
+            (
+                references("role", "reader", ""),
+                (vec!["readers"], vec!["staff"], vec![]),
+            ),
+            (
+                references("group", "staff", ""),
+                (vec![], vec![], vec!["reader"]),
+            ),
+        ] {
+            let (tx, rx) = channel::<DsResponse>();
+            ds.get_references(req, tx).await;
+            match rx.await {
+                Ok(DsResponse::References(found)) => {
+                    let (policies, groups, roles): (Vec<&str>, Vec<&str>, Vec<&str>) = expected;
+                    assert_eq!(found.policies, policies);
+                    assert_eq!(found.groups, groups);
+                    assert_eq!(found.roles, roles);
+                }
+                _ => panic!("expected references"),
+            }
+        }
+
+        // the entity has to exist
+        let (tx, rx) = channel::<DsResponse>();
+        ds.get_references(references("target", "db", "database"), tx)
+            .await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(s)) if s.code() == tonic::Code::NotFound));
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.get_references(references("webhook", "audit", ""), tx)
+            .await;
+        assert!(
+            matches!(rx.await, Ok(DsResponse::Error(s)) if s.code() == tonic::Code::InvalidArgument)
+        );
+    }
+
+    #[test]
+    async fn test_copy_group_and_policy() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let group = RegisteredGroup::new("staff", None, HashSet::new(), HashSet::new());
+        ds.groups.write().await.insert(str("staff"), group);
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddRoleRequest {
+            name: str("reader"),
+            granted_to: vec![str("staff")],
+            ..Default::default()
+        };
+        ds.add_role(req, tx).await;
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let rule = PolicyRule {
+            name: str("staff-read"),
+            actor_check: Some(crate::proto::policies::ActorCheck {
+                attributes: vec![crate::proto::policies::KvCheck {
+                    key: str("member-of"),
+                    op: crate::proto::policies::Kv::Has.into(),
+                    vals: vec![str("staff")],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let req = AddPolicyRequest {
+            rule: Some(rule),
+            ..Default::default()
+        };
+        ds.add_policy(req, tx).await;
+
+        // renaming moves the role grant and the policy check over to the new name
+        let (tx, rx) = channel::<DsResponse>();
+        ds.copy_group(str("staff"), str("Employees"), true, false, tx)
+            .await;
+        assert!(
+            matches!(rx.await, Ok(DsResponse::SingleGroup(g, r)) if g.name == "employees" && r == vec!["reader"])
+        );
+        assert!(!ds.groups.read().await.contains_key("staff"));
+        assert_eq!(
+            ds.roles.read().await["reader"].groups,
+            HashSet::from([str("employees")])
+        );
+        let rule = ds.policies.read().await["staff-read"].clone();
+        assert!(rule.refers_to_attribute("member-of", "employees"));
+        assert!(!rule.refers_to_attribute("member-of", "staff"));
+
+        // cloning grants the roles to the copy as well
+        let (tx, rx) = channel::<DsResponse>();
+        ds.copy_group(str("employees"), str("contractors"), false, false, tx)
+            .await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleGroup(..))));
+        assert_eq!(
+            ds.roles.read().await["reader"].groups,
+            HashSet::from([str("employees"), str("contractors")])
+        );
+
+        // names can't be taken twice
+        let (tx, rx) = channel::<DsResponse>();
+        ds.copy_group(str("employees"), str("contractors"), true, false, tx)
+            .await;
+        assert!(
+            matches!(rx.await, Ok(DsResponse::Error(s)) if s.code() == tonic::Code::AlreadyExists)
+        );
+
+        // policies can be copied and renamed too
+        let (tx, rx) = channel::<DsResponse>();
+        ds.copy_policy(str("staff-read"), str("copy"), false, false, tx)
+            .await;
+        assert!(matches!(rx.await, Ok(DsResponse::SinglePolicy(p, _)) if p.name == "copy"));
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.copy_policy(str("copy"), str("renamed"), true, false, tx)
+            .await;
+        assert!(matches!(rx.await, Ok(DsResponse::SinglePolicy(p, _)) if p.name == "renamed"));
+
+        let policies = ds.policies.read().await;
+        let mut names: Vec<&String> = policies.keys().collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["renamed", "staff-read"]);
+        assert_eq!(
+            policies["renamed"].actor_check,
+            policies["staff-read"].actor_check
+        );
+    }
+
+    #[test]
+    async fn test_bulk_modify_memberships() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let member = |name: &str| GroupMember {
+            name: str(name),
+            typestr: str("user"),
+        };
+        let alice = RegisteredGroupMember::from(member("alice"));
+        let bob = RegisteredGroupMember::from(member("bob"));
+
+        for name in ["staff", "eng", "oncall"] {
+            let group =
+                RegisteredGroup::new(name, None, HashSet::from([bob.clone()]), HashSet::new());
+            ds.groups.write().await.insert(str(name), group);
+        }
+
+        // onboard alice and offboard bob across two groups
+        let (tx, rx) = channel::<DsResponse>();
+        let req = BulkModifyMembershipsRequest {
+            groups: vec![str("Staff"), str("eng")],
+            add_members: vec![member("alice")],
+            remove_members: vec![member("bob")],
+            ..Default::default()
+        };
+        ds.bulk_modify_memberships(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::MultipleGroups(g)) if g.len() == 2));
+
+        let groups = ds.groups.read().await;
+        for name in ["staff", "eng"] {
+            assert_eq!(groups[name].members, HashSet::from([alice.clone()]));
+        }
+        assert_eq!(groups["oncall"].members, HashSet::from([bob.clone()]));
+        drop(groups);
+
+        // a missing group means no group is changed
+        let (tx, rx) = channel::<DsResponse>();
+        let req = BulkModifyMembershipsRequest {
+            groups: vec![str("oncall"), str("nobody")],
+            add_members: vec![member("alice")],
+            ..Default::default()
+        };
+        ds.bulk_modify_memberships(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(s)) if s.code() == tonic::Code::NotFound));
+        assert_eq!(
+            ds.groups.read().await["oncall"].members,
+            HashSet::from([bob])
+        );
+    }
 
-        assert_eq!(ds.targets.read().await.len(), 1);
-        assert!(ds.targets.read().await.contains_key("typetest"));
-        assert!(ds
-            .targets
-            .read()
+    #[test]
+    async fn test_actor_memberships() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let member = RegisteredGroupMember {
+            name: str("alice"),
+            typestr: intern("user"),
+        };
+        let roles = |names: &[&str]| names.iter().map(|name| str(name)).collect();
+        let groups = [
+            ("staff", roles(&["reader"])),
+            ("admins", roles(&["writer", "reader"])),
+        ];
+        for (name, roles) in groups {
+            let group = RegisteredGroup::new(name, None, HashSet::from([member.clone()]), roles);
+            ds.groups.write().await.insert(str(name), group);
+        }
+        let group = RegisteredGroup::new("others", None, HashSet::new(), roles(&["deployer"]));
+        ds.groups.write().await.insert(str("others"), group);
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = GetActorMembershipsRequest {
+            name: str("Alice"),
+            typestr: str("User"),
+        };
+        ds.get_actor_memberships(req, tx).await;
+        let memberships = match rx.await {
+            Ok(DsResponse::ActorMemberships(memberships)) => memberships,
+            _ => panic!("expected memberships"),
+        };
+        assert_eq!(memberships.groups, vec!["admins", "staff"]);
+        assert_eq!(memberships.roles, vec!["reader", "writer"]);
+
+        // an actor in no groups has no memberships
+        let (tx, rx) = channel::<DsResponse>();
+        let req = GetActorMembershipsRequest {
+            name: str("bob"),
+            typestr: str("user"),
+        };
+        ds.get_actor_memberships(req, tx).await;
+        assert!(matches!(
+            rx.await,
+            Ok(DsResponse::ActorMemberships(m)) if m.groups.is_empty() && m.roles.is_empty()
+        ));
+    }
+
+    #[test]
+    async fn test_apply_transaction() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let mutation = |op: Op| Mutation { op: Some(op) };
+        let mutations = vec![
+            mutation(Op::AddRole(AddRoleRequest {
+                name: str("reader"),
+                ..Default::default()
+            })),
+            mutation(Op::AddGroup(AddGroupRequest {
+                name: str("staff"),
+                roles: vec![str("reader")],
+                ..Default::default()
+            })),
+            mutation(Op::AddPolicy(AddPolicyRequest {
+                rule: Some(PolicyRule {
+                    name: str("readers-read"),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })),
+        ];
+
+        // a dry run reports the changes without making them
+        let (tx, rx) = channel::<DsResponse>();
+        let req = ApplyTransactionRequest {
+            mutations: mutations.clone(),
+            dry_run: true,
+        };
+        ds.apply_transaction(req, tx).await;
+        let changes = match rx.await {
+            Ok(DsResponse::TransactionApplied(changes)) => changes,
+            _ => panic!("expected changes"),
+        };
+        let mut names: Vec<String> = changes
+            .iter()
+            .map(|c| format!("{} {}/{}", c.op, c.kind, c.name))
+            .collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            vec![
+                "put group/staff",
+                "put policy/readers-read",
+                "put role/reader"
+            ]
+        );
+        assert!(ds.roles.read().await.is_empty());
+
+        // a failing mutation means nothing is changed
+        let mut failing = mutations.clone();
+        failing.push(mutation(Op::RemoveGroup(RemoveGroupRequest {
+            name: str("nobody"),
+            ..Default::default()
+        })));
+        let (tx, rx) = channel::<DsResponse>();
+        let req = ApplyTransactionRequest {
+            mutations: failing,
+            ..Default::default()
+        };
+        ds.apply_transaction(req, tx).await;
+        assert!(matches!(
+            rx.await,
+            Ok(DsResponse::Error(s)) if s.code() == tonic::Code::NotFound && s.message().starts_with("Mutation 3")
+        ));
+        assert!(ds.roles.read().await.is_empty());
+        assert!(ds.groups.read().await.is_empty());
+
+        // and otherwise everything is, with each change seeing the ones before it
+        let (tx, rx) = channel::<DsResponse>();
+        let req = ApplyTransactionRequest {
+            mutations,
+            ..Default::default()
+        };
+        ds.apply_transaction(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::TransactionApplied(c)) if c.len() == 3));
+        assert!(ds.roles.read().await["reader"].groups.contains("staff"));
+        assert!(ds.groups.read().await["staff"].roles.contains("reader"));
+        assert!(ds.policies.read().await.contains_key("readers-read"));
+    }
+
+    #[test]
+    async fn test_apply_bundle() {
+        let basepath =
+            std::env::temp_dir().join(format!("gatehouse-bundle-{}", std::process::id()));
+        let storage = StorageType::FileSystem(basepath.to_str().unwrap().to_string());
+        let open = || async {
+            let (req_tx, req_rx) = flume::unbounded();
+            Datastore::new(&storage, Config::default(), req_tx, req_rx).await
+        };
+        let ds = open().await;
+
+        for name in ["kept", "dropped"] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = AddPolicyRequest {
+                rule: Some(PolicyRule {
+                    name: str(name),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            ds.add_policy(req, tx).await;
+            assert!(matches!(rx.await, Ok(DsResponse::SinglePolicy(..))));
+        }
+
+        let rules = vec![
+            PolicyRule {
+                name: str("kept"),
+                desc: Some(str("changed")),
+                ..Default::default()
+            },
+            PolicyRule {
+                name: str("added"),
+                ..Default::default()
+            },
+        ];
+        let bundle = PolicyBundle { rules, sequence: 5 };
+        let (tx, rx) = channel::<DsResponse>();
+        ds.apply_bundle(bundle.clone(), false, tx).await;
+        let changes = match rx.await {
+            Ok(DsResponse::TransactionApplied(changes)) => changes,
+            _ => panic!("expected changes"),
+        };
+        let mut names: Vec<String> = changes
+            .iter()
+            .map(|c| format!("{} {}", c.op, c.name))
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["delete dropped", "put added", "put kept"]);
+
+        let policies = ds.policies.read().await.clone();
+        assert!(!policies.contains_key("dropped"));
+        assert_eq!(policies["kept"].desc.as_deref(), Some("changed"));
+
+        // after a restart, neither the same bundle nor an older one can be applied again
+        let ds = open().await;
+        let apply = |sequence: u64, dry_run: bool| {
+            let ds = &ds;
+            let bundle = PolicyBundle {
+                sequence,
+                ..bundle.clone()
+            };
+            async move {
+                let (tx, rx) = channel::<DsResponse>();
+                ds.apply_bundle(bundle, dry_run, tx).await;
+                match rx.await {
+                    Ok(DsResponse::TransactionApplied(_)) => Ok(()),
+                    Ok(DsResponse::Error(status)) => Err(status.code()),
+                    _ => panic!("expected changes"),
+                }
+            }
+        };
+        for sequence in [4, 5] {
+            assert_eq!(
+                apply(sequence, false).await,
+                Err(tonic::Code::FailedPrecondition)
+            );
+        }
+        assert_eq!(apply(6, true).await, Ok(()));
+        assert_eq!(apply(6, false).await, Ok(()));
+        assert_eq!(apply(6, false).await, Err(tonic::Code::FailedPrecondition));
+
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+
+    #[test]
+    async fn test_api_keys() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        // even with no keys issued, a call needs one
+        let (tx, rx) = channel::<DsResponse>();
+        ds.verify_api_key(None, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(_))));
+
+        let (key, secret) = RegisteredApiKey::issue(
+            str("ci"),
+            apikey::Scope::CheckOnly,
+            vec![],
+            str("ci-bot"),
+            0,
+        )
+        .unwrap();
+        let id = key.id.clone();
+        let (tx, rx) = channel::<DsResponse>();
+        ds.add_api_key(key, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleApiKey(_))));
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.verify_api_key(Some(secret.clone()), tx).await;
+        match rx.await {
+            Ok(DsResponse::VerifiedApiKey(key)) => {
+                assert_eq!(key.id, id);
+                assert_eq!(key.principal, "ci-bot");
+            }
+            _ => panic!("expected the key to verify"),
+        }
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.verify_api_key(None, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(_))));
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.revoke_api_key(id, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleApiKey(_))));
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.verify_api_key(Some(secret), tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(_))));
+
+        // the key the configuration starts with is registered, once
+        let (req_tx, req_rx) = flume::unbounded();
+        let config = Config {
+            bootstrap_api_key: Some(crate::secrets::Secret::from("ghk_0123_secret")),
+            bootstrap_principal: str("alice"),
+            ..Default::default()
+        };
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+        ds.bootstrap_api_key().await;
+        assert_eq!(ds.api_keys.read().await.len(), 1);
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.verify_api_key(Some(str("ghk_0123_secret")), tx).await;
+        match rx.await {
+            Ok(DsResponse::VerifiedApiKey(key)) => {
+                assert_eq!(key.scope, apikey::Scope::Admin);
+                assert_eq!(key.principal, "alice");
+            }
+            _ => panic!("expected the bootstrap key to verify"),
+        }
+    }
+
+    #[test]
+    async fn test_replication() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let primary = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+        let mut events = primary.watchers.subscribe();
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddTargetRequest {
+            name: str("db"),
+            typestr: str("database"),
+            actions: vec![str("read")],
+            ..Default::default()
+        };
+        primary.add_target(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleTarget(..))));
+
+        // every change is sent to watchers with the next revision
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.revision, 1);
+        assert!(matches!(event.change, Some(Change::PutTarget(ref t)) if t.name == "db"));
+
+        let (tx, rx) = channel::<DsResponse>();
+        primary.sync(tx).await;
+        let state = match rx.await {
+            Ok(DsResponse::SyncResult(state)) => *state,
+            _ => panic!("expected a sync result"),
+        };
+        assert_eq!(state.revision, 1);
+
+        // a replica loads the synced state and applies changes after it
+        let (req_tx, req_rx) = flume::unbounded();
+        let replica = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let (tx, rx) = channel::<DsResponse>();
+        replica.load(state, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Loaded(1))));
+        assert!(replica.targets.read().await["database"].contains_key("db"));
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = RemoveTargetRequest {
+            name: str("db"),
+            typestr: str("database"),
+            ..Default::default()
+        };
+        primary.remove_target(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleTarget(..))));
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.revision, 2);
+        replica.update(event.change.unwrap().into()).await;
+        assert!(replica.targets.read().await["database"].is_empty());
+    }
+
+    #[test]
+    async fn test_replicate() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let target = |actions: &[&str]| crate::proto::targets::Target {
+            name: str("db"),
+            typestr: str("database"),
+            actions: actions.iter().map(|a| str(a)).collect(),
+            ..Default::default()
+        };
+        let change = |after: &[&str], before: Option<&[&str]>| ReplicatedChange {
+            event: Some(WatchEvent {
+                revision: 1,
+                change: Some(Change::PutTarget(target(after))),
+            }),
+            previous: before.map(|before| WatchEvent {
+                revision: 0,
+                change: Some(Change::PutTarget(target(before))),
+            }),
+        };
+        let replicate = |changes: Vec<ReplicatedChange>, conflicts: ConflictPolicy| {
+            let ds = &ds;
+            async move {
+                let (tx, rx) = channel::<DsResponse>();
+                let req = ReplicateRequest {
+                    changes,
+                    conflicts: conflicts.into(),
+                };
+                ds.replicate(req, tx).await;
+                match rx.await {
+                    Ok(DsResponse::Replicated(resp)) => resp,
+                    _ => panic!("expected a replicate response"),
+                }
+            }
+        };
+        let actions = || async {
+            let targets = ds.targets.read().await;
+            let mut actions: Vec<String> =
+                targets["database"]["db"].actions.iter().cloned().collect();
+            actions.sort();
+            actions
+        };
+
+        // a new entity is applied, and applying it again changes nothing
+        let resp = replicate(vec![change(&["read"], None)], ConflictPolicy::SourceWins).await;
+        assert_eq!(resp.applied, 1);
+        let resp = replicate(vec![change(&["read"], None)], ConflictPolicy::SourceWins).await;
+        assert_eq!(resp.unchanged, 1);
+
+        // a change made after what the source last sent
+        let resp = replicate(
+            vec![change(&["read", "write"], Some(&["read"]))],
+            ConflictPolicy::SourceWins,
+        )
+        .await;
+        assert_eq!(resp.applied, 1);
+        assert_eq!(resp.conflicts, 0);
+
+        // the source thinks it is still read only, so this conflicts
+        let conflicting = || change(&["read", "admin"], Some(&["read"]));
+        let resp = replicate(vec![conflicting()], ConflictPolicy::TargetWins).await;
+        assert_eq!((resp.applied, resp.conflicts), (0, 1));
+        assert_eq!(actions().await, vec![str("read"), str("write")]);
+
+        let resp = replicate(vec![conflicting()], ConflictPolicy::SourceWins).await;
+        assert_eq!((resp.applied, resp.conflicts), (1, 1));
+        assert_eq!(actions().await, vec![str("admin"), str("read")]);
+
+        // a replicated actor can't claim a role any more than one registered here
+        let actor = crate::proto::actors::Actor {
+            name: str("mallory"),
+            typestr: str("user"),
+            attributes: HashMap::from([(
+                str("has-role"),
+                crate::proto::common::AttributeValues {
+                    values: vec![str("admin")],
+                },
+            )]),
+            ..Default::default()
+        };
+        let req = ReplicateRequest {
+            changes: vec![ReplicatedChange {
+                event: Some(WatchEvent {
+                    revision: 2,
+                    change: Some(Change::PutActor(actor)),
+                }),
+                previous: None,
+            }],
+            ..Default::default()
+        };
+        let (tx, rx) = channel::<DsResponse>();
+        ds.replicate(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::Error(status)) => assert_eq!(status.code(), Code::InvalidArgument),
+            _ => panic!("expected an error"),
+        }
+        assert!(ds.actors.read().await.get("user").is_none());
+    }
+
+    #[test]
+    async fn test_reconcile() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+        let mut events = ds.watchers.subscribe();
+
+        let target = |name: &str, action: &str| {
+            RegisteredTarget::from(Target {
+                name: str(name),
+                typestr: str("database"),
+                actions: vec![str(action)],
+                ..Default::default()
+            })
+        };
+        ds.update(BackendUpdate::PutTarget(target("db", "read")))
+            .await;
+        ds.update(BackendUpdate::PutTarget(target("cache", "read")))
+            .await;
+        ds.update(BackendUpdate::PutRole(RegisteredRole::new("admin", None)))
+            .await;
+        let _ = events.recv().await;
+        let _ = events.recv().await;
+        let _ = events.recv().await;
+
+        // the reload changed db, dropped cache, added queue, and left admin alone
+        let loaded = vec![
+            BackendUpdate::PutTarget(target("db", "write")),
+            BackendUpdate::PutTarget(target("queue", "read")),
+            BackendUpdate::PutRole(RegisteredRole::new("admin", None)),
+        ];
+        let (tx, rx) = channel::<DsResponse>();
+        ds.reconcile(loaded, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Reconciled(3))));
+
+        let targets = ds.targets.read().await;
+        assert!(targets["database"]["db"].actions.contains("write"));
+        assert!(targets["database"].contains_key("queue"));
+        assert!(!targets["database"].contains_key("cache"));
+        assert!(ds.roles.read().await.contains_key("admin"));
+
+        // watchers hear about each change
+        let mut changes = 0;
+        while let Ok(event) = events.try_recv() {
+            assert!(matches!(
+                event.change,
+                Some(Change::PutTarget(_)) | Some(Change::DeleteTarget(_))
+            ));
+            changes += 1;
+        }
+        assert_eq!(changes, 3);
+    }
+
+    #[test]
+    async fn test_reload_state() {
+        // without a backend there is nothing to reload
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+        let (tx, rx) = channel::<DsResponse>();
+        ds.reload_state(tx).await;
+        match rx.await {
+            Ok(DsResponse::Error(status)) => assert_eq!(status.code(), Code::FailedPrecondition),
+            _ => panic!("expected an error"),
+        }
+
+        let basepath =
+            std::env::temp_dir().join(format!("gatehouse-reload-{}", std::process::id()));
+        let path = basepath.to_str().unwrap().to_string();
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(
+            &StorageType::FileSystem(path.clone()),
+            Config::default(),
+            req_tx,
+            req_rx,
+        )
+        .await;
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddTargetRequest {
+            name: str("maindb"),
+            typestr: str("database"),
+            ..Default::default()
+        };
+        ds.add_target(req, tx).await;
+
+        // the backend is edited behind the server's back
+        let external = FileStorage::new(&path, false).await;
+        external.remove_target("database", "maindb").await.unwrap();
+        external
+            .save_role(&RegisteredRole::new("auditor", None))
             .await
-            .get("typetest")
+            .unwrap();
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.reload_state(tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Reconciled(2))));
+        assert!(ds.targets.read().await["database"].is_empty());
+        assert!(ds.roles.read().await.contains_key("auditor"));
+
+        // reloading again changes nothing
+        let (tx, rx) = channel::<DsResponse>();
+        ds.reload_state(tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Reconciled(0))));
+
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+
+    #[test]
+    async fn test_proposals_persist() {
+        let basepath =
+            std::env::temp_dir().join(format!("gatehouse-proposals-{}", std::process::id()));
+        let storage = StorageType::FileSystem(basepath.to_str().unwrap().to_string());
+        let open = || async {
+            let (req_tx, req_rx) = flume::unbounded();
+            Datastore::new(&storage, Config::default(), req_tx, req_rx).await
+        };
+        let propose = |ds: Arc<Datastore>| async move {
+            let proposal = Proposal {
+                proposer: str("alice"),
+                change: Some(ProposedChange::Remove(RemovePolicyRequest {
+                    name: str("old"),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            };
+            let (tx, rx) = channel::<DsResponse>();
+            ds.propose(proposal, tx).await;
+            match rx.await {
+                Ok(DsResponse::SingleProposal(proposal)) => proposal.id,
+                _ => panic!("expected a proposal"),
+            }
+        };
+
+        let ds = Arc::new(open().await);
+        assert_eq!(propose(ds.clone()).await, 1);
+
+        // a restart keeps the proposal and goes on from its id
+        let ds = Arc::new(open().await);
+        let proposals = ds.proposals.read().await.clone();
+        assert_eq!(proposals.len(), 1);
+        assert_eq!(proposals[&1].proposer, "alice");
+        assert!(matches!(
+            proposals[&1].change,
+            Some(ProposedChange::Remove(_))
+        ));
+        assert_eq!(propose(ds.clone()).await, 2);
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.reject_proposal(1, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleProposal(_))));
+
+        let ds = open().await;
+        let ids: Vec<u64> = ds.proposals.read().await.keys().copied().collect();
+        assert_eq!(ids, vec![2]);
+
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+
+    #[test]
+    async fn test_break_glasses_persist() {
+        let basepath =
+            std::env::temp_dir().join(format!("gatehouse-breakglass-{}", std::process::id()));
+        let storage = StorageType::FileSystem(basepath.to_str().unwrap().to_string());
+        let open = || async {
+            let (req_tx, req_rx) = flume::unbounded();
+            let config = Config {
+                break_glass_role: Some(str("oncall")),
+                ..Default::default()
+            };
+            Datastore::new(&storage, config, req_tx, req_rx).await
+        };
+
+        let ds = open().await;
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddRoleRequest {
+            name: str("oncall"),
+            ..Default::default()
+        };
+        ds.add_role(req, tx).await;
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddGroupRequest {
+            name: str("sre"),
+            members: vec![GroupMember {
+                name: str("maria"),
+                typestr: str("user"),
+            }],
+            roles: vec![str("oncall")],
+            ..Default::default()
+        };
+        ds.add_group(req, tx).await;
+
+        // breaking the glass is sent to watchers, so replicas elevate kaitlyn too
+        let mut events = ds.watchers.subscribe();
+        let req = BreakGlassRequest {
+            actor_type: str("user"),
+            actor_name: str("kaitlyn"),
+            minutes: 30,
+            reason: str("outage"),
+            ..Default::default()
+        };
+        let (tx, rx) = channel::<DsResponse>();
+        ds.break_glass(req, str("user/maria"), tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleBreakGlass(_))));
+        let event = events.recv().await.unwrap();
+        assert!(matches!(event.change, Some(Change::PutBreakGlass(ref b)) if b.id == 1));
+
+        // a restart keeps it, and its removal once it runs out
+        let ds = open().await;
+        let break_glasses = ds.break_glasses.read().await.clone();
+        assert_eq!(break_glasses[&1].actor_name, "kaitlyn");
+        assert_eq!(ds.next_break_glass.load(Ordering::SeqCst), 1);
+        for break_glass in ds.break_glasses.write().await.values_mut() {
+            break_glass.expires_at = now();
+        }
+        let mut events = ds.watchers.subscribe();
+        ds.end_break_glasses().await;
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.change, Some(Change::DeleteBreakGlass(1)));
+
+        let ds = open().await;
+        assert!(ds.break_glasses.read().await.is_empty());
+
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+
+    #[test]
+    async fn test_server_info() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddTargetRequest {
+            name: str("maindb"),
+            typestr: str("database"),
+            ..Default::default()
+        };
+        ds.add_target(req, tx).await;
+
+        let (tx, rx) = channel::<DsResponse>();
+        ds.get_server_info(tx).await;
+        let info = match rx.await {
+            Ok(DsResponse::ServerInfo(info)) => info,
+            _ => panic!("expected server info"),
+        };
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.storage, "nil");
+        assert_eq!(info.targets, 1);
+        assert_eq!(info.actors, 0);
+        assert_eq!(info.features.contains(&str("wasm")), cfg!(feature = "wasm"));
+    }
+
+    #[test]
+    async fn test_startup_issues() {
+        for mode in [StartupMode::Lenient, StartupMode::Repair] {
+            let (req_tx, req_rx) = flume::unbounded();
+            let config = Config {
+                startup_mode: mode,
+                ..Default::default()
+            };
+            let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+            // a group granting a role that is gone, and a policy needing a missing module
+            let mut group = RegisteredGroup::new("admins", None, HashSet::new(), HashSet::new());
+            group.roles.insert(str("superuser"));
+            ds.groups.write().await.insert(str("admins"), group);
+            let policy = RegisteredPolicyRule {
+                wasm_module: Some(str("missing")),
+                ..RegisteredPolicyRule::from(PolicyRule {
+                    name: str("custom"),
+                    ..Default::default()
+                })
+            };
+            ds.policies.write().await.insert(str("custom"), policy);
+
+            ds.resolve_startup_issues(vec![]).await;
+
+            // the policy is left out either way
+            assert!(ds.policies.read().await.is_empty());
+
+            let (tx, rx) = channel::<DsResponse>();
+            ds.get_server_stats(GetServerStatsRequest {}, tx).await;
+            let stats = match rx.await {
+                Ok(DsResponse::ServerStats(stats)) => stats,
+                _ => panic!("expected server stats"),
+            };
+            assert_eq!(stats.startup_mode(), mode);
+            assert_eq!(stats.groups, 1);
+            assert_eq!(stats.policies, 0);
+
+            let actions: HashMap<&str, &str> = stats
+                .startup_issues
+                .iter()
+                .map(|issue| (issue.kind.as_str(), issue.action.as_str()))
+                .collect();
+            assert_eq!(actions["policies"], "skipped");
+
+            // the dangling role is only dropped when repairing
+            let roles = ds.groups.read().await["admins"].roles.len();
+            match mode {
+                StartupMode::Repair => {
+                    assert_eq!(actions["groups"], "repaired");
+                    assert_eq!(roles, 0);
+                }
+                _ => {
+                    assert_eq!(actions["groups"], "reported");
+                    assert_eq!(roles, 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    async fn test_file_backend_failure() {
+        let basepath =
+            std::env::temp_dir().join(format!("gatehouse-partial-{}", std::process::id()));
+        let storage = StorageType::FileSystem(basepath.to_str().unwrap().to_string());
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&storage, Config::default(), req_tx, req_rx).await;
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddRoleRequest {
+            name: str("reader"),
+            ..Default::default()
+        };
+        ds.add_role(req, tx).await;
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddGroupRequest {
+            name: str("storage"),
+            roles: vec![str("reader")],
+            ..Default::default()
+        };
+        ds.add_group(req, tx).await;
+
+        // the role's file is already gone, which the file backend finds before it writes the
+        // group without the role, so nothing is saved
+        std::fs::remove_file(basepath.join("roles/reader.json")).unwrap();
+        let (tx, rx) = channel::<DsResponse>();
+        let req = RemoveRoleRequest {
+            name: str("reader"),
+            dry_run: false,
+        };
+        ds.remove_role(req, tx).await;
+        let status = match rx.await {
+            Ok(DsResponse::Error(status)) => status,
+            _ => panic!("expected an error"),
+        };
+        assert_eq!(status.code(), Code::Internal);
+        assert!(status.details().is_empty());
+        assert!(status.message().contains("reader.json"));
+
+        // and nothing is applied either
+        let group = basepath.join("groups/storage.json");
+        assert!(std::fs::read_to_string(group).unwrap().contains("reader"));
+        assert_eq!(ds.groups.read().await["storage"].roles.len(), 1);
+        assert!(ds.roles.read().await.contains_key("reader"));
+
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+
+    #[test]
+    async fn test_chaos() {
+        // removals fail, so removing a role fails after the group granting it is saved
+        let config = Config {
+            chaos: Some(ChaosConfig::parse("fail=1,ops=remove").unwrap()),
+            ..Default::default()
+        };
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddRoleRequest {
+            name: str("reader"),
+            ..Default::default()
+        };
+        ds.add_role(req, tx).await;
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddGroupRequest {
+            name: str("storage"),
+            roles: vec![str("reader")],
+            ..Default::default()
+        };
+        ds.add_group(req, tx).await;
+        let (tx, rx) = channel::<DsResponse>();
+        let req = RemoveRoleRequest {
+            name: str("reader"),
+            dry_run: false,
+        };
+        ds.remove_role(req, tx).await;
+        let status = match rx.await {
+            Ok(DsResponse::Error(status)) => status,
+            _ => panic!("expected an error"),
+        };
+        let failure = PartialFailure::decode(status.details()).unwrap();
+        assert_eq!(failure.saved, vec![str("put group storage")]);
+        assert_eq!(failure.unsaved, vec![str("delete role reader")]);
+        assert_eq!(failure.error, "Injected failure of remove_role");
+        assert!(ds.roles.read().await.contains_key("reader"));
+
+        // saves that keep failing trip the breaker
+        let config = Config {
+            chaos: Some(ChaosConfig::parse("fail=1,ops=save").unwrap()),
+            ..Default::default()
+        };
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+        for name in ["reader", "writer", "admin"] {
+            assert!(ds.storage_health.is_available());
+            let (tx, _rx) = channel::<DsResponse>();
+            let req = AddRoleRequest {
+                name: str(name),
+                ..Default::default()
+            };
+            ds.add_role(req, tx).await;
+        }
+        assert!(!ds.storage_health.is_available());
+        assert!(ds.roles.read().await.is_empty());
+        assert_eq!(
+            ds.storage_health.status().last_error,
+            "Injected failure of save_role"
+        );
+    }
+
+    #[test]
+    async fn test_migrations() {
+        let basepath =
+            std::env::temp_dir().join(format!("gatehouse-migrate-{}", std::process::id()));
+        let storage = StorageType::FileSystem(basepath.to_str().unwrap().to_string());
+        let path = basepath.join("roles/reader.json");
+
+        for mode in [MigrationMode::DryRun, MigrationMode::Apply] {
+            // a role saved before schema versions were stamped
+            std::fs::create_dir_all(basepath.join("roles")).unwrap();
+            let old = serde_json::to_string(&RegisteredRole::new("reader", None)).unwrap();
+            std::fs::write(&path, &old).unwrap();
+
+            let (req_tx, req_rx) = flume::unbounded();
+            let config = Config {
+                migrations: mode,
+                ..Default::default()
+            };
+            let ds = Datastore::new(&storage, config, req_tx, req_rx).await;
+            assert!(ds.roles.read().await.contains_key("reader"));
+
+            let issues = ds.startup_issues.read().await.clone();
+            assert_eq!(issues.len(), 1);
+            assert_eq!(issues[0].name, "reader");
+            assert_eq!(issues[0].problem, "Stored in schema version 0");
+
+            // only applying saves it in the current schema
+            let stored = std::fs::read_to_string(&path).unwrap();
+            match mode {
+                MigrationMode::DryRun => {
+                    assert_eq!(issues[0].action, "would migrate");
+                    assert_eq!(stored, old);
+                }
+                MigrationMode::Apply => {
+                    assert_eq!(issues[0].action, "migrated");
+                    assert!(stored.contains("\"schema_version\":1"));
+                }
+            }
+        }
+
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+
+    #[test]
+    async fn test_repair_drift() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddRoleRequest {
+            name: str("reader"),
+            ..Default::default()
+        };
+        ds.add_role(req, tx).await;
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddGroupRequest {
+            name: str("storage"),
+            roles: vec![str("reader")],
+            ..Default::default()
+        };
+        ds.add_group(req, tx).await;
+
+        // the role forgets the group granting it, and the group grants a role that is gone
+        ds.roles
+            .write()
+            .await
+            .get_mut("reader")
             .unwrap()
-            .contains_key("test"));
+            .groups
+            .clear();
+        let mut group = ds.groups.read().await["storage"].clone();
+        group.roles.insert(str("ghost"));
+        ds.groups.write().await.insert(str("storage"), group);
+
+        let repair = || async {
+            let (tx, rx) = channel::<DsResponse>();
+            ds.repair_drift(tx).await;
+            match rx.await {
+                Ok(DsResponse::DriftRepaired(repaired)) => repaired,
+                _ => panic!("expected repairs"),
+            }
+        };
+        let stats = || async {
+            let (tx, rx) = channel::<DsResponse>();
+            ds.get_server_stats(GetServerStatsRequest {}, tx).await;
+            match rx.await {
+                Ok(DsResponse::ServerStats(stats)) => (stats.drift, stats.drift_repairs),
+                _ => panic!("expected server stats"),
+            }
+        };
+
+        // drift is only repaired the second time it is seen
+        assert!(repair().await.is_empty());
+        assert_eq!(stats().await, (2, 0));
+        assert_eq!(
+            repair().await,
+            vec![str("put group storage"), str("put role reader")]
+        );
+        assert_eq!(stats().await, (0, 2));
+
+        assert!(ds.roles.read().await["reader"].groups.contains("storage"));
+        let roles = ds.groups.read().await["storage"].roles.clone();
+        assert_eq!(roles, HashSet::from([str("reader")]));
+        assert!(repair().await.is_empty());
+    }
+
+    #[test]
+    async fn test_webhooks() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+
+        let mut webhook = Webhook {
+            name: str("Alerts"),
+            url: str("ftp://example.com/hook"),
+            events: vec![crate::proto::webhooks::Event::DenyDecision.into()],
+            secret: Some(str("shh")),
+        };
+
+        // only http and https are supported
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddWebhookRequest {
+            webhook: Some(webhook.clone()),
+            ..Default::default()
+        };
+        ds.add_webhook(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::Error(_))));
+
+        webhook.url = str("https://example.com/hook");
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddWebhookRequest {
+            webhook: Some(webhook),
+            ..Default::default()
+        };
+        ds.add_webhook(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleWebhook(_))));
+
+        // the secret is never handed back
+        let (tx, rx) = channel::<DsResponse>();
+        let req = GetWebhooksRequest {
+            name: Some(str("alerts")),
+        };
+        ds.get_webhooks(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::MultipleWebhooks(hooks)) => {
+                assert_eq!(hooks.len(), 1);
+                assert_eq!(hooks[0].name, "alerts");
+                assert_eq!(hooks[0].secret, None);
+            }
+            _ => panic!("expected to get the webhook back"),
+        }
+        assert_eq!(ds.webhooks.read().await["alerts"].secret, Some(str("shh")));
+    }
+
+    #[test]
+    async fn test_attribute_rules() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let config = Config {
+            attribute_rules: Some(
+                serde_json::from_str(r#"{"actors": {"region": {"one_of": ["us", "emea"]}}}"#)
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+        let region = |value: &str| {
+            HashMap::from([(
+                str("region"),
+                AttributeValues {
+                    values: vec![str(value)],
+                },
+            )])
+        };
+
+        // a value that breaks the rule is refused, with the field it was given in
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddActorRequest {
+            name: str("kaitlyn"),
+            typestr: str("user"),
+            attributes: region("mars"),
+            ..Default::default()
+        };
+        ds.add_actor(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::Error(status)) => {
+                assert_eq!(status.code(), tonic::Code::InvalidArgument);
+                let violations = crate::helpers::attribute_violations(&status).unwrap();
+                assert_eq!(violations.violations[0].field, "attributes.region");
+                assert_eq!(violations.violations[0].value, "mars");
+            }
+            _ => panic!("Expected the actor to be refused"),
+        }
+        assert!(ds.actors.read().await.get("user").is_none());
+
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddActorRequest {
+            name: str("kaitlyn"),
+            typestr: str("user"),
+            attributes: region("us"),
+            ..Default::default()
+        };
+        ds.add_actor(req, tx).await;
+        assert!(matches!(rx.await, Ok(DsResponse::SingleActor(..))));
+
+        // modifications are held to the same rules
+        let (tx, rx) = channel::<DsResponse>();
+        let req = ModifyActorRequest {
+            name: str("kaitlyn"),
+            typestr: str("user"),
+            add_attributes: region("apac"),
+            ..Default::default()
+        };
+        ds.modify_actor(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::Error(status)) => {
+                assert!(status.message().contains("add_attributes.region"))
+            }
+            _ => panic!("Expected the change to be refused"),
+        }
+
+        // and so are bulk adds, one actor at a time
+        let (tx, rx) = channel::<DsResponse>();
+        let req = AddActorsRequest {
+            actors: ["emea", "mars"]
+                .into_iter()
+                .map(|value| AddActorRequest {
+                    name: format!("from-{value}"),
+                    typestr: str("user"),
+                    attributes: region(value),
+                    ..Default::default()
+                })
+                .collect(),
+            dry_run: false,
+        };
+        ds.add_actors(req, tx).await;
+        match rx.await {
+            Ok(DsResponse::AddedActors(results)) => {
+                assert!(results[0].error.is_empty());
+                assert_eq!(
+                    results[1].error,
+                    "attributes.region: \"mars\" is not one of us, emea"
+                );
+            }
+            _ => panic!("Expected results"),
+        }
+    }
+
+    #[test]
+    async fn test_decision_sampling() {
+        let dir = std::env::temp_dir().join(format!("gatehouse-sampling-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("samples.jsonl");
+
+        let (req_tx, req_rx) = flume::unbounded();
+        let config = Config {
+            sample_decisions: Some(path.to_str().unwrap().to_string()),
+            decision_sample_rate: 2,
+            ..Default::default()
+        };
+        let ds = Datastore::new(&StorageType::Nil, config, req_tx, req_rx).await;
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let req = AddActorRequest {
+            name: str("kaitlyn"),
+            typestr: str("user"),
+            attributes: HashMap::from([(
+                str("team"),
+                AttributeValues {
+                    values: vec![str("eng")],
+                },
+            )]),
+            ..Default::default()
+        };
+        ds.add_actor(req, tx).await;
+
+        for _ in 0..4 {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = CheckRequest {
+                actor: Some(Actor {
+                    name: str("kaitlyn"),
+                    typestr: str("user"),
+                    attributes: HashMap::new(),
+                    ..Default::default()
+                }),
+                target_name: str("db"),
+                target_type: str("database"),
+                target_action: vec![str("read")],
+                ..Default::default()
+            };
+            ds.check(req, tx).await;
+            assert!(matches!(rx.await, Ok(DsResponse::CheckResult(_))));
+        }
+
+        // every other check is sampled, with the attributes it was decided on
+        let samples = std::fs::read_to_string(&path).unwrap();
+        let samples: Vec<serde_json::Value> = samples
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0]["actor"]["attributes"]["team"], json!(["eng"]));
+        assert_eq!(samples[0]["decision"], json!("NOT_APPLICABLE"));
+        assert_eq!(samples[0]["actions"], json!(["read"]));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    async fn test_group_summary() {
+        let (req_tx, req_rx) = flume::unbounded();
+        let ds = Datastore::new(&StorageType::Nil, Config::default(), req_tx, req_rx).await;
+        for role in ["reader", "admin"] {
+            let (tx, _rx) = channel::<DsResponse>();
+            let req = AddRoleRequest {
+                name: str(role),
+                ..Default::default()
+            };
+            ds.add_role(req, tx).await;
+        }
+
+        let (tx, _rx) = channel::<DsResponse>();
+        let member = |name: &str, typestr: &str| GroupMember {
+            name: str(name),
+            typestr: str(typestr),
+        };
+        let req = AddGroupRequest {
+            name: str("admins"),
+            desc: Some(str("Administrators")),
+            members: vec![
+                member("kaitlyn", "user"),
+                member("ada", "user"),
+                member("deployer", "service"),
+            ],
+            roles: vec![str("reader"), str("admin")],
+            ..Default::default()
+        };
+        ds.add_group(req, tx).await;
+
+        for summary in [false, true] {
+            let (tx, rx) = channel::<DsResponse>();
+            let req = GetGroupsRequest {
+                summary,
+                ..Default::default()
+            };
+            ds.get_groups(req, tx).await;
+            let group = match rx.await {
+                Ok(DsResponse::MultipleGroups(mut groups)) => groups.remove(0),
+                _ => panic!("expected groups"),
+            };
+
+            // a summary leaves the members out, but not how many there are
+            assert_eq!(group.members.is_empty(), summary);
+            assert_eq!(
+                group.member_counts,
+                HashMap::from([(str("user"), 2), (str("service"), 1)])
+            );
+            assert_eq!(
+                group.to_string(),
+                "group[admins] \"Administrators\": 3 members (service: 1, user: 2)  roles [admin, \
+                 reader]"
+            );
+            if !summary {
+                assert!(format!("{group:#}")
+                    .ends_with("\n  service/deployer\n  user/ada\n  user/kaitlyn"));
+            }
+        }
     }
 
     // TODO! -- add more unit tests
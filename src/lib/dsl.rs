@@ -0,0 +1,786 @@
+#![warn(missing_docs)]
+
+//! A text language for writing policy rules
+//!
+//! Each rule reads like a sentence, and a file can hold any number of them:
+//!
+//! ```text
+//! # engineers can read anything but production databases
+//! policy eng-read: allow user(team=eng) to read database(env!=prod)
+//! policy no-contractors: deny user|service(contractor) to * *
+//! policy two-person-delete: allow with 2 approvals anyone(has-role=dba) to delete,drop database
+//!     when region=us|ca
+//! policy try-mfa: shadow deny anyone(!mfa) to write *(name=ledger)
+//! ```
+//!
+//! * `policy <name>:` starts a rule; comment lines right above it become its description
+//! * `shadow` only logs the decision; the decision is `allow`, `deny`, or `allow with approval`
+//!   (or `with N approvals`)
+//! * the actor is `anyone` (or `*`) or one or more types separated by `|`, and the target is `*`
+//!   or one or more types; either can have conditions in parentheses
+//! * the actions after `to` are separated by commas, or `*` for every action; `except` and more
+//!   actions or action groups leaves those out, e.g. `to * except delete,drop`
+//! * `when` adds conditions on the environment attributes
+//!
+//! A condition `key=a|b` needs the attribute to have one of the values, `key!=a|b` none of them,
+//! `key` any value, and `!key` no value. The key `name` checks the actor's or target's name
+//! instead. Names, types, keys, and values can have letters, digits, and `-_.:/@`; anything else,
+//! including a literal `*` or `name` or a word of the language, goes in double quotes.
+//!
+//! Only some of what a policy rule can check can be written this way, so [`print`] refuses rules
+//! that use the rest, e.g. WASM modules, rate checks, or attribute comparisons, and rules with
+//! tags.
+
+use std::collections::HashSet;
+
+use crate::proto::policies::{
+    ActorCheck, Decide, Kv, KvCheck, Mode, PolicyRule, Set, StringCheck, TargetCheck,
+};
+
+/// Words of the language, which values have to be quoted to be
+const KEYWORDS: [&str; 11] = [
+    "policy",
+    "shadow",
+    "allow",
+    "deny",
+    "with",
+    "approval",
+    "approvals",
+    "anyone",
+    "to",
+    "except",
+    "when",
+];
+
+/// Parse rules written in the language
+pub fn parse(text: &str) -> Result<Vec<PolicyRule>, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+
+    let mut rules = Vec::new();
+    let mut names = HashSet::new();
+    loop {
+        let desc = parser.description();
+        if parser.peek().is_none() {
+            break;
+        }
+        let rule = parser.rule(desc)?;
+        if !names.insert(rule.name.to_ascii_lowercase()) {
+            return Err(format!("More than one rule is named {}", rule.name));
+        }
+        rules.push(rule);
+    }
+
+    Ok(rules)
+}
+
+/// Write a rule in the language, if it only checks what the language can express
+pub fn print(rule: &PolicyRule) -> Result<String, String> {
+    let unsupported = |what: &str| Err(format!("Policy {} {what}", rule.name));
+    if rule.wasm_module.is_some() {
+        return unsupported("uses a WASM module");
+    }
+    if !rule.tags.is_empty() {
+        return unsupported("has tags");
+    }
+    if !rule.compare_checks.is_empty() {
+        return unsupported("compares attributes");
+    }
+    if !rule.cidr_checks.is_empty() {
+        return unsupported("checks IP addresses");
+    }
+    if !rule.rate_checks.is_empty() {
+        return unsupported("has rate checks");
+    }
+    if rule.risk.is_some() {
+        return unsupported("checks the risk score");
+    }
+
+    let mut text = String::new();
+    if let Some(ref desc) = rule.desc {
+        for line in desc.lines() {
+            text.push_str(&format!("# {line}\n"));
+        }
+    }
+    text.push_str(&format!("policy {}: ", quote(&rule.name)));
+    if rule.mode() == Mode::Shadow {
+        text.push_str("shadow ");
+    }
+    match rule.decision() {
+        Decide::Allow => text.push_str("allow "),
+        Decide::Deny => text.push_str("deny "),
+        Decide::AllowWithApproval => match rule.approvals {
+            0 | 1 => text.push_str("allow with approval "),
+            approvals => text.push_str(&format!("allow with {approvals} approvals ")),
+        },
+        decision @ (Decide::Pending | Decide::NotApplicable | Decide::Indeterminate) => {
+            return unsupported(&format!("decides {decision}"))
+        }
+    }
+
+    // the actor
+    let default_actor = ActorCheck::default();
+    let actor = rule.actor_check.as_ref().unwrap_or(&default_actor);
+    if actor.bucket.is_some() {
+        return unsupported("checks the actor's bucket");
+    }
+    match actor.typestr {
+        None => text.push_str("anyone"),
+        Some(ref check) => text.push_str(
+            &types(check)
+                .map_err(|err| format!("Policy {} checks the actor type {err}", rule.name))?,
+        ),
+    }
+    let conditions = print_conditions(&actor.name, &actor.attributes)
+        .map_err(|err| format!("Policy {} checks the actor {err}", rule.name))?;
+    if !conditions.is_empty() {
+        text.push_str(&format!("({conditions})"));
+    }
+
+    // the actions and target
+    let default_target = TargetCheck::default();
+    let target = rule.target_check.as_ref().unwrap_or(&default_target);
+    if !target.match_in_actor.is_empty() || !target.match_in_env.is_empty() {
+        return unsupported("matches target attributes with others");
+    }
+    let actions = match target.action {
+        None => String::from("*"),
+        Some(ref check) if check.val_cmp() == Set::Has && !check.vals.is_empty() => {
+            let actions: Vec<String> = check.vals.iter().map(|val| quote(val)).collect();
+            actions.join(",")
+        }
+        Some(_) => return unsupported("checks actions with something other than a list"),
+    };
+    text.push_str(&format!(" to {actions} "));
+    if !target.action_except.is_empty() {
+        let excepted: Vec<String> = target.action_except.iter().map(|val| quote(val)).collect();
+        text.push_str(&format!("except {} ", excepted.join(",")));
+    }
+
+    match (rule.target_types.is_empty(), &target.typestr) {
+        (true, None) => text.push('*'),
+        (false, None) => {
+            let target_types: Vec<String> =
+                rule.target_types.iter().map(|val| quote(val)).collect();
+            text.push_str(&target_types.join("|"));
+        }
+        (true, Some(check)) => text.push_str(
+            &types(check)
+                .map_err(|err| format!("Policy {} checks the target type {err}", rule.name))?,
+        ),
+        (false, Some(_)) => return unsupported("checks the target type twice"),
+    }
+    let conditions = print_conditions(&target.name, &target.attributes)
+        .map_err(|err| format!("Policy {} checks the target {err}", rule.name))?;
+    if !conditions.is_empty() {
+        text.push_str(&format!("({conditions})"));
+    }
+
+    // the environment
+    let conditions = print_conditions(&None, &rule.env_attributes)
+        .map_err(|err| format!("Policy {} checks the environment {err}", rule.name))?;
+    if !conditions.is_empty() {
+        text.push_str(&format!(" when {conditions}"));
+    }
+
+    Ok(text)
+}
+
+/// A type check as types separated by `|`
+fn types(check: &StringCheck) -> Result<String, String> {
+    if check.val_cmp() != Set::Has || check.vals.is_empty() {
+        return Err(String::from("with something other than a list"));
+    }
+    let vals: Vec<String> = check.vals.iter().map(|val| quote(val)).collect();
+    Ok(vals.join("|"))
+}
+
+/// A name check and attribute checks as conditions separated by commas
+fn print_conditions(name: &Option<StringCheck>, attributes: &[KvCheck]) -> Result<String, String> {
+    let alternatives = |vals: &[String]| {
+        let vals: Vec<String> = vals.iter().map(|val| quote(val)).collect();
+        vals.join("|")
+    };
+
+    let mut conditions = Vec::new();
+    if let Some(check) = name {
+        if check.vals.is_empty() {
+            return Err(String::from("name against no values"));
+        }
+        match check.val_cmp() {
+            Set::Has => conditions.push(format!("name={}", alternatives(&check.vals))),
+            Set::HasNot => conditions.push(format!("name!={}", alternatives(&check.vals))),
+        }
+    }
+    for check in attributes {
+        let key = match check.key.as_str() {
+            "name" => String::from("\"name\""),
+            key => quote(key),
+        };
+        match check.op() {
+            Kv::Has | Kv::HasNot if check.vals.is_empty() => {
+                return Err(format!("{} against no values", check.key))
+            }
+            Kv::Has => conditions.push(format!("{key}={}", alternatives(&check.vals))),
+            Kv::HasNot => conditions.push(format!("{key}!={}", alternatives(&check.vals))),
+            Kv::Exists => conditions.push(key),
+            Kv::NotExists => conditions.push(format!("!{key}")),
+            Kv::CountAtLeast | Kv::ContainsAll => {
+                return Err(format!("{} with a count or for every value", check.key))
+            }
+        }
+    }
+
+    Ok(conditions.join(", "))
+}
+
+/// Quote a value unless it can be written as it is
+fn quote(val: &str) -> String {
+    let plain = !val.is_empty()
+        && val != "*"
+        && !val.ends_with(':')
+        && !KEYWORDS.contains(&val.to_ascii_lowercase().as_str())
+        && val.chars().all(|c| is_word(c) || c == ':');
+    match plain {
+        true => val.to_string(),
+        false => format!("\"{}\"", val.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
+
+/// Whether a character can be part of a value without quotes, besides `:` between others
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || "-_.@/*".contains(c)
+}
+
+/// A piece of the text, with the line it is on
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// a name, type, key, or value, or a word of the language
+    Word(String),
+    /// a value in double quotes, never a word of the language
+    Quoted(String),
+    /// punctuation: `(`, `)`, `,`, `|`, `:`, `=`, `!=`, or `!`
+    Sym(&'static str),
+    /// a comment, without the `#`
+    Comment(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{word}"),
+            Token::Quoted(val) => write!(f, "\"{val}\""),
+            Token::Sym(sym) => write!(f, "{sym}"),
+            Token::Comment(_) => write!(f, "a comment"),
+        }
+    }
+}
+
+/// Split the text into tokens
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line_no = line_no + 1;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                c if c.is_whitespace() => continue,
+                '#' => Token::Comment(chars.by_ref().collect::<String>().trim().to_string()),
+                '(' => Token::Sym("("),
+                ')' => Token::Sym(")"),
+                ',' => Token::Sym(","),
+                '|' => Token::Sym("|"),
+                ':' => Token::Sym(":"),
+                '=' => Token::Sym("="),
+                '!' if chars.peek() == Some(&'=') => {
+                    chars.next();
+                    Token::Sym("!=")
+                }
+                '!' => Token::Sym("!"),
+                '"' => {
+                    let mut val = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => match chars.next() {
+                                Some(c) => val.push(c),
+                                None => return Err(format!("Line {line_no}: unfinished quote")),
+                            },
+                            Some(c) => val.push(c),
+                            None => return Err(format!("Line {line_no}: unfinished quote")),
+                        }
+                    }
+                    Token::Quoted(val)
+                }
+                c if is_word(c) => {
+                    let mut word = String::from(c);
+                    loop {
+                        match chars.peek() {
+                            Some(&c) if is_word(c) => word.push(c),
+                            // a colon is part of a word only when more of the word follows
+                            Some(':') => {
+                                let mut ahead = chars.clone();
+                                ahead.next();
+                                match ahead.peek() {
+                                    Some(&c) if is_word(c) => word.push(':'),
+                                    _ => break,
+                                }
+                            }
+                            _ => break,
+                        }
+                        chars.next();
+                    }
+                    Token::Word(word)
+                }
+                c => return Err(format!("Line {line_no}: unexpected {c}")),
+            };
+            tokens.push((token, line_no));
+        }
+    }
+    Ok(tokens)
+}
+
+/// What an actor or target is checked for, besides its type
+#[derive(Default)]
+struct Conditions {
+    name: Option<StringCheck>,
+    attributes: Vec<KvCheck>,
+}
+
+/// Reads rules from the tokens of a text
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    /// The next token that isn't a comment, and the line it is on
+    fn peek_line(&self) -> Option<&(Token, usize)> {
+        self.tokens[self.pos..]
+            .iter()
+            .find(|(token, _)| !matches!(token, Token::Comment(_)))
+    }
+
+    /// The next token that isn't a comment
+    fn peek(&self) -> Option<&Token> {
+        self.peek_line().map(|(token, _)| token)
+    }
+
+    /// Take the next token that isn't a comment
+    fn next(&mut self) -> Option<Token> {
+        while let (Token::Comment(_), _) = self.tokens.get(self.pos)? {
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Some(self.tokens[self.pos - 1].0.clone())
+    }
+
+    /// An error at the next token
+    fn error<T>(&self, expected: &str) -> Result<T, String> {
+        match self.peek_line() {
+            Some((token, line_no)) => {
+                Err(format!("Line {line_no}: expected {expected}, not {token}"))
+            }
+            None => Err(format!("Expected {expected}, not the end")),
+        }
+    }
+
+    /// Take a word of the language if it is next
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Take punctuation if it is next
+    fn eat_sym(&mut self, sym: &str) -> bool {
+        match self.peek() {
+            Some(Token::Sym(next)) if *next == sym => {
+                self.next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Take a word of the language that has to be next
+    fn keyword(&mut self, keyword: &str) -> Result<(), String> {
+        match self.eat_keyword(keyword) {
+            true => Ok(()),
+            false => self.error(keyword),
+        }
+    }
+
+    /// Take a value that has to be next, and whether it was quoted
+    fn value(&mut self, expected: &str) -> Result<(String, bool), String> {
+        match self.peek() {
+            Some(Token::Word(_)) | Some(Token::Quoted(_)) => match self.next() {
+                Some(Token::Word(word)) => Ok((word, false)),
+                Some(Token::Quoted(val)) => Ok((val, true)),
+                _ => unreachable!(),
+            },
+            _ => self.error(expected),
+        }
+    }
+
+    /// Take values separated by `|`
+    fn alternatives(&mut self, expected: &str) -> Result<Vec<String>, String> {
+        let mut vals = vec![self.value(expected)?.0];
+        while self.eat_sym("|") {
+            vals.push(self.value(expected)?.0);
+        }
+        Ok(vals)
+    }
+
+    /// The comment lines right above the next rule, as its description
+    fn description(&mut self) -> Option<String> {
+        let mut lines: Vec<(String, usize)> = Vec::new();
+        while let Some((Token::Comment(comment), line_no)) = self.tokens.get(self.pos) {
+            // a comment after a rule on the same line, or apart from the ones before, starts over
+            let trailing = self.pos > 0 && self.tokens[self.pos - 1].1 == *line_no;
+            if trailing || lines.last().is_some_and(|(_, last)| last + 1 != *line_no) {
+                lines.clear();
+            }
+            if !trailing {
+                lines.push((comment.clone(), *line_no));
+            }
+            self.pos += 1;
+        }
+
+        let next_line = self.tokens.get(self.pos).map(|(_, line_no)| *line_no);
+        match lines.last() {
+            Some((_, last)) if Some(last + 1) == next_line => {
+                let lines: Vec<String> = lines.into_iter().map(|(line, _)| line).collect();
+                Some(lines.join("\n"))
+            }
+            _ => None,
+        }
+    }
+
+    /// Read a rule
+    fn rule(&mut self, desc: Option<String>) -> Result<PolicyRule, String> {
+        self.keyword("policy")?;
+        let (name, _) = self.value("a policy name")?;
+        if !self.eat_sym(":") {
+            return self.error(":");
+        }
+
+        let mode = match self.eat_keyword("shadow") {
+            true => Mode::Shadow,
+            false => Mode::Enforce,
+        };
+        let (decision, approvals) = if self.eat_keyword("deny") {
+            (Decide::Deny, 0)
+        } else if self.eat_keyword("allow") {
+            match self.eat_keyword("with") {
+                false => (Decide::Allow, 0),
+                true if self.eat_keyword("approval") => (Decide::AllowWithApproval, 1),
+                true => {
+                    let approvals = match self.peek() {
+                        Some(Token::Word(word)) => word.parse::<u32>().ok(),
+                        _ => None,
+                    };
+                    let approvals = match approvals {
+                        Some(approvals) => approvals,
+                        None => return self.error("a number of approvals"),
+                    };
+                    self.next();
+                    if !self.eat_keyword("approvals") && !self.eat_keyword("approval") {
+                        return self.error("approvals");
+                    }
+                    (Decide::AllowWithApproval, approvals)
+                }
+            }
+        } else {
+            return self.error("allow or deny");
+        };
+
+        // the actor
+        let mut actor = ActorCheck::default();
+        if !self.eat_keyword("anyone") {
+            match self.value("anyone or an actor type")? {
+                (typestr, false) if typestr == "*" => (),
+                (typestr, _) => {
+                    let mut types = vec![typestr];
+                    while self.eat_sym("|") {
+                        types.push(self.value("an actor type")?.0);
+                    }
+                    actor.typestr = Some(StringCheck {
+                        val_cmp: Set::Has.into(),
+                        vals: types,
+                    });
+                }
+            }
+        }
+        if self.eat_sym("(") {
+            let conditions = self.conditions(true)?;
+            if !self.eat_sym(")") {
+                return self.error(", or )");
+            }
+            actor.name = conditions.name;
+            actor.attributes = conditions.attributes;
+        }
+
+        // the actions and target
+        self.keyword("to")?;
+        let mut target = TargetCheck::default();
+        match self.value("actions")? {
+            (action, false) if action == "*" => (),
+            (action, _) => {
+                let mut actions = vec![action];
+                while self.eat_sym(",") {
+                    actions.push(self.value("an action")?.0);
+                }
+                target.action = Some(StringCheck {
+                    val_cmp: Set::Has.into(),
+                    vals: actions,
+                });
+            }
+        }
+        if self.eat_keyword("except") {
+            target.action_except.push(self.value("an action")?.0);
+            while self.eat_sym(",") {
+                target.action_except.push(self.value("an action")?.0);
+            }
+        }
+        let target_types = match self.value("* or a target type")? {
+            (target_type, false) if target_type == "*" => vec![],
+            (target_type, _) => {
+                let mut target_types = vec![target_type];
+                while self.eat_sym("|") {
+                    target_types.push(self.value("a target type")?.0);
+                }
+                target_types
+            }
+        };
+        if self.eat_sym("(") {
+            let conditions = self.conditions(true)?;
+            if !self.eat_sym(")") {
+                return self.error(", or )");
+            }
+            target.name = conditions.name;
+            target.attributes = conditions.attributes;
+        }
+
+        // the environment
+        let mut env_attributes = vec![];
+        if self.eat_keyword("when") {
+            env_attributes = self.conditions(false)?.attributes;
+        }
+        match self.peek() {
+            None => (),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("policy") => (),
+            Some(_) => return self.error("the next policy"),
+        }
+
+        Ok(PolicyRule {
+            name,
+            desc,
+            actor_check: match actor == ActorCheck::default() {
+                true => None,
+                false => Some(actor),
+            },
+            env_attributes,
+            target_check: match target == TargetCheck::default() {
+                true => None,
+                false => Some(target),
+            },
+            decision: decision.into(),
+            mode: mode.into(),
+            target_types,
+            approvals,
+            ..Default::default()
+        })
+    }
+
+    /// Read conditions separated by commas; `name` checks the name if names can be checked
+    fn conditions(&mut self, names: bool) -> Result<Conditions, String> {
+        let mut conditions = Conditions::default();
+        loop {
+            if self.eat_sym("!") {
+                let (key, _) = self.value("an attribute")?;
+                conditions.attributes.push(KvCheck {
+                    key,
+                    op: Kv::NotExists.into(),
+                    ..Default::default()
+                });
+            } else {
+                let (key, quoted) = self.value("a condition")?;
+                let op = if self.eat_sym("=") {
+                    Some(Set::Has)
+                } else if self.eat_sym("!=") {
+                    Some(Set::HasNot)
+                } else {
+                    None
+                };
+
+                match op {
+                    Some(op) if names && !quoted && key == "name" => {
+                        if conditions.name.is_some() {
+                            return self.error("one name condition");
+                        }
+                        conditions.name = Some(StringCheck {
+                            val_cmp: op.into(),
+                            vals: self.alternatives("a name")?,
+                        });
+                    }
+                    Some(op) => conditions.attributes.push(KvCheck {
+                        key,
+                        op: match op {
+                            Set::Has => Kv::Has,
+                            Set::HasNot => Kv::HasNot,
+                        }
+                        .into(),
+                        vals: self.alternatives("a value")?,
+                        ..Default::default()
+                    }),
+                    None => conditions.attributes.push(KvCheck {
+                        key,
+                        op: Kv::Exists.into(),
+                        ..Default::default()
+                    }),
+                }
+            }
+
+            if !self.eat_sym(",") {
+                return Ok(conditions);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let rules = parse(
+            r#"
+            # policies for the team
+
+            # engineers can read
+            # anything but production
+            policy eng-read: allow user(team=eng) to read database(env!=prod)
+            policy delete: allow with 2 approvals anyone(has-role=dba, !suspended) to delete,drop
+                database|queue when region=us|ca, vpn
+            policy no-ledger: shadow deny * to "*" *(name=ledger, "name"=x) # not yet
+            policy ssh: allow service to principal:deploy host
+            policy most: allow user to * except delete,admin-actions database
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 5);
+
+        let rule = &rules[0];
+        assert_eq!(rule.name, "eng-read");
+        assert_eq!(
+            rule.desc.as_deref(),
+            Some("engineers can read\nanything but production")
+        );
+        assert_eq!(rule.decision(), Decide::Allow);
+        let actor = rule.actor_check.as_ref().unwrap();
+        assert_eq!(actor.typestr.as_ref().unwrap().vals, vec!["user"]);
+        assert_eq!(actor.attributes[0].key, "team");
+        assert_eq!(actor.attributes[0].op(), Kv::Has);
+        assert_eq!(rule.target_types, vec!["database"]);
+        let target = rule.target_check.as_ref().unwrap();
+        assert_eq!(target.action.as_ref().unwrap().vals, vec!["read"]);
+        assert_eq!(target.attributes[0].op(), Kv::HasNot);
+
+        let rule = &rules[1];
+        assert_eq!(rule.desc, None);
+        assert_eq!(rule.decision(), Decide::AllowWithApproval);
+        assert_eq!(rule.approvals, 2);
+        assert!(rule.actor_check.as_ref().unwrap().typestr.is_none());
+        assert_eq!(
+            rule.actor_check.as_ref().unwrap().attributes[1].op(),
+            Kv::NotExists
+        );
+        assert_eq!(rule.target_types, vec!["database", "queue"]);
+        assert_eq!(rule.env_attributes[0].vals, vec!["us", "ca"]);
+        assert_eq!(rule.env_attributes[1].op(), Kv::Exists);
+
+        // a quoted * or name is just a value
+        let rule = &rules[2];
+        assert_eq!(rule.mode(), Mode::Shadow);
+        assert!(rule.actor_check.is_none());
+        let target = rule.target_check.as_ref().unwrap();
+        assert_eq!(target.action.as_ref().unwrap().vals, vec!["*"]);
+        assert_eq!(target.name.as_ref().unwrap().vals, vec!["ledger"]);
+        assert_eq!(target.attributes[0].key, "name");
+        assert!(rule.target_types.is_empty());
+
+        assert_eq!(
+            rules[3]
+                .target_check
+                .as_ref()
+                .unwrap()
+                .action
+                .as_ref()
+                .unwrap()
+                .vals,
+            vec!["principal:deploy"]
+        );
+
+        // every action but some
+        let target = rules[4].target_check.as_ref().unwrap();
+        assert!(target.action.is_none());
+        assert_eq!(target.action_except, vec!["delete", "admin-actions"]);
+
+        for (text, err) in [
+            (
+                "policy a allow anyone to * *",
+                "Line 1: expected :, not allow",
+            ),
+            ("policy a: allow anyone to", "Expected actions, not the end"),
+            (
+                "policy a: permit anyone to * *",
+                "Line 1: expected allow or deny, not permit",
+            ),
+            (
+                "policy a: deny x(a=b to * *",
+                "Line 1: expected , or ), not to",
+            ),
+            (
+                "policy a: deny anyone to * * extra",
+                "Line 1: expected the next policy, not extra",
+            ),
+            (
+                "policy a: deny anyone to * *\npolicy A: deny anyone to * *",
+                "More than one rule is named A",
+            ),
+        ] {
+            assert_eq!(parse(text).unwrap_err(), err);
+        }
+    }
+
+    #[test]
+    fn test_print() {
+        let text = r#"# engineers can read
+policy eng-read: allow user(team=eng) to read database(env!=prod)
+policy delete: allow with 2 approvals anyone(has-role=dba, !suspended) to delete,drop database|queue when region=us|ca, vpn
+policy no-ledger: shadow deny "*"(name!=root|"has space") to "*" *(name=ledger, "name"="allow")
+policy most: allow user to read,write except "except" database"#;
+        let rules = parse(text).unwrap();
+        let printed: Vec<String> = rules.iter().map(|rule| print(rule).unwrap()).collect();
+        assert_eq!(printed.join("\n"), text);
+
+        let rule = PolicyRule {
+            name: String::from("limited"),
+            rate_checks: vec![Default::default()],
+            ..Default::default()
+        };
+        assert_eq!(print(&rule).unwrap_err(), "Policy limited has rate checks");
+        assert_eq!(
+            print(&PolicyRule::default()).unwrap(),
+            "policy \"\": deny anyone to * *"
+        );
+    }
+}
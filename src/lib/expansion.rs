@@ -0,0 +1,184 @@
+#![warn(missing_docs)]
+
+//! Actors' attributes with their groups and roles added, kept between checks
+//!
+//! Adding the groups an actor is in and the roles those give it means copying the actor's
+//! attributes, so the result is kept for the next check of the same actor. It is only used while
+//! the actor's registered attributes and the groups are unchanged: the groups carry a version
+//! that every change bumps, and a changed actor always has new attributes. Only registered actors
+//! are kept, so memory is bounded by how many are registered.
+
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::attribute::AttributeMap;
+use crate::intern::intern;
+use crate::shard::Typed;
+
+/// Attributes, by name
+pub(crate) type Attributes = Arc<AttributeMap>;
+
+/// the most actors kept; past this, they are all forgotten and kept again as they are checked
+const MAX_KEPT: usize = 100_000;
+
+/// A lock whose version changes with every write
+#[derive(Debug, Default)]
+pub(crate) struct Versioned<T> {
+    inner: RwLock<T>,
+    version: AtomicU64,
+}
+
+impl<T> Versioned<T> {
+    /// Wrap a value at version 0
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: RwLock::new(value),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    /// Lock for reading
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.inner.read().await
+    }
+
+    /// Lock for writing; the version changes when the lock is let go
+    pub async fn write(&self) -> VersionedWrite<'_, T> {
+        VersionedWrite {
+            guard: self.inner.write().await,
+            version: &self.version,
+        }
+    }
+
+    /// The current version; only stable while a read lock is held
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+}
+
+/// A versioned lock, locked for writing
+pub(crate) struct VersionedWrite<'a, T> {
+    guard: RwLockWriteGuard<'a, T>,
+    version: &'a AtomicU64,
+}
+
+impl<T> Deref for VersionedWrite<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for VersionedWrite<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for VersionedWrite<'_, T> {
+    fn drop(&mut self) {
+        // bumped before the lock is let go, so no reader sees the new value at the old version
+        self.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// An actor's attributes, and what they became with groups and roles added
+#[derive(Debug)]
+struct Expansion {
+    /// the version of the groups it was made with
+    version: u64,
+    registered: Attributes,
+    expanded: Attributes,
+}
+
+/// The expanded attributes of the actors checked, by type and then name
+#[derive(Debug, Default)]
+pub(crate) struct Expansions {
+    kept: Mutex<Typed<Expansion>>,
+}
+
+impl Expansions {
+    /// The expanded attributes of an actor, if they were made from the same attributes and groups
+    pub fn get(
+        &self,
+        typestr: &str,
+        name: &str,
+        registered: &Attributes,
+        version: u64,
+    ) -> Option<Attributes> {
+        let kept = self.kept.lock().unwrap();
+        kept.get(typestr)
+            .and_then(|typed| typed.get(name))
+            .filter(|kept| kept.version == version && Arc::ptr_eq(&kept.registered, registered))
+            .map(|kept| Arc::clone(&kept.expanded))
+    }
+
+    /// Keep the expanded attributes of an actor
+    pub fn keep(
+        &self,
+        typestr: &str,
+        name: &str,
+        registered: Attributes,
+        version: u64,
+        expanded: Attributes,
+    ) {
+        let mut kept = self.kept.lock().unwrap();
+        if kept.values().map(HashMap::len).sum::<usize>() >= MAX_KEPT {
+            kept.clear();
+        }
+        kept.entry(intern(typestr)).or_default().insert(
+            name.to_string(),
+            Expansion {
+                version,
+                registered,
+                expanded,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute;
+
+    #[tokio::test]
+    async fn test_expansions() {
+        let groups = Versioned::new(0u32);
+        let registered: Attributes = Arc::default();
+        let expanded: Attributes = Arc::new(AttributeMap::from_iter([(
+            Arc::from("member-of"),
+            attribute::values([String::from("staff")]),
+        )]));
+
+        let expansions = Expansions::default();
+        let version = groups.version();
+        expansions.keep(
+            "user",
+            "kaitlyn",
+            registered.clone(),
+            version,
+            expanded.clone(),
+        );
+        let found = expansions.get("user", "kaitlyn", &registered, version);
+        assert!(found.is_some_and(|found| Arc::ptr_eq(&found, &expanded)));
+
+        // changed attributes, even if equal, aren't the ones it was made from
+        let changed: Attributes = Arc::default();
+        assert!(expansions
+            .get("user", "kaitlyn", &changed, version)
+            .is_none());
+
+        // nor are changed groups
+        *groups.write().await += 1;
+        assert_ne!(groups.version(), version);
+        assert!(expansions
+            .get("user", "kaitlyn", &registered, groups.version())
+            .is_none());
+    }
+}
@@ -0,0 +1,204 @@
+#![warn(missing_docs)]
+
+//! How checks are answered when the datastore can't be reached
+//!
+//! A check the datastore doesn't answer in time, or at all, normally fails. It can instead be
+//! denied, allowed, or given the last decision the datastore made on the same check. The
+//! response's `source` says which, so PEPs know how far to trust the decision.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::cache::RequestKey;
+use crate::proto::base::{ActionDecision, ActionMode, CheckRequest, CheckResponse, DecisionSource};
+use crate::proto::policies::Decide;
+
+/// how long the datastore has to answer a check when no timeout is set
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// the most decisions remembered for answering checks from
+const MAX_REMEMBERED: usize = 10_000;
+
+/// What a check gets when the datastore can't answer it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnavailableChecks {
+    /// the check fails with the datastore's error
+    #[default]
+    Error,
+    /// the check is denied
+    Deny,
+    /// the check is allowed
+    Allow,
+    /// the check gets the last decision made on the same check, or is denied if there was none
+    Cached,
+}
+
+impl UnavailableChecks {
+    /// Read the behaviour from its name: `error`, `deny`, `allow`, or `cached`
+    pub fn parse(val: &str) -> Result<Self, String> {
+        match val.to_ascii_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "deny" => Ok(Self::Deny),
+            "allow" => Ok(Self::Allow),
+            "cached" => Ok(Self::Cached),
+            _ => Err(format!("Unknown behaviour for unavailable checks: {val}")),
+        }
+    }
+}
+
+/// Answers checks the datastore of one store can't
+#[derive(Debug, Default)]
+pub(crate) struct Fallback {
+    mode: UnavailableChecks,
+    /// how long the datastore has to answer a check
+    pub timeout: Duration,
+    /// decisions the datastore made, by request, along with when they were made
+    decisions: Mutex<HashMap<RequestKey, (CheckResponse, Instant)>>,
+}
+
+impl Fallback {
+    /// Answer checks in this mode when the datastore doesn't answer within the timeout
+    pub fn new(mode: UnavailableChecks, timeout: Duration) -> Self {
+        Self {
+            mode,
+            timeout,
+            decisions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remember a decision the datastore made, if we may need to answer with it later
+    ///
+    /// Only decisions that came from evaluating the policies are kept; ones that need approval
+    /// or didn't can't stand in for the datastore.
+    pub fn remember(&self, req: &CheckRequest, resp: &CheckResponse) {
+        if self.mode != UnavailableChecks::Cached
+            || resp.source() != DecisionSource::Evaluated
+            || resp.decision() == Decide::Pending
+        {
+            return;
+        }
+
+        let mut decisions = self.decisions.lock().unwrap();
+        if decisions.len() >= MAX_REMEMBERED {
+            if let Some(key) = decisions
+                .iter()
+                .min_by_key(|(_, (_, at))| *at)
+                .map(|(key, _)| key.clone())
+            {
+                decisions.remove(&key);
+            }
+        }
+        decisions.insert(RequestKey::new(req), (resp.clone(), Instant::now()));
+    }
+
+    /// The answer to a check the datastore couldn't answer, if checks get one
+    pub fn answer(&self, req: &CheckRequest) -> Option<CheckResponse> {
+        let (decision, source) = match self.mode {
+            UnavailableChecks::Error => return None,
+            UnavailableChecks::Deny => (Decide::Deny, DecisionSource::FailedClosed),
+            UnavailableChecks::Allow => (Decide::Allow, DecisionSource::FailedOpen),
+            UnavailableChecks::Cached => {
+                let decisions = self.decisions.lock().unwrap();
+                if let Some((resp, _)) = decisions.get(&RequestKey::new(req)) {
+                    return Some(CheckResponse {
+                        cache_ttl: 0,
+                        correlation_id: req.correlation_id.clone(),
+                        source: DecisionSource::Cached.into(),
+                        ..resp.clone()
+                    });
+                }
+                (Decide::Deny, DecisionSource::FailedClosed)
+            }
+        };
+
+        let action_decisions = match req.action_mode() {
+            ActionMode::EachAction => req
+                .target_action
+                .iter()
+                .map(|action| ActionDecision {
+                    action: action.clone(),
+                    decision: decision.into(),
+                })
+                .collect(),
+            ActionMode::AllActions => vec![],
+        };
+        Some(CheckResponse {
+            decision: decision.into(),
+            action_decisions,
+            correlation_id: req.correlation_id.clone(),
+            source: source.into(),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::actors::Actor;
+
+    use super::*;
+
+    #[test]
+    fn test_answer() {
+        let req = CheckRequest {
+            actor: Some(Actor {
+                name: String::from("kaitlyn"),
+                typestr: String::from("user"),
+                attributes: HashMap::new(),
+                ..Default::default()
+            }),
+            target_name: String::from("db"),
+            target_type: String::from("database"),
+            target_action: vec![String::from("read"), String::from("write")],
+            action_mode: ActionMode::EachAction.into(),
+            correlation_id: String::from("abc"),
+            ..Default::default()
+        };
+
+        let error = Fallback::new(UnavailableChecks::Error, DEFAULT_TIMEOUT);
+        assert!(error.answer(&req).is_none());
+
+        let allow = Fallback::new(UnavailableChecks::Allow, DEFAULT_TIMEOUT);
+        let resp = allow.answer(&req).unwrap();
+        assert_eq!(resp.decision(), Decide::Allow);
+        assert_eq!(resp.source(), DecisionSource::FailedOpen);
+        assert_eq!(resp.action_decisions.len(), 2);
+        assert_eq!(resp.correlation_id, "abc");
+
+        let cached = Fallback::new(UnavailableChecks::Cached, DEFAULT_TIMEOUT);
+        assert_eq!(
+            cached.answer(&req).unwrap().source(),
+            DecisionSource::FailedClosed
+        );
+        cached.remember(
+            &req,
+            &CheckResponse {
+                decision: Decide::Allow.into(),
+                cache_ttl: 60,
+                correlation_id: String::from("old"),
+                ..Default::default()
+            },
+        );
+        let resp = cached.answer(&req).unwrap();
+        assert_eq!(resp.decision(), Decide::Allow);
+        assert_eq!(resp.source(), DecisionSource::Cached);
+        assert_eq!(resp.cache_ttl, 0);
+        assert_eq!(resp.correlation_id, "abc");
+
+        // a decision that wasn't evaluated isn't remembered
+        let other = CheckRequest {
+            target_name: String::from("other"),
+            ..req.clone()
+        };
+        cached.remember(
+            &other,
+            &CheckResponse {
+                decision: Decide::Allow.into(),
+                source: DecisionSource::FailedOpen.into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(cached.answer(&other).unwrap().decision(), Decide::Deny);
+    }
+}
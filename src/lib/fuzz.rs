@@ -0,0 +1,389 @@
+#![warn(missing_docs)]
+
+//! Fuzzing of policy evaluation
+//!
+//! [`check_policies`] turns arbitrary bytes into a handful of policy rules and a check request,
+//! evaluates the request, and verifies the invariants every decision must hold:
+//!
+//! * evaluation is deterministic and doesn't depend on the order the rules were added in
+//! * a DENY from any enforced rule that applies overrides everything else
+//! * the decision is the one the rules that apply make, and NOT_APPLICABLE if none do
+//! * shadow rules never change the decision
+//! * the trace of a rule matches exactly when the rule applies
+//!
+//! The unit tests run it on inputs from proptest; the `fuzz` directory has a `cargo fuzz` target
+//! that runs it on inputs from libFuzzer. The module is only built for tests and with the
+//! `fuzzing` feature, which the fuzz target turns on.
+
+use std::collections::HashSet;
+
+use crate::actor::RegisteredActor;
+use crate::attribute::{self, AttributeMap};
+use crate::policy::{
+    decide_actions, Decide, Mode, PolicyStore, RegisteredPolicyRule, TargetAction,
+};
+use crate::proto::policies as protos;
+use crate::quota::EvalBudget;
+use crate::velocity::Velocity;
+use crate::wasm::WasmModules;
+
+const NAMES: &[&str] = &["kaitlyn", "sam", "builder"];
+const ACTOR_TYPES: &[&str] = &["user", "service"];
+const KEYS: &[&str] = &["role", "team", "region"];
+const VALUES: &[&str] = &["admin", "ops", "us", "emea"];
+const TARGET_NAMES: &[&str] = &["maindb", "website"];
+const TARGET_TYPES: &[&str] = &["database", "webapp"];
+const ACTIONS: &[&str] = &["read", "write", "delete"];
+const ACTION_GROUPS: &[&str] = &["readers", "writers"];
+
+/// Draws choices from the input bytes; once they run out, every choice is the first one
+struct Draw<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Draw<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn byte(&mut self) -> u8 {
+        let byte = self.data.get(self.pos).copied().unwrap_or_default();
+        self.pos += 1;
+        byte
+    }
+
+    /// a number below `n`
+    fn below(&mut self, n: usize) -> usize {
+        usize::from(self.byte()) % n
+    }
+
+    fn chance(&mut self) -> bool {
+        self.byte() & 1 == 1
+    }
+
+    fn pick(&mut self, items: &[&str]) -> String {
+        items[self.below(items.len())].to_string()
+    }
+
+    /// some of the items, picked by the bits of a byte
+    fn some(&mut self, items: &[&str]) -> Vec<String> {
+        let bits = self.byte();
+        items
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| bits & (1 << i) != 0)
+            .map(|(_, item)| item.to_string())
+            .collect()
+    }
+
+    fn string_check(&mut self, items: &[&str]) -> Option<protos::StringCheck> {
+        if !self.chance() {
+            return None;
+        }
+
+        let val_cmp = match self.chance() {
+            true => protos::Set::Has,
+            false => protos::Set::HasNot,
+        };
+        Some(protos::StringCheck {
+            val_cmp: val_cmp.into(),
+            vals: self.some(items),
+        })
+    }
+
+    fn kv_checks(&mut self) -> Vec<protos::KvCheck> {
+        (0..self.below(3))
+            .map(|_| {
+                let op = match self.below(6) {
+                    0 => protos::Kv::Has,
+                    1 => protos::Kv::HasNot,
+                    2 => protos::Kv::Exists,
+                    3 => protos::Kv::NotExists,
+                    4 => protos::Kv::CountAtLeast,
+                    _ => protos::Kv::ContainsAll,
+                };
+                protos::KvCheck {
+                    key: self.pick(KEYS),
+                    op: op.into(),
+                    vals: self.some(VALUES),
+                    count: self.chance().then(|| self.below(4) as u32),
+                }
+            })
+            .collect()
+    }
+
+    fn attributes(&mut self) -> AttributeMap {
+        let mut map = AttributeMap::default();
+        for key in KEYS {
+            if self.chance() {
+                map.insert((*key).into(), attribute::values(self.some(VALUES)));
+            }
+        }
+        map
+    }
+
+    fn rule(&mut self, name: String) -> RegisteredPolicyRule {
+        let actor_check = self.chance().then(|| protos::ActorCheck {
+            name: self.string_check(NAMES),
+            typestr: self.string_check(ACTOR_TYPES),
+            attributes: self.kv_checks(),
+            bucket: None,
+        });
+        let env_attributes = self.kv_checks();
+        let target_check = self.chance().then(|| protos::TargetCheck {
+            name: self.string_check(TARGET_NAMES),
+            typestr: self.string_check(TARGET_TYPES),
+            attributes: self.kv_checks(),
+            match_in_actor: self.some(KEYS),
+            match_in_env: self.some(KEYS),
+            action: self.string_check(&[ACTIONS, ACTION_GROUPS, &["*"]].concat()),
+            action_except: match self.below(4) {
+                0 => self.some(&[ACTIONS, ACTION_GROUPS].concat()),
+                _ => Vec::new(),
+            },
+        });
+        let decision = match self.below(3) {
+            0 => protos::Decide::Deny,
+            1 => protos::Decide::Allow,
+            _ => protos::Decide::AllowWithApproval,
+        };
+        let mode = match self.below(4) {
+            0 => protos::Mode::Shadow,
+            _ => protos::Mode::Enforce,
+        };
+        let target_types = match self.below(4) {
+            0 => self.some(TARGET_TYPES),
+            _ => Vec::new(),
+        };
+
+        RegisteredPolicyRule::from(protos::PolicyRule {
+            name,
+            actor_check,
+            env_attributes,
+            target_check,
+            decision: decision.into(),
+            mode: mode.into(),
+            target_types,
+            approvals: self.below(3) as u32,
+            ..Default::default()
+        })
+    }
+
+    fn request(&mut self) -> Request {
+        let actor_attributes = self.attributes();
+        Request {
+            actor: RegisteredActor::new(
+                &self.pick(NAMES),
+                &self.pick(ACTOR_TYPES),
+                actor_attributes,
+            ),
+            env_attributes: self.attributes(),
+            target_name: self.pick(TARGET_NAMES),
+            target_type: self.pick(TARGET_TYPES),
+            target_attributes: self.attributes(),
+            actions: (0..=self.below(3))
+                .map(|_| TargetAction {
+                    name: self.pick(ACTIONS),
+                    groups: self.some(ACTION_GROUPS).into_iter().collect::<HashSet<_>>(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A check request drawn from the input
+#[derive(Debug)]
+struct Request {
+    actor: RegisteredActor,
+    env_attributes: AttributeMap,
+    target_name: String,
+    target_type: String,
+    target_attributes: AttributeMap,
+    actions: Vec<TargetAction>,
+}
+
+impl Request {
+    fn decide(&self, rules: &[RegisteredPolicyRule]) -> Decide {
+        let store: PolicyStore = rules
+            .iter()
+            .map(|rule| (rule.name.clone(), rule.clone()))
+            .collect();
+        decide_actions(
+            &store,
+            &Default::default(),
+            &self.actor,
+            &self.env_attributes,
+            &self.target_name,
+            &self.target_type,
+            &self.target_attributes,
+            &self.actions,
+            &WasmModules::default(),
+            &Velocity::default(),
+            None,
+            &EvalBudget::unlimited(),
+        )
+    }
+
+    fn decision_for(&self, rule: &RegisteredPolicyRule, action: &TargetAction) -> Option<Decide> {
+        rule.decision_for(
+            &self.actor,
+            &self.env_attributes,
+            &self.target_name,
+            &self.target_type,
+            &self.target_attributes,
+            action,
+            &WasmModules::default(),
+            &Velocity::default(),
+        )
+    }
+
+    fn trace(&self, rule: &RegisteredPolicyRule, action: &TargetAction) -> protos::PolicyTrace {
+        rule.trace(
+            &self.actor,
+            &self.env_attributes,
+            &self.target_name,
+            &self.target_type,
+            &self.target_attributes,
+            action,
+            &WasmModules::default(),
+            &Velocity::default(),
+        )
+    }
+
+    /// the decision worked out from the decision of each rule on its own
+    fn expected(&self, rules: &[RegisteredPolicyRule]) -> Decide {
+        let mut decision = Decide::Allow;
+        for action in &self.actions {
+            let decisions: Vec<Decide> = rules
+                .iter()
+                .filter(|rule| rule.mode == Mode::Enforce)
+                .filter_map(|rule| self.decision_for(rule, action))
+                .collect();
+            let decided = match decisions.contains(&Decide::Deny) {
+                true => Decide::Deny,
+                false => decisions
+                    .into_iter()
+                    .reduce(Decide::strictest)
+                    .unwrap_or(Decide::NotApplicable),
+            };
+            decision = decision.strictest(decided);
+        }
+        decision
+    }
+}
+
+/// Check the invariants of policy evaluation on the rules and request drawn from the input
+///
+/// Any input is valid; the error describes the invariant that didn't hold.
+pub fn check_policies(data: &[u8]) -> Result<(), String> {
+    let mut draw = Draw::new(data);
+    let rules: Vec<RegisteredPolicyRule> = (0..=draw.below(5))
+        .map(|i| draw.rule(format!("rule{i}")))
+        .collect();
+    let request = draw.request();
+    let fail = |invariant: &str| {
+        Err(format!(
+            "{invariant}\nrules: {rules:#?}\nrequest: {request:#?}"
+        ))
+    };
+
+    let decision = request.decide(&rules);
+    if request.decide(&rules) != decision {
+        return fail("the same request got two decisions");
+    }
+
+    let mut reversed = rules.clone();
+    reversed.reverse();
+    if request.decide(&reversed) != decision {
+        return fail("the decision depends on the order of the rules");
+    }
+
+    if decision != request.expected(&rules) {
+        return fail(&format!(
+            "decided {decision:?} but the rules decide {:?}",
+            request.expected(&rules)
+        ));
+    }
+
+    let denied = rules.iter().any(|rule| {
+        rule.mode == Mode::Enforce
+            && request
+                .actions
+                .iter()
+                .any(|action| request.decision_for(rule, action) == Some(Decide::Deny))
+    });
+    if denied && decision != Decide::Deny {
+        return fail(&format!("decided {decision:?} although a rule denies"));
+    }
+
+    // a rule that denies everything always wins
+    let mut with_deny = rules.clone();
+    with_deny.push(RegisteredPolicyRule::from(protos::PolicyRule {
+        name: "deny everything".to_string(),
+        decision: protos::Decide::Deny.into(),
+        ..Default::default()
+    }));
+    if request.decide(&with_deny) != Decide::Deny {
+        return fail("a rule that denies everything was overridden");
+    }
+
+    // shadow rules, whatever they decide, are only logged
+    let mut with_shadows = rules.clone();
+    for decision in [protos::Decide::Deny, protos::Decide::Allow] {
+        with_shadows.push(RegisteredPolicyRule::from(protos::PolicyRule {
+            name: format!("shadow {decision}"),
+            decision: decision.into(),
+            mode: protos::Mode::Shadow.into(),
+            ..Default::default()
+        }));
+    }
+    if request.decide(&with_shadows) != decision {
+        return fail("a shadow rule changed the decision");
+    }
+
+    for rule in &rules {
+        for action in &request.actions {
+            let decided = request.decision_for(rule, action);
+            let trace = request.trace(rule, action);
+            if trace.matched != decided.is_some() {
+                return fail(&format!(
+                    "the trace of {} disagrees with its decision {decided:?}",
+                    rule.name
+                ));
+            }
+            if decided.is_some_and(|decided| decided != rule.decision) {
+                return fail(&format!(
+                    "{} decided something else than it says",
+                    rule.name
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(2000))]
+
+        #[test]
+        fn test_check_policies(data in vec(any::<u8>(), 0..256)) {
+            if let Err(err) = check_policies(&data) {
+                return Err(TestCaseError::fail(err));
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_policies_empty() {
+        // running out of input is fine too
+        check_policies(&[]).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+#![warn(missing_docs)]
+
+//! Short-lived grant tokens for checks that were allowed
+//!
+//! A grant is a JSON Web Token signed with HMAC-SHA256 (`HS256`) and a secret shared with the
+//! services that accept it. Its claims name the actor, the target, the actions that were allowed,
+//! and when it expires, so a service holding the secret can check access with [`verify`] and
+//! [`Grant::allows`] without asking Gatehouse. Any JWT library can verify it too.
+
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::ds::now;
+use crate::proto::base::Grant;
+use crate::webhook::sign::hmac_sha256;
+
+/// how long grants are valid for, in seconds, unless configured
+pub const DEFAULT_TTL: u32 = 5 * 60;
+
+/// the issuer claim of every grant
+const ISSUER: &str = "gatehouse";
+
+/// the header of every grant, as a JWT
+const HEADER: &str = r#"{"alg":"HS256","typ":"JWT"}"#;
+
+/// The claims of a grant token
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    iss: String,
+    sub: String,
+    actor_type: String,
+    actor_name: String,
+    target_type: String,
+    target_name: String,
+    actions: Vec<String>,
+    iat: u64,
+    exp: u64,
+}
+
+impl From<&Grant> for Claims {
+    fn from(grant: &Grant) -> Self {
+        Self {
+            iss: String::from(ISSUER),
+            sub: format!("{}/{}", grant.actor_type, grant.actor_name),
+            actor_type: grant.actor_type.clone(),
+            actor_name: grant.actor_name.clone(),
+            target_type: grant.target_type.clone(),
+            target_name: grant.target_name.clone(),
+            actions: grant.actions.clone(),
+            iat: grant.issued_at,
+            exp: grant.expires_at,
+        }
+    }
+}
+
+impl From<Claims> for Grant {
+    fn from(claims: Claims) -> Self {
+        Self {
+            actor_type: claims.actor_type,
+            actor_name: claims.actor_name,
+            target_type: claims.target_type,
+            target_name: claims.target_name,
+            actions: claims.actions,
+            issued_at: claims.iat,
+            expires_at: claims.exp,
+        }
+    }
+}
+
+impl Grant {
+    /// Whether the grant is for a target and allows an action on it; empty values aren't checked
+    pub fn allows(&self, target_type: &str, target_name: &str, action: &str) -> bool {
+        (target_type.is_empty() || self.target_type.eq_ignore_ascii_case(target_type))
+            && (target_name.is_empty() || self.target_name.eq_ignore_ascii_case(target_name))
+            && (action.is_empty()
+                || self
+                    .actions
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(action)))
+    }
+}
+
+/// Sign a grant as a token
+pub(crate) fn issue(secret: &str, grant: &Grant) -> Result<String, String> {
+    let claims = serde_json::to_vec(&Claims::from(grant)).map_err(|err| err.to_string())?;
+    let signed = format!("{}.{}", encode(HEADER.as_bytes()), encode(&claims));
+    let mac = hmac_sha256(secret.as_bytes(), signed.as_bytes());
+
+    Ok(format!("{signed}.{}", encode(&mac)))
+}
+
+/// Verify a token was signed with the secret and hasn't expired, and get its grant
+pub fn verify(token: &str, secret: &str) -> Result<Grant, String> {
+    let grant = decode(token, secret)?;
+    if grant.expires_at <= now() {
+        return Err(String::from("Grant has expired"));
+    }
+    Ok(grant)
+}
+
+/// Get the grant of a token if it was signed with the secret, whether or not it has expired
+pub(crate) fn decode(token: &str, secret: &str) -> Result<Grant, String> {
+    let (signed, mac) = token
+        .rsplit_once('.')
+        .ok_or_else(|| String::from("Grant is not a token"))?;
+    let (header, claims) = signed
+        .split_once('.')
+        .ok_or_else(|| String::from("Grant is not a token"))?;
+
+    let mac = base64::decode_config(mac, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| String::from("Grant signature is not base64"))?;
+    let expected = hmac_sha256(secret.as_bytes(), signed.as_bytes());
+    if !bool::from(mac.ct_eq(&expected[..])) {
+        return Err(String::from("Grant signature is not valid"));
+    }
+
+    // the signature is good, so the header is ours, but make sure nobody else's slipped through
+    let header = base64::decode_config(header, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| String::from("Grant header is not base64"))?;
+    if header != HEADER.as_bytes() {
+        return Err(String::from("Grant header is not one we issue"));
+    }
+    let claims = base64::decode_config(claims, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| String::from("Grant claims are not base64"))?;
+    let claims: Claims = serde_json::from_slice(&claims)
+        .map_err(|err| format!("Grant claims are not valid: {err}"))?;
+
+    Ok(claims.into())
+}
+
+/// Encode as unpadded URL-safe base64, as JWTs are
+fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let grant = Grant {
+            actor_type: String::from("user"),
+            actor_name: String::from("kaitlyn"),
+            target_type: String::from("database"),
+            target_name: String::from("db"),
+            actions: vec![String::from("read"), String::from("write")],
+            issued_at: now(),
+            expires_at: now() + 60,
+        };
+        let token = issue("secret", &grant).unwrap();
+        assert_eq!(token.split('.').count(), 3);
+
+        let verified = verify(&token, "secret").unwrap();
+        assert_eq!(verified, grant);
+        assert!(verified.allows("database", "DB", "write"));
+        assert!(verified.allows("", "", "read"));
+        assert!(!verified.allows("database", "db", "delete"));
+        assert!(!verified.allows("database", "other-db", "read"));
+
+        // another secret, or changed claims, don't verify
+        assert!(verify(&token, "other").is_err());
+        let (header, rest) = token.split_once('.').unwrap();
+        let (_, mac) = rest.split_once('.').unwrap();
+        let claims = Claims {
+            actions: vec![String::from("delete")],
+            ..Claims::from(&grant)
+        };
+        let forged = format!(
+            "{header}.{}.{mac}",
+            encode(&serde_json::to_vec(&claims).unwrap())
+        );
+        assert!(verify(&forged, "secret").is_err());
+
+        let expired = Grant {
+            expires_at: now() - 1,
+            ..grant
+        };
+        let token = issue("secret", &expired).unwrap();
+        assert_eq!(verify(&token, "secret").unwrap_err(), "Grant has expired");
+        assert!(decode(&token, "secret").is_ok());
+    }
+}
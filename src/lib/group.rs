@@ -3,18 +3,26 @@
 //! The Target type and methods
 use core::hash::Hash;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
 use crate::actor::RegisteredActor;
+use crate::intern;
 use crate::proto::groups::{Group, GroupMember};
+use crate::timestamps::Timestamps;
+
+/// The type of a member that is itself a group; everyone in it is in the group too
+pub(crate) const GROUP_MEMBER_TYPE: &str = "group";
 
 #[derive(Debug, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub(crate) struct RegisteredGroupMember {
     pub name: String,
-    pub typestr: String,
+    /// interned, so members of a type share one copy of it
+    #[serde(deserialize_with = "intern::deserialize")]
+    pub typestr: Arc<str>,
 }
 
 #[derive(Debug, Clone, Eq, Serialize, Deserialize)]
@@ -23,6 +31,22 @@ pub(crate) struct RegisteredGroup {
     pub desc: Option<String>,
     pub members: HashSet<RegisteredGroupMember>,
     pub roles: HashSet<String>,
+    /// the external system that manages this group, if any
+    #[serde(default)]
+    pub managed_by: Option<String>,
+    /// when the group was added and last changed
+    #[serde(flatten)]
+    pub timestamps: Timestamps,
+}
+
+impl RegisteredGroupMember {
+    /// The member that stands for everyone in a group
+    pub(crate) fn group(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            typestr: intern::intern(GROUP_MEMBER_TYPE),
+        }
+    }
 }
 
 impl RegisteredGroup {
@@ -38,19 +62,38 @@ impl RegisteredGroup {
             desc,
             members,
             roles,
+            managed_by: None,
+            timestamps: Timestamps::default(),
+        }
+    }
+
+    /// The group without its members, only how many there are of each type
+    pub(crate) fn summary(&self) -> Group {
+        Group {
+            name: self.name.clone(),
+            desc: self.desc.clone(),
+            members: Vec::new(),
+            roles: self.roles.iter().cloned().collect(),
+            managed_by: self.managed_by.clone(),
+            member_counts: member_counts(&self.members),
+            created_at: self.timestamps.created_at,
+            updated_at: self.timestamps.updated_at,
         }
     }
 }
 
+/// How many members of each type there are
+fn member_counts(members: &HashSet<RegisteredGroupMember>) -> HashMap<String, u64> {
+    let mut counts = HashMap::new();
+    for member in members {
+        *counts.entry(member.typestr.to_string()).or_default() += 1;
+    }
+    counts
+}
+
 impl Display for RegisteredGroup {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "group[{}]: {} members  {} roles",
-            self.name,
-            self.members.len(),
-            self.roles.len()
-        )
+        Display::fmt(&Group::from(self.clone()), f)
     }
 }
 
@@ -70,16 +113,21 @@ impl From<RegisteredGroupMember> for GroupMember {
     fn from(g: RegisteredGroupMember) -> Self {
         Self {
             name: g.name,
-            typestr: g.typestr,
+            typestr: g.typestr.to_string(),
         }
     }
 }
 
 impl From<GroupMember> for RegisteredGroupMember {
     fn from(g: GroupMember) -> Self {
+        // group names are kept in lowercase
+        let name = match g.typestr == GROUP_MEMBER_TYPE {
+            true => g.name.to_ascii_lowercase(),
+            false => g.name,
+        };
         Self {
-            name: g.name,
-            typestr: g.typestr,
+            name,
+            typestr: intern::intern(&g.typestr),
         }
     }
 }
@@ -95,10 +143,30 @@ impl From<&RegisteredActor> for RegisteredGroupMember {
 impl From<RegisteredGroup> for Group {
     fn from(g: RegisteredGroup) -> Self {
         Self {
+            member_counts: member_counts(&g.members),
             name: g.name,
             desc: g.desc,
             members: g.members.iter().map(|m| m.clone().into()).collect(),
             roles: g.roles.into_iter().collect(),
+            managed_by: g.managed_by,
+            created_at: g.timestamps.created_at,
+            updated_at: g.timestamps.updated_at,
+        }
+    }
+}
+
+impl From<Group> for RegisteredGroup {
+    fn from(g: Group) -> Self {
+        Self {
+            name: g.name.to_ascii_lowercase(),
+            desc: g.desc,
+            members: g.members.into_iter().map(|m| m.into()).collect(),
+            roles: g.roles.into_iter().collect(),
+            managed_by: g.managed_by,
+            timestamps: Timestamps {
+                created_at: g.created_at,
+                updated_at: g.updated_at,
+            },
         }
     }
 }
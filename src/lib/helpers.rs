@@ -1,31 +1,131 @@
 //! Helpers to quickly create requests and use a client to talk to the server
+//!
+//! The calls fail with a [`CallError`], which keeps the status code the server answered with.
 
 use std::collections::HashMap;
+use std::marker::PhantomData;
 
+use crate::bulk::MAX_BULK_ADD;
 use crate::proto::actors::{
-    Actor, AddActorRequest, GetActorsRequest, ModifyActorRequest, RemoveActorRequest,
+    Actor, AddActorRequest, AddActorResult, AddActorsRequest, GetActorsRequest, ModifyActorRequest,
+    RemoveActorRequest,
 };
-use crate::proto::common::AttributeValues;
+use crate::proto::common::{AttributeValues, AttributeViolations, PartialFailure, References};
 use crate::proto::groups::{
     AddGroupRequest, GetGroupsRequest, Group, GroupMember, ModifyGroupRequest, RemoveGroupRequest,
 };
 use crate::proto::policies::{
-    ActorCheck, AddPolicyRequest, Decide, GetPoliciesRequest, KvCheck, ModifyPolicyRequest,
-    PolicyRule, RemovePolicyRequest, TargetCheck,
+    AddPolicyRequest, GetPoliciesRequest, ModifyPolicyRequest, PolicyRule, RemovePolicyRequest,
 };
 use crate::proto::roles::{AddRoleRequest, GetRolesRequest, RemoveRoleRequest, Role};
-use tonic::transport::Channel;
+use tonic::body::BoxBody;
+use tonic::client::GrpcService;
+use tonic::codegen::{Body, Bytes, StdError};
+use tonic::{Code, Status};
 
 use crate::proto::base::gatehouse_client::GatehouseClient;
+use crate::proto::base::{
+    ActionMode, CheckRequest, CoverageReportRequest, CoverageReportResponse, GetServerInfoRequest,
+    GetServerInfoResponse, PolicyTestCase, ReloadStateRequest, TestPoliciesRequest,
+    TestPoliciesResponse, WhatIfRequest, WhatIfResponse,
+};
 use crate::proto::targets::{
-    AddTargetRequest, GetTargetsRequest, ModifyTargetRequest, RemoveTargetRequest, Target,
+    ActionGroup, AddTargetRequest, AddTargetResult, AddTargetsRequest, GetTargetsRequest,
+    ModifyTargetRequest, RemoveTargetRequest, Target,
+};
+use crate::proto::webhooks::{
+    AddWebhookRequest, Delivery, Event, GetDeliveriesRequest, GetWebhooksRequest,
+    RemoveWebhookRequest, Webhook,
 };
 
+/// A connection a client can make calls over, such as a `Channel`
+pub trait Transport:
+    GrpcService<BoxBody, Error = Self::Failure, ResponseBody = Self::Reply>
+{
+    /// why a call couldn't be made
+    type Failure: Into<StdError>;
+    /// the body of a response
+    type Reply: Body<Data = Bytes, Error = Self::ReplyError> + Send + 'static;
+    /// why a response's body couldn't be read
+    type ReplyError: Into<StdError> + Send;
+}
+
+impl<T> Transport for T
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    type Failure = T::Error;
+    type Reply = T::ResponseBody;
+    type ReplyError = <T::ResponseBody as Body>::Error;
+}
+
 /// Helper to quickly create a string
 pub fn str(s: &str) -> String {
     s.to_string()
 }
 
+/// Why a call to the server failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallError {
+    /// the status code the server answered with, or INTERNAL if its answer was missing something
+    pub code: Code,
+    /// what went wrong
+    pub message: String,
+}
+
+impl CallError {
+    /// a call the server answered with an error
+    fn status(context: &str, status: Status) -> Self {
+        Self {
+            code: status.code(),
+            message: format!("{context}: {}", status.message()),
+        }
+    }
+
+    /// a call whose answer was missing what it should have held
+    fn missing(message: &str) -> Self {
+        Self {
+            code: Code::Internal,
+            message: str(message),
+        }
+    }
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CallError {}
+
+impl From<CallError> for String {
+    fn from(err: CallError) -> Self {
+        err.message
+    }
+}
+
+/// What a change saved before it failed partway through, if that's why it failed
+pub fn partial_failure(status: &Status) -> Option<PartialFailure> {
+    match (status.code(), status.details()) {
+        (_, []) => None,
+        (Code::Internal, details) => prost::Message::decode(details).ok(),
+        _ => None,
+    }
+}
+
+/// The attribute values a write was refused for, if that's why it was refused
+pub fn attribute_violations(status: &Status) -> Option<AttributeViolations> {
+    match (status.code(), status.details()) {
+        (_, []) => None,
+        (Code::InvalidArgument, details) => prost::Message::decode(details).ok(),
+        _ => None,
+    }
+}
+
 /// Quickly create a hashmap of attribute names and attribute values
 pub fn to_attribs(attributes: Vec<(String, Vec<&str>)>) -> HashMap<String, AttributeValues> {
     attributes
@@ -41,428 +141,998 @@ pub fn to_attribs(attributes: Vec<(String, Vec<&str>)>) -> HashMap<String, Attri
         .collect()
 }
 
-/// Adds a single target
-pub async fn add_target(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
-    typestr: &str,
-    actions: Vec<&str>,
-    attributes: Vec<(String, Vec<&str>)>,
-) -> Result<Target, String> {
-    let actions = actions.into_iter().map(str).collect();
-    let attributes = to_attribs(attributes);
+/// Quickly create a hashmap of action group names and their actions
+pub fn to_action_groups(groups: Vec<(String, Vec<&str>)>) -> HashMap<String, ActionGroup> {
+    groups
+        .into_iter()
+        .map(|kv| {
+            (
+                kv.0,
+                ActionGroup {
+                    actions: kv.1.into_iter().map(str).collect(),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Marks a required field of a builder that hasn't been given yet; `build` is only there once
+/// every required field is [`Given`]
+#[derive(Debug, Clone, Copy)]
+pub struct Missing;
+
+/// Marks a required field of a builder that has been given
+#[derive(Debug, Clone, Copy)]
+pub struct Given;
+
+/// Builds a check request; the actor and the target are required
+///
+/// ```
+/// use gatehouse::helpers::CheckRequestBuilder;
+/// use gatehouse::proto::actors::Actor;
+///
+/// let actor = Actor { name: "kaitlyn".to_string(), typestr: "user".to_string(), ..Default::default() };
+/// let req = CheckRequestBuilder::new()
+///     .actor(actor)
+///     .target("database", "maindb")
+///     .action("read")
+///     .build();
+/// assert_eq!(req.target_action, vec!["read"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CheckRequestBuilder<A = Missing, T = Missing> {
+    req: CheckRequest,
+    required: PhantomData<(A, T)>,
+}
+
+impl Default for CheckRequestBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CheckRequestBuilder {
+    /// Start a check request with no actions
+    pub fn new() -> Self {
+        Self {
+            req: CheckRequest::default(),
+            required: PhantomData,
+        }
+    }
+}
+
+impl<A, T> CheckRequestBuilder<A, T> {
+    /// Move to other required fields
+    fn given<B, U>(self) -> CheckRequestBuilder<B, U> {
+        CheckRequestBuilder {
+            req: self.req,
+            required: PhantomData,
+        }
+    }
+
+    /// The actor taking the actions
+    pub fn actor(mut self, actor: Actor) -> CheckRequestBuilder<Given, T> {
+        self.req.actor = Some(actor);
+        self.given()
+    }
+
+    /// The target the actions are taken on
+    pub fn target(mut self, typestr: &str, name: &str) -> CheckRequestBuilder<A, Given> {
+        self.req.target_type = str(typestr);
+        self.req.target_name = str(name);
+        self.given()
+    }
+
+    /// Add an action; can be called again to check several
+    pub fn action(mut self, action: &str) -> Self {
+        self.req.target_action.push(str(action));
+        self
+    }
+
+    /// Set an environment attribute
+    pub fn env_attribute(mut self, key: &str, vals: &[&str]) -> Self {
+        self.req.env_attributes.insert(
+            str(key),
+            AttributeValues {
+                values: vals.iter().map(|v| str(v)).collect(),
+            },
+        );
+        self
+    }
+
+    /// Get a decision for each action as well as the overall one
+    pub fn each_action(mut self) -> Self {
+        self.req.action_mode = ActionMode::EachAction.into();
+        self
+    }
+
+    /// Reject the check if the target isn't registered or an action isn't one of its actions
+    pub fn strict(mut self) -> Self {
+        self.req.strict = true;
+        self
+    }
+
+    /// Follow the check through the logs with this id
+    pub fn correlation_id(mut self, id: &str) -> Self {
+        self.req.correlation_id = str(id);
+        self
+    }
+
+    /// Use the approval a PENDING decision on the same check asked for
+    pub fn approval_id(mut self, id: u64) -> Self {
+        self.req.approval_id = id;
+        self
+    }
+}
+
+impl CheckRequestBuilder<Given, Given> {
+    /// The check request
+    pub fn build(self) -> CheckRequest {
+        self.req
+    }
+}
+
+/// Builds a new target; its name and type are required up front
+///
+/// ```
+/// use gatehouse::helpers::AddTargetRequestBuilder;
+///
+/// let req = AddTargetRequestBuilder::new("database", "maindb")
+///     .actions(&["read", "write"])
+///     .action_group("writers", &["write"])
+///     .build();
+/// assert_eq!(req.actions, vec!["read", "write"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AddTargetRequestBuilder {
+    req: AddTargetRequest,
+}
+
+impl AddTargetRequestBuilder {
+    /// Start a target with no actions or attributes
+    pub fn new(typestr: &str, name: &str) -> Self {
+        Self {
+            req: AddTargetRequest {
+                name: str(name),
+                typestr: str(typestr),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Add these actions
+    pub fn actions(mut self, actions: &[&str]) -> Self {
+        self.req.actions.extend(actions.iter().map(|a| str(a)));
+        self
+    }
 
+    /// Add values to an attribute
+    pub fn attribute(mut self, key: &str, vals: &[&str]) -> Self {
+        self.req
+            .attributes
+            .extend(to_attribs(vec![(str(key), vals.to_vec())]));
+        self
+    }
+
+    /// Add an action group
+    pub fn action_group(mut self, group: &str, actions: &[&str]) -> Self {
+        self.req
+            .action_groups
+            .extend(to_action_groups(vec![(str(group), actions.to_vec())]));
+        self
+    }
+
+    /// Only validate the target, without adding it
+    pub fn dry_run(mut self) -> Self {
+        self.req.dry_run = true;
+        self
+    }
+
+    /// The target to add
+    pub fn build(self) -> AddTargetRequest {
+        self.req
+    }
+}
+
+/// Builds a change to a target; its name and type are required up front
+#[derive(Debug, Clone)]
+pub struct ModifyTargetRequestBuilder {
+    req: ModifyTargetRequest,
+}
+
+impl ModifyTargetRequestBuilder {
+    /// Start a change to a target that changes nothing
+    pub fn new(typestr: &str, name: &str) -> Self {
+        Self {
+            req: ModifyTargetRequest {
+                name: str(name),
+                typestr: str(typestr),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Add these actions
+    pub fn add_actions(mut self, actions: &[&str]) -> Self {
+        self.req.add_actions.extend(actions.iter().map(|a| str(a)));
+        self
+    }
+
+    /// Remove these actions
+    pub fn remove_actions(mut self, actions: &[&str]) -> Self {
+        self.req
+            .remove_actions
+            .extend(actions.iter().map(|a| str(a)));
+        self
+    }
+
+    /// Add values to an attribute
+    pub fn add_attribute(mut self, key: &str, vals: &[&str]) -> Self {
+        self.req
+            .add_attributes
+            .extend(to_attribs(vec![(str(key), vals.to_vec())]));
+        self
+    }
+
+    /// Remove values from an attribute; it is removed once it has none left
+    pub fn remove_attribute(mut self, key: &str, vals: &[&str]) -> Self {
+        self.req
+            .remove_attributes
+            .extend(to_attribs(vec![(str(key), vals.to_vec())]));
+        self
+    }
+
+    /// Add actions to an action group
+    pub fn add_action_group(mut self, group: &str, actions: &[&str]) -> Self {
+        self.req
+            .add_action_groups
+            .extend(to_action_groups(vec![(str(group), actions.to_vec())]));
+        self
+    }
+
+    /// Remove these action groups
+    pub fn remove_action_groups(mut self, groups: &[&str]) -> Self {
+        self.req
+            .remove_action_groups
+            .extend(groups.iter().map(|g| str(g)));
+        self
+    }
+
+    /// Only report what would change, without changing it
+    pub fn dry_run(mut self) -> Self {
+        self.req.dry_run = true;
+        self
+    }
+
+    /// The change to the target
+    pub fn build(self) -> ModifyTargetRequest {
+        self.req
+    }
+}
+
+/// Builds a new actor; its name and type are required up front
+///
+/// ```
+/// use gatehouse::helpers::AddActorRequestBuilder;
+///
+/// let req = AddActorRequestBuilder::new("user", "kaitlyn")
+///     .attribute("role", &["reader"])
+///     .build();
+/// assert_eq!(req.attributes["role"].values, vec!["reader"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AddActorRequestBuilder {
+    req: AddActorRequest,
+}
+
+impl AddActorRequestBuilder {
+    /// Start an actor with no attributes
+    pub fn new(typestr: &str, name: &str) -> Self {
+        Self {
+            req: AddActorRequest {
+                name: str(name),
+                typestr: str(typestr),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Add values to an attribute
+    pub fn attribute(mut self, key: &str, vals: &[&str]) -> Self {
+        self.req
+            .attributes
+            .extend(to_attribs(vec![(str(key), vals.to_vec())]));
+        self
+    }
+
+    /// Only validate the actor, without adding it
+    pub fn dry_run(mut self) -> Self {
+        self.req.dry_run = true;
+        self
+    }
+
+    /// The actor to add
+    pub fn build(self) -> AddActorRequest {
+        self.req
+    }
+}
+
+/// Builds a change to an actor; its name and type are required up front
+#[derive(Debug, Clone)]
+pub struct ModifyActorRequestBuilder {
+    req: ModifyActorRequest,
+}
+
+impl ModifyActorRequestBuilder {
+    /// Start a change to an actor that changes nothing
+    pub fn new(typestr: &str, name: &str) -> Self {
+        Self {
+            req: ModifyActorRequest {
+                name: str(name),
+                typestr: str(typestr),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Add values to an attribute
+    pub fn add_attribute(mut self, key: &str, vals: &[&str]) -> Self {
+        self.req
+            .add_attributes
+            .extend(to_attribs(vec![(str(key), vals.to_vec())]));
+        self
+    }
+
+    /// Remove values from an attribute; it is removed once it has none left
+    pub fn remove_attribute(mut self, key: &str, vals: &[&str]) -> Self {
+        self.req
+            .remove_attributes
+            .extend(to_attribs(vec![(str(key), vals.to_vec())]));
+        self
+    }
+
+    /// Only report what would change, without changing it
+    pub fn dry_run(mut self) -> Self {
+        self.req.dry_run = true;
+        self
+    }
+
+    /// The change to the actor
+    pub fn build(self) -> ModifyActorRequest {
+        self.req
+    }
+}
+
+/// Builds a new group; its name is required up front
+///
+/// ```
+/// use gatehouse::helpers::AddGroupRequestBuilder;
+///
+/// let req = AddGroupRequestBuilder::new("storage")
+///     .member("user", "kaitlyn")
+///     .roles(&["reader"])
+///     .build();
+/// assert_eq!(req.members[0].name, "kaitlyn");
+/// ```
+#[derive(Debug, Clone)]
+pub struct AddGroupRequestBuilder {
+    req: AddGroupRequest,
+}
+
+impl AddGroupRequestBuilder {
+    /// Start a group with no members or roles
+    pub fn new(name: &str) -> Self {
+        Self {
+            req: AddGroupRequest {
+                name: str(name),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Describe the group
+    pub fn desc(mut self, desc: &str) -> Self {
+        self.req.desc = Some(str(desc));
+        self
+    }
+
+    /// Add a member; can be called again to add more
+    pub fn member(mut self, typestr: &str, name: &str) -> Self {
+        self.req.members.push(member(typestr, name));
+        self
+    }
+
+    /// Grant these roles to the members
+    pub fn roles(mut self, roles: &[&str]) -> Self {
+        self.req.roles.extend(roles.iter().map(|r| str(r)));
+        self
+    }
+
+    /// Only validate the group, without adding it
+    pub fn dry_run(mut self) -> Self {
+        self.req.dry_run = true;
+        self
+    }
+
+    /// The group to add
+    pub fn build(self) -> AddGroupRequest {
+        self.req
+    }
+}
+
+/// Builds a change to a group; its name is required up front
+#[derive(Debug, Clone)]
+pub struct ModifyGroupRequestBuilder {
+    req: ModifyGroupRequest,
+}
+
+impl ModifyGroupRequestBuilder {
+    /// Start a change to a group that changes nothing
+    pub fn new(name: &str) -> Self {
+        Self {
+            req: ModifyGroupRequest {
+                name: str(name),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Describe the group
+    pub fn desc(mut self, desc: &str) -> Self {
+        self.req.desc = Some(str(desc));
+        self
+    }
+
+    /// Add a member; can be called again to add more
+    pub fn add_member(mut self, typestr: &str, name: &str) -> Self {
+        self.req.add_members.push(member(typestr, name));
+        self
+    }
+
+    /// Remove a member; can be called again to remove more
+    pub fn remove_member(mut self, typestr: &str, name: &str) -> Self {
+        self.req.remove_members.push(member(typestr, name));
+        self
+    }
+
+    /// Grant these roles to the members
+    pub fn add_roles(mut self, roles: &[&str]) -> Self {
+        self.req.add_roles.extend(roles.iter().map(|r| str(r)));
+        self
+    }
+
+    /// Revoke these roles from the members
+    pub fn remove_roles(mut self, roles: &[&str]) -> Self {
+        self.req.remove_roles.extend(roles.iter().map(|r| str(r)));
+        self
+    }
+
+    /// Only report what would change, without changing it
+    pub fn dry_run(mut self) -> Self {
+        self.req.dry_run = true;
+        self
+    }
+
+    /// The change to the group
+    pub fn build(self) -> ModifyGroupRequest {
+        self.req
+    }
+}
+
+/// A group member of a type and name
+fn member(typestr: &str, name: &str) -> GroupMember {
+    GroupMember {
+        name: str(name),
+        typestr: str(typestr),
+    }
+}
+
+/// Add a target, as put together with an [`AddTargetRequestBuilder`]
+pub async fn add_target(
+    client: &mut GatehouseClient<impl Transport>,
+    req: AddTargetRequest,
+) -> Result<Target, CallError> {
     client
-        .add_target(AddTargetRequest {
-            name: str(name),
-            typestr: str(typestr),
-            actions,
-            attributes,
-        })
+        .add_target(req)
         .await
-        .map_err(|err| format!("Failed to add target: {err}"))?
+        .map_err(|err| CallError::status("Failed to add target", err))?
         .into_inner()
         .target
-        .ok_or_else(|| str("No target returned after creation"))
+        .ok_or_else(|| CallError::missing("No target returned after creation"))
 }
 
-/// Modify a target
-pub async fn modify_target(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
-    typestr: &str,
-    add_actions: Vec<&str>,
-    add_attributes: Vec<(String, Vec<&str>)>,
-    remove_actions: Vec<&str>,
-    remove_attributes: Vec<(String, Vec<&str>)>,
-) -> Result<Target, String> {
-    let add_actions = add_actions.into_iter().map(str).collect();
-    let add_attributes = to_attribs(add_attributes);
-    let remove_actions = remove_actions.into_iter().map(str).collect();
-    let remove_attributes = to_attribs(remove_attributes);
+/// Add many targets, as many at a time as the server takes, and get a result for each
+pub async fn add_targets(
+    client: &mut GatehouseClient<impl Transport>,
+    targets: Vec<AddTargetRequest>,
+) -> Result<Vec<AddTargetResult>, CallError> {
+    let mut results = Vec::with_capacity(targets.len());
+    for batch in targets.chunks(MAX_BULK_ADD) {
+        let req = AddTargetsRequest {
+            targets: batch.to_vec(),
+            dry_run: false,
+        };
+        let resp = client
+            .add_targets(req)
+            .await
+            .map_err(|err| CallError::status("Failed to add targets", err))?;
+        results.extend(resp.into_inner().results);
+    }
+    Ok(results)
+}
 
+/// Modify a target, as put together with a [`ModifyTargetRequestBuilder`]
+pub async fn modify_target(
+    client: &mut GatehouseClient<impl Transport>,
+    req: ModifyTargetRequest,
+) -> Result<Target, CallError> {
     client
-        .modify_target(ModifyTargetRequest {
-            name: str(name),
-            typestr: str(typestr),
-            add_actions,
-            add_attributes,
-            remove_actions,
-            remove_attributes,
-        })
+        .modify_target(req)
         .await
-        .map_err(|err| format!("Failed to modify target: {err}"))?
+        .map_err(|err| CallError::status("Failed to modify target", err))?
         .into_inner()
         .target
-        .ok_or_else(|| str("No target returned after update"))
+        .ok_or_else(|| CallError::missing("No target returned after update"))
 }
 
 /// Remove target
 pub async fn remove_target(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
+    client: &mut GatehouseClient<impl Transport>,
     typestr: &str,
-) -> Result<Target, String> {
+    name: &str,
+) -> Result<Target, CallError> {
     client
         .remove_target(RemoveTargetRequest {
             name: str(name),
             typestr: str(typestr),
+            dry_run: false,
+            references: References::Leave.into(),
         })
         .await
-        .map_err(|err| format!("Failed to remove target: {err}"))?
+        .map_err(|err| CallError::status("Failed to remove target", err))?
         .into_inner()
         .target
-        .ok_or_else(|| str("No target returned after deletion"))
+        .ok_or_else(|| CallError::missing("No target returned after deletion"))
 }
 
-/// Get all targets
+/// Get all targets, or those of a type and name
 pub async fn get_targets<S: Into<String>>(
-    client: &mut GatehouseClient<Channel>,
-    name: Option<S>,
+    client: &mut GatehouseClient<impl Transport>,
     typestr: Option<S>,
-) -> Result<Vec<Target>, String> {
+    name: Option<S>,
+) -> Result<Vec<Target>, CallError> {
     let name = name.map(|str| str.into());
     let typestr = typestr.map(|str| str.into());
 
     Ok(client
-        .get_targets(GetTargetsRequest { name, typestr })
+        .get_targets(GetTargetsRequest {
+            name,
+            typestr,
+            ..Default::default()
+        })
         .await
-        .map_err(|err| format!("Failed to get targets: {err}"))?
+        .map_err(|err| CallError::status("Failed to get targets", err))?
         .into_inner()
         .targets)
 }
 
-/// Adds a single actor
+/// Add an actor, as put together with an [`AddActorRequestBuilder`]
 pub async fn add_actor(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
-    typestr: &str,
-    attributes: Vec<(String, Vec<&str>)>,
-) -> Result<Actor, String> {
-    let attributes = to_attribs(attributes);
-
+    client: &mut GatehouseClient<impl Transport>,
+    req: AddActorRequest,
+) -> Result<Actor, CallError> {
     client
-        .add_actor(AddActorRequest {
-            name: str(name),
-            typestr: str(typestr),
-            attributes,
-        })
+        .add_actor(req)
         .await
-        .map_err(|err| format!("Failed to add actor: {err}"))?
+        .map_err(|err| CallError::status("Failed to add actor", err))?
         .into_inner()
         .actor
-        .ok_or_else(|| str("No target returned after creation"))
+        .ok_or_else(|| CallError::missing("No actor returned after creation"))
 }
 
-/// Modify a actor
-pub async fn modify_actor(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
-    typestr: &str,
-    add_attributes: Vec<(String, Vec<&str>)>,
-    remove_attributes: Vec<(String, Vec<&str>)>,
-) -> Result<Actor, String> {
-    let add_attributes = to_attribs(add_attributes);
-    let remove_attributes = to_attribs(remove_attributes);
+/// Add many actors, as many at a time as the server takes, and get a result for each
+pub async fn add_actors(
+    client: &mut GatehouseClient<impl Transport>,
+    actors: Vec<AddActorRequest>,
+) -> Result<Vec<AddActorResult>, CallError> {
+    let mut results = Vec::with_capacity(actors.len());
+    for batch in actors.chunks(MAX_BULK_ADD) {
+        let req = AddActorsRequest {
+            actors: batch.to_vec(),
+            dry_run: false,
+        };
+        let resp = client
+            .add_actors(req)
+            .await
+            .map_err(|err| CallError::status("Failed to add actors", err))?;
+        results.extend(resp.into_inner().results);
+    }
+    Ok(results)
+}
 
+/// Modify an actor, as put together with a [`ModifyActorRequestBuilder`]
+pub async fn modify_actor(
+    client: &mut GatehouseClient<impl Transport>,
+    req: ModifyActorRequest,
+) -> Result<Actor, CallError> {
     client
-        .modify_actor(ModifyActorRequest {
-            name: str(name),
-            typestr: str(typestr),
-            add_attributes,
-            remove_attributes,
-        })
+        .modify_actor(req)
         .await
-        .map_err(|err| format!("Failed to modify actor: {err}"))?
+        .map_err(|err| CallError::status("Failed to modify actor", err))?
         .into_inner()
         .actor
-        .ok_or_else(|| str("No actor returned after update"))
+        .ok_or_else(|| CallError::missing("No actor returned after update"))
 }
 
 /// Remove actor
 pub async fn remove_actor(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
+    client: &mut GatehouseClient<impl Transport>,
     typestr: &str,
-) -> Result<Actor, String> {
+    name: &str,
+) -> Result<Actor, CallError> {
     client
         .remove_actor(RemoveActorRequest {
             name: str(name),
             typestr: str(typestr),
+            dry_run: false,
+            references: References::Leave.into(),
         })
         .await
-        .map_err(|err| format!("Failed to remove actor: {err}"))?
+        .map_err(|err| CallError::status("Failed to remove actor", err))?
         .into_inner()
         .actor
-        .ok_or_else(|| str("No actor returned after deletion"))
+        .ok_or_else(|| CallError::missing("No actor returned after deletion"))
 }
 
-/// Get all actors
+/// Get all actors, or those of a type and name
 pub async fn get_actors<S: Into<String>>(
-    client: &mut GatehouseClient<Channel>,
-    name: Option<S>,
+    client: &mut GatehouseClient<impl Transport>,
     typestr: Option<S>,
-) -> Result<Vec<Actor>, String> {
+    name: Option<S>,
+) -> Result<Vec<Actor>, CallError> {
     let name = name.map(|s| s.into());
     let typestr = typestr.map(|s| s.into());
 
     Ok(client
-        .get_actors(GetActorsRequest { name, typestr })
+        .get_actors(GetActorsRequest {
+            name,
+            typestr,
+            ..Default::default()
+        })
         .await
-        .map_err(|err| format!("Failed to get actors: {err}"))?
+        .map_err(|err| CallError::status("Failed to get actors", err))?
         .into_inner()
         .actors)
 }
 
 /// Add a role
 pub async fn add_role(
-    client: &mut GatehouseClient<Channel>,
+    client: &mut GatehouseClient<impl Transport>,
     name: &str,
     desc: Option<String>,
     groups: Vec<String>,
-) -> Result<Role, String> {
+) -> Result<Role, CallError> {
     client
         .add_role(AddRoleRequest {
             name: str(name),
             desc,
             granted_to: groups,
+            dry_run: false,
         })
         .await
-        .map_err(|err| format!("Failed to add role: {err}"))?
+        .map_err(|err| CallError::status("Failed to add role", err))?
         .into_inner()
         .role
-        .ok_or_else(|| str("No target returned after creation"))
+        .ok_or_else(|| CallError::missing("No target returned after creation"))
 }
 
 /// Remove role
 pub async fn remove_role(
-    client: &mut GatehouseClient<Channel>,
+    client: &mut GatehouseClient<impl Transport>,
     name: &str,
-) -> Result<Role, String> {
+) -> Result<Role, CallError> {
     client
-        .remove_role(RemoveRoleRequest { name: str(name) })
+        .remove_role(RemoveRoleRequest {
+            name: str(name),
+            dry_run: false,
+        })
         .await
-        .map_err(|err| format!("Failed to remove role: {err}"))?
+        .map_err(|err| CallError::status("Failed to remove role", err))?
         .into_inner()
         .role
-        .ok_or_else(|| str("No role returned after deletion"))
+        .ok_or_else(|| CallError::missing("No role returned after deletion"))
 }
 
 /// Get all roles
 pub async fn get_roles(
-    client: &mut GatehouseClient<Channel>,
+    client: &mut GatehouseClient<impl Transport>,
     name: Option<&str>,
-) -> Result<Vec<Role>, String> {
+) -> Result<Vec<Role>, CallError> {
     let name = name.map(str);
 
     Ok(client
-        .get_roles(GetRolesRequest { name })
+        .get_roles(GetRolesRequest {
+            name,
+            ..Default::default()
+        })
         .await
-        .map_err(|err| format!("Failed to get roles: {err}"))?
+        .map_err(|err| CallError::status("Failed to get roles", err))?
         .into_inner()
         .roles)
 }
 
-/// Add a group
+/// Add a group, as put together with an [`AddGroupRequestBuilder`]
 pub async fn add_group(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
-    desc: Option<&str>,
-    members: Vec<(&str, &str)>,
-    roles: Vec<&str>,
-) -> Result<Group, String> {
-    let members: Vec<GroupMember> = members
-        .iter()
-        .map(|(n, t)| GroupMember {
-            name: n.to_string(),
-            typestr: t.to_string(),
-        })
-        .collect();
-    let roles = roles.iter().map(|r| r.to_string()).collect();
-
-    let req = AddGroupRequest {
-        name: name.to_string(),
-        desc: desc.map(String::from),
-        members,
-        roles,
-    };
-
+    client: &mut GatehouseClient<impl Transport>,
+    req: AddGroupRequest,
+) -> Result<Group, CallError> {
     client
         .add_group(req)
         .await
-        .map_err(|err| format!("Failed to add group: {err}"))?
+        .map_err(|err| CallError::status("Failed to add group", err))?
         .into_inner()
         .group
-        .ok_or_else(|| str("No group in add group response"))
+        .ok_or_else(|| CallError::missing("No group in add group response"))
 }
 
-/// Modify a group
+/// Modify a group, as put together with a [`ModifyGroupRequestBuilder`]
 pub async fn modify_group(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
-    desc: Option<&str>,
-    add_members: Vec<(&str, &str)>,
-    add_roles: Vec<&str>,
-    remove_members: Vec<(&str, &str)>,
-    remove_roles: Vec<&str>,
-) -> Result<Group, String> {
-    let add_members: Vec<GroupMember> = add_members
-        .iter()
-        .map(|(n, t)| GroupMember {
-            name: n.to_string(),
-            typestr: t.to_string(),
-        })
-        .collect();
-    let add_roles = add_roles.iter().map(|r| r.to_string()).collect();
-
-    let remove_members: Vec<GroupMember> = remove_members
-        .iter()
-        .map(|(n, t)| GroupMember {
-            name: n.to_string(),
-            typestr: t.to_string(),
-        })
-        .collect();
-    let remove_roles = remove_roles.iter().map(|r| r.to_string()).collect();
-
-    let req = ModifyGroupRequest {
-        name: name.to_string(),
-        desc: desc.map(String::from),
-        add_members,
-        add_roles,
-        remove_members,
-        remove_roles,
-    };
-
+    client: &mut GatehouseClient<impl Transport>,
+    req: ModifyGroupRequest,
+) -> Result<Group, CallError> {
     client
         .modify_group(req)
         .await
-        .map_err(|err| format!("Failed to modify group: {err}"))?
+        .map_err(|err| CallError::status("Failed to modify group", err))?
         .into_inner()
         .group
-        .ok_or_else(|| str("Did not get group after modifications"))
+        .ok_or_else(|| CallError::missing("Did not get group after modifications"))
 }
 
 /// Remove group
 pub async fn remove_group(
-    client: &mut GatehouseClient<Channel>,
+    client: &mut GatehouseClient<impl Transport>,
     name: &str,
-) -> Result<Group, String> {
+) -> Result<Group, CallError> {
     client
         .remove_group(RemoveGroupRequest {
             name: name.to_string(),
+            dry_run: false,
         })
         .await
-        .map_err(|err| format!("Failed to remove group: {err}"))?
+        .map_err(|err| CallError::status("Failed to remove group", err))?
         .into_inner()
         .group
-        .ok_or_else(|| str("Did not get returned group after removal"))
+        .ok_or_else(|| CallError::missing("Did not get returned group after removal"))
 }
 
-/// Get groups
+/// Get groups, optionally those with a member, given as its type and name; with `summary`, each
+/// group comes without its members, just their counts by type
 pub async fn get_groups(
-    client: &mut GatehouseClient<Channel>,
+    client: &mut GatehouseClient<impl Transport>,
     name: Option<&str>,
     member: Option<(&str, &str)>,
     role: Option<&str>,
-) -> Result<Vec<Group>, String> {
+    summary: bool,
+) -> Result<Vec<Group>, CallError> {
     let name = name.map(String::from);
-    let member = member.map(|(name, typestr)| GroupMember {
-        name: name.to_string(),
-        typestr: typestr.to_string(),
-    });
+    let member = member.map(|(typestr, name)| self::member(typestr, name));
     let role = role.map(String::from);
 
     Ok(client
-        .get_groups(GetGroupsRequest { name, member, role })
+        .get_groups(GetGroupsRequest {
+            name,
+            member,
+            role,
+            summary,
+            ..Default::default()
+        })
         .await
-        .map_err(|err| format!("Failed to get groups: {err}"))?
+        .map_err(|err| CallError::status("Failed to get groups", err))?
         .into_inner()
         .groups)
 }
 
-/// Add a policy
+/// Add a policy, e.g. one put together with a [`crate::model::PolicyRuleBuilder`]
 pub async fn add_policy(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
-    desc: Option<&str>,
-    actor_check: Option<ActorCheck>,
-    env_attributes: Vec<KvCheck>,
-    target_check: Option<TargetCheck>,
-    decision: Decide,
-) -> Result<PolicyRule, String> {
-    let rule = PolicyRule {
-        name: name.to_string(),
-        desc: desc.map(String::from),
-        actor_check,
-        env_attributes,
-        target_check,
-        decision: decision.into(),
-    };
+    client: &mut GatehouseClient<impl Transport>,
+    rule: impl Into<PolicyRule>,
+) -> Result<PolicyRule, CallError> {
     client
-        .add_policy(AddPolicyRequest { rule: Some(rule) })
+        .add_policy(AddPolicyRequest {
+            rule: Some(rule.into()),
+            dry_run: false,
+        })
         .await
-        .map_err(|err| format!("Failed to add policy: {err}"))?
+        .map_err(|err| CallError::status("Failed to add policy", err))?
         .into_inner()
         .rule
-        .ok_or_else(|| str("No policy returned after creation"))
+        .ok_or_else(|| CallError::missing("No policy returned after creation"))
 }
 
-/// Modify/replace an existing policy
+/// Modify/replace an existing policy with one of the same name
 pub async fn modify_policy(
-    client: &mut GatehouseClient<Channel>,
-    name: &str,
-    desc: Option<&str>,
-    actor_check: Option<ActorCheck>,
-    env_attributes: Vec<KvCheck>,
-    target_check: Option<TargetCheck>,
-    decision: Decide,
-) -> Result<PolicyRule, String> {
-    let rule = PolicyRule {
-        name: name.to_string(),
-        desc: desc.map(String::from),
-        actor_check,
-        env_attributes,
-        target_check,
-        decision: decision.into(),
-    };
+    client: &mut GatehouseClient<impl Transport>,
+    rule: impl Into<PolicyRule>,
+) -> Result<PolicyRule, CallError> {
     client
-        .modify_policy(ModifyPolicyRequest { rule: Some(rule) })
+        .modify_policy(ModifyPolicyRequest {
+            rule: Some(rule.into()),
+            dry_run: false,
+        })
         .await
-        .map_err(|err| format!("Failed to modify policy: {err}"))?
+        .map_err(|err| CallError::status("Failed to modify policy", err))?
         .into_inner()
         .rule
-        .ok_or_else(|| str("No policy returned after update"))
+        .ok_or_else(|| CallError::missing("No policy returned after update"))
 }
 
 /// Remove an existing policy
 pub async fn remove_policy(
-    client: &mut GatehouseClient<Channel>,
+    client: &mut GatehouseClient<impl Transport>,
     name: &str,
-) -> Result<PolicyRule, String> {
+) -> Result<PolicyRule, CallError> {
     client
         .remove_policy(RemovePolicyRequest {
             name: name.to_string(),
+            dry_run: false,
         })
         .await
-        .map_err(|err| format!("Failed to remove policy: {err}"))?
+        .map_err(|err| CallError::status("Failed to remove policy", err))?
         .into_inner()
         .rule
-        .ok_or_else(|| str("No policy returned after removal"))
+        .ok_or_else(|| CallError::missing("No policy returned after removal"))
 }
 
 /// Search for policies
 pub async fn get_policies(
-    client: &mut GatehouseClient<Channel>,
+    client: &mut GatehouseClient<impl Transport>,
     name: Option<&str>,
-) -> Result<Vec<PolicyRule>, String> {
+) -> Result<Vec<PolicyRule>, CallError> {
     let req = GetPoliciesRequest {
         name: name.map(String::from),
+        ..Default::default()
     };
 
     Ok(client
         .get_policies(req)
         .await
-        .map_err(|err| format!("Failed to get policies: {err}"))?
+        .map_err(|err| CallError::status("Failed to get policies", err))?
         .into_inner()
         .rules)
 }
+
+/// Add a webhook that is sent the given events
+pub async fn add_webhook(
+    client: &mut GatehouseClient<impl Transport>,
+    name: &str,
+    url: &str,
+    events: Vec<Event>,
+    secret: Option<&str>,
+) -> Result<Webhook, CallError> {
+    let webhook = Webhook {
+        name: name.to_string(),
+        url: url.to_string(),
+        events: events.into_iter().map(i32::from).collect(),
+        secret: secret.map(str),
+    };
+
+    client
+        .add_webhook(AddWebhookRequest {
+            webhook: Some(webhook),
+            dry_run: false,
+        })
+        .await
+        .map_err(|err| CallError::status("Failed to add webhook", err))?
+        .into_inner()
+        .webhook
+        .ok_or_else(|| CallError::missing("No webhook returned after add"))
+}
+
+/// Remove a webhook
+pub async fn remove_webhook(
+    client: &mut GatehouseClient<impl Transport>,
+    name: &str,
+) -> Result<Webhook, CallError> {
+    client
+        .remove_webhook(RemoveWebhookRequest {
+            name: name.to_string(),
+            dry_run: false,
+        })
+        .await
+        .map_err(|err| CallError::status("Failed to remove webhook", err))?
+        .into_inner()
+        .webhook
+        .ok_or_else(|| CallError::missing("No webhook returned after removal"))
+}
+
+/// Search for webhooks
+pub async fn get_webhooks(
+    client: &mut GatehouseClient<impl Transport>,
+    name: Option<&str>,
+) -> Result<Vec<Webhook>, CallError> {
+    Ok(client
+        .get_webhooks(GetWebhooksRequest {
+            name: name.map(str),
+        })
+        .await
+        .map_err(|err| CallError::status("Failed to get webhooks", err))?
+        .into_inner()
+        .webhooks)
+}
+
+/// Get the status of recent webhook deliveries, optionally for a single webhook
+pub async fn get_webhook_deliveries(
+    client: &mut GatehouseClient<impl Transport>,
+    webhook: Option<&str>,
+) -> Result<Vec<Delivery>, CallError> {
+    Ok(client
+        .get_webhook_deliveries(GetDeliveriesRequest {
+            webhook: webhook.map(str),
+        })
+        .await
+        .map_err(|err| CallError::status("Failed to get webhook deliveries", err))?
+        .into_inner()
+        .deliveries)
+}
+
+/// Replay the recorded check requests against the current policies
+pub async fn coverage_report(
+    client: &mut GatehouseClient<impl Transport>,
+) -> Result<CoverageReportResponse, CallError> {
+    Ok(client
+        .coverage_report(CoverageReportRequest {})
+        .await
+        .map_err(|err| CallError::status("Failed to get coverage report", err))?
+        .into_inner())
+}
+
+/// See which requests would change decision if the policies were replaced by the candidates
+///
+/// If no sample requests are given, the server replays its recorded check requests.
+pub async fn what_if(
+    client: &mut GatehouseClient<impl Transport>,
+    policies: Vec<PolicyRule>,
+    requests: Vec<CheckRequest>,
+) -> Result<WhatIfResponse, CallError> {
+    Ok(client
+        .what_if(WhatIfRequest { policies, requests })
+        .await
+        .map_err(|err| CallError::status("Failed to compare policies", err))?
+        .into_inner())
+}
+
+/// Run policy test cases against the server's policies, or the given ones if there are any
+pub async fn test_policies(
+    client: &mut GatehouseClient<impl Transport>,
+    cases: Vec<PolicyTestCase>,
+    policies: Vec<PolicyRule>,
+) -> Result<TestPoliciesResponse, CallError> {
+    Ok(client
+        .test_policies(TestPoliciesRequest { cases, policies })
+        .await
+        .map_err(|err| CallError::status("Failed to test policies", err))?
+        .into_inner())
+}
+
+/// Get the version of the server, how it was built, and what it holds
+pub async fn get_server_info(
+    client: &mut GatehouseClient<impl Transport>,
+) -> Result<GetServerInfoResponse, CallError> {
+    Ok(client
+        .get_server_info(GetServerInfoRequest {})
+        .await
+        .map_err(|err| CallError::status("Failed to get server info", err))?
+        .into_inner())
+}
+
+/// Have the server load everything again from its storage backend, returning how many entities
+/// that changed
+pub async fn reload_state(client: &mut GatehouseClient<impl Transport>) -> Result<u64, CallError> {
+    Ok(client
+        .reload_state(ReloadStateRequest {})
+        .await
+        .map_err(|err| CallError::status("Failed to reload state", err))?
+        .into_inner()
+        .changed)
+}
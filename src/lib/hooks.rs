@@ -0,0 +1,65 @@
+#![warn(missing_docs)]
+
+//! Hooks that let embedders see and change every call to the service
+//!
+//! Request hooks run before a call is handled, in the order they were added. They can read and
+//! change the request metadata, for instance to authenticate the caller and set
+//! `x-gatehouse-caller`, and can reject the call by returning an error. Clients can't set
+//! `x-gatehouse-caller` themselves, as it is dropped before the hooks run, and the principal of
+//! an API key takes its place when keys are required. Response hooks run after
+//! a call is handled, with its response metadata or error and how long it took, which is enough
+//! for metrics or for adding response headers; calls rejected by a request hook don't reach them.
+//! Hooks are given the name of the method called, e.g. `add_target` or `check`.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tonic::metadata::MetadataMap;
+use tonic::{Response, Status};
+
+/// A hook run before every call; returning an error rejects the call with it
+pub type RequestHook = Arc<dyn Fn(&str, &mut MetadataMap) -> Result<(), Box<Status>> + Send + Sync>;
+
+/// A hook run after every call with its response metadata, or its error, and how long it took
+pub type ResponseHook =
+    Arc<dyn Fn(&str, Result<&mut MetadataMap, &Status>, Duration) + Send + Sync>;
+
+/// The hooks added to a service
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub(crate) request: Vec<RequestHook>,
+    pub(crate) response: Vec<ResponseHook>,
+}
+
+impl Hooks {
+    /// Run the response hooks on the outcome of a call
+    pub(crate) fn on_response<T>(
+        &self,
+        method: &str,
+        started: Instant,
+        result: &mut Result<Response<T>, Status>,
+    ) {
+        if self.response.is_empty() {
+            return;
+        }
+
+        let elapsed = started.elapsed();
+        for hook in &self.response {
+            let outcome = match result {
+                Ok(response) => Ok(response.metadata_mut()),
+                Err(status) => Err(&*status),
+            };
+            hook(method, outcome, elapsed);
+        }
+    }
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("request", &self.request.len())
+            .field("response", &self.response.len())
+            .finish()
+    }
+}
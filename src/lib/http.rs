@@ -0,0 +1,33 @@
+#![warn(missing_docs)]
+
+//! The HTTP client Gatehouse calls other services with
+//!
+//! OIDC providers, secret managers, and webhooks are all called with the one client built here. It
+//! speaks HTTPS, trusting the system's root certificates, as well as plain HTTP.
+
+use std::sync::OnceLock;
+
+use hyper::client::HttpConnector;
+use hyper::Client;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+
+/// A client for `http://` and `https://` urls
+pub(crate) type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
+/// The shared client, built the first time it is asked for
+///
+/// Its clones share its pool of connections, and the root certificates are only loaded once.
+pub(crate) fn client() -> HttpsClient {
+    static CLIENT: OnceLock<HttpsClient> = OnceLock::new();
+    CLIENT
+        .get_or_init(|| {
+            let https = HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_or_http()
+                .enable_http1()
+                .build();
+
+            Client::builder().build(https)
+        })
+        .clone()
+}
@@ -0,0 +1,50 @@
+#![warn(missing_docs)]
+
+//! One shared copy of the strings entities repeat
+//!
+//! With many actors and targets, the same few strings come up over and over: their types, the
+//! names of their attributes, and values like teams and role names. Interned strings are stored
+//! once and shared by everything that has them, so 100k actors of the same type hold one copy of
+//! it. Strings come from callers, so only so many are interned; when the set fills up, the strings
+//! nothing else holds any more are dropped, and if it is still full, new strings get their own
+//! copy like any other.
+
+use std::sync::{Arc, OnceLock, RwLock};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::attribute::FastSet;
+
+/// the most strings interned
+const MAX_INTERNED: usize = 1_000_000;
+
+/// The interned strings
+fn interned() -> &'static RwLock<FastSet<Arc<str>>> {
+    static INTERNED: OnceLock<RwLock<FastSet<Arc<str>>>> = OnceLock::new();
+    INTERNED.get_or_init(Default::default)
+}
+
+/// The shared copy of a string
+pub fn intern(text: &str) -> Arc<str> {
+    if let Some(text) = interned().read().unwrap().get(text) {
+        return Arc::clone(text);
+    }
+
+    let mut interned = interned().write().unwrap();
+    if let Some(text) = interned.get(text) {
+        return Arc::clone(text);
+    }
+    if interned.len() >= MAX_INTERNED {
+        interned.retain(|text| Arc::strong_count(text) > 1);
+    }
+    let text: Arc<str> = Arc::from(text);
+    if interned.len() < MAX_INTERNED {
+        interned.insert(Arc::clone(&text));
+    }
+    text
+}
+
+/// Deserialize a string as its shared copy, for `#[serde(deserialize_with)]`
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<str>, D::Error> {
+    String::deserialize(deserializer).map(|text| intern(&text))
+}
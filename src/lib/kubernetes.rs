@@ -0,0 +1,351 @@
+#![warn(missing_docs)]
+
+//! An HTTP/JSON endpoint for Kubernetes webhook authorization
+//!
+//! The API server POSTs a `SubjectAccessReview` to `/authorize`, which is mapped onto a Gatehouse
+//! check. The user becomes an actor of type `user`, with its groups, uid, and extra info as
+//! attributes. For a resource request, the target type is the resource, followed by `.` and its
+//! API group unless it is in the core group, and then by `/` and the subresource, if any, e.g.
+//! `pods`, `deployments.apps`, or `pods/log`. The target name is the name of the object, which is
+//! empty for requests like `list` and `create`, and the namespace is an environment attribute.
+//! A non-resource request has a target of type `nonresource` named after its path. The verb is
+//! the action in both cases.
+//!
+//! A check that a policy denies is answered as denied. One that no policy applies to is answered
+//! as not allowed but not denied, which leaves the decision to the authorizers after this one.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::header::HeaderValue;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tonic::metadata::MetadataMap;
+
+use crate::proto::actors::Actor;
+use crate::proto::base::gatehouse_server::Gatehouse;
+use crate::proto::base::CheckRequest;
+use crate::proto::common::AttributeValues;
+use crate::proto::policies::Decide;
+use crate::svc::GatehouseSvc;
+
+/// the path the API server is configured to send reviews to
+const AUTHORIZE_PATH: &str = "/authorize";
+
+/// A SubjectAccessReview, as sent by the API server
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SubjectAccessReview {
+    api_version: String,
+    spec: ReviewSpec,
+}
+
+/// What a review asks about
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReviewSpec {
+    resource_attributes: Option<ResourceAttributes>,
+    non_resource_attributes: Option<NonResourceAttributes>,
+    #[serde(default)]
+    user: String,
+    /// `v1beta1` calls this `group`
+    #[serde(default, alias = "group")]
+    groups: Vec<String>,
+    #[serde(default)]
+    uid: String,
+    #[serde(default)]
+    extra: HashMap<String, Vec<String>>,
+}
+
+/// A request for an API resource
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ResourceAttributes {
+    namespace: String,
+    verb: String,
+    group: String,
+    resource: String,
+    subresource: String,
+    name: String,
+}
+
+/// A request for a path that isn't an API resource, e.g. `/healthz`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct NonResourceAttributes {
+    path: String,
+    verb: String,
+}
+
+impl TryFrom<ReviewSpec> for CheckRequest {
+    type Error = String;
+
+    fn try_from(spec: ReviewSpec) -> Result<Self, Self::Error> {
+        if spec.user.is_empty() {
+            return Err(String::from("Review has no user"));
+        }
+
+        let mut attributes = HashMap::new();
+        if !spec.groups.is_empty() {
+            attributes.insert(String::from("groups"), values(spec.groups));
+        }
+        if !spec.uid.is_empty() {
+            attributes.insert(String::from("uid"), values(vec![spec.uid]));
+        }
+        for (key, vals) in spec.extra {
+            attributes.insert(key, values(vals));
+        }
+
+        let mut env_attributes = HashMap::new();
+        let (target_type, target_name, verb) =
+            match (spec.resource_attributes, spec.non_resource_attributes) {
+                (Some(res), _) => {
+                    let mut typestr = res.resource;
+                    if !res.group.is_empty() {
+                        typestr = format!("{typestr}.{}", res.group);
+                    }
+                    if !res.subresource.is_empty() {
+                        typestr = format!("{typestr}/{}", res.subresource);
+                    }
+                    if !res.namespace.is_empty() {
+                        env_attributes
+                            .insert(String::from("namespace"), values(vec![res.namespace]));
+                    }
+                    (typestr, res.name, res.verb)
+                }
+                (None, Some(non_res)) => (String::from("nonresource"), non_res.path, non_res.verb),
+                (None, None) => {
+                    return Err(String::from(
+                        "Review has neither resource nor non-resource attributes",
+                    ))
+                }
+            };
+
+        Ok(CheckRequest {
+            actor: Some(Actor {
+                name: spec.user,
+                typestr: String::from("user"),
+                attributes,
+                ..Default::default()
+            }),
+            env_attributes,
+            target_name,
+            target_type,
+            target_action: vec![verb],
+            ..Default::default()
+        })
+    }
+}
+
+/// Values as attribute values
+fn values(values: Vec<String>) -> AttributeValues {
+    AttributeValues { values }
+}
+
+/// Serve the authorization webhook until the server fails
+pub async fn serve(addr: SocketAddr, svc: Arc<GatehouseSvc>) -> Result<(), hyper::Error> {
+    let make_svc = make_service_fn(move |_| {
+        let svc = svc.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let svc = svc.clone();
+                async move { Ok::<_, Infallible>(handle(&svc, req).await) }
+            }))
+        }
+    });
+
+    Server::bind(&addr).serve(make_svc).await
+}
+
+/// Handle a single HTTP request
+async fn handle(svc: &GatehouseSvc, req: Request<Body>) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::POST, AUTHORIZE_PATH) => authorize(svc, req).await,
+        (_, AUTHORIZE_PATH) => error(StatusCode::METHOD_NOT_ALLOWED, "Use POST"),
+        _ => error(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+/// Run a review as a check
+async fn authorize(svc: &GatehouseSvc, req: Request<Body>) -> Response<Body> {
+    let (parts, body) = req.into_parts();
+
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => return error(StatusCode::BAD_REQUEST, &err.to_string()),
+    };
+    let review: SubjectAccessReview = match serde_json::from_slice(&body) {
+        Ok(review) => review,
+        Err(err) => return error(StatusCode::BAD_REQUEST, &err.to_string()),
+    };
+    let api_version = review.api_version;
+    let check = match CheckRequest::try_from(review.spec) {
+        Ok(check) => check,
+        Err(err) => return error(StatusCode::BAD_REQUEST, &err),
+    };
+
+    let mut check = tonic::Request::new(check);
+    *check.metadata_mut() = MetadataMap::from_headers(parts.headers);
+
+    // the API server treats an error as no opinion, so failures are reported in the review
+    let status = match svc.check(check).await {
+        Ok(resp) => match resp.get_ref().decision() {
+            Decide::Allow => json!({ "allowed": true }),
+            Decide::Deny => {
+                json!({ "allowed": false, "denied": true, "reason": "denied by Gatehouse" })
+            }
+            Decide::Indeterminate => json!({
+                "allowed": false,
+                "evaluationError": "a Gatehouse policy could not be evaluated",
+            }),
+            _ => json!({ "allowed": false, "reason": "no Gatehouse policy applies" }),
+        },
+        Err(status) => json!({ "allowed": false, "evaluationError": status.message() }),
+    };
+
+    json_response(
+        StatusCode::OK,
+        json!({
+            "apiVersion": api_version,
+            "kind": "SubjectAccessReview",
+            "status": status,
+        }),
+    )
+}
+
+/// Build an error response
+fn error(code: StatusCode, msg: &str) -> Response<Body> {
+    json_response(code, json!({ "error": msg }))
+}
+
+/// Build a JSON response
+fn json_response(code: StatusCode, body: Value) -> Response<Body> {
+    let mut resp = Response::new(Body::from(body.to_string()));
+    *resp.status_mut() = code;
+    resp.headers_mut()
+        .insert("content-type", HeaderValue::from_static("application/json"));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::test;
+
+    use crate::proto::policies::{
+        ActorCheck, AddPolicyRequest, Kv, KvCheck, PolicyRule, Set, StringCheck, TargetCheck,
+    };
+    use crate::StorageType;
+
+    use super::*;
+
+    async fn post(svc: &GatehouseSvc, body: Value) -> (StatusCode, Value) {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(AUTHORIZE_PATH)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let resp = handle(svc, req).await;
+        let code = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (code, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    async fn test_review() {
+        let svc = GatehouseSvc::new(&StorageType::Nil).await;
+
+        // admins can read deployment scales
+        let rule = PolicyRule {
+            name: String::from("admins-scale"),
+            actor_check: Some(ActorCheck {
+                attributes: vec![KvCheck {
+                    key: String::from("groups"),
+                    op: Kv::Has.into(),
+                    vals: vec![String::from("admins")],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            target_check: Some(TargetCheck {
+                typestr: Some(StringCheck {
+                    val_cmp: Set::Has.into(),
+                    vals: vec![String::from("deployments.apps/scale")],
+                }),
+                ..Default::default()
+            }),
+            env_attributes: vec![KvCheck {
+                key: String::from("namespace"),
+                op: Kv::Has.into(),
+                vals: vec![String::from("prod")],
+                ..Default::default()
+            }],
+            decision: Decide::Allow.into(),
+            ..Default::default()
+        };
+        svc.add_policy(tonic::Request::new(AddPolicyRequest {
+            rule: Some(rule),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+
+        let review = |groups: Vec<&str>, namespace: &str| {
+            json!({
+                "apiVersion": "authorization.k8s.io/v1",
+                "kind": "SubjectAccessReview",
+                "spec": {
+                    "resourceAttributes": {
+                        "namespace": namespace,
+                        "verb": "get",
+                        "group": "apps",
+                        "resource": "deployments",
+                        "subresource": "scale",
+                        "name": "web",
+                    },
+                    "user": "jane",
+                    "groups": groups,
+                },
+            })
+        };
+
+        let (code, body) = post(&svc, review(vec!["admins"], "prod")).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["apiVersion"], "authorization.k8s.io/v1");
+        assert_eq!(body["status"]["allowed"], true);
+
+        let (_, body) = post(&svc, review(vec!["devs"], "prod")).await;
+        assert_eq!(body["status"]["allowed"], false);
+        assert!(body["status"].get("denied").is_none());
+
+        let deny = PolicyRule {
+            name: String::from("no-dev-scaling"),
+            env_attributes: vec![KvCheck {
+                key: String::from("namespace"),
+                op: Kv::Has.into(),
+                vals: vec![String::from("dev")],
+                ..Default::default()
+            }],
+            decision: Decide::Deny.into(),
+            ..Default::default()
+        };
+        svc.add_policy(tonic::Request::new(AddPolicyRequest {
+            rule: Some(deny),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        let (_, body) = post(&svc, review(vec!["admins"], "dev")).await;
+        assert_eq!(body["status"]["allowed"], false);
+        assert_eq!(body["status"]["denied"], true);
+
+        // a review has to say what it is about
+        let empty = json!({ "apiVersion": "authorization.k8s.io/v1", "spec": { "user": "jane" } });
+        let (code, _) = post(&svc, empty).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,131 @@
+#![warn(missing_docs)]
+
+//! Timing of how long policies take to evaluate in checks
+//!
+//! Every evaluation of an enforced rule in a check is timed, and each rule keeps a moving
+//! average of its times, so the rules that are expensive to evaluate can be found. An
+//! evaluation that takes longer than the budget, if one is set, is logged as it happens. Rules
+//! in policy sets are timed as part of the set, not on their own. Times are kept in memory
+//! only, so they start over with the server.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::proto::base::PolicyStats;
+
+/// how much the latest evaluation counts toward the moving average
+const SMOOTHING: f64 = 0.1;
+
+/// how many policies are listed when the caller doesn't say
+pub(crate) const DEFAULT_TOP: usize = 10;
+
+/// How long a rule has taken to evaluate
+#[derive(Debug, Clone, Copy, Default)]
+struct Timing {
+    evaluations: u64,
+    /// moving average, in microseconds
+    average: f64,
+    /// the longest evaluation, in microseconds
+    max: u64,
+    over_budget: u64,
+}
+
+/// The times of every rule evaluated in checks, by rule name
+#[derive(Debug, Default)]
+pub(crate) struct PolicyTimings {
+    budget: Option<Duration>,
+    timings: Mutex<HashMap<String, Timing>>,
+}
+
+impl PolicyTimings {
+    /// Time evaluations, warning about any longer than the budget in microseconds; 0 sets none
+    pub fn new(budget_micros: u64) -> Self {
+        Self {
+            budget: (budget_micros > 0).then(|| Duration::from_micros(budget_micros)),
+            timings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The budget for a single evaluation, in microseconds; 0 if there is none
+    pub fn budget_micros(&self) -> u64 {
+        self.budget.map_or(0, |budget| budget.as_micros() as u64)
+    }
+
+    /// Record how long an evaluation of a rule took
+    pub fn record(&self, name: &str, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        let over_budget = self.budget.is_some_and(|budget| elapsed > budget);
+        if over_budget {
+            eprintln!(
+                "Slow policy[{name}]: took {micros}us, over the budget of {}us",
+                self.budget_micros()
+            );
+        }
+
+        let mut timings = self.timings.lock().unwrap();
+        let timing = match timings.get_mut(name) {
+            Some(timing) => timing,
+            None => timings.entry(name.to_string()).or_default(),
+        };
+        timing.average = match timing.evaluations {
+            0 => micros as f64,
+            _ => timing.average + SMOOTHING * (micros as f64 - timing.average),
+        };
+        timing.evaluations += 1;
+        timing.max = timing.max.max(micros);
+        if over_budget {
+            timing.over_budget += 1;
+        }
+    }
+
+    /// The slowest rules on average, slowest first, forgetting the ones that no longer exist
+    pub fn slowest(&self, top: usize, exists: impl Fn(&str) -> bool) -> Vec<PolicyStats> {
+        let mut timings = self.timings.lock().unwrap();
+        timings.retain(|name, _| exists(name));
+
+        let mut stats: Vec<PolicyStats> = timings
+            .iter()
+            .map(|(name, timing)| PolicyStats {
+                name: name.clone(),
+                evaluations: timing.evaluations,
+                average_micros: timing.average,
+                max_micros: timing.max,
+                over_budget: timing.over_budget,
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            b.average_micros
+                .total_cmp(&a.average_micros)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        stats.truncate(top);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slowest() {
+        let timings = PolicyTimings::new(100);
+        timings.record("quick", Duration::from_micros(10));
+        timings.record("slow", Duration::from_micros(50));
+        timings.record("slow", Duration::from_micros(150));
+        timings.record("removed", Duration::from_micros(1000));
+
+        let stats = timings.slowest(5, |name| name != "removed");
+        let names: Vec<&str> = stats.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["slow", "quick"]);
+
+        // the moving average leans on the first evaluation
+        assert_eq!(stats[0].evaluations, 2);
+        assert!((stats[0].average_micros - 60.0).abs() < 0.001);
+        assert_eq!(stats[0].max_micros, 150);
+        assert_eq!(stats[0].over_budget, 1);
+
+        assert_eq!(timings.slowest(1, |_| true).len(), 1);
+    }
+}
@@ -5,7 +5,7 @@
 use std::fmt::Display;
 
 /// Gatehouse protobuf definitions
-#[allow(clippy::derive_partial_eq_without_eq)]
+#[allow(clippy::derive_partial_eq_without_eq, clippy::large_enum_variant)]
 pub mod proto {
     /// Common protobufs between other packages
     pub mod common {
@@ -14,7 +14,33 @@ pub mod proto {
 
     /// Base protobufs for the server and client
     pub mod base {
+        use std::fmt::Display;
+
         tonic::include_proto!("gatehouse");
+
+        impl Display for CheckRequest {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let actor = self
+                    .actor
+                    .as_ref()
+                    .map(|a| format!("{}/{}", a.typestr, a.name))
+                    .unwrap_or_default();
+
+                write!(
+                    f,
+                    "check[{} -> {} on {}/{}]",
+                    actor,
+                    self.target_action.join(", "),
+                    self.target_type,
+                    self.target_name
+                )?;
+
+                if !self.correlation_id.is_empty() {
+                    write!(f, " ({})", self.correlation_id)?;
+                }
+                Ok(())
+            }
+        }
     }
 
     /// Actor related protobufs
@@ -39,19 +65,56 @@ pub mod proto {
 
     /// Group related protobufs
     pub mod groups {
+        use std::collections::BTreeMap;
         use std::fmt::Display;
 
         tonic::include_proto!("groups");
 
+        /// The alternate form, `{:#}`, also lists each member on a line of its own
         impl Display for Group {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                write!(
-                    f,
-                    "group[{}]: {} members  {} roles",
-                    self.name,
-                    self.members.len(),
-                    self.roles.len()
-                )
+                // counts come with groups from the server, but not with ones put together locally
+                let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+                match self.member_counts.is_empty() {
+                    true => self.members.iter().for_each(|member| {
+                        *counts.entry(member.typestr.as_str()).or_default() += 1;
+                    }),
+                    false => counts.extend(
+                        self.member_counts
+                            .iter()
+                            .map(|(typestr, count)| (typestr.as_str(), *count)),
+                    ),
+                }
+                let total: u64 = counts.values().sum();
+                let counts = counts
+                    .iter()
+                    .map(|(typestr, count)| format!("{typestr}: {count}"))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                let mut roles: Vec<&str> = self.roles.iter().map(String::as_str).collect();
+                roles.sort_unstable();
+
+                write!(f, "group[{}]", self.name)?;
+                if let Some(desc) = &self.desc {
+                    write!(f, " \"{desc}\"")?;
+                }
+                write!(f, ": {total} members")?;
+                if !counts.is_empty() {
+                    write!(f, " ({counts})")?;
+                }
+                write!(f, "  roles [{}]", roles.join(", "))?;
+                if let Some(managed_by) = &self.managed_by {
+                    write!(f, "  managed by {managed_by}")?;
+                }
+
+                if f.alternate() {
+                    let mut members: Vec<&GroupMember> = self.members.iter().collect();
+                    members.sort_by(|a, b| (&a.typestr, &a.name).cmp(&(&b.typestr, &b.name)));
+                    for member in members {
+                        write!(f, "\n  {}/{}", member.typestr, member.name)?;
+                    }
+                }
+                Ok(())
             }
         }
     }
@@ -67,6 +130,17 @@ pub mod proto {
                 write!(f, "policy[{}]", self.name)
             }
         }
+
+        impl Display for PolicySet {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(
+                    f,
+                    "policyset[{}]: {} policies",
+                    self.name,
+                    self.policies.len()
+                )
+            }
+        }
     }
 
     /// Role related protobufs
@@ -96,10 +170,15 @@ pub mod proto {
                     .map(|kv| format!("{}: {}", kv.0, kv.1.values.join(", ")))
                     .collect::<Vec<String>>()
                     .join("; ");
+                let groupvals = self
+                    .action_groups
+                    .iter()
+                    .map(|kv| format!(" // {}:: {}", kv.0, kv.1.actions.join(", ")))
+                    .collect::<String>();
 
                 write!(
                     f,
-                    "tgt[{}/{}]: actions:: {} // attribs:: {}",
+                    "tgt[{}/{}]: actions:: {} // attribs:: {}{}",
                     self.typestr,
                     self.name,
                     self.actions
@@ -107,20 +186,37 @@ pub mod proto {
                         .map(|s| s.to_string())
                         .collect::<Vec<String>>()
                         .join(", "),
-                    attribvals
+                    attribvals,
+                    groupvals
                 )
             }
         }
     }
+
+    /// Protobufs related to webhooks
+    pub mod webhooks {
+        use std::fmt::Display;
+
+        tonic::include_proto!("webhooks");
+
+        impl Display for Webhook {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "webhook[{}]: {}", self.name, self.url)
+            }
+        }
+    }
 }
 
 /// Specify the type of persistent backend to use
+#[derive(Clone)]
 pub enum StorageType {
     /// indicates no backend should be used, useful for unit tests
     Nil,
     /// indicates a file backend should be used at the given path
     FileSystem(String),
-    /// indicates an Etcd backend should be used with the given url
+    /// indicates an append-only log backend should be used at the given path
+    Log(String),
+    /// indicates an Etcd backend should be used with the given comma separated urls
     Etcd(String),
 }
 impl Display for StorageType {
@@ -128,6 +224,7 @@ impl Display for StorageType {
         match self {
             Self::Nil => write!(f, "Nil"),
             Self::FileSystem(path) => write!(f, "File system({})", path),
+            Self::Log(path) => write!(f, "Log({})", path),
             Self::Etcd(url) => write!(f, "Etcd({})", url),
         }
     }
@@ -148,6 +245,7 @@ impl StorageType {
             match typestr.to_ascii_lowercase().as_str() {
                 "etcd" => return Self::Etcd(val.to_string()),
                 "file" => return Self::FileSystem(val.to_string()),
+                "log" => return Self::Log(val.to_string()),
                 "nil" => return Self::Nil,
                 _ => {
                     eprintln!("Unknown storage type: {}", typestr.to_ascii_lowercase());
@@ -158,15 +256,92 @@ impl StorageType {
 
         Self::FileSystem("/tmp/gatehouse".to_string())
     }
+
+    /// The kind of backend, as it is named in `GATESTORAGE`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Nil => "nil",
+            Self::FileSystem(_) => "file",
+            Self::Log(_) => "log",
+            Self::Etcd(_) => "etcd",
+        }
+    }
+
+    /// The storage kept apart for a namespace
+    pub(crate) fn for_namespace(&self, namespace: &str) -> Self {
+        match self {
+            Self::Nil => Self::Nil,
+            Self::FileSystem(path) => Self::FileSystem(format!("{path}/namespaces/{namespace}")),
+            Self::Log(path) => Self::Log(format!("{path}/namespaces/{namespace}")),
+            // etcd keeps namespaces apart by prefix instead (see `Config::for_namespace`)
+            Self::Etcd(urls) => Self::Etcd(urls.clone()),
+        }
+    }
 }
 
 pub(crate) mod actor;
+pub mod admin;
+pub mod apikey;
+pub mod attribute;
+pub mod authzen;
+pub(crate) mod breakglass;
+pub mod bulk;
+pub mod bundle;
+pub mod cache;
+pub(crate) mod canary;
+pub mod chaos;
+pub mod compat;
+pub mod config;
+pub(crate) mod delegation;
+pub(crate) mod drift;
 pub(crate) mod ds;
+pub mod dsl;
+pub(crate) mod expansion;
+pub mod fallback;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+pub mod grant;
 pub(crate) mod group;
 pub mod helpers;
+pub mod hooks;
+pub(crate) mod http;
+pub mod intern;
+pub mod kubernetes;
+pub(crate) mod latency;
+pub mod limits;
+pub mod merge;
+pub mod migrate;
+pub mod model;
 pub(crate) mod msgs;
+pub mod oidc;
 pub(crate) mod policy;
+pub(crate) mod policyset;
+pub mod policytest;
+pub mod profile;
+pub(crate) mod proposal;
+pub mod quota;
+pub mod region;
+pub mod render;
+pub mod replay;
+pub(crate) mod replica;
+pub mod risk;
 pub(crate) mod role;
+pub mod runtime;
+pub(crate) mod sampling;
+pub mod schema;
+pub mod secrets;
+pub(crate) mod shard;
+pub(crate) mod ssh;
 pub(crate) mod storage;
+pub mod streak;
 pub mod svc;
+pub mod sync;
 pub(crate) mod target;
+pub mod testing;
+pub(crate) mod timestamps;
+pub(crate) mod unused;
+pub(crate) mod usage;
+pub mod validation;
+pub(crate) mod velocity;
+pub(crate) mod wasm;
+pub(crate) mod webhook;
@@ -0,0 +1,628 @@
+#![warn(missing_docs)]
+
+//! Limits on the server's connections and the messages they carry
+//!
+//! tonic doesn't limit message sizes itself, so they are held to their limits as they are read
+//! and written by [`LimitedServer`], and calls with messages that are too large fail with
+//! `RESOURCE_EXHAUSTED` and a message naming the limit. It goes inside tonic-web, which turns
+//! gRPC-Web calls into gRPC ones, so those are held to the limits too.
+//! The rest are passed to tonic's server, except for the limit on connections: connections past
+//! it are closed as soon as they are accepted.
+
+use std::fmt::Display;
+use std::io::{IoSlice, Read};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use hyper::server::conn::AddrStream;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_stream::{Stream, StreamExt};
+use tonic::body::BoxBody;
+use tonic::codegen::http::{HeaderMap, HeaderValue, Request, Response};
+use tonic::codegen::{Body, BoxFuture, Bytes, Service, StdError};
+use tonic::server::NamedService;
+use tonic::transport::server::{Connected, TcpIncoming};
+use tonic::transport::Server;
+use tonic::{Code, Status};
+
+use crate::config::number_from_env;
+
+/// the largest message taken or sent by default
+pub const MAX_MESSAGE: usize = 64 * 1024 * 1024;
+
+/// Limits on the server's connections and messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerLimits {
+    /// the largest message, in bytes, the server takes
+    pub max_decode_size: usize,
+    /// the largest message, in bytes, the server sends
+    pub max_encode_size: usize,
+    /// the most connections open at once, if limited
+    pub max_connections: Option<usize>,
+    /// the most calls a connection can have running at once, if limited
+    pub connection_concurrency: Option<usize>,
+    /// the most HTTP/2 streams a connection can have open at once, if limited
+    pub max_concurrent_streams: Option<u32>,
+    /// how often HTTP/2 pings are sent to keep connections alive, if they are
+    pub keepalive_interval: Option<Duration>,
+    /// how long a ping has to be answered before the connection is closed
+    pub keepalive_timeout: Option<Duration>,
+    /// how often TCP keepalive probes are sent, if they are
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        Self {
+            max_decode_size: MAX_MESSAGE,
+            max_encode_size: MAX_MESSAGE,
+            max_connections: None,
+            connection_concurrency: None,
+            max_concurrent_streams: None,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_keepalive: None,
+        }
+    }
+}
+
+impl ServerLimits {
+    /// Build the limits from the environment
+    ///
+    /// * `GATEMAXDECODESIZE`: the largest message in bytes the server takes (default 64 MiB)
+    /// * `GATEMAXENCODESIZE`: the largest message in bytes the server sends (default 64 MiB)
+    /// * `GATEMAXCONNECTIONS`: the most connections open at once (default unlimited)
+    /// * `GATECONNECTIONCONCURRENCY`: the most calls running at once on a connection (default
+    ///   unlimited)
+    /// * `GATEMAXSTREAMS`: the most HTTP/2 streams open at once on a connection (default
+    ///   unlimited)
+    /// * `GATEKEEPALIVESECS`: seconds between HTTP/2 keepalive pings (default 0, none)
+    /// * `GATEKEEPALIVETIMEOUTSECS`: seconds a keepalive ping has to be answered (default 20)
+    /// * `GATETCPKEEPALIVESECS`: seconds between TCP keepalive probes (default 0, none)
+    pub fn from_env() -> Result<Self, String> {
+        let secs = |var| {
+            Ok::<_, String>(
+                number_from_env(var)?
+                    .filter(|secs| *secs > 0)
+                    .map(|secs| Duration::from_secs(secs as u64)),
+            )
+        };
+        let limit = |var| Ok::<_, String>(number_from_env(var)?.filter(|limit| *limit > 0));
+
+        Ok(Self {
+            max_decode_size: limit("GATEMAXDECODESIZE")?.unwrap_or(MAX_MESSAGE),
+            max_encode_size: limit("GATEMAXENCODESIZE")?.unwrap_or(MAX_MESSAGE),
+            max_connections: limit("GATEMAXCONNECTIONS")?,
+            connection_concurrency: limit("GATECONNECTIONCONCURRENCY")?,
+            max_concurrent_streams: limit("GATEMAXSTREAMS")?
+                .map(|streams| u32::try_from(streams).unwrap_or(u32::MAX)),
+            keepalive_interval: secs("GATEKEEPALIVESECS")?,
+            keepalive_timeout: secs("GATEKEEPALIVETIMEOUTSECS")?,
+            tcp_keepalive: secs("GATETCPKEEPALIVESECS")?,
+        })
+    }
+
+    /// Set a server up with the limits tonic's server keeps
+    pub fn apply(&self, server: Server) -> Server {
+        let server = server
+            .max_concurrent_streams(self.max_concurrent_streams)
+            .http2_keepalive_interval(self.keepalive_interval)
+            .http2_keepalive_timeout(self.keepalive_timeout)
+            .tcp_keepalive(self.tcp_keepalive);
+        match self.connection_concurrency {
+            Some(limit) => server.concurrency_limit_per_connection(limit),
+            None => server,
+        }
+    }
+
+    /// Listen for connections on an address, closing those past the most allowed
+    pub fn incoming(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<impl Stream<Item = Result<Connection, std::io::Error>>, String> {
+        let incoming = TcpIncoming::new(addr, true, self.tcp_keepalive)
+            .map_err(|err| format!("Can't listen on {addr}: {err}"))?;
+        let permits = self
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+
+        Ok(incoming.filter_map(move |conn| {
+            let conn = match conn {
+                Ok(conn) => conn,
+                Err(err) => return Some(Err(err)),
+            };
+            let permit = match permits {
+                Some(ref permits) => match permits.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        eprintln!(
+                            "Closed connection from {}: too many connections",
+                            conn.remote_addr()
+                        );
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            Some(Ok(Connection {
+                inner: conn,
+                _permit: permit,
+            }))
+        }))
+    }
+}
+
+impl Display for ServerLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "messages up to {} bytes in and {} out",
+            self.max_decode_size, self.max_encode_size
+        )?;
+        if let Some(max) = self.max_connections {
+            write!(f, ", {max} connections")?;
+        }
+        if let Some(limit) = self.connection_concurrency {
+            write!(f, ", {limit} calls per connection")?;
+        }
+        if let Some(streams) = self.max_concurrent_streams {
+            write!(f, ", {streams} streams per connection")?;
+        }
+        if let Some(interval) = self.keepalive_interval {
+            write!(f, ", keepalive every {}s", interval.as_secs())?;
+        }
+        if let Some(interval) = self.tcp_keepalive {
+            write!(f, ", tcp keepalive every {}s", interval.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// An accepted connection, counted against the most allowed until it closes
+pub struct Connection {
+    inner: AddrStream,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Connected for Connection {
+    type ConnectInfo = <AddrStream as Connected>::ConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.inner.connect_info()
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A gRPC service whose messages are held to size limits
+///
+/// gzip-compressed messages the server takes are decompressed here and passed on uncompressed, so
+/// they are held to the limit once decompressed and not decompressed again by tonic. Messages it
+/// sends are held to the limit as they are sent. Wrap it in tonic-web for gRPC-Web calls to be
+/// limited too.
+#[derive(Debug, Clone)]
+pub struct LimitedServer<S> {
+    inner: S,
+    /// the largest message taken
+    max_decode: usize,
+    /// the largest message sent
+    max_encode: usize,
+}
+
+impl<S> LimitedServer<S> {
+    /// Hold a service's messages to the limits
+    pub fn new(inner: S, limits: &ServerLimits) -> Self {
+        Self {
+            inner,
+            max_decode: limits.max_decode_size,
+            max_encode: limits.max_encode_size,
+        }
+    }
+}
+
+impl<S: NamedService> NamedService for LimitedServer<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<Request<hyper::Body>> for LimitedServer<S>
+where
+    S: Service<Request<hyper::Body>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<hyper::Body>) -> Self::Future {
+        if !is_grpc(req.headers()) {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let (max_decode, max_encode) = (self.max_decode, self.max_encode);
+        let req = req.map(|body| hyper::Body::wrap_stream(Frames::new(body, max_decode, true)));
+
+        let call = self.inner.call(req);
+        Box::pin(async move {
+            let res = call.await?;
+            Ok(res.map(|body| {
+                Frames {
+                    trailers: true,
+                    ..Frames::new(body, max_encode, false)
+                }
+                .boxed_unsync()
+            }))
+        })
+    }
+}
+
+/// Whether a call is gRPC, rather than gRPC-Web
+fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get("content-type")
+        .and_then(|val| val.to_str().ok())
+        .is_some_and(|val| val == "application/grpc" || val.starts_with("application/grpc+"))
+}
+
+/// A gRPC body whose messages are checked against a limit as they pass through
+struct Frames<B> {
+    inner: B,
+    /// the largest message passed on
+    limit: usize,
+    /// whether compressed messages are decompressed, and held to the limit once they are
+    gunzip: bool,
+    /// whether a failure ends the body with its status in the trailers, as a server's does
+    trailers: bool,
+    /// what has been read of the messages not yet passed on
+    buffer: Vec<u8>,
+    /// where in the buffer the next message starts
+    start: usize,
+    /// whether the inner body has no more data
+    done: bool,
+    /// the status the body failed with, sent in place of the inner body's trailers
+    failed: Option<HeaderMap>,
+}
+
+impl<B> Frames<B> {
+    fn new(inner: B, limit: usize, gunzip: bool) -> Self {
+        Self {
+            inner,
+            limit,
+            gunzip,
+            trailers: false,
+            buffer: Vec::new(),
+            start: 0,
+            done: false,
+            failed: None,
+        }
+    }
+
+    /// The next whole message in the buffer, as a frame, if it is within the limit
+    fn next_frame(&mut self) -> Result<Option<Bytes>, (Code, String)> {
+        let pending = &self.buffer[self.start..];
+        if pending.len() < 5 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([pending[1], pending[2], pending[3], pending[4]]) as usize;
+        if len > self.limit {
+            return Err((
+                Code::ResourceExhausted,
+                format!(
+                    "Message of {len} bytes is larger than the limit of {} bytes",
+                    self.limit
+                ),
+            ));
+        }
+        if pending.len() < 5 + len {
+            return Ok(None);
+        }
+        if self.gunzip && pending[0] == 1 {
+            let message = gunzip(&pending[5..5 + len], self.limit)?;
+            self.start += 5 + len;
+            let mut frame = Vec::with_capacity(5 + message.len());
+            frame.push(0);
+            frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            frame.extend_from_slice(&message);
+            return Ok(Some(Bytes::from(frame)));
+        }
+
+        // a whole buffer of one message, as most calls are, is passed on as it is
+        if self.start == 0 && pending.len() == 5 + len {
+            return Ok(Some(Bytes::from(std::mem::take(&mut self.buffer))));
+        }
+        let frame = Bytes::copy_from_slice(&pending[..5 + len]);
+        self.start += 5 + len;
+        Ok(Some(frame))
+    }
+}
+
+impl<B> Body for Frames<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: Into<StdError>,
+{
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            let (code, message) = match this.next_frame() {
+                Ok(Some(frame)) => return Poll::Ready(Some(Ok(frame))),
+                Err(failure) => failure,
+                Ok(None) if !this.done => {
+                    this.buffer.drain(..this.start);
+                    this.start = 0;
+                    match ready!(Pin::new(&mut this.inner).poll_data(cx)) {
+                        Some(Ok(data)) => this.buffer.extend_from_slice(&data),
+                        Some(Err(err)) => {
+                            return Poll::Ready(Some(Err(Status::from_error(err.into()))))
+                        }
+                        None => this.done = true,
+                    }
+                    continue;
+                }
+                Ok(None) if this.start == this.buffer.len() => return Poll::Ready(None),
+                Ok(None) => (
+                    Code::Internal,
+                    String::from("Body ended partway through a message"),
+                ),
+            };
+
+            // nothing more is passed on after a failure
+            this.done = true;
+            this.buffer.clear();
+            this.start = 0;
+            if !this.trailers {
+                return Poll::Ready(Some(Err(Status::new(code, message))));
+            }
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from(code as i32));
+            if let Ok(message) = HeaderValue::from_str(&message) {
+                trailers.insert("grpc-message", message);
+            }
+            this.failed = Some(trailers);
+            return Poll::Ready(None);
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let this = self.get_mut();
+        if let Some(failed) = this.failed.take() {
+            return Poll::Ready(Ok(Some(failed)));
+        }
+        Pin::new(&mut this.inner)
+            .poll_trailers(cx)
+            .map_err(|err| Status::from_error(err.into()))
+    }
+}
+
+impl<B> Stream for Frames<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: Into<StdError>,
+{
+    type Item = Result<Bytes, Status>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_data(cx)
+    }
+}
+
+/// Decompress a gzip-compressed message, if it comes to no more than a limit
+fn gunzip(message: &[u8], limit: usize) -> Result<Vec<u8>, (Code, String)> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(message)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|err| {
+            (
+                Code::Internal,
+                format!("Compressed message is not gzip: {err}"),
+            )
+        })?;
+    if decompressed.len() > limit {
+        return Err((
+            Code::ResourceExhausted,
+            format!("Message is larger than the limit of {limit} bytes once decompressed"),
+        ));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzEncoder;
+    use flate2::Compression;
+    use tokio::net::TcpStream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let limits = ServerLimits {
+            max_connections: Some(1),
+            ..Default::default()
+        };
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let listener = std::net::TcpListener::bind(addr).unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let mut incoming = Box::pin(limits.incoming(addr).unwrap());
+
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let first = incoming.next().await.unwrap().unwrap();
+
+        // a second connection is closed while the first is open, but not after
+        let _second = TcpStream::connect(addr).await.unwrap();
+        let waited = tokio::time::timeout(Duration::from_millis(200), incoming.next()).await;
+        assert!(waited.is_err());
+        drop(first);
+        let third = TcpStream::connect(addr).await.unwrap();
+        let accepted = incoming.next().await.unwrap().unwrap();
+        assert_eq!(
+            accepted.connect_info().remote_addr(),
+            third.local_addr().ok()
+        );
+    }
+
+    /// frames of messages, with their compressed flags
+    fn frames(messages: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut frames = Vec::new();
+        for (flag, message) in messages {
+            frames.push(*flag);
+            frames.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            frames.extend_from_slice(message);
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn test_frames() {
+        let large = vec![b'a'; 5000];
+        let mut compressed = Vec::new();
+        GzEncoder::new(large.as_slice(), Compression::default())
+            .read_to_end(&mut compressed)
+            .unwrap();
+        let body = frames(&[(0, b"small"), (1, &compressed), (0, b"")]);
+
+        // messages split across chunks are put back together, and compressed ones are passed on
+        // decompressed
+        let chunks: Vec<Result<Bytes, std::io::Error>> = body
+            .chunks(7)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let limited = Frames::new(
+            hyper::Body::wrap_stream(tokio_stream::iter(chunks)),
+            5000,
+            true,
+        );
+        assert_eq!(
+            hyper::body::to_bytes(limited).await.unwrap().to_vec(),
+            frames(&[(0, b"small"), (0, &large), (0, b"")])
+        );
+
+        // a body can't end partway through a message
+        let limited = Frames::new(hyper::Body::from(body[..20].to_vec()), MAX_MESSAGE, true);
+        assert!(hyper::body::to_bytes(limited).await.is_err());
+
+        // compressed messages are held to the limit once decompressed when they are taken
+        let limited = Frames::new(hyper::Body::from(body.clone()), 1000, true);
+        let err = hyper::body::to_bytes(limited).await.unwrap_err();
+        assert_eq!(err.code(), Code::ResourceExhausted);
+        let limited = Frames::new(hyper::Body::from(body.clone()), 1000, false);
+        assert_eq!(hyper::body::to_bytes(limited).await.unwrap().to_vec(), body);
+
+        // messages over the limit fail a server's call with its trailers
+        let body = hyper::Body::from(frames(&[(0, b"small"), (0, &large)]));
+        let mut limited = Frames {
+            trailers: true,
+            ..Frames::new(body, 1000, false)
+        };
+        assert_eq!(limited.data().await.unwrap().unwrap().len(), 10);
+        assert!(limited.data().await.is_none());
+        let trailers = limited.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "8");
+    }
+
+    /// a service that reads the whole message, failing if it can't
+    #[derive(Clone)]
+    struct Sink;
+
+    impl NamedService for Sink {
+        const NAME: &'static str = "sink";
+    }
+
+    impl Service<Request<hyper::Body>> for Sink {
+        type Response = Response<BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<hyper::Body>) -> Self::Future {
+            Box::pin(async move {
+                let status = match hyper::body::to_bytes(req.into_body()).await {
+                    Ok(_) => Status::ok(""),
+                    Err(_) => Status::resource_exhausted("too large"),
+                };
+                Ok(status.to_http())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_web() {
+        let limits = ServerLimits {
+            max_decode_size: 10,
+            ..Default::default()
+        };
+        let mut service = tonic_web::enable(LimitedServer::new(Sink, &limits));
+
+        // gRPC-Web calls are made gRPC ones before they are limited
+        for (message, status) in [(&b"small"[..], "0"), (&[b'a'; 100][..], "8")] {
+            let req = Request::builder()
+                .method("POST")
+                .uri("/sink/Call")
+                .header("content-type", "application/grpc-web")
+                .body(hyper::Body::from(frames(&[(0, message)])))
+                .unwrap();
+            let res = service.call(req).await.unwrap();
+            assert_eq!(res.headers()["grpc-status"], status);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+#![warn(missing_docs)]
+
+//! How the attributes a check brings for its actor merge with the actor's registered ones
+//!
+//! A check can describe its actor's attributes, and if the actor is registered, its registered
+//! attributes are merged in, key by key. By default the registered values of a key replace the
+//! check's, and keys the actor has no registered values for are kept as the check gave them.
+//! That lets a caller add attributes nobody registered. Each key can merge its own way instead:
+//! the check's values can be added to the registered ones, or ignored altogether, so callers can't
+//! claim privileged attributes for an actor.
+//!
+//! The attributes Gatehouse sets itself, `member-of`, `has-role`, and `risk`, are always ignored,
+//! so callers can't claim groups or roles they weren't given. A policy can't name a merge for
+//! them.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::attribute::{self, AttributeMap};
+
+/// How the values a check brings for an attribute merge with the registered ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AttributeMerge {
+    /// the check's values and the registered ones are combined
+    Union,
+    /// the registered values replace the check's, which are kept if there are none
+    #[default]
+    RegisteredWins,
+    /// the check's values are dropped, so only registered values count
+    CallerIgnored,
+}
+
+impl AttributeMerge {
+    /// Read the merge from its name: `union`, `registered-wins`, or `caller-ignored`
+    pub fn parse(val: &str) -> Result<Self, String> {
+        match val.trim().to_ascii_lowercase().as_str() {
+            "union" => Ok(Self::Union),
+            "registered-wins" => Ok(Self::RegisteredWins),
+            "caller-ignored" => Ok(Self::CallerIgnored),
+            _ => Err(format!("Unknown attribute merge: {val}")),
+        }
+    }
+}
+
+impl Display for AttributeMerge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttributeMerge::Union => write!(f, "union"),
+            AttributeMerge::RegisteredWins => write!(f, "registered-wins"),
+            AttributeMerge::CallerIgnored => write!(f, "caller-ignored"),
+        }
+    }
+}
+
+/// How each attribute a check brings merges with the registered ones
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergePolicy {
+    /// how attributes without a merge of their own merge
+    pub default: AttributeMerge,
+    /// the attributes that merge their own way, by key
+    pub keys: HashMap<String, AttributeMerge>,
+}
+
+impl MergePolicy {
+    /// Read the policy from a comma-separated list of `key=merge`, where the key `*` sets the
+    /// default, e.g. `*=union,level=caller-ignored`
+    pub fn parse(val: &str) -> Result<Self, String> {
+        let mut policy = Self::default();
+        for entry in val.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (key, merge) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Attribute merge must be key=merge: {entry}"))?;
+            let merge = AttributeMerge::parse(merge)?;
+            match key.trim() {
+                "" => return Err(format!("Attribute merge has no key: {entry}")),
+                "*" => policy.default = merge,
+                key if attribute::SYSTEM_ATTRIBUTES
+                    .iter()
+                    .any(|system| system.eq_ignore_ascii_case(key)) =>
+                {
+                    return Err(format!("{key} is set by Gatehouse and can't be merged"));
+                }
+                key => {
+                    policy.keys.insert(key.to_string(), merge);
+                }
+            }
+        }
+        Ok(policy)
+    }
+
+    /// How an attribute merges
+    pub fn merge_for(&self, key: &str) -> AttributeMerge {
+        if attribute::is_system(key) {
+            return AttributeMerge::CallerIgnored;
+        }
+        self.keys.get(key).copied().unwrap_or(self.default)
+    }
+
+    /// Whether any of the attributes a check brought are dropped
+    pub fn ignores_any(&self, attributes: &AttributeMap) -> bool {
+        attributes
+            .keys()
+            .any(|key| self.merge_for(key) == AttributeMerge::CallerIgnored)
+    }
+
+    /// Merge an actor's registered attributes, if it has any, into those a check brought
+    pub fn merge(&self, attributes: &mut AttributeMap, registered: Option<&AttributeMap>) {
+        if self.ignores_any(attributes) {
+            attributes.retain(|key, _| self.merge_for(key) != AttributeMerge::CallerIgnored);
+        }
+
+        for (key, vals) in registered.into_iter().flatten() {
+            match self.merge_for(key) {
+                AttributeMerge::Union => attributes
+                    .entry(key.clone())
+                    .or_default()
+                    .extend(vals.iter().cloned()),
+                AttributeMerge::RegisteredWins | AttributeMerge::CallerIgnored => {
+                    attributes.insert(key.clone(), vals.clone());
+                }
+            }
+        }
+    }
+}
+
+impl Display for MergePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} by default", self.default)?;
+        let mut keys: Vec<_> = self.keys.iter().collect();
+        keys.sort_by_key(|(key, _)| *key);
+        for (key, merge) in keys {
+            write!(f, ", {merge} for {key}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::values;
+    use crate::intern::intern;
+
+    #[test]
+    fn test_merge() {
+        let attributes = |pairs: &[(&str, &[&str])]| -> AttributeMap {
+            pairs
+                .iter()
+                .map(|(key, vals)| (intern(key), values(vals.iter().copied())))
+                .collect()
+        };
+        let registered = attributes(&[("team", &["eng"]), ("level", &["2"])]);
+        let caller = attributes(&[
+            ("team", &["ops"]),
+            ("level", &["9"]),
+            ("has-role", &["admin"]),
+        ]);
+
+        // by default, registered values win, other keys are kept, and roles can't be claimed
+        let mut merged = caller.clone();
+        MergePolicy::default().merge(&mut merged, Some(&registered));
+        let expected = attributes(&[("team", &["eng"]), ("level", &["2"])]);
+        assert_eq!(merged, expected);
+
+        // and no policy can trust callers with them
+        assert_eq!(
+            MergePolicy::parse("has-role=union").unwrap_err(),
+            "has-role is set by Gatehouse and can't be merged"
+        );
+        assert!(MergePolicy::parse("Member-Of=registered-wins").is_err());
+
+        // even a union by default leaves them out
+        let policy = MergePolicy::parse("*=union, level=registered-wins").unwrap();
+        assert_eq!(
+            policy.to_string(),
+            "union by default, registered-wins for level"
+        );
+        let mut merged = caller.clone();
+        policy.merge(&mut merged, Some(&registered));
+        let expected = attributes(&[("team", &["eng", "ops"]), ("level", &["2"])]);
+        assert_eq!(merged, expected);
+
+        // ignored keys are dropped for actors that aren't registered too
+        let mut merged = caller;
+        policy.merge(&mut merged, None);
+        let expected = attributes(&[("team", &["ops"]), ("level", &["9"])]);
+        assert_eq!(merged, expected);
+
+        assert!(MergePolicy::parse("team").is_err());
+        assert!(MergePolicy::parse("team=overwrite").is_err());
+    }
+}
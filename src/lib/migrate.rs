@@ -0,0 +1,248 @@
+#![warn(missing_docs)]
+
+//! Upgrades of stored entities from older schemas
+//!
+//! Every entity a backend stores is stamped with the schema version it was written in, as
+//! `schema_version`. Documents written before versions were stamped count as version 0. As they
+//! are loaded, documents from older versions are brought up to [`SCHEMA_VERSION`] one migration
+//! at a time, before they are read into their `Registered*` structs, so changing those structs
+//! only needs a migration rather than breaking existing deployments. Documents from a newer
+//! version than this server knows are refused rather than guessed at.
+//!
+//! What was upgraded is reported at startup with the other startup issues. By default the
+//! upgraded entities are then saved back in the current schema; in dry-run mode they are only
+//! reported, and storage is left as it was, so an older server can still read it.
+
+use std::fmt::Display;
+use std::sync::Mutex;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// the schema version entities are stored in
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// the key documents carry their schema version under
+pub(crate) const VERSION_KEY: &str = "schema_version";
+
+/// A change to the stored form of one kind of entity, from one version to the next
+pub(crate) struct Migration {
+    /// the kind of entity, as the backends name it, e.g. `policies`
+    pub kind: &'static str,
+    /// the version the migration upgrades from
+    pub from: u32,
+    /// change a document from the old version's form to the next one's
+    pub apply: fn(&mut Map<String, Value>) -> Result<(), String>,
+}
+
+/// Every migration, by version
+///
+/// Version 1 is the first stamped version, and documents from before it only need stamping.
+/// When a `Registered*` struct changes in a way older documents can't be read as, bump
+/// [`SCHEMA_VERSION`] and add a migration from the old version here.
+const MIGRATIONS: &[Migration] = &[];
+
+/// What happens to entities stored in an older schema
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MigrationMode {
+    /// upgrade them and save them back in the current schema
+    #[default]
+    Apply,
+    /// upgrade them in memory and report what would be saved, leaving storage as it was
+    DryRun,
+}
+
+impl MigrationMode {
+    /// Parse a mode: `apply` or `dry-run`
+    pub fn parse(val: &str) -> Result<Self, String> {
+        match val.trim().to_ascii_lowercase().as_str() {
+            "apply" => Ok(Self::Apply),
+            "dry-run" | "dryrun" => Ok(Self::DryRun),
+            _ => Err(format!("Unknown migration mode: {val}")),
+        }
+    }
+}
+
+impl Display for MigrationMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Apply => write!(f, "apply"),
+            Self::DryRun => write!(f, "dry-run"),
+        }
+    }
+}
+
+/// An entity that was stored in an older schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Upgraded {
+    /// the kind of entity, as the backends name it
+    pub kind: String,
+    /// the entity's key: its name, `type/name` for actors and targets, or an API key's id
+    pub key: String,
+    /// the version it was stored in
+    pub from: u32,
+}
+
+/// The entities a backend upgraded as it loaded them
+#[derive(Debug, Default)]
+pub(crate) struct Upgrades(Mutex<Vec<Upgraded>>);
+
+impl Upgrades {
+    /// Read a stored document, remembering it if it had to be upgraded
+    pub fn decode<T: DeserializeOwned>(&self, kind: &str, json: &str) -> Result<T, String> {
+        let value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        self.decode_value(kind, value)
+    }
+
+    /// Read a stored document that is already parsed, remembering it if it had to be upgraded
+    pub fn decode_value<T: DeserializeOwned>(&self, kind: &str, value: Value) -> Result<T, String> {
+        let (obj, upgraded) = upgrade(kind, value, MIGRATIONS)?;
+        if let Some(upgraded) = upgraded {
+            if let Ok(mut upgrades) = self.0.lock() {
+                upgrades.push(upgraded);
+            }
+        }
+        Ok(obj)
+    }
+
+    /// Everything upgraded since this was last called
+    pub fn take(&self) -> Vec<Upgraded> {
+        self.0
+            .lock()
+            .map(|mut upgrades| std::mem::take(&mut *upgrades))
+            .unwrap_or_default()
+    }
+}
+
+/// Read a stored document, upgrading it if it is from an older schema
+pub(crate) fn decode<T: DeserializeOwned>(kind: &str, json: &str) -> Result<T, String> {
+    let value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    upgrade(kind, value, MIGRATIONS).map(|(obj, _)| obj)
+}
+
+/// Write an entity as a document stamped with the current schema version
+pub(crate) fn encode<T: Serialize>(obj: &T) -> Result<String, String> {
+    serde_json::to_string(&stamp(obj)?).map_err(|err| err.to_string())
+}
+
+/// An entity as a document stamped with the current schema version
+pub(crate) fn stamp<T: Serialize>(obj: &T) -> Result<Value, String> {
+    let mut value = serde_json::to_value(obj).map_err(|err| err.to_string())?;
+    if let Value::Object(ref mut doc) = value {
+        doc.insert(VERSION_KEY.to_string(), Value::from(SCHEMA_VERSION));
+    }
+    Ok(value)
+}
+
+/// Bring a document up to the current schema and read it, along with what was upgraded if it
+/// was from an older one
+fn upgrade<T: DeserializeOwned>(
+    kind: &str,
+    value: Value,
+    migrations: &[Migration],
+) -> Result<(T, Option<Upgraded>), String> {
+    let Value::Object(mut doc) = value else {
+        return Err(format!("Stored {kind} are objects"));
+    };
+    let from = match doc.remove(VERSION_KEY) {
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("Bad schema version: {version}"))?,
+        None => 0,
+    };
+    if from > SCHEMA_VERSION {
+        return Err(format!(
+            "Stored in schema version {from}, but this server only knows up to {SCHEMA_VERSION}"
+        ));
+    }
+
+    let upgraded = match from < SCHEMA_VERSION {
+        true => {
+            for migration in migrations
+                .iter()
+                .filter(|migration| migration.kind == kind && migration.from >= from)
+            {
+                (migration.apply)(&mut doc).map_err(|err| {
+                    format!(
+                        "Could not upgrade from schema version {}: {err}",
+                        migration.from
+                    )
+                })?;
+            }
+            Some(Upgraded {
+                kind: kind.to_string(),
+                key: key(kind, &doc),
+                from,
+            })
+        }
+        false => None,
+    };
+
+    let obj = serde_json::from_value(Value::Object(doc)).map_err(|err| err.to_string())?;
+    Ok((obj, upgraded))
+}
+
+/// The key of a stored entity
+fn key(kind: &str, doc: &Map<String, Value>) -> String {
+    let field = |name| doc.get(name).and_then(Value::as_str).unwrap_or_default();
+    match kind {
+        "actors" | "targets" => format!("{}/{}", field("typestr"), field("name")),
+        "apikeys" => field("id").to_string(),
+        "proposals" | "breakglasses" => doc.get("id").map(Value::to_string).unwrap_or_default(),
+        "bundles" => String::from("applied"),
+        _ => field("name").to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::role::RegisteredRole;
+
+    use super::*;
+
+    #[test]
+    fn test_migrate() {
+        let role = RegisteredRole::new("admin", None);
+        let json = encode(&role).unwrap();
+        assert!(json.contains(&format!("\"schema_version\":{SCHEMA_VERSION}")));
+
+        // current documents aren't upgraded, and older ones are
+        let upgrades = Upgrades::default();
+        let loaded: RegisteredRole = upgrades.decode("roles", &json).unwrap();
+        assert_eq!(loaded, role);
+        assert!(upgrades.take().is_empty());
+        let old = serde_json::to_string(&role).unwrap();
+        let loaded: RegisteredRole = upgrades.decode("roles", &old).unwrap();
+        assert_eq!(loaded, role);
+        let upgraded = Upgraded {
+            kind: String::from("roles"),
+            key: String::from("admin"),
+            from: 0,
+        };
+        assert_eq!(upgrades.take(), vec![upgraded]);
+
+        // migrations for the kind run on documents from before their version
+        let rename = Migration {
+            kind: "roles",
+            from: 0,
+            apply: |doc| {
+                let name = doc.remove("title").ok_or("no title")?;
+                doc.insert(String::from("name"), name);
+                Ok(())
+            },
+        };
+        let old = old.replace("\"name\"", "\"title\"");
+        let value = serde_json::from_str(&old).unwrap();
+        let (loaded, _): (RegisteredRole, _) = upgrade("roles", value, &[rename]).unwrap();
+        assert_eq!(loaded, role);
+
+        // newer documents are refused
+        let newer = json.replace(
+            &format!("\"schema_version\":{SCHEMA_VERSION}"),
+            &format!("\"schema_version\":{}", SCHEMA_VERSION + 1),
+        );
+        assert!(decode::<RegisteredRole>("roles", &newer).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+#![warn(missing_docs)]
+
+//! Policy rules as Rust types
+//!
+//! These are the types the server evaluates policy rules as, for tooling that builds or analyzes
+//! policies without going through protobufs. They convert to and from the protobuf messages with
+//! `From`, and serialize the way the file storage backend keeps rules.
+//!
+//! The module follows semver. Structs and enums are `#[non_exhaustive]`, so checks and fields can
+//! be added in minor releases; the builders put together the structs.
+//!
+//! ```
+//! use gatehouse::model::{KvCheck, PolicyRuleBuilder, StringCheck, TargetCheckBuilder};
+//!
+//! let rule = PolicyRuleBuilder::new("readers")
+//!     .actor_attribute(KvCheck::Has("role".to_string(), vec!["reader".to_string()]))
+//!     .target(
+//!         TargetCheckBuilder::new()
+//!             .action(StringCheck::OneOf(vec!["read".to_string()]))
+//!             .build(),
+//!     )
+//!     .build();
+//! assert!(rule.refers_to_attribute("role", "reader"));
+//! ```
+
+pub use crate::policy::{
+    ActorCheck, AttributeRef, Cidr, CidrCheck, Compare, CompareCheck, Decide, KvCheck, Mode,
+    NumberCheck, RateCheck, RegisteredPolicyRule, Source, StringCheck, TargetCheck,
+};
+use crate::proto::policies::PolicyRule;
+pub use crate::timestamps::Timestamps;
+
+/// Builds a policy rule; it allows everything until told otherwise
+#[derive(Debug, Clone)]
+pub struct PolicyRuleBuilder {
+    rule: RegisteredPolicyRule,
+}
+
+impl PolicyRuleBuilder {
+    /// Start a rule with a name
+    pub fn new(name: &str) -> Self {
+        Self {
+            rule: RegisteredPolicyRule {
+                name: name.to_string(),
+                desc: None,
+                actor_check: None,
+                env_attributes: Vec::new(),
+                target_check: None,
+                decision: Decide::Allow,
+                mode: Mode::Enforce,
+                wasm_module: None,
+                compare_checks: Vec::new(),
+                cidr_checks: Vec::new(),
+                target_types: Vec::new(),
+                rate_checks: Vec::new(),
+                risk: None,
+                tags: Vec::new(),
+                timestamps: Timestamps::default(),
+            },
+        }
+    }
+
+    /// Describe the rule
+    pub fn desc(mut self, desc: &str) -> Self {
+        self.rule.desc = Some(desc.to_string());
+        self
+    }
+
+    /// Allow what the rule matches
+    pub fn allow(mut self) -> Self {
+        self.rule.decision = Decide::Allow;
+        self
+    }
+
+    /// Deny what the rule matches
+    pub fn deny(mut self) -> Self {
+        self.rule.decision = Decide::Deny;
+        self
+    }
+
+    /// Allow what the rule matches once this many approvers co-sign the check
+    pub fn allow_with_approval(mut self, approvals: u32) -> Self {
+        self.rule.decision = Decide::AllowWithApproval(approvals.max(1));
+        self
+    }
+
+    /// Only log the rule's decision rather than enforce it
+    pub fn shadow(mut self) -> Self {
+        self.rule.mode = Mode::Shadow;
+        self
+    }
+
+    /// Only match actors that pass this check
+    pub fn actor(mut self, check: ActorCheck) -> Self {
+        self.rule.actor_check = Some(check);
+        self
+    }
+
+    /// Only match actors whose attributes pass this check; can be called again to add more
+    pub fn actor_attribute(mut self, check: KvCheck) -> Self {
+        self.rule
+            .actor_check
+            .get_or_insert_with(|| ActorCheckBuilder::new().build())
+            .attributes
+            .push(check);
+        self
+    }
+
+    /// Only match checks whose environment passes this check; can be called again to add more
+    pub fn env_attribute(mut self, check: KvCheck) -> Self {
+        self.rule.env_attributes.push(check);
+        self
+    }
+
+    /// Only match targets and actions that pass this check
+    pub fn target(mut self, check: TargetCheck) -> Self {
+        self.rule.target_check = Some(check);
+        self
+    }
+
+    /// Only match targets of these types
+    pub fn target_types(mut self, types: &[&str]) -> Self {
+        self.rule
+            .target_types
+            .extend(types.iter().map(|t| t.to_string()));
+        self
+    }
+
+    /// Only match checks that pass this comparison; can be called again to add more
+    pub fn compare(mut self, check: CompareCheck) -> Self {
+        self.rule.compare_checks.push(check);
+        self
+    }
+
+    /// Only match checks that pass this IP address check; can be called again to add more
+    pub fn cidr(mut self, check: CidrCheck) -> Self {
+        self.rule.cidr_checks.push(check);
+        self
+    }
+
+    /// Only match once the actor's uses reach this rate; can be called again to add more
+    pub fn rate(mut self, check: RateCheck) -> Self {
+        self.rule.rate_checks.push(check);
+        self
+    }
+
+    /// Only match checks whose risk score passes this check
+    pub fn risk(mut self, check: NumberCheck) -> Self {
+        self.rule.risk = Some(check);
+        self
+    }
+
+    /// Only match when the condition of this WASM module does
+    pub fn wasm_module(mut self, module: &str) -> Self {
+        self.rule.wasm_module = Some(module.to_string());
+        self
+    }
+
+    /// Label the rule
+    pub fn tags(mut self, tags: &[&str]) -> Self {
+        self.rule.tags.extend(tags.iter().map(|t| t.to_string()));
+        self
+    }
+
+    /// The policy rule, with names lowercased the way the server stores them
+    pub fn build(self) -> RegisteredPolicyRule {
+        RegisteredPolicyRule::from(PolicyRule::from(self.rule))
+    }
+}
+
+/// Builds an actor check; it matches every actor until told otherwise
+#[derive(Debug, Clone)]
+pub struct ActorCheckBuilder {
+    check: ActorCheck,
+}
+
+impl Default for ActorCheckBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ActorCheckBuilder {
+    /// Start a check that matches every actor
+    pub fn new() -> Self {
+        Self {
+            check: ActorCheck {
+                name: None,
+                typestr: None,
+                attributes: Vec::new(),
+                bucket: None,
+            },
+        }
+    }
+
+    /// Only match actors whose name passes this check
+    pub fn name(mut self, check: StringCheck) -> Self {
+        self.check.name = Some(check);
+        self
+    }
+
+    /// Only match actors whose type passes this check
+    pub fn typestr(mut self, check: StringCheck) -> Self {
+        self.check.typestr = Some(check);
+        self
+    }
+
+    /// Only match actors whose attributes pass this check; can be called again to add more
+    pub fn attribute(mut self, check: KvCheck) -> Self {
+        self.check.attributes.push(check);
+        self
+    }
+
+    /// Only match actors in buckets that pass this check
+    pub fn bucket(mut self, check: NumberCheck) -> Self {
+        self.check.bucket = Some(check);
+        self
+    }
+
+    /// The actor check
+    pub fn build(self) -> ActorCheck {
+        self.check
+    }
+}
+
+/// Builds a target check; it matches every target and action until told otherwise
+#[derive(Debug, Clone)]
+pub struct TargetCheckBuilder {
+    check: TargetCheck,
+}
+
+impl Default for TargetCheckBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TargetCheckBuilder {
+    /// Start a check that matches every target and action
+    pub fn new() -> Self {
+        Self {
+            check: TargetCheck {
+                name: None,
+                typestr: None,
+                attributes: Vec::new(),
+                match_in_actor: Vec::new(),
+                match_in_env: Vec::new(),
+                action: None,
+                action_except: Vec::new(),
+            },
+        }
+    }
+
+    /// Only match targets whose name passes this check
+    pub fn name(mut self, check: StringCheck) -> Self {
+        self.check.name = Some(check);
+        self
+    }
+
+    /// Only match targets whose type passes this check
+    pub fn typestr(mut self, check: StringCheck) -> Self {
+        self.check.typestr = Some(check);
+        self
+    }
+
+    /// Only match targets whose attributes pass this check; can be called again to add more
+    pub fn attribute(mut self, check: KvCheck) -> Self {
+        self.check.attributes.push(check);
+        self
+    }
+
+    /// Only match targets that share a value of this attribute with the actor
+    pub fn match_in_actor(mut self, key: &str) -> Self {
+        self.check.match_in_actor.push(key.to_string());
+        self
+    }
+
+    /// Only match targets that share a value of this attribute with the environment
+    pub fn match_in_env(mut self, key: &str) -> Self {
+        self.check.match_in_env.push(key.to_string());
+        self
+    }
+
+    /// Only match actions that pass this check
+    pub fn action(mut self, check: StringCheck) -> Self {
+        self.check.action = Some(check);
+        self
+    }
+
+    /// Never match this action, or actions in this group, whatever the action check says; can be
+    /// called again to add more
+    pub fn except_action(mut self, action: &str) -> Self {
+        self.check.action_except.push(action.to_string());
+        self
+    }
+
+    /// The target check
+    pub fn build(self) -> TargetCheck {
+        self.check
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builders() {
+        let rule = PolicyRuleBuilder::new("Admins")
+            .deny()
+            .actor(
+                ActorCheckBuilder::new()
+                    .typestr(StringCheck::OneOf(vec!["user".to_string()]))
+                    .build(),
+            )
+            .actor_attribute(KvCheck::Has("role".to_string(), vec!["admin".to_string()]))
+            .target(
+                TargetCheckBuilder::new()
+                    .name(StringCheck::OneOf(vec!["maindb".to_string()]))
+                    .match_in_actor("team")
+                    .except_action("Delete")
+                    .build(),
+            )
+            .target_types(&["Database"])
+            .compare(CompareCheck::new(
+                AttributeRef::new(Source::Actor, "region"),
+                Compare::Equal,
+                AttributeRef::new(Source::Env, "region"),
+            ))
+            .rate(RateCheck::new(&["Read"], 10, 60))
+            .tags(&["Ops"])
+            .build();
+
+        // the actor check that was set is added to rather than replaced
+        let actor_check = rule.actor_check.as_ref().unwrap();
+        assert!(actor_check.typestr.is_some());
+        assert_eq!(actor_check.attributes.len(), 1);
+        assert_eq!(rule.decision, Decide::Deny);
+        assert!(rule.refers_to_target("database", "maindb"));
+        assert!(rule.refers_to_attribute("role", "admin"));
+
+        // names are normalized the way the server stores them
+        assert_eq!(rule.target_types, vec!["database"]);
+        assert_eq!(rule.tags, vec!["ops"]);
+        assert_eq!(rule.rate_checks[0].actions, vec!["read"]);
+        assert_eq!(
+            rule.target_check.as_ref().unwrap().action_except,
+            vec!["delete"]
+        );
+
+        // a built rule is the same as one that came in through protobufs
+        let proto = PolicyRule::from(rule.clone());
+        assert_eq!(RegisteredPolicyRule::from(proto), rule);
+    }
+}
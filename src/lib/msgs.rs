@@ -2,39 +2,69 @@
 
 //! Internal messages
 
+use tokio::sync::broadcast;
 use tokio::sync::oneshot::Sender;
 use tonic::Status;
 
+use crate::apikey::RegisteredApiKey;
+use crate::group::RegisteredGroup;
 use crate::proto::actors::{
-    Actor, AddActorRequest, GetActorsRequest, ModifyActorRequest, RemoveActorRequest,
+    Actor, ActorMembershipsResponse, AddActorRequest, AddActorResult, AddActorsRequest,
+    AddDelegationRequest, Delegation, GetActorMembershipsRequest, GetActorsRequest,
+    GetDelegationsRequest, ModifyActorRequest, RemoveActorRequest, RemoveDelegationRequest,
 };
-use crate::proto::base::CheckRequest;
+use crate::proto::base::mutation::Op;
+use crate::proto::base::watch_event::Change;
+use crate::proto::base::{
+    ApiKey, ApplyTransactionRequest, Approval, BreakGlass, BreakGlassRequest, CheckRequest,
+    CheckResponse, CoverageReportRequest, CoverageReportResponse, EntityChange, FindUnusedRequest,
+    FindUnusedResponse, GetPolicyStatsRequest, GetPolicyStatsResponse, GetReferencesRequest,
+    GetReferencesResponse, GetServerInfoResponse, GetServerStatsRequest, GetServerStatsResponse,
+    ReplicateRequest, ReplicateResponse, SyncResponse, TestPoliciesRequest, TestPoliciesResponse,
+    TraceCheckResponse, WatchEvent, WhatIfRequest, WhatIfResponse,
+};
+use crate::proto::common::CheckHits;
 use crate::proto::groups::{
-    AddGroupRequest, GetGroupsRequest, Group, ModifyGroupRequest, RemoveGroupRequest,
+    AddGroupRequest, BulkModifyMembershipsRequest, CloneGroupRequest, GetGroupMembersRequest,
+    GetGroupsRequest, Group, GroupMembersResponse, ModifyGroupRequest, RemoveGroupRequest,
+    RenameGroupRequest,
 };
 use crate::proto::policies::{
-    AddPolicyRequest, Decide, GetPoliciesRequest, ModifyPolicyRequest, PolicyRule,
-    RemovePolicyRequest,
+    AddPolicyRequest, AddPolicySetRequest, ClonePolicyRequest, GetPoliciesRequest,
+    GetPolicySetsRequest, ModifyPolicyRequest, ModifyPolicySetRequest, PolicyBundle, PolicyImpact,
+    PolicyRule, PolicySet, Proposal, RemovePolicyRequest, RemovePolicySetRequest,
+    RenamePolicyRequest,
 };
 use crate::proto::roles::{
     AddRoleRequest, GetRolesRequest, ModifyRoleRequest, RemoveRoleRequest, Role,
 };
 use crate::proto::targets::{
-    AddTargetRequest, GetTargetsRequest, ModifyTargetRequest, RemoveTargetRequest, Target,
+    AddTargetRequest, AddTargetResult, AddTargetsRequest, GetTargetsRequest, ModifyTargetRequest,
+    RemoveTargetRequest, Target,
+};
+use crate::proto::webhooks::{
+    AddWebhookRequest, Delivery, GetDeliveriesRequest, GetWebhooksRequest, RemoveWebhookRequest,
+    Webhook,
 };
 use crate::storage::BackendUpdate;
 
 #[derive(Debug)]
 pub(crate) enum DsRequest {
     AddTarget(AddTargetRequest, Sender<DsResponse>),
+    AddTargets(AddTargetsRequest, Sender<DsResponse>),
     ModifyTarget(ModifyTargetRequest, Sender<DsResponse>),
     RemoveTarget(RemoveTargetRequest, Sender<DsResponse>),
     GetTargets(GetTargetsRequest, Sender<DsResponse>),
 
     AddActor(AddActorRequest, Sender<DsResponse>),
+    AddActors(AddActorsRequest, Sender<DsResponse>),
     ModifyActor(ModifyActorRequest, Sender<DsResponse>),
     RemoveActor(RemoveActorRequest, Sender<DsResponse>),
     GetActors(GetActorsRequest, Sender<DsResponse>),
+    GetActorMemberships(GetActorMembershipsRequest, Sender<DsResponse>),
+    AddDelegation(AddDelegationRequest, Sender<DsResponse>),
+    RemoveDelegation(RemoveDelegationRequest, Sender<DsResponse>),
+    GetDelegations(GetDelegationsRequest, Sender<DsResponse>),
 
     AddRole(AddRoleRequest, Sender<DsResponse>),
     ModifyRole(ModifyRoleRequest, Sender<DsResponse>),
@@ -43,36 +73,231 @@ pub(crate) enum DsRequest {
 
     AddGroup(AddGroupRequest, Sender<DsResponse>),
     ModifyGroup(ModifyGroupRequest, Sender<DsResponse>),
+    BulkModifyMemberships(BulkModifyMembershipsRequest, Sender<DsResponse>),
     RemoveGroup(RemoveGroupRequest, Sender<DsResponse>),
+    CloneGroup(CloneGroupRequest, Sender<DsResponse>),
+    RenameGroup(RenameGroupRequest, Sender<DsResponse>),
     GetGroups(GetGroupsRequest, Sender<DsResponse>),
+    GetGroupMembers(GetGroupMembersRequest, Sender<DsResponse>),
+    SyncGroups(String, Vec<RegisteredGroup>, Sender<DsResponse>),
 
     AddPolicy(AddPolicyRequest, Sender<DsResponse>),
     ModifyPolicy(ModifyPolicyRequest, Sender<DsResponse>),
     RemovePolicy(RemovePolicyRequest, Sender<DsResponse>),
+    ClonePolicy(ClonePolicyRequest, Sender<DsResponse>),
+    RenamePolicy(RenamePolicyRequest, Sender<DsResponse>),
+    Propose(Proposal, Sender<DsResponse>),
+    ListProposals(Sender<DsResponse>),
+    /// approve a proposal by id, on behalf of an approver
+    ApproveProposal(u64, String, Sender<DsResponse>),
+    RejectProposal(u64, Sender<DsResponse>),
     GetPolicies(GetPoliciesRequest, Sender<DsResponse>),
+    AddPolicySet(AddPolicySetRequest, Sender<DsResponse>),
+    ModifyPolicySet(ModifyPolicySetRequest, Sender<DsResponse>),
+    RemovePolicySet(RemovePolicySetRequest, Sender<DsResponse>),
+    GetPolicySets(GetPolicySetsRequest, Sender<DsResponse>),
+
+    AddWebhook(AddWebhookRequest, Sender<DsResponse>),
+    RemoveWebhook(RemoveWebhookRequest, Sender<DsResponse>),
+    GetWebhooks(GetWebhooksRequest, Sender<DsResponse>),
+    GetWebhookDeliveries(GetDeliveriesRequest, Sender<DsResponse>),
+
+    AddApiKey(RegisteredApiKey, Sender<DsResponse>),
+    /// revoke an API key by id
+    RevokeApiKey(String, Sender<DsResponse>),
+    GetApiKeys(Sender<DsResponse>),
+    /// find the issued key an API key is, if one was given
+    VerifyApiKey(Option<String>, Sender<DsResponse>),
 
     Check(CheckRequest, Sender<DsResponse>),
+    /// co-sign an approval by id, on behalf of an approver
+    Approve(u64, String, Sender<DsResponse>),
+    GetApprovals(Sender<DsResponse>),
+    /// grant emergency access, on behalf of a caller
+    BreakGlass(BreakGlassRequest, String, Sender<DsResponse>),
+    GetBreakGlass(Sender<DsResponse>),
+    TraceCheck(CheckRequest, Sender<DsResponse>),
+    CoverageReport(CoverageReportRequest, Sender<DsResponse>),
+    WhatIf(WhatIfRequest, Sender<DsResponse>),
+    TestPolicies(TestPoliciesRequest, Sender<DsResponse>),
+    GetServerStats(GetServerStatsRequest, Sender<DsResponse>),
+    GetServerInfo(Sender<DsResponse>),
+    GetPolicyStats(GetPolicyStatsRequest, Sender<DsResponse>),
     Update(BackendUpdate),
+    Reconcile(Vec<BackendUpdate>, Sender<DsResponse>),
+    ApplyTransaction(ApplyTransactionRequest, Sender<DsResponse>),
+    /// make the policies those of a verified bundle, or only say how for a dry run
+    ApplyBundle(PolicyBundle, bool, Sender<DsResponse>),
+    GetReferences(GetReferencesRequest, Sender<DsResponse>),
+    FindUnused(FindUnusedRequest, Sender<DsResponse>),
+    RemoveUnused(FindUnusedRequest, Sender<DsResponse>),
+    /// bring roles back in step with the groups that grant them
+    RepairDrift(Sender<DsResponse>),
+    /// load everything again from the storage backend
+    ReloadState(Sender<DsResponse>),
+
+    Sync(Sender<DsResponse>),
+    Watch(Sender<DsResponse>),
+    StreamChanges(String, Sender<DsResponse>),
+    Load(Box<SyncResponse>, Sender<DsResponse>),
+    Apply(BackendUpdate, Sender<DsResponse>),
+    Replicate(ReplicateRequest, Sender<DsResponse>),
+}
+
+impl DsRequest {
+    /// Whether the request changes anything
+    pub(crate) fn is_mutation(&self) -> bool {
+        matches!(
+            self,
+            DsRequest::AddTarget(..)
+                | DsRequest::AddTargets(..)
+                | DsRequest::ModifyTarget(..)
+                | DsRequest::RemoveTarget(..)
+                | DsRequest::AddActor(..)
+                | DsRequest::AddActors(..)
+                | DsRequest::ModifyActor(..)
+                | DsRequest::RemoveActor(..)
+                | DsRequest::AddDelegation(..)
+                | DsRequest::RemoveDelegation(..)
+                | DsRequest::AddRole(..)
+                | DsRequest::ModifyRole(..)
+                | DsRequest::RemoveRole(..)
+                | DsRequest::AddGroup(..)
+                | DsRequest::ModifyGroup(..)
+                | DsRequest::BulkModifyMemberships(..)
+                | DsRequest::RemoveGroup(..)
+                | DsRequest::CloneGroup(..)
+                | DsRequest::RenameGroup(..)
+                | DsRequest::SyncGroups(..)
+                | DsRequest::AddPolicy(..)
+                | DsRequest::ModifyPolicy(..)
+                | DsRequest::RemovePolicy(..)
+                | DsRequest::ClonePolicy(..)
+                | DsRequest::RenamePolicy(..)
+                | DsRequest::AddPolicySet(..)
+                | DsRequest::ModifyPolicySet(..)
+                | DsRequest::RemovePolicySet(..)
+                | DsRequest::Propose(..)
+                | DsRequest::ApproveProposal(..)
+                | DsRequest::RejectProposal(..)
+                | DsRequest::ApplyTransaction(..)
+                | DsRequest::ApplyBundle(..)
+                | DsRequest::Replicate(..)
+                | DsRequest::RemoveUnused(..)
+                | DsRequest::RepairDrift(..)
+                | DsRequest::AddWebhook(..)
+                | DsRequest::RemoveWebhook(..)
+                | DsRequest::AddApiKey(..)
+                | DsRequest::RevokeApiKey(..)
+                | DsRequest::BreakGlass(..)
+                | DsRequest::Approve(..)
+        )
+    }
+
+    /// Whether the request changes policies or policy sets other than by applying a bundle
+    pub(crate) fn changes_policies(&self) -> bool {
+        match self {
+            DsRequest::AddPolicy(..)
+            | DsRequest::ModifyPolicy(..)
+            | DsRequest::RemovePolicy(..)
+            | DsRequest::ClonePolicy(..)
+            | DsRequest::RenamePolicy(..)
+            | DsRequest::ApproveProposal(..)
+            | DsRequest::AddPolicySet(..)
+            | DsRequest::ModifyPolicySet(..)
+            | DsRequest::RemovePolicySet(..) => true,
+            DsRequest::ApplyTransaction(req, _) => req.mutations.iter().any(|mutation| {
+                matches!(
+                    mutation.op,
+                    Some(Op::AddPolicy(_)) | Some(Op::ModifyPolicy(_)) | Some(Op::RemovePolicy(_))
+                )
+            }),
+            DsRequest::Replicate(req, _) => req.changes.iter().any(|change| {
+                matches!(
+                    change
+                        .event
+                        .as_ref()
+                        .and_then(|event| event.change.as_ref()),
+                    Some(Change::PutPolicy(_))
+                        | Some(Change::DeletePolicy(_))
+                        | Some(Change::PutPolicySet(_))
+                        | Some(Change::DeletePolicySet(_))
+                )
+            }),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum DsResponse {
     Error(Status),
 
-    SingleTarget(Target),
-    MultipleTargets(Vec<Target>),
+    /// a target, and what referred to it when it was removed
+    SingleTarget(Target, Vec<String>),
+    MultipleTargets(Vec<Target>, Vec<CheckHits>),
+    AddedTargets(Vec<AddTargetResult>),
 
-    SingleActor(Actor),
-    MultipleActors(Vec<Actor>),
+    /// an actor, and what referred to it when it was removed
+    SingleActor(Actor, Vec<String>),
+    MultipleActors(Vec<Actor>, Vec<CheckHits>),
+    AddedActors(Vec<AddActorResult>),
+    ActorMemberships(ActorMembershipsResponse),
+    SingleDelegation(Delegation),
+    MultipleDelegations(Vec<Delegation>),
 
-    SingleRole(Role),
+    /// a role, and the groups the change also updated
+    SingleRole(Role, Vec<String>),
     MultipleRoles(Vec<Role>),
 
-    SingleGroup(Group),
+    /// a group, and the roles the change also updated
+    SingleGroup(Group, Vec<String>),
     MultipleGroups(Vec<Group>),
+    GroupMembers(GroupMembersResponse),
+    GroupsSynced(usize),
 
-    SinglePolicy(Box<PolicyRule>),
+    /// a policy, and what adding, modifying, or removing it can affect
+    SinglePolicy(Box<PolicyRule>, Option<PolicyImpact>),
     MultiplePolicies(Vec<PolicyRule>),
+    SinglePolicySet(PolicySet),
+    MultiplePolicySets(Vec<PolicySet>),
+    SingleProposal(Box<Proposal>),
+    MultipleProposals(Vec<Proposal>),
+
+    SingleWebhook(Webhook),
+    MultipleWebhooks(Vec<Webhook>),
+    Deliveries(Vec<Delivery>),
+
+    SingleApiKey(ApiKey),
+    MultipleApiKeys(Vec<ApiKey>),
+    /// the key a call was made with
+    VerifiedApiKey(RegisteredApiKey),
+
+    CheckResult(CheckResponse),
+    SingleApproval(Box<Approval>),
+    MultipleApprovals(Vec<Approval>),
+    SingleBreakGlass(Box<BreakGlass>),
+    MultipleBreakGlasses(Vec<BreakGlass>),
+    TraceResult(TraceCheckResponse),
+    CoverageResult(CoverageReportResponse),
+    WhatIfResult(WhatIfResponse),
+    PolicyTestResults(TestPoliciesResponse),
+    ServerStats(GetServerStatsResponse),
+    ServerInfo(GetServerInfoResponse),
+    PolicyStats(GetPolicyStatsResponse),
 
-    CheckResult(Decide),
+    SyncResult(Box<SyncResponse>),
+    Watcher(broadcast::Receiver<WatchEvent>),
+    /// the epoch of the datastore, the revision streamed from, the changes made since, and the
+    /// changes to come
+    Changes(u64, u64, Vec<WatchEvent>, broadcast::Receiver<WatchEvent>),
+    Loaded(u64),
+    Applied,
+    Replicated(ReplicateResponse),
+    Reconciled(usize),
+    TransactionApplied(Vec<EntityChange>),
+    References(GetReferencesResponse),
+    Unused(FindUnusedResponse),
+    /// the updates that repaired drift between roles and groups
+    DriftRepaired(Vec<String>),
 }
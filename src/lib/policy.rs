@@ -1,59 +1,223 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use crate::actor::RegisteredActor;
+use crate::attribute::{AttributeMap, FastMap, FastSet};
+use crate::intern::intern;
+use crate::latency::PolicyTimings;
+use crate::policyset::{members, RegisteredPolicySet};
 use crate::proto::policies as protos;
+use crate::quota::EvalBudget;
+use crate::risk::RISK_ATTRIBUTE;
+use crate::target::RegisteredTarget;
+use crate::timestamps::Timestamps;
+use crate::velocity::{Velocity, MAX_WINDOW};
+use crate::wasm::WasmModules;
+
+/// A string in lowercase, only copied if it wasn't already
+pub(crate) fn lowercase(text: &str) -> Cow<'_, str> {
+    match text.bytes().any(|b| b.is_ascii_uppercase()) {
+        true => Cow::Owned(text.to_ascii_lowercase()),
+        false => Cow::Borrowed(text),
+    }
+}
 
 /// A string comparison check
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub(crate) enum StringCheck {
-    // check if string equals one of these values
+#[non_exhaustive]
+pub enum StringCheck {
+    /// check if string equals one of these values
     OneOf(Vec<String>),
-    // check if string is not equal to one of these values
+    /// check if string is not equal to one of these values
     NotOneOf(Vec<String>),
 }
 impl StringCheck {
-    // check a string value against this string check
+    /// check a string value against this string check
     pub fn check(&self, val: &str) -> bool {
         match self {
             StringCheck::OneOf(check_val) => check_val.iter().any(|v| v == val),
             StringCheck::NotOneOf(check_val) => !check_val.iter().any(|v| v == val),
         }
     }
+
+    /// whether a value is listed, ignoring case, be it included or excluded
+    pub fn lists(&self, val: &str) -> bool {
+        match self {
+            StringCheck::OneOf(vals) | StringCheck::NotOneOf(vals) => {
+                vals.iter().any(|v| v.eq_ignore_ascii_case(val))
+            }
+        }
+    }
+
+    /// check an action; values can also name a group that includes the action or be `*`
+    pub(crate) fn check_action(&self, action: &TargetAction) -> bool {
+        let matches = |v: &String| {
+            v == "*"
+                || v.eq_ignore_ascii_case(&action.name)
+                || action.groups.contains(&*lowercase(v))
+        };
+        match self {
+            StringCheck::OneOf(check_val) => check_val.iter().any(matches),
+            StringCheck::NotOneOf(check_val) => !check_val.iter().any(matches),
+        }
+    }
+}
+
+impl Display for StringCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StringCheck::OneOf(vals) => write!(f, "is one of [{}]", vals.join(", ")),
+            StringCheck::NotOneOf(vals) => write!(f, "is not one of [{}]", vals.join(", ")),
+        }
+    }
+}
+
+/// The action of a request, in lowercase, along with the target's action groups that include it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TargetAction {
+    pub name: String,
+    pub groups: HashSet<String>,
+}
+impl TargetAction {
+    /// an action that is not part of any group
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_ascii_lowercase(),
+            groups: HashSet::new(),
+        }
+    }
+}
+
+impl Display for TargetAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.groups.is_empty() {
+            return write!(f, "{}", self.name);
+        }
+
+        let mut groups: Vec<&str> = self.groups.iter().map(String::as_str).collect();
+        groups.sort_unstable();
+        write!(f, "{} (in {})", self.name, groups.join(", "))
+    }
 }
 
 /// A key value check
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub(crate) enum KvCheck {
-    // check if a particular key has one of the given values
+#[non_exhaustive]
+pub enum KvCheck {
+    /// check if a particular key has one of the given values
     Has(String, Vec<String>),
-    // check if a particular key does not have one of the given values
+    /// check if a particular key does not have one of the given values
     HasNot(String, Vec<String>),
+    /// check if a particular key is set with at least one value
+    Exists(String),
+    /// check if a particular key is unset or has no values
+    NotExists(String),
+    /// check if a particular key has at least this many values
+    CountAtLeast(String, usize),
+    /// check if a particular key has every one of the given values
+    ContainsAll(String, Vec<String>),
 }
 impl KvCheck {
-    // check a map of attrib/vals for a match
-    pub fn check(&self, attr_map: &HashMap<String, HashSet<String>>) -> bool {
+    /// check a map of attrib/vals for a match
+    pub fn check(&self, attr_map: &AttributeMap) -> bool {
         match self {
             KvCheck::Has(key, vals) => {
-                if !attr_map.contains_key(key) {
+                if !attr_map.contains_key(key.as_str()) {
                     false
-                } else if let Some(attr_vals) = attr_map.get(key) {
-                    vals.iter().any(|check_val| attr_vals.contains(check_val))
+                } else if let Some(attr_vals) = attr_map.get(key.as_str()) {
+                    vals.iter()
+                        .any(|check_val| attr_vals.contains(check_val.as_str()))
                 } else {
                     false
                 }
             }
             KvCheck::HasNot(key, vals) => {
-                if !attr_map.contains_key(key) {
+                if !attr_map.contains_key(key.as_str()) {
                     true
-                } else if let Some(attr_vals) = attr_map.get(key) {
-                    !vals.iter().any(|check_val| attr_vals.contains(check_val))
+                } else if let Some(attr_vals) = attr_map.get(key.as_str()) {
+                    !vals
+                        .iter()
+                        .any(|check_val| attr_vals.contains(check_val.as_str()))
                 } else {
                     true
                 }
             }
+            KvCheck::Exists(key) => attr_map
+                .get(key.as_str())
+                .is_some_and(|vals| !vals.is_empty()),
+            KvCheck::NotExists(key) => attr_map
+                .get(key.as_str())
+                .is_none_or(|vals| vals.is_empty()),
+            KvCheck::CountAtLeast(key, count) => {
+                attr_map.get(key.as_str()).map_or(0, |vals| vals.len()) >= *count
+            }
+            KvCheck::ContainsAll(key, vals) => match attr_map.get(key.as_str()) {
+                Some(attr_vals) => vals
+                    .iter()
+                    .all(|check_val| attr_vals.contains(check_val.as_str())),
+                None => vals.is_empty(),
+            },
+        }
+    }
+
+    /// whether the check names a value of a key, ignoring case
+    pub fn lists(&self, key: &str, val: &str) -> bool {
+        match self {
+            KvCheck::Has(k, vals) | KvCheck::HasNot(k, vals) | KvCheck::ContainsAll(k, vals) => {
+                k == key && vals.iter().any(|v| v.eq_ignore_ascii_case(val))
+            }
+            KvCheck::Exists(_) | KvCheck::NotExists(_) | KvCheck::CountAtLeast(..) => false,
+        }
+    }
+
+    /// replace a value of a key the check names, ignoring case
+    pub fn rename(&mut self, key: &str, from: &str, to: &str) {
+        match self {
+            KvCheck::Has(k, vals) | KvCheck::HasNot(k, vals) | KvCheck::ContainsAll(k, vals)
+                if k == key =>
+            {
+                for v in vals.iter_mut().filter(|v| v.eq_ignore_ascii_case(from)) {
+                    *v = to.to_string();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// the attribute key this check examines
+    pub fn key(&self) -> &str {
+        match self {
+            KvCheck::Has(key, _)
+            | KvCheck::HasNot(key, _)
+            | KvCheck::Exists(key)
+            | KvCheck::NotExists(key)
+            | KvCheck::CountAtLeast(key, _)
+            | KvCheck::ContainsAll(key, _) => key,
+        }
+    }
+}
+
+impl Display for KvCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KvCheck::Has(key, vals) => write!(f, "{} has one of [{}]", key, vals.join(", ")),
+            KvCheck::HasNot(key, vals) => write!(f, "{} has none of [{}]", key, vals.join(", ")),
+            KvCheck::Exists(key) => write!(f, "{} is set", key),
+            KvCheck::NotExists(key) => write!(f, "{} is not set", key),
+            KvCheck::CountAtLeast(key, count) => {
+                write!(f, "{} has at least {} values", key, count)
+            }
+            KvCheck::ContainsAll(key, vals) => {
+                write!(f, "{} has all of [{}]", key, vals.join(", "))
+            }
         }
     }
 }
@@ -61,8 +225,12 @@ impl KvCheck {
 impl From<protos::KvCheck> for KvCheck {
     fn from(kv: protos::KvCheck) -> Self {
         match kv.op() {
-            protos::Set::Has => Self::Has(kv.key, kv.vals),
-            protos::Set::HasNot => Self::HasNot(kv.key, kv.vals),
+            protos::Kv::Has => Self::Has(kv.key, kv.vals),
+            protos::Kv::HasNot => Self::HasNot(kv.key, kv.vals),
+            protos::Kv::Exists => Self::Exists(kv.key),
+            protos::Kv::NotExists => Self::NotExists(kv.key),
+            protos::Kv::CountAtLeast => Self::CountAtLeast(kv.key, kv.count.unwrap_or(1) as usize),
+            protos::Kv::ContainsAll => Self::ContainsAll(kv.key, kv.vals),
         }
     }
 }
@@ -71,13 +239,39 @@ impl From<KvCheck> for protos::KvCheck {
         match kv {
             KvCheck::Has(key, vals) => Self {
                 key,
-                op: protos::Set::Has.into(),
+                op: protos::Kv::Has.into(),
                 vals,
+                count: None,
             },
             KvCheck::HasNot(key, vals) => Self {
                 key,
-                op: protos::Set::HasNot.into(),
+                op: protos::Kv::HasNot.into(),
+                vals,
+                count: None,
+            },
+            KvCheck::Exists(key) => Self {
+                key,
+                op: protos::Kv::Exists.into(),
+                vals: vec![],
+                count: None,
+            },
+            KvCheck::NotExists(key) => Self {
+                key,
+                op: protos::Kv::NotExists.into(),
+                vals: vec![],
+                count: None,
+            },
+            KvCheck::CountAtLeast(key, count) => Self {
+                key,
+                op: protos::Kv::CountAtLeast.into(),
+                vals: vec![],
+                count: Some(count as u32),
+            },
+            KvCheck::ContainsAll(key, vals) => Self {
+                key,
+                op: protos::Kv::ContainsAll.into(),
                 vals,
+                count: None,
             },
         }
     }
@@ -85,12 +279,13 @@ impl From<KvCheck> for protos::KvCheck {
 
 /// A numerical check
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub(crate) enum NumberCheck {
-    // check if number equals this value
+#[non_exhaustive]
+pub enum NumberCheck {
+    /// check if number equals this value
     Equals(i32),
-    // check if number is less than this value
+    /// check if number is less than this value
     LessThan(i32),
-    // check if number is more than this value
+    /// check if number is more than this value
     MoreThan(i32),
 }
 impl NumberCheck {
@@ -104,6 +299,16 @@ impl NumberCheck {
     }
 }
 
+impl Display for NumberCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NumberCheck::Equals(val) => write!(f, "equals {}", val),
+            NumberCheck::LessThan(val) => write!(f, "is less than {}", val),
+            NumberCheck::MoreThan(val) => write!(f, "is more than {}", val),
+        }
+    }
+}
+
 impl From<protos::NumberCheck> for NumberCheck {
     fn from(nc: protos::NumberCheck) -> Self {
         match nc.op() {
@@ -134,19 +339,56 @@ impl From<NumberCheck> for protos::NumberCheck {
 
 /// represents the decision of a rule
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub(crate) enum Decide {
-    // rule fails
+#[non_exhaustive]
+pub enum Decide {
+    /// rule fails
     Deny,
-    // rule passes
+    /// rule passes
     Allow,
+    /// rule passes once this many approvers co-sign the check
+    AllowWithApproval(u32),
+    /// no rule applies; only decided by checks
+    NotApplicable,
+    /// a rule that applies couldn't be evaluated; only decided by checks
+    Indeterminate,
 }
 
-/// convert from proto to enum
+impl Decide {
+    /// the stricter of two decisions: DENY, then INDETERMINATE, then NOT_APPLICABLE, then ALLOW
+    /// with the most approvals, then ALLOW
+    pub fn strictest(self, other: Decide) -> Decide {
+        match (self, other) {
+            (Decide::Deny, _) | (_, Decide::Deny) => Decide::Deny,
+            (Decide::Indeterminate, _) | (_, Decide::Indeterminate) => Decide::Indeterminate,
+            (Decide::NotApplicable, _) | (_, Decide::NotApplicable) => Decide::NotApplicable,
+            (Decide::AllowWithApproval(n), Decide::AllowWithApproval(m)) => {
+                Decide::AllowWithApproval(n.max(m))
+            }
+            (approval @ Decide::AllowWithApproval(_), Decide::Allow)
+            | (Decide::Allow, approval @ Decide::AllowWithApproval(_)) => approval,
+            (Decide::Allow, Decide::Allow) => Decide::Allow,
+        }
+    }
+
+    /// whether the decision refuses access, explicitly or not
+    pub fn denies(&self) -> bool {
+        matches!(
+            self,
+            Decide::Deny | Decide::NotApplicable | Decide::Indeterminate
+        )
+    }
+}
+
+/// convert from proto to enum; a rule can't decide PENDING, NOT_APPLICABLE, or INDETERMINATE,
+/// so validate it first
 impl From<protos::Decide> for Decide {
     fn from(d: protos::Decide) -> Self {
         match d {
-            protos::Decide::Deny => Self::Deny,
+            protos::Decide::Deny | protos::Decide::Pending => Self::Deny,
             protos::Decide::Allow => Self::Allow,
+            protos::Decide::AllowWithApproval => Self::AllowWithApproval(1),
+            protos::Decide::NotApplicable => Self::NotApplicable,
+            protos::Decide::Indeterminate => Self::Indeterminate,
         }
     }
 }
@@ -155,6 +397,9 @@ impl From<Decide> for protos::Decide {
         match d {
             Decide::Deny => Self::Deny,
             Decide::Allow => Self::Allow,
+            Decide::AllowWithApproval(_) => Self::AllowWithApproval,
+            Decide::NotApplicable => Self::NotApplicable,
+            Decide::Indeterminate => Self::Indeterminate,
         }
     }
 }
@@ -163,6 +408,39 @@ impl Display for protos::Decide {
         match self {
             protos::Decide::Deny => write!(f, "DENY"),
             protos::Decide::Allow => write!(f, "ALLOW"),
+            protos::Decide::AllowWithApproval => write!(f, "ALLOW_WITH_APPROVAL"),
+            protos::Decide::Pending => write!(f, "PENDING"),
+            protos::Decide::NotApplicable => write!(f, "NOT_APPLICABLE"),
+            protos::Decide::Indeterminate => write!(f, "INDETERMINATE"),
+        }
+    }
+}
+
+/// represents whether the decision of a rule is enforced
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Mode {
+    /// decision is enforced
+    #[default]
+    Enforce,
+    /// decision is only logged
+    Shadow,
+}
+
+/// convert from proto to enum
+impl From<protos::Mode> for Mode {
+    fn from(m: protos::Mode) -> Self {
+        match m {
+            protos::Mode::Enforce => Self::Enforce,
+            protos::Mode::Shadow => Self::Shadow,
+        }
+    }
+}
+impl From<Mode> for protos::Mode {
+    fn from(m: Mode) -> Self {
+        match m {
+            Mode::Enforce => Self::Enforce,
+            Mode::Shadow => Self::Shadow,
         }
     }
 }
@@ -193,15 +471,20 @@ impl From<StringCheck> for protos::StringCheck {
 
 /// The actor match check in a rule
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub(crate) struct ActorCheck {
+#[non_exhaustive]
+pub struct ActorCheck {
+    /// check on the actor's name
     pub name: Option<StringCheck>,
+    /// check on the actor's type
     pub typestr: Option<StringCheck>,
+    /// checks on the actor's attributes, which must all pass
     pub attributes: Vec<KvCheck>,
+    /// check on the bucket the actor falls into
     pub bucket: Option<NumberCheck>,
 }
 impl ActorCheck {
     /// perform a check against a potential actor
-    pub fn check(&self, actor: &RegisteredActor) -> bool {
+    pub(crate) fn check(&self, actor: &RegisteredActor) -> bool {
         if let Some(ref name_check) = self.name {
             if !name_check.check(&actor.name) {
                 // name does not match
@@ -209,213 +492,1610 @@ impl ActorCheck {
             }
         }
 
-        if let Some(ref type_check) = self.typestr {
-            if !type_check.check(&actor.typestr) {
-                // type does not match
-                return false;
-            }
+        if let Some(ref type_check) = self.typestr {
+            if !type_check.check(&actor.typestr) {
+                // type does not match
+                return false;
+            }
+        }
+
+        if self.attributes.iter().any(|a| !a.check(&actor.attributes)) {
+            return false;
+        }
+
+        if let Some(ref bucket_check) = self.bucket {
+            if !bucket_check.check(actor.bucket().into()) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// perform every check against an actor, recording the outcome of each
+    pub(crate) fn trace(&self, actor: &RegisteredActor) -> Vec<protos::CheckTrace> {
+        let mut traces = Vec::new();
+
+        if let Some(ref name_check) = self.name {
+            traces.push(trace(
+                format!("actor name {name_check}"),
+                actor.name.clone(),
+                name_check.check(&actor.name),
+            ));
+        }
+
+        if let Some(ref type_check) = self.typestr {
+            traces.push(trace(
+                format!("actor type {type_check}"),
+                actor.typestr.to_string(),
+                type_check.check(&actor.typestr),
+            ));
+        }
+
+        for attr_check in &self.attributes {
+            traces.push(trace(
+                format!("actor attribute {attr_check}"),
+                attribute_values(&actor.attributes, attr_check.key()),
+                attr_check.check(&actor.attributes),
+            ));
+        }
+
+        if let Some(ref bucket_check) = self.bucket {
+            let bucket = actor.bucket();
+            traces.push(trace(
+                format!("actor bucket {bucket_check}"),
+                bucket.to_string(),
+                bucket_check.check(bucket.into()),
+            ));
+        }
+
+        traces
+    }
+}
+
+/// convert the protobuf version to our version
+impl From<protos::ActorCheck> for ActorCheck {
+    fn from(ec: protos::ActorCheck) -> Self {
+        Self {
+            name: ec.name.map(StringCheck::from),
+            typestr: ec.typestr.map(StringCheck::from),
+            attributes: ec.attributes.into_iter().map(KvCheck::from).collect(),
+            bucket: ec.bucket.map(NumberCheck::from),
+        }
+    }
+}
+impl From<ActorCheck> for protos::ActorCheck {
+    fn from(ec: ActorCheck) -> Self {
+        Self {
+            name: ec.name.map(protos::StringCheck::from),
+            typestr: ec.typestr.map(protos::StringCheck::from),
+            attributes: ec
+                .attributes
+                .into_iter()
+                .map(protos::KvCheck::from)
+                .collect(),
+            bucket: ec.bucket.map(protos::NumberCheck::from),
+        }
+    }
+}
+
+/// The check to see if the requested target/action match this policy rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct TargetCheck {
+    /// check on the target's name
+    pub name: Option<StringCheck>,
+    /// check on the target's type
+    pub typestr: Option<StringCheck>,
+    /// checks on the target's attributes, which must all pass
+    pub attributes: Vec<KvCheck>,
+    /// attributes the target must share a value of with the actor
+    pub match_in_actor: Vec<String>,
+    /// attributes the target must share a value of with the environment
+    pub match_in_env: Vec<String>,
+    /// check on the action; values can also name an action group or be `*`
+    pub action: Option<StringCheck>,
+    /// actions, or action groups, the check never passes, whatever the action check says
+    #[serde(default)]
+    pub action_except: Vec<String>,
+}
+impl TargetCheck {
+    /// whether an action is excluded, by name or by one of its groups
+    fn excludes_action(&self, action: &TargetAction) -> bool {
+        self.action_except
+            .iter()
+            .any(|except| *except == action.name || action.groups.contains(except))
+    }
+
+    /// see if one of our attribute values exists in another set of attribute values
+    fn check_attr_match(
+        &self,
+        attr_to_check: &str,
+        our_map: &AttributeMap,
+        other_map: &AttributeMap,
+    ) -> bool {
+        let Some(our_attr_vals) = our_map.get(attr_to_check) else {
+            // the target itself doesn't contain the attribute we are supposed to match with the actor
+            return false;
+        };
+
+        let Some(other_attr_vals) = other_map.get(attr_to_check) else {
+            // the actor doesn't contain the attribute we are supposed to check
+            return false;
+        };
+
+        // we need to find a single value in the target's attribute values that is also in the
+        // actor's attribute values
+        !our_attr_vals.is_disjoint(other_attr_vals)
+    }
+
+    /// perform a check against a potential actor
+    pub(crate) fn check(
+        &self,
+        target_name: &str,
+        target_type: &str,
+        target_attributes: &AttributeMap,
+        target_action: &TargetAction,
+        actor_attributes: &AttributeMap,
+        env_attributes: &AttributeMap,
+    ) -> bool {
+        if let Some(ref name_check) = self.name {
+            if !name_check.check(target_name) {
+                // name does not match
+                return false;
+            }
+        }
+
+        if let Some(ref type_check) = self.typestr {
+            if !type_check.check(target_type) {
+                // type does not match
+                return false;
+            }
+        }
+
+        if self.attributes.iter().any(|a| !a.check(target_attributes)) {
+            // one or more attributes do not match
+            return false;
+        }
+
+        // make sure every attribute that's supposed to match with the actor does match
+        for attr_to_check in &self.match_in_actor {
+            if !self.check_attr_match(attr_to_check, target_attributes, actor_attributes) {
+                // this attribute did not match between the target and the actor
+                return false;
+            }
+        }
+
+        // make sure every attribute that's supposed to match with the environment does match
+        for attr_to_check in &self.match_in_env {
+            if !self.check_attr_match(attr_to_check, target_attributes, env_attributes) {
+                // this attribute did not match between the target and the environment
+                return false;
+            }
+        }
+
+        if let Some(action_check) = &self.action {
+            if !action_check.check_action(target_action) {
+                // action does not match
+                return false;
+            }
+        }
+
+        if self.excludes_action(target_action) {
+            return false;
+        }
+
+        true
+    }
+
+    /// perform every check against a target, recording the outcome of each
+    pub(crate) fn trace(
+        &self,
+        target_name: &str,
+        target_type: &str,
+        target_attributes: &AttributeMap,
+        target_action: &TargetAction,
+        actor_attributes: &AttributeMap,
+        env_attributes: &AttributeMap,
+    ) -> Vec<protos::CheckTrace> {
+        let mut traces = Vec::new();
+
+        if let Some(ref name_check) = self.name {
+            traces.push(trace(
+                format!("target name {name_check}"),
+                target_name.to_string(),
+                name_check.check(target_name),
+            ));
+        }
+
+        if let Some(ref type_check) = self.typestr {
+            traces.push(trace(
+                format!("target type {type_check}"),
+                target_type.to_string(),
+                type_check.check(target_type),
+            ));
+        }
+
+        for attr_check in &self.attributes {
+            traces.push(trace(
+                format!("target attribute {attr_check}"),
+                attribute_values(target_attributes, attr_check.key()),
+                attr_check.check(target_attributes),
+            ));
+        }
+
+        for attr_to_check in &self.match_in_actor {
+            traces.push(trace(
+                format!("target attribute {attr_to_check} matches actor"),
+                format!(
+                    "target: {}  actor: {}",
+                    attribute_values(target_attributes, attr_to_check),
+                    attribute_values(actor_attributes, attr_to_check)
+                ),
+                self.check_attr_match(attr_to_check, target_attributes, actor_attributes),
+            ));
+        }
+
+        for attr_to_check in &self.match_in_env {
+            traces.push(trace(
+                format!("target attribute {attr_to_check} matches environment"),
+                format!(
+                    "target: {}  env: {}",
+                    attribute_values(target_attributes, attr_to_check),
+                    attribute_values(env_attributes, attr_to_check)
+                ),
+                self.check_attr_match(attr_to_check, target_attributes, env_attributes),
+            ));
+        }
+
+        if let Some(action_check) = &self.action {
+            traces.push(trace(
+                format!("target action {action_check}"),
+                target_action.to_string(),
+                action_check.check_action(target_action),
+            ));
+        }
+
+        if !self.action_except.is_empty() {
+            traces.push(trace(
+                format!("target action is not [{}]", self.action_except.join(", ")),
+                target_action.to_string(),
+                !self.excludes_action(target_action),
+            ));
+        }
+
+        traces
+    }
+}
+
+impl From<protos::TargetCheck> for TargetCheck {
+    fn from(tc: protos::TargetCheck) -> Self {
+        Self {
+            name: tc.name.map(StringCheck::from),
+            typestr: tc.typestr.map(StringCheck::from),
+            attributes: tc.attributes.into_iter().map(KvCheck::from).collect(),
+            match_in_actor: tc.match_in_actor,
+            match_in_env: tc.match_in_env,
+            action: tc.action.map(StringCheck::from),
+            action_except: lowercased(tc.action_except),
+        }
+    }
+}
+impl From<TargetCheck> for protos::TargetCheck {
+    fn from(tc: TargetCheck) -> protos::TargetCheck {
+        Self {
+            name: tc.name.map(protos::StringCheck::from),
+            typestr: tc.typestr.map(protos::StringCheck::from),
+            attributes: tc
+                .attributes
+                .into_iter()
+                .map(protos::KvCheck::from)
+                .collect(),
+            match_in_actor: tc.match_in_actor,
+            match_in_env: tc.match_in_env,
+            action: tc.action.map(protos::StringCheck::from),
+            action_except: tc.action_except,
+        }
+    }
+}
+
+/// Where an attribute comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Source {
+    /// the actor's attributes
+    Actor,
+    /// the target's attributes
+    Target,
+    /// the environment attributes of the check
+    Env,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Actor => write!(f, "actor"),
+            Source::Target => write!(f, "target"),
+            Source::Env => write!(f, "env"),
+        }
+    }
+}
+
+/// An attribute of the actor, target, or environment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct AttributeRef {
+    /// where the attribute comes from
+    pub source: Source,
+    /// the attribute key
+    pub key: String,
+}
+impl AttributeRef {
+    /// an attribute of the actor, target, or environment
+    pub fn new(source: Source, key: &str) -> Self {
+        Self {
+            source,
+            key: key.to_string(),
+        }
+    }
+
+    /// pick the attributes this refers to from a request
+    fn attributes<'a>(
+        &self,
+        actor_attributes: &'a AttributeMap,
+        target_attributes: &'a AttributeMap,
+        env_attributes: &'a AttributeMap,
+    ) -> &'a AttributeMap {
+        match self.source {
+            Source::Actor => actor_attributes,
+            Source::Target => target_attributes,
+            Source::Env => env_attributes,
+        }
+    }
+}
+
+impl Display for AttributeRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.source, self.key)
+    }
+}
+
+/// How two attributes are compared
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Compare {
+    /// both attributes have exactly the same values
+    Equal,
+    /// the attributes share at least one value
+    Intersect,
+}
+
+/// A comparison between two attributes of the actor, target, or environment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CompareCheck {
+    /// the first attribute
+    pub left: AttributeRef,
+    /// the second attribute
+    pub right: AttributeRef,
+    /// how they are compared
+    pub op: Compare,
+}
+impl CompareCheck {
+    /// a comparison between two attributes
+    pub fn new(left: AttributeRef, op: Compare, right: AttributeRef) -> Self {
+        Self { left, right, op }
+    }
+
+    /// compare the attributes; fails if either is not set
+    pub fn check(
+        &self,
+        actor_attributes: &AttributeMap,
+        target_attributes: &AttributeMap,
+        env_attributes: &AttributeMap,
+    ) -> bool {
+        let left = self
+            .left
+            .attributes(actor_attributes, target_attributes, env_attributes)
+            .get(self.left.key.as_str());
+        let right = self
+            .right
+            .attributes(actor_attributes, target_attributes, env_attributes)
+            .get(self.right.key.as_str());
+
+        match (left, right) {
+            (Some(left), Some(right)) => match self.op {
+                Compare::Equal => left == right,
+                Compare::Intersect => !left.is_disjoint(right),
+            },
+            _ => false,
+        }
+    }
+}
+
+impl Display for CompareCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.op {
+            Compare::Equal => write!(f, "{} equals {}", self.left, self.right),
+            Compare::Intersect => write!(f, "{} shares a value with {}", self.left, self.right),
+        }
+    }
+}
+
+impl From<protos::AttributeRef> for AttributeRef {
+    fn from(ar: protos::AttributeRef) -> Self {
+        let source = match ar.source() {
+            protos::Source::Actor => Source::Actor,
+            protos::Source::Target => Source::Target,
+            protos::Source::Env => Source::Env,
+        };
+        Self {
+            source,
+            key: ar.key,
+        }
+    }
+}
+impl From<AttributeRef> for protos::AttributeRef {
+    fn from(ar: AttributeRef) -> Self {
+        let source = match ar.source {
+            Source::Actor => protos::Source::Actor,
+            Source::Target => protos::Source::Target,
+            Source::Env => protos::Source::Env,
+        };
+        Self {
+            source: source.into(),
+            key: ar.key,
+        }
+    }
+}
+
+impl From<protos::CompareCheck> for CompareCheck {
+    fn from(cc: protos::CompareCheck) -> Self {
+        let op = match cc.op() {
+            protos::Compare::Equal => Compare::Equal,
+            protos::Compare::Intersect => Compare::Intersect,
+        };
+        Self {
+            left: cc.left.unwrap_or_default().into(),
+            right: cc.right.unwrap_or_default().into(),
+            op,
+        }
+    }
+}
+impl From<CompareCheck> for protos::CompareCheck {
+    fn from(cc: CompareCheck) -> Self {
+        let op = match cc.op {
+            Compare::Equal => protos::Compare::Equal,
+            Compare::Intersect => protos::Compare::Intersect,
+        };
+        Self {
+            left: Some(cc.left.into()),
+            right: Some(cc.right.into()),
+            op: op.into(),
+        }
+    }
+}
+
+/// A block of IP addresses, e.g. 10.0.0.0/8; a bare address is a block of one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+impl Cidr {
+    /// see if an address is in this block
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid CIDR block {s}"))?;
+
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("Invalid CIDR block {s}"))?,
+            None => max,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+}
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// An IP address check on an attribute of the actor, target, or environment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum CidrCheck {
+    /// check if the attribute has an address in one of the blocks
+    In(AttributeRef, Vec<Cidr>),
+    /// check if the attribute has no address in any of the blocks
+    NotIn(AttributeRef, Vec<Cidr>),
+}
+impl CidrCheck {
+    /// check the attribute's addresses; values that aren't IP addresses are ignored
+    pub fn check(
+        &self,
+        actor_attributes: &AttributeMap,
+        target_attributes: &AttributeMap,
+        env_attributes: &AttributeMap,
+    ) -> bool {
+        let attr = self.attribute();
+        let blocks = match self {
+            CidrCheck::In(_, blocks) | CidrCheck::NotIn(_, blocks) => blocks,
+        };
+
+        let found = attr
+            .attributes(actor_attributes, target_attributes, env_attributes)
+            .get(attr.key.as_str())
+            .is_some_and(|vals| {
+                vals.iter()
+                    .filter_map(|val| val.trim().parse::<IpAddr>().ok())
+                    .any(|ip| blocks.iter().any(|block| block.contains(&ip)))
+            });
+
+        match self {
+            CidrCheck::In(..) => found,
+            CidrCheck::NotIn(..) => !found,
+        }
+    }
+
+    /// the attribute this check examines
+    pub fn attribute(&self) -> &AttributeRef {
+        match self {
+            CidrCheck::In(attr, _) | CidrCheck::NotIn(attr, _) => attr,
+        }
+    }
+}
+
+impl Display for CidrCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (attr, verb, blocks) = match self {
+            CidrCheck::In(attr, blocks) => (attr, "is in", blocks),
+            CidrCheck::NotIn(attr, blocks) => (attr, "is not in", blocks),
+        };
+        let blocks: Vec<String> = blocks.iter().map(Cidr::to_string).collect();
+        write!(f, "{} {} [{}]", attr, verb, blocks.join(", "))
+    }
+}
+
+/// convert the proto to enum; invalid blocks are dropped, so validate them first
+impl From<protos::CidrCheck> for CidrCheck {
+    fn from(cc: protos::CidrCheck) -> Self {
+        let attr = cc.attribute.clone().unwrap_or_default().into();
+        let blocks = cc
+            .blocks
+            .iter()
+            .filter_map(|block| block.parse().ok())
+            .collect();
+        match cc.op() {
+            protos::Set::Has => Self::In(attr, blocks),
+            protos::Set::HasNot => Self::NotIn(attr, blocks),
+        }
+    }
+}
+impl From<CidrCheck> for protos::CidrCheck {
+    fn from(cc: CidrCheck) -> Self {
+        let (op, attr, blocks) = match cc {
+            CidrCheck::In(attr, blocks) => (protos::Set::Has, attr, blocks),
+            CidrCheck::NotIn(attr, blocks) => (protos::Set::HasNot, attr, blocks),
+        };
+        Self {
+            attribute: Some(attr.into()),
+            op: op.into(),
+            blocks: blocks.into_iter().map(String::from).collect(),
+        }
+    }
+}
+
+/// A count of the actor's recent allowed uses of actions, which passes once it reaches a limit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct RateCheck {
+    /// the actions counted; the action being checked if empty
+    pub actions: Vec<String>,
+    /// how many uses make the check pass
+    pub limit: u32,
+    /// how far back uses are counted, in seconds
+    pub window: u32,
+}
+impl RateCheck {
+    /// a count of uses of the actions (or the action being checked, if none) within a window
+    pub fn new(actions: &[&str], limit: u32, window: u32) -> Self {
+        Self {
+            actions: lowercased(actions.iter().map(|a| a.to_string()).collect()),
+            limit,
+            window,
+        }
+    }
+
+    /// whether uses of an action count towards this check
+    pub fn counts(&self, action: &str) -> bool {
+        self.actions.is_empty() || self.actions.iter().any(|a| a.eq_ignore_ascii_case(action))
+    }
+
+    /// how many times the actor used the counted actions in the window
+    pub(crate) fn uses(&self, actor: &RegisteredActor, action: &str, rates: &Velocity) -> u64 {
+        let actions: Vec<&str> = match self.actions.is_empty() {
+            true => vec![action],
+            false => self.actions.iter().map(String::as_str).collect(),
+        };
+        rates.count(actor, &actions, self.window)
+    }
+
+    /// check the actor's uses against the limit
+    pub(crate) fn check(&self, actor: &RegisteredActor, action: &str, rates: &Velocity) -> bool {
+        self.uses(actor, action, rates) >= u64::from(self.limit)
+    }
+
+    /// make sure the check can be made
+    pub fn validate(&self) -> Result<(), String> {
+        if self.limit == 0 {
+            return Err(String::from("A rate check needs a limit of at least 1"));
+        }
+        if self.window == 0 || self.window > MAX_WINDOW {
+            return Err(format!(
+                "A rate check window must be between 1 and {MAX_WINDOW} seconds"
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Display for RateCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let actions = match self.actions.is_empty() {
+            true => String::from("this action"),
+            false => format!("[{}]", self.actions.join(", ")),
+        };
+        write!(
+            f,
+            "uses of {} reach {} in {}s",
+            actions, self.limit, self.window
+        )
+    }
+}
+
+impl From<protos::RateCheck> for RateCheck {
+    fn from(rc: protos::RateCheck) -> Self {
+        Self {
+            actions: lowercased(rc.actions),
+            limit: rc.limit,
+            window: rc.window_seconds,
+        }
+    }
+}
+impl From<RateCheck> for protos::RateCheck {
+    fn from(rc: RateCheck) -> Self {
+        Self {
+            actions: rc.actions,
+            limit: rc.limit,
+            window_seconds: rc.window,
+        }
+    }
+}
+
+/// whether a name check and a type check name an entity: the name is listed and the type isn't
+/// ruled out
+fn names(
+    name_check: &Option<StringCheck>,
+    type_check: &Option<StringCheck>,
+    typestr: &str,
+    name: &str,
+) -> bool {
+    let named = name_check.as_ref().is_some_and(|c| c.lists(name));
+    let typed = match type_check {
+        Some(c @ StringCheck::OneOf(_)) => c.lists(typestr),
+        Some(c @ StringCheck::NotOneOf(_)) => !c.lists(typestr),
+        None => true,
+    };
+
+    named && typed
+}
+
+/// A policy rule registered with Gatehouse
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct RegisteredPolicyRule {
+    /// the name of this policy rule
+    pub name: String,
+    /// the optional human description
+    pub desc: Option<String>,
+
+    /// determine if actor matches or should match all actors if None
+    pub actor_check: Option<ActorCheck>,
+
+    /// list of environment attributes to check
+    pub env_attributes: Vec<KvCheck>,
+
+    /// determine if rule applies to target
+    pub target_check: Option<TargetCheck>,
+
+    /// The decision to make if this rule matches
+    pub decision: Decide,
+
+    /// Whether the decision is enforced or only logged
+    #[serde(default)]
+    pub mode: Mode,
+
+    /// optional WASM module whose condition must also match
+    #[serde(default)]
+    pub wasm_module: Option<String>,
+
+    /// comparisons between attributes of the actor, target, and environment
+    #[serde(default)]
+    pub compare_checks: Vec<CompareCheck>,
+
+    /// IP address checks on attributes of the actor, target, and environment
+    #[serde(default)]
+    pub cidr_checks: Vec<CidrCheck>,
+
+    /// the target types the rule is limited to, or every type if empty
+    #[serde(default)]
+    pub target_types: Vec<String>,
+
+    /// counts of the actor's recent uses of actions
+    #[serde(default)]
+    pub rate_checks: Vec<RateCheck>,
+
+    /// check on the risk score of the request
+    #[serde(default)]
+    pub risk: Option<NumberCheck>,
+
+    /// labels to find the rule by
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// when the rule was added and last changed
+    #[serde(flatten)]
+    pub timestamps: Timestamps,
+}
+
+impl RegisteredPolicyRule {
+    /// whether the rule's target check names a target: it lists the target's name and doesn't
+    /// rule out its type
+    pub fn refers_to_target(&self, typestr: &str, name: &str) -> bool {
+        match self.target_check {
+            Some(ref check) => {
+                self.applies_to_type(typestr) && names(&check.name, &check.typestr, typestr, name)
+            }
+            None => false,
+        }
+    }
+
+    /// whether the rule's actor check names an actor, the same way targets are named
+    pub fn refers_to_actor(&self, typestr: &str, name: &str) -> bool {
+        match self.actor_check {
+            Some(ref check) => names(&check.name, &check.typestr, typestr, name),
+            None => false,
+        }
+    }
+
+    /// whether the rule checks an actor attribute for a value, e.g. `member-of` for a group
+    pub fn refers_to_attribute(&self, key: &str, val: &str) -> bool {
+        self.actor_check
+            .as_ref()
+            .is_some_and(|check| check.attributes.iter().any(|kv| kv.lists(key, val)))
+    }
+
+    /// the rule checking for a renamed value of an actor attribute instead, e.g. a renamed group
+    pub fn with_attribute_renamed(&self, key: &str, from: &str, to: &str) -> Self {
+        let mut rule = self.clone();
+        if let Some(ref mut check) = rule.actor_check {
+            for kv in check.attributes.iter_mut() {
+                kv.rename(key, from, to);
+            }
+        }
+        rule
+    }
+
+    /// the rule without its reference to a target, or None if the target was all it applied to
+    pub fn without_target(&self, name: &str) -> Option<Self> {
+        let mut rule = self.clone();
+        let check = match rule.target_check {
+            Some(ref mut check) => check,
+            None => return Some(rule),
+        };
+
+        match check.name {
+            Some(StringCheck::OneOf(ref mut vals)) => {
+                vals.retain(|v| !v.eq_ignore_ascii_case(name));
+                if vals.is_empty() {
+                    return None;
+                }
+            }
+            Some(StringCheck::NotOneOf(ref mut vals)) => {
+                vals.retain(|v| !v.eq_ignore_ascii_case(name));
+                // excluding nothing is the same as not checking
+                if vals.is_empty() {
+                    check.name = None;
+                }
+            }
+            None => {}
+        }
+
+        Some(rule)
+    }
+
+    /// whether the rule can apply to targets of a type at all
+    pub fn applies_to_type(&self, target_type: &str) -> bool {
+        self.target_types.is_empty()
+            || self
+                .target_types
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(target_type))
+    }
+
+    /// whether the rule can apply to targets of a type, by its target types and its check of the
+    /// target's type
+    pub fn can_target_type(&self, target_type: &str) -> bool {
+        let checked = match self.target_check.as_ref().and_then(|c| c.typestr.as_ref()) {
+            Some(c @ StringCheck::OneOf(_)) => c.lists(target_type),
+            Some(c @ StringCheck::NotOneOf(_)) => !c.lists(target_type),
+            None => true,
+        };
+        self.applies_to_type(target_type) && checked
+    }
+
+    /// whether the rule can apply to an actor, judging only by its actor check
+    pub(crate) fn can_match_actor(&self, actor: &RegisteredActor) -> bool {
+        self.actor_check
+            .as_ref()
+            .is_none_or(|check| check.check(actor))
+    }
+
+    /// whether the rule can apply to a target, judging only by its target types and its checks of
+    /// the target's name, type, and attributes
+    pub(crate) fn can_match_target(&self, target: &RegisteredTarget) -> bool {
+        self.applies_to_type(&target.typestr)
+            && self.target_check.as_ref().is_none_or(|check| {
+                check.name.as_ref().is_none_or(|c| c.check(&target.name))
+                    && check
+                        .typestr
+                        .as_ref()
+                        .is_none_or(|c| c.check(&target.typestr))
+                    && check.attributes.iter().all(|a| a.check(&target.attributes))
+            })
+    }
+
+    /// whether every word of a search is in the rule's name or description, ignoring case
+    pub fn mentions(&self, search: &str) -> bool {
+        let text =
+            format!("{} {}", self.name, self.desc.as_deref().unwrap_or_default()).to_lowercase();
+        search
+            .split_whitespace()
+            .all(|word| text.contains(&word.to_lowercase()))
+    }
+
+    /// see if this rule applies to a request; if it does, its decision should be taken
+    ///
+    /// If the rule can't be evaluated, DENY rules apply and ALLOW rules don't, so a broken rule
+    /// never grants access.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn matches(
+        &self,
+        actor: &RegisteredActor,
+        env_attributes: &AttributeMap,
+        target_name: &str,
+        target_type: &str,
+        target_attributes: &AttributeMap,
+        target_action: &TargetAction,
+        wasm: &WasmModules,
+        rates: &Velocity,
+    ) -> bool {
+        match self.evaluate(
+            actor,
+            env_attributes,
+            target_name,
+            target_type,
+            target_attributes,
+            target_action,
+            wasm,
+            rates,
+        ) {
+            Ok(matched) => matched,
+            Err(err) => {
+                eprintln!("{err}");
+                self.decision == Decide::Deny
+            }
+        }
+    }
+
+    /// the rule's decision on a request if it applies, or INDETERMINATE if it can't be evaluated
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn decision_for(
+        &self,
+        actor: &RegisteredActor,
+        env_attributes: &AttributeMap,
+        target_name: &str,
+        target_type: &str,
+        target_attributes: &AttributeMap,
+        target_action: &TargetAction,
+        wasm: &WasmModules,
+        rates: &Velocity,
+    ) -> Option<Decide> {
+        match self.evaluate(
+            actor,
+            env_attributes,
+            target_name,
+            target_type,
+            target_attributes,
+            target_action,
+            wasm,
+            rates,
+        ) {
+            Ok(true) => Some(self.decision.clone()),
+            Ok(false) => None,
+            Err(err) => {
+                eprintln!("{err}");
+                Some(Decide::Indeterminate)
+            }
+        }
+    }
+
+    /// see if this rule applies to a request, or why it couldn't be evaluated
+    #[allow(clippy::too_many_arguments)]
+    fn evaluate(
+        &self,
+        actor: &RegisteredActor,
+        env_attributes: &AttributeMap,
+        target_name: &str,
+        target_type: &str,
+        target_attributes: &AttributeMap,
+        target_action: &TargetAction,
+        wasm: &WasmModules,
+        rates: &Velocity,
+    ) -> Result<bool, String> {
+        if !self.applies_to_type(target_type) {
+            return Ok(false);
+        }
+
+        if let Some(ref actor_check) = self.actor_check {
+            if !actor_check.check(actor) {
+                // this actor check does not apply to this request
+                return Ok(false);
+            }
+        }
+
+        // perform environment check
+        if !self
+            .env_attributes
+            .iter()
+            .all(|ea| ea.check(env_attributes))
+        {
+            // these environment checks do not match
+            return Ok(false);
+        }
+
+        if let Some(ref target_check) = self.target_check {
+            if !target_check.check(
+                target_name,
+                target_type,
+                target_attributes,
+                target_action,
+                &actor.attributes,
+                env_attributes,
+            ) {
+                // this target does not match
+                return Ok(false);
+            }
+        }
+
+        if !self
+            .compare_checks
+            .iter()
+            .all(|cc| cc.check(&actor.attributes, target_attributes, env_attributes))
+        {
+            // the compared attributes do not match
+            return Ok(false);
+        }
+
+        if !self
+            .cidr_checks
+            .iter()
+            .all(|cc| cc.check(&actor.attributes, target_attributes, env_attributes))
+        {
+            // the addresses are not where they need to be
+            return Ok(false);
+        }
+
+        if !self
+            .rate_checks
+            .iter()
+            .all(|rc| rc.check(actor, &target_action.name, rates))
+        {
+            // the actor hasn't used the actions enough
+            return Ok(false);
         }
 
-        if self.attributes.iter().any(|a| !a.check(&actor.attributes)) {
-            return false;
+        if let Some(ref risk_check) = self.risk {
+            if !risk(env_attributes).is_some_and(|risk| risk_check.check(risk)) {
+                // the request is not as risky as that
+                return Ok(false);
+            }
         }
 
-        if let Some(ref bucket_check) = self.bucket {
-            if !bucket_check.check(actor.bucket().into()) {
-                return false;
-            }
+        if let Some(ref module) = self.wasm_module {
+            let input = wasm_input(
+                actor,
+                env_attributes,
+                target_name,
+                target_type,
+                target_attributes,
+                target_action,
+            );
+            return wasm
+                .evaluate(module, &input)
+                .map_err(|err| format!("Policy {} could not run WASM module: {err}", self.name));
         }
 
-        true
+        Ok(true)
     }
-}
 
-/// convert the protobuf version to our version
-impl From<protos::ActorCheck> for ActorCheck {
-    fn from(ec: protos::ActorCheck) -> Self {
-        Self {
-            name: ec.name.map(StringCheck::from),
-            typestr: ec.typestr.map(StringCheck::from),
-            attributes: ec.attributes.into_iter().map(KvCheck::from).collect(),
-            bucket: ec.bucket.map(NumberCheck::from),
-        }
-    }
-}
-impl From<ActorCheck> for protos::ActorCheck {
-    fn from(ec: ActorCheck) -> Self {
-        Self {
-            name: ec.name.map(protos::StringCheck::from),
-            typestr: ec.typestr.map(protos::StringCheck::from),
-            attributes: ec
-                .attributes
-                .into_iter()
-                .map(protos::KvCheck::from)
-                .collect(),
-            bucket: ec.bucket.map(protos::NumberCheck::from),
+    /// run the rule's WASM condition
+    ///
+    /// If the module cannot be run, DENY rules apply and ALLOW rules don't, so a broken module
+    /// never grants access.
+    fn wasm_check(&self, wasm: &WasmModules, module: &str, input: &serde_json::Value) -> bool {
+        match wasm.evaluate(module, input) {
+            Ok(matched) => matched,
+            Err(err) => {
+                eprintln!("Policy {} could not run WASM module: {}", self.name, err);
+                self.decision == Decide::Deny
+            }
         }
     }
-}
 
-/// The check to see if the requested target/action match this policy rule
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub(crate) struct TargetCheck {
-    name: Option<StringCheck>,
-    typestr: Option<StringCheck>,
-    attributes: Vec<KvCheck>,
-    match_in_actor: Vec<String>,
-    match_in_env: Vec<String>,
-    action: Option<StringCheck>,
-}
-impl TargetCheck {
-    /// see if one of our attribute values exists in another set of attribute values
-    fn check_attr_match(
+    /// evaluate every check in this rule, recording the outcome of each
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn trace(
         &self,
-        attr_to_check: &str,
-        our_map: &HashMap<String, HashSet<String>>,
-        other_map: &HashMap<String, HashSet<String>>,
-    ) -> bool {
-        if !our_map.contains_key(attr_to_check) {
-            // the target itself doesn't contain the attribute we are supposed to match with the actor
-            return false;
+        actor: &RegisteredActor,
+        env_attributes: &AttributeMap,
+        target_name: &str,
+        target_type: &str,
+        target_attributes: &AttributeMap,
+        target_action: &TargetAction,
+        wasm: &WasmModules,
+        rates: &Velocity,
+    ) -> protos::PolicyTrace {
+        let mut checks = Vec::new();
+
+        if !self.target_types.is_empty() {
+            checks.push(trace(
+                format!("policy target types [{}]", self.target_types.join(", ")),
+                target_type.to_string(),
+                self.applies_to_type(target_type),
+            ));
         }
 
-        if !other_map.contains_key(attr_to_check) {
-            // the actor doesn't contain the attribute we are supposed to check
-            return false;
+        if let Some(ref actor_check) = self.actor_check {
+            checks.extend(actor_check.trace(actor));
         }
 
-        let our_attr_vals: Vec<&String> = our_map.get(attr_to_check).unwrap().iter().collect();
+        for env_check in &self.env_attributes {
+            checks.push(trace(
+                format!("env attribute {env_check}"),
+                attribute_values(env_attributes, env_check.key()),
+                env_check.check(env_attributes),
+            ));
+        }
 
-        let other_attr_vals: Vec<&String> = other_map.get(attr_to_check).unwrap().iter().collect();
+        if let Some(ref target_check) = self.target_check {
+            checks.extend(target_check.trace(
+                target_name,
+                target_type,
+                target_attributes,
+                target_action,
+                &actor.attributes,
+                env_attributes,
+            ));
+        }
 
-        if !our_attr_vals
-            .iter()
-            .any(|tav| other_attr_vals.contains(tav))
-        {
-            // we didn't find a single value in the target's attribute values that were also in the
-            // actor's attribute values
-            return false;
+        for compare_check in &self.compare_checks {
+            let input = [&compare_check.left, &compare_check.right]
+                .iter()
+                .map(|attr| {
+                    let attributes =
+                        attr.attributes(&actor.attributes, target_attributes, env_attributes);
+                    attribute_values(attributes, &attr.key)
+                })
+                .collect::<Vec<String>>()
+                .join(" / ");
+            checks.push(trace(
+                compare_check.to_string(),
+                input,
+                compare_check.check(&actor.attributes, target_attributes, env_attributes),
+            ));
         }
 
-        true
-    }
+        for cidr_check in &self.cidr_checks {
+            let attr = cidr_check.attribute();
+            let attributes = attr.attributes(&actor.attributes, target_attributes, env_attributes);
+            checks.push(trace(
+                cidr_check.to_string(),
+                attribute_values(attributes, &attr.key),
+                cidr_check.check(&actor.attributes, target_attributes, env_attributes),
+            ));
+        }
 
-    /// perform a check against a potential actor
-    pub fn check(
-        &self,
-        target_name: &str,
-        target_type: &str,
-        target_attributes: &HashMap<String, HashSet<String>>,
-        target_action: &str,
-        actor_attributes: &HashMap<String, HashSet<String>>,
-        env_attributes: &HashMap<String, HashSet<String>>,
-    ) -> bool {
-        if let Some(ref name_check) = self.name {
-            if !name_check.check(target_name) {
-                // name does not match
-                return false;
-            }
+        for rate_check in &self.rate_checks {
+            let uses = rate_check.uses(actor, &target_action.name, rates);
+            checks.push(trace(
+                rate_check.to_string(),
+                uses.to_string(),
+                uses >= u64::from(rate_check.limit),
+            ));
         }
 
-        if let Some(ref type_check) = self.typestr {
-            if !type_check.check(target_type) {
-                // type does not match
-                return false;
-            }
+        if let Some(ref risk_check) = self.risk {
+            checks.push(trace(
+                format!("risk {risk_check}"),
+                attribute_values(env_attributes, RISK_ATTRIBUTE),
+                risk(env_attributes).is_some_and(|risk| risk_check.check(risk)),
+            ));
         }
 
-        if self.attributes.iter().any(|a| !a.check(target_attributes)) {
-            // one or more attributes do not match
-            return false;
+        if let Some(ref module) = self.wasm_module {
+            let input = wasm_input(
+                actor,
+                env_attributes,
+                target_name,
+                target_type,
+                target_attributes,
+                target_action,
+            );
+            checks.push(trace(
+                format!("wasm module {module}"),
+                input.to_string(),
+                self.wasm_check(wasm, module, &input),
+            ));
         }
 
-        // make sure every attribute that's supposed to match with the actor does match
-        for attr_to_check in &self.match_in_actor {
-            if !self.check_attr_match(attr_to_check, target_attributes, actor_attributes) {
-                // this attribute did not match between the target and the actor
-                return false;
-            }
+        protos::PolicyTrace {
+            name: self.name.clone(),
+            matched: checks.iter().all(|c| c.passed),
+            checks,
+            decision: protos::Decide::from(self.decision.clone()).into(),
+            mode: protos::Mode::from(self.mode.clone()).into(),
         }
+    }
 
-        // make sure every attribute that's supposed to match with the environment does match
-        for attr_to_check in &self.match_in_env {
-            if !self.check_attr_match(attr_to_check, target_attributes, env_attributes) {
-                // this attribute did not match between the target and the environment
-                return false;
+    /// the rule as sections of labelled lines, for people to read and compare
+    pub fn outline(&self) -> Vec<(&'static str, Vec<(&'static str, String)>)> {
+        let mut rule = vec![("name", self.name.clone())];
+        if let Some(ref desc) = self.desc {
+            rule.push(("description", desc.clone()));
+        }
+        rule.extend(self.tags.iter().map(|tag| ("tag", tag.clone())));
+        rule.push((
+            "decision",
+            match self.decision {
+                Decide::Deny => String::from("DENY"),
+                Decide::Allow => String::from("ALLOW"),
+                Decide::AllowWithApproval(n) => format!("ALLOW with {n} approvals"),
+                Decide::NotApplicable => String::from("NOT_APPLICABLE"),
+                Decide::Indeterminate => String::from("INDETERMINATE"),
+            },
+        ));
+        if self.mode == Mode::Shadow {
+            rule.push(("mode", String::from("shadow")));
+        }
+        if let Some(ref module) = self.wasm_module {
+            rule.push(("wasm module", module.clone()));
+        }
+        if let Some(ref risk) = self.risk {
+            rule.push(("risk", risk.to_string()));
+        }
+
+        let mut actor = Vec::new();
+        if let Some(ref check) = self.actor_check {
+            actor.extend(check.name.iter().map(|c| ("name", c.to_string())));
+            actor.extend(check.typestr.iter().map(|c| ("type", c.to_string())));
+            actor.extend(
+                check
+                    .attributes
+                    .iter()
+                    .map(|kv| ("attribute", kv.to_string())),
+            );
+            actor.extend(check.bucket.iter().map(|c| ("bucket", c.to_string())));
+        }
+
+        let mut target = Vec::new();
+        if !self.target_types.is_empty() {
+            target.push(("types", format!("[{}]", self.target_types.join(", "))));
+        }
+        if let Some(ref check) = self.target_check {
+            target.extend(check.name.iter().map(|c| ("name", c.to_string())));
+            target.extend(check.typestr.iter().map(|c| ("type", c.to_string())));
+            target.extend(check.action.iter().map(|c| ("action", c.to_string())));
+            if !check.action_except.is_empty() {
+                target.push((
+                    "except actions",
+                    format!("[{}]", check.action_except.join(", ")),
+                ));
             }
+            target.extend(
+                check
+                    .attributes
+                    .iter()
+                    .map(|kv| ("attribute", kv.to_string())),
+            );
+            target.extend(
+                check
+                    .match_in_actor
+                    .iter()
+                    .map(|k| ("matches actor", k.clone())),
+            );
+            target.extend(
+                check
+                    .match_in_env
+                    .iter()
+                    .map(|k| ("matches env", k.clone())),
+            );
         }
 
-        if let Some(action_check) = &self.action {
-            if !action_check.check(target_action) {
-                // action does not match
-                return false;
+        let env = self
+            .env_attributes
+            .iter()
+            .map(|kv| ("attribute", kv.to_string()))
+            .collect();
+
+        let mut conditions = Vec::new();
+        conditions.extend(
+            self.compare_checks
+                .iter()
+                .map(|c| ("compare", c.to_string())),
+        );
+        conditions.extend(self.cidr_checks.iter().map(|c| ("address", c.to_string())));
+        conditions.extend(self.rate_checks.iter().map(|c| ("rate", c.to_string())));
+
+        vec![
+            ("rule", rule),
+            ("actor", actor),
+            ("target", target),
+            ("environment", env),
+            ("conditions", conditions),
+        ]
+    }
+}
+
+/// The registered policy rules, with their names also bucketed by the target types they apply to
+///
+/// Reads go straight to the rules by name; `for_type` only looks at the rules that can apply to
+/// a type, so checks don't have to go through every rule.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PolicyStore {
+    rules: HashMap<String, RegisteredPolicyRule>,
+    /// names of the rules limited to a type, by type
+    typed: FastMap<Arc<str>, FastSet<String>>,
+    /// names of the rules that apply to every type
+    untyped: FastSet<String>,
+}
+
+impl PolicyStore {
+    /// add or replace a rule
+    pub fn insert(&mut self, name: String, rule: RegisteredPolicyRule) {
+        self.remove(&name);
+
+        if rule.target_types.is_empty() {
+            self.untyped.insert(name.clone());
+        }
+        for typestr in &rule.target_types {
+            self.typed
+                .entry(intern(typestr))
+                .or_default()
+                .insert(name.clone());
+        }
+        self.rules.insert(name, rule);
+    }
+
+    /// remove a rule, returning it if it was there
+    pub fn remove(&mut self, name: &str) -> Option<RegisteredPolicyRule> {
+        let rule = self.rules.remove(name)?;
+
+        self.untyped.remove(name);
+        for typestr in &rule.target_types {
+            if let Some(names) = self.typed.get_mut(typestr.as_str()) {
+                names.remove(name);
+                if names.is_empty() {
+                    self.typed.remove(typestr.as_str());
+                }
             }
         }
+        Some(rule)
+    }
 
-        true
+    /// the rules that can apply to targets of a type, by name
+    pub fn for_type<'a>(
+        &'a self,
+        target_type: &str,
+    ) -> impl Iterator<Item = (&'a String, &'a RegisteredPolicyRule)> + 'a {
+        let typed = self.typed.get(lowercase(target_type).as_ref());
+        self.untyped
+            .iter()
+            .chain(typed.into_iter().flatten())
+            .filter_map(|name| self.rules.get_key_value(name))
     }
 }
 
-impl From<protos::TargetCheck> for TargetCheck {
-    fn from(tc: protos::TargetCheck) -> Self {
-        Self {
-            name: tc.name.map(StringCheck::from),
-            typestr: tc.typestr.map(StringCheck::from),
-            attributes: tc.attributes.into_iter().map(KvCheck::from).collect(),
-            match_in_actor: tc.match_in_actor,
-            match_in_env: tc.match_in_env,
-            action: tc.action.map(StringCheck::from),
+impl std::ops::Deref for PolicyStore {
+    type Target = HashMap<String, RegisteredPolicyRule>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.rules
+    }
+}
+
+impl FromIterator<(String, RegisteredPolicyRule)> for PolicyStore {
+    fn from_iter<T: IntoIterator<Item = (String, RegisteredPolicyRule)>>(iter: T) -> Self {
+        let mut store = Self::default();
+        for (name, rule) in iter {
+            store.insert(name, rule);
         }
+        store
     }
 }
-impl From<TargetCheck> for protos::TargetCheck {
-    fn from(tc: TargetCheck) -> protos::TargetCheck {
-        Self {
-            name: tc.name.map(protos::StringCheck::from),
-            typestr: tc.typestr.map(protos::StringCheck::from),
-            attributes: tc
-                .attributes
-                .into_iter()
-                .map(protos::KvCheck::from)
-                .collect(),
-            match_in_actor: tc.match_in_actor,
-            match_in_env: tc.match_in_env,
-            action: tc.action.map(protos::StringCheck::from),
+
+impl From<HashMap<String, RegisteredPolicyRule>> for PolicyStore {
+    fn from(rules: HashMap<String, RegisteredPolicyRule>) -> Self {
+        rules.into_iter().collect()
+    }
+}
+
+/// decide on a request using the enforced rules and the policy sets; shadow rules are ignored
+///
+/// Rules that belong to a set (`in_sets`) are only evaluated as part of the set. If nothing
+/// applies, the decision is NOT_APPLICABLE. Each rule evaluated is spent from the budget, and
+/// once it runs out, no more are.
+#[allow(clippy::too_many_arguments)]
+fn decide(
+    policies: &PolicyStore,
+    sets: &HashMap<String, RegisteredPolicySet>,
+    in_sets: &HashSet<&str>,
+    actor: &RegisteredActor,
+    env_attributes: &AttributeMap,
+    target_name: &str,
+    target_type: &str,
+    target_attributes: &AttributeMap,
+    target_action: &TargetAction,
+    wasm: &WasmModules,
+    rates: &Velocity,
+    timings: Option<&PolicyTimings>,
+    budget: &EvalBudget,
+) -> Decide {
+    let rules = policies
+        .for_type(target_type)
+        .filter(|(name, policy)| !in_sets.contains(name.as_str()) && policy.mode == Mode::Enforce)
+        .take_while(|_| budget.spend())
+        .filter_map(|(name, policy)| {
+            let started = timings.is_some().then(Instant::now);
+            let decided = policy.decision_for(
+                actor,
+                env_attributes,
+                target_name,
+                target_type,
+                target_attributes,
+                target_action,
+                wasm,
+                rates,
+            );
+            if let (Some(timings), Some(started)) = (timings, started) {
+                timings.record(name, started.elapsed());
+            }
+            decided
+        });
+    let from_sets = sets.values().filter_map(|set| {
+        set.decide(
+            policies,
+            actor,
+            env_attributes,
+            target_name,
+            target_type,
+            target_attributes,
+            target_action,
+            wasm,
+            rates,
+            budget,
+        )
+    });
+
+    // if we get an explicit DENY from any rule or set, we exit immediately; an ALLOW never
+    // waives the approvals another rule asks for, nor a rule that couldn't be evaluated
+    let mut decision: Option<Decide> = None;
+    for decided in rules.chain(from_sets) {
+        if let Decide::Deny = decided {
+            return Decide::Deny;
         }
+        decision = Some(match decision {
+            Some(decision) => decision.strictest(decided),
+            None => decided,
+        });
     }
+
+    decision.unwrap_or(Decide::NotApplicable)
 }
 
-/// A policy rule registered with Gatehouse
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub(crate) struct RegisteredPolicyRule {
-    /// the name of this policy rule
-    pub name: String,
-    /// the optional human description
-    pub desc: Option<String>,
+/// decide on a request that stands for several actions; every one of them has to be allowed, and
+/// if any needs approval, the request does
+///
+/// With timings, how long each rule takes to evaluate is recorded in them. Evaluations stop once
+/// the budget runs out, and what was decided by then is returned.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decide_actions(
+    policies: &PolicyStore,
+    sets: &HashMap<String, RegisteredPolicySet>,
+    actor: &RegisteredActor,
+    env_attributes: &AttributeMap,
+    target_name: &str,
+    target_type: &str,
+    target_attributes: &AttributeMap,
+    target_actions: &[TargetAction],
+    wasm: &WasmModules,
+    rates: &Velocity,
+    timings: Option<&PolicyTimings>,
+    budget: &EvalBudget,
+) -> Decide {
+    let in_sets = members(sets);
+    let mut decision = Decide::Allow;
+    for action in target_actions {
+        decision = decision.strictest(decide(
+            policies,
+            sets,
+            &in_sets,
+            actor,
+            env_attributes,
+            target_name,
+            target_type,
+            target_attributes,
+            action,
+            wasm,
+            rates,
+            timings,
+            budget,
+        ));
+        if decision == Decide::Deny {
+            break;
+        }
+    }
 
-    /// determine if actor matches or should match all actors if None
-    pub actor_check: Option<ActorCheck>,
+    decision
+}
 
-    /// list of environment attributes to check
-    pub env_attributes: Vec<KvCheck>,
+/// the risk score of a request, if it was scored
+fn risk(env_attributes: &AttributeMap) -> Option<i32> {
+    env_attributes
+        .get(RISK_ATTRIBUTE)?
+        .iter()
+        .filter_map(|val| val.parse::<i64>().ok())
+        .max()
+        .map(|risk| risk.clamp(i32::MIN.into(), i32::MAX.into()) as i32)
+}
 
-    /// determine if rule applies to target
-    pub target_check: Option<TargetCheck>,
+/// the request handed to a WASM module, as JSON
+fn wasm_input(
+    actor: &RegisteredActor,
+    env_attributes: &AttributeMap,
+    target_name: &str,
+    target_type: &str,
+    target_attributes: &AttributeMap,
+    target_action: &TargetAction,
+) -> serde_json::Value {
+    json!({
+        "actor": {
+            "name": actor.name,
+            "typestr": actor.typestr,
+            "attributes": actor.attributes,
+        },
+        "env": env_attributes,
+        "target": {
+            "name": target_name,
+            "typestr": target_type,
+            "action": target_action.name,
+            "attributes": target_attributes,
+        },
+    })
+}
 
-    /// The decision to make if this rule matches
-    pub decision: Decide,
+/// build the trace of a single check
+fn trace(check: String, input: String, passed: bool) -> protos::CheckTrace {
+    protos::CheckTrace {
+        check,
+        input,
+        passed,
+    }
+}
+
+/// show the (sorted) values of an attribute for a trace
+fn attribute_values(attributes: &AttributeMap, key: &str) -> String {
+    match attributes.get(key) {
+        Some(vals) => {
+            let mut vals: Vec<&str> = vals.iter().map(|val| val.as_ref()).collect();
+            vals.sort_unstable();
+            format!("[{}]", vals.join(", "))
+        }
+        None => String::from("<unset>"),
+    }
 }
 
 impl From<protos::PolicyRule> for RegisteredPolicyRule {
     fn from(rule: protos::PolicyRule) -> Self {
-        let decision = rule.decision();
+        let decision = match rule.decision() {
+            protos::Decide::AllowWithApproval => Decide::AllowWithApproval(rule.approvals.max(1)),
+            decision => Decide::from(decision),
+        };
+        let mode = rule.mode();
         Self {
             name: rule.name,
             desc: rule.desc,
             actor_check: rule.actor_check.map(ActorCheck::from),
             env_attributes: rule.env_attributes.into_iter().map(KvCheck::from).collect(),
             target_check: rule.target_check.map(TargetCheck::from),
-            decision: Decide::from(decision),
+            decision,
+            mode: Mode::from(mode),
+            wasm_module: rule.wasm_module.map(|m| m.to_ascii_lowercase()),
+            compare_checks: rule
+                .compare_checks
+                .into_iter()
+                .map(CompareCheck::from)
+                .collect(),
+            cidr_checks: rule.cidr_checks.into_iter().map(CidrCheck::from).collect(),
+            target_types: lowercased(rule.target_types),
+            rate_checks: rule.rate_checks.into_iter().map(RateCheck::from).collect(),
+            risk: rule.risk.map(NumberCheck::from),
+            tags: lowercased(
+                rule.tags
+                    .into_iter()
+                    .map(|tag| tag.trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect(),
+            ),
+            timestamps: Timestamps {
+                created_at: rule.created_at,
+                updated_at: rule.updated_at,
+            },
         }
     }
 }
@@ -428,16 +2108,51 @@ impl From<RegisteredPolicyRule> for protos::PolicyRule {
             actor_check: rpr.actor_check.map(ActorCheck::into),
             env_attributes: rpr.env_attributes.into_iter().map(KvCheck::into).collect(),
             target_check: rpr.target_check.map(TargetCheck::into),
+            approvals: match rpr.decision {
+                Decide::AllowWithApproval(approvals) => approvals,
+                _ => 0,
+            },
             decision: protos::Decide::from(rpr.decision).into(),
+            mode: protos::Mode::from(rpr.mode).into(),
+            wasm_module: rpr.wasm_module,
+            compare_checks: rpr
+                .compare_checks
+                .into_iter()
+                .map(protos::CompareCheck::from)
+                .collect(),
+            cidr_checks: rpr
+                .cidr_checks
+                .into_iter()
+                .map(protos::CidrCheck::from)
+                .collect(),
+            target_types: rpr.target_types,
+            rate_checks: rpr
+                .rate_checks
+                .into_iter()
+                .map(protos::RateCheck::from)
+                .collect(),
+            risk: rpr.risk.map(protos::NumberCheck::from),
+            tags: rpr.tags,
+            created_at: rpr.timestamps.created_at,
+            updated_at: rpr.timestamps.updated_at,
         }
     }
 }
 
+/// lowercase, sort, and dedup a list of names
+fn lowercased(names: Vec<String>) -> Vec<String> {
+    let mut names: Vec<String> = names.iter().map(|n| n.to_ascii_lowercase()).collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
 #[cfg(test)]
 mod tests {
-    use std::collections::{HashMap, HashSet};
+    use std::collections::HashSet;
 
     use super::*;
+    use crate::attribute;
 
     fn str(val: &str) -> String {
         val.to_string()
@@ -455,14 +2170,14 @@ mod tests {
 
     #[test]
     fn test_kvcheck() {
-        let mut map: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut map = AttributeMap::default();
         map.insert(
-            str("role"),
-            HashSet::from_iter(vec![str("admin"), str("user")]),
+            str("role").into(),
+            attribute::values(vec![str("admin"), str("user")]),
         );
         map.insert(
-            str("region"),
-            HashSet::from_iter(vec![str("us"), str("emea")]),
+            str("region").into(),
+            attribute::values(vec![str("us"), str("emea")]),
         );
 
         assert!(KvCheck::Has(str("role"), vec![str("banned"), str("user")]).check(&map));
@@ -471,6 +2186,23 @@ mod tests {
         assert!(!KvCheck::Has(str("office"), vec![str("london"), str("dublin")]).check(&map));
         assert!(KvCheck::HasNot(str("region"), vec![str("anz")]).check(&map));
         assert!(KvCheck::HasNot(str("office"), vec![str("london")]).check(&map));
+
+        assert!(KvCheck::Exists(str("role")).check(&map));
+        assert!(!KvCheck::Exists(str("office")).check(&map));
+        assert!(KvCheck::NotExists(str("office")).check(&map));
+        assert!(!KvCheck::NotExists(str("region")).check(&map));
+
+        assert!(KvCheck::CountAtLeast(str("role"), 2).check(&map));
+        assert!(!KvCheck::CountAtLeast(str("role"), 3).check(&map));
+        assert!(KvCheck::CountAtLeast(str("office"), 0).check(&map));
+
+        assert!(KvCheck::ContainsAll(str("region"), vec![str("us"), str("emea")]).check(&map));
+        assert!(!KvCheck::ContainsAll(str("region"), vec![str("us"), str("anz")]).check(&map));
+        assert!(!KvCheck::ContainsAll(str("office"), vec![str("london")]).check(&map));
+
+        map.insert(str("office").into(), Default::default());
+        assert!(!KvCheck::Exists(str("office")).check(&map));
+        assert!(KvCheck::NotExists(str("office")).check(&map));
     }
 
     #[test]
@@ -485,12 +2217,12 @@ mod tests {
 
     #[test]
     fn test_actorcheck() {
-        let mut map: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut map = AttributeMap::default();
         map.insert(
-            str("role"),
-            HashSet::from_iter(vec![str("admin"), str("user")]),
+            str("role").into(),
+            attribute::values(vec![str("admin"), str("user")]),
         );
-        map.insert(str("region"), HashSet::from_iter(vec![str("us")]));
+        map.insert(str("region").into(), attribute::values(vec![str("us")]));
         let actor = RegisteredActor::new("kaitlyn", "user", map);
 
         // an "everything passes" check
@@ -567,27 +2299,290 @@ mod tests {
         .check(&actor));
     }
 
+    #[test]
+    fn test_trace() {
+        let mut map = AttributeMap::default();
+        map.insert(str("region").into(), attribute::values(vec![str("us")]));
+        let actor = RegisteredActor::new("kaitlyn", "user", map);
+
+        let rule = RegisteredPolicyRule {
+            name: str("trace-me"),
+            desc: None,
+            actor_check: Some(ActorCheck {
+                name: Some(StringCheck::OneOf(vec![str("kaitlyn")])),
+                typestr: None,
+                attributes: vec![KvCheck::Has(str("region"), vec![str("emea")])],
+                bucket: None,
+            }),
+            env_attributes: vec![],
+            target_check: None,
+            decision: Decide::Allow,
+            mode: Mode::Enforce,
+            wasm_module: None,
+            compare_checks: vec![],
+            cidr_checks: vec![],
+            target_types: vec![],
+            rate_checks: vec![],
+            risk: None,
+            tags: vec![],
+            timestamps: Timestamps::default(),
+        };
+
+        let trace = rule.trace(
+            &actor,
+            &AttributeMap::default(),
+            "db",
+            "database",
+            &AttributeMap::default(),
+            &TargetAction::new("read"),
+            &WasmModules::default(),
+            &Velocity::default(),
+        );
+        assert_eq!(trace.name, "trace-me");
+        assert!(!trace.matched);
+        assert_eq!(trace.checks.len(), 2);
+        assert!(trace.checks[0].passed);
+        assert_eq!(trace.checks[0].input, "kaitlyn");
+        assert!(!trace.checks[1].passed);
+        assert_eq!(trace.checks[1].input, "[us]");
+    }
+
+    #[test]
+    fn test_policy_store() {
+        let rule = |name: &str, types: &[&str]| -> RegisteredPolicyRule {
+            protos::PolicyRule {
+                name: str(name),
+                decision: protos::Decide::Allow.into(),
+                target_types: types.iter().map(|t| str(t)).collect(),
+                ..Default::default()
+            }
+            .into()
+        };
+        let mut store: PolicyStore = [
+            rule("any", &[]),
+            rule("db", &["Database"]),
+            rule("web", &["website"]),
+        ]
+        .into_iter()
+        .map(|rule| (rule.name.clone(), rule))
+        .collect();
+
+        let names = |store: &PolicyStore, typestr: &str| -> HashSet<String> {
+            store.for_type(typestr).map(|(n, _)| n.clone()).collect()
+        };
+        assert_eq!(
+            names(&store, "database"),
+            HashSet::from([str("any"), str("db")])
+        );
+        assert_eq!(names(&store, "queue"), HashSet::from([str("any")]));
+
+        // a typed rule never matches another type, even if nothing else is checked
+        let actor = RegisteredActor::new("kaitlyn", "user", AttributeMap::default());
+        let matches = |typestr: &str| {
+            store["db"].matches(
+                &actor,
+                &AttributeMap::default(),
+                "main",
+                typestr,
+                &AttributeMap::default(),
+                &TargetAction::new("read"),
+                &WasmModules::default(),
+                &Velocity::default(),
+            )
+        };
+        assert!(matches("database"));
+        assert!(!matches("website"));
+
+        // replacing a rule moves it to its new types
+        store.insert(str("db"), rule("db", &["website"]));
+        assert_eq!(names(&store, "database"), HashSet::from([str("any")]));
+        assert_eq!(
+            names(&store, "website"),
+            HashSet::from([str("any"), str("db"), str("web")])
+        );
+
+        store.remove("web");
+        assert_eq!(
+            names(&store, "website"),
+            HashSet::from([str("any"), str("db")])
+        );
+    }
+
+    #[test]
+    fn test_comparecheck() {
+        let actor_attrs = AttributeMap::from_iter([
+            (str("region").into(), attribute::values([str("us")])),
+            (
+                str("teams").into(),
+                attribute::values([str("infra"), str("db")]),
+            ),
+        ]);
+        let target_attrs =
+            AttributeMap::from_iter([(str("owners").into(), attribute::values([str("db")]))]);
+        let env_attrs = AttributeMap::from_iter([(
+            str("request_region").into(),
+            attribute::values([str("us")]),
+        )]);
+
+        let attr = |source, key: &str| AttributeRef {
+            source,
+            key: str(key),
+        };
+
+        let same_region = CompareCheck {
+            left: attr(Source::Actor, "region"),
+            right: attr(Source::Env, "request_region"),
+            op: Compare::Equal,
+        };
+        assert!(same_region.check(&actor_attrs, &target_attrs, &env_attrs));
+        assert_eq!(
+            same_region.to_string(),
+            "actor.region equals env.request_region"
+        );
+
+        // teams has more values than owners, so they aren't equal but do intersect
+        let mut owner = CompareCheck {
+            left: attr(Source::Target, "owners"),
+            right: attr(Source::Actor, "teams"),
+            op: Compare::Equal,
+        };
+        assert!(!owner.check(&actor_attrs, &target_attrs, &env_attrs));
+        owner.op = Compare::Intersect;
+        assert!(owner.check(&actor_attrs, &target_attrs, &env_attrs));
+
+        // unset attributes never match
+        let unset = CompareCheck {
+            left: attr(Source::Env, "owners"),
+            right: attr(Source::Env, "owners"),
+            op: Compare::Equal,
+        };
+        assert!(!unset.check(&actor_attrs, &target_attrs, &env_attrs));
+    }
+
+    #[test]
+    fn test_cidrcheck() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com/8".parse::<Cidr>().is_err());
+        assert_eq!(
+            "10.1.2.3".parse::<Cidr>().unwrap().to_string(),
+            "10.1.2.3/32"
+        );
+
+        let actor_attrs = AttributeMap::default();
+        let target_attrs = AttributeMap::default();
+        let env_attrs = AttributeMap::from_iter([
+            (
+                str("client_ip").into(),
+                attribute::values([str("10.20.30.40")]),
+            ),
+            (
+                str("forwarded").into(),
+                attribute::values([str("unknown"), str("::ffff:192.168.1.5")]),
+            ),
+        ]);
+
+        let blocks = vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "192.168.1.0/24".parse().unwrap(),
+            "fd00::/8".parse().unwrap(),
+        ];
+        let attr = |key: &str| AttributeRef {
+            source: Source::Env,
+            key: str(key),
+        };
+
+        let internal = CidrCheck::In(attr("client_ip"), blocks.clone());
+        assert!(internal.check(&actor_attrs, &target_attrs, &env_attrs));
+        assert_eq!(
+            internal.to_string(),
+            "env.client_ip is in [10.0.0.0/8, 192.168.1.0/24, fd00::/8]"
+        );
+
+        // values that aren't addresses are skipped and mapped IPv4 addresses are matched
+        let forwarded = CidrCheck::In(attr("forwarded"), blocks.clone());
+        assert!(forwarded.check(&actor_attrs, &target_attrs, &env_attrs));
+
+        let external = CidrCheck::NotIn(attr("client_ip"), blocks.clone());
+        assert!(!external.check(&actor_attrs, &target_attrs, &env_attrs));
+
+        // an unset attribute has no address in any block
+        let unset = CidrCheck::In(attr("missing"), blocks.clone());
+        assert!(!unset.check(&actor_attrs, &target_attrs, &env_attrs));
+        let unset = CidrCheck::NotIn(attr("missing"), blocks);
+        assert!(unset.check(&actor_attrs, &target_attrs, &env_attrs));
+    }
+
+    #[test]
+    fn test_wasm_fail_closed() {
+        let actor = RegisteredActor::new("kaitlyn", "user", AttributeMap::default());
+        let wasm = WasmModules::default();
+
+        let mut rule = RegisteredPolicyRule {
+            name: str("custom"),
+            desc: None,
+            actor_check: None,
+            env_attributes: vec![],
+            target_check: None,
+            decision: Decide::Allow,
+            mode: Mode::Enforce,
+            wasm_module: Some(str("missing")),
+            compare_checks: vec![],
+            cidr_checks: vec![],
+            target_types: vec![],
+            rate_checks: vec![],
+            risk: None,
+            tags: vec![],
+            timestamps: Timestamps::default(),
+        };
+
+        // a module that can't be run never lets an ALLOW rule apply...
+        let empty = AttributeMap::default();
+        assert!(!rule.matches(
+            &actor,
+            &empty,
+            "db",
+            "database",
+            &empty,
+            &TargetAction::new("read"),
+            &wasm,
+            &Velocity::default()
+        ));
+
+        // ...but DENY rules still do
+        rule.decision = Decide::Deny;
+        assert!(rule.matches(
+            &actor,
+            &empty,
+            "db",
+            "database",
+            &empty,
+            &TargetAction::new("read"),
+            &wasm,
+            &Velocity::default()
+        ));
+    }
+
     #[test]
     fn test_targetcheck() {
-        let mut target_attrs: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut target_attrs = AttributeMap::default();
         target_attrs.insert(
-            str("role"),
-            HashSet::from_iter(vec![str("main"), str("backup")]),
+            str("role").into(),
+            attribute::values(vec![str("main"), str("backup")]),
         );
-        target_attrs.insert(str("env"), HashSet::from_iter(vec![str("test")]));
+        target_attrs.insert(str("env").into(), attribute::values(vec![str("test")]));
 
-        let mut actor_attrs: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut actor_attrs = AttributeMap::default();
         actor_attrs.insert(
-            str("office"),
-            HashSet::from_iter(vec![str("sfo"), str("remote")]),
+            str("office").into(),
+            attribute::values(vec![str("sfo"), str("remote")]),
         );
         actor_attrs.insert(
-            str("env"),
-            HashSet::from_iter(vec![str("test"), str("prod")]),
+            str("env").into(),
+            attribute::values(vec![str("test"), str("prod")]),
         );
 
-        let mut env_attrs: HashMap<String, HashSet<String>> = HashMap::new();
-        env_attrs.insert(str("env"), HashSet::from_iter(vec![str("test")]));
+        let mut env_attrs = AttributeMap::default();
+        env_attrs.insert(str("env").into(), attribute::values(vec![str("test")]));
 
         // test "any target should pass" check
         assert!(TargetCheck {
@@ -597,12 +2592,13 @@ mod tests {
             match_in_actor: vec![],
             match_in_env: vec![],
             action: None,
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -615,12 +2611,13 @@ mod tests {
             match_in_actor: vec![],
             match_in_env: vec![],
             action: None,
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -631,12 +2628,13 @@ mod tests {
             match_in_actor: vec![],
             match_in_env: vec![],
             action: None,
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -649,12 +2647,13 @@ mod tests {
             match_in_actor: vec![],
             match_in_env: vec![],
             action: None,
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -665,12 +2664,13 @@ mod tests {
             match_in_actor: vec![],
             match_in_env: vec![],
             action: None,
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -683,12 +2683,13 @@ mod tests {
             match_in_actor: vec![],
             match_in_env: vec![],
             action: None,
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -699,12 +2700,13 @@ mod tests {
             match_in_actor: vec![],
             match_in_env: vec![],
             action: None,
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -717,12 +2719,13 @@ mod tests {
             match_in_actor: vec![],
             match_in_env: vec![],
             action: Some(StringCheck::OneOf(vec![str("read"),])),
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -733,12 +2736,13 @@ mod tests {
             match_in_actor: vec![],
             match_in_env: vec![],
             action: Some(StringCheck::OneOf(vec![str("write")])),
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -751,12 +2755,13 @@ mod tests {
             match_in_actor: vec![str("env")],
             match_in_env: vec![],
             action: Some(StringCheck::OneOf(vec![str("read"),])),
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
@@ -767,14 +2772,42 @@ mod tests {
             match_in_actor: vec![str("role")],
             match_in_env: vec![],
             action: Some(StringCheck::OneOf(vec![str("read"),])),
+            action_except: vec![],
         }
         .check(
             "bree",
             "db",
             &target_attrs,
-            "read",
+            &TargetAction::new("read"),
             &actor_attrs,
             &env_attrs
         ));
+
+        // test every action but some, by name or by group
+        let all_but = TargetCheck {
+            name: None,
+            typestr: None,
+            attributes: vec![],
+            match_in_actor: vec![],
+            match_in_env: vec![],
+            action: Some(StringCheck::OneOf(vec![str("*")])),
+            action_except: vec![str("delete"), str("admin")],
+        };
+        let check = |action: &TargetAction| {
+            all_but.check(
+                "bree",
+                "db",
+                &target_attrs,
+                action,
+                &actor_attrs,
+                &env_attrs,
+            )
+        };
+        assert!(check(&TargetAction::new("read")));
+        assert!(!check(&TargetAction::new("delete")));
+        assert!(!check(&TargetAction {
+            name: str("drop"),
+            groups: HashSet::from([str("admin")]),
+        }));
     }
 }
@@ -0,0 +1,279 @@
+#![warn(missing_docs)]
+
+//! Sets of policies that are scoped, combined, and enabled together
+//!
+//! A set is evaluated as a unit: if it is enabled and the target is in its scope, the decisions
+//! of its matching policies are combined into one decision. That decision then counts like the
+//! decision of a single policy. Policies in a set are never evaluated on their own.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::actor::RegisteredActor;
+use crate::attribute::AttributeMap;
+use crate::policy::{Decide, Mode, RegisteredPolicyRule, TargetAction, TargetCheck};
+use crate::proto::policies as protos;
+use crate::quota::EvalBudget;
+use crate::timestamps::Timestamps;
+use crate::velocity::Velocity;
+use crate::wasm::WasmModules;
+
+/// how the decisions of a set's policies are combined
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Combine {
+    // any matching DENY wins
+    #[default]
+    DenyOverrides,
+    // any matching ALLOW wins
+    AllowOverrides,
+    // the first matching policy wins
+    FirstApplicable,
+}
+
+/// convert from proto to enum
+impl From<protos::Combine> for Combine {
+    fn from(c: protos::Combine) -> Self {
+        match c {
+            protos::Combine::DenyOverrides => Self::DenyOverrides,
+            protos::Combine::AllowOverrides => Self::AllowOverrides,
+            protos::Combine::FirstApplicable => Self::FirstApplicable,
+        }
+    }
+}
+impl From<Combine> for protos::Combine {
+    fn from(c: Combine) -> Self {
+        match c {
+            Combine::DenyOverrides => Self::DenyOverrides,
+            Combine::AllowOverrides => Self::AllowOverrides,
+            Combine::FirstApplicable => Self::FirstApplicable,
+        }
+    }
+}
+
+/// A policy set registered with Gatehouse
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RegisteredPolicySet {
+    /// the name of this set
+    pub name: String,
+    /// the optional human description
+    pub desc: Option<String>,
+    /// the targets the set applies to, or all of them if None
+    pub scope: Option<TargetCheck>,
+    /// how the decisions of the policies are combined
+    pub combine: Combine,
+    /// whether the set is evaluated at all
+    pub enabled: bool,
+    /// the names of the policies in the set, in order
+    pub policies: Vec<String>,
+    /// when the set was added and last changed
+    #[serde(flatten)]
+    pub timestamps: Timestamps,
+}
+
+impl RegisteredPolicySet {
+    /// the decision of the set, or None if it is disabled, out of scope, or none of its enforced
+    /// policies match; each policy evaluated is spent from the budget
+    #[allow(clippy::too_many_arguments)]
+    pub fn decide(
+        &self,
+        policies: &HashMap<String, RegisteredPolicyRule>,
+        actor: &RegisteredActor,
+        env_attributes: &AttributeMap,
+        target_name: &str,
+        target_type: &str,
+        target_attributes: &AttributeMap,
+        target_action: &TargetAction,
+        wasm: &WasmModules,
+        rates: &Velocity,
+        budget: &EvalBudget,
+    ) -> Option<Decide> {
+        if !self.enabled {
+            return None;
+        }
+
+        if let Some(ref scope) = self.scope {
+            if !scope.check(
+                target_name,
+                target_type,
+                target_attributes,
+                target_action,
+                &actor.attributes,
+                env_attributes,
+            ) {
+                return None;
+            }
+        }
+
+        // policies that have since been removed are skipped
+        let mut decisions = self
+            .policies
+            .iter()
+            .filter_map(|name| policies.get(name))
+            .filter(|policy| policy.mode == Mode::Enforce)
+            .take_while(|_| budget.spend())
+            .filter_map(|policy| {
+                policy.decision_for(
+                    actor,
+                    env_attributes,
+                    target_name,
+                    target_type,
+                    target_attributes,
+                    target_action,
+                    wasm,
+                    rates,
+                )
+            });
+
+        match self.combine {
+            Combine::DenyOverrides => overriding(decisions, Decide::Deny),
+            Combine::AllowOverrides => overriding(decisions, Decide::Allow),
+            Combine::FirstApplicable => decisions.next(),
+        }
+    }
+}
+
+/// the winning decision if any policy made it, otherwise INDETERMINATE if a policy couldn't be
+/// evaluated, otherwise the last decision made
+fn overriding(decisions: impl Iterator<Item = Decide>, winner: Decide) -> Option<Decide> {
+    let mut decided = None;
+    let mut indeterminate = false;
+    for decision in decisions {
+        if decision == winner {
+            return Some(decision);
+        }
+        indeterminate = indeterminate || decision == Decide::Indeterminate;
+        decided = Some(decision);
+    }
+    match indeterminate {
+        true => Some(Decide::Indeterminate),
+        false => decided,
+    }
+}
+
+/// the names of the policies that belong to a set
+pub(crate) fn members(sets: &HashMap<String, RegisteredPolicySet>) -> HashSet<&str> {
+    sets.values()
+        .flat_map(|set| set.policies.iter().map(String::as_str))
+        .collect()
+}
+
+impl Display for RegisteredPolicySet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "policyset[{}] ({} policies)",
+            self.name,
+            self.policies.len()
+        )
+    }
+}
+
+impl From<protos::PolicySet> for RegisteredPolicySet {
+    fn from(set: protos::PolicySet) -> Self {
+        let combine = set.combine();
+        Self {
+            name: set.name.to_ascii_lowercase(),
+            desc: set.desc,
+            scope: set.scope.map(TargetCheck::from),
+            combine: Combine::from(combine),
+            enabled: set.enabled,
+            policies: set
+                .policies
+                .into_iter()
+                .map(|name| name.to_ascii_lowercase())
+                .collect(),
+            timestamps: Timestamps {
+                created_at: set.created_at,
+                updated_at: set.updated_at,
+            },
+        }
+    }
+}
+
+impl From<RegisteredPolicySet> for protos::PolicySet {
+    fn from(set: RegisteredPolicySet) -> Self {
+        Self {
+            name: set.name,
+            desc: set.desc,
+            scope: set.scope.map(TargetCheck::into),
+            combine: protos::Combine::from(set.combine).into(),
+            enabled: set.enabled,
+            policies: set.policies,
+            created_at: set.timestamps.created_at,
+            updated_at: set.timestamps.updated_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, decision: Decide) -> RegisteredPolicyRule {
+        protos::PolicyRule {
+            name: name.to_string(),
+            decision: protos::Decide::from(decision).into(),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_combine() {
+        let policies: HashMap<String, RegisteredPolicyRule> =
+            [rule("allow", Decide::Allow), rule("deny", Decide::Deny)]
+                .into_iter()
+                .map(|rule| (rule.name.clone(), rule))
+                .collect();
+
+        let actor = RegisteredActor::new("jdoe", "user", AttributeMap::default());
+        let action = TargetAction::new("read");
+        let wasm = WasmModules::default();
+        let rates = Velocity::default();
+
+        let mut set = RegisteredPolicySet {
+            name: String::from("set"),
+            desc: None,
+            scope: None,
+            combine: Combine::DenyOverrides,
+            enabled: true,
+            policies: vec![String::from("allow"), String::from("deny")],
+            timestamps: Timestamps::default(),
+        };
+        let decide = |set: &RegisteredPolicySet| {
+            set.decide(
+                &policies,
+                &actor,
+                &AttributeMap::default(),
+                "db",
+                "database",
+                &AttributeMap::default(),
+                &action,
+                &wasm,
+                &rates,
+                &EvalBudget::unlimited(),
+            )
+        };
+
+        assert_eq!(decide(&set), Some(Decide::Deny));
+
+        set.combine = Combine::AllowOverrides;
+        assert_eq!(decide(&set), Some(Decide::Allow));
+
+        set.combine = Combine::FirstApplicable;
+        assert_eq!(decide(&set), Some(Decide::Allow));
+        set.policies.reverse();
+        assert_eq!(decide(&set), Some(Decide::Deny));
+
+        // a disabled set doesn't decide anything
+        set.enabled = false;
+        assert_eq!(decide(&set), None);
+
+        assert_eq!(
+            members(&HashMap::from([(set.name.clone(), set)])),
+            HashSet::from(["allow", "deny"])
+        );
+    }
+}
@@ -0,0 +1,241 @@
+#![warn(missing_docs)]
+
+//! Policy test cases written as YAML
+//!
+//! A test file holds a list of cases. Each gives an actor, a target, the environment, and the
+//! actions, and the decision the check is expected to get:
+//!
+//! ```yaml
+//! - name: readers can read the main db
+//!   actor:
+//!     name: kaitlyn
+//!     type: user
+//!     attributes:
+//!       role: [reader]
+//!   target:
+//!     name: maindb
+//!     type: database
+//!   env:
+//!     network: internal
+//!   actions: [read]
+//!   expect: allow
+//! ```
+//!
+//! If the target has `attributes`, they are used instead of those of the registered target.
+//! Attribute values can be a single string or a list of them. `expect` can be `allow`, `deny`,
+//! `not_applicable`, or `indeterminate`; `deny` is also met when no policy applies.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::policy::RegisteredPolicyRule;
+use crate::proto::actors::Actor;
+use crate::proto::base::{CheckRequest, PolicyTestCase};
+use crate::proto::common::AttributeValues;
+use crate::proto::policies::{Decide, PolicyRule};
+use crate::proto::targets::Target;
+
+/// A single test case as written in a test file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyTest {
+    name: String,
+    actor: Entity,
+    target: Entity,
+    #[serde(default)]
+    env: HashMap<String, Values>,
+    #[serde(default)]
+    actions: Vec<String>,
+    expect: Expect,
+}
+
+/// An actor or target in a test case
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entity {
+    name: String,
+    #[serde(rename = "type")]
+    typestr: String,
+    attributes: Option<HashMap<String, Values>>,
+}
+
+/// The values of an attribute, which can be written as a single one
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Values {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The decision a test case expects
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Expect {
+    Allow,
+    Deny,
+    NotApplicable,
+    Indeterminate,
+}
+
+/// Turn the attributes of a test case into attributes of a request
+fn attributes(attrs: HashMap<String, Values>) -> HashMap<String, AttributeValues> {
+    attrs
+        .into_iter()
+        .map(|(key, vals)| {
+            let values = match vals {
+                Values::One(val) => vec![val],
+                Values::Many(vals) => vals,
+            };
+            (key, AttributeValues { values })
+        })
+        .collect()
+}
+
+impl From<PolicyTest> for PolicyTestCase {
+    fn from(test: PolicyTest) -> Self {
+        let check = CheckRequest {
+            actor: Some(Actor {
+                name: test.actor.name,
+                typestr: test.actor.typestr,
+                attributes: attributes(test.actor.attributes.unwrap_or_default()),
+                ..Default::default()
+            }),
+            env_attributes: attributes(test.env),
+            target_name: test.target.name.clone(),
+            target_type: test.target.typestr.clone(),
+            target_action: test.actions,
+            ..Default::default()
+        };
+
+        // only a target that brings its own attributes replaces the registered one
+        let target = test.target.attributes.map(|attrs| Target {
+            name: test.target.name,
+            typestr: test.target.typestr,
+            attributes: attributes(attrs),
+            ..Default::default()
+        });
+
+        let expect = match test.expect {
+            Expect::Allow => Decide::Allow,
+            Expect::Deny => Decide::Deny,
+            Expect::NotApplicable => Decide::NotApplicable,
+            Expect::Indeterminate => Decide::Indeterminate,
+        };
+
+        PolicyTestCase {
+            name: test.name,
+            check: Some(check),
+            target,
+            expect: expect.into(),
+        }
+    }
+}
+
+/// Parse the test cases in a test file
+pub fn parse_tests(yaml: &str) -> Result<Vec<PolicyTestCase>, String> {
+    let tests: Vec<PolicyTest> = serde_yaml::from_str(yaml).map_err(|err| err.to_string())?;
+    Ok(tests.into_iter().map(PolicyTestCase::from).collect())
+}
+
+/// Load the test cases in a file, or in every `.yaml` and `.yml` file in a directory
+pub fn load_tests(path: &Path) -> Result<Vec<PolicyTestCase>, String> {
+    let mut cases = Vec::new();
+    for file in files(path, &["yaml", "yml"])? {
+        let yaml = read(&file)?;
+        let tests = parse_tests(&yaml).map_err(|err| format!("{}: {err}", file.display()))?;
+        cases.extend(tests);
+    }
+    Ok(cases)
+}
+
+/// Load the policy rules in a file, or in every `.json`, `.yaml`, and `.yml` file in a directory
+///
+/// Each file holds one rule in the format the file storage backend keeps them in, so its
+/// `policies` directory can be tested directly.
+pub fn load_policies(path: &Path) -> Result<Vec<PolicyRule>, String> {
+    let mut rules = Vec::new();
+    for file in files(path, &["json", "yaml", "yml"])? {
+        let contents = read(&file)?;
+        let rule: RegisteredPolicyRule =
+            serde_yaml::from_str(&contents).map_err(|err| format!("{}: {err}", file.display()))?;
+        rules.push(PolicyRule::from(rule));
+    }
+    Ok(rules)
+}
+
+/// The path itself if it is a file, otherwise the files in it with one of the extensions, in order
+fn files(path: &Path, extensions: &[&str]) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let entries =
+        fs::read_dir(path).map_err(|err| format!("Could not read {}: {err}", path.display()))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| {
+            file.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| extensions.contains(&ext))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn read(file: &Path) -> Result<String, String> {
+    fs::read_to_string(file).map_err(|err| format!("Could not read {}: {err}", file.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tests() {
+        let yaml = r#"
+- name: readers can read
+  actor:
+    name: kaitlyn
+    type: user
+    attributes:
+      role: reader
+  target:
+    name: maindb
+    type: database
+  env:
+    network: [internal, vpn]
+  actions: [read]
+  expect: allow
+- name: prod is off limits
+  actor: { name: kaitlyn, type: user }
+  target: { name: maindb, type: database, attributes: { env: prod } }
+  expect: deny
+"#;
+
+        let cases = parse_tests(yaml).unwrap();
+        assert_eq!(cases.len(), 2);
+
+        let check = cases[0].check.as_ref().unwrap();
+        let actor = check.actor.as_ref().unwrap();
+        assert_eq!(actor.attributes["role"].values, vec!["reader"]);
+        assert_eq!(
+            check.env_attributes["network"].values,
+            vec!["internal", "vpn"]
+        );
+        assert_eq!(check.target_action, vec!["read"]);
+        assert_eq!(cases[0].expect(), Decide::Allow);
+        assert!(cases[0].target.is_none());
+
+        let target = cases[1].target.as_ref().unwrap();
+        assert_eq!(target.attributes["env"].values, vec!["prod"]);
+        assert_eq!(cases[1].expect(), Decide::Deny);
+
+        // typos are caught rather than ignored
+        let yaml = "- { name: typo, actor: { name: a, type: user }, target: { name: t, type: db }, expected: allow }";
+        assert!(parse_tests(yaml).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+#![warn(missing_docs)]
+
+//! Named connection profiles for gatecli
+//!
+//! Profiles are read from `$XDG_CONFIG_HOME/gatehouse/config.toml`, or
+//! `~/.config/gatehouse/config.toml`, one table per profile:
+//!
+//! ```toml
+//! [profiles.default]
+//! host = "localhost"
+//!
+//! [profiles.prod]
+//! host = "gatehouse.prod.example.com"
+//! port = 6174
+//! tls = true
+//! token = "..."
+//! ```
+//!
+//! Unknown tables and keys are errors rather than ignored, so a typo doesn't quietly send
+//! commands to the wrong server.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+
+/// the profile used when none is asked for, if the file has one
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Where to connect, and how
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// the server's host
+    #[serde(default)]
+    pub host: Option<String>,
+    /// the server's port
+    #[serde(default, deserialize_with = "port")]
+    pub port: Option<u32>,
+    /// whether to connect with TLS
+    #[serde(default)]
+    pub tls: bool,
+    /// a token to authenticate with, sent as the API key of every call
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// The config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// Read a port, refusing numbers that can't be one
+fn port<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let port = i64::deserialize(deserializer)?;
+    u32::try_from(port)
+        .ok()
+        .filter(|port| (1..=65535).contains(port))
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("{port} is not a port")))
+}
+
+/// The config file, if there is a home directory to find it in
+pub fn default_path() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("gatehouse").join("config.toml"))
+}
+
+/// Load the profiles in a config file; a missing file has none
+pub fn load(path: &Path) -> Result<HashMap<String, Profile>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => parse(&text).map_err(|err| format!("{}: {err}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(err) => Err(format!("Could not read {}: {err}", path.display())),
+    }
+}
+
+/// Parse the profiles in a config file
+pub fn parse(text: &str) -> Result<HashMap<String, Profile>, String> {
+    toml::from_str::<ConfigFile>(text)
+        .map(|file| file.profiles)
+        .map_err(|err| err.message().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let profiles = parse(
+            r#"
+            # where our servers are
+            [profiles.default]
+            host = "localhost"
+
+            [profiles."prod-us"]
+            host = "gatehouse.prod.example.com" # the load balancer
+            port = 6_174
+            tls = true
+            token = "s3cr\"t#1"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(
+            profiles[DEFAULT_PROFILE],
+            Profile {
+                host: Some(String::from("localhost")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            profiles["prod-us"],
+            Profile {
+                host: Some(String::from("gatehouse.prod.example.com")),
+                port: Some(6174),
+                tls: true,
+                token: Some(String::from("s3cr\"t#1")),
+            }
+        );
+        assert!(parse("").unwrap().is_empty());
+
+        for (text, err) in [
+            ("host = \"x\"", "unknown field `host`"),
+            ("[servers]", "unknown field `servers`"),
+            ("[profiles.a]\nhots = \"x\"", "unknown field `hots`"),
+            ("[profiles.a]\nport = \"6174\"", "invalid type: string"),
+            ("[profiles.a]\nport = 70000", "70000 is not a port"),
+            ("[profiles.a]\n[profiles.a]", "duplicate"),
+        ] {
+            let parsed = parse(text).unwrap_err();
+            assert!(parsed.contains(err), "{text}: {parsed}");
+        }
+    }
+}
@@ -0,0 +1,87 @@
+#![warn(missing_docs)]
+
+//! Policy changes held until someone other than their proposer approves them
+//!
+//! Proposals are stored like everything else, so they outlive a restart. The change itself is a
+//! policy request, which is kept as its protobuf encoding.
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::proto::policies::Proposal;
+
+/// A proposal registered with Gatehouse
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RegisteredProposal {
+    /// identifies the proposal
+    pub id: u64,
+    /// who proposed the change
+    pub proposer: String,
+    /// when the change was proposed, in seconds since the epoch
+    pub proposed_at: u64,
+    /// the proposal, change included, as base64 encoded protobuf
+    pub change: String,
+}
+
+impl RegisteredProposal {
+    /// The key the proposal is stored under
+    pub fn key(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+impl From<&Proposal> for RegisteredProposal {
+    fn from(proposal: &Proposal) -> Self {
+        Self {
+            id: proposal.id,
+            proposer: proposal.proposer.clone(),
+            proposed_at: proposal.proposed_at,
+            change: base64::encode(proposal.encode_to_vec()),
+        }
+    }
+}
+
+impl TryFrom<&RegisteredProposal> for Proposal {
+    type Error = String;
+
+    fn try_from(proposal: &RegisteredProposal) -> Result<Self, Self::Error> {
+        let bytes = base64::decode(&proposal.change)
+            .map_err(|err| format!("Proposal {} is not base64: {err}", proposal.id))?;
+        let mut decoded = Proposal::decode(bytes.as_slice())
+            .map_err(|err| format!("Proposal {} does not decode: {err}", proposal.id))?;
+        decoded.id = proposal.id;
+        decoded.proposer = proposal.proposer.clone();
+        decoded.proposed_at = proposal.proposed_at;
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::policies::proposal::Change;
+    use crate::proto::policies::RemovePolicyRequest;
+
+    #[test]
+    fn test_round_trip() {
+        let proposal = Proposal {
+            id: 7,
+            proposer: String::from("alice"),
+            proposed_at: 1000,
+            change: Some(Change::Remove(RemovePolicyRequest {
+                name: String::from("old"),
+                ..Default::default()
+            })),
+        };
+
+        let registered = RegisteredProposal::from(&proposal);
+        assert_eq!(registered.key(), "7");
+        assert_eq!(Proposal::try_from(&registered), Ok(proposal));
+
+        let broken = RegisteredProposal {
+            change: String::from("not base64!"),
+            ..registered
+        };
+        assert!(Proposal::try_from(&broken).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+#![warn(missing_docs)]
+
+//! Resource limits enforced by the datastore
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::number_from_env;
+
+/// Limits on how much data can be registered with Gatehouse. A limit of `None` means unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quotas {
+    /// maximum number of registered actors (across all types)
+    pub max_actors: Option<usize>,
+    /// maximum number of policy rules
+    pub max_policies: Option<usize>,
+    /// maximum number of members in a single group
+    pub max_group_size: Option<usize>,
+}
+
+impl Quotas {
+    /// Build quotas from the `GATEMAXACTORS`, `GATEMAXPOLICIES`, and `GATEMAXGROUPSIZE`
+    /// environment variables. Unset variables mean no limit.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            max_actors: number_from_env("GATEMAXACTORS")?,
+            max_policies: number_from_env("GATEMAXPOLICIES")?,
+            max_group_size: number_from_env("GATEMAXGROUPSIZE")?,
+        })
+    }
+
+    /// Build the quotas of a namespace from `GATEMAXACTORS_<NAMESPACE>`, `GATEMAXPOLICIES_<..>`,
+    /// and `GATEMAXGROUPSIZE_<..>`, with the namespace in uppercase and `-` as `_`. Unset
+    /// variables keep these quotas' limits, and `unlimited` lifts one.
+    pub fn for_namespace_from_env(&self, namespace: &str) -> Result<Self, String> {
+        let suffix = namespace.to_ascii_uppercase().replace('-', "_");
+        let limit = |var: &str, default: Option<usize>| {
+            let var = format!("{var}_{suffix}");
+            match std::env::var(&var).as_deref() {
+                Ok("unlimited") => Ok(None),
+                Ok(_) => number_from_env(&var),
+                Err(_) => Ok(default),
+            }
+        };
+
+        Ok(Self {
+            max_actors: limit("GATEMAXACTORS", self.max_actors)?,
+            max_policies: limit("GATEMAXPOLICIES", self.max_policies)?,
+            max_group_size: limit("GATEMAXGROUPSIZE", self.max_group_size)?,
+        })
+    }
+}
+
+/// Limits on how much work deciding a single check can take. A limit of `None` means unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// maximum number of policy evaluations in one check, counting every rule and policy set
+    /// that is evaluated, for the actor and for anyone who delegated to them
+    pub max_policy_evals: Option<usize>,
+    /// maximum milliseconds spent deciding one check, checked before each evaluation
+    pub max_eval_millis: Option<u64>,
+    /// maximum levels of groups within groups that are followed to find an actor's groups
+    pub max_group_depth: Option<usize>,
+    /// whether a check that goes over a limit is allowed rather than denied
+    pub fail_open: bool,
+}
+
+impl EvalLimits {
+    /// Build the limits from the `GATEMAXPOLICYEVALS`, `GATEMAXEVALMILLIS`, and
+    /// `GATEMAXGROUPDEPTH` environment variables, and fail open if `GATEEVALFAILOPEN` is `true`.
+    /// Unset variables mean no limit.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            max_policy_evals: number_from_env("GATEMAXPOLICYEVALS")?,
+            max_eval_millis: number_from_env("GATEMAXEVALMILLIS")?.map(|millis| millis as u64),
+            max_group_depth: number_from_env("GATEMAXGROUPDEPTH")?,
+            fail_open: matches!(
+                std::env::var("GATEEVALFAILOPEN").as_deref(),
+                Ok("true") | Ok("1")
+            ),
+        })
+    }
+}
+
+/// The work one check may still do under its [`EvalLimits`], spent as it is decided
+///
+/// Once a limit is hit, nothing more is allowed and [`EvalBudget::exceeded`] says which one.
+#[derive(Debug, Default)]
+pub(crate) struct EvalBudget {
+    max_policy_evals: Option<usize>,
+    max_eval_millis: Option<u64>,
+    max_group_depth: Option<usize>,
+    deadline: Option<Instant>,
+    evals: AtomicUsize,
+    exceeded: Mutex<Option<String>>,
+}
+
+impl EvalBudget {
+    /// A budget for a check that starts now
+    pub(crate) fn new(limits: &EvalLimits) -> Self {
+        Self {
+            max_policy_evals: limits.max_policy_evals,
+            max_eval_millis: limits.max_eval_millis,
+            max_group_depth: limits.max_group_depth,
+            deadline: limits
+                .max_eval_millis
+                .map(|millis| Instant::now() + Duration::from_millis(millis)),
+            ..Default::default()
+        }
+    }
+
+    /// A budget with no limits, for evaluations that aren't checks
+    pub(crate) fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Spend one policy evaluation, if the budget has room for it
+    pub(crate) fn spend(&self) -> bool {
+        let evals = self.evals.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(max_policy_evals) = self.max_policy_evals {
+            if evals > max_policy_evals {
+                return self.exceed(format!(
+                    "Policy evaluation limit of {max_policy_evals} exceeded"
+                ));
+            }
+        }
+        self.in_time()
+    }
+
+    /// Whether the check is still within its time limit
+    pub(crate) fn in_time(&self) -> bool {
+        match (self.deadline, self.max_eval_millis) {
+            (Some(deadline), Some(max_eval_millis)) if Instant::now() > deadline => self.exceed(
+                format!("Evaluation time limit of {max_eval_millis}ms exceeded"),
+            ),
+            _ => !self.is_exceeded(),
+        }
+    }
+
+    /// Whether groups this many levels deep may be followed
+    pub(crate) fn allows_depth(&self, depth: usize) -> bool {
+        match self.max_group_depth {
+            Some(max_group_depth) if depth > max_group_depth => {
+                self.exceed(format!("Group nesting limit of {max_group_depth} exceeded"))
+            }
+            _ => self.in_time(),
+        }
+    }
+
+    /// Which limit was hit, if any
+    pub(crate) fn exceeded(&self) -> Option<String> {
+        self.exceeded.lock().unwrap().clone()
+    }
+
+    fn is_exceeded(&self) -> bool {
+        self.exceeded.lock().unwrap().is_some()
+    }
+
+    /// Remember the first limit that was hit; always false, so nothing more is allowed
+    fn exceed(&self, err: String) -> bool {
+        self.exceeded.lock().unwrap().get_or_insert(err);
+        false
+    }
+}
+
+impl Display for Quotas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn show(limit: Option<usize>) -> String {
+            limit.map_or_else(|| String::from("unlimited"), |l| l.to_string())
+        }
+
+        write!(
+            f,
+            "actors: {}  policies: {}  group size: {}",
+            show(self.max_actors),
+            show(self.max_policies),
+            show(self.max_group_size)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_namespace_quotas() {
+        // the variables are named for a namespace no other test uses
+        std::env::set_var("GATEMAXACTORS_QUOTA_TENANT", "10");
+        std::env::set_var("GATEMAXGROUPSIZE_QUOTA_TENANT", "unlimited");
+        let global = Quotas {
+            max_actors: Some(100),
+            max_policies: Some(50),
+            max_group_size: Some(20),
+        };
+        let tenant = global.for_namespace_from_env("quota-tenant").unwrap();
+        assert_eq!(
+            tenant,
+            Quotas {
+                max_actors: Some(10),
+                max_policies: Some(50),
+                max_group_size: None,
+            }
+        );
+
+        // a namespace without quotas of its own gets the global ones
+        let config = Config {
+            quotas: global.clone(),
+            namespace_quotas: HashMap::from([(String::from("quota-tenant"), tenant.clone())]),
+            ..Default::default()
+        };
+        assert_eq!(config.for_namespace("quota-tenant").quotas, tenant);
+        assert_eq!(config.for_namespace("other").quotas, global);
+    }
+}
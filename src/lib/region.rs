@@ -0,0 +1,349 @@
+#![warn(missing_docs)]
+
+//! Replication of changes to a Gatehouse server in another region, for disaster recovery
+//!
+//! The replicator watches the local datastore, syncs its full state, and pushes everything to
+//! the remote server with the `Replicate` RPC. After that, it pushes every change as it is made,
+//! in batches, along with the entity as it was before the change. The remote server uses that to
+//! find changes that conflict with changes made there, and deals with them as configured. If
+//! anything fails, the replicator starts over with a full sync; changes the remote server
+//! already has are skipped, so nothing is applied twice.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use flume::Sender;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::oneshot::channel;
+use tokio::time::{sleep, Duration};
+use tonic::Request;
+
+use crate::apikey::API_KEY_METADATA_KEY;
+use crate::msgs::{DsRequest, DsResponse};
+use crate::proto::base::gatehouse_client::GatehouseClient;
+use crate::proto::base::watch_event::Change;
+use crate::proto::base::{
+    ConflictPolicy, RegionStatus, ReplicateRequest, ReplicateResponse, ReplicatedChange,
+    SyncResponse, WatchEvent,
+};
+use crate::replica::{call, endpoint};
+use crate::secrets::Secret;
+
+/// how long to wait before starting over after a failure
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// the most changes sent at once
+const BATCH_SIZE: usize = 100;
+
+/// Where and how to replicate changes to another region
+#[derive(Debug, Clone)]
+pub struct RegionConfig {
+    /// the address of the Gatehouse server in the other region
+    pub remote: String,
+    /// what the remote server does with changes that conflict with changes made there
+    pub conflicts: ConflictPolicy,
+    /// the replication API key to call the remote server with, which it needs to take changes
+    pub api_key: Option<Secret>,
+}
+
+impl RegionConfig {
+    /// Build the configuration from environment variables, if replication is configured
+    ///
+    /// * `GATEREPLICATETO`: address of the Gatehouse server in the other region
+    /// * `GATEREPLICATECONFLICTS`: `source-wins` (default) or `target-wins`
+    ///
+    /// The API key to call the remote server with, `GATEREPLICATEAPIKEY`, is a secret, so it is
+    /// read along with the rest of the configuration.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let remote = match std::env::var("GATEREPLICATETO") {
+            Ok(remote) => remote,
+            Err(_) => return Ok(None),
+        };
+        let conflicts = match std::env::var("GATEREPLICATECONFLICTS").as_deref() {
+            Err(_) | Ok("source-wins") => ConflictPolicy::SourceWins,
+            Ok("target-wins") => ConflictPolicy::TargetWins,
+            Ok(val) => {
+                return Err(format!(
+                    "GATEREPLICATECONFLICTS must be source-wins or target-wins: {val}"
+                ))
+            }
+        };
+
+        Ok(Some(Self {
+            remote,
+            conflicts,
+            api_key: None,
+        }))
+    }
+}
+
+impl Display for RegionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let conflicts = match self.conflicts {
+            ConflictPolicy::SourceWins => "source wins",
+            ConflictPolicy::TargetWins => "target wins",
+        };
+        write!(f, "{} ({conflicts} conflicts)", self.remote)
+    }
+}
+
+/// How far replication has got
+#[derive(Debug, Default)]
+struct Progress {
+    /// the revision of the most recent change we have seen
+    revision: u64,
+    /// the revision of the most recent change the remote server has
+    replicated_revision: u64,
+    /// when we saw the oldest change the remote server doesn't have yet
+    waiting_since: Option<Instant>,
+    applied: u64,
+    conflicts: u64,
+    errors: u64,
+    last_error: Option<String>,
+}
+
+/// Replicates changes to another region and reports how it is doing
+#[derive(Debug)]
+pub(crate) struct RegionReplicator {
+    config: RegionConfig,
+    progress: Mutex<Progress>,
+}
+
+impl RegionReplicator {
+    fn new(config: RegionConfig) -> Self {
+        Self {
+            config,
+            progress: Mutex::new(Progress::default()),
+        }
+    }
+
+    /// Report how replication is doing
+    pub(crate) fn status(&self) -> RegionStatus {
+        let progress = self.progress.lock().unwrap();
+
+        RegionStatus {
+            remote: self.config.remote.clone(),
+            revision: progress.revision,
+            replicated_revision: progress.replicated_revision,
+            lag_seconds: progress
+                .waiting_since
+                .map(|since| since.elapsed().as_secs())
+                .unwrap_or(0),
+            applied: progress.applied,
+            conflicts: progress.conflicts,
+            errors: progress.errors,
+            last_error: progress.last_error.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Note that we have seen a change
+    fn seen(&self, revision: u64) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.revision = progress.revision.max(revision);
+        if progress.revision > progress.replicated_revision {
+            progress.waiting_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Note that the remote server has every change up to a revision
+    fn replicated(&self, revision: u64, resp: &ReplicateResponse) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.replicated_revision = revision;
+        progress.applied += u64::from(resp.applied);
+        progress.conflicts += u64::from(resp.conflicts);
+        if revision >= progress.revision {
+            progress.waiting_since = None;
+        }
+    }
+
+    fn failed(&self, err: String) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.errors += 1;
+        progress.last_error = Some(err);
+    }
+}
+
+/// Replicate changes to another region until the datastore goes away
+pub(crate) fn spawn(config: RegionConfig, dstx: Sender<DsRequest>) -> Arc<RegionReplicator> {
+    let replicator = Arc::new(RegionReplicator::new(config));
+
+    let me = replicator.clone();
+    tokio::spawn(async move {
+        while !dstx.is_disconnected() {
+            let remote = &me.config.remote;
+            match push(&me, &dstx).await {
+                Ok(_) => eprintln!("Replication to {remote} ended; syncing again"),
+                Err(err) => {
+                    eprintln!("Replication to {remote} failed: {err}");
+                    me.failed(err);
+                }
+            }
+            sleep(RETRY_DELAY).await;
+        }
+    });
+
+    replicator
+}
+
+/// Push the full state and then every change to the remote server until something fails
+async fn push(replicator: &RegionReplicator, dstx: &Sender<DsRequest>) -> Result<(), String> {
+    let config = &replicator.config;
+    let mut client = GatehouseClient::connect(endpoint(&config.remote))
+        .await
+        .map_err(|err| format!("Could not connect: {err}"))?;
+
+    // watch before syncing so no change can slip between the two
+    let (tx, rx) = channel::<DsResponse>();
+    let mut events = match call(dstx, DsRequest::Watch(tx), rx).await? {
+        DsResponse::Watcher(events) => events,
+        _ => return Err(String::from("Got unexpected answer from datastore")),
+    };
+    let (tx, rx) = channel::<DsResponse>();
+    let state = match call(dstx, DsRequest::Sync(tx), rx).await? {
+        DsResponse::SyncResult(state) => *state,
+        _ => return Err(String::from("Got unexpected answer from datastore")),
+    };
+
+    let revision = state.revision;
+    replicator.seen(revision);
+
+    // what the remote server should have, so we can tell it what each entity was before a change
+    let mut mirror = HashMap::new();
+    let puts = puts(state);
+    for batch in puts.chunks(BATCH_SIZE) {
+        let changes = batch
+            .iter()
+            .map(|change| replicated(&mut mirror, change.clone(), revision))
+            .collect();
+        let resp = send(&mut client, config, changes).await?;
+        replicator.replicated(revision, &resp);
+    }
+    println!(
+        "Replicated {} entities to {} at revision {revision}",
+        puts.len(),
+        config.remote
+    );
+
+    loop {
+        let mut batch = match events.recv().await {
+            Ok(event) => vec![event],
+            Err(RecvError::Closed) => return Ok(()),
+            Err(RecvError::Lagged(missed)) => {
+                return Err(format!("Missed {missed} changes"));
+            }
+        };
+        while batch.len() < BATCH_SIZE {
+            match events.try_recv() {
+                Ok(event) => batch.push(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => return Ok(()),
+                Err(TryRecvError::Lagged(missed)) => {
+                    return Err(format!("Missed {missed} changes"));
+                }
+            }
+        }
+
+        // the sync already has these changes
+        batch.retain(|event| event.revision > revision);
+        let last = match batch.last() {
+            Some(event) => event.revision,
+            None => continue,
+        };
+        replicator.seen(last);
+
+        let changes = batch
+            .into_iter()
+            .filter_map(|event| Some((event.change?, event.revision)))
+            .map(|(change, revision)| replicated(&mut mirror, change, revision))
+            .collect();
+        let resp = send(&mut client, config, changes).await?;
+        replicator.replicated(last, &resp);
+    }
+}
+
+/// Send changes to the remote server
+async fn send(
+    client: &mut GatehouseClient<tonic::transport::Channel>,
+    config: &RegionConfig,
+    changes: Vec<ReplicatedChange>,
+) -> Result<ReplicateResponse, String> {
+    let mut request = Request::new(ReplicateRequest {
+        changes,
+        conflicts: config.conflicts.into(),
+    });
+    if let Some(ref api_key) = config.api_key {
+        let api_key = api_key
+            .expose()
+            .parse()
+            .map_err(|_| String::from("API key is not valid metadata"))?;
+        request.metadata_mut().insert(API_KEY_METADATA_KEY, api_key);
+    }
+
+    client
+        .replicate(request)
+        .await
+        .map(|resp| resp.into_inner())
+        .map_err(|err| format!("Could not replicate: {}", err.message()))
+}
+
+/// A change along with what it changed, which is then remembered as what the remote server has
+fn replicated(
+    mirror: &mut HashMap<String, Change>,
+    change: Change,
+    revision: u64,
+) -> ReplicatedChange {
+    let (key, put) = key(&change);
+    let previous = match put {
+        true => mirror.insert(key, change.clone()),
+        false => mirror.remove(&key),
+    };
+
+    ReplicatedChange {
+        event: Some(WatchEvent {
+            revision,
+            change: Some(change),
+        }),
+        previous: previous.map(|change| WatchEvent {
+            revision: 0,
+            change: Some(change),
+        }),
+    }
+}
+
+/// The key of the entity a change is for, and whether it puts the entity
+fn key(change: &Change) -> (String, bool) {
+    match change {
+        Change::PutTarget(t) => (format!("target/{}/{}", t.typestr, t.name), true),
+        Change::PutActor(a) => (format!("actor/{}/{}", a.typestr, a.name), true),
+        Change::PutRole(r) => (format!("role/{}", r.name), true),
+        Change::PutGroup(g) => (format!("group/{}", g.name), true),
+        Change::PutPolicy(p) => (format!("policy/{}", p.name), true),
+        Change::PutPolicySet(s) => (format!("policyset/{}", s.name), true),
+        Change::PutDelegation(d) => (format!("delegation/{}", d.name), true),
+        Change::PutBreakGlass(b) => (format!("breakglass/{}", b.id), true),
+        Change::DeleteTarget(t) => (format!("target/{}/{}", t.typestr, t.name), false),
+        Change::DeleteActor(a) => (format!("actor/{}/{}", a.typestr, a.name), false),
+        Change::DeleteRole(name) => (format!("role/{name}"), false),
+        Change::DeleteGroup(name) => (format!("group/{name}"), false),
+        Change::DeletePolicy(name) => (format!("policy/{name}"), false),
+        Change::DeletePolicySet(name) => (format!("policyset/{name}"), false),
+        Change::DeleteDelegation(name) => (format!("delegation/{name}"), false),
+        Change::DeleteBreakGlass(id) => (format!("breakglass/{id}"), false),
+    }
+}
+
+/// The full state as changes that put every entity
+fn puts(state: SyncResponse) -> Vec<Change> {
+    let mut puts = Vec::new();
+    puts.extend(state.targets.into_iter().map(Change::PutTarget));
+    puts.extend(state.actors.into_iter().map(Change::PutActor));
+    puts.extend(state.roles.into_iter().map(Change::PutRole));
+    puts.extend(state.groups.into_iter().map(Change::PutGroup));
+    puts.extend(state.policies.into_iter().map(Change::PutPolicy));
+    puts.extend(state.policy_sets.into_iter().map(Change::PutPolicySet));
+    puts.extend(state.delegations.into_iter().map(Change::PutDelegation));
+    puts.extend(state.break_glasses.into_iter().map(Change::PutBreakGlass));
+    puts
+}
@@ -0,0 +1,130 @@
+#![warn(missing_docs)]
+
+//! Readable views of policy rules, and of what differs between two versions of one
+//!
+//! A rule is shown as sections (the rule itself, then its actor, target, environment, and other
+//! conditions), each a list of labelled lines. A diff compares two rules section by section: a
+//! label that appears once on both sides is shown as changed, anything else as removed or added.
+
+use std::collections::BTreeSet;
+
+use crate::policy::RegisteredPolicyRule;
+use crate::proto::policies::PolicyRule;
+
+/// The rule in the format the file storage backend keeps it in, as YAML
+pub fn yaml(rule: &PolicyRule) -> Result<String, String> {
+    serde_yaml::to_string(&RegisteredPolicyRule::from(rule.clone())).map_err(|err| err.to_string())
+}
+
+/// The rule as an indented tree, leaving out sections with nothing in them
+pub fn pretty(rule: &PolicyRule) -> String {
+    let mut text = String::new();
+    for (section, lines) in RegisteredPolicyRule::from(rule.clone()).outline() {
+        if lines.is_empty() {
+            continue;
+        }
+        text.push_str(&format!("{section}:\n"));
+        for (label, val) in lines {
+            text.push_str(&format!("  {label}: {val}\n"));
+        }
+    }
+    text
+}
+
+/// What changes from one version of a rule to another, or nothing if they are the same
+pub fn diff(from: &PolicyRule, to: &PolicyRule) -> String {
+    let from = RegisteredPolicyRule::from(from.clone()).outline();
+    let to = RegisteredPolicyRule::from(to.clone()).outline();
+
+    let mut text = String::new();
+    for ((section, old), (_, new)) in from.into_iter().zip(to) {
+        let changes = diff_lines(&old, &new);
+        if changes.is_empty() {
+            continue;
+        }
+        text.push_str(&format!("{section}:\n"));
+        for change in changes {
+            text.push_str(&format!("  {change}\n"));
+        }
+    }
+    text
+}
+
+/// The changes between the lines of a section, label by label in the order they first appear
+fn diff_lines(old: &[(&str, String)], new: &[(&str, String)]) -> Vec<String> {
+    let mut labels: Vec<&str> = Vec::new();
+    for (label, _) in old.iter().chain(new) {
+        if !labels.contains(label) {
+            labels.push(label);
+        }
+    }
+
+    let mut changes = Vec::new();
+    for label in labels {
+        let vals = |lines: &[(&str, String)]| -> Vec<String> {
+            lines
+                .iter()
+                .filter(|(l, _)| *l == label)
+                .map(|(_, val)| val.clone())
+                .collect()
+        };
+        let (old, new) = (vals(old), vals(new));
+
+        if let ([old], [new]) = (old.as_slice(), new.as_slice()) {
+            if old != new {
+                changes.push(format!("~ {label}: {old} -> {new}"));
+            }
+            continue;
+        }
+        let (old_set, new_set): (BTreeSet<&String>, BTreeSet<&String>) =
+            (old.iter().collect(), new.iter().collect());
+        changes.extend(
+            old.iter()
+                .filter(|val| !new_set.contains(val))
+                .map(|val| format!("- {label}: {val}")),
+        );
+        changes.extend(
+            new.iter()
+                .filter(|val| !old_set.contains(val))
+                .map(|val| format!("+ {label}: {val}")),
+        );
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    #[test]
+    fn test_render() {
+        let stored = dsl::parse(
+            "# engineers can read\npolicy eng-read: allow user(team=eng) to read database",
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(
+            pretty(&stored),
+            "rule:\n  name: eng-read\n  description: engineers can read\n  decision: ALLOW\n\
+             actor:\n  type: is one of [user]\n  attribute: team has one of [eng]\n\
+             target:\n  types: [database]\n  action: is one of [read]\n"
+        );
+        assert_eq!(diff(&stored, &stored), "");
+
+        let local = dsl::parse(
+            "# engineers can read\npolicy eng-read: deny user(team=eng|ops, level) to read database",
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(
+            diff(&stored, &local),
+            "rule:\n  ~ decision: ALLOW -> DENY\n\
+             actor:\n  - attribute: team has one of [eng]\n  \
+             + attribute: team has one of [eng, ops]\n  + attribute: level is set\n"
+        );
+
+        let reloaded: RegisteredPolicyRule = serde_yaml::from_str(&yaml(&local).unwrap()).unwrap();
+        assert_eq!(diff(&local, &reloaded.into()), "");
+    }
+}
@@ -0,0 +1,593 @@
+#![warn(missing_docs)]
+
+//! Recording the datastore's requests, and replaying them to reproduce bugs
+//!
+//! With `GATERECORDREQUESTS` set, every request the datastore takes is written to a file as it
+//! arrives, one JSON object a line, along with when it arrived. Protobuf messages are written as
+//! their base64 encoding. The server handles requests concurrently, so bugs that depend on the
+//! order they arrive in can be hard to reproduce; `gatehouse-replay` feeds a recording to a
+//! fresh datastore without storage one request at a time, in the order they arrived, with the
+//! clock pinned to when each one did, so it goes the same way every time.
+//!
+//! API key verifications aren't recorded, so keys don't end up in the file, but everything else
+//! is, including the attributes of checks: keep recordings as safe as the data itself.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use tokio::sync::oneshot::{channel, Receiver};
+use tonic::Status;
+
+use crate::apikey::RegisteredApiKey;
+use crate::config::Config;
+use crate::ds::{pin_clock, Datastore};
+use crate::group::RegisteredGroup;
+use crate::msgs::{DsRequest, DsResponse};
+use crate::proto::actors::{
+    AddActorRequest, AddActorsRequest, AddDelegationRequest, GetActorMembershipsRequest,
+    GetActorsRequest, GetDelegationsRequest, ModifyActorRequest, RemoveActorRequest,
+    RemoveDelegationRequest,
+};
+use crate::proto::base::{
+    ApplyTransactionRequest, BreakGlassRequest, CheckRequest, CoverageReportRequest,
+    FindUnusedRequest, GetPolicyStatsRequest, GetReferencesRequest, GetServerStatsRequest,
+    GetServerStatsResponse, ReplicateRequest, SyncResponse, TestPoliciesRequest, WhatIfRequest,
+};
+use crate::proto::groups::{
+    AddGroupRequest, BulkModifyMembershipsRequest, CloneGroupRequest, GetGroupMembersRequest,
+    GetGroupsRequest, ModifyGroupRequest, RemoveGroupRequest, RenameGroupRequest,
+};
+use crate::proto::policies::{
+    AddPolicyRequest, AddPolicySetRequest, ClonePolicyRequest, GetPoliciesRequest,
+    GetPolicySetsRequest, ModifyPolicyRequest, ModifyPolicySetRequest, PolicyBundle, Proposal,
+    RemovePolicyRequest, RemovePolicySetRequest, RenamePolicyRequest,
+};
+use crate::proto::roles::{AddRoleRequest, GetRolesRequest, ModifyRoleRequest, RemoveRoleRequest};
+use crate::proto::targets::{
+    AddTargetRequest, AddTargetsRequest, GetTargetsRequest, ModifyTargetRequest,
+    RemoveTargetRequest,
+};
+use crate::proto::webhooks::{
+    AddWebhookRequest, GetDeliveriesRequest, GetWebhooksRequest, RemoveWebhookRequest,
+};
+use crate::storage::BackendUpdate;
+use crate::StorageType;
+
+/// A protobuf message, recorded as its base64 encoding
+#[derive(Debug)]
+struct Proto<T>(T);
+
+impl<T: Message> Serialize for Proto<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(self.0.encode_to_vec()))
+    }
+}
+
+impl<'de, T: Message + Default> Deserialize<'de> for Proto<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        let bytes = base64::decode(encoded).map_err(D::Error::custom)?;
+        T::decode(bytes.as_slice())
+            .map(Proto)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// A request to the datastore, without the channel its answer goes back on
+#[derive(Debug, Serialize, Deserialize)]
+enum Recorded {
+    AddTarget(Proto<AddTargetRequest>),
+    AddTargets(Proto<AddTargetsRequest>),
+    ModifyTarget(Proto<ModifyTargetRequest>),
+    RemoveTarget(Proto<RemoveTargetRequest>),
+    GetTargets(Proto<GetTargetsRequest>),
+
+    AddActor(Proto<AddActorRequest>),
+    AddActors(Proto<AddActorsRequest>),
+    ModifyActor(Proto<ModifyActorRequest>),
+    RemoveActor(Proto<RemoveActorRequest>),
+    GetActors(Proto<GetActorsRequest>),
+    GetActorMemberships(Proto<GetActorMembershipsRequest>),
+    AddDelegation(Proto<AddDelegationRequest>),
+    RemoveDelegation(Proto<RemoveDelegationRequest>),
+    GetDelegations(Proto<GetDelegationsRequest>),
+
+    AddRole(Proto<AddRoleRequest>),
+    ModifyRole(Proto<ModifyRoleRequest>),
+    RemoveRole(Proto<RemoveRoleRequest>),
+    GetRoles(Proto<GetRolesRequest>),
+
+    AddGroup(Proto<AddGroupRequest>),
+    ModifyGroup(Proto<ModifyGroupRequest>),
+    BulkModifyMemberships(Proto<BulkModifyMembershipsRequest>),
+    RemoveGroup(Proto<RemoveGroupRequest>),
+    CloneGroup(Proto<CloneGroupRequest>),
+    RenameGroup(Proto<RenameGroupRequest>),
+    GetGroups(Proto<GetGroupsRequest>),
+    GetGroupMembers(Proto<GetGroupMembersRequest>),
+    SyncGroups(String, Vec<RegisteredGroup>),
+
+    AddPolicy(Proto<AddPolicyRequest>),
+    ModifyPolicy(Proto<ModifyPolicyRequest>),
+    RemovePolicy(Proto<RemovePolicyRequest>),
+    ClonePolicy(Proto<ClonePolicyRequest>),
+    RenamePolicy(Proto<RenamePolicyRequest>),
+    Propose(Proto<Proposal>),
+    ListProposals,
+    ApproveProposal(u64, String),
+    RejectProposal(u64),
+    GetPolicies(Proto<GetPoliciesRequest>),
+    AddPolicySet(Proto<AddPolicySetRequest>),
+    ModifyPolicySet(Proto<ModifyPolicySetRequest>),
+    RemovePolicySet(Proto<RemovePolicySetRequest>),
+    GetPolicySets(Proto<GetPolicySetsRequest>),
+
+    AddWebhook(Proto<AddWebhookRequest>),
+    RemoveWebhook(Proto<RemoveWebhookRequest>),
+    GetWebhooks(Proto<GetWebhooksRequest>),
+    GetWebhookDeliveries(Proto<GetDeliveriesRequest>),
+
+    AddApiKey(RegisteredApiKey),
+    RevokeApiKey(String),
+    GetApiKeys,
+
+    Check(Proto<CheckRequest>),
+    Approve(u64, String),
+    GetApprovals,
+    BreakGlass(Proto<BreakGlassRequest>, String),
+    GetBreakGlass,
+    TraceCheck(Proto<CheckRequest>),
+    CoverageReport(Proto<CoverageReportRequest>),
+    WhatIf(Proto<WhatIfRequest>),
+    TestPolicies(Proto<TestPoliciesRequest>),
+    GetServerStats(Proto<GetServerStatsRequest>),
+    GetServerInfo,
+    GetPolicyStats(Proto<GetPolicyStatsRequest>),
+    Update(BackendUpdate),
+    Reconcile(Vec<BackendUpdate>),
+    ApplyTransaction(Proto<ApplyTransactionRequest>),
+    ApplyBundle(Proto<PolicyBundle>, bool),
+    GetReferences(Proto<GetReferencesRequest>),
+    FindUnused(Proto<FindUnusedRequest>),
+    RemoveUnused(Proto<FindUnusedRequest>),
+    RepairDrift,
+    ReloadState,
+
+    Sync,
+    Watch,
+    StreamChanges(String),
+    Load(Proto<SyncResponse>),
+    Apply(BackendUpdate),
+    Replicate(Proto<ReplicateRequest>),
+}
+
+impl Recorded {
+    /// The request as it is recorded, or none if it isn't
+    fn from_request(req: &DsRequest) -> Option<Self> {
+        let recorded = match req {
+            DsRequest::AddTarget(req, _) => Self::AddTarget(Proto(req.clone())),
+            DsRequest::AddTargets(req, _) => Self::AddTargets(Proto(req.clone())),
+            DsRequest::ModifyTarget(req, _) => Self::ModifyTarget(Proto(req.clone())),
+            DsRequest::RemoveTarget(req, _) => Self::RemoveTarget(Proto(req.clone())),
+            DsRequest::GetTargets(req, _) => Self::GetTargets(Proto(req.clone())),
+
+            DsRequest::AddActor(req, _) => Self::AddActor(Proto(req.clone())),
+            DsRequest::AddActors(req, _) => Self::AddActors(Proto(req.clone())),
+            DsRequest::ModifyActor(req, _) => Self::ModifyActor(Proto(req.clone())),
+            DsRequest::RemoveActor(req, _) => Self::RemoveActor(Proto(req.clone())),
+            DsRequest::GetActors(req, _) => Self::GetActors(Proto(req.clone())),
+            DsRequest::GetActorMemberships(req, _) => Self::GetActorMemberships(Proto(req.clone())),
+            DsRequest::AddDelegation(req, _) => Self::AddDelegation(Proto(req.clone())),
+            DsRequest::RemoveDelegation(req, _) => Self::RemoveDelegation(Proto(req.clone())),
+            DsRequest::GetDelegations(req, _) => Self::GetDelegations(Proto(req.clone())),
+
+            DsRequest::AddRole(req, _) => Self::AddRole(Proto(req.clone())),
+            DsRequest::ModifyRole(req, _) => Self::ModifyRole(Proto(req.clone())),
+            DsRequest::RemoveRole(req, _) => Self::RemoveRole(Proto(req.clone())),
+            DsRequest::GetRoles(req, _) => Self::GetRoles(Proto(req.clone())),
+
+            DsRequest::AddGroup(req, _) => Self::AddGroup(Proto(req.clone())),
+            DsRequest::ModifyGroup(req, _) => Self::ModifyGroup(Proto(req.clone())),
+            DsRequest::BulkModifyMemberships(req, _) => {
+                Self::BulkModifyMemberships(Proto(req.clone()))
+            }
+            DsRequest::RemoveGroup(req, _) => Self::RemoveGroup(Proto(req.clone())),
+            DsRequest::CloneGroup(req, _) => Self::CloneGroup(Proto(req.clone())),
+            DsRequest::RenameGroup(req, _) => Self::RenameGroup(Proto(req.clone())),
+            DsRequest::GetGroups(req, _) => Self::GetGroups(Proto(req.clone())),
+            DsRequest::GetGroupMembers(req, _) => Self::GetGroupMembers(Proto(req.clone())),
+            DsRequest::SyncGroups(source, groups, _) => {
+                Self::SyncGroups(source.clone(), groups.clone())
+            }
+
+            DsRequest::AddPolicy(req, _) => Self::AddPolicy(Proto(req.clone())),
+            DsRequest::ModifyPolicy(req, _) => Self::ModifyPolicy(Proto(req.clone())),
+            DsRequest::RemovePolicy(req, _) => Self::RemovePolicy(Proto(req.clone())),
+            DsRequest::ClonePolicy(req, _) => Self::ClonePolicy(Proto(req.clone())),
+            DsRequest::RenamePolicy(req, _) => Self::RenamePolicy(Proto(req.clone())),
+            DsRequest::Propose(proposal, _) => Self::Propose(Proto(proposal.clone())),
+            DsRequest::ListProposals(_) => Self::ListProposals,
+            DsRequest::ApproveProposal(id, approver, _) => {
+                Self::ApproveProposal(*id, approver.clone())
+            }
+            DsRequest::RejectProposal(id, _) => Self::RejectProposal(*id),
+            DsRequest::GetPolicies(req, _) => Self::GetPolicies(Proto(req.clone())),
+            DsRequest::AddPolicySet(req, _) => Self::AddPolicySet(Proto(req.clone())),
+            DsRequest::ModifyPolicySet(req, _) => Self::ModifyPolicySet(Proto(req.clone())),
+            DsRequest::RemovePolicySet(req, _) => Self::RemovePolicySet(Proto(req.clone())),
+            DsRequest::GetPolicySets(req, _) => Self::GetPolicySets(Proto(req.clone())),
+
+            DsRequest::AddWebhook(req, _) => Self::AddWebhook(Proto(req.clone())),
+            DsRequest::RemoveWebhook(req, _) => Self::RemoveWebhook(Proto(req.clone())),
+            DsRequest::GetWebhooks(req, _) => Self::GetWebhooks(Proto(req.clone())),
+            DsRequest::GetWebhookDeliveries(req, _) => {
+                Self::GetWebhookDeliveries(Proto(req.clone()))
+            }
+
+            DsRequest::AddApiKey(key, _) => Self::AddApiKey(key.clone()),
+            DsRequest::RevokeApiKey(id, _) => Self::RevokeApiKey(id.clone()),
+            DsRequest::GetApiKeys(_) => Self::GetApiKeys,
+            // these carry the key itself, and change nothing
+            DsRequest::VerifyApiKey(..) => return None,
+
+            DsRequest::Check(req, _) => Self::Check(Proto(req.clone())),
+            DsRequest::Approve(id, approver, _) => Self::Approve(*id, approver.clone()),
+            DsRequest::GetApprovals(_) => Self::GetApprovals,
+            DsRequest::BreakGlass(req, caller, _) => {
+                Self::BreakGlass(Proto(req.clone()), caller.clone())
+            }
+            DsRequest::GetBreakGlass(_) => Self::GetBreakGlass,
+            DsRequest::TraceCheck(req, _) => Self::TraceCheck(Proto(req.clone())),
+            DsRequest::CoverageReport(req, _) => Self::CoverageReport(Proto(req.clone())),
+            DsRequest::WhatIf(req, _) => Self::WhatIf(Proto(req.clone())),
+            DsRequest::TestPolicies(req, _) => Self::TestPolicies(Proto(req.clone())),
+            DsRequest::GetServerStats(req, _) => Self::GetServerStats(Proto(req.clone())),
+            DsRequest::GetServerInfo(_) => Self::GetServerInfo,
+            DsRequest::GetPolicyStats(req, _) => Self::GetPolicyStats(Proto(req.clone())),
+            DsRequest::Update(update) => Self::Update(update.clone()),
+            DsRequest::Reconcile(updates, _) => Self::Reconcile(updates.clone()),
+            DsRequest::ApplyTransaction(req, _) => Self::ApplyTransaction(Proto(req.clone())),
+            DsRequest::ApplyBundle(bundle, dry_run, _) => {
+                Self::ApplyBundle(Proto(bundle.clone()), *dry_run)
+            }
+            DsRequest::GetReferences(req, _) => Self::GetReferences(Proto(req.clone())),
+            DsRequest::FindUnused(req, _) => Self::FindUnused(Proto(req.clone())),
+            DsRequest::RemoveUnused(req, _) => Self::RemoveUnused(Proto(req.clone())),
+            DsRequest::RepairDrift(_) => Self::RepairDrift,
+            DsRequest::ReloadState(_) => Self::ReloadState,
+
+            DsRequest::Sync(_) => Self::Sync,
+            DsRequest::Watch(_) => Self::Watch,
+            DsRequest::StreamChanges(token, _) => Self::StreamChanges(token.clone()),
+            DsRequest::Load(state, _) => Self::Load(Proto(*state.clone())),
+            DsRequest::Apply(update, _) => Self::Apply(update.clone()),
+            DsRequest::Replicate(req, _) => Self::Replicate(Proto(req.clone())),
+        };
+        Some(recorded)
+    }
+
+    /// The request to make again, and where its answer comes back, if it has one
+    fn into_request(self) -> (DsRequest, Option<Receiver<DsResponse>>) {
+        let (tx, rx) = channel();
+        let req = match self {
+            Self::AddTarget(Proto(req)) => DsRequest::AddTarget(req, tx),
+            Self::AddTargets(Proto(req)) => DsRequest::AddTargets(req, tx),
+            Self::ModifyTarget(Proto(req)) => DsRequest::ModifyTarget(req, tx),
+            Self::RemoveTarget(Proto(req)) => DsRequest::RemoveTarget(req, tx),
+            Self::GetTargets(Proto(req)) => DsRequest::GetTargets(req, tx),
+
+            Self::AddActor(Proto(req)) => DsRequest::AddActor(req, tx),
+            Self::AddActors(Proto(req)) => DsRequest::AddActors(req, tx),
+            Self::ModifyActor(Proto(req)) => DsRequest::ModifyActor(req, tx),
+            Self::RemoveActor(Proto(req)) => DsRequest::RemoveActor(req, tx),
+            Self::GetActors(Proto(req)) => DsRequest::GetActors(req, tx),
+            Self::GetActorMemberships(Proto(req)) => DsRequest::GetActorMemberships(req, tx),
+            Self::AddDelegation(Proto(req)) => DsRequest::AddDelegation(req, tx),
+            Self::RemoveDelegation(Proto(req)) => DsRequest::RemoveDelegation(req, tx),
+            Self::GetDelegations(Proto(req)) => DsRequest::GetDelegations(req, tx),
+
+            Self::AddRole(Proto(req)) => DsRequest::AddRole(req, tx),
+            Self::ModifyRole(Proto(req)) => DsRequest::ModifyRole(req, tx),
+            Self::RemoveRole(Proto(req)) => DsRequest::RemoveRole(req, tx),
+            Self::GetRoles(Proto(req)) => DsRequest::GetRoles(req, tx),
+
+            Self::AddGroup(Proto(req)) => DsRequest::AddGroup(req, tx),
+            Self::ModifyGroup(Proto(req)) => DsRequest::ModifyGroup(req, tx),
+            Self::BulkModifyMemberships(Proto(req)) => DsRequest::BulkModifyMemberships(req, tx),
+            Self::RemoveGroup(Proto(req)) => DsRequest::RemoveGroup(req, tx),
+            Self::CloneGroup(Proto(req)) => DsRequest::CloneGroup(req, tx),
+            Self::RenameGroup(Proto(req)) => DsRequest::RenameGroup(req, tx),
+            Self::GetGroups(Proto(req)) => DsRequest::GetGroups(req, tx),
+            Self::GetGroupMembers(Proto(req)) => DsRequest::GetGroupMembers(req, tx),
+            Self::SyncGroups(source, groups) => DsRequest::SyncGroups(source, groups, tx),
+
+            Self::AddPolicy(Proto(req)) => DsRequest::AddPolicy(req, tx),
+            Self::ModifyPolicy(Proto(req)) => DsRequest::ModifyPolicy(req, tx),
+            Self::RemovePolicy(Proto(req)) => DsRequest::RemovePolicy(req, tx),
+            Self::ClonePolicy(Proto(req)) => DsRequest::ClonePolicy(req, tx),
+            Self::RenamePolicy(Proto(req)) => DsRequest::RenamePolicy(req, tx),
+            Self::Propose(Proto(proposal)) => DsRequest::Propose(proposal, tx),
+            Self::ListProposals => DsRequest::ListProposals(tx),
+            Self::ApproveProposal(id, approver) => DsRequest::ApproveProposal(id, approver, tx),
+            Self::RejectProposal(id) => DsRequest::RejectProposal(id, tx),
+            Self::GetPolicies(Proto(req)) => DsRequest::GetPolicies(req, tx),
+            Self::AddPolicySet(Proto(req)) => DsRequest::AddPolicySet(req, tx),
+            Self::ModifyPolicySet(Proto(req)) => DsRequest::ModifyPolicySet(req, tx),
+            Self::RemovePolicySet(Proto(req)) => DsRequest::RemovePolicySet(req, tx),
+            Self::GetPolicySets(Proto(req)) => DsRequest::GetPolicySets(req, tx),
+
+            Self::AddWebhook(Proto(req)) => DsRequest::AddWebhook(req, tx),
+            Self::RemoveWebhook(Proto(req)) => DsRequest::RemoveWebhook(req, tx),
+            Self::GetWebhooks(Proto(req)) => DsRequest::GetWebhooks(req, tx),
+            Self::GetWebhookDeliveries(Proto(req)) => DsRequest::GetWebhookDeliveries(req, tx),
+
+            Self::AddApiKey(key) => DsRequest::AddApiKey(key, tx),
+            Self::RevokeApiKey(id) => DsRequest::RevokeApiKey(id, tx),
+            Self::GetApiKeys => DsRequest::GetApiKeys(tx),
+
+            Self::Check(Proto(req)) => DsRequest::Check(req, tx),
+            Self::Approve(id, approver) => DsRequest::Approve(id, approver, tx),
+            Self::GetApprovals => DsRequest::GetApprovals(tx),
+            Self::BreakGlass(Proto(req), caller) => DsRequest::BreakGlass(req, caller, tx),
+            Self::GetBreakGlass => DsRequest::GetBreakGlass(tx),
+            Self::TraceCheck(Proto(req)) => DsRequest::TraceCheck(req, tx),
+            Self::CoverageReport(Proto(req)) => DsRequest::CoverageReport(req, tx),
+            Self::WhatIf(Proto(req)) => DsRequest::WhatIf(req, tx),
+            Self::TestPolicies(Proto(req)) => DsRequest::TestPolicies(req, tx),
+            Self::GetServerStats(Proto(req)) => DsRequest::GetServerStats(req, tx),
+            Self::GetServerInfo => DsRequest::GetServerInfo(tx),
+            Self::GetPolicyStats(Proto(req)) => DsRequest::GetPolicyStats(req, tx),
+            Self::Update(update) => return (DsRequest::Update(update), None),
+            Self::Reconcile(updates) => DsRequest::Reconcile(updates, tx),
+            Self::ApplyTransaction(Proto(req)) => DsRequest::ApplyTransaction(req, tx),
+            Self::ApplyBundle(Proto(bundle), dry_run) => {
+                DsRequest::ApplyBundle(bundle, dry_run, tx)
+            }
+            Self::GetReferences(Proto(req)) => DsRequest::GetReferences(req, tx),
+            Self::FindUnused(Proto(req)) => DsRequest::FindUnused(req, tx),
+            Self::RemoveUnused(Proto(req)) => DsRequest::RemoveUnused(req, tx),
+            Self::RepairDrift => DsRequest::RepairDrift(tx),
+            Self::ReloadState => DsRequest::ReloadState(tx),
+
+            Self::Sync => DsRequest::Sync(tx),
+            Self::Watch => DsRequest::Watch(tx),
+            Self::StreamChanges(token) => DsRequest::StreamChanges(token, tx),
+            Self::Load(Proto(state)) => DsRequest::Load(Box::new(state), tx),
+            Self::Apply(update) => DsRequest::Apply(update, tx),
+            Self::Replicate(Proto(req)) => DsRequest::Replicate(req, tx),
+        };
+        (req, Some(rx))
+    }
+}
+
+/// A request as it is written to a recording
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry<R> {
+    /// when the datastore took it, in milliseconds since the epoch
+    at: u64,
+    /// the request
+    request: R,
+}
+
+/// Writes the datastore's requests to a file as they arrive
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl Recorder {
+    /// Record to a file, adding to what it already has
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("Could not open {path} to record requests to: {err}"))?;
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Record a request, if it is one that is recorded
+    ///
+    /// Each request is flushed as it is written, so a recording survives the server crashing.
+    pub fn record(&self, req: &DsRequest) {
+        let Some(request) = Recorded::from_request(req) else {
+            return;
+        };
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or_default();
+        let line = match serde_json::to_string(&Entry { at, request }) {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("Could not record request: {err}");
+                return;
+            }
+        };
+
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        if let Err(err) = writeln!(file, "{line}").and_then(|_| file.flush()) {
+            eprintln!("Could not record request: {err}");
+        }
+    }
+}
+
+/// How one replayed request went
+#[derive(Debug)]
+pub struct Outcome {
+    /// the line of the recording it was on
+    pub line: usize,
+    /// when it was recorded, in milliseconds since the epoch
+    pub at: u64,
+    /// the kind of request, e.g. `AddTarget`
+    pub request: String,
+    /// the error it got, if it got one
+    pub error: Option<Status>,
+}
+
+/// Replay a recording against a fresh datastore without storage, telling `report` how each
+/// request went, and return the datastore's stats at the end
+///
+/// The config is used as it is, other than for recording and storage. Requests are replayed
+/// one at a time, each only once the one before it has been answered, and the clock is pinned
+/// to when each was recorded until the replay is done. Lines past `until` are left out.
+pub async fn replay(
+    path: &Path,
+    mut config: Config,
+    until: Option<usize>,
+    mut report: impl FnMut(&Outcome),
+) -> Result<GetServerStatsResponse, String> {
+    let file = File::open(path)
+        .map_err(|err| format!("Could not open recording {}: {err}", path.display()))?;
+    config.record_requests = None;
+    config.sample_decisions = None;
+    let (dstx, _, _) = Datastore::create(&StorageType::Nil, config).await;
+
+    for (pos, line) in BufReader::new(file).lines().enumerate() {
+        let line_no = pos + 1;
+        if until.is_some_and(|until| line_no > until) {
+            break;
+        }
+        let line = line.map_err(|err| format!("Could not read line {line_no}: {err}"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry<Value> =
+            serde_json::from_str(&line).map_err(|err| format!("Line {line_no}: {err}"))?;
+        let request = match entry.request {
+            Value::String(ref name) => name.clone(),
+            Value::Object(ref fields) => fields.keys().next().cloned().unwrap_or_default(),
+            _ => String::new(),
+        };
+        let recorded: Recorded = serde_json::from_value(entry.request)
+            .map_err(|err| format!("Line {line_no}: {err}"))?;
+
+        pin_clock(entry.at / 1000);
+        let (req, rx) = recorded.into_request();
+        dstx.send_async(req)
+            .await
+            .map_err(|_| String::from("The datastore stopped"))?;
+        let error = match rx {
+            Some(rx) => match rx.await {
+                Ok(DsResponse::Error(status)) => Some(status),
+                Ok(_) => None,
+                Err(_) => Some(Status::internal("The datastore didn't answer")),
+            },
+            None => None,
+        };
+        report(&Outcome {
+            line: line_no,
+            at: entry.at,
+            request,
+            error,
+        });
+    }
+
+    let (tx, rx) = channel();
+    let stats = dstx
+        .send_async(DsRequest::GetServerStats(GetServerStatsRequest {}, tx))
+        .await
+        .map_err(|_| String::from("The datastore stopped"));
+    let stats = match stats {
+        Ok(_) => match rx.await {
+            Ok(DsResponse::ServerStats(stats)) => Ok(stats),
+            _ => Err(String::from("Could not get the datastore's stats")),
+        },
+        Err(err) => Err(err),
+    };
+    pin_clock(0);
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::actors::Actor;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay() {
+        let path = std::env::temp_dir().join(format!("gatehouse-replay-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let recorder = Recorder::open(path.to_str().unwrap()).unwrap();
+
+        let (tx, _) = channel();
+        recorder.record(&DsRequest::AddTarget(
+            AddTargetRequest {
+                name: String::from("maindb"),
+                typestr: String::from("database"),
+                actions: vec![String::from("read")],
+                ..Default::default()
+            },
+            tx,
+        ));
+        let (tx, _) = channel();
+        recorder.record(&DsRequest::VerifyApiKey(Some(String::from("secret")), tx));
+        let (tx, _) = channel();
+        recorder.record(&DsRequest::RemoveRole(
+            RemoveRoleRequest {
+                name: String::from("nope"),
+                ..Default::default()
+            },
+            tx,
+        ));
+        let (tx, _) = channel();
+        recorder.record(&DsRequest::Check(
+            CheckRequest {
+                actor: Some(Actor {
+                    name: String::from("kaitlyn"),
+                    typestr: String::from("user"),
+                    ..Default::default()
+                }),
+                target_name: String::from("maindb"),
+                target_type: String::from("database"),
+                target_action: vec![String::from("read")],
+                ..Default::default()
+            },
+            tx,
+        ));
+        drop(recorder);
+
+        // API keys are never written down
+        let recording = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(recording.lines().count(), 3);
+        assert!(!recording.contains("secret"));
+
+        // the requests are replayed in order, and go the same way every time
+        for _ in 0..2 {
+            let mut outcomes = Vec::new();
+            let stats = replay(&path, Config::default(), None, |outcome| {
+                outcomes.push((outcome.request.clone(), outcome.error.is_some()))
+            })
+            .await
+            .unwrap();
+            let expected = vec![
+                (String::from("AddTarget"), false),
+                (String::from("RemoveRole"), true),
+                (String::from("Check"), false),
+            ];
+            assert_eq!(outcomes, expected);
+            assert_eq!(stats.targets, 1);
+            assert_eq!(stats.checks, 1);
+        }
+
+        // and can stop partway
+        let stats = replay(&path, Config::default(), Some(2), |_| {})
+            .await
+            .unwrap();
+        assert_eq!(stats.checks, 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
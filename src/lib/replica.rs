@@ -0,0 +1,285 @@
+#![warn(missing_docs)]
+
+//! Replication of the state of another Gatehouse server
+//!
+//! A replica keeps no storage of its own. It opens a watch on the primary, syncs the full state,
+//! and then applies every change streamed after that sync. If the stream breaks, for instance
+//! because the primary restarted or the replica fell too far behind, it syncs again. If the
+//! primary requires API keys, the replica calls it with the one it is given.
+
+use flume::Sender;
+use tokio::sync::oneshot::{channel, Receiver};
+use tokio::time::{sleep, Duration};
+use tonic::Request;
+
+use crate::apikey::API_KEY_METADATA_KEY;
+use crate::msgs::{DsRequest, DsResponse};
+use crate::proto::actors::Actor;
+use crate::proto::base::gatehouse_client::GatehouseClient;
+use crate::proto::base::watch_event::Change;
+use crate::proto::base::{SyncRequest, WatchRequest};
+use crate::proto::targets::Target;
+use crate::secrets::Secret;
+use crate::storage::BackendUpdate;
+
+/// how long to wait before reconnecting to the primary
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Keep the datastore in sync with a primary server until the datastore goes away
+pub(crate) fn spawn(primary: String, api_key: Option<Secret>, dstx: Sender<DsRequest>) {
+    tokio::spawn(async move {
+        while !dstx.is_disconnected() {
+            match follow(&primary, api_key.as_ref(), &dstx).await {
+                Ok(_) => eprintln!("Replication from {primary} ended; syncing again"),
+                Err(err) => eprintln!("Replication from {primary} failed: {err}"),
+            }
+            sleep(RETRY_DELAY).await;
+        }
+    });
+}
+
+/// Sync the state of the primary and apply its changes until the watch stream ends
+async fn follow(
+    primary: &str,
+    api_key: Option<&Secret>,
+    dstx: &Sender<DsRequest>,
+) -> Result<(), String> {
+    let mut client = GatehouseClient::connect(endpoint(primary))
+        .await
+        .map_err(|err| format!("Could not connect: {err}"))?;
+
+    // watch before syncing so no change can slip between the two
+    let mut events = client
+        .watch(with_api_key(WatchRequest {}, api_key)?)
+        .await
+        .map_err(|err| format!("Could not watch: {err}"))?
+        .into_inner();
+    let state = client
+        .sync(with_api_key(SyncRequest {}, api_key)?)
+        .await
+        .map_err(|err| format!("Could not sync: {err}"))?
+        .into_inner();
+
+    let (tx, rx) = channel::<DsResponse>();
+    let revision = match call(dstx, DsRequest::Load(Box::new(state), tx), rx).await? {
+        DsResponse::Loaded(revision) => revision,
+        _ => return Err(String::from("Got unexpected answer from datastore")),
+    };
+    println!("Replicating {primary} from revision {revision}");
+
+    while let Some(event) = events.message().await.map_err(|err| err.to_string())? {
+        // the sync already has this change
+        if event.revision <= revision {
+            continue;
+        }
+
+        if let Some(change) = event.change {
+            let (tx, rx) = channel::<DsResponse>();
+            call(dstx, DsRequest::Apply(change.into(), tx), rx).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A request to the primary, carrying the API key if there is one
+fn with_api_key<T>(message: T, api_key: Option<&Secret>) -> Result<Request<T>, String> {
+    let mut request = Request::new(message);
+    if let Some(api_key) = api_key {
+        let api_key = api_key
+            .expose()
+            .parse()
+            .map_err(|_| String::from("API key is not valid metadata"))?;
+        request.metadata_mut().insert(API_KEY_METADATA_KEY, api_key);
+    }
+    Ok(request)
+}
+
+/// Send a request to the datastore and wait for the answer
+pub(crate) async fn call(
+    dstx: &Sender<DsRequest>,
+    req: DsRequest,
+    rx: Receiver<DsResponse>,
+) -> Result<DsResponse, String> {
+    dstx.send_async(req)
+        .await
+        .map_err(|err| format!("Could not reach datastore: {err}"))?;
+
+    match rx.await {
+        Ok(DsResponse::Error(status)) => Err(status.message().to_string()),
+        Ok(resp) => Ok(resp),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Turn an address into a url we can connect to, assuming plain http if there is no scheme
+pub(crate) fn endpoint(addr: &str) -> String {
+    if addr.contains("://") {
+        addr.to_string()
+    } else {
+        format!("http://{addr}")
+    }
+}
+
+/// The change to send to watchers for an update, if it is replicated
+pub(crate) fn watch_change(update: &BackendUpdate) -> Option<Change> {
+    let change = match update {
+        BackendUpdate::PutActor(actor) => Change::PutActor(actor.clone().into()),
+        BackendUpdate::PutGroup(group) => Change::PutGroup(group.clone().into()),
+        BackendUpdate::PutPolicyRule(rule) => Change::PutPolicy((**rule).clone().into()),
+        BackendUpdate::PutPolicySet(set) => Change::PutPolicySet(set.clone().into()),
+        BackendUpdate::PutDelegation(delegation) => {
+            Change::PutDelegation(delegation.clone().into())
+        }
+        BackendUpdate::PutRole(role) => Change::PutRole(role.clone().into()),
+        BackendUpdate::PutTarget(target) => Change::PutTarget(target.clone().into()),
+        BackendUpdate::DeleteActor(typestr, name) => Change::DeleteActor(Actor {
+            name: name.clone(),
+            typestr: typestr.clone(),
+            ..Default::default()
+        }),
+        BackendUpdate::DeleteGroup(name) => Change::DeleteGroup(name.clone()),
+        BackendUpdate::DeletePolicyRule(name) => Change::DeletePolicy(name.clone()),
+        BackendUpdate::DeletePolicySet(name) => Change::DeletePolicySet(name.clone()),
+        BackendUpdate::DeleteDelegation(name) => Change::DeleteDelegation(name.clone()),
+        BackendUpdate::PutBreakGlass(break_glass) => {
+            Change::PutBreakGlass(break_glass.clone().into())
+        }
+        BackendUpdate::DeleteBreakGlass(id) => Change::DeleteBreakGlass(id.parse().ok()?),
+        BackendUpdate::DeleteRole(name) => Change::DeleteRole(name.clone()),
+        BackendUpdate::DeleteTarget(typestr, name) => Change::DeleteTarget(Target {
+            name: name.clone(),
+            typestr: typestr.clone(),
+            ..Default::default()
+        }),
+        BackendUpdate::PutWebhook(_)
+        | BackendUpdate::DeleteWebhook(_)
+        | BackendUpdate::PutApiKey(_)
+        | BackendUpdate::DeleteApiKey(_)
+        | BackendUpdate::PutProposal(_)
+        | BackendUpdate::DeleteProposal(_)
+        | BackendUpdate::PutBundle(_) => return None,
+    };
+
+    Some(change)
+}
+
+impl From<Change> for BackendUpdate {
+    fn from(change: Change) -> Self {
+        match change {
+            Change::PutTarget(target) => BackendUpdate::PutTarget(target.into()),
+            Change::PutActor(actor) => BackendUpdate::PutActor(actor.into()),
+            Change::PutRole(role) => BackendUpdate::PutRole(role.into()),
+            Change::PutGroup(group) => BackendUpdate::PutGroup(group.into()),
+            Change::PutPolicy(rule) => BackendUpdate::PutPolicyRule(Box::new(rule.into())),
+            Change::PutPolicySet(set) => BackendUpdate::PutPolicySet(set.into()),
+            Change::PutDelegation(delegation) => BackendUpdate::PutDelegation(delegation.into()),
+            Change::DeleteTarget(target) => BackendUpdate::DeleteTarget(
+                target.typestr.to_ascii_lowercase(),
+                target.name.to_ascii_lowercase(),
+            ),
+            Change::DeleteActor(actor) => BackendUpdate::DeleteActor(
+                actor.typestr.to_ascii_lowercase(),
+                actor.name.to_ascii_lowercase(),
+            ),
+            Change::DeleteRole(name) => BackendUpdate::DeleteRole(name.to_ascii_lowercase()),
+            Change::DeleteGroup(name) => BackendUpdate::DeleteGroup(name.to_ascii_lowercase()),
+            Change::DeletePolicy(name) => BackendUpdate::DeletePolicyRule(name),
+            Change::DeletePolicySet(name) => {
+                BackendUpdate::DeletePolicySet(name.to_ascii_lowercase())
+            }
+            Change::DeleteDelegation(name) => {
+                BackendUpdate::DeleteDelegation(name.to_ascii_lowercase())
+            }
+            Change::PutBreakGlass(break_glass) => BackendUpdate::PutBreakGlass(break_glass.into()),
+            Change::DeleteBreakGlass(id) => BackendUpdate::DeleteBreakGlass(id.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::test;
+    use tokio::time::Instant;
+
+    use crate::config::Config;
+    use crate::model::{PolicyRuleBuilder, StringCheck, TargetCheckBuilder};
+    use crate::proto::base::gatehouse_server::Gatehouse;
+    use crate::proto::base::{ApiKeyScope, CreateApiKeyRequest};
+    use crate::proto::policies::{AddPolicyRequest, Decide};
+    use crate::svc::GatehouseSvc;
+    use crate::testing::{ActorBuilder, CheckBuilder, TestHarness};
+    use crate::StorageType;
+
+    use super::*;
+
+    #[test]
+    async fn test_follow_with_api_key() {
+        let admin = Secret::from("ghk_0123_secret");
+        let config = Config {
+            api_keys_required: true,
+            bootstrap_api_key: Some(admin.clone()),
+            ..Default::default()
+        };
+        let primary = TestHarness::with_config(config).await.unwrap();
+        let addr = primary.addr().to_string();
+
+        // without a key, the primary won't even let us watch
+        let (dstx, _dsrx) = flume::unbounded();
+        let err = follow(&addr, None, &dstx).await.unwrap_err();
+        assert!(err.starts_with("Could not watch"), "{err}");
+
+        let req = CreateApiKeyRequest {
+            name: String::from("sidecar"),
+            scope: ApiKeyScope::ReadOnly.into(),
+            principal: String::from("replica/sidecar"),
+            ..Default::default()
+        };
+        let key = primary
+            .client()
+            .create_api_key(with_api_key(req, Some(&admin)).unwrap())
+            .await
+            .unwrap()
+            .into_inner()
+            .secret;
+        let read = StringCheck::OneOf(vec![String::from("read")]);
+        let rule = PolicyRuleBuilder::new("readers")
+            .target(TargetCheckBuilder::new().action(read).build())
+            .build();
+        let req = AddPolicyRequest {
+            rule: Some(rule.into()),
+            dry_run: false,
+        };
+        primary
+            .client()
+            .add_policy(with_api_key(req, Some(&admin)).unwrap())
+            .await
+            .unwrap();
+
+        // a read-only key is enough to follow the primary
+        let config = Config {
+            replica_of: Some(addr),
+            primary_api_key: Some(Secret::from(key)),
+            ..Default::default()
+        };
+        let replica = GatehouseSvc::with_config(&StorageType::Nil, config).await;
+        let check = || {
+            let actor = ActorBuilder::new("user", "kaitlyn").build();
+            CheckBuilder::new(actor, "database", "db")
+                .action("read")
+                .build()
+        };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let resp = replica.check(Request::new(check())).await.unwrap();
+            if resp.get_ref().decision() == Decide::Allow {
+                break;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "the replica never got the policy"
+            );
+            sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
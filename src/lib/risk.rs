@@ -0,0 +1,369 @@
+#![warn(missing_docs)]
+
+//! Risk scores for check requests
+//!
+//! When risk scoring is configured, every check is given a score: the sum of the weights of the
+//! signals that fire for it. The score is set as the `risk` environment attribute, so policies can
+//! check it with a number check, e.g. deny deletes when the risk is more than 50. A `risk` the PEP
+//! sends is always dropped, even when scoring isn't configured. Scores above zero are logged with
+//! the signals that made them up.
+//!
+//! Two kinds of signal can be configured in a JSON file:
+//!
+//! * `new_value`: an environment attribute has a value the actor hasn't been seen with before,
+//!   e.g. a `device` attribute for a new device
+//! * `unusual_value`: an environment attribute has none of a list of usual values, or isn't set,
+//!   e.g. a `country` outside the usual ones
+//!
+//! Embedders can add signals of their own by implementing [`RiskSignal`].
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display};
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+
+use crate::attribute::AttributeMap;
+
+/// the environment attribute the score is set as
+pub const RISK_ATTRIBUTE: &str = "risk";
+
+/// the most actors a `new_value` signal remembers values for
+const MAX_ACTORS: usize = 1_000_000;
+
+/// the most values a `new_value` signal remembers for one actor
+const MAX_VALUES: usize = 100;
+
+/// What a signal can see of a check
+#[derive(Debug)]
+pub struct RiskInput<'a> {
+    /// the type of the actor
+    pub actor_type: &'a str,
+    /// the name of the actor
+    pub actor_name: &'a str,
+    /// the actor's attributes, including the ones Gatehouse added
+    pub actor_attributes: &'a AttributeMap,
+    /// the environment attributes sent with the check
+    pub env_attributes: &'a AttributeMap,
+    /// the type of the target
+    pub target_type: &'a str,
+    /// the name of the target
+    pub target_name: &'a str,
+    /// the actions checked
+    pub actions: &'a [String],
+}
+
+/// Something about a check that makes it riskier
+pub trait RiskSignal: Send + Sync {
+    /// The name the signal is logged under
+    fn name(&self) -> &str;
+
+    /// How much the signal adds to the risk of a check; 0 if it doesn't fire
+    fn score(&self, input: &RiskInput) -> u32;
+
+    /// Learn from a check once it is scored; only called for checks, not traces
+    fn observe(&self, _input: &RiskInput) {}
+}
+
+/// How risk is scored
+#[derive(Clone, Default, Deserialize)]
+pub struct RiskConfig {
+    /// the signals described in the config file
+    #[serde(default)]
+    pub signals: Vec<SignalConfig>,
+    /// signals implemented by an embedder
+    #[serde(skip)]
+    pub custom: Vec<Arc<dyn RiskSignal>>,
+}
+
+impl RiskConfig {
+    /// Load the config from a JSON file
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read risk config {path}: {err}"))?;
+
+        serde_json::from_str(&contents)
+            .map_err(|err| format!("Could not parse risk config {path}: {err}"))
+    }
+}
+
+impl Debug for RiskConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RiskConfig")
+            .field("signals", &self.signals)
+            .field("custom", &self.custom.len())
+            .finish()
+    }
+}
+
+impl Display for RiskConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} signals, {} custom",
+            self.signals.len(),
+            self.custom.len()
+        )
+    }
+}
+
+/// A signal described in the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SignalConfig {
+    /// an environment attribute has a value the actor hasn't been seen with before
+    NewValue {
+        /// the environment attribute
+        key: String,
+        /// how much the signal adds to the score
+        weight: u32,
+    },
+    /// an environment attribute has none of the usual values
+    UnusualValue {
+        /// the environment attribute
+        key: String,
+        /// the values that aren't unusual
+        usual: Vec<String>,
+        /// how much the signal adds to the score
+        weight: u32,
+    },
+}
+
+/// Fires when an environment attribute has a value the actor hasn't been seen with
+///
+/// The first check an actor makes with the attribute set doesn't fire, since there is nothing to
+/// compare it to yet.
+struct NewValue {
+    name: String,
+    key: String,
+    weight: u32,
+    /// the values seen, by actor
+    seen: Mutex<HashMap<String, HashSet<Arc<str>>>>,
+}
+
+impl NewValue {
+    fn actor_key(input: &RiskInput) -> String {
+        format!(
+            "{}/{}",
+            input.actor_type.to_ascii_lowercase(),
+            input.actor_name.to_ascii_lowercase()
+        )
+    }
+}
+
+impl RiskSignal for NewValue {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn score(&self, input: &RiskInput) -> u32 {
+        let vals = match input.env_attributes.get(self.key.as_str()) {
+            Some(vals) if !vals.is_empty() => vals,
+            _ => return 0,
+        };
+        let seen = self.seen.lock().unwrap();
+        match seen.get(&Self::actor_key(input)) {
+            Some(known) if !vals.iter().all(|val| known.contains(val)) => self.weight,
+            _ => 0,
+        }
+    }
+
+    fn observe(&self, input: &RiskInput) {
+        let vals = match input.env_attributes.get(self.key.as_str()) {
+            Some(vals) => vals,
+            None => return,
+        };
+        let mut seen = self.seen.lock().unwrap();
+        let actor_key = Self::actor_key(input);
+        if seen.len() >= MAX_ACTORS && !seen.contains_key(&actor_key) {
+            return;
+        }
+        let known = seen.entry(actor_key).or_default();
+        for val in vals {
+            if known.len() >= MAX_VALUES {
+                break;
+            }
+            known.insert(val.clone());
+        }
+    }
+}
+
+/// Fires when an environment attribute has none of the usual values
+struct UnusualValue {
+    name: String,
+    key: String,
+    usual: HashSet<String>,
+    weight: u32,
+}
+
+impl RiskSignal for UnusualValue {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn score(&self, input: &RiskInput) -> u32 {
+        let usual = input
+            .env_attributes
+            .get(self.key.as_str())
+            .is_some_and(|vals| vals.iter().any(|val| self.usual.contains(val.as_ref())));
+        match usual {
+            true => 0,
+            false => self.weight,
+        }
+    }
+}
+
+impl From<SignalConfig> for Arc<dyn RiskSignal> {
+    fn from(config: SignalConfig) -> Self {
+        match config {
+            SignalConfig::NewValue { key, weight } => Arc::new(NewValue {
+                name: format!("new {key}"),
+                key,
+                weight,
+                seen: Mutex::new(HashMap::new()),
+            }),
+            SignalConfig::UnusualValue { key, usual, weight } => Arc::new(UnusualValue {
+                name: format!("unusual {key}"),
+                key,
+                usual: usual.into_iter().collect(),
+                weight,
+            }),
+        }
+    }
+}
+
+/// The score of a check and the signals that fired, with what each added
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RiskScore {
+    pub score: u32,
+    pub fired: Vec<(String, u32)>,
+}
+
+impl Display for RiskScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fired: Vec<String> = self
+            .fired
+            .iter()
+            .map(|(name, score)| format!("{name} +{score}"))
+            .collect();
+        write!(f, "{} [{}]", self.score, fired.join(", "))
+    }
+}
+
+/// Scores checks with the configured signals
+#[derive(Default)]
+pub(crate) struct RiskScorer {
+    signals: Vec<Arc<dyn RiskSignal>>,
+}
+
+impl RiskScorer {
+    /// Build the signals of a config
+    pub(crate) fn new(config: RiskConfig) -> Self {
+        let mut signals: Vec<Arc<dyn RiskSignal>> =
+            config.signals.into_iter().map(Arc::from).collect();
+        signals.extend(config.custom);
+        Self { signals }
+    }
+
+    /// Whether there is anything to score with
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.signals.is_empty()
+    }
+
+    /// Score a check, letting the signals learn from it if it is a real one
+    pub(crate) fn score(&self, input: &RiskInput, observe: bool) -> RiskScore {
+        let mut risk = RiskScore::default();
+        for signal in &self.signals {
+            let score = signal.score(input);
+            if score > 0 {
+                risk.score = risk.score.saturating_add(score);
+                risk.fired.push((signal.name().to_string(), score));
+            }
+        }
+
+        if observe {
+            for signal in &self.signals {
+                signal.observe(input);
+            }
+        }
+        risk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute;
+
+    struct Weekend;
+
+    impl RiskSignal for Weekend {
+        fn name(&self) -> &str {
+            "weekend"
+        }
+
+        fn score(&self, input: &RiskInput) -> u32 {
+            match input.env_attributes.contains_key("weekend") {
+                true => 5,
+                false => 0,
+            }
+        }
+    }
+
+    #[test]
+    fn test_score() {
+        let mut config: RiskConfig = serde_json::from_str(
+            r#"{"signals": [
+                {"kind": "new_value", "key": "device", "weight": 40},
+                {"kind": "unusual_value", "key": "country", "usual": ["us", "ca"], "weight": 30}
+            ]}"#,
+        )
+        .unwrap();
+        config.custom.push(Arc::new(Weekend));
+        let scorer = RiskScorer::new(config);
+
+        let actor_attributes = AttributeMap::default();
+        let score = |env: &[(&str, &str)], observe: bool| {
+            let env_attributes = env
+                .iter()
+                .map(|(key, val)| (Arc::from(*key), attribute::values([val])))
+                .collect();
+            let input = RiskInput {
+                actor_type: "user",
+                actor_name: "kaitlyn",
+                actor_attributes: &actor_attributes,
+                env_attributes: &env_attributes,
+                target_type: "database",
+                target_name: "db",
+                actions: &[],
+            };
+            scorer.score(&input, observe)
+        };
+
+        // the first device is the one we know
+        assert_eq!(
+            score(&[("device", "laptop"), ("country", "us")], true).score,
+            0
+        );
+        assert_eq!(
+            score(&[("device", "phone"), ("country", "us")], false).score,
+            40
+        );
+        // a trace doesn't make the phone known
+        assert_eq!(
+            score(&[("device", "phone"), ("country", "us")], true).score,
+            40
+        );
+        assert_eq!(
+            score(&[("device", "phone"), ("country", "us")], true).score,
+            0
+        );
+
+        let risk = score(&[("device", "tablet"), ("weekend", "yes")], true);
+        assert_eq!(risk.score, 75);
+        assert_eq!(
+            risk.to_string(),
+            "75 [new device +40, unusual country +30, weekend +5]"
+        );
+    }
+}
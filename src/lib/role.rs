@@ -5,12 +5,16 @@ use std::fmt::Display;
 use serde::{Deserialize, Serialize};
 
 use crate::proto::roles::Role;
+use crate::timestamps::Timestamps;
 
 #[derive(Debug, Clone, Eq, Serialize, Deserialize)]
 pub(crate) struct RegisteredRole {
     pub name: String,
     pub desc: Option<String>,
     pub groups: HashSet<String>,
+    /// when the role was added and last changed
+    #[serde(flatten)]
+    pub timestamps: Timestamps,
 }
 
 impl RegisteredRole {
@@ -20,6 +24,7 @@ impl RegisteredRole {
             name,
             desc,
             groups: HashSet::new(),
+            timestamps: Timestamps::default(),
         }
     }
 }
@@ -48,6 +53,22 @@ impl From<RegisteredRole> for Role {
             name: role.name,
             desc: role.desc,
             granted_to: role.groups.into_iter().collect(),
+            created_at: role.timestamps.created_at,
+            updated_at: role.timestamps.updated_at,
+        }
+    }
+}
+
+impl From<Role> for RegisteredRole {
+    fn from(role: Role) -> Self {
+        Self {
+            name: role.name.to_ascii_lowercase(),
+            desc: role.desc,
+            groups: role.granted_to.into_iter().collect(),
+            timestamps: Timestamps {
+                created_at: role.created_at,
+                updated_at: role.updated_at,
+            },
         }
     }
 }
@@ -0,0 +1,238 @@
+#![warn(missing_docs)]
+
+//! The tokio runtimes the server runs on
+//!
+//! Checks run on the main runtime. Storage calls can be given a runtime of their own, so heavy
+//! storage traffic, such as a slow etcd cluster or a large sync, can't hold up the threads that
+//! decide checks. On Linux, the threads of each runtime can also be pinned to their own cores:
+//! the main runtime gets the first cores, and the storage runtime the ones after them.
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::config::number_from_env;
+
+/// How the runtimes are set up
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    /// how many worker threads checks run on; `None` uses one per core, or one per core left
+    /// after storage's if pinned
+    pub worker_threads: Option<usize>,
+    /// the most threads for blocking work; `None` uses tokio's default of 512
+    pub blocking_threads: Option<usize>,
+    /// how many worker threads storage calls run on; 0 runs them with checks
+    pub storage_threads: usize,
+    /// whether each runtime's threads are pinned to its own cores
+    pub pin_threads: bool,
+}
+
+/// The built runtimes
+pub struct Runtimes {
+    /// the runtime checks and everything but storage run on
+    pub main: Runtime,
+    /// the runtime storage calls run on, if they have their own
+    pub storage: Option<Runtime>,
+}
+
+impl Runtimes {
+    /// A handle to the storage runtime, if there is one
+    pub fn storage_handle(&self) -> Option<Handle> {
+        self.storage
+            .as_ref()
+            .map(|storage| storage.handle().clone())
+    }
+
+    /// Stop both runtimes without waiting on what is still running
+    pub fn shutdown(self) {
+        if let Some(storage) = self.storage {
+            storage.shutdown_background();
+        }
+        self.main.shutdown_background();
+    }
+}
+
+impl RuntimeConfig {
+    /// Build the config from `GATEWORKERTHREADS`, `GATEBLOCKINGTHREADS`, `GATESTORAGETHREADS`,
+    /// and `GATEPINTHREADS`. By default storage calls run with checks and nothing is pinned.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            worker_threads: number_from_env("GATEWORKERTHREADS")?.filter(|threads| *threads > 0),
+            blocking_threads: number_from_env("GATEBLOCKINGTHREADS")?
+                .filter(|threads| *threads > 0),
+            storage_threads: number_from_env("GATESTORAGETHREADS")?.unwrap_or(0),
+            pin_threads: matches!(
+                std::env::var("GATEPINTHREADS").as_deref(),
+                Ok("true") | Ok("1")
+            ),
+        })
+    }
+
+    /// Which cores the main and storage runtimes are pinned to, given how many there are
+    fn layout(&self, cores: usize) -> Result<(Range<usize>, Range<usize>), String> {
+        let workers = match self.worker_threads {
+            Some(workers) => workers,
+            None => cores.saturating_sub(self.storage_threads),
+        };
+        if workers == 0 || workers + self.storage_threads > cores {
+            return Err(format!(
+                "Can't pin {} worker and {} storage threads to {} cores",
+                workers, self.storage_threads, cores
+            ));
+        }
+        Ok((0..workers, workers..workers + self.storage_threads))
+    }
+
+    /// Build the runtimes
+    pub fn build(&self) -> Result<Runtimes, String> {
+        let pinned = match self.pin_threads {
+            true => {
+                let cores = std::thread::available_parallelism()
+                    .map_err(|err| format!("Can't count cores to pin threads to: {err}"))?;
+                Some(self.layout(cores.get())?)
+            }
+            false => None,
+        };
+
+        let mut main = Builder::new_multi_thread();
+        main.enable_all().thread_name("gatehouse-worker");
+        match (self.worker_threads, &pinned) {
+            (Some(workers), _) => main.worker_threads(workers),
+            (None, Some((cores, _))) => main.worker_threads(cores.len()),
+            (None, None) => &mut main,
+        };
+        if let Some(blocking) = self.blocking_threads {
+            main.max_blocking_threads(blocking);
+        }
+        if let Some((ref cores, _)) = pinned {
+            let cores = cores.clone();
+            main.on_thread_start(move || pin_thread(&cores));
+        }
+        let main = main
+            .build()
+            .map_err(|err| format!("Can't start the runtime: {err}"))?;
+
+        let storage = match self.storage_threads {
+            0 => None,
+            threads => {
+                let mut storage = Builder::new_multi_thread();
+                storage
+                    .enable_all()
+                    .thread_name("gatehouse-storage")
+                    .worker_threads(threads);
+                if let Some((_, ref cores)) = pinned {
+                    let cores = cores.clone();
+                    storage.on_thread_start(move || pin_thread(&cores));
+                }
+                let storage = storage
+                    .build()
+                    .map_err(|err| format!("Can't start the storage runtime: {err}"))?;
+                Some(storage)
+            }
+        };
+
+        Ok(Runtimes { main, storage })
+    }
+}
+
+impl Display for RuntimeConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.worker_threads {
+            Some(workers) => write!(f, "{workers} workers")?,
+            None => write!(f, "default workers")?,
+        }
+        if let Some(blocking) = self.blocking_threads {
+            write!(f, ", up to {blocking} blocking")?;
+        }
+        match self.storage_threads {
+            0 => write!(f, ", storage shared")?,
+            threads => write!(f, ", {threads} storage")?,
+        }
+        if self.pin_threads {
+            write!(f, ", pinned")?;
+        }
+        Ok(())
+    }
+}
+
+/// Pin the current thread to some cores, logging if it can't be; with no cores, it isn't pinned
+fn pin_thread(cores: &Range<usize>) {
+    let Some(last) = cores.end.checked_sub(1).filter(|last| *last >= cores.start) else {
+        return;
+    };
+    if let Err(err) = set_affinity(cores) {
+        eprintln!("Can't pin thread to cores {}-{last}: {err}", cores.start);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cores: &Range<usize>) -> Result<(), String> {
+    // SAFETY: cpu_set_t is plain data that is valid zeroed, and is only read by the call
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for core in cores.clone() {
+            libc::CPU_SET(core, &mut set);
+        }
+        match libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error().to_string()),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cores: &Range<usize>) -> Result<(), String> {
+    Err(String::from("threads can only be pinned on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let config = RuntimeConfig {
+            storage_threads: 2,
+            pin_threads: true,
+            ..Default::default()
+        };
+        assert_eq!(config.layout(8), Ok((0..6, 6..8)));
+
+        let config = RuntimeConfig {
+            worker_threads: Some(4),
+            ..config
+        };
+        assert_eq!(config.layout(8), Ok((0..4, 4..6)));
+        assert!(config.layout(5).is_err());
+
+        // storage can't take every core
+        let config = RuntimeConfig {
+            storage_threads: 4,
+            pin_threads: true,
+            ..Default::default()
+        };
+        assert!(config.layout(4).is_err());
+
+        // a thread with no cores to pin to is left alone
+        pin_thread(&(0..0));
+        pin_thread(&(3..3));
+
+        let runtimes = RuntimeConfig {
+            worker_threads: Some(1),
+            storage_threads: 1,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let storage = runtimes.storage_handle().unwrap();
+        let name = runtimes.main.block_on(async move {
+            storage
+                .spawn(async { std::thread::current().name().map(String::from) })
+                .await
+                .unwrap()
+        });
+        assert_eq!(name.as_deref(), Some("gatehouse-storage"));
+        runtimes.shutdown();
+    }
+}
@@ -0,0 +1,125 @@
+#![warn(missing_docs)]
+
+//! Sampling of check decisions for offline analysis
+//!
+//! With a sample rate of N, one in every N checks is written to a file as a line of JSON, with
+//! the actor, target, and environment attributes the check was decided on, after the actor's
+//! registered attributes, groups, and roles were added. That is enough to study access patterns
+//! without keeping an audit log of every check.
+//!
+//! Each line looks like:
+//!
+//! ```json
+//! {"at": 1700000000000, "correlation_id": "req-1",
+//!  "actor": {"typestr": "user", "name": "kaitlyn", "attributes": {"member-of": ["eng"]}},
+//!  "target": {"typestr": "database", "name": "maindb", "attributes": {}},
+//!  "env": {"region": ["us"]}, "actions": ["read"], "decision": "ALLOW", "source": "EVALUATED",
+//!  "action_decisions": [], "risk": null}
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+use crate::attribute::AttributeMap;
+
+/// Writes one in every so many checks to a file
+#[derive(Debug)]
+pub(crate) struct DecisionSampler {
+    rate: u64,
+    /// every check made, sampled or not
+    checks: AtomicU64,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl DecisionSampler {
+    /// Sample one in every `rate` checks to a file, adding to what it already has; 0 samples
+    /// every check
+    pub fn open(path: &str, rate: u32) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("Could not open {path} to sample decisions to: {err}"))?;
+        Ok(Self {
+            rate: u64::from(rate.max(1)),
+            checks: AtomicU64::new(0),
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Count a check, returning whether it should be sampled
+    pub fn sample(&self) -> bool {
+        self.checks
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.rate)
+    }
+
+    /// Write a sampled check, stamped with the time
+    ///
+    /// Each sample is flushed as it is written, so a sample file survives the server crashing.
+    pub fn write(&self, mut sample: Value) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or_default();
+        sample["at"] = json!(at);
+
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        if let Err(err) = writeln!(file, "{sample}").and_then(|_| file.flush()) {
+            eprintln!("Could not sample decision: {err}");
+        }
+    }
+}
+
+/// Attributes as a JSON object, with their values sorted so samples compare easily
+pub(crate) fn attributes_json(attributes: &AttributeMap) -> Value {
+    let mut object = Map::new();
+    for (key, vals) in attributes {
+        let mut vals: Vec<&str> = vals.iter().map(|val| val.as_ref()).collect();
+        vals.sort_unstable();
+        object.insert(key.to_string(), json!(vals));
+    }
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::values;
+    use crate::intern::intern;
+
+    #[test]
+    fn test_sampler() {
+        let dir = std::env::temp_dir().join(format!("gatehouse-samples-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("samples.jsonl");
+        let path = path.to_str().unwrap();
+
+        // one in four checks is sampled, starting with the first
+        let sampler = DecisionSampler::open(path, 4).unwrap();
+        let sampled: Vec<bool> = (0..8).map(|_| sampler.sample()).collect();
+        assert_eq!(
+            sampled,
+            vec![true, false, false, false, true, false, false, false]
+        );
+
+        let attributes: AttributeMap =
+            AttributeMap::from_iter([(intern("team"), values(["ops", "eng"]))]);
+        sampler.write(json!({"actor": {"attributes": attributes_json(&attributes)}}));
+        drop(sampler);
+
+        let written = std::fs::read_to_string(path).unwrap();
+        let sample: Value = serde_json::from_str(written.lines().next().unwrap()).unwrap();
+        assert_eq!(sample["actor"]["attributes"]["team"], json!(["eng", "ops"]));
+        assert!(sample["at"].as_u64().unwrap() > 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
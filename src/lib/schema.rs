@@ -0,0 +1,705 @@
+#![warn(missing_docs)]
+
+//! JSON Schemas for the documents Gatehouse reads and writes
+//!
+//! These cover the files `gatecli` imports, the documents of the admin API, and the entities as
+//! the storage backends keep them. Tools and UIs can use them to check a document before
+//! submitting it. The schemas are written by hand to match the serde forms of the types they
+//! describe, and follow JSON Schema 2020-12. YAML documents, such as policy tests, are checked
+//! as the JSON they parse to.
+
+use serde_json::{json, Map, Value};
+
+use crate::migrate::SCHEMA_VERSION;
+
+/// the dialect every schema is written in
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// A named schema for one kind of document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    /// the name the schema is asked for by
+    pub name: &'static str,
+    /// what documents it describes
+    pub description: &'static str,
+    /// the schema itself
+    pub schema: Value,
+}
+
+/// Every schema, by name
+pub fn schemas() -> Vec<Schema> {
+    let schema = |name, description, schema| Schema {
+        name,
+        description,
+        schema: with_header(name, description, schema),
+    };
+
+    vec![
+        schema(
+            "bulk",
+            "Actors or targets to add in bulk, as given to `actors add-many` and `targets add-many`",
+            bulk(),
+        ),
+        schema(
+            "policy-tests",
+            "Policy test cases, as given to `gatecli test` in YAML",
+            policy_tests(),
+        ),
+        schema(
+            "attribute-rules",
+            "Validation rules for attribute values, as given to the server in `GATEATTRIBUTERULES`",
+            attribute_rules(),
+        ),
+        schema(
+            "admin/target",
+            "A target in the admin API",
+            admin_entity(true),
+        ),
+        schema("admin/actor", "An actor in the admin API", admin_entity(false)),
+        schema("admin/role", "A role in the admin API", admin_role()),
+        schema("admin/group", "A group in the admin API", admin_group()),
+        schema(
+            "admin/policy",
+            "A policy in the admin API, in the form it is stored in",
+            policy(false),
+        ),
+        schema(
+            "stored/target",
+            "A target as storage keeps it",
+            stored_target(),
+        ),
+        schema("stored/actor", "An actor as storage keeps it", stored_actor()),
+        schema("stored/role", "A role as storage keeps it", stored_role()),
+        schema("stored/group", "A group as storage keeps it", stored_group()),
+        schema(
+            "stored/policy",
+            "A policy as storage keeps it; policy test runs also load policies in this form",
+            policy(true),
+        ),
+        schema(
+            "stored/policy-set",
+            "A policy set as storage keeps it",
+            stored_policy_set(),
+        ),
+        schema(
+            "stored/webhook",
+            "A webhook as storage keeps it",
+            stored_webhook(),
+        ),
+        schema(
+            "stored/api-key",
+            "An API key as storage keeps it",
+            stored_api_key(),
+        ),
+        schema(
+            "stored/delegation",
+            "A delegation as storage keeps it",
+            stored_delegation(),
+        ),
+    ]
+}
+
+/// The schema with a name, if there is one
+pub fn schema(name: &str) -> Option<Schema> {
+    schemas().into_iter().find(|schema| schema.name == name)
+}
+
+/// Add the dialect, title, and description to a schema
+fn with_header(name: &str, description: &str, schema: Value) -> Value {
+    let mut header = Map::new();
+    header.insert(String::from("$schema"), Value::from(DIALECT));
+    header.insert(String::from("title"), Value::from(name));
+    header.insert(String::from("description"), Value::from(description));
+    if let Value::Object(body) = schema {
+        header.extend(body);
+    }
+    Value::Object(header)
+}
+
+/// An object with exactly these properties, of which some are required
+fn object(properties: Value, required: &[&str]) -> Value {
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// A stored entity, which may carry the schema version it was saved in
+fn stored(mut properties: Value, required: &[&str]) -> Value {
+    properties[crate::migrate::VERSION_KEY] = json!({
+        "type": "integer",
+        "minimum": 0,
+        "maximum": SCHEMA_VERSION,
+    });
+    object(properties, required)
+}
+
+/// A schema, or null
+fn nullable(schema: Value) -> Value {
+    json!({"anyOf": [schema, {"type": "null"}]})
+}
+
+fn strings() -> Value {
+    json!({"type": "array", "items": {"type": "string"}})
+}
+
+fn unique_strings() -> Value {
+    json!({"type": "array", "items": {"type": "string"}, "uniqueItems": true})
+}
+
+fn count() -> Value {
+    json!({"type": "integer", "minimum": 0})
+}
+
+/// Attributes as storage and the admin API keep them: each name has a list of values
+fn attributes() -> Value {
+    json!({"type": "object", "additionalProperties": unique_strings()})
+}
+
+/// Attributes as files give them: each name has a value or a list of them
+fn loose_attributes() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": {"anyOf": [{"type": "string"}, strings()]},
+    })
+}
+
+/// Action groups, by name
+fn action_groups() -> Value {
+    json!({"type": "object", "additionalProperties": unique_strings()})
+}
+
+/// An enum variant with data, written as an object with just that key
+fn variant(name: &str, data: Value) -> Value {
+    object(json!({ name: data }), &[name])
+}
+
+/// A tuple variant's data, written as an array
+fn tuple(items: Vec<Value>) -> Value {
+    let len = items.len();
+    json!({
+        "type": "array",
+        "prefixItems": items,
+        "minItems": len,
+        "maxItems": len,
+    })
+}
+
+fn bulk() -> Value {
+    json!({
+        "type": "array",
+        "items": object(
+            json!({
+                "type": {"type": "string"},
+                "name": {"type": "string"},
+                "attributes": loose_attributes(),
+                "actions": strings(),
+                "action_groups": action_groups(),
+            }),
+            &["type", "name"],
+        ),
+    })
+}
+
+fn policy_tests() -> Value {
+    let entity = object(
+        json!({
+            "name": {"type": "string"},
+            "type": {"type": "string"},
+            "attributes": nullable(loose_attributes()),
+        }),
+        &["name", "type"],
+    );
+    json!({
+        "type": "array",
+        "items": object(
+            json!({
+                "name": {"type": "string"},
+                "actor": entity,
+                "target": entity,
+                "env": loose_attributes(),
+                "actions": strings(),
+                "expect": {"enum": ["allow", "deny", "not_applicable", "indeterminate"]},
+            }),
+            &["name", "actor", "target", "expect"],
+        ),
+    })
+}
+
+fn attribute_rules() -> Value {
+    let rules = json!({
+        "type": "object",
+        "additionalProperties": object(
+            json!({
+                "pattern": {"type": "string"},
+                "one_of": strings(),
+                "min": {"type": "number"},
+                "max": {"type": "number"},
+            }),
+            &[],
+        ),
+    });
+    object(json!({"actors": rules, "targets": rules}), &[])
+}
+
+fn admin_entity(target: bool) -> Value {
+    let mut properties = json!({
+        "type": {"type": "string"},
+        "name": {"type": "string"},
+        "attributes": attributes(),
+    });
+    if target {
+        properties["actions"] = unique_strings();
+        properties["action_groups"] = action_groups();
+    }
+    object(properties, &["type", "name"])
+}
+
+fn admin_role() -> Value {
+    object(
+        json!({
+            "name": {"type": "string"},
+            "description": {"type": "string"},
+        }),
+        &["name"],
+    )
+}
+
+fn admin_group() -> Value {
+    let member = object(
+        json!({"type": {"type": "string"}, "name": {"type": "string"}}),
+        &["type", "name"],
+    );
+    object(
+        json!({
+            "name": {"type": "string"},
+            "description": {"type": "string"},
+            "members": {"type": "array", "items": member, "uniqueItems": true},
+            "roles": unique_strings(),
+        }),
+        &["name"],
+    )
+}
+
+fn stored_target() -> Value {
+    stored(
+        json!({
+            "name": {"type": "string"},
+            "typestr": {"type": "string"},
+            "actions": unique_strings(),
+            "attributes": attributes(),
+            "action_groups": action_groups(),
+            "created_at": count(),
+            "updated_at": count(),
+        }),
+        &["name", "typestr", "actions", "attributes"],
+    )
+}
+
+fn stored_actor() -> Value {
+    stored(
+        json!({
+            "name": {"type": "string"},
+            "typestr": {"type": "string"},
+            "attributes": attributes(),
+            "created_at": count(),
+            "updated_at": count(),
+        }),
+        &["name", "typestr", "attributes"],
+    )
+}
+
+fn stored_role() -> Value {
+    stored(
+        json!({
+            "name": {"type": "string"},
+            "desc": nullable(json!({"type": "string"})),
+            "groups": unique_strings(),
+            "created_at": count(),
+            "updated_at": count(),
+        }),
+        &["name", "groups"],
+    )
+}
+
+fn stored_group() -> Value {
+    let member = object(
+        json!({"name": {"type": "string"}, "typestr": {"type": "string"}}),
+        &["name", "typestr"],
+    );
+    stored(
+        json!({
+            "name": {"type": "string"},
+            "desc": nullable(json!({"type": "string"})),
+            "members": {"type": "array", "items": member, "uniqueItems": true},
+            "roles": unique_strings(),
+            "managed_by": nullable(json!({"type": "string"})),
+            "created_at": count(),
+            "updated_at": count(),
+        }),
+        &["name", "members", "roles"],
+    )
+}
+
+/// A policy, as stored or, without the schema version, in the admin API
+fn policy(versioned: bool) -> Value {
+    let properties = json!({
+        "name": {"type": "string"},
+        "desc": nullable(json!({"type": "string"})),
+        "actor_check": nullable(json!({"$ref": "#/$defs/actor_check"})),
+        "env_attributes": {"type": "array", "items": {"$ref": "#/$defs/kv_check"}},
+        "target_check": nullable(json!({"$ref": "#/$defs/target_check"})),
+        "decision": {"$ref": "#/$defs/decide"},
+        "mode": {"enum": ["Enforce", "Shadow"]},
+        "wasm_module": nullable(json!({"type": "string"})),
+        "compare_checks": {"type": "array", "items": {"$ref": "#/$defs/compare_check"}},
+        "cidr_checks": {"type": "array", "items": {"$ref": "#/$defs/cidr_check"}},
+        "target_types": strings(),
+        "rate_checks": {"type": "array", "items": {"$ref": "#/$defs/rate_check"}},
+        "risk": nullable(json!({"$ref": "#/$defs/number_check"})),
+        "tags": strings(),
+        "created_at": count(),
+        "updated_at": count(),
+    });
+    let required = ["name", "env_attributes", "decision"];
+    let mut policy = match versioned {
+        true => stored(properties, &required),
+        false => object(properties, &required),
+    };
+    policy["$defs"] = check_defs();
+    policy
+}
+
+fn stored_policy_set() -> Value {
+    let mut set = stored(
+        json!({
+            "name": {"type": "string"},
+            "desc": nullable(json!({"type": "string"})),
+            "scope": nullable(json!({"$ref": "#/$defs/target_check"})),
+            "combine": {"enum": ["DenyOverrides", "AllowOverrides", "FirstApplicable"]},
+            "enabled": {"type": "boolean"},
+            "policies": strings(),
+            "created_at": count(),
+            "updated_at": count(),
+        }),
+        &["name", "combine", "enabled", "policies"],
+    );
+    set["$defs"] = check_defs();
+    set
+}
+
+fn stored_webhook() -> Value {
+    let events = [
+        "EntityChanged",
+        "DenyDecision",
+        "StorageHealth",
+        "DelegatedDecision",
+        "BreakGlass",
+        "EvaluationLimit",
+        "DenyStreak",
+    ];
+    stored(
+        json!({
+            "name": {"type": "string"},
+            "url": {"type": "string"},
+            "events": {"type": "array", "items": {"enum": events}, "uniqueItems": true},
+            "secret": nullable(json!({"type": "string"})),
+        }),
+        &["name", "url", "events"],
+    )
+}
+
+fn stored_api_key() -> Value {
+    stored(
+        json!({
+            "id": {"type": "string"},
+            "name": {"type": "string"},
+            "scope": {"enum": ["CheckOnly", "ReadOnly", "Admin"]},
+            "namespaces": strings(),
+            "hash": {"type": "string", "pattern": "^[0-9a-f]{64}$"},
+            "created": count(),
+        }),
+        &["id", "name", "scope", "namespaces", "hash", "created"],
+    )
+}
+
+fn stored_delegation() -> Value {
+    let fields = [
+        "name",
+        "delegator_type",
+        "delegator_name",
+        "delegate_type",
+        "delegate_name",
+        "target_type",
+        "target_names",
+        "actions",
+        "expires_at",
+        "created_at",
+    ];
+    stored(
+        json!({
+            "name": {"type": "string"},
+            "delegator_type": {"type": "string"},
+            "delegator_name": {"type": "string"},
+            "delegate_type": {"type": "string"},
+            "delegate_name": {"type": "string"},
+            "target_type": {"type": "string"},
+            "target_names": strings(),
+            "actions": strings(),
+            "expires_at": count(),
+            "created_at": count(),
+        }),
+        &fields,
+    )
+}
+
+/// The checks policies and policy sets are made of
+fn check_defs() -> Value {
+    let string_check = || json!({"$ref": "#/$defs/string_check"});
+    let kv_checks = || json!({"type": "array", "items": {"$ref": "#/$defs/kv_check"}});
+    let attribute_ref = || json!({"$ref": "#/$defs/attribute_ref"});
+    let string = || json!({"type": "string"});
+
+    json!({
+        "string_check": {"oneOf": [
+            variant("OneOf", strings()),
+            variant("NotOneOf", strings()),
+        ]},
+        "kv_check": {"oneOf": [
+            variant("Has", tuple(vec![string(), strings()])),
+            variant("HasNot", tuple(vec![string(), strings()])),
+            variant("Exists", string()),
+            variant("NotExists", string()),
+            variant("CountAtLeast", tuple(vec![string(), count()])),
+            variant("ContainsAll", tuple(vec![string(), strings()])),
+        ]},
+        "number_check": {"oneOf": [
+            variant("Equals", json!({"type": "integer"})),
+            variant("LessThan", json!({"type": "integer"})),
+            variant("MoreThan", json!({"type": "integer"})),
+        ]},
+        "decide": {"oneOf": [
+            {"enum": ["Deny", "Allow", "NotApplicable", "Indeterminate"]},
+            variant("AllowWithApproval", count()),
+        ]},
+        "actor_check": object(
+            json!({
+                "name": nullable(string_check()),
+                "typestr": nullable(string_check()),
+                "attributes": kv_checks(),
+                "bucket": nullable(json!({"$ref": "#/$defs/number_check"})),
+            }),
+            &["attributes"],
+        ),
+        "target_check": object(
+            json!({
+                "name": nullable(string_check()),
+                "typestr": nullable(string_check()),
+                "attributes": kv_checks(),
+                "match_in_actor": strings(),
+                "match_in_env": strings(),
+                "action": nullable(string_check()),
+                "action_except": strings(),
+            }),
+            &["attributes", "match_in_actor", "match_in_env"],
+        ),
+        "attribute_ref": object(
+            json!({
+                "source": {"enum": ["Actor", "Target", "Env"]},
+                "key": string(),
+            }),
+            &["source", "key"],
+        ),
+        "compare_check": object(
+            json!({
+                "left": attribute_ref(),
+                "right": attribute_ref(),
+                "op": {"enum": ["Equal", "Intersect"]},
+            }),
+            &["left", "right", "op"],
+        ),
+        "cidr_check": {"oneOf": [
+            variant("In", tuple(vec![attribute_ref(), strings()])),
+            variant("NotIn", tuple(vec![attribute_ref(), strings()])),
+        ]},
+        "rate_check": object(
+            json!({
+                "actions": strings(),
+                "limit": count(),
+                "window": count(),
+            }),
+            &["actions", "limit", "window"],
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::group::RegisteredGroup;
+    use crate::policy::RegisteredPolicyRule;
+    use crate::role::RegisteredRole;
+
+    use super::*;
+
+    /// Check a document against the parts of JSON Schema the schemas use
+    fn validate(schema: &Value, doc: &Value, root: &Value) -> Result<(), String> {
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let name = reference.trim_start_matches("#/$defs/");
+            return validate(&root["$defs"][name], doc, root);
+        }
+        if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+            if !options.contains(doc) {
+                return Err(format!("{doc} is not one of {options:?}"));
+            }
+        }
+        if let Some(options) = schema.get("anyOf").and_then(Value::as_array) {
+            if !options.iter().any(|opt| validate(opt, doc, root).is_ok()) {
+                return Err(format!("{doc} matches none of {schema}"));
+            }
+        }
+        if let Some(options) = schema.get("oneOf").and_then(Value::as_array) {
+            let matches = options
+                .iter()
+                .filter(|opt| validate(opt, doc, root).is_ok());
+            if matches.count() != 1 {
+                return Err(format!("{doc} doesn't match exactly one of {schema}"));
+            }
+        }
+        let typed = match schema.get("type").and_then(Value::as_str) {
+            Some("object") => doc.is_object(),
+            Some("array") => doc.is_array(),
+            Some("string") => doc.is_string(),
+            Some("integer") => doc.is_i64() || doc.is_u64(),
+            Some("number") => doc.is_number(),
+            Some("boolean") => doc.is_boolean(),
+            Some("null") => doc.is_null(),
+            _ => true,
+        };
+        if !typed {
+            return Err(format!("{doc} is not a {}", schema["type"]));
+        }
+        if let Some(min) = schema.get("minimum").and_then(Value::as_i64) {
+            if doc.as_i64().is_some_and(|num| num < min) {
+                return Err(format!("{doc} is less than {min}"));
+            }
+        }
+
+        if let Value::Object(fields) = doc {
+            for name in schema["required"].as_array().into_iter().flatten() {
+                if !fields.contains_key(name.as_str().unwrap()) {
+                    return Err(format!("missing {name}"));
+                }
+            }
+            for (name, val) in fields {
+                match schema["properties"].get(name) {
+                    Some(prop) => validate(prop, val, root)?,
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => return Err(format!("unknown {name}")),
+                        Some(extra) if extra.is_object() => validate(extra, val, root)?,
+                        _ => {}
+                    },
+                }
+            }
+        }
+        if let Value::Array(items) = doc {
+            let tuple = schema.get("prefixItems").and_then(Value::as_array);
+            if let Some(len) = schema.get("maxItems").and_then(Value::as_u64) {
+                if items.len() as u64 != len {
+                    return Err(format!("{doc} should have {len} items"));
+                }
+            }
+            for (pos, item) in items.iter().enumerate() {
+                match tuple {
+                    Some(tuple) => validate(&tuple[pos], item, root)?,
+                    None => validate(&schema["items"], item, root)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check(name: &str, doc: &Value) -> Result<(), String> {
+        let schema = schema(name).unwrap().schema;
+        validate(&schema, doc, &schema)
+    }
+
+    #[test]
+    fn test_schemas() {
+        let names: HashSet<&str> = schemas().iter().map(|schema| schema.name).collect();
+        assert_eq!(names.len(), schemas().len());
+        assert!(schema("nope").is_none());
+
+        // stored entities match their schemas
+        let rules = crate::dsl::parse(
+            r#"
+            policy eng-read: allow user(team=eng) to read database(env!=prod)
+            policy delete: allow with 2 approvals anyone(has-role=dba, !suspended) to delete,drop
+                database|queue when region=us|ca, vpn
+            policy no-ledger: shadow deny * to "*" *(name=ledger, "name"=x)
+            "#,
+        )
+        .unwrap();
+        for rule in rules {
+            let stored = crate::migrate::stamp(&RegisteredPolicyRule::from(rule)).unwrap();
+            check("stored/policy", &stored).unwrap();
+        }
+        let role = crate::migrate::stamp(&RegisteredRole::new("admin", None)).unwrap();
+        check("stored/role", &role).unwrap();
+        let group = RegisteredGroup::new("admins", None, HashSet::new(), HashSet::new());
+        check("stored/group", &serde_json::to_value(group).unwrap()).unwrap();
+
+        // documents that match the schema can be read, with every kind of check
+        let policy = json!({
+            "name": "near",
+            "desc": null,
+            "env_attributes": [{"CountAtLeast": ["vpn", 1]}],
+            "decision": {"AllowWithApproval": 1},
+            "compare_checks": [{
+                "left": {"source": "Actor", "key": "team"},
+                "right": {"source": "Target", "key": "team"},
+                "op": "Intersect",
+            }],
+            "cidr_checks": [{"In": [{"source": "Env", "key": "ip"}, ["10.0.0.0/8"]]}],
+            "rate_checks": [{"actions": ["read"], "limit": 10, "window": 60}],
+            "risk": {"LessThan": 50},
+        });
+        check("admin/policy", &policy).unwrap();
+        serde_json::from_value::<RegisteredPolicyRule>(policy.clone()).unwrap();
+
+        // and those that don't are refused
+        let mut bad = policy.clone();
+        bad["decision"] = json!("Maybe");
+        assert!(check("admin/policy", &bad).is_err());
+        let mut bad = policy;
+        bad["schema_version"] = json!(1);
+        assert!(check("admin/policy", &bad).is_err());
+
+        let entities = json!([
+            {"type": "user", "name": "kaitlyn", "attributes": {"team": ["eng"], "level": "3"}},
+            {"type": "database", "name": "maindb", "actions": ["read"]},
+        ]);
+        check("bulk", &entities).unwrap();
+        assert!(check("bulk", &json!([{"type": "user"}])).is_err());
+        let tests: Value = serde_yaml::from_str(
+            "- name: readers read\n  actor: {name: kaitlyn, type: user}\n  target: {name: db, type: database}\n  actions: [read]\n  expect: allow\n",
+        )
+        .unwrap();
+        check("policy-tests", &tests).unwrap();
+
+        let rules = json!({
+            "actors": {"region": {"one_of": ["us", "emea"]}},
+            "targets": {"tier": {"min": 0, "max": 3}, "env": {"pattern": "(dev|prod)"}},
+        });
+        check("attribute-rules", &rules).unwrap();
+        serde_json::from_value::<crate::validation::AttributeRules>(rules).unwrap();
+        assert!(check(
+            "attribute-rules",
+            &json!({"actors": {"region": {"max": "3"}}})
+        )
+        .is_err());
+    }
+}
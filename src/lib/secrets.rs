@@ -0,0 +1,409 @@
+#![warn(missing_docs)]
+
+//! Sensitive settings read from the environment, files, or a secret manager
+//!
+//! A sensitive setting, such as the grant secret or a storage password, can be given as is, or
+//! as a reference to where it is kept: `env://VAR` for another environment variable,
+//! `file:///path` for a file, `vault://mount/path#key` for a key of a Vault KV secret, or
+//! `aws-secrets://secret-id#key` for AWS Secrets Manager. Every setting is read the same way, so
+//! anything that takes a secret takes any of them, and embedders can add providers of their own.
+//!
+//! Secrets are kept as [`Secret`]s, which never show their value when printed, so they stay out
+//! of logs and debug output. Vault and AWS Secrets Manager are reached over HTTPS, or over plain
+//! HTTP on this host, such as a local Vault agent or the Secrets Manager Agent, which answers on
+//! localhost. Tokens and secrets would cross the network in the clear otherwise, so plain HTTP
+//! never reaches another host.
+
+use std::fmt::{Debug, Display};
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use hyper::{Body, Request, StatusCode, Uri};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::time::timeout;
+
+use crate::http;
+
+/// how long a secret manager has to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// where the AWS Secrets Manager Agent listens by default
+const AWS_AGENT: &str = "http://localhost:2773";
+
+/// A sensitive value, which shows as `<redacted>` when printed
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    /// The value itself
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(val: String) -> Self {
+        Self(val)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(val: &str) -> Self {
+        Self(val.to_string())
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
+/// Somewhere secrets are kept
+pub trait Provider: Send + Sync {
+    /// The scheme of references to secrets kept here, e.g. `vault` for `vault://...`
+    fn scheme(&self) -> &str;
+
+    /// The secret at a location, which is the part of a reference after `scheme://`
+    fn fetch(&self, location: &str) -> Result<String, String>;
+}
+
+/// Secrets kept in other environment variables
+pub struct EnvProvider;
+
+impl Provider for EnvProvider {
+    fn scheme(&self) -> &str {
+        "env"
+    }
+
+    fn fetch(&self, location: &str) -> Result<String, String> {
+        std::env::var(location).map_err(|err| format!("Could not read {location}: {err}"))
+    }
+}
+
+/// Secrets kept in files, without the line ending they may finish with
+pub struct FileProvider;
+
+impl Provider for FileProvider {
+    fn scheme(&self) -> &str {
+        "file"
+    }
+
+    fn fetch(&self, location: &str) -> Result<String, String> {
+        let contents = std::fs::read_to_string(location)
+            .map_err(|err| format!("Could not read {location}: {err}"))?;
+        Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Secrets kept in a Vault KV version 2 engine, named as `mount/path#key`
+pub struct VaultProvider {
+    /// the address of Vault, or of an agent or proxy in front of it
+    pub addr: String,
+    /// the token to read secrets with, if the agent doesn't add one
+    pub token: Option<Secret>,
+    /// the Vault namespace secrets are in, if any
+    pub namespace: Option<String>,
+}
+
+impl VaultProvider {
+    /// Reach Vault as `VAULT_ADDR`, `VAULT_TOKEN`, and `VAULT_NAMESPACE` say; the address
+    /// defaults to a local agent on port 8200
+    pub fn from_env() -> Self {
+        Self {
+            addr: std::env::var("VAULT_ADDR")
+                .unwrap_or_else(|_| String::from("http://127.0.0.1:8200")),
+            token: std::env::var("VAULT_TOKEN").ok().map(Secret::from),
+            namespace: std::env::var("VAULT_NAMESPACE").ok(),
+        }
+    }
+}
+
+impl Provider for VaultProvider {
+    fn scheme(&self) -> &str {
+        "vault"
+    }
+
+    fn fetch(&self, location: &str) -> Result<String, String> {
+        let (path, key) = location
+            .split_once('#')
+            .ok_or_else(|| format!("Vault secrets are named as mount/path#key: {location}"))?;
+        let (mount, path) = path
+            .split_once('/')
+            .ok_or_else(|| format!("Vault secrets are named as mount/path#key: {location}"))?;
+
+        let mut headers = Vec::new();
+        if let Some(ref token) = self.token {
+            headers.push(("X-Vault-Token", token.expose()));
+        }
+        if let Some(ref namespace) = self.namespace {
+            headers.push(("X-Vault-Namespace", namespace.as_str()));
+        }
+        let url = format!("{}/v1/{mount}/data/{path}", self.addr.trim_end_matches('/'));
+        let body = get(&url, &headers)?;
+
+        let secret: Value = serde_json::from_str(&body)
+            .map_err(|err| format!("Could not parse Vault secret {path}: {err}"))?;
+        match secret["data"]["data"][key] {
+            Value::String(ref val) => Ok(val.clone()),
+            _ => Err(format!("Vault secret {mount}/{path} has no key {key}")),
+        }
+    }
+}
+
+/// Secrets kept in AWS Secrets Manager, named as `secret-id` or, for a key of a JSON secret,
+/// `secret-id#key`, and read through the Secrets Manager Agent
+pub struct AwsSecretsProvider {
+    /// the address of the agent
+    pub addr: String,
+    /// the token the agent expects
+    pub token: Option<Secret>,
+}
+
+impl AwsSecretsProvider {
+    /// Reach the agent as `AWS_SECRETS_AGENT_ADDR` says, default `http://localhost:2773`, with
+    /// `AWS_TOKEN` or else `AWS_SESSION_TOKEN` as the token
+    pub fn from_env() -> Self {
+        Self {
+            addr: std::env::var("AWS_SECRETS_AGENT_ADDR")
+                .unwrap_or_else(|_| String::from(AWS_AGENT)),
+            token: std::env::var("AWS_TOKEN")
+                .or_else(|_| std::env::var("AWS_SESSION_TOKEN"))
+                .ok()
+                .map(Secret::from),
+        }
+    }
+}
+
+impl Provider for AwsSecretsProvider {
+    fn scheme(&self) -> &str {
+        "aws-secrets"
+    }
+
+    fn fetch(&self, location: &str) -> Result<String, String> {
+        let (id, key) = match location.split_once('#') {
+            Some((id, key)) => (id, Some(key)),
+            None => (location, None),
+        };
+
+        let mut headers = Vec::new();
+        if let Some(ref token) = self.token {
+            headers.push(("X-Aws-Parameters-Secrets-Token", token.expose()));
+        }
+        let url = format!(
+            "{}/secretsmanager/get?secretId={}",
+            self.addr.trim_end_matches('/'),
+            utf8_percent_encode(id, NON_ALPHANUMERIC)
+        );
+        let body = get(&url, &headers)?;
+
+        let secret: Value = serde_json::from_str(&body)
+            .map_err(|err| format!("Could not parse AWS secret {id}: {err}"))?;
+        let val = match secret["SecretString"] {
+            Value::String(ref val) => val,
+            _ => return Err(format!("AWS secret {id} has no SecretString")),
+        };
+        let Some(key) = key else {
+            return Ok(val.clone());
+        };
+        let fields: Value = serde_json::from_str(val)
+            .map_err(|_| format!("AWS secret {id} isn't JSON, so it has no key {key}"))?;
+        match fields[key] {
+            Value::String(ref val) => Ok(val.clone()),
+            _ => Err(format!("AWS secret {id} has no key {key}")),
+        }
+    }
+}
+
+/// The providers secrets are read from
+pub struct Secrets {
+    providers: Vec<Box<dyn Provider>>,
+}
+
+impl Default for Secrets {
+    /// The environment, files, Vault, and AWS Secrets Manager
+    fn default() -> Self {
+        Self {
+            providers: vec![
+                Box::new(EnvProvider),
+                Box::new(FileProvider),
+                Box::new(VaultProvider::from_env()),
+                Box::new(AwsSecretsProvider::from_env()),
+            ],
+        }
+    }
+}
+
+impl Secrets {
+    /// Read secrets from another provider too, in place of any with the same scheme
+    pub fn with(mut self, provider: impl Provider + 'static) -> Self {
+        self.providers.retain(|p| p.scheme() != provider.scheme());
+        self.providers.push(Box::new(provider));
+        self
+    }
+
+    /// A secret given as is, or read from where a reference to it says
+    pub fn resolve(&self, val: &str) -> Result<Secret, String> {
+        let Some((scheme, location)) = val.split_once("://") else {
+            return Ok(Secret::from(val));
+        };
+        match self.providers.iter().find(|p| p.scheme() == scheme) {
+            Some(provider) => provider.fetch(location).map(Secret::from),
+            None => Ok(Secret::from(val)),
+        }
+    }
+
+    /// The secret an environment variable gives, if it is set and not empty
+    pub fn from_env(&self, var: &str) -> Result<Option<Secret>, String> {
+        match std::env::var(var) {
+            Ok(val) if !val.is_empty() => self
+                .resolve(&val)
+                .map(Some)
+                .map_err(|err| format!("Could not read {var}: {err}")),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Get a page, failing unless it answers 200; plain HTTP only reaches this host
+fn get(url: &str, headers: &[(&str, &str)]) -> Result<String, String> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|err| format!("{url} is not a valid address: {err}"))?;
+    match uri.scheme_str() {
+        Some("https") => {}
+        Some("http") => local_only(&uri)?,
+        _ => {
+            return Err(format!(
+                "Only http:// and https:// addresses can be reached: {url}"
+            ))
+        }
+    }
+
+    let mut req = Request::get(uri).header("Accept", "application/json");
+    for (name, val) in headers {
+        req = req.header(*name, *val);
+    }
+    let req = req.body(Body::empty()).map_err(|err| err.to_string())?;
+
+    // secrets are read while the configuration loads, which can be before any runtime is built
+    // or inside one, so the request gets a runtime and a thread of its own
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|err| format!("Could not get {url}: {err}"))?;
+                runtime.block_on(request(url, req))
+            })
+            .join()
+            .unwrap_or_else(|_| Err(format!("Could not get {url}")))
+    })
+}
+
+/// Make a request with the shared client and read the body of the answer
+async fn request(url: &str, req: Request<Body>) -> Result<String, String> {
+    let resp = timeout(TIMEOUT, http::client().request(req))
+        .await
+        .map_err(|_| format!("Timeout getting {url}"))?
+        .map_err(|err| format!("Could not get {url}: {err}"))?;
+    if resp.status() != StatusCode::OK {
+        return Err(format!("{url} answered {}", resp.status().as_u16()));
+    }
+
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .map_err(|err| format!("Could not get {url}: {err}"))?;
+    String::from_utf8(body.to_vec()).map_err(|_| format!("{url} didn't answer with text"))
+}
+
+/// Make sure a plain HTTP address is on this host, so nothing crosses the network in the clear
+fn local_only(uri: &Uri) -> Result<(), String> {
+    let host = uri.host().unwrap_or_default();
+    let addr = (host.trim_matches(['[', ']']), uri.port_u16().unwrap_or(80))
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("Could not resolve {host}"))?;
+    if !addr.ip().is_loopback() {
+        return Err(format!(
+            "Refusing to reach {host} without TLS; use https:// or an agent or proxy on this host"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_secrets() {
+        // a Vault agent answering one request in chunks
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let vault = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut req = [0; 1024];
+            let len = stream.read(&mut req).unwrap();
+            let body = r#"{"data":{"data":{"grant":"from-vault"}}}"#;
+            let (first, rest) = body.split_at(10);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{first}\r\n{:x}\r\n{rest}\r\n0\r\n\r\n",
+                first.len(),
+                rest.len()
+            )
+            .unwrap();
+            String::from_utf8_lossy(&req[..len]).to_string()
+        });
+
+        let path = std::env::temp_dir().join(format!("gatehouse-secret-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+        let secrets = Secrets::default().with(VaultProvider {
+            addr,
+            token: Some(Secret::from("root")),
+            namespace: None,
+        });
+
+        assert_eq!(secrets.resolve("plain").unwrap().expose(), "plain");
+        let file = format!("file://{}", path.display());
+        assert_eq!(secrets.resolve(&file).unwrap().expose(), "from-file");
+        let secret = secrets.resolve("vault://secret/gatehouse#grant").unwrap();
+        assert_eq!(secret.expose(), "from-vault");
+        let req = vault.join().unwrap();
+        assert!(req.starts_with("GET /v1/secret/data/gatehouse HTTP/1.1\r\n"));
+        assert!(req.to_ascii_lowercase().contains("x-vault-token: root\r\n"));
+
+        // secrets never show themselves
+        assert_eq!(format!("{secret} {secret:?}"), "<redacted> <redacted>");
+        assert!(secrets.resolve("vault://gatehouse").is_err());
+        assert!(secrets.resolve("file:///no/such/secret").is_err());
+
+        // nothing is sent in the clear to another host
+        let remote = Secrets::default().with(VaultProvider {
+            addr: String::from("http://192.0.2.1:8200"),
+            token: Some(Secret::from("root")),
+            namespace: None,
+        });
+        let err = remote
+            .resolve("vault://secret/gatehouse#grant")
+            .unwrap_err();
+        assert!(err.contains("without TLS"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
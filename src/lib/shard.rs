@@ -0,0 +1,188 @@
+#![warn(missing_docs)]
+
+//! Actors and targets split into shards by type
+//!
+//! Each shard holds the entities of some types behind its own lock, so changes to entities of
+//! one type don't wait on checks and changes that only touch others. The shard a type belongs
+//! to is picked by hashing its typestr. Calls that look at a single type take only its shard's
+//! lock; calls that look at every type take all of them, always in the same order.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Index;
+use std::sync::Arc;
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Entities of one kind, by interned type string and then by name
+pub(crate) type Typed<T> = HashMap<Arc<str>, HashMap<String, T>>;
+
+/// Entities split into shards by type string
+#[derive(Debug)]
+pub(crate) struct Sharded<T> {
+    shards: Vec<RwLock<Typed<T>>>,
+}
+
+impl<T> Sharded<T> {
+    /// Split entities into this many shards; 0 is the same as 1
+    pub fn new(count: usize, entities: Typed<T>) -> Self {
+        let mut shards: Vec<Typed<T>> = (0..count.max(1)).map(|_| HashMap::new()).collect();
+        let len = shards.len();
+        for (typestr, typed) in entities {
+            shards[index(len, &typestr)].insert(typestr, typed);
+        }
+
+        Self {
+            shards: shards.into_iter().map(RwLock::new).collect(),
+        }
+    }
+
+    /// The shard that holds the entities of a type
+    pub fn shard(&self, typestr: &str) -> &RwLock<Typed<T>> {
+        &self.shards[index(self.shards.len(), typestr)]
+    }
+
+    /// Lock every shard for reading
+    pub async fn read(&self) -> ShardsRead<'_, T> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            guards.push(shard.read().await);
+        }
+        ShardsRead { guards }
+    }
+
+    /// Lock the shard that holds a type for writing, along with how many entities there are in
+    /// every shard
+    ///
+    /// The shards are locked in the same order `read` locks them, so the two can't deadlock.
+    pub async fn write_counted(&self, typestr: &str) -> (RwLockWriteGuard<'_, Typed<T>>, usize) {
+        let own = index(self.shards.len(), typestr);
+        let mut count = 0;
+        let mut written = None;
+        let mut read = Vec::with_capacity(self.shards.len());
+        for (i, shard) in self.shards.iter().enumerate() {
+            if i == own {
+                let guard = shard.write().await;
+                count += guard.values().map(HashMap::len).sum::<usize>();
+                written = Some(guard);
+            } else {
+                let guard = shard.read().await;
+                count += guard.values().map(HashMap::len).sum::<usize>();
+                read.push(guard);
+            }
+        }
+        (written.expect("every type has a shard"), count)
+    }
+
+    /// Replace every entity at once
+    pub async fn replace(&self, entities: Typed<T>) {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            let mut guard = shard.write().await;
+            guard.clear();
+            guards.push(guard);
+        }
+        let len = guards.len();
+        for (typestr, typed) in entities {
+            guards[index(len, &typestr)].insert(typestr, typed);
+        }
+    }
+}
+
+/// Every shard, locked for reading
+pub(crate) struct ShardsRead<'a, T> {
+    guards: Vec<RwLockReadGuard<'a, Typed<T>>>,
+}
+
+impl<T> ShardsRead<'_, T> {
+    /// The entities of a type, by name
+    pub fn get(&self, typestr: &str) -> Option<&HashMap<String, T>> {
+        self.guards[index(self.guards.len(), typestr)].get(typestr)
+    }
+
+    /// Every type and its entities, by name
+    pub fn iter(&self) -> impl Iterator<Item = (&Arc<str>, &HashMap<String, T>)> {
+        self.guards.iter().flat_map(|guard| guard.iter())
+    }
+
+    /// The entities of every type, by name
+    pub fn values(&self) -> impl Iterator<Item = &HashMap<String, T>> {
+        self.guards.iter().flat_map(|guard| guard.values())
+    }
+}
+
+impl<T: Clone> ShardsRead<'_, T> {
+    /// A copy of every entity, with the shards put back together
+    pub fn to_map(&self) -> Typed<T> {
+        self.iter()
+            .map(|(typestr, typed)| (typestr.clone(), typed.clone()))
+            .collect()
+    }
+}
+
+impl<T> Index<&str> for ShardsRead<'_, T> {
+    type Output = HashMap<String, T>;
+
+    fn index(&self, typestr: &str) -> &Self::Output {
+        self.get(typestr).expect("no entities of that type")
+    }
+}
+
+/// The shard a type belongs in
+fn index(shards: usize, typestr: &str) -> usize {
+    if shards == 1 {
+        return 0;
+    }
+    let mut hasher = DefaultHasher::new();
+    typestr.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sharded() {
+        let mut entities: Typed<u32> = HashMap::new();
+        for (i, typestr) in ["user", "service", "database", "bucket"].iter().enumerate() {
+            entities.insert(
+                Arc::from(*typestr),
+                HashMap::from([(String::from("one"), i as u32)]),
+            );
+        }
+
+        let sharded = Sharded::new(3, entities.clone());
+        {
+            let all = sharded.read().await;
+            assert_eq!(all.iter().count(), 4);
+            assert_eq!(all["database"]["one"], 2);
+            assert!(all.get("missing").is_none());
+            assert_eq!(all.to_map(), entities);
+        }
+
+        // a type is always found in its shard
+        for typestr in ["user", "service", "database", "bucket"] {
+            assert!(sharded.shard(typestr).read().await.contains_key(typestr));
+        }
+
+        sharded
+            .shard("user")
+            .write()
+            .await
+            .get_mut("user")
+            .unwrap()
+            .insert(String::from("two"), 5);
+        assert_eq!(sharded.read().await["user"].len(), 2);
+
+        // every shard is counted, not only the one that is written
+        let (guard, count) = sharded.write_counted("bucket").await;
+        assert!(guard.contains_key("bucket"));
+        assert_eq!(count, 5);
+        drop(guard);
+
+        sharded.replace(HashMap::new()).await;
+        assert_eq!(sharded.read().await.values().count(), 0);
+    }
+}
@@ -0,0 +1,112 @@
+#![warn(missing_docs)]
+
+//! Decisions for an SSH certificate authority
+//!
+//! A request for a certificate is checked against the host as a target, by default of type
+//! `host`. Each principal asked for is checked as the action `principal:<name>` and each option
+//! as `option:<name>`, so policies can allow, for instance, `principal:deploy` on every host of a
+//! team. The certificate may name the principals that are allowed, and have the options that are
+//! allowed; if no principal is allowed, it may not be issued at all. It may be valid for as long
+//! as was asked for, up to the host's `ssh-max-ttl` attribute in seconds, or an hour if the host
+//! has none.
+
+use std::collections::HashSet;
+
+use crate::proto::base::{
+    ActionMode, CheckRequest, CheckResponse, SshCertRequest, SshCertResponse,
+};
+use crate::proto::policies::Decide;
+use crate::proto::targets::Target;
+
+/// the target type hosts are registered as, unless the request says otherwise
+pub(crate) const DEFAULT_HOST_TYPE: &str = "host";
+
+/// how long certificates may be valid for, in seconds, when the host doesn't say
+const DEFAULT_MAX_TTL: u32 = 60 * 60;
+
+/// the host attribute that limits how long certificates may be valid for, in seconds
+const MAX_TTL_ATTRIBUTE: &str = "ssh-max-ttl";
+
+/// The type of target the host of a request is
+pub(crate) fn host_type(req: &SshCertRequest) -> String {
+    match req.host_type.is_empty() {
+        true => String::from(DEFAULT_HOST_TYPE),
+        false => req.host_type.clone(),
+    }
+}
+
+/// The check that decides on a request, with a decision for each principal and option
+pub(crate) fn check_request(req: &SshCertRequest) -> Result<CheckRequest, String> {
+    if req.host.is_empty() {
+        return Err(String::from("Host cannot be empty"));
+    }
+    if req.principals.is_empty() {
+        return Err(String::from("At least one principal must be asked for"));
+    }
+
+    let actions = req
+        .principals
+        .iter()
+        .map(|principal| format!("principal:{principal}"))
+        .chain(req.options.iter().map(|option| format!("option:{option}")))
+        .collect();
+
+    Ok(CheckRequest {
+        actor: req.actor.clone(),
+        env_attributes: req.env_attributes.clone(),
+        target_name: req.host.clone(),
+        target_type: host_type(req),
+        target_action: actions,
+        action_mode: ActionMode::EachAction.into(),
+        ..Default::default()
+    })
+}
+
+/// How long a host lets certificates be valid for; the smallest value if it has several
+pub(crate) fn max_ttl(host: Option<&Target>) -> u32 {
+    host.and_then(|host| host.attributes.get(MAX_TTL_ATTRIBUTE))
+        .and_then(|vals| vals.values.iter().filter_map(|val| val.parse().ok()).min())
+        .unwrap_or(DEFAULT_MAX_TTL)
+}
+
+/// What the certificate may be, given the decision on each of its principals and options
+pub(crate) fn response(req: SshCertRequest, check: CheckResponse, max_ttl: u32) -> SshCertResponse {
+    let allowed: HashSet<&str> = check
+        .action_decisions
+        .iter()
+        .filter(|decision| decision.decision() == Decide::Allow)
+        .map(|decision| decision.action.as_str())
+        .collect();
+    let is_allowed = |prefix: &str, name: &String| allowed.contains(&*format!("{prefix}:{name}"));
+
+    let principals: Vec<String> = req
+        .principals
+        .into_iter()
+        .filter(|principal| is_allowed("principal", principal))
+        .collect();
+    if principals.is_empty() {
+        return SshCertResponse {
+            decision: Decide::Deny.into(),
+            correlation_id: check.correlation_id,
+            ..Default::default()
+        };
+    }
+
+    let options = req
+        .options
+        .into_iter()
+        .filter(|option| is_allowed("option", option))
+        .collect();
+    let ttl_seconds = match req.ttl_seconds {
+        0 => max_ttl,
+        ttl => ttl.min(max_ttl),
+    };
+
+    SshCertResponse {
+        decision: Decide::Allow.into(),
+        principals,
+        options,
+        ttl_seconds,
+        correlation_id: check.correlation_id,
+    }
+}
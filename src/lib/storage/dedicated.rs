@@ -0,0 +1,273 @@
+//! A storage backend running on a runtime of its own
+//!
+//! Every call is spawned onto the storage runtime and awaited from the caller's, so the work of
+//! talking to the backend never takes a thread checks could be decided on. The arguments of a
+//! call are copied so the spawned task can own them.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tonic::async_trait;
+
+use crate::actor::RegisteredActor;
+use crate::apikey::RegisteredApiKey;
+use crate::breakglass::RegisteredBreakGlass;
+use crate::bundle::AppliedBundle;
+use crate::delegation::RegisteredDelegation;
+use crate::group::RegisteredGroup;
+use crate::migrate::Upgraded;
+use crate::policy::RegisteredPolicyRule;
+use crate::policyset::RegisteredPolicySet;
+use crate::proposal::RegisteredProposal;
+use crate::role::RegisteredRole;
+use crate::shard::Typed;
+use crate::target::RegisteredTarget;
+use crate::webhook::RegisteredWebhook;
+
+use super::{BackendUpdate, Leadership, PersistError, Storage};
+
+type Backend = Arc<dyn Storage + Send + Sync>;
+
+/// A storage backend whose calls run on another runtime
+pub(crate) struct DedicatedStorage {
+    inner: Backend,
+    runtime: Handle,
+}
+
+impl DedicatedStorage {
+    /// Run a backend's calls on a runtime
+    pub(crate) fn new(inner: Box<dyn Storage + Send + Sync>, runtime: Handle) -> Self {
+        Self {
+            inner: Arc::from(inner),
+            runtime,
+        }
+    }
+
+    /// Run a call on the storage runtime
+    async fn run<T, E, F>(&self, call: impl FnOnce(Backend) -> F) -> Result<T, E>
+    where
+        T: Send + 'static,
+        E: From<String> + Send + 'static,
+        F: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        self.runtime
+            .spawn(call(self.inner.clone()))
+            .await
+            .map_err(|err| E::from(format!("Storage call failed: {err}")))?
+    }
+}
+
+#[async_trait]
+impl Storage for DedicatedStorage {
+    async fn save_target(&self, tgt: &RegisteredTarget) -> Result<(), String> {
+        let tgt = tgt.clone();
+        self.run(|inner| async move { inner.save_target(&tgt).await })
+            .await
+    }
+    async fn remove_target(&self, typestr: &str, name: &str) -> Result<(), String> {
+        let (typestr, name) = (typestr.to_string(), name.to_string());
+        self.run(|inner| async move { inner.remove_target(&typestr, &name).await })
+            .await
+    }
+    async fn load_targets(&self) -> Result<Typed<RegisteredTarget>, String> {
+        self.run(|inner| async move { inner.load_targets().await })
+            .await
+    }
+    async fn save_actor(&self, tgt: &RegisteredActor) -> Result<(), String> {
+        let tgt = tgt.clone();
+        self.run(|inner| async move { inner.save_actor(&tgt).await })
+            .await
+    }
+    async fn remove_actor(&self, typestr: &str, name: &str) -> Result<(), String> {
+        let (typestr, name) = (typestr.to_string(), name.to_string());
+        self.run(|inner| async move { inner.remove_actor(&typestr, &name).await })
+            .await
+    }
+    async fn load_actors(&self) -> Result<Typed<RegisteredActor>, String> {
+        self.run(|inner| async move { inner.load_actors().await })
+            .await
+    }
+    async fn save_role(&self, role: &RegisteredRole) -> Result<(), String> {
+        let role = role.clone();
+        self.run(|inner| async move { inner.save_role(&role).await })
+            .await
+    }
+    async fn remove_role(&self, name: &str) -> Result<(), String> {
+        let name = name.to_string();
+        self.run(|inner| async move { inner.remove_role(&name).await })
+            .await
+    }
+    async fn load_roles(&self) -> Result<HashMap<String, RegisteredRole>, String> {
+        self.run(|inner| async move { inner.load_roles().await })
+            .await
+    }
+    async fn save_group(&self, group: &RegisteredGroup) -> Result<(), String> {
+        let group = group.clone();
+        self.run(|inner| async move { inner.save_group(&group).await })
+            .await
+    }
+    async fn remove_group(&self, name: &str) -> Result<(), String> {
+        let name = name.to_string();
+        self.run(|inner| async move { inner.remove_group(&name).await })
+            .await
+    }
+    async fn load_groups(&self) -> Result<HashMap<String, RegisteredGroup>, String> {
+        self.run(|inner| async move { inner.load_groups().await })
+            .await
+    }
+    async fn save_policy(&self, policy: &RegisteredPolicyRule) -> Result<(), String> {
+        let policy = policy.clone();
+        self.run(|inner| async move { inner.save_policy(&policy).await })
+            .await
+    }
+    async fn remove_policy(&self, name: &str) -> Result<(), String> {
+        let name = name.to_string();
+        self.run(|inner| async move { inner.remove_policy(&name).await })
+            .await
+    }
+    async fn load_policies(&self) -> Result<HashMap<String, RegisteredPolicyRule>, String> {
+        self.run(|inner| async move { inner.load_policies().await })
+            .await
+    }
+    async fn save_policy_set(&self, set: &RegisteredPolicySet) -> Result<(), String> {
+        let set = set.clone();
+        self.run(|inner| async move { inner.save_policy_set(&set).await })
+            .await
+    }
+    async fn remove_policy_set(&self, name: &str) -> Result<(), String> {
+        let name = name.to_string();
+        self.run(|inner| async move { inner.remove_policy_set(&name).await })
+            .await
+    }
+    async fn load_policy_sets(&self) -> Result<HashMap<String, RegisteredPolicySet>, String> {
+        self.run(|inner| async move { inner.load_policy_sets().await })
+            .await
+    }
+    async fn save_webhook(&self, hook: &RegisteredWebhook) -> Result<(), String> {
+        let hook = hook.clone();
+        self.run(|inner| async move { inner.save_webhook(&hook).await })
+            .await
+    }
+    async fn remove_webhook(&self, name: &str) -> Result<(), String> {
+        let name = name.to_string();
+        self.run(|inner| async move { inner.remove_webhook(&name).await })
+            .await
+    }
+    async fn load_webhooks(&self) -> Result<HashMap<String, RegisteredWebhook>, String> {
+        self.run(|inner| async move { inner.load_webhooks().await })
+            .await
+    }
+    async fn save_api_key(&self, key: &RegisteredApiKey) -> Result<(), String> {
+        let key = key.clone();
+        self.run(|inner| async move { inner.save_api_key(&key).await })
+            .await
+    }
+    async fn remove_api_key(&self, id: &str) -> Result<(), String> {
+        let id = id.to_string();
+        self.run(|inner| async move { inner.remove_api_key(&id).await })
+            .await
+    }
+    async fn load_api_keys(&self) -> Result<HashMap<String, RegisteredApiKey>, String> {
+        self.run(|inner| async move { inner.load_api_keys().await })
+            .await
+    }
+    async fn save_delegation(&self, delegation: &RegisteredDelegation) -> Result<(), String> {
+        let delegation = delegation.clone();
+        self.run(|inner| async move { inner.save_delegation(&delegation).await })
+            .await
+    }
+    async fn remove_delegation(&self, name: &str) -> Result<(), String> {
+        let name = name.to_string();
+        self.run(|inner| async move { inner.remove_delegation(&name).await })
+            .await
+    }
+    async fn load_delegations(&self) -> Result<HashMap<String, RegisteredDelegation>, String> {
+        self.run(|inner| async move { inner.load_delegations().await })
+            .await
+    }
+    async fn save_proposal(&self, proposal: &RegisteredProposal) -> Result<(), String> {
+        let proposal = proposal.clone();
+        self.run(|inner| async move { inner.save_proposal(&proposal).await })
+            .await
+    }
+    async fn remove_proposal(&self, id: &str) -> Result<(), String> {
+        let id = id.to_string();
+        self.run(|inner| async move { inner.remove_proposal(&id).await })
+            .await
+    }
+    async fn load_proposals(&self) -> Result<HashMap<String, RegisteredProposal>, String> {
+        self.run(|inner| async move { inner.load_proposals().await })
+            .await
+    }
+    async fn save_break_glass(&self, break_glass: &RegisteredBreakGlass) -> Result<(), String> {
+        let break_glass = break_glass.clone();
+        self.run(|inner| async move { inner.save_break_glass(&break_glass).await })
+            .await
+    }
+    async fn remove_break_glass(&self, id: &str) -> Result<(), String> {
+        let id = id.to_string();
+        self.run(|inner| async move { inner.remove_break_glass(&id).await })
+            .await
+    }
+    async fn load_break_glasses(&self) -> Result<HashMap<String, RegisteredBreakGlass>, String> {
+        self.run(|inner| async move { inner.load_break_glasses().await })
+            .await
+    }
+    async fn save_bundle(&self, bundle: &AppliedBundle) -> Result<(), String> {
+        let bundle = bundle.clone();
+        self.run(|inner| async move { inner.save_bundle(&bundle).await })
+            .await
+    }
+    async fn load_bundle(&self) -> Result<Option<AppliedBundle>, String> {
+        self.run(|inner| async move { inner.load_bundle().await })
+            .await
+    }
+    async fn persist_changes(&self, updates: &[BackendUpdate]) -> Result<(), PersistError> {
+        let updates = updates.to_vec();
+        self.run(|inner| async move { inner.persist_changes(&updates).await })
+            .await
+    }
+    async fn ping(&self) -> Result<(), String> {
+        self.run(|inner| async move { inner.ping().await }).await
+    }
+    fn upgraded(&self) -> Vec<Upgraded> {
+        self.inner.upgraded()
+    }
+    fn leadership(&self) -> watch::Receiver<Leadership> {
+        self.inner.leadership()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::runtime::Builder;
+
+    use super::*;
+    use crate::storage::nil::NilStorage;
+
+    #[tokio::test]
+    async fn test_dedicated_storage() {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("storage-test")
+            .enable_all()
+            .build()
+            .unwrap();
+        let storage = DedicatedStorage::new(Box::new(NilStorage {}), runtime.handle().clone());
+
+        let name: Result<_, String> = storage
+            .run(|_| async { Ok(std::thread::current().name().map(String::from)) })
+            .await;
+        assert_eq!(name, Ok(Some(String::from("storage-test"))));
+        assert!(storage
+            .load_roles()
+            .await
+            .is_ok_and(|roles| roles.is_empty()));
+
+        // a runtime can't be dropped from within another
+        runtime.shutdown_background();
+    }
+}
@@ -2,48 +2,234 @@ use std::collections::HashMap;
 use std::process::exit;
 use std::sync::Arc;
 
-use etcd_client::{Client, Event, EventType, GetOptions, WatchOptions, WatchStream};
+use etcd_client::{
+    Client, ConnectOptions, Event, EventType, GetOptions, KeyValue, LeaseKeepAliveStream,
+    LeaseKeeper, Txn, TxnOp, WatchOptions, WatchStream,
+};
 use flume::Receiver;
-use lazy_static::lazy_static;
-use regex::Regex;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use tokio::time::{sleep, Duration};
 use tonic::async_trait;
 
 use crate::actor::RegisteredActor;
+use crate::apikey::RegisteredApiKey;
+use crate::breakglass::RegisteredBreakGlass;
+use crate::bundle::AppliedBundle;
+use crate::config::Config;
+use crate::delegation::RegisteredDelegation;
 use crate::group::RegisteredGroup;
-use crate::msgs::DsRequest;
+use crate::migrate::{self, Upgraded, Upgrades};
+use crate::msgs::{DsRequest, DsResponse};
 use crate::policy::RegisteredPolicyRule;
+use crate::policyset::RegisteredPolicySet;
+use crate::proposal::RegisteredProposal;
+use crate::proto::base::ServingRole;
 use crate::role::RegisteredRole;
-use crate::storage::BackendUpdate;
+use crate::secrets::Secret;
+use crate::shard::Typed;
+use crate::storage::{BackendUpdate, Leadership, PersistError};
 use crate::target::RegisteredTarget;
+use crate::webhook::RegisteredWebhook;
 
 use super::Storage;
 
-lazy_static! {
-    static ref TYPE_MATCH: Regex = Regex::new(r"/gatehouse/(.*?)/(.*)").unwrap();
-}
+/// where data is kept when no prefix is configured
+const DEFAULT_PREFIX: &str = "/gatehouse";
+
+/// the servers sharing a base path campaign in an election under here, kept outside of the base
+/// path so the watch doesn't see it
+const ELECTION_PREFIX: &str = "/election";
+
+/// how many seconds the leader can go without renewing its lease before it loses leadership
+const LEASE_TTL: i64 = 10;
 
 fn econv<T: std::fmt::Display>(err: T) -> String {
     err.to_string()
 }
 
+/// Why a watch stopped, when it wasn't because of an error
+enum WatchEnd {
+    /// we were told to stop
+    Killed,
+    /// the changes we hadn't seen yet were compacted away, as of this revision
+    Compacted(i64),
+}
+
+/// The key prefix to keep data under, set apart for each environment so several of them can
+/// share one cluster
+fn basepath(prefix: Option<&str>, environment: Option<&str>) -> Result<String, String> {
+    let prefix = prefix.unwrap_or(DEFAULT_PREFIX).trim_end_matches('/');
+    if !prefix.starts_with('/') {
+        return Err(format!(
+            "Etcd prefix must be a path like {DEFAULT_PREFIX}: {prefix:?}"
+        ));
+    }
+
+    match environment {
+        Some(env) if env.is_empty() || env.contains('/') => Err(format!(
+            "Environment must be a name without slashes: {env:?}"
+        )),
+        Some(env) => Ok(format!("{prefix}-{env}")),
+        None => Ok(prefix.to_string()),
+    }
+}
+
+/// Split a key under the base path into the object type and name
+fn split_key<'a>(basepath: &str, key: &'a str) -> Result<(&'a str, &'a str), String> {
+    key.strip_prefix(basepath)
+        .and_then(|path| path.strip_prefix('/'))
+        .and_then(|path| path.split_once('/'))
+        .ok_or_else(|| format!("Could not determine type/name from key {key}"))
+}
+
+/// Turn a key and its value into the message back to the datastore
+fn kv_update(
+    basepath: &str,
+    event_type: &EventType,
+    kv: &KeyValue,
+) -> Result<BackendUpdate, String> {
+    let key = kv.key_str().map_err(econv)?;
+    let val = kv.value_str().map_err(econv)?;
+
+    let (obj_type, obj_name) = split_key(basepath, key)?;
+
+    build_update(event_type, obj_type, obj_name, val)
+}
+
+/// Build the message back to the datastore
+fn build_update(
+    event_type: &EventType,
+    obj_type: &str,
+    obj_name: &str,
+    val: &str,
+) -> Result<BackendUpdate, String> {
+    match event_type {
+        EventType::Put => match obj_type {
+            "actors" => {
+                let obj: RegisteredActor = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutActor(obj))
+            }
+            "groups" => {
+                let obj: RegisteredGroup = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutGroup(obj))
+            }
+            "policies" => {
+                let obj: RegisteredPolicyRule = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutPolicyRule(Box::new(obj)))
+            }
+            "policysets" => {
+                let obj: RegisteredPolicySet = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutPolicySet(obj))
+            }
+            "roles" => {
+                let obj: RegisteredRole = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutRole(obj))
+            }
+            "targets" => {
+                let obj: RegisteredTarget = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutTarget(obj))
+            }
+            "webhooks" => {
+                let obj: RegisteredWebhook = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutWebhook(obj))
+            }
+            "apikeys" => {
+                let obj: RegisteredApiKey = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutApiKey(obj))
+            }
+            "delegations" => {
+                let obj: RegisteredDelegation = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutDelegation(obj))
+            }
+            "proposals" => {
+                let obj: RegisteredProposal = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutProposal(obj))
+            }
+            "breakglasses" => {
+                let obj: RegisteredBreakGlass = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutBreakGlass(obj))
+            }
+            "bundles" => {
+                let obj: AppliedBundle = migrate::decode(obj_type, val)?;
+                Ok(BackendUpdate::PutBundle(obj))
+            }
+            _ => Err(format!("Unknown object type: {obj_type}")),
+        },
+        EventType::Delete => match obj_type {
+            "actors" => {
+                let (typestr, name) = obj_name
+                    .split_once('/')
+                    .ok_or_else(|| format!("Could not get type and name from actor {obj_name}"))?;
+                Ok(BackendUpdate::DeleteActor(
+                    typestr.to_string(),
+                    name.to_string(),
+                ))
+            }
+            "groups" => Ok(BackendUpdate::DeleteGroup(obj_name.to_string())),
+            "policies" => Ok(BackendUpdate::DeletePolicyRule(obj_name.to_string())),
+            "policysets" => Ok(BackendUpdate::DeletePolicySet(obj_name.to_string())),
+            "roles" => Ok(BackendUpdate::DeleteRole(obj_name.to_string())),
+            "targets" => {
+                let (typestr, name) = obj_name
+                    .split_once('/')
+                    .ok_or_else(|| format!("Could not get type and name from target {obj_name}"))?;
+                Ok(BackendUpdate::DeleteTarget(
+                    typestr.to_string(),
+                    name.to_string(),
+                ))
+            }
+            "webhooks" => Ok(BackendUpdate::DeleteWebhook(obj_name.to_string())),
+            "apikeys" => Ok(BackendUpdate::DeleteApiKey(obj_name.to_string())),
+            "delegations" => Ok(BackendUpdate::DeleteDelegation(obj_name.to_string())),
+            "proposals" => Ok(BackendUpdate::DeleteProposal(obj_name.to_string())),
+            "breakglasses" => Ok(BackendUpdate::DeleteBreakGlass(obj_name.to_string())),
+            _ => Err(format!("Unknown object type: {obj_type}")),
+        },
+    }
+}
+
 pub(crate) struct EtcdStorage {
     basepath: String,
     client: Client,
+    leadership: watch::Receiver<Leadership>,
+    /// what was upgraded from an older schema as it was loaded
+    upgrades: Upgrades,
 }
 
 impl EtcdStorage {
-    pub async fn new(url: &str, req_tx: flume::Sender<DsRequest>) -> Self {
-        let mut client = match Client::connect([url], None).await {
+    /// Connect to any of a comma separated list of endpoints
+    pub async fn new(urls: &str, req_tx: flume::Sender<DsRequest>, config: &Config) -> Self {
+        let endpoints: Vec<&str> = urls
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .collect();
+        if endpoints.is_empty() {
+            eprintln!("No Etcd endpoints given");
+            exit(1);
+        }
+
+        let basepath = match basepath(config.etcd_prefix.as_deref(), config.environment.as_deref())
+        {
+            Ok(basepath) => basepath,
+            Err(err) => {
+                eprintln!("{err}");
+                exit(1);
+            }
+        };
+
+        let options = config.etcd_user.as_ref().map(|user| {
+            let password = config.etcd_password.as_ref().map(Secret::expose);
+            ConnectOptions::new().with_user(user, password.unwrap_or_default())
+        });
+        let mut client = match Client::connect(&endpoints, options).await {
             Ok(client) => client,
             Err(err) => {
                 eprintln!("Could not connect to Etcd storage: {err}");
                 exit(1);
             }
         };
-
-        let basepath = String::from("/gatehouse");
+        println!("Using Etcd at {} under {basepath}", endpoints.join(", "));
 
         // test our connection to Etcd
         if let Err(err) = client.get(basepath.as_bytes(), None).await {
@@ -77,7 +263,117 @@ impl EtcdStorage {
             EtcdStorage::watch_manager(client_copy, &basepath_copy, last_rev_arc, req_tx).await
         });
 
-        Self { basepath, client }
+        // without an election we are the only server using this backend
+        let (leadership_tx, leadership) = watch::channel(Leadership::default());
+        if let Some(ref name) = config.election {
+            leadership_tx.send_replace(Leadership {
+                role: ServingRole::Standby,
+                leader: None,
+            });
+
+            let name = name.clone();
+            let election = format!("{ELECTION_PREFIX}{basepath}");
+            let client_copy = client.clone();
+            tokio::spawn(async move {
+                EtcdStorage::campaign(client_copy, &election, &name, leadership_tx).await
+            });
+        }
+
+        Self {
+            basepath,
+            client,
+            leadership,
+            upgrades: Upgrades::default(),
+        }
+    }
+
+    /// Campaign for leadership for as long as we run, standing by whenever we aren't the leader
+    async fn campaign(
+        mut client: Client,
+        election: &str,
+        name: &str,
+        leadership: watch::Sender<Leadership>,
+    ) {
+        loop {
+            let err = Self::lead(&mut client, election, name, &leadership).await;
+            eprintln!("Not leading as {name}: {err}");
+
+            leadership.send_modify(|l| {
+                l.role = ServingRole::Standby;
+                l.leader = None;
+            });
+            sleep(Duration::from_secs(2)).await;
+        }
+    }
+
+    /// Wait to be elected and then lead until our lease is lost, returning why we stopped
+    async fn lead(
+        client: &mut Client,
+        election: &str,
+        name: &str,
+        leadership: &watch::Sender<Leadership>,
+    ) -> String {
+        let lease = match client.lease_grant(LEASE_TTL, None).await {
+            Ok(lease) => lease.id(),
+            Err(err) => return format!("Could not get a lease: {err}"),
+        };
+        let (keeper, responses) = match client.lease_keep_alive(lease).await {
+            Ok(keep_alive) => keep_alive,
+            Err(err) => return format!("Could not keep our lease alive: {err}"),
+        };
+        let mut observer = match client.observe(election).await {
+            Ok(observer) => observer,
+            Err(err) => return format!("Could not observe the election: {err}"),
+        };
+
+        let keep_alive = Self::keep_alive(keeper, responses);
+        tokio::pin!(keep_alive);
+        let mut campaigner = client.clone();
+        let campaign = campaigner.campaign(election, name, lease);
+        tokio::pin!(campaign);
+
+        // keep track of who leads while we wait for our turn
+        loop {
+            tokio::select! {
+                err = &mut keep_alive => return err,
+                resp = observer.message() => match resp {
+                    Ok(Some(resp)) => {
+                        let leader = resp.kv().and_then(|kv| kv.value_str().ok());
+                        leadership.send_modify(|l| l.leader = leader.map(String::from));
+                    }
+                    Ok(None) => return String::from("Election observer closed"),
+                    Err(err) => return format!("Election observer failed: {err}"),
+                },
+                resp = &mut campaign => match resp {
+                    Ok(_) => break,
+                    Err(err) => return format!("Campaign failed: {err}"),
+                },
+            }
+        }
+
+        println!("Elected leader as {name}");
+        leadership.send_replace(Leadership {
+            role: ServingRole::Leader,
+            leader: Some(name.to_string()),
+        });
+
+        keep_alive.await
+    }
+
+    /// Renew a lease until we can't, returning why
+    async fn keep_alive(mut keeper: LeaseKeeper, mut responses: LeaseKeepAliveStream) -> String {
+        loop {
+            sleep(Duration::from_secs(LEASE_TTL as u64 / 3)).await;
+
+            if let Err(err) = keeper.keep_alive().await {
+                return format!("Could not renew our lease: {err}");
+            }
+            match responses.message().await {
+                Ok(Some(resp)) if resp.ttl() > 0 => {}
+                Ok(_) => return String::from("Our lease expired"),
+                Err(err) => return format!("Could not renew our lease: {err}"),
+            }
+        }
     }
 
     /// The watch manager establishes the watch on Etcd and reestablishs the watch if connectivity
@@ -97,9 +393,10 @@ impl EtcdStorage {
         loop {
             println!("STARTING WATCH AT {}", last_rev.lock().await);
             // start the watch
+            // the trailing slash keeps us from seeing prefixes that merely start like ours
             let (mut watcher, watch_stream) = match client
                 .watch(
-                    basepath,
+                    format!("{basepath}/"),
                     Some(
                         WatchOptions::new()
                             .with_prefix()
@@ -122,13 +419,24 @@ impl EtcdStorage {
             let last_rev_copy = last_rev.clone();
             let (kill_signal, kill_receiver) = flume::bounded(1);
             let req_tx_clone = req_tx.clone();
+            let basepath_copy = basepath.to_string();
             let stream_watcher = tokio::spawn(async move {
-                match Self::watch_changes(watch_stream, last_rev_copy, kill_receiver, req_tx_clone)
-                    .await
-                {
-                    Ok(_) => println!("stream watched exited normally"),
-                    Err(err) => println!("stream watch exited with error: {err}"),
+                let ended = Self::watch_changes(
+                    watch_stream,
+                    &basepath_copy,
+                    last_rev_copy,
+                    kill_receiver,
+                    req_tx_clone,
+                )
+                .await;
+                match ended {
+                    Ok(WatchEnd::Killed) => println!("stream watched exited normally"),
+                    Ok(WatchEnd::Compacted(rev)) => {
+                        println!("stream watch missed changes compacted at revision {rev}")
+                    }
+                    Err(ref err) => println!("stream watch exited with error: {err}"),
                 }
+                ended
             });
 
             // this loop askes for progress so we can detect if our stream died
@@ -154,96 +462,110 @@ impl EtcdStorage {
             if !stream_watcher.is_finished() {
                 match kill_signal.send(()) {
                     Err(err) => {
+                        // the stream watcher has already dropped its end, so it is done
                         eprintln!("Error sending kill signal to stream watcher: {err}");
-                        // stream_watcher.abort();
                     }
                     Ok(_) => {
                         println!("Waiting for watch stream to stop so we can restart");
-                        let _ = stream_watcher.await;
                     }
                 }
             } else {
                 println!("Stream watcher is already shut down");
             }
 
+            // the changes we missed are gone, so reload everything before watching again
+            if let Ok(Ok(WatchEnd::Compacted(_))) = stream_watcher.await {
+                Self::resync(&mut client, basepath, &last_rev, &req_tx).await;
+            }
+
             // take a breather before starting back up
             sleep(Duration::from_secs(10)).await;
         }
     }
 
+    /// Reload everything under the base path and reconcile the datastore with it, retrying
+    /// until it works
+    ///
+    /// The watch restarts from the revision of the reload, so nothing after it is missed.
+    async fn resync(
+        client: &mut Client,
+        basepath: &str,
+        last_rev: &Mutex<i64>,
+        req_tx: &flume::Sender<DsRequest>,
+    ) {
+        loop {
+            match Self::reload(client, basepath, req_tx).await {
+                Ok((revision, changed)) => {
+                    println!("Resynced with Etcd at revision {revision}; {changed} changes");
+                    *last_rev.lock().await = revision;
+                    return;
+                }
+                Err(err) => {
+                    eprintln!("Could not resync with Etcd: {err}");
+                    eprintln!("Retrying in 2 seconds...");
+                    sleep(Duration::from_secs(2)).await;
+                }
+            }
+        }
+    }
+
+    /// Load every key under the base path and have the datastore reconcile with them,
+    /// returning the revision loaded and how many entities changed
+    async fn reload(
+        client: &mut Client,
+        basepath: &str,
+        req_tx: &flume::Sender<DsRequest>,
+    ) -> Result<(i64, usize), String> {
+        let results = client
+            .get(
+                format!("{basepath}/"),
+                Some(GetOptions::new().with_prefix()),
+            )
+            .await
+            .map_err(econv)?;
+        let revision = results
+            .header()
+            .map(|header| header.revision())
+            .ok_or_else(|| String::from("No header in Etcd response"))?;
+
+        let mut loaded = Vec::new();
+        for kv in results.kvs() {
+            match kv_update(basepath, &EventType::Put, kv) {
+                Ok(update) => loaded.push(update),
+                Err(err) => eprintln!("Skipping {:?} in resync: {err}", kv.key_str()),
+            }
+        }
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        req_tx
+            .send_async(DsRequest::Reconcile(loaded, tx))
+            .await
+            .map_err(econv)?;
+        match rx.await.map_err(econv)? {
+            DsResponse::Reconciled(changed) => Ok((revision, changed)),
+            _ => Err(String::from("Got unexpected answer from datastore")),
+        }
+    }
+
     /// Watch the stream for incoming changes
     ///
     /// As new messages arrive, we'll send them to the datastore. If we hit an error, send a signal back
     /// to the watch manager so we can restart everything.  If we get a kill signal, stop and exit.
+    /// If the revisions we still need were compacted away, say so, since the manager has to reload
+    /// everything to catch up.
     ///
     /// When new messages are processed, update the revision number so we can restart a watch as needed
     async fn watch_changes(
         mut stream: WatchStream,
+        basepath: &str,
         last_rev: Arc<Mutex<i64>>,
         kill_receiver: Receiver<()>,
         req_tx: flume::Sender<DsRequest>,
-    ) -> Result<(), String> {
-        // build the message back to the datastore
-        fn build_update(
-            event_type: &EventType,
-            obj_type: &str,
-            obj_name: &str,
-            val: &str,
-        ) -> Result<BackendUpdate, String> {
-            match event_type {
-                EventType::Put => match obj_type {
-                    "actors" => {
-                        let obj: RegisteredActor = serde_json::from_str(val).map_err(econv)?;
-                        Ok(BackendUpdate::PutActor(obj))
-                    }
-                    "groups" => {
-                        let obj: RegisteredGroup = serde_json::from_str(val).map_err(econv)?;
-                        Ok(BackendUpdate::PutGroup(obj))
-                    }
-                    "policies" => {
-                        let obj: RegisteredPolicyRule = serde_json::from_str(val).map_err(econv)?;
-                        Ok(BackendUpdate::PutPolicyRule(obj))
-                    }
-                    "roles" => {
-                        let obj: RegisteredRole = serde_json::from_str(val).map_err(econv)?;
-                        Ok(BackendUpdate::PutRole(obj))
-                    }
-                    "targets" => {
-                        let obj: RegisteredTarget = serde_json::from_str(val).map_err(econv)?;
-                        Ok(BackendUpdate::PutTarget(obj))
-                    }
-                    _ => Err(format!("Unknown object type: {obj_type}")),
-                },
-                EventType::Delete => match obj_type {
-                    "actors" => {
-                        let (typestr, name) = obj_name.split_once('/').ok_or_else(|| {
-                            format!("Could not get type and name from actor {obj_name}")
-                        })?;
-                        Ok(BackendUpdate::DeleteActor(
-                            typestr.to_string(),
-                            name.to_string(),
-                        ))
-                    }
-                    "groups" => Ok(BackendUpdate::DeleteGroup(obj_name.to_string())),
-                    "policies" => Ok(BackendUpdate::DeletePolicyRule(obj_name.to_string())),
-                    "roles" => Ok(BackendUpdate::DeleteRole(obj_name.to_string())),
-                    "targets" => {
-                        let (typestr, name) = obj_name.split_once('/').ok_or_else(|| {
-                            format!("Could not get type and name from target {obj_name}")
-                        })?;
-                        Ok(BackendUpdate::DeleteTarget(
-                            typestr.to_string(),
-                            name.to_string(),
-                        ))
-                    }
-                    _ => Err(format!("Unknown object type: {obj_type}")),
-                },
-            }
-        }
-
+    ) -> Result<WatchEnd, String> {
         // handle a put or delete event
         async fn handle_event(
             event: &Event,
+            basepath: &str,
             req_tx: &flume::Sender<DsRequest>,
         ) -> Result<(), String> {
             if event.kv().is_none() {
@@ -251,27 +573,7 @@ impl EtcdStorage {
                 return Err("No KV in event".to_string());
             }
 
-            let kv = event.kv().unwrap();
-            let key = kv.key_str().map_err(econv)?;
-            let val = kv.value_str().map_err(econv)?;
-
-            let caps = TYPE_MATCH
-                .captures(key)
-                .ok_or_else(|| String::from("Could not determine type/name from key"))?;
-
-            let obj_type = caps
-                .get(1)
-                .ok_or_else(|| format!("Did not get object type from key: {key}"))?;
-            let obj_name = caps
-                .get(2)
-                .ok_or_else(|| format!("Could not get name from key {key}"))?;
-
-            let update = build_update(
-                &event.event_type(),
-                obj_type.as_str(),
-                obj_name.as_str(),
-                val,
-            )?;
+            let update = kv_update(basepath, &event.event_type(), event.kv().unwrap())?;
 
             req_tx
                 .send_async(DsRequest::Update(update))
@@ -292,6 +594,14 @@ impl EtcdStorage {
                     }
 
                     if let Ok(Some(mut msg)) = response {
+                        if msg.compact_revision() > 0 {
+                            // the watch is cancelled and can't pick up where we left off
+                            return Ok(WatchEnd::Compacted(msg.compact_revision()));
+                        }
+                        if msg.canceled() {
+                            return Err(format!("Watch was cancelled: {}", msg.cancel_reason()));
+                        }
+
                         if let Some(headers) = msg.take_header() {
                             if *last_rev.lock().await == headers.revision() {
                                 // we've already processed this revision so break
@@ -305,7 +615,7 @@ impl EtcdStorage {
                         }
 
                         for event in msg.events() {
-                            if let Err(err) = handle_event(event, &req_tx).await {
+                            if let Err(err) = handle_event(event, basepath, &req_tx).await {
                                 eprintln!("Error handling event {:?}: {}", event, err);
                             }
                         }
@@ -313,7 +623,83 @@ impl EtcdStorage {
                 },
             }
         }
-        Err("Watch stream was closed".to_string())
+        Ok(WatchEnd::Killed)
+    }
+
+    /// The operation of a transaction that makes a change, at the key the change's entity is
+    /// kept under
+    fn txn_op(&self, update: &BackendUpdate) -> Result<TxnOp, String> {
+        let (kind, key, json) = match update {
+            BackendUpdate::PutActor(actor) => (
+                "actors",
+                format!("{}/{}", actor.typestr, actor.name),
+                Some(migrate::encode(actor)?),
+            ),
+            BackendUpdate::PutDelegation(delegation) => (
+                "delegations",
+                delegation.name.clone(),
+                Some(migrate::encode(delegation)?),
+            ),
+            BackendUpdate::PutGroup(group) => {
+                ("groups", group.name.clone(), Some(migrate::encode(group)?))
+            }
+            BackendUpdate::PutPolicyRule(policy) => (
+                "policies",
+                policy.name.clone(),
+                Some(migrate::encode(policy.as_ref())?),
+            ),
+            BackendUpdate::PutPolicySet(set) => {
+                ("policysets", set.name.clone(), Some(migrate::encode(set)?))
+            }
+            BackendUpdate::PutRole(role) => {
+                ("roles", role.name.clone(), Some(migrate::encode(role)?))
+            }
+            BackendUpdate::PutTarget(tgt) => (
+                "targets",
+                format!("{}/{}", tgt.typestr, tgt.name),
+                Some(migrate::encode(tgt)?),
+            ),
+            BackendUpdate::PutWebhook(hook) => {
+                ("webhooks", hook.name.clone(), Some(migrate::encode(hook)?))
+            }
+            BackendUpdate::PutApiKey(key) => {
+                ("apikeys", key.id.clone(), Some(migrate::encode(key)?))
+            }
+            BackendUpdate::PutProposal(proposal) => (
+                "proposals",
+                proposal.id.to_string(),
+                Some(migrate::encode(proposal)?),
+            ),
+            BackendUpdate::PutBreakGlass(break_glass) => (
+                "breakglasses",
+                break_glass.id.to_string(),
+                Some(migrate::encode(break_glass)?),
+            ),
+            BackendUpdate::PutBundle(bundle) => {
+                ("bundles", bundle.key(), Some(migrate::encode(bundle)?))
+            }
+            BackendUpdate::DeleteActor(typestr, name) => {
+                ("actors", format!("{typestr}/{name}"), None)
+            }
+            BackendUpdate::DeleteDelegation(name) => ("delegations", name.clone(), None),
+            BackendUpdate::DeleteGroup(name) => ("groups", name.clone(), None),
+            BackendUpdate::DeletePolicyRule(name) => ("policies", name.clone(), None),
+            BackendUpdate::DeletePolicySet(name) => ("policysets", name.clone(), None),
+            BackendUpdate::DeleteRole(name) => ("roles", name.clone(), None),
+            BackendUpdate::DeleteTarget(typestr, name) => {
+                ("targets", format!("{typestr}/{name}"), None)
+            }
+            BackendUpdate::DeleteWebhook(name) => ("webhooks", name.clone(), None),
+            BackendUpdate::DeleteApiKey(id) => ("apikeys", id.clone(), None),
+            BackendUpdate::DeleteProposal(id) => ("proposals", id.clone(), None),
+            BackendUpdate::DeleteBreakGlass(id) => ("breakglasses", id.clone(), None),
+        };
+
+        let path = format!("{}/{kind}/{key}", self.basepath);
+        Ok(match json {
+            Some(json) => TxnOp::put(path, json, None),
+            None => TxnOp::delete(path, None),
+        })
     }
 }
 
@@ -322,7 +708,7 @@ impl Storage for EtcdStorage {
     async fn save_target(&self, tgt: &RegisteredTarget) -> Result<(), String> {
         let target_path = format!("{}/targets/{}/{}", self.basepath, tgt.typestr, tgt.name);
 
-        let json = serde_json::to_string(&tgt).map_err(|err| err.to_string())?;
+        let json = migrate::encode(tgt)?;
 
         self.client
             .kv_client()
@@ -344,9 +730,7 @@ impl Storage for EtcdStorage {
         Ok(())
     }
 
-    async fn load_targets(
-        &self,
-    ) -> Result<HashMap<String, HashMap<String, RegisteredTarget>>, String> {
+    async fn load_targets(&self) -> Result<Typed<RegisteredTarget>, String> {
         let targets_path = format!("{}/targets", self.basepath);
 
         let results = self
@@ -359,7 +743,7 @@ impl Storage for EtcdStorage {
         let mut map = HashMap::new();
         for kv in results.kvs() {
             let val = std::str::from_utf8(kv.value()).map_err(econv)?;
-            let target: RegisteredTarget = serde_json::from_str(val).map_err(econv)?;
+            let target: RegisteredTarget = self.upgrades.decode("targets", val)?;
             let typed_targets = map
                 .entry(target.typestr.clone())
                 .or_insert_with(HashMap::new);
@@ -371,7 +755,7 @@ impl Storage for EtcdStorage {
     async fn save_actor(&self, tgt: &RegisteredActor) -> Result<(), String> {
         let actor_path = format!("{}/actors/{}/{}", self.basepath, tgt.typestr, tgt.name);
 
-        let json = serde_json::to_string(&tgt).map_err(|err| err.to_string())?;
+        let json = migrate::encode(tgt)?;
 
         self.client
             .kv_client()
@@ -391,9 +775,7 @@ impl Storage for EtcdStorage {
 
         Ok(())
     }
-    async fn load_actors(
-        &self,
-    ) -> Result<HashMap<String, HashMap<String, RegisteredActor>>, String> {
+    async fn load_actors(&self) -> Result<Typed<RegisteredActor>, String> {
         let actors_path = format!("{}/actors", self.basepath);
 
         let results = self
@@ -406,7 +788,7 @@ impl Storage for EtcdStorage {
         let mut map = HashMap::new();
         for kv in results.kvs() {
             let val = std::str::from_utf8(kv.value()).map_err(econv)?;
-            let actor: RegisteredActor = serde_json::from_str(val).map_err(econv)?;
+            let actor: RegisteredActor = self.upgrades.decode("actors", val)?;
             let typed_actors = map
                 .entry(actor.typestr.clone())
                 .or_insert_with(HashMap::new);
@@ -418,7 +800,7 @@ impl Storage for EtcdStorage {
     async fn save_role(&self, role: &RegisteredRole) -> Result<(), String> {
         let role_path = format!("{}/roles/{}", self.basepath, role.name);
 
-        let json = serde_json::to_string(&role).map_err(|err| err.to_string())?;
+        let json = migrate::encode(role)?;
 
         self.client
             .kv_client()
@@ -452,7 +834,7 @@ impl Storage for EtcdStorage {
         let mut map = HashMap::new();
         for kv in results.kvs() {
             let val = std::str::from_utf8(kv.value()).map_err(econv)?;
-            let role: RegisteredRole = serde_json::from_str(val).map_err(econv)?;
+            let role: RegisteredRole = self.upgrades.decode("roles", val)?;
             map.insert(role.name.clone(), role);
         }
 
@@ -461,7 +843,7 @@ impl Storage for EtcdStorage {
     async fn save_group(&self, group: &RegisteredGroup) -> Result<(), String> {
         let group_path = format!("{}/groups/{}", self.basepath, group.name);
 
-        let json = serde_json::to_string(&group).map_err(|err| err.to_string())?;
+        let json = migrate::encode(group)?;
 
         self.client
             .kv_client()
@@ -495,7 +877,7 @@ impl Storage for EtcdStorage {
         let mut map = HashMap::new();
         for kv in response.kvs() {
             let val = std::str::from_utf8(kv.value()).map_err(econv)?;
-            let group: RegisteredGroup = serde_json::from_str(val).map_err(econv)?;
+            let group: RegisteredGroup = self.upgrades.decode("groups", val)?;
             map.insert(group.name.clone(), group);
         }
 
@@ -504,7 +886,7 @@ impl Storage for EtcdStorage {
     async fn save_policy(&self, policy: &RegisteredPolicyRule) -> Result<(), String> {
         let policy_path = format!("{}/policies/{}", self.basepath, policy.name);
 
-        let json = serde_json::to_string(&policy).map_err(|err| err.to_string())?;
+        let json = migrate::encode(policy)?;
 
         self.client
             .kv_client()
@@ -538,33 +920,373 @@ impl Storage for EtcdStorage {
         let mut map = HashMap::new();
         for kv in response.kvs() {
             let val = std::str::from_utf8(kv.value()).map_err(econv)?;
-            let policy: RegisteredPolicyRule = serde_json::from_str(val).map_err(econv)?;
+            let policy: RegisteredPolicyRule = self.upgrades.decode("policies", val)?;
             map.insert(policy.name.clone(), policy);
         }
 
         Ok(map)
     }
 
-    async fn persist_changes(&self, updates: &[BackendUpdate]) -> Result<(), String> {
-        for update in updates {
-            match update {
-                BackendUpdate::PutActor(actor) => self.save_actor(actor).await?,
-                BackendUpdate::PutGroup(group) => self.save_group(group).await?,
-                BackendUpdate::PutPolicyRule(policy) => self.save_policy(policy).await?,
-                BackendUpdate::PutRole(role) => self.save_role(role).await?,
-                BackendUpdate::PutTarget(tgt) => self.save_target(tgt).await?,
-                BackendUpdate::DeleteActor(typestr, name) => {
-                    self.remove_actor(typestr, name).await?
-                }
-                BackendUpdate::DeleteGroup(name) => self.remove_group(name).await?,
-                BackendUpdate::DeletePolicyRule(name) => self.remove_policy(name).await?,
-                BackendUpdate::DeleteRole(name) => self.remove_role(name).await?,
-                BackendUpdate::DeleteTarget(typestr, name) => {
-                    self.remove_target(typestr, name).await?
-                }
+    async fn save_policy_set(&self, set: &RegisteredPolicySet) -> Result<(), String> {
+        let set_path = format!("{}/policysets/{}", self.basepath, set.name);
+
+        let json = migrate::encode(set)?;
+
+        self.client
+            .kv_client()
+            .put(set_path, json, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn remove_policy_set(&self, name: &str) -> Result<(), String> {
+        let set_path = format!("{}/policysets/{}", self.basepath, name);
+
+        self.client
+            .kv_client()
+            .delete(set_path, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn load_policy_sets(&self) -> Result<HashMap<String, RegisteredPolicySet>, String> {
+        let sets_path = format!("{}/policysets", self.basepath);
+
+        let response = self
+            .client
+            .kv_client()
+            .get(sets_path, Some(GetOptions::new().with_prefix()))
+            .await
+            .map_err(econv)?;
+
+        let mut map = HashMap::new();
+        for kv in response.kvs() {
+            let val = std::str::from_utf8(kv.value()).map_err(econv)?;
+            let set: RegisteredPolicySet = self.upgrades.decode("policysets", val)?;
+            map.insert(set.name.clone(), set);
+        }
+
+        Ok(map)
+    }
+
+    async fn save_webhook(&self, hook: &RegisteredWebhook) -> Result<(), String> {
+        let webhook_path = format!("{}/webhooks/{}", self.basepath, hook.name);
+
+        let json = migrate::encode(hook)?;
+
+        self.client
+            .kv_client()
+            .put(webhook_path, json, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn remove_webhook(&self, name: &str) -> Result<(), String> {
+        let webhook_path = format!("{}/webhooks/{}", self.basepath, name);
+
+        self.client
+            .kv_client()
+            .delete(webhook_path, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn load_webhooks(&self) -> Result<HashMap<String, RegisteredWebhook>, String> {
+        let webhooks_path = format!("{}/webhooks", self.basepath);
+
+        let response = self
+            .client
+            .kv_client()
+            .get(webhooks_path, Some(GetOptions::new().with_prefix()))
+            .await
+            .map_err(econv)?;
+
+        let mut map = HashMap::new();
+        for kv in response.kvs() {
+            let val = std::str::from_utf8(kv.value()).map_err(econv)?;
+            let hook: RegisteredWebhook = self.upgrades.decode("webhooks", val)?;
+            map.insert(hook.name.clone(), hook);
+        }
+
+        Ok(map)
+    }
+
+    async fn save_api_key(&self, key: &RegisteredApiKey) -> Result<(), String> {
+        let key_path = format!("{}/apikeys/{}", self.basepath, key.id);
+
+        let json = migrate::encode(key)?;
+
+        self.client
+            .kv_client()
+            .put(key_path, json, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn remove_api_key(&self, id: &str) -> Result<(), String> {
+        let key_path = format!("{}/apikeys/{}", self.basepath, id);
+
+        self.client
+            .kv_client()
+            .delete(key_path, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn load_api_keys(&self) -> Result<HashMap<String, RegisteredApiKey>, String> {
+        let keys_path = format!("{}/apikeys", self.basepath);
+
+        let response = self
+            .client
+            .kv_client()
+            .get(keys_path, Some(GetOptions::new().with_prefix()))
+            .await
+            .map_err(econv)?;
+
+        let mut map = HashMap::new();
+        for kv in response.kvs() {
+            let val = std::str::from_utf8(kv.value()).map_err(econv)?;
+            let key: RegisteredApiKey = self.upgrades.decode("apikeys", val)?;
+            map.insert(key.id.clone(), key);
+        }
+
+        Ok(map)
+    }
+
+    async fn save_delegation(&self, delegation: &RegisteredDelegation) -> Result<(), String> {
+        let delegation_path = format!("{}/delegations/{}", self.basepath, delegation.name);
+
+        let json = migrate::encode(delegation)?;
+
+        self.client
+            .kv_client()
+            .put(delegation_path, json, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn remove_delegation(&self, name: &str) -> Result<(), String> {
+        let delegation_path = format!("{}/delegations/{}", self.basepath, name);
+
+        self.client
+            .kv_client()
+            .delete(delegation_path, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn load_delegations(&self) -> Result<HashMap<String, RegisteredDelegation>, String> {
+        let delegations_path = format!("{}/delegations", self.basepath);
+
+        let response = self
+            .client
+            .kv_client()
+            .get(delegations_path, Some(GetOptions::new().with_prefix()))
+            .await
+            .map_err(econv)?;
+
+        let mut map = HashMap::new();
+        for kv in response.kvs() {
+            let val = std::str::from_utf8(kv.value()).map_err(econv)?;
+            let delegation: RegisteredDelegation = self.upgrades.decode("delegations", val)?;
+            map.insert(delegation.name.clone(), delegation);
+        }
+
+        Ok(map)
+    }
+
+    async fn save_proposal(&self, proposal: &RegisteredProposal) -> Result<(), String> {
+        let proposal_path = format!("{}/proposals/{}", self.basepath, proposal.id);
+
+        let json = migrate::encode(proposal)?;
+
+        self.client
+            .kv_client()
+            .put(proposal_path, json, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn remove_proposal(&self, id: &str) -> Result<(), String> {
+        let proposal_path = format!("{}/proposals/{}", self.basepath, id);
+
+        self.client
+            .kv_client()
+            .delete(proposal_path, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn load_proposals(&self) -> Result<HashMap<String, RegisteredProposal>, String> {
+        let proposals_path = format!("{}/proposals", self.basepath);
+
+        let response = self
+            .client
+            .kv_client()
+            .get(proposals_path, Some(GetOptions::new().with_prefix()))
+            .await
+            .map_err(econv)?;
+
+        let mut map = HashMap::new();
+        for kv in response.kvs() {
+            let val = std::str::from_utf8(kv.value()).map_err(econv)?;
+            let proposal: RegisteredProposal = self.upgrades.decode("proposals", val)?;
+            map.insert(proposal.key(), proposal);
+        }
+
+        Ok(map)
+    }
+
+    async fn save_break_glass(&self, break_glass: &RegisteredBreakGlass) -> Result<(), String> {
+        let break_glass_path = format!("{}/breakglasses/{}", self.basepath, break_glass.id);
+
+        let json = migrate::encode(break_glass)?;
+
+        self.client
+            .kv_client()
+            .put(break_glass_path, json, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn remove_break_glass(&self, id: &str) -> Result<(), String> {
+        let break_glass_path = format!("{}/breakglasses/{}", self.basepath, id);
+
+        self.client
+            .kv_client()
+            .delete(break_glass_path, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn load_break_glasses(&self) -> Result<HashMap<String, RegisteredBreakGlass>, String> {
+        let break_glasses_path = format!("{}/breakglasses", self.basepath);
+
+        let response = self
+            .client
+            .kv_client()
+            .get(break_glasses_path, Some(GetOptions::new().with_prefix()))
+            .await
+            .map_err(econv)?;
+
+        let mut map = HashMap::new();
+        for kv in response.kvs() {
+            let val = std::str::from_utf8(kv.value()).map_err(econv)?;
+            let break_glass: RegisteredBreakGlass = self.upgrades.decode("breakglasses", val)?;
+            map.insert(break_glass.key(), break_glass);
+        }
+
+        Ok(map)
+    }
+
+    async fn save_bundle(&self, bundle: &AppliedBundle) -> Result<(), String> {
+        let bundle_path = format!("{}/bundles/{}", self.basepath, bundle.key());
+
+        let json = migrate::encode(bundle)?;
+
+        self.client
+            .kv_client()
+            .put(bundle_path, json, None)
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+    async fn load_bundle(&self) -> Result<Option<AppliedBundle>, String> {
+        let bundle_path = format!("{}/bundles/applied", self.basepath);
+
+        let response = self
+            .client
+            .kv_client()
+            .get(bundle_path, None)
+            .await
+            .map_err(econv)?;
+
+        match response.kvs().first() {
+            Some(kv) => {
+                let val = std::str::from_utf8(kv.value()).map_err(econv)?;
+                self.upgrades.decode("bundles", val).map(Some)
             }
+            None => Ok(None),
         }
+    }
+
+    /// Persist changes in one etcd transaction, so either all of them are persisted or none are
+    ///
+    /// etcd limits how many operations a transaction can have (`--max-txn-ops`, 128 by
+    /// default), and a change with more than that fails as a whole.
+    async fn persist_changes(&self, updates: &[BackendUpdate]) -> Result<(), PersistError> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let ops = updates
+            .iter()
+            .map(|update| self.txn_op(update))
+            .collect::<Result<Vec<TxnOp>, String>>()?;
+        self.client
+            .kv_client()
+            .txn(Txn::new().and_then(ops))
+            .await
+            .map_err(econv)?;
 
         Ok(())
     }
+
+    async fn ping(&self) -> Result<(), String> {
+        self.client
+            .kv_client()
+            .get(
+                self.basepath.as_bytes(),
+                Some(GetOptions::new().with_count_only()),
+            )
+            .await
+            .map_err(econv)?;
+
+        Ok(())
+    }
+
+    fn upgraded(&self) -> Vec<Upgraded> {
+        self.upgrades.take()
+    }
+    fn leadership(&self) -> watch::Receiver<Leadership> {
+        self.leadership.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basepath() {
+        assert_eq!(basepath(None, None).unwrap(), "/gatehouse");
+        assert_eq!(basepath(Some("/authz/"), None).unwrap(), "/authz");
+        assert_eq!(
+            basepath(None, Some("staging")).unwrap(),
+            "/gatehouse-staging"
+        );
+        assert!(basepath(Some("authz"), None).is_err());
+        assert!(basepath(Some("/"), None).is_err());
+        assert!(basepath(None, Some("a/b")).is_err());
+
+        // keys are split relative to the base path
+        assert_eq!(
+            split_key("/gatehouse-staging", "/gatehouse-staging/targets/db/main").unwrap(),
+            ("targets", "db/main")
+        );
+        assert!(split_key("/gatehouse", "/gatehouse-staging/targets/db/main").is_err());
+    }
 }
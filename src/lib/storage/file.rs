@@ -1,21 +1,132 @@
 use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
+use serde::de::DeserializeOwned;
 use tonic::async_trait;
 
 use crate::actor::RegisteredActor;
+use crate::apikey::RegisteredApiKey;
+use crate::breakglass::RegisteredBreakGlass;
+use crate::bundle::AppliedBundle;
+use crate::delegation::RegisteredDelegation;
 use crate::group::RegisteredGroup;
+use crate::migrate::{self, Upgraded, Upgrades};
 use crate::policy::RegisteredPolicyRule;
+use crate::policyset::RegisteredPolicySet;
+use crate::proposal::RegisteredProposal;
 use crate::role::RegisteredRole;
+use crate::shard::Typed;
 use crate::target::RegisteredTarget;
+use crate::webhook::RegisteredWebhook;
+
+use super::{BackendUpdate, PersistError, Storage};
+
+/// Write a file through a uniquely named temporary file next to it, renamed over it once written
+fn replace_file(path: &Path, contents: &[u8], fsync: bool) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut tmp = tempfile::Builder::new()
+        .prefix(".")
+        .suffix(".tmp")
+        .tempfile_in(dir)?;
+    tmp.write_all(contents)?;
+    if fsync {
+        tmp.as_file().sync_all()?;
+    }
+    tmp.persist(path).map_err(|err| err.error)?;
+
+    if fsync {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// A change of a file, made ready before any change is made
+enum Staged {
+    /// a temporary file with the new contents, to be renamed over the file
+    Put(PathBuf, tempfile::NamedTempFile),
+    /// a file to be removed, which exists
+    Delete(PathBuf),
+}
+
+/// Persist changes to files together
+///
+/// Every new file is written, and every file to be removed is found, before any is renamed into
+/// place or removed. A change that fails before then leaves no file changed, and the temporary
+/// files are removed. If a rename or removal fails after that, the changes before it stay made.
+fn persist_files(changes: Vec<(PathBuf, Option<String>)>, fsync: bool) -> Result<(), PersistError> {
+    let mut staged = Vec::new();
+    for (path, contents) in changes {
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        match contents {
+            Some(contents) => {
+                let write = || -> std::io::Result<tempfile::NamedTempFile> {
+                    let mut tmp = tempfile::Builder::new()
+                        .prefix(".")
+                        .suffix(".tmp")
+                        .tempfile_in(dir)?;
+                    tmp.write_all(contents.as_bytes())?;
+                    if fsync {
+                        tmp.as_file().sync_all()?;
+                    }
+                    Ok(tmp)
+                };
+                let tmp = write().map_err(|err| format!("{}: {err}", path.display()))?;
+                staged.push(Staged::Put(path, tmp));
+            }
+            None => {
+                std::fs::metadata(&path).map_err(|err| format!("{}: {err}", path.display()))?;
+                staged.push(Staged::Delete(path));
+            }
+        }
+    }
+
+    let mut dirs = Vec::new();
+    for (saved, change) in staged.into_iter().enumerate() {
+        let (path, done) = match change {
+            Staged::Put(path, tmp) => {
+                let done = tmp.persist(&path).map(|_| ()).map_err(|err| err.error);
+                (path, done)
+            }
+            Staged::Delete(path) => {
+                let done = std::fs::remove_file(&path);
+                (path, done)
+            }
+        };
+        done.map_err(|err| PersistError {
+            saved,
+            err: format!("{}: {err}", path.display()),
+        })?;
+        if let Some(dir) = path.parent() {
+            if !dirs.iter().any(|seen: &PathBuf| seen == dir) {
+                dirs.push(dir.to_path_buf());
+            }
+        }
+    }
 
-use super::{BackendUpdate, Storage};
+    if fsync {
+        for dir in dirs {
+            std::fs::File::open(&dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(|err| err.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// where files that can't be loaded are moved to, under the base path
+const QUARANTINE: &str = "quarantine";
 
 pub(crate) struct FileStorage {
     basepath: String,
+    /// whether writes are synced to disk before they are reported done
+    fsync: bool,
+    /// what was upgraded from an older schema as it was loaded
+    upgrades: Upgrades,
 }
 
 impl FileStorage {
-    pub async fn new(basepath: &str) -> Self {
+    pub async fn new(basepath: &str, fsync: bool) -> Self {
         tokio::fs::create_dir_all(format!("{}/targets/", basepath))
             .await
             .expect("Could not create file backend storage");
@@ -36,10 +147,182 @@ impl FileStorage {
             .await
             .expect("Could not create file backend storage");
 
+        tokio::fs::create_dir_all(format!("{}/policysets/", basepath))
+            .await
+            .expect("Could not create file backend storage");
+
+        tokio::fs::create_dir_all(format!("{}/webhooks/", basepath))
+            .await
+            .expect("Could not create file backend storage");
+
+        tokio::fs::create_dir_all(format!("{}/apikeys/", basepath))
+            .await
+            .expect("Could not create file backend storage");
+
+        tokio::fs::create_dir_all(format!("{}/delegations/", basepath))
+            .await
+            .expect("Could not create file backend storage");
+
+        tokio::fs::create_dir_all(format!("{}/proposals/", basepath))
+            .await
+            .expect("Could not create file backend storage");
+
+        tokio::fs::create_dir_all(format!("{}/breakglasses/", basepath))
+            .await
+            .expect("Could not create file backend storage");
+
+        tokio::fs::create_dir_all(format!("{}/bundles/", basepath))
+            .await
+            .expect("Could not create file backend storage");
+
+        tokio::fs::create_dir_all(format!("{}/{}/", basepath, QUARANTINE))
+            .await
+            .expect("Could not create file backend storage");
+
         Self {
             basepath: basepath.to_string(),
+            fsync,
+            upgrades: Upgrades::default(),
         }
     }
+
+    /// Replace a file without ever leaving it half written
+    ///
+    /// The contents go to a temporary file of its own, so concurrent writes of the same object
+    /// can't clobber each other's, which is then renamed over the old one. With fsync on, the
+    /// file and then its directory are synced so the change survives a crash.
+    async fn write(&self, path: &str, json: String) -> Result<(), String> {
+        let path = PathBuf::from(path);
+        let fsync = self.fsync;
+        tokio::task::spawn_blocking(move || replace_file(&path, json.as_bytes(), fsync))
+            .await
+            .map_err(|err| err.to_string())?
+            .map_err(|err| err.to_string())
+    }
+
+    /// The file a change is made to, and its new contents unless it is removed
+    fn change(&self, update: &BackendUpdate) -> Result<(PathBuf, Option<String>), String> {
+        let (kind, key, json) = match update {
+            BackendUpdate::PutActor(actor) => (
+                "actors",
+                format!("{}-{}", actor.typestr, actor.name),
+                Some(migrate::encode(actor)?),
+            ),
+            BackendUpdate::PutDelegation(delegation) => (
+                "delegations",
+                delegation.name.clone(),
+                Some(migrate::encode(delegation)?),
+            ),
+            BackendUpdate::PutGroup(group) => {
+                ("groups", group.name.clone(), Some(migrate::encode(group)?))
+            }
+            BackendUpdate::PutPolicyRule(policy) => (
+                "policies",
+                policy.name.clone(),
+                Some(migrate::encode(policy.as_ref())?),
+            ),
+            BackendUpdate::PutPolicySet(set) => {
+                ("policysets", set.name.clone(), Some(migrate::encode(set)?))
+            }
+            BackendUpdate::PutRole(role) => {
+                ("roles", role.name.clone(), Some(migrate::encode(role)?))
+            }
+            BackendUpdate::PutTarget(tgt) => (
+                "targets",
+                format!("{}-{}", tgt.typestr, tgt.name),
+                Some(migrate::encode(tgt)?),
+            ),
+            BackendUpdate::PutWebhook(hook) => {
+                ("webhooks", hook.name.clone(), Some(migrate::encode(hook)?))
+            }
+            BackendUpdate::PutApiKey(key) => {
+                ("apikeys", key.id.clone(), Some(migrate::encode(key)?))
+            }
+            BackendUpdate::PutProposal(proposal) => (
+                "proposals",
+                proposal.id.to_string(),
+                Some(migrate::encode(proposal)?),
+            ),
+            BackendUpdate::PutBreakGlass(break_glass) => (
+                "breakglasses",
+                break_glass.id.to_string(),
+                Some(migrate::encode(break_glass)?),
+            ),
+            BackendUpdate::PutBundle(bundle) => {
+                ("bundles", bundle.key(), Some(migrate::encode(bundle)?))
+            }
+            BackendUpdate::DeleteActor(typestr, name) => {
+                ("actors", format!("{typestr}-{name}"), None)
+            }
+            BackendUpdate::DeleteDelegation(name) => ("delegations", name.clone(), None),
+            BackendUpdate::DeleteGroup(name) => ("groups", name.clone(), None),
+            BackendUpdate::DeletePolicyRule(name) => ("policies", name.clone(), None),
+            BackendUpdate::DeletePolicySet(name) => ("policysets", name.clone(), None),
+            BackendUpdate::DeleteRole(name) => ("roles", name.clone(), None),
+            BackendUpdate::DeleteTarget(typestr, name) => {
+                ("targets", format!("{typestr}-{name}"), None)
+            }
+            BackendUpdate::DeleteWebhook(name) => ("webhooks", name.clone(), None),
+            BackendUpdate::DeleteApiKey(id) => ("apikeys", id.clone(), None),
+            BackendUpdate::DeleteProposal(id) => ("proposals", id.clone(), None),
+            BackendUpdate::DeleteBreakGlass(id) => ("breakglasses", id.clone(), None),
+        };
+
+        let path = PathBuf::from(format!("{}/{kind}/{key}.json", self.basepath));
+        Ok((path, json))
+    }
+
+    /// Load every object of a kind
+    ///
+    /// Temporary files left by an interrupted write are removed, and files that can't be read
+    /// are moved to the quarantine directory so one bad file doesn't stop the server starting.
+    async fn load<T: DeserializeOwned>(&self, kind: &str) -> Result<Vec<T>, String> {
+        let mut loaded = Vec::new();
+
+        let mut dir = tokio::fs::read_dir(format!("{}/{}", self.basepath, kind))
+            .await
+            .map_err(|err| format!("Could not read {kind} from filesystem: {err}"))?;
+
+        while let Some(entry) = dir.next_entry().await.map_err(|err| err.to_string())? {
+            let path = entry.path();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("json") => {}
+                Some("tmp") => {
+                    println!("Removing unfinished write {}", path.display());
+                    let _ = tokio::fs::remove_file(&path).await;
+                    continue;
+                }
+                _ => continue,
+            }
+
+            let parsed = match tokio::fs::read_to_string(&path).await {
+                Ok(json) => self.upgrades.decode(kind, &json),
+                Err(err) => Err(err.to_string()),
+            };
+
+            match parsed {
+                Ok(obj) => loaded.push(obj),
+                Err(err) => {
+                    let quarantined = format!(
+                        "{}/{}/{}-{}",
+                        self.basepath,
+                        QUARANTINE,
+                        kind,
+                        entry.file_name().to_string_lossy()
+                    );
+                    eprintln!(
+                        "Could not load {}: {err}; moving it to {quarantined}",
+                        path.display()
+                    );
+                    tokio::fs::rename(&path, &quarantined)
+                        .await
+                        .map_err(|err| err.to_string())?;
+                }
+            }
+        }
+
+        Ok(loaded)
+    }
 }
 
 #[async_trait]
@@ -50,13 +333,9 @@ impl Storage for FileStorage {
             self.basepath, tgt.typestr, tgt.name
         );
 
-        let json = serde_json::to_string(&tgt).map_err(|err| err.to_string())?;
+        let json = migrate::encode(tgt)?;
 
-        tokio::fs::write(target_path, json)
-            .await
-            .map_err(|err| err.to_string())?;
-
-        Ok(())
+        self.write(&target_path, json).await
     }
 
     async fn remove_target(&self, typestr: &str, name: &str) -> Result<(), String> {
@@ -69,23 +348,10 @@ impl Storage for FileStorage {
         Ok(())
     }
 
-    async fn load_targets(
-        &self,
-    ) -> Result<HashMap<String, HashMap<String, RegisteredTarget>>, String> {
+    async fn load_targets(&self) -> Result<Typed<RegisteredTarget>, String> {
         let mut targets = HashMap::new();
 
-        let mut dir = tokio::fs::read_dir(format!("{}/targets", self.basepath))
-            .await
-            .expect("Could not read targets from filesystem");
-
-        while let Some(entry) = dir.next_entry().await.map_err(|err| err.to_string())? {
-            let json = tokio::fs::read_to_string(entry.path())
-                .await
-                .map_err(|err| err.to_string())?;
-
-            let target: RegisteredTarget =
-                serde_json::from_str(&json).map_err(|err| err.to_string())?;
-
+        for target in self.load::<RegisteredTarget>("targets").await? {
             // get or create the hashmap for this "type" of target
             let typed_targets = targets
                 .entry(target.typestr.clone())
@@ -105,13 +371,9 @@ impl Storage for FileStorage {
             self.basepath, actor.typestr, actor.name
         );
 
-        let json = serde_json::to_string(&actor).map_err(|err| err.to_string())?;
-
-        tokio::fs::write(actors_path, json)
-            .await
-            .map_err(|err| err.to_string())?;
+        let json = migrate::encode(actor)?;
 
-        Ok(())
+        self.write(&actors_path, json).await
     }
 
     async fn remove_actor(&self, typestr: &str, name: &str) -> Result<(), String> {
@@ -124,23 +386,10 @@ impl Storage for FileStorage {
         Ok(())
     }
 
-    async fn load_actors(
-        &self,
-    ) -> Result<HashMap<String, HashMap<String, RegisteredActor>>, String> {
+    async fn load_actors(&self) -> Result<Typed<RegisteredActor>, String> {
         let mut targets = HashMap::new();
 
-        let mut dir = tokio::fs::read_dir(format!("{}/actors", self.basepath))
-            .await
-            .expect("Could not read actors from filesystem");
-
-        while let Some(entry) = dir.next_entry().await.map_err(|err| err.to_string())? {
-            let json = tokio::fs::read_to_string(entry.path())
-                .await
-                .map_err(|err| err.to_string())?;
-
-            let target: RegisteredActor =
-                serde_json::from_str(&json).map_err(|err| err.to_string())?;
-
+        for target in self.load::<RegisteredActor>("actors").await? {
             // get or create the hashmap for this "type" of target
             let typed_targets = targets
                 .entry(target.typestr.clone())
@@ -157,13 +406,9 @@ impl Storage for FileStorage {
     async fn save_role(&self, role: &RegisteredRole) -> Result<(), String> {
         let target_path = format!("{}/roles/{}.json", self.basepath, role.name);
 
-        let json = serde_json::to_string(&role).map_err(|err| err.to_string())?;
+        let json = migrate::encode(role)?;
 
-        tokio::fs::write(target_path, json)
-            .await
-            .map_err(|err| err.to_string())?;
-
-        Ok(())
+        self.write(&target_path, json).await
     }
 
     async fn remove_role(&self, name: &str) -> Result<(), String> {
@@ -179,17 +424,7 @@ impl Storage for FileStorage {
     async fn load_roles(&self) -> Result<HashMap<String, RegisteredRole>, String> {
         let mut roles = HashMap::new();
 
-        let mut dir = tokio::fs::read_dir(format!("{}/roles", self.basepath))
-            .await
-            .expect("Could not read actors from filesystem");
-
-        while let Some(entry) = dir.next_entry().await.map_err(|err| err.to_string())? {
-            let json = tokio::fs::read_to_string(entry.path())
-                .await
-                .map_err(|err| err.to_string())?;
-
-            let role: RegisteredRole =
-                serde_json::from_str(&json).map_err(|err| err.to_string())?;
+        for role in self.load::<RegisteredRole>("roles").await? {
             roles.insert(role.name.clone(), role.clone());
 
             println!(
@@ -205,13 +440,9 @@ impl Storage for FileStorage {
     async fn save_group(&self, group: &RegisteredGroup) -> Result<(), String> {
         let target_path = format!("{}/groups/{}.json", self.basepath, group.name);
 
-        let json = serde_json::to_string(&group).map_err(|err| err.to_string())?;
+        let json = migrate::encode(group)?;
 
-        tokio::fs::write(target_path, json)
-            .await
-            .map_err(|err| err.to_string())?;
-
-        Ok(())
+        self.write(&target_path, json).await
     }
 
     async fn remove_group(&self, name: &str) -> Result<(), String> {
@@ -227,17 +458,7 @@ impl Storage for FileStorage {
     async fn load_groups(&self) -> Result<HashMap<String, RegisteredGroup>, String> {
         let mut groups = HashMap::new();
 
-        let mut dir = tokio::fs::read_dir(format!("{}/groups", self.basepath))
-            .await
-            .expect("Could not read actors from filesystem");
-
-        while let Some(entry) = dir.next_entry().await.map_err(|err| err.to_string())? {
-            let json = tokio::fs::read_to_string(entry.path())
-                .await
-                .map_err(|err| err.to_string())?;
-
-            let group: RegisteredGroup =
-                serde_json::from_str(&json).map_err(|err| err.to_string())?;
+        for group in self.load::<RegisteredGroup>("groups").await? {
             groups.insert(group.name.clone(), group.clone());
 
             println!(
@@ -254,13 +475,9 @@ impl Storage for FileStorage {
     async fn save_policy(&self, policy: &RegisteredPolicyRule) -> Result<(), String> {
         let target_path = format!("{}/policies/{}.json", self.basepath, policy.name);
 
-        let json = serde_json::to_string(&policy).map_err(|err| err.to_string())?;
+        let json = migrate::encode(policy)?;
 
-        tokio::fs::write(target_path, json)
-            .await
-            .map_err(|err| err.to_string())?;
-
-        Ok(())
+        self.write(&target_path, json).await
     }
 
     async fn remove_policy(&self, name: &str) -> Result<(), String> {
@@ -276,17 +493,7 @@ impl Storage for FileStorage {
     async fn load_policies(&self) -> Result<HashMap<String, RegisteredPolicyRule>, String> {
         let mut groups = HashMap::new();
 
-        let mut dir = tokio::fs::read_dir(format!("{}/policies", self.basepath))
-            .await
-            .expect("Could not read policies from filesystem");
-
-        while let Some(entry) = dir.next_entry().await.map_err(|err| err.to_string())? {
-            let json = tokio::fs::read_to_string(entry.path())
-                .await
-                .map_err(|err| err.to_string())?;
-
-            let policy: RegisteredPolicyRule =
-                serde_json::from_str(&json).map_err(|err| err.to_string())?;
+        for policy in self.load::<RegisteredPolicyRule>("policies").await? {
             groups.insert(policy.name.clone(), policy.clone());
 
             println!("Loaded policy {}", policy.name,);
@@ -295,26 +502,329 @@ impl Storage for FileStorage {
         Ok(groups)
     }
 
-    async fn persist_changes(&self, updates: &[BackendUpdate]) -> Result<(), String> {
-        for update in updates {
-            match update {
-                BackendUpdate::PutActor(actor) => self.save_actor(actor).await?,
-                BackendUpdate::PutGroup(group) => self.save_group(group).await?,
-                BackendUpdate::PutPolicyRule(policy) => self.save_policy(policy).await?,
-                BackendUpdate::PutRole(role) => self.save_role(role).await?,
-                BackendUpdate::PutTarget(tgt) => self.save_target(tgt).await?,
-                BackendUpdate::DeleteActor(typestr, name) => {
-                    self.remove_actor(typestr, name).await?
-                }
-                BackendUpdate::DeleteGroup(name) => self.remove_group(name).await?,
-                BackendUpdate::DeletePolicyRule(name) => self.remove_policy(name).await?,
-                BackendUpdate::DeleteRole(name) => self.remove_role(name).await?,
-                BackendUpdate::DeleteTarget(typestr, name) => {
-                    self.remove_target(typestr, name).await?
-                }
-            }
+    async fn save_policy_set(&self, set: &RegisteredPolicySet) -> Result<(), String> {
+        let target_path = format!("{}/policysets/{}.json", self.basepath, set.name);
+
+        let json = migrate::encode(set)?;
+
+        self.write(&target_path, json).await
+    }
+
+    async fn remove_policy_set(&self, name: &str) -> Result<(), String> {
+        let target_path = format!("{}/policysets/{}.json", self.basepath, name);
+
+        tokio::fs::remove_file(target_path)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    async fn load_policy_sets(&self) -> Result<HashMap<String, RegisteredPolicySet>, String> {
+        let mut sets = HashMap::new();
+
+        for set in self.load::<RegisteredPolicySet>("policysets").await? {
+            sets.insert(set.name.clone(), set.clone());
+
+            println!("Loaded policy set {}", set.name);
+        }
+
+        Ok(sets)
+    }
+
+    async fn save_webhook(&self, hook: &RegisteredWebhook) -> Result<(), String> {
+        let target_path = format!("{}/webhooks/{}.json", self.basepath, hook.name);
+
+        let json = migrate::encode(hook)?;
+
+        self.write(&target_path, json).await
+    }
+
+    async fn remove_webhook(&self, name: &str) -> Result<(), String> {
+        let target_path = format!("{}/webhooks/{}.json", self.basepath, name);
+
+        tokio::fs::remove_file(target_path)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    async fn load_webhooks(&self) -> Result<HashMap<String, RegisteredWebhook>, String> {
+        let mut hooks = HashMap::new();
+
+        for hook in self.load::<RegisteredWebhook>("webhooks").await? {
+            hooks.insert(hook.name.clone(), hook.clone());
+
+            println!("Loaded webhook {}", hook.name);
+        }
+
+        Ok(hooks)
+    }
+
+    async fn save_api_key(&self, key: &RegisteredApiKey) -> Result<(), String> {
+        let target_path = format!("{}/apikeys/{}.json", self.basepath, key.id);
+
+        let json = migrate::encode(key)?;
+
+        self.write(&target_path, json).await
+    }
+
+    async fn remove_api_key(&self, id: &str) -> Result<(), String> {
+        let target_path = format!("{}/apikeys/{}.json", self.basepath, id);
+
+        tokio::fs::remove_file(target_path)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    async fn load_api_keys(&self) -> Result<HashMap<String, RegisteredApiKey>, String> {
+        let mut keys = HashMap::new();
+
+        for key in self.load::<RegisteredApiKey>("apikeys").await? {
+            println!("Loaded API key {}", key.id);
+
+            keys.insert(key.id.clone(), key);
+        }
+
+        Ok(keys)
+    }
+
+    async fn save_delegation(&self, delegation: &RegisteredDelegation) -> Result<(), String> {
+        let target_path = format!("{}/delegations/{}.json", self.basepath, delegation.name);
+
+        let json = migrate::encode(delegation)?;
+
+        self.write(&target_path, json).await
+    }
+
+    async fn remove_delegation(&self, name: &str) -> Result<(), String> {
+        let target_path = format!("{}/delegations/{}.json", self.basepath, name);
+
+        tokio::fs::remove_file(target_path)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    async fn load_delegations(&self) -> Result<HashMap<String, RegisteredDelegation>, String> {
+        let mut delegations = HashMap::new();
+
+        for delegation in self.load::<RegisteredDelegation>("delegations").await? {
+            delegations.insert(delegation.name.clone(), delegation.clone());
+
+            println!("Loaded delegation {}", delegation.name);
         }
 
+        Ok(delegations)
+    }
+
+    async fn save_proposal(&self, proposal: &RegisteredProposal) -> Result<(), String> {
+        let target_path = format!("{}/proposals/{}.json", self.basepath, proposal.id);
+
+        let json = migrate::encode(proposal)?;
+
+        self.write(&target_path, json).await
+    }
+
+    async fn remove_proposal(&self, id: &str) -> Result<(), String> {
+        let target_path = format!("{}/proposals/{}.json", self.basepath, id);
+
+        tokio::fs::remove_file(target_path)
+            .await
+            .map_err(|err| err.to_string())?;
+
         Ok(())
     }
+
+    async fn load_proposals(&self) -> Result<HashMap<String, RegisteredProposal>, String> {
+        let mut proposals = HashMap::new();
+
+        for proposal in self.load::<RegisteredProposal>("proposals").await? {
+            println!("Loaded proposal {}", proposal.id);
+
+            proposals.insert(proposal.key(), proposal);
+        }
+
+        Ok(proposals)
+    }
+
+    async fn save_break_glass(&self, break_glass: &RegisteredBreakGlass) -> Result<(), String> {
+        let target_path = format!("{}/breakglasses/{}.json", self.basepath, break_glass.id);
+
+        let json = migrate::encode(break_glass)?;
+
+        self.write(&target_path, json).await
+    }
+
+    async fn remove_break_glass(&self, id: &str) -> Result<(), String> {
+        let target_path = format!("{}/breakglasses/{}.json", self.basepath, id);
+
+        tokio::fs::remove_file(target_path)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    async fn load_break_glasses(&self) -> Result<HashMap<String, RegisteredBreakGlass>, String> {
+        let mut break_glasses = HashMap::new();
+
+        for break_glass in self.load::<RegisteredBreakGlass>("breakglasses").await? {
+            println!("Loaded break glass {}", break_glass.id);
+
+            break_glasses.insert(break_glass.key(), break_glass);
+        }
+
+        Ok(break_glasses)
+    }
+
+    async fn save_bundle(&self, bundle: &AppliedBundle) -> Result<(), String> {
+        let target_path = format!("{}/bundles/{}.json", self.basepath, bundle.key());
+
+        let json = migrate::encode(bundle)?;
+
+        self.write(&target_path, json).await
+    }
+
+    async fn load_bundle(&self) -> Result<Option<AppliedBundle>, String> {
+        let bundles = self.load::<AppliedBundle>("bundles").await?;
+
+        Ok(bundles.into_iter().next())
+    }
+
+    /// Persist changes together; see [`persist_files`] for how far that goes
+    async fn persist_changes(&self, updates: &[BackendUpdate]) -> Result<(), PersistError> {
+        let changes = updates
+            .iter()
+            .map(|update| self.change(update))
+            .collect::<Result<Vec<_>, String>>()?;
+        let fsync = self.fsync;
+        tokio::task::spawn_blocking(move || persist_files(changes, fsync))
+            .await
+            .map_err(|err| err.to_string())?
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        tokio::fs::metadata(&self.basepath)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    fn upgraded(&self) -> Vec<Upgraded> {
+        self.upgrades.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::test;
+
+    use super::*;
+
+    #[test]
+    async fn test_quarantine() {
+        let basepath = std::env::temp_dir().join(format!("gatehouse-test-{}", std::process::id()));
+        let basepath = basepath.to_str().unwrap();
+        let storage = FileStorage::new(basepath, true).await;
+
+        let role = RegisteredRole::new("admin", None);
+        storage.save_role(&role).await.unwrap();
+        tokio::fs::write(format!("{basepath}/roles/broken.json"), "{\"name\":")
+            .await
+            .unwrap();
+        tokio::fs::write(format!("{basepath}/roles/viewer.json.tmp"), "{}")
+            .await
+            .unwrap();
+
+        // the bad file doesn't stop the good one from loading
+        let roles = storage.load_roles().await.unwrap();
+        assert_eq!(roles.len(), 1);
+        assert!(roles.contains_key("admin"));
+
+        assert!(Path::new(&format!("{basepath}/quarantine/roles-broken.json")).exists());
+        assert!(!Path::new(&format!("{basepath}/roles/broken.json")).exists());
+        assert!(!Path::new(&format!("{basepath}/roles/viewer.json.tmp")).exists());
+
+        tokio::fs::remove_dir_all(basepath).await.unwrap();
+    }
+
+    #[test]
+    async fn test_concurrent_writes() {
+        let basepath =
+            std::env::temp_dir().join(format!("gatehouse-writes-{}", std::process::id()));
+        let basepath = basepath.to_str().unwrap();
+        let storage = std::sync::Arc::new(FileStorage::new(basepath, false).await);
+
+        // writes of the same object at once each have their own temporary file
+        let writes: Vec<_> = (0..16)
+            .map(|_| {
+                let storage = storage.clone();
+                tokio::spawn(
+                    async move { storage.save_role(&RegisteredRole::new("admin", None)).await },
+                )
+            })
+            .collect();
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+
+        let mut dir = tokio::fs::read_dir(format!("{basepath}/roles"))
+            .await
+            .unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = dir.next_entry().await.unwrap() {
+            names.push(entry.file_name().into_string().unwrap());
+        }
+        assert_eq!(names, vec![String::from("admin.json")]);
+
+        tokio::fs::remove_dir_all(basepath).await.unwrap();
+    }
+
+    #[test]
+    async fn test_persist_changes() {
+        let basepath = std::env::temp_dir().join(format!("gatehouse-txn-{}", std::process::id()));
+        let basepath = basepath.to_str().unwrap();
+        let storage = FileStorage::new(basepath, true).await;
+        storage
+            .save_role(&RegisteredRole::new("viewer", None))
+            .await
+            .unwrap();
+
+        // removing a role that was never saved fails, and nothing before it is saved either
+        let err = storage
+            .persist_changes(&[
+                BackendUpdate::PutRole(RegisteredRole::new("admin", None)),
+                BackendUpdate::DeleteRole(String::from("viewer")),
+                BackendUpdate::DeleteRole(String::from("editor")),
+            ])
+            .await
+            .unwrap_err();
+        assert_eq!(err.saved, 0);
+        let mut dir = tokio::fs::read_dir(format!("{basepath}/roles"))
+            .await
+            .unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = dir.next_entry().await.unwrap() {
+            names.push(entry.file_name().into_string().unwrap());
+        }
+        assert_eq!(names, vec![String::from("viewer.json")]);
+
+        storage
+            .persist_changes(&[
+                BackendUpdate::PutRole(RegisteredRole::new("admin", None)),
+                BackendUpdate::DeleteRole(String::from("viewer")),
+            ])
+            .await
+            .unwrap();
+        let roles = storage.load_roles().await.unwrap();
+        assert_eq!(roles.keys().collect::<Vec<_>>(), vec!["admin"]);
+
+        tokio::fs::remove_dir_all(basepath).await.unwrap();
+    }
 }
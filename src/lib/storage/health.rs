@@ -0,0 +1,349 @@
+//! Health monitoring of the storage backend
+//!
+//! Every call to the backend is timed and its result recorded. After several failures in a row
+//! the circuit breaker trips and changes fail fast instead of waiting on a backend that is down.
+//! The backend is pinged in the background, and the first successful call closes the breaker.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration};
+use tonic::async_trait;
+
+use crate::actor::RegisteredActor;
+use crate::apikey::RegisteredApiKey;
+use crate::breakglass::RegisteredBreakGlass;
+use crate::bundle::AppliedBundle;
+use crate::delegation::RegisteredDelegation;
+use crate::group::RegisteredGroup;
+use crate::migrate::Upgraded;
+use crate::policy::RegisteredPolicyRule;
+use crate::policyset::RegisteredPolicySet;
+use crate::proposal::RegisteredProposal;
+use crate::proto::base::{ServingRole, StorageStatus};
+use crate::role::RegisteredRole;
+use crate::shard::Typed;
+use crate::target::RegisteredTarget;
+use crate::webhook::RegisteredWebhook;
+
+use super::{BackendUpdate, Leadership, PersistError, Storage};
+
+/// how many failures in a row trip the breaker
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// how often the backend is pinged
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// how much each call moves the average latency
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// What we know about how the backend has been doing
+#[derive(Debug, Default)]
+struct Stats {
+    operations: u64,
+    errors: u64,
+    consecutive_failures: u32,
+    /// moving average of the latency of calls, in milliseconds
+    latency_ms: f64,
+    last_error: Option<String>,
+}
+
+/// The health of the storage backend and the state of its circuit breaker
+#[derive(Debug)]
+pub(crate) struct StorageHealth {
+    stats: Mutex<Stats>,
+    /// false while the breaker is tripped
+    available: watch::Sender<bool>,
+}
+
+impl StorageHealth {
+    fn new() -> Self {
+        Self {
+            stats: Mutex::new(Stats::default()),
+            available: watch::channel(true).0,
+        }
+    }
+
+    /// Whether changes can be made, i.e. the breaker is not tripped
+    pub(crate) fn is_available(&self) -> bool {
+        *self.available.borrow()
+    }
+
+    /// Follow whether the backend is available
+    pub(crate) fn subscribe(&self) -> watch::Receiver<bool> {
+        self.available.subscribe()
+    }
+
+    /// Report on the backend
+    pub(crate) fn status(&self) -> StorageStatus {
+        let stats = self.stats.lock().unwrap();
+
+        StorageStatus {
+            available: self.is_available(),
+            operations: stats.operations,
+            errors: stats.errors,
+            latency_ms: stats.latency_ms,
+            last_error: stats.last_error.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Record the outcome of a call, tripping or closing the breaker as needed
+    fn record<T, E: Display>(&self, elapsed: Duration, result: &Result<T, E>) {
+        let mut stats = self.stats.lock().unwrap();
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+
+        stats.latency_ms = match stats.operations {
+            0 => elapsed_ms,
+            _ => stats.latency_ms + LATENCY_WEIGHT * (elapsed_ms - stats.latency_ms),
+        };
+        stats.operations += 1;
+
+        match result {
+            Ok(_) => {
+                stats.consecutive_failures = 0;
+                if !self.is_available() {
+                    println!("Storage backend recovered");
+                    self.available.send_replace(true);
+                }
+            }
+            Err(err) => {
+                stats.errors += 1;
+                stats.consecutive_failures += 1;
+                stats.last_error = Some(err.to_string());
+                if stats.consecutive_failures >= FAILURE_THRESHOLD && self.is_available() {
+                    eprintln!("Storage backend is unavailable: {err}");
+                    self.available.send_replace(false);
+                }
+            }
+        }
+    }
+}
+
+/// A storage backend that records the health of every call it makes
+pub(crate) struct MonitoredStorage {
+    inner: Arc<dyn Storage + Send + Sync>,
+    health: Arc<StorageHealth>,
+    leadership: watch::Receiver<Leadership>,
+}
+
+impl MonitoredStorage {
+    /// Monitor a backend, pinging it in the background for as long as it is in use
+    pub(crate) fn new(inner: Box<dyn Storage + Send + Sync>) -> Self {
+        let inner: Arc<dyn Storage + Send + Sync> = Arc::from(inner);
+        let health = Arc::new(StorageHealth::new());
+
+        let leadership = inner.leadership();
+
+        let backend = Arc::downgrade(&inner);
+        let pinged = health.clone();
+        tokio::spawn(async move { Self::ping(backend, pinged).await });
+
+        Self {
+            inner,
+            health,
+            leadership,
+        }
+    }
+
+    pub(crate) fn health(&self) -> Arc<StorageHealth> {
+        self.health.clone()
+    }
+
+    /// Ping the backend so outages are noticed, and recoveries too, when nothing is changing
+    async fn ping(backend: Weak<dyn Storage + Send + Sync>, health: Arc<StorageHealth>) {
+        loop {
+            sleep(PING_INTERVAL).await;
+
+            let backend = match backend.upgrade() {
+                Some(backend) => backend,
+                None => return,
+            };
+            let start = Instant::now();
+            let result = backend.ping().await;
+            health.record(start.elapsed(), &result);
+        }
+    }
+
+    /// Time a call and record how it went
+    async fn track<T, E: Display>(&self, call: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+        let start = Instant::now();
+        let result = call.await;
+        self.health.record(start.elapsed(), &result);
+        result
+    }
+
+    /// Time a change and record how it went
+    ///
+    /// Changes fail fast if we are a standby, so background syncs don't write over the leader,
+    /// or if the breaker is tripped.
+    async fn change<E: Display + From<String>>(
+        &self,
+        call: impl Future<Output = Result<(), E>>,
+    ) -> Result<(), E> {
+        if self.leadership.borrow().role == ServingRole::Standby {
+            return Err(E::from(String::from("Only the leader can make changes")));
+        }
+        if !self.health.is_available() {
+            return Err(E::from(String::from("Storage backend is unavailable")));
+        }
+        self.track(call).await
+    }
+}
+
+#[async_trait]
+impl Storage for MonitoredStorage {
+    async fn save_target(&self, tgt: &RegisteredTarget) -> Result<(), String> {
+        self.change(self.inner.save_target(tgt)).await
+    }
+    async fn remove_target(&self, typestr: &str, name: &str) -> Result<(), String> {
+        self.change(self.inner.remove_target(typestr, name)).await
+    }
+    async fn load_targets(&self) -> Result<Typed<RegisteredTarget>, String> {
+        self.track(self.inner.load_targets()).await
+    }
+    async fn save_actor(&self, tgt: &RegisteredActor) -> Result<(), String> {
+        self.change(self.inner.save_actor(tgt)).await
+    }
+    async fn remove_actor(&self, typestr: &str, name: &str) -> Result<(), String> {
+        self.change(self.inner.remove_actor(typestr, name)).await
+    }
+    async fn load_actors(&self) -> Result<Typed<RegisteredActor>, String> {
+        self.track(self.inner.load_actors()).await
+    }
+    async fn save_role(&self, role: &RegisteredRole) -> Result<(), String> {
+        self.change(self.inner.save_role(role)).await
+    }
+    async fn remove_role(&self, name: &str) -> Result<(), String> {
+        self.change(self.inner.remove_role(name)).await
+    }
+    async fn load_roles(&self) -> Result<HashMap<String, RegisteredRole>, String> {
+        self.track(self.inner.load_roles()).await
+    }
+    async fn save_group(&self, group: &RegisteredGroup) -> Result<(), String> {
+        self.change(self.inner.save_group(group)).await
+    }
+    async fn remove_group(&self, name: &str) -> Result<(), String> {
+        self.change(self.inner.remove_group(name)).await
+    }
+    async fn load_groups(&self) -> Result<HashMap<String, RegisteredGroup>, String> {
+        self.track(self.inner.load_groups()).await
+    }
+    async fn save_policy(&self, policy: &RegisteredPolicyRule) -> Result<(), String> {
+        self.change(self.inner.save_policy(policy)).await
+    }
+    async fn remove_policy(&self, name: &str) -> Result<(), String> {
+        self.change(self.inner.remove_policy(name)).await
+    }
+    async fn load_policies(&self) -> Result<HashMap<String, RegisteredPolicyRule>, String> {
+        self.track(self.inner.load_policies()).await
+    }
+    async fn save_policy_set(&self, set: &RegisteredPolicySet) -> Result<(), String> {
+        self.change(self.inner.save_policy_set(set)).await
+    }
+    async fn remove_policy_set(&self, name: &str) -> Result<(), String> {
+        self.change(self.inner.remove_policy_set(name)).await
+    }
+    async fn load_policy_sets(&self) -> Result<HashMap<String, RegisteredPolicySet>, String> {
+        self.track(self.inner.load_policy_sets()).await
+    }
+    async fn save_webhook(&self, hook: &RegisteredWebhook) -> Result<(), String> {
+        self.change(self.inner.save_webhook(hook)).await
+    }
+    async fn remove_webhook(&self, name: &str) -> Result<(), String> {
+        self.change(self.inner.remove_webhook(name)).await
+    }
+    async fn load_webhooks(&self) -> Result<HashMap<String, RegisteredWebhook>, String> {
+        self.track(self.inner.load_webhooks()).await
+    }
+    async fn save_api_key(&self, key: &RegisteredApiKey) -> Result<(), String> {
+        self.change(self.inner.save_api_key(key)).await
+    }
+    async fn remove_api_key(&self, id: &str) -> Result<(), String> {
+        self.change(self.inner.remove_api_key(id)).await
+    }
+    async fn load_api_keys(&self) -> Result<HashMap<String, RegisteredApiKey>, String> {
+        self.track(self.inner.load_api_keys()).await
+    }
+    async fn save_delegation(&self, delegation: &RegisteredDelegation) -> Result<(), String> {
+        self.change(self.inner.save_delegation(delegation)).await
+    }
+    async fn remove_delegation(&self, name: &str) -> Result<(), String> {
+        self.change(self.inner.remove_delegation(name)).await
+    }
+    async fn load_delegations(&self) -> Result<HashMap<String, RegisteredDelegation>, String> {
+        self.track(self.inner.load_delegations()).await
+    }
+    async fn save_proposal(&self, proposal: &RegisteredProposal) -> Result<(), String> {
+        self.change(self.inner.save_proposal(proposal)).await
+    }
+    async fn remove_proposal(&self, id: &str) -> Result<(), String> {
+        self.change(self.inner.remove_proposal(id)).await
+    }
+    async fn load_proposals(&self) -> Result<HashMap<String, RegisteredProposal>, String> {
+        self.track(self.inner.load_proposals()).await
+    }
+    async fn save_break_glass(&self, break_glass: &RegisteredBreakGlass) -> Result<(), String> {
+        self.change(self.inner.save_break_glass(break_glass)).await
+    }
+    async fn remove_break_glass(&self, id: &str) -> Result<(), String> {
+        self.change(self.inner.remove_break_glass(id)).await
+    }
+    async fn load_break_glasses(&self) -> Result<HashMap<String, RegisteredBreakGlass>, String> {
+        self.track(self.inner.load_break_glasses()).await
+    }
+    async fn save_bundle(&self, bundle: &AppliedBundle) -> Result<(), String> {
+        self.change(self.inner.save_bundle(bundle)).await
+    }
+    async fn load_bundle(&self) -> Result<Option<AppliedBundle>, String> {
+        self.track(self.inner.load_bundle()).await
+    }
+    async fn persist_changes(&self, updates: &[BackendUpdate]) -> Result<(), PersistError> {
+        self.change(self.inner.persist_changes(updates)).await
+    }
+    async fn ping(&self) -> Result<(), String> {
+        self.track(self.inner.ping()).await
+    }
+    fn upgraded(&self) -> Vec<Upgraded> {
+        self.inner.upgraded()
+    }
+    fn leadership(&self) -> watch::Receiver<Leadership> {
+        self.leadership.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker() {
+        let health = StorageHealth::new();
+        let failed: Result<(), String> = Err(String::from("connection refused"));
+
+        // a couple of failures don't trip the breaker
+        for _ in 1..FAILURE_THRESHOLD {
+            health.record(Duration::from_millis(10), &failed);
+        }
+        assert!(health.is_available());
+
+        health.record(Duration::from_millis(10), &failed);
+        assert!(!health.is_available());
+
+        let status = health.status();
+        assert_eq!(status.operations, u64::from(FAILURE_THRESHOLD));
+        assert_eq!(status.errors, u64::from(FAILURE_THRESHOLD));
+        assert_eq!(status.latency_ms, 10.0);
+        assert_eq!(status.last_error, "connection refused");
+
+        // one success closes it again
+        let available = health.subscribe();
+        health.record(Duration::from_millis(20), &Ok::<(), String>(()));
+        assert!(health.is_available());
+        assert!(available.has_changed().unwrap());
+        assert_eq!(health.status().latency_ms, 12.0);
+    }
+}
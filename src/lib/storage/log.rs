@@ -0,0 +1,555 @@
+//! Storage in a single append-only log
+//!
+//! Every change is appended to `log.jsonl` as one JSON line. Once enough changes pile up, the
+//! whole state is written to `snapshot.json` and the log starts over. Loading reads the snapshot
+//! and replays the log on top of it. This is much faster than one file per object for bulk
+//! imports, and the two files are easy to back up.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tonic::async_trait;
+
+use crate::actor::RegisteredActor;
+use crate::apikey::RegisteredApiKey;
+use crate::breakglass::RegisteredBreakGlass;
+use crate::bundle::AppliedBundle;
+use crate::delegation::RegisteredDelegation;
+use crate::group::RegisteredGroup;
+use crate::migrate::{self, Upgraded, Upgrades};
+use crate::policy::RegisteredPolicyRule;
+use crate::policyset::RegisteredPolicySet;
+use crate::proposal::RegisteredProposal;
+use crate::role::RegisteredRole;
+use crate::shard::Typed;
+use crate::target::RegisteredTarget;
+use crate::webhook::RegisteredWebhook;
+
+use super::{BackendUpdate, PersistError, Storage};
+
+/// how many entries the log can grow to before it is compacted into a snapshot
+const COMPACT_AFTER: usize = 10_000;
+
+/// A single change in the log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Entry {
+    Put {
+        kind: String,
+        key: String,
+        value: Value,
+    },
+    Delete {
+        kind: String,
+        key: String,
+    },
+}
+
+impl Entry {
+    fn put<T: Serialize>(kind: &str, key: String, obj: &T) -> Result<Self, String> {
+        Ok(Entry::Put {
+            kind: kind.to_string(),
+            key,
+            value: migrate::stamp(obj)?,
+        })
+    }
+
+    fn delete(kind: &str, key: String) -> Self {
+        Entry::Delete {
+            kind: kind.to_string(),
+            key,
+        }
+    }
+}
+
+impl TryFrom<&BackendUpdate> for Entry {
+    type Error = String;
+
+    fn try_from(update: &BackendUpdate) -> Result<Self, Self::Error> {
+        match update {
+            BackendUpdate::PutActor(actor) => {
+                Entry::put("actors", format!("{}/{}", actor.typestr, actor.name), actor)
+            }
+            BackendUpdate::PutGroup(group) => Entry::put("groups", group.name.clone(), group),
+            BackendUpdate::PutPolicyRule(policy) => {
+                Entry::put("policies", policy.name.clone(), policy)
+            }
+            BackendUpdate::PutPolicySet(set) => Entry::put("policysets", set.name.clone(), set),
+            BackendUpdate::PutRole(role) => Entry::put("roles", role.name.clone(), role),
+            BackendUpdate::PutTarget(tgt) => {
+                Entry::put("targets", format!("{}/{}", tgt.typestr, tgt.name), tgt)
+            }
+            BackendUpdate::PutWebhook(hook) => Entry::put("webhooks", hook.name.clone(), hook),
+            BackendUpdate::PutApiKey(key) => Entry::put("apikeys", key.id.clone(), key),
+            BackendUpdate::PutDelegation(delegation) => {
+                Entry::put("delegations", delegation.name.clone(), delegation)
+            }
+            BackendUpdate::PutProposal(proposal) => {
+                Entry::put("proposals", proposal.key(), proposal)
+            }
+            BackendUpdate::PutBreakGlass(break_glass) => {
+                Entry::put("breakglasses", break_glass.key(), break_glass)
+            }
+            BackendUpdate::PutBundle(bundle) => Entry::put("bundles", bundle.key(), bundle),
+            BackendUpdate::DeleteActor(typestr, name) => {
+                Ok(Entry::delete("actors", format!("{typestr}/{name}")))
+            }
+            BackendUpdate::DeleteGroup(name) => Ok(Entry::delete("groups", name.clone())),
+            BackendUpdate::DeletePolicyRule(name) => Ok(Entry::delete("policies", name.clone())),
+            BackendUpdate::DeletePolicySet(name) => Ok(Entry::delete("policysets", name.clone())),
+            BackendUpdate::DeleteRole(name) => Ok(Entry::delete("roles", name.clone())),
+            BackendUpdate::DeleteTarget(typestr, name) => {
+                Ok(Entry::delete("targets", format!("{typestr}/{name}")))
+            }
+            BackendUpdate::DeleteWebhook(name) => Ok(Entry::delete("webhooks", name.clone())),
+            BackendUpdate::DeleteApiKey(id) => Ok(Entry::delete("apikeys", id.clone())),
+            BackendUpdate::DeleteDelegation(name) => Ok(Entry::delete("delegations", name.clone())),
+            BackendUpdate::DeleteProposal(id) => Ok(Entry::delete("proposals", id.clone())),
+            BackendUpdate::DeleteBreakGlass(id) => Ok(Entry::delete("breakglasses", id.clone())),
+        }
+    }
+}
+
+/// Every object by kind and then key, as stored in the snapshot
+type State = HashMap<String, BTreeMap<String, Value>>;
+
+struct Log {
+    state: State,
+    file: File,
+    /// how many entries are in the log since the last snapshot
+    entries: usize,
+}
+
+impl Log {
+    fn apply(&mut self, entry: Entry) {
+        match entry {
+            Entry::Put { kind, key, value } => {
+                self.state.entry(kind).or_default().insert(key, value);
+            }
+            Entry::Delete { kind, key } => {
+                if let Some(objs) = self.state.get_mut(&kind) {
+                    objs.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) struct LogStorage {
+    basepath: String,
+    /// whether writes are synced to disk before they are reported done
+    fsync: bool,
+    log: Mutex<Log>,
+    /// what was upgraded from an older schema as it was loaded
+    upgrades: Upgrades,
+}
+
+impl LogStorage {
+    pub async fn new(basepath: &str, fsync: bool) -> Self {
+        tokio::fs::create_dir_all(basepath)
+            .await
+            .expect("Could not create log backend storage");
+
+        let storage = Self::open(basepath, fsync)
+            .await
+            .expect("Could not open log backend storage");
+
+        if storage.log.lock().await.entries >= COMPACT_AFTER {
+            storage
+                .compact()
+                .await
+                .expect("Could not compact log backend storage");
+        }
+
+        storage
+    }
+
+    /// Read the snapshot and replay the log on top of it
+    async fn open(basepath: &str, fsync: bool) -> Result<Self, String> {
+        let snapshot_path = format!("{basepath}/snapshot.json");
+        let log_path = format!("{basepath}/log.jsonl");
+
+        let state: State = match tokio::fs::read_to_string(&snapshot_path).await {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|err| format!("Could not read {snapshot_path}: {err}"))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => State::new(),
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let lines = match tokio::fs::read_to_string(&log_path).await {
+            Ok(lines) => lines,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        // start new entries on a line of their own if the last write was cut short
+        if !lines.is_empty() && !lines.ends_with('\n') {
+            file.write_all(b"\n").await.map_err(|err| err.to_string())?;
+        }
+
+        let mut log = Log {
+            state,
+            file,
+            entries: 0,
+        };
+        for (num, line) in lines.lines().enumerate() {
+            match serde_json::from_str::<Entry>(line) {
+                Ok(entry) => {
+                    log.apply(entry);
+                    log.entries += 1;
+                }
+                // most likely the last write was cut short by a crash
+                Err(err) => eprintln!("Skipping line {} of {log_path}: {err}", num + 1),
+            }
+        }
+        println!(
+            "Replayed {} log entries on top of the snapshot in {basepath}",
+            log.entries
+        );
+
+        Ok(Self {
+            basepath: basepath.to_string(),
+            fsync,
+            log: Mutex::new(log),
+            upgrades: Upgrades::default(),
+        })
+    }
+
+    /// Append changes to the log in a single write, compacting it if it has grown too long
+    async fn append(&self, entries: Vec<Entry>) -> Result<(), String> {
+        let mut buf = Vec::new();
+        for entry in &entries {
+            serde_json::to_writer(&mut buf, entry).map_err(|err| err.to_string())?;
+            buf.push(b'\n');
+        }
+
+        let compact = {
+            let mut log = self.log.lock().await;
+            log.file
+                .write_all(&buf)
+                .await
+                .map_err(|err| err.to_string())?;
+            if self.fsync {
+                log.file.sync_data().await.map_err(|err| err.to_string())?;
+            }
+
+            log.entries += entries.len();
+            for entry in entries {
+                log.apply(entry);
+            }
+            log.entries >= COMPACT_AFTER
+        };
+
+        if compact {
+            self.compact().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the whole state to the snapshot and start the log over
+    ///
+    /// The snapshot is renamed into place before the log is cleared, so a crash in between only
+    /// means replaying changes the snapshot already has.
+    async fn compact(&self) -> Result<(), String> {
+        let mut log = self.log.lock().await;
+
+        let json = serde_json::to_string(&log.state).map_err(|err| err.to_string())?;
+        let snapshot_path = format!("{}/snapshot.json", self.basepath);
+        let tmp_path = format!("{snapshot_path}.tmp");
+
+        let mut file = File::create(&tmp_path)
+            .await
+            .map_err(|err| err.to_string())?;
+        file.write_all(json.as_bytes())
+            .await
+            .map_err(|err| err.to_string())?;
+        file.sync_all().await.map_err(|err| err.to_string())?;
+        drop(file);
+
+        tokio::fs::rename(&tmp_path, &snapshot_path)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        log.file.set_len(0).await.map_err(|err| err.to_string())?;
+        if self.fsync {
+            log.file.sync_all().await.map_err(|err| err.to_string())?;
+        }
+
+        println!("Compacted {} log entries into {snapshot_path}", log.entries);
+        log.entries = 0;
+
+        Ok(())
+    }
+
+    /// Every object of a kind
+    async fn load<T: DeserializeOwned>(&self, kind: &str) -> Result<Vec<T>, String> {
+        let log = self.log.lock().await;
+
+        match log.state.get(kind) {
+            Some(objs) => objs
+                .values()
+                .map(|value| self.upgrades.decode_value(kind, value.clone()))
+                .collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn save<T: Serialize>(&self, kind: &str, key: String, obj: &T) -> Result<(), String> {
+        self.append(vec![Entry::put(kind, key, obj)?]).await
+    }
+
+    async fn remove(&self, kind: &str, key: String) -> Result<(), String> {
+        self.append(vec![Entry::delete(kind, key)]).await
+    }
+}
+
+#[async_trait]
+impl Storage for LogStorage {
+    async fn save_target(&self, tgt: &RegisteredTarget) -> Result<(), String> {
+        self.save("targets", format!("{}/{}", tgt.typestr, tgt.name), tgt)
+            .await
+    }
+    async fn remove_target(&self, typestr: &str, name: &str) -> Result<(), String> {
+        self.remove("targets", format!("{typestr}/{name}")).await
+    }
+    async fn load_targets(&self) -> Result<Typed<RegisteredTarget>, String> {
+        let mut targets = HashMap::new();
+        for target in self.load::<RegisteredTarget>("targets").await? {
+            let typed_targets = targets
+                .entry(target.typestr.clone())
+                .or_insert_with(HashMap::new);
+            typed_targets.insert(target.name.clone(), target);
+        }
+
+        Ok(targets)
+    }
+    async fn save_actor(&self, actor: &RegisteredActor) -> Result<(), String> {
+        self.save("actors", format!("{}/{}", actor.typestr, actor.name), actor)
+            .await
+    }
+    async fn remove_actor(&self, typestr: &str, name: &str) -> Result<(), String> {
+        self.remove("actors", format!("{typestr}/{name}")).await
+    }
+    async fn load_actors(&self) -> Result<Typed<RegisteredActor>, String> {
+        let mut actors = HashMap::new();
+        for actor in self.load::<RegisteredActor>("actors").await? {
+            let typed_actors = actors
+                .entry(actor.typestr.clone())
+                .or_insert_with(HashMap::new);
+            typed_actors.insert(actor.name.clone(), actor);
+        }
+
+        Ok(actors)
+    }
+    async fn save_role(&self, role: &RegisteredRole) -> Result<(), String> {
+        self.save("roles", role.name.clone(), role).await
+    }
+    async fn remove_role(&self, name: &str) -> Result<(), String> {
+        self.remove("roles", name.to_string()).await
+    }
+    async fn load_roles(&self) -> Result<HashMap<String, RegisteredRole>, String> {
+        let roles = self.load::<RegisteredRole>("roles").await?;
+        Ok(roles
+            .into_iter()
+            .map(|role| (role.name.clone(), role))
+            .collect())
+    }
+    async fn save_group(&self, group: &RegisteredGroup) -> Result<(), String> {
+        self.save("groups", group.name.clone(), group).await
+    }
+    async fn remove_group(&self, name: &str) -> Result<(), String> {
+        self.remove("groups", name.to_string()).await
+    }
+    async fn load_groups(&self) -> Result<HashMap<String, RegisteredGroup>, String> {
+        let groups = self.load::<RegisteredGroup>("groups").await?;
+        Ok(groups
+            .into_iter()
+            .map(|group| (group.name.clone(), group))
+            .collect())
+    }
+    async fn save_policy(&self, policy: &RegisteredPolicyRule) -> Result<(), String> {
+        self.save("policies", policy.name.clone(), policy).await
+    }
+    async fn remove_policy(&self, name: &str) -> Result<(), String> {
+        self.remove("policies", name.to_string()).await
+    }
+    async fn load_policies(&self) -> Result<HashMap<String, RegisteredPolicyRule>, String> {
+        let policies = self.load::<RegisteredPolicyRule>("policies").await?;
+        Ok(policies
+            .into_iter()
+            .map(|policy| (policy.name.clone(), policy))
+            .collect())
+    }
+    async fn save_policy_set(&self, set: &RegisteredPolicySet) -> Result<(), String> {
+        self.save("policysets", set.name.clone(), set).await
+    }
+    async fn remove_policy_set(&self, name: &str) -> Result<(), String> {
+        self.remove("policysets", name.to_string()).await
+    }
+    async fn load_policy_sets(&self) -> Result<HashMap<String, RegisteredPolicySet>, String> {
+        let sets = self.load::<RegisteredPolicySet>("policysets").await?;
+        Ok(sets
+            .into_iter()
+            .map(|set| (set.name.clone(), set))
+            .collect())
+    }
+    async fn save_webhook(&self, hook: &RegisteredWebhook) -> Result<(), String> {
+        self.save("webhooks", hook.name.clone(), hook).await
+    }
+    async fn remove_webhook(&self, name: &str) -> Result<(), String> {
+        self.remove("webhooks", name.to_string()).await
+    }
+    async fn load_webhooks(&self) -> Result<HashMap<String, RegisteredWebhook>, String> {
+        let hooks = self.load::<RegisteredWebhook>("webhooks").await?;
+        Ok(hooks
+            .into_iter()
+            .map(|hook| (hook.name.clone(), hook))
+            .collect())
+    }
+    async fn save_api_key(&self, key: &RegisteredApiKey) -> Result<(), String> {
+        self.save("apikeys", key.id.clone(), key).await
+    }
+    async fn remove_api_key(&self, id: &str) -> Result<(), String> {
+        self.remove("apikeys", id.to_string()).await
+    }
+    async fn load_api_keys(&self) -> Result<HashMap<String, RegisteredApiKey>, String> {
+        let keys = self.load::<RegisteredApiKey>("apikeys").await?;
+        Ok(keys.into_iter().map(|key| (key.id.clone(), key)).collect())
+    }
+    async fn save_delegation(&self, delegation: &RegisteredDelegation) -> Result<(), String> {
+        self.save("delegations", delegation.name.clone(), delegation)
+            .await
+    }
+    async fn remove_delegation(&self, name: &str) -> Result<(), String> {
+        self.remove("delegations", name.to_string()).await
+    }
+    async fn load_delegations(&self) -> Result<HashMap<String, RegisteredDelegation>, String> {
+        let delegations = self.load::<RegisteredDelegation>("delegations").await?;
+        Ok(delegations
+            .into_iter()
+            .map(|delegation| (delegation.name.clone(), delegation))
+            .collect())
+    }
+    async fn save_proposal(&self, proposal: &RegisteredProposal) -> Result<(), String> {
+        self.save("proposals", proposal.key(), proposal).await
+    }
+    async fn remove_proposal(&self, id: &str) -> Result<(), String> {
+        self.remove("proposals", id.to_string()).await
+    }
+    async fn load_proposals(&self) -> Result<HashMap<String, RegisteredProposal>, String> {
+        let proposals = self.load::<RegisteredProposal>("proposals").await?;
+        Ok(proposals
+            .into_iter()
+            .map(|proposal| (proposal.key(), proposal))
+            .collect())
+    }
+    async fn save_break_glass(&self, break_glass: &RegisteredBreakGlass) -> Result<(), String> {
+        self.save("breakglasses", break_glass.key(), break_glass)
+            .await
+    }
+    async fn remove_break_glass(&self, id: &str) -> Result<(), String> {
+        self.remove("breakglasses", id.to_string()).await
+    }
+    async fn load_break_glasses(&self) -> Result<HashMap<String, RegisteredBreakGlass>, String> {
+        let break_glasses = self.load::<RegisteredBreakGlass>("breakglasses").await?;
+        Ok(break_glasses
+            .into_iter()
+            .map(|break_glass| (break_glass.key(), break_glass))
+            .collect())
+    }
+    async fn save_bundle(&self, bundle: &AppliedBundle) -> Result<(), String> {
+        self.save("bundles", bundle.key(), bundle).await
+    }
+    async fn load_bundle(&self) -> Result<Option<AppliedBundle>, String> {
+        let bundles = self.load::<AppliedBundle>("bundles").await?;
+        Ok(bundles.into_iter().next())
+    }
+
+    /// All the changes go to the log in one write
+    async fn persist_changes(&self, updates: &[BackendUpdate]) -> Result<(), PersistError> {
+        let entries = updates
+            .iter()
+            .map(Entry::try_from)
+            .collect::<Result<Vec<Entry>, String>>()?;
+
+        Ok(self.append(entries).await?)
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        tokio::fs::metadata(&self.basepath)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    fn upgraded(&self) -> Vec<Upgraded> {
+        self.upgrades.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tokio::test;
+
+    use super::*;
+
+    #[test]
+    async fn test_log() {
+        let basepath = std::env::temp_dir().join(format!("gatehouse-log-{}", std::process::id()));
+        let basepath = basepath.to_str().unwrap();
+        let storage = LogStorage::new(basepath, false).await;
+
+        storage
+            .persist_changes(&[
+                BackendUpdate::PutRole(RegisteredRole::new("admin", None)),
+                BackendUpdate::PutRole(RegisteredRole::new("viewer", None)),
+            ])
+            .await
+            .unwrap();
+        storage.compact().await.unwrap();
+
+        // changes after the snapshot are replayed on top of it
+        storage.remove_role("viewer").await.unwrap();
+        storage
+            .save_role(&RegisteredRole::new("editor", None))
+            .await
+            .unwrap();
+        let log_path = format!("{basepath}/log.jsonl");
+        let log = tokio::fs::read_to_string(&log_path).await.unwrap();
+        assert_eq!(log.lines().count(), 2);
+
+        // a write cut short by a crash is skipped
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&log_path)
+            .await
+            .unwrap();
+        file.write_all(b"{\"op\":\"put\",\"ki").await.unwrap();
+        drop(file);
+
+        let reopened = LogStorage::new(basepath, false).await;
+        let roles = reopened.load_roles().await.unwrap();
+        let mut names: Vec<&String> = roles.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["admin", "editor"]);
+        assert!(Path::new(&format!("{basepath}/snapshot.json")).exists());
+
+        tokio::fs::remove_dir_all(basepath).await.unwrap();
+    }
+}
@@ -1,43 +1,126 @@
 use std::collections::HashMap;
+use std::fmt::Display;
 
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 use tonic::async_trait;
 
 use crate::actor::RegisteredActor;
+use crate::apikey::RegisteredApiKey;
+use crate::breakglass::RegisteredBreakGlass;
+use crate::bundle::AppliedBundle;
+use crate::delegation::RegisteredDelegation;
 use crate::group::RegisteredGroup;
+use crate::migrate::Upgraded;
 use crate::policy::RegisteredPolicyRule;
+use crate::policyset::RegisteredPolicySet;
+use crate::proposal::RegisteredProposal;
+use crate::proto::base::ServingRole;
 use crate::role::RegisteredRole;
+use crate::shard::Typed;
 use crate::target::RegisteredTarget;
+use crate::webhook::RegisteredWebhook;
 
+pub(crate) mod dedicated;
 pub(crate) mod etcd;
 pub(crate) mod file;
+pub(crate) mod health;
+pub(crate) mod log;
 pub(crate) mod nil;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) enum BackendUpdate {
     PutActor(RegisteredActor),
+    PutDelegation(RegisteredDelegation),
     PutGroup(RegisteredGroup),
-    PutPolicyRule(RegisteredPolicyRule),
+    PutPolicyRule(Box<RegisteredPolicyRule>),
+    PutPolicySet(RegisteredPolicySet),
     PutRole(RegisteredRole),
     PutTarget(RegisteredTarget),
+    PutWebhook(RegisteredWebhook),
+    PutApiKey(RegisteredApiKey),
+    PutProposal(RegisteredProposal),
+    PutBreakGlass(RegisteredBreakGlass),
+    PutBundle(AppliedBundle),
     DeleteActor(String, String),
+    DeleteDelegation(String),
     DeleteGroup(String),
     DeletePolicyRule(String),
+    DeletePolicySet(String),
     DeleteRole(String),
     DeleteTarget(String, String),
+    DeleteWebhook(String),
+    DeleteApiKey(String),
+    DeleteProposal(String),
+    DeleteBreakGlass(String),
+}
+
+impl BackendUpdate {
+    /// Record on the entity this puts, if it keeps timestamps, that it changed at `now`
+    pub(crate) fn touch(&mut self, now: u64) {
+        match self {
+            BackendUpdate::PutActor(actor) => actor.timestamps.touch(now),
+            BackendUpdate::PutGroup(group) => group.timestamps.touch(now),
+            BackendUpdate::PutPolicyRule(rule) => rule.timestamps.touch(now),
+            BackendUpdate::PutPolicySet(set) => set.timestamps.touch(now),
+            BackendUpdate::PutRole(role) => role.timestamps.touch(now),
+            BackendUpdate::PutTarget(target) => target.timestamps.touch(now),
+            _ => {}
+        }
+    }
+}
+
+/// Whether this server may make changes, as decided by leader election
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Leadership {
+    pub role: ServingRole,
+    /// the name of the current leader, if known
+    pub leader: Option<String>,
+}
+
+impl Default for Leadership {
+    fn default() -> Self {
+        Self {
+            role: ServingRole::Standalone,
+            leader: None,
+        }
+    }
+}
+
+/// Why changes couldn't be persisted, and how many of them were before the failure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PersistError {
+    /// how many of the changes, from the first, were persisted
+    pub saved: usize,
+    pub err: String,
+}
+
+impl Display for PersistError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.err)
+    }
+}
+
+impl From<String> for PersistError {
+    fn from(err: String) -> Self {
+        Self { saved: 0, err }
+    }
+}
+
+impl From<PersistError> for String {
+    fn from(err: PersistError) -> Self {
+        err.err
+    }
 }
 
 #[async_trait]
 pub(crate) trait Storage {
     async fn save_target(&self, tgt: &RegisteredTarget) -> Result<(), String>;
     async fn remove_target(&self, typestr: &str, name: &str) -> Result<(), String>;
-    async fn load_targets(
-        &self,
-    ) -> Result<HashMap<String, HashMap<String, RegisteredTarget>>, String>;
+    async fn load_targets(&self) -> Result<Typed<RegisteredTarget>, String>;
     async fn save_actor(&self, tgt: &RegisteredActor) -> Result<(), String>;
     async fn remove_actor(&self, typestr: &str, name: &str) -> Result<(), String>;
-    async fn load_actors(
-        &self,
-    ) -> Result<HashMap<String, HashMap<String, RegisteredActor>>, String>;
+    async fn load_actors(&self) -> Result<Typed<RegisteredActor>, String>;
     async fn save_role(&self, role: &RegisteredRole) -> Result<(), String>;
     async fn remove_role(&self, name: &str) -> Result<(), String>;
     async fn load_roles(&self) -> Result<HashMap<String, RegisteredRole>, String>;
@@ -47,5 +130,79 @@ pub(crate) trait Storage {
     async fn save_policy(&self, policy: &RegisteredPolicyRule) -> Result<(), String>;
     async fn remove_policy(&self, name: &str) -> Result<(), String>;
     async fn load_policies(&self) -> Result<HashMap<String, RegisteredPolicyRule>, String>;
-    async fn persist_changes(&self, updates: &[BackendUpdate]) -> Result<(), String>;
+    async fn save_policy_set(&self, set: &RegisteredPolicySet) -> Result<(), String>;
+    async fn remove_policy_set(&self, name: &str) -> Result<(), String>;
+    async fn load_policy_sets(&self) -> Result<HashMap<String, RegisteredPolicySet>, String>;
+    async fn save_webhook(&self, hook: &RegisteredWebhook) -> Result<(), String>;
+    async fn remove_webhook(&self, name: &str) -> Result<(), String>;
+    async fn load_webhooks(&self) -> Result<HashMap<String, RegisteredWebhook>, String>;
+    async fn save_api_key(&self, key: &RegisteredApiKey) -> Result<(), String>;
+    async fn remove_api_key(&self, id: &str) -> Result<(), String>;
+    async fn load_api_keys(&self) -> Result<HashMap<String, RegisteredApiKey>, String>;
+    async fn save_delegation(&self, delegation: &RegisteredDelegation) -> Result<(), String>;
+    async fn remove_delegation(&self, name: &str) -> Result<(), String>;
+    async fn load_delegations(&self) -> Result<HashMap<String, RegisteredDelegation>, String>;
+    async fn save_proposal(&self, proposal: &RegisteredProposal) -> Result<(), String>;
+    async fn remove_proposal(&self, id: &str) -> Result<(), String>;
+    async fn load_proposals(&self) -> Result<HashMap<String, RegisteredProposal>, String>;
+    async fn save_break_glass(&self, break_glass: &RegisteredBreakGlass) -> Result<(), String>;
+    async fn remove_break_glass(&self, id: &str) -> Result<(), String>;
+    async fn load_break_glasses(&self) -> Result<HashMap<String, RegisteredBreakGlass>, String>;
+    async fn save_bundle(&self, bundle: &AppliedBundle) -> Result<(), String>;
+    async fn load_bundle(&self) -> Result<Option<AppliedBundle>, String>;
+
+    /// Persist changes in order, one at a time; backends that can persist them all at once
+    /// should, so a failure leaves none of them persisted
+    async fn persist_changes(&self, updates: &[BackendUpdate]) -> Result<(), PersistError> {
+        for (saved, update) in updates.iter().enumerate() {
+            let persisted = match update {
+                BackendUpdate::PutActor(actor) => self.save_actor(actor).await,
+                BackendUpdate::PutGroup(group) => self.save_group(group).await,
+                BackendUpdate::PutPolicyRule(policy) => self.save_policy(policy).await,
+                BackendUpdate::PutPolicySet(set) => self.save_policy_set(set).await,
+                BackendUpdate::PutRole(role) => self.save_role(role).await,
+                BackendUpdate::PutTarget(tgt) => self.save_target(tgt).await,
+                BackendUpdate::DeleteActor(typestr, name) => self.remove_actor(typestr, name).await,
+                BackendUpdate::DeleteGroup(name) => self.remove_group(name).await,
+                BackendUpdate::DeletePolicyRule(name) => self.remove_policy(name).await,
+                BackendUpdate::DeletePolicySet(name) => self.remove_policy_set(name).await,
+                BackendUpdate::DeleteRole(name) => self.remove_role(name).await,
+                BackendUpdate::DeleteTarget(typestr, name) => {
+                    self.remove_target(typestr, name).await
+                }
+                BackendUpdate::PutWebhook(hook) => self.save_webhook(hook).await,
+                BackendUpdate::DeleteWebhook(name) => self.remove_webhook(name).await,
+                BackendUpdate::PutApiKey(key) => self.save_api_key(key).await,
+                BackendUpdate::DeleteApiKey(id) => self.remove_api_key(id).await,
+                BackendUpdate::PutDelegation(delegation) => self.save_delegation(delegation).await,
+                BackendUpdate::DeleteDelegation(name) => self.remove_delegation(name).await,
+                BackendUpdate::PutProposal(proposal) => self.save_proposal(proposal).await,
+                BackendUpdate::DeleteProposal(id) => self.remove_proposal(id).await,
+                BackendUpdate::PutBreakGlass(break_glass) => {
+                    self.save_break_glass(break_glass).await
+                }
+                BackendUpdate::DeleteBreakGlass(id) => self.remove_break_glass(id).await,
+                BackendUpdate::PutBundle(bundle) => self.save_bundle(bundle).await,
+            };
+            persisted.map_err(|err| PersistError { saved, err })?;
+        }
+
+        Ok(())
+    }
+
+    /// Make sure the backend can be reached
+    async fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// What was upgraded from an older schema as it was loaded; backends that don't store
+    /// documents never upgrade anything
+    fn upgraded(&self) -> Vec<Upgraded> {
+        Vec::new()
+    }
+
+    /// Follow our role; backends without leader election are always standalone
+    fn leadership(&self) -> watch::Receiver<Leadership> {
+        watch::channel(Leadership::default()).1
+    }
 }
@@ -3,12 +3,20 @@ use std::collections::HashMap;
 use tonic::async_trait;
 
 use crate::actor::RegisteredActor;
+use crate::apikey::RegisteredApiKey;
+use crate::breakglass::RegisteredBreakGlass;
+use crate::bundle::AppliedBundle;
+use crate::delegation::RegisteredDelegation;
 use crate::group::RegisteredGroup;
 use crate::policy::RegisteredPolicyRule;
+use crate::policyset::RegisteredPolicySet;
+use crate::proposal::RegisteredProposal;
 use crate::role::RegisteredRole;
+use crate::shard::Typed;
 use crate::target::RegisteredTarget;
+use crate::webhook::RegisteredWebhook;
 
-use super::{BackendUpdate, Storage};
+use super::{BackendUpdate, PersistError, Storage};
 
 pub(crate) struct NilStorage;
 
@@ -20,9 +28,7 @@ impl Storage for NilStorage {
     async fn remove_target(&self, _typestr: &str, _name: &str) -> Result<(), String> {
         Ok(())
     }
-    async fn load_targets(
-        &self,
-    ) -> Result<HashMap<String, HashMap<String, RegisteredTarget>>, String> {
+    async fn load_targets(&self) -> Result<Typed<RegisteredTarget>, String> {
         Ok(HashMap::new())
     }
     async fn save_actor(&self, _tgt: &RegisteredActor) -> Result<(), String> {
@@ -31,9 +37,7 @@ impl Storage for NilStorage {
     async fn remove_actor(&self, _typestr: &str, _name: &str) -> Result<(), String> {
         Ok(())
     }
-    async fn load_actors(
-        &self,
-    ) -> Result<HashMap<String, HashMap<String, RegisteredActor>>, String> {
+    async fn load_actors(&self) -> Result<Typed<RegisteredActor>, String> {
         Ok(HashMap::new())
     }
     async fn save_role(&self, _role: &RegisteredRole) -> Result<(), String> {
@@ -63,7 +67,67 @@ impl Storage for NilStorage {
     async fn load_policies(&self) -> Result<HashMap<String, RegisteredPolicyRule>, String> {
         Ok(HashMap::new())
     }
-    async fn persist_changes(&self, _updates: &[BackendUpdate]) -> Result<(), String> {
+    async fn save_policy_set(&self, _set: &RegisteredPolicySet) -> Result<(), String> {
+        Ok(())
+    }
+    async fn remove_policy_set(&self, _name: &str) -> Result<(), String> {
+        Ok(())
+    }
+    async fn load_policy_sets(&self) -> Result<HashMap<String, RegisteredPolicySet>, String> {
+        Ok(HashMap::new())
+    }
+    async fn save_webhook(&self, _hook: &RegisteredWebhook) -> Result<(), String> {
+        Ok(())
+    }
+    async fn remove_webhook(&self, _name: &str) -> Result<(), String> {
+        Ok(())
+    }
+    async fn load_webhooks(&self) -> Result<HashMap<String, RegisteredWebhook>, String> {
+        Ok(HashMap::new())
+    }
+    async fn save_api_key(&self, _key: &RegisteredApiKey) -> Result<(), String> {
+        Ok(())
+    }
+    async fn remove_api_key(&self, _id: &str) -> Result<(), String> {
+        Ok(())
+    }
+    async fn load_api_keys(&self) -> Result<HashMap<String, RegisteredApiKey>, String> {
+        Ok(HashMap::new())
+    }
+    async fn save_delegation(&self, _delegation: &RegisteredDelegation) -> Result<(), String> {
+        Ok(())
+    }
+    async fn remove_delegation(&self, _name: &str) -> Result<(), String> {
+        Ok(())
+    }
+    async fn load_delegations(&self) -> Result<HashMap<String, RegisteredDelegation>, String> {
+        Ok(HashMap::new())
+    }
+    async fn save_proposal(&self, _proposal: &RegisteredProposal) -> Result<(), String> {
+        Ok(())
+    }
+    async fn remove_proposal(&self, _id: &str) -> Result<(), String> {
+        Ok(())
+    }
+    async fn load_proposals(&self) -> Result<HashMap<String, RegisteredProposal>, String> {
+        Ok(HashMap::new())
+    }
+    async fn save_break_glass(&self, _break_glass: &RegisteredBreakGlass) -> Result<(), String> {
+        Ok(())
+    }
+    async fn remove_break_glass(&self, _id: &str) -> Result<(), String> {
+        Ok(())
+    }
+    async fn load_break_glasses(&self) -> Result<HashMap<String, RegisteredBreakGlass>, String> {
+        Ok(HashMap::new())
+    }
+    async fn save_bundle(&self, _bundle: &AppliedBundle) -> Result<(), String> {
+        Ok(())
+    }
+    async fn load_bundle(&self) -> Result<Option<AppliedBundle>, String> {
+        Ok(None)
+    }
+    async fn persist_changes(&self, _updates: &[BackendUpdate]) -> Result<(), PersistError> {
         Ok(())
     }
 }
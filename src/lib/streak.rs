@@ -0,0 +1,232 @@
+#![warn(missing_docs)]
+
+//! Detection of actors that keep getting denied
+//!
+//! Each actor's denies are counted until a check of theirs is allowed. An actor denied as many
+//! times as the threshold within the window has a deny streak, which can be a sign of stolen
+//! credentials being used to probe what they can reach. The streak is reported, and the actor
+//! can optionally be locked out, so every check of theirs is denied for a while.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::actor::RegisteredActor;
+use crate::config::number_from_env;
+use crate::ds::now;
+
+/// the window denies are counted in when none is set, in seconds
+pub const DEFAULT_WINDOW: u64 = 300;
+
+/// the most actors tracked, so a flood of actors can't take all the memory
+const MAX_TRACKED: usize = 100_000;
+
+/// When a run of denies counts as a streak, and what happens to the actor then
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DenyStreakConfig {
+    /// how many denies in a row make a streak; `None` disables detection
+    pub threshold: Option<usize>,
+    /// how many seconds the denies must fall within; 0 uses five minutes
+    pub window: u64,
+    /// how many seconds an actor with a streak is locked out for; 0 doesn't lock them out
+    pub lockout: u64,
+}
+
+impl DenyStreakConfig {
+    /// Build the config from `GATEDENYSTREAK` (the threshold), `GATEDENYSTREAKWINDOW`, and
+    /// `GATEDENYLOCKOUT`. Without a threshold, deny streaks aren't tracked.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            threshold: number_from_env("GATEDENYSTREAK")?.filter(|threshold| *threshold > 0),
+            window: number_from_env("GATEDENYSTREAKWINDOW")?.unwrap_or(0) as u64,
+            lockout: number_from_env("GATEDENYLOCKOUT")?.unwrap_or(0) as u64,
+        })
+    }
+}
+
+/// A streak an actor was found to have
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Streak {
+    /// how many denies in a row the actor had
+    pub denies: usize,
+    /// when the actor's lockout ends, if they were locked out
+    pub locked_until: Option<u64>,
+}
+
+/// An actor's recent denies and lockout
+#[derive(Debug, Default)]
+struct Tracked {
+    /// when each deny in the current run happened, oldest first
+    denies: VecDeque<u64>,
+    /// when the actor's lockout ends, if they are locked out
+    locked_until: Option<u64>,
+}
+
+/// Tracks the denies of every actor
+#[derive(Debug, Default)]
+pub(crate) struct DenyStreaks {
+    config: DenyStreakConfig,
+    actors: Mutex<HashMap<String, Tracked>>,
+    /// how many streaks were found since the server started
+    found: AtomicU64,
+}
+
+impl DenyStreaks {
+    /// Track denies as the config says
+    pub fn new(config: DenyStreakConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// The key an actor is tracked under
+    fn key(actor: &RegisteredActor) -> String {
+        format!(
+            "{}/{}",
+            actor.typestr.to_ascii_lowercase(),
+            actor.name.to_ascii_lowercase()
+        )
+    }
+
+    /// How many seconds denies are counted in
+    fn window(&self) -> u64 {
+        match self.config.window {
+            0 => DEFAULT_WINDOW,
+            window => window,
+        }
+    }
+
+    /// How many more seconds an actor is locked out for, if they are
+    pub fn locked_out(&self, actor: &RegisteredActor) -> Option<u64> {
+        self.config.threshold?;
+        let now = now();
+        let actors = self.actors.lock().unwrap();
+        actors
+            .get(&Self::key(actor))
+            .and_then(|tracked| tracked.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Record the decision on a check of an actor, and return the streak if it made one
+    pub fn record(&self, actor: &RegisteredActor, denied: bool) -> Option<Streak> {
+        self.config.threshold?;
+        self.record_at(Self::key(actor), denied, now())
+    }
+
+    /// Record a decision made at a given second
+    fn record_at(&self, key: String, denied: bool, at: u64) -> Option<Streak> {
+        let threshold = self.config.threshold?;
+        let mut actors = self.actors.lock().unwrap();
+
+        if !denied {
+            // an allow ends the run, but not a lockout
+            if let Some(tracked) = actors.get_mut(&key) {
+                tracked.denies.clear();
+                if tracked.locked_until.is_none() {
+                    actors.remove(&key);
+                }
+            }
+            return None;
+        }
+        if actors.len() >= MAX_TRACKED && !actors.contains_key(&key) {
+            return None;
+        }
+
+        let window = self.window();
+        let tracked = actors.entry(key).or_default();
+        tracked.denies.push_back(at);
+        while tracked
+            .denies
+            .front()
+            .is_some_and(|second| *second + window <= at)
+        {
+            tracked.denies.pop_front();
+        }
+        if tracked.denies.len() < threshold {
+            return None;
+        }
+
+        // the next streak needs as many denies again
+        let denies = tracked.denies.len();
+        tracked.denies.clear();
+        if self.config.lockout > 0 {
+            tracked.locked_until = Some(at + self.config.lockout);
+        }
+        self.found.fetch_add(1, Ordering::Relaxed);
+        Some(Streak {
+            denies,
+            locked_until: tracked.locked_until,
+        })
+    }
+
+    /// Forget runs of denies too old to make a streak, and lockouts that are over
+    pub fn prune(&self) {
+        let now = now();
+        let window = self.window();
+        let mut actors = self.actors.lock().unwrap();
+        actors.retain(|_, tracked| {
+            tracked.denies.retain(|second| *second + window > now);
+            if tracked.locked_until.is_some_and(|until| until <= now) {
+                tracked.locked_until = None;
+            }
+            !tracked.denies.is_empty() || tracked.locked_until.is_some()
+        });
+    }
+
+    /// How many streaks were found since the server started, and how many actors are locked out
+    pub fn stats(&self) -> (u64, u64) {
+        let now = now();
+        let actors = self.actors.lock().unwrap();
+        let locked = actors
+            .values()
+            .filter(|tracked| tracked.locked_until.is_some_and(|until| until > now))
+            .count();
+        (self.found.load(Ordering::Relaxed), locked as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deny_streaks() {
+        let streaks = DenyStreaks::new(DenyStreakConfig {
+            threshold: Some(3),
+            window: 60,
+            lockout: 600,
+        });
+        let actor = RegisteredActor::new("Kaitlyn", "user", Default::default());
+        let key = DenyStreaks::key(&actor);
+        let now = now();
+
+        // a deny from before the window doesn't count
+        assert_eq!(streaks.record_at(key.clone(), true, now - 120), None);
+        assert_eq!(streaks.record(&actor, true), None);
+        assert_eq!(streaks.record(&actor, true), None);
+
+        // an allow ends the run
+        assert_eq!(streaks.record(&actor, false), None);
+        assert_eq!(streaks.record(&actor, true), None);
+        assert_eq!(streaks.record(&actor, true), None);
+        assert_eq!(streaks.locked_out(&actor), None);
+
+        let streak = streaks.record(&actor, true).unwrap();
+        assert_eq!(streak.denies, 3);
+        assert!(streak.locked_until.unwrap() >= now + 600);
+        assert!(streaks.locked_out(&actor).is_some());
+        assert_eq!(streaks.stats(), (1, 1));
+
+        // an allow doesn't end a lockout
+        streaks.record(&actor, false);
+        streaks.prune();
+        assert!(streaks.locked_out(&actor).is_some());
+
+        // nothing is tracked without a threshold
+        let disabled = DenyStreaks::default();
+        assert_eq!(disabled.record(&actor, true), None);
+        assert_eq!(disabled.stats(), (0, 0));
+    }
+}
@@ -1,51 +1,461 @@
 //! The main Gatehouse server binary
 
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
 use flume::Sender;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::oneshot::{channel, Receiver};
+use tokio::sync::{broadcast, mpsc, watch};
 use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
 use tonic::{Request, Response, Status};
 
-use crate::ds::Datastore;
+use crate::apikey::{self, RegisteredApiKey, API_KEY_METADATA_KEY};
+use crate::bundle;
+use crate::canary;
+use crate::config::Config;
+use crate::drift;
+use crate::ds::{now, resume_token, Datastore};
+use crate::dsl;
+use crate::fallback::{self, Fallback};
+use crate::grant;
+use crate::hooks::Hooks;
 use crate::msgs::{DsRequest, DsResponse};
+use crate::oidc::Introspector;
 use crate::proto::actors::{
-    ActorResponse, AddActorRequest, GetActorsRequest, ModifyActorRequest, MultiActorResponse,
-    RemoveActorRequest,
+    ActorMembershipsResponse, ActorResponse, AddActorRequest, AddActorsRequest, AddActorsResponse,
+    AddDelegationRequest, Delegation, DelegationResponse, GetActorMembershipsRequest,
+    GetActorsRequest, GetDelegationsRequest, ModifyActorRequest, MultiActorResponse,
+    MultiDelegationResponse, RemoveActorRequest, RemoveDelegationRequest,
 };
 use crate::proto::base::gatehouse_server::Gatehouse;
-use crate::proto::base::{CheckRequest, CheckResponse};
+use crate::proto::base::mutation::Op;
+use crate::proto::base::{
+    ApiKeyResponse, ApplyBundleRequest, ApplyTransactionRequest, ApplyTransactionResponse,
+    ApprovalResponse, ApproveRequest, BreakGlassRequest, BreakGlassResponse, CanaryStatus,
+    ChangeEvent, CheckRequest, CheckResponse, CoverageReportRequest, CoverageReportResponse,
+    CreateApiKeyRequest, CreateApiKeyResponse, EntitySchema, FindUnusedRequest, FindUnusedResponse,
+    GetApiKeysRequest, GetApprovalsRequest, GetBreakGlassRequest, GetPolicyStatsRequest,
+    GetPolicyStatsResponse, GetReferencesRequest, GetReferencesResponse, GetSchemasRequest,
+    GetSchemasResponse, GetServerInfoRequest, GetServerInfoResponse, GetServerStatsRequest,
+    GetServerStatsResponse, Grant, GrantRequest, GrantResponse, HealthRequest, HealthResponse,
+    MultiApiKeyResponse, MultiApprovalResponse, MultiBreakGlassResponse, ReloadStateRequest,
+    ReloadStateResponse, ReplicateRequest, ReplicateResponse, RevokeApiKeyRequest, ServingRole,
+    SshCertRequest, SshCertResponse, StreamChangesRequest, SyncRequest, SyncResponse,
+    TestPoliciesRequest, TestPoliciesResponse, TraceCheckResponse, VerifyGrantRequest,
+    VerifyGrantResponse, WatchEvent, WatchRequest, WhatIfRequest, WhatIfResponse,
+};
 use crate::proto::groups::{
-    AddGroupRequest, GetGroupsRequest, GroupResponse, ModifyGroupRequest, MultiGroupResponse,
-    RemoveGroupRequest,
+    AddGroupRequest, BulkModifyMembershipsRequest, CloneGroupRequest, GetGroupMembersRequest,
+    GetGroupsRequest, GroupMembersResponse, GroupResponse, ModifyGroupRequest, MultiGroupResponse,
+    RemoveGroupRequest, RenameGroupRequest,
 };
+use crate::proto::policies::proposal::Change as ProposedChange;
 use crate::proto::policies::{
-    AddPolicyRequest, GetPoliciesRequest, ModifyPolicyRequest, MultiPolicyResponse, PolicyResponse,
-    RemovePolicyRequest,
+    AddPolicyRequest, AddPolicySetRequest, ApproveProposalRequest, ClonePolicyRequest,
+    CompilePoliciesRequest, Decide, GetPoliciesRequest, GetPolicySetsRequest, ListProposalsRequest,
+    ModifyPolicyRequest, ModifyPolicySetRequest, MultiPolicyResponse, MultiPolicySetResponse,
+    MultiProposalResponse, PolicyImpact, PolicyResponse, PolicySetResponse, PrintPoliciesResponse,
+    Proposal, ProposalResponse, RejectProposalRequest, RemovePolicyRequest, RemovePolicySetRequest,
+    RenamePolicyRequest,
 };
 use crate::proto::roles::{
     AddRoleRequest, GetRolesRequest, ModifyRoleRequest, MultiRoleResponse, RemoveRoleRequest,
     RoleResponse,
 };
 use crate::proto::targets::{
-    AddTargetRequest, GetTargetsRequest, ModifyTargetRequest, MultiTargetResponse,
-    RemoveTargetRequest, TargetResponse,
+    AddTargetRequest, AddTargetsRequest, AddTargetsResponse, GetTargetsRequest,
+    ModifyTargetRequest, MultiTargetResponse, RemoveTargetRequest, TargetResponse,
 };
+use crate::proto::webhooks::{
+    AddWebhookRequest, DeliveriesResponse, GetDeliveriesRequest, GetWebhooksRequest,
+    MultiWebhookResponse, RemoveWebhookRequest, WebhookResponse,
+};
+use crate::region::{self, RegionReplicator};
+use crate::replica;
+use crate::schema;
+use crate::ssh;
+use crate::storage::health::StorageHealth;
+use crate::storage::Leadership;
+use crate::sync;
+use crate::unused;
 use crate::StorageType;
 
+/// request metadata a request hook names the caller with, once it has authenticated them;
+/// clients can't set it themselves
+pub(crate) const CALLER_METADATA_KEY: &str = "x-gatehouse-caller";
+
+/// request and response metadata that carries the correlation id of a check
+const CORRELATION_METADATA_KEY: &str = "x-correlation-id";
+
+/// request metadata that picks the namespace a call is for
+const NAMESPACE_METADATA_KEY: &str = "x-gatehouse-namespace";
+
+/// Who a call is made by, as authenticated by their API key or a request hook
+#[derive(Debug, Clone)]
+struct Caller(String);
+
+/// counts the correlation ids we have generated, so they are unique
+static CORRELATION_IDS: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    /// the namespace of the call being handled, if it isn't for the default store
+    static NAMESPACE: Option<String>;
+}
+
+/// A datastore and what we know of its storage
+#[derive(Debug)]
+struct Store {
+    dstx: Sender<DsRequest>,
+    /// whether we are the leader, when the backend elects one
+    leadership: watch::Receiver<Leadership>,
+    /// how the storage backend has been doing
+    storage_health: Arc<StorageHealth>,
+    /// how checks the datastore can't answer are answered
+    fallback: Fallback,
+}
+
+impl Store {
+    async fn create(storage: &StorageType, config: Config) -> Self {
+        let unused = match config.replica_of {
+            // a replica can't remove anything, and its primary flags what is unused
+            Some(_) => None,
+            None => Some((config.unused_days, config.remove_unused)).filter(|(days, _)| *days > 0),
+        };
+        let drift = match config.replica_of {
+            // a replica's primary repairs drift, and sends it the repairs
+            Some(_) => None,
+            None => Some(config.drift_secs).filter(|secs| *secs > 0),
+        };
+
+        let timeout = match config.check_timeout_ms {
+            0 => fallback::DEFAULT_TIMEOUT,
+            ms => Duration::from_millis(ms.into()),
+        };
+        let fallback = Fallback::new(config.unavailable_checks, timeout);
+
+        let (dstx, leadership, storage_health) = Datastore::create(storage, config).await;
+        if let Some((days, remove)) = unused {
+            unused::spawn(days, remove, dstx.clone());
+        }
+        if let Some(secs) = drift {
+            drift::spawn(Duration::from_secs(secs.into()), dstx.clone());
+        }
+
+        Self {
+            dstx,
+            leadership,
+            storage_health,
+            fallback,
+        }
+    }
+}
+
 #[derive(Debug)]
 /// The core Gatehouse server
 pub struct GatehouseSvc {
-    dstx: Sender<DsRequest>,
+    /// the default store
+    store: Store,
+    /// the stores of other namespaces, by name
+    namespaces: HashMap<String, Store>,
+    oidc: Option<Introspector>,
+    /// whether we replicate another server and so only serve checks
+    replica: bool,
+    /// how replication to another region is doing, if we replicate to one
+    region: Option<Arc<RegionReplicator>>,
+    /// callers who can change policies directly and approve proposals
+    approvers: Vec<String>,
+    /// the secret grant tokens are signed with, if grants are enabled
+    grant_secret: Option<String>,
+    /// how many seconds grants can be valid for
+    grant_ttl: u32,
+    /// hooks run around every call
+    hooks: Hooks,
+    /// how the canary checks run at startup did, if there were any
+    canaries: Option<CanaryStatus>,
+    /// whether we are only ready if every canary passed
+    canaries_required: bool,
+    /// whether every call must carry an API key
+    api_keys_required: bool,
+    /// the public key policy bundles must be signed with
+    bundle_key: Option<[u8; 32]>,
+    /// whether policies can only be changed by applying a signed bundle
+    bundle_only: bool,
 }
 
 impl GatehouseSvc {
     /// Create a new Gatehouse service
     pub async fn new(storage: &StorageType) -> Self {
-        let dstx = Datastore::create(storage).await;
-        GatehouseSvc { dstx }
+        Self::with_config(storage, Config::default()).await
+    }
+
+    /// Create a new Gatehouse service with the given configuration
+    pub async fn with_config(storage: &StorageType, config: Config) -> Self {
+        let ldap = config.ldap.clone();
+        let oidc = config.oidc.clone().map(Introspector::new);
+        let primary = config.replica_of.clone();
+        let primary_api_key = config.primary_api_key.clone();
+        let region = config.region.clone();
+        let approvers = config.approvers.clone();
+        let grant_secret = config
+            .grant_secret
+            .as_ref()
+            .map(|secret| secret.expose().to_string());
+        let grant_ttl = match config.grant_ttl {
+            0 => grant::DEFAULT_TTL,
+            ttl => ttl,
+        };
+        let canaries = config.canaries.clone();
+        let canaries_required = config.canaries_required;
+        let bundle_key = config.bundle_key;
+        let api_keys_required = config.api_keys_required;
+        let bundle_only = config.bundle_only;
+
+        // a replica only has what its primary sends to the default store
+        let mut namespaces = HashMap::new();
+        if primary.is_none() {
+            for namespace in &config.namespaces {
+                let store = Store::create(
+                    &storage.for_namespace(namespace),
+                    config.for_namespace(namespace),
+                )
+                .await;
+                namespaces.insert(namespace.clone(), store);
+            }
+        }
+
+        let store = Store::create(storage, config).await;
+
+        if let Some(ldap) = ldap {
+            sync::ldap::spawn(ldap, store.dstx.clone());
+        }
+
+        // a replica has nothing of its own to replicate to another region
+        let region = match primary {
+            Some(_) => None,
+            None => region.map(|region| region::spawn(region, store.dstx.clone())),
+        };
+
+        let replica = primary.is_some();
+        if let Some(primary) = primary {
+            replica::spawn(primary, primary_api_key, store.dstx.clone());
+        }
+
+        // a replica has nothing to check until its primary sends it the policies
+        let canaries = match (canaries, replica) {
+            (Some(source), false) => Some(canary::run(&source, &store.dstx).await),
+            _ => None,
+        };
+
+        GatehouseSvc {
+            store,
+            namespaces,
+            oidc,
+            replica,
+            region,
+            approvers,
+            grant_secret,
+            grant_ttl,
+            hooks: Hooks::default(),
+            canaries,
+            canaries_required,
+            api_keys_required,
+            bundle_key,
+            bundle_only,
+        }
+    }
+
+    /// Add a hook that runs before every call (see `hooks`)
+    ///
+    /// The hook is given the name of the method and the request metadata, which it can change.
+    /// If it returns an error, the call is rejected with it.
+    pub fn with_request_hook(
+        mut self,
+        hook: impl Fn(&str, &mut MetadataMap) -> Result<(), Box<Status>> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.request.push(Arc::new(hook));
+        self
+    }
+
+    /// Add a hook that runs after every call (see `hooks`)
+    ///
+    /// The hook is given the name of the method, the response metadata or the error, and how long
+    /// the call took.
+    pub fn with_response_hook(
+        mut self,
+        hook: impl Fn(&str, Result<&mut MetadataMap, &Status>, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.response.push(Arc::new(hook));
+        self
     }
 }
 
 impl GatehouseSvc {
+    /// Whether approval is needed for a caller's policy changes
+    fn needs_approval(&self, caller: &Option<String>) -> bool {
+        match caller {
+            _ if self.approvers.is_empty() => false,
+            Some(caller) => !self.approvers.contains(caller),
+            None => true,
+        }
+    }
+
+    /// Whether the caller may manage a delegation: when approvers are configured, only the
+    /// delegator, named as `type/name`, or an approver can
+    fn may_delegate(&self, caller: &Option<String>, delegation: &Delegation) -> bool {
+        match caller {
+            _ if self.approvers.is_empty() => true,
+            Some(caller) => {
+                self.approvers.contains(caller)
+                    || caller.split_once('/').is_some_and(|(typestr, name)| {
+                        typestr == delegation.delegator_type && name == delegation.delegator_name
+                    })
+            }
+            None => false,
+        }
+    }
+
+    /// The caller, if they are an approver
+    fn approver<T>(&self, request: &Request<T>) -> Option<String> {
+        caller(request).filter(|caller| self.approvers.contains(caller))
+    }
+
+    /// Hold a policy change for approval, returning the id of the proposal
+    async fn propose(
+        &self,
+        proposer: Option<String>,
+        change: ProposedChange,
+    ) -> Result<u64, Status> {
+        let proposal = Proposal {
+            proposer: proposer.unwrap_or_default(),
+            change: Some(change),
+            ..Default::default()
+        };
+        let (tx, rx) = channel::<DsResponse>();
+
+        match self
+            .call_datastore(
+                DsRequest::Propose(proposal, tx),
+                "propose policy change",
+                rx,
+            )
+            .await?
+        {
+            DsResponse::SingleProposal(proposal) => {
+                println!("Proposed policy change {}", proposal.id);
+                Ok(proposal.id)
+            }
+            DsResponse::Error(status) => Err(status),
+            _ => Err(Status::internal("Got unexpected answer from datastore")),
+        }
+    }
+
+    /// Handle a call, running the hooks around it
+    async fn hooked<T, U, F>(
+        &self,
+        method: &str,
+        mut request: Request<T>,
+        handle: impl FnOnce(Request<T>) -> F,
+    ) -> Result<Response<U>, Status>
+    where
+        F: Future<Output = Result<Response<U>, Status>>,
+    {
+        // a caller can only be named by a hook that authenticated them, not by the client
+        request.metadata_mut().remove(CALLER_METADATA_KEY);
+
+        // the first hook to reject the call stops it
+        for hook in &self.hooks.request {
+            hook(method, request.metadata_mut()).map_err(|status| *status)?;
+        }
+        if let Some(caller) = request.metadata_mut().remove(CALLER_METADATA_KEY) {
+            if let Ok(caller) = caller.to_str() {
+                request.extensions_mut().insert(Caller(caller.to_string()));
+            }
+        }
+
+        let namespace = match request.metadata().get(NAMESPACE_METADATA_KEY) {
+            Some(val) => {
+                let namespace = val.to_str().unwrap_or_default().to_ascii_lowercase();
+                if !self.namespaces.contains_key(&namespace) {
+                    return Err(Status::not_found(format!("Unknown namespace: {namespace}")));
+                }
+                Some(namespace)
+            }
+            None => None,
+        };
+
+        // the principal of the key is who makes the call, whatever a hook said
+        if self.api_keys_required || apikey::REPLICATION_CALLS.contains(&method) {
+            let key = self
+                .authenticate(method, request.metadata(), namespace.as_deref())
+                .await?;
+            if !key.principal.is_empty() {
+                request.extensions_mut().insert(Caller(key.principal));
+            }
+        }
+
+        let started = Instant::now();
+        let mut result = NAMESPACE.scope(namespace, handle(request)).await;
+        self.hooks.on_response(method, started, &mut result);
+        result
+    }
+
+    /// Make sure a call carries an API key that may make it, returning the key
+    ///
+    /// Keys are kept in the default store. The first one comes from the configuration, as no
+    /// call can be made without one.
+    async fn authenticate(
+        &self,
+        method: &str,
+        metadata: &MetadataMap,
+        namespace: Option<&str>,
+    ) -> Result<RegisteredApiKey, Status> {
+        let key = metadata
+            .get(API_KEY_METADATA_KEY)
+            .and_then(|val| val.to_str().ok())
+            .map(String::from);
+
+        let (tx, rx) = channel::<DsResponse>();
+        if let Err(err) = self
+            .store
+            .dstx
+            .send_async(DsRequest::VerifyApiKey(key, tx))
+            .await
+        {
+            return Err(Status::internal(err.to_string()));
+        }
+        let key = match rx.await.map_err(|err| Status::internal(err.to_string()))? {
+            DsResponse::VerifiedApiKey(key) => key,
+            DsResponse::Error(status) => return Err(status),
+            _ => return Err(Status::internal("Got unexpected answer from datastore")),
+        };
+
+        match key {
+            key if !key.scope.allows(method) => Err(Status::permission_denied(format!(
+                "API key {} may not {method}",
+                key.id
+            ))),
+            key if !key.allows_namespace(namespace) => Err(Status::permission_denied(format!(
+                "API key {} may not be used in this namespace",
+                key.id
+            ))),
+            key => Ok(key),
+        }
+    }
+
+    /// The store of the namespace the call being handled is for
+    fn store(&self) -> &Store {
+        match NAMESPACE.try_with(Clone::clone) {
+            Ok(Some(namespace)) => self.namespaces.get(&namespace).unwrap_or(&self.store),
+            _ => &self.store,
+        }
+    }
+
     /// Wait for a response from the datastore
     async fn call_datastore(
         &self,
@@ -53,13 +463,59 @@ impl GatehouseSvc {
         op: &str,
         rx: Receiver<DsResponse>,
     ) -> Result<DsResponse, Status> {
-        if let Err(err) = self.dstx.send_async(req).await {
+        self.call_datastore_within(req, op, rx, fallback::DEFAULT_TIMEOUT)
+            .await
+    }
+
+    /// Wait for a response from the datastore, for no longer than a timeout
+    async fn call_datastore_within(
+        &self,
+        req: DsRequest,
+        op: &str,
+        rx: Receiver<DsResponse>,
+        timeout: Duration,
+    ) -> Result<DsResponse, Status> {
+        if self.replica && !matches!(req, DsRequest::Check(..)) {
+            return Err(Status::failed_precondition(format!(
+                "Cannot {op}: this server is a replica and only serves checks"
+            )));
+        }
+
+        if self.bundle_only && req.changes_policies() {
+            return Err(Status::permission_denied(format!(
+                "Cannot {op}: policies can only be changed by applying a signed bundle"
+            )));
+        }
+
+        let store = self.store();
+
+        if req.is_mutation() {
+            let leadership = store.leadership.borrow();
+            if leadership.role == ServingRole::Standby {
+                let leader = match leadership.leader {
+                    Some(ref leader) => format!("; the leader is {leader}"),
+                    None => String::new(),
+                };
+                return Err(Status::failed_precondition(format!(
+                    "Cannot {op}: this server is a standby{leader}"
+                )));
+            }
+        }
+
+        // fail fast rather than wait on a backend we know is down
+        if req.is_mutation() && !store.storage_health.is_available() {
+            return Err(Status::unavailable(format!(
+                "Cannot {op}: the storage backend is unavailable"
+            )));
+        }
+
+        if let Err(err) = store.dstx.send_async(req).await {
             // TODO! -- add metrics
             eprintln!("{} failed: {:?}", op, err);
             return Err(Status::internal(err.to_string()));
         }
         tokio::select! {
-            _ = sleep(Duration::from_secs(30)) => {
+            _ = sleep(timeout) => {
                 // TODO! -- add metrics
                 eprintln!("Timeout waiting of response");
                 Err(Status::deadline_exceeded("Timeout waiting for response from datastore"))
@@ -70,6 +526,40 @@ impl GatehouseSvc {
             },
         }
     }
+
+    /// Inject claims from the bearer token in a check request, if introspection is configured
+    async fn enrich_check(&self, request: Request<CheckRequest>) -> Result<CheckRequest, Status> {
+        let token = self
+            .oidc
+            .as_ref()
+            .and_then(|oidc| oidc.token(request.metadata()));
+        let correlation_id = request
+            .metadata()
+            .get(CORRELATION_METADATA_KEY)
+            .and_then(|val| val.to_str().ok())
+            .map(String::from);
+        let mut req = request.into_inner();
+
+        if req.correlation_id.is_empty() {
+            req.correlation_id = correlation_id.unwrap_or_else(new_correlation_id);
+        }
+
+        if let Some(ref oidc) = self.oidc {
+            match token {
+                Some(token) => {
+                    if let Err(err) = oidc.enrich(&token, &mut req).await {
+                        // TODO! -- add metrics
+                        eprintln!("Token introspection failed: {}", err);
+                        return Err(Status::unavailable(err));
+                    }
+                }
+                // only a verified token can set the attributes its claims are injected as
+                None => oidc.strip(&mut req),
+            }
+        }
+
+        Ok(req)
+    }
 }
 
 #[tonic::async_trait]
@@ -81,26 +571,57 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<AddTargetRequest>,
     ) -> Result<Response<TargetResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("add_target", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        println!("svc: Add {}/{}", req.typestr, req.name);
-        if req.typestr.is_empty() || req.name.is_empty() {
-            return Err(Status::invalid_argument("Name and typestr cannot be null"));
-        }
+            println!("svc: Add {}/{}", req.typestr, req.name);
+            if req.typestr.is_empty() || req.name.is_empty() {
+                return Err(Status::invalid_argument("Name and typestr cannot be null"));
+            }
 
-        match self
-            .call_datastore(DsRequest::AddTarget(req.clone(), tx), "add target", rx)
-            .await?
-        {
-            DsResponse::SingleTarget(tgt) => {
-                //TODO! -- add metrics
-                println!("Added target {}", tgt);
-                return Ok(Response::new(TargetResponse { target: Some(tgt) }));
+            match self
+                .call_datastore(DsRequest::AddTarget(req.clone(), tx), "add target", rx)
+                .await?
+            {
+                DsResponse::SingleTarget(tgt, _) => {
+                    //TODO! -- add metrics
+                    println!("Added target {}", tgt);
+                    Ok(Response::new(TargetResponse {
+                        target: Some(tgt),
+                        ..Default::default()
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
+    }
+
+    /// Add many targets at once
+    async fn add_targets(
+        &self,
+        request: Request<AddTargetsRequest>,
+    ) -> Result<Response<AddTargetsResponse>, Status> {
+        self.hooked("add_targets", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::AddTargets(req, tx), "add targets", rx)
+                .await?
+            {
+                DsResponse::AddedTargets(results) => {
+                    let added = results.iter().filter(|r| r.error.is_empty()).count();
+                    println!("Added {added} of {} targets", results.len());
+                    Ok(Response::new(AddTargetsResponse { results }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     /// Modify an existing target
@@ -108,25 +629,31 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<ModifyTargetRequest>,
     ) -> Result<Response<TargetResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("modify_target", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(
-                DsRequest::ModifyTarget(req.clone(), tx),
-                "modify target",
-                rx,
-            )
-            .await?
-        {
-            DsResponse::SingleTarget(tgt) => {
-                //TODO! -- add metrics
-                println!("Updated target: {}", tgt);
-                return Ok(Response::new(TargetResponse { target: Some(tgt) }));
+            match self
+                .call_datastore(
+                    DsRequest::ModifyTarget(req.clone(), tx),
+                    "modify target",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::SingleTarget(tgt, _) => {
+                    //TODO! -- add metrics
+                    println!("Updated target: {}", tgt);
+                    Ok(Response::new(TargetResponse {
+                        target: Some(tgt),
+                        ..Default::default()
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     /// Remove an existing target
@@ -134,25 +661,31 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<RemoveTargetRequest>,
     ) -> Result<Response<TargetResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("remove_target", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(
-                DsRequest::RemoveTarget(req.clone(), tx),
-                "remove target",
-                rx,
-            )
-            .await?
-        {
-            DsResponse::SingleTarget(tgt) => {
-                //TODO! -- add metrics
-                println!("Removed target {}", tgt);
-                return Ok(Response::new(TargetResponse { target: Some(tgt) }));
+            match self
+                .call_datastore(
+                    DsRequest::RemoveTarget(req.clone(), tx),
+                    "remove target",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::SingleTarget(tgt, references) => {
+                    //TODO! -- add metrics
+                    println!("Removed target {}", tgt);
+                    Ok(Response::new(TargetResponse {
+                        target: Some(tgt),
+                        references,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     /// Get all targets
@@ -160,21 +693,27 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<GetTargetsRequest>,
     ) -> Result<Response<MultiTargetResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("get_targets", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::GetTargets(req.clone(), tx), "get target(s)", rx)
-            .await?
-        {
-            DsResponse::MultipleTargets(tgts) => {
-                //TODO! -- add metrics
-                println!("Got {} targets", tgts.len());
-                return Ok(Response::new(MultiTargetResponse { targets: tgts }));
+            match self
+                .call_datastore(DsRequest::GetTargets(req.clone(), tx), "get target(s)", rx)
+                .await?
+            {
+                DsResponse::MultipleTargets(tgts, hits) => {
+                    //TODO! -- add metrics
+                    println!("Got {} targets", tgts.len());
+                    Ok(Response::new(MultiTargetResponse {
+                        targets: tgts,
+                        hits,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     //** ENTITIES **//
@@ -184,22 +723,53 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<AddActorRequest>,
     ) -> Result<Response<ActorResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("add_actor", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        // wait for the datastore to respond
-        match self
-            .call_datastore(DsRequest::AddActor(req.clone(), tx), "add actor", rx)
-            .await?
-        {
-            DsResponse::SingleActor(actor) => {
-                //TODO! -- add metrics
-                println!("Added actor {}", actor);
-                Ok(Response::new(ActorResponse { actor: Some(actor) }))
+            // wait for the datastore to respond
+            match self
+                .call_datastore(DsRequest::AddActor(req.clone(), tx), "add actor", rx)
+                .await?
+            {
+                DsResponse::SingleActor(actor, _) => {
+                    //TODO! -- add metrics
+                    println!("Added actor {}", actor);
+                    Ok(Response::new(ActorResponse {
+                        actor: Some(actor),
+                        ..Default::default()
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
+    }
+
+    /// Add many actors at once
+    async fn add_actors(
+        &self,
+        request: Request<AddActorsRequest>,
+    ) -> Result<Response<AddActorsResponse>, Status> {
+        self.hooked("add_actors", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::AddActors(req, tx), "add actors", rx)
+                .await?
+            {
+                DsResponse::AddedActors(results) => {
+                    let added = results.iter().filter(|r| r.error.is_empty()).count();
+                    println!("Added {added} of {} actors", results.len());
+                    Ok(Response::new(AddActorsResponse { results }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     /// Modify an actor
@@ -207,21 +777,27 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<ModifyActorRequest>,
     ) -> Result<Response<ActorResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("modify_actor", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::ModifyActor(req.clone(), tx), "modify actor", rx)
-            .await?
-        {
-            DsResponse::SingleActor(actor) => {
-                //TODO! -- add metrics
-                println!("Modify actor {}", actor);
-                Ok(Response::new(ActorResponse { actor: Some(actor) }))
+            match self
+                .call_datastore(DsRequest::ModifyActor(req.clone(), tx), "modify actor", rx)
+                .await?
+            {
+                DsResponse::SingleActor(actor, _) => {
+                    //TODO! -- add metrics
+                    println!("Modify actor {}", actor);
+                    Ok(Response::new(ActorResponse {
+                        actor: Some(actor),
+                        ..Default::default()
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     /// Remove an actor
@@ -229,21 +805,27 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<RemoveActorRequest>,
     ) -> Result<Response<ActorResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("remove_actor", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::RemoveActor(req.clone(), tx), "remove actor", rx)
-            .await?
-        {
-            DsResponse::SingleActor(actor) => {
-                //TODO! -- add metrics
-                println!("Remove actor {}", actor);
-                Ok(Response::new(ActorResponse { actor: Some(actor) }))
+            match self
+                .call_datastore(DsRequest::RemoveActor(req.clone(), tx), "remove actor", rx)
+                .await?
+            {
+                DsResponse::SingleActor(actor, references) => {
+                    //TODO! -- add metrics
+                    println!("Remove actor {}", actor);
+                    Ok(Response::new(ActorResponse {
+                        actor: Some(actor),
+                        references,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     /// Get all entries
@@ -251,21 +833,171 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<GetActorsRequest>,
     ) -> Result<Response<MultiActorResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("get_actors", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::GetActors(req.clone(), tx), "get actors", rx)
-            .await?
-        {
-            DsResponse::MultipleActors(actors) => {
-                //TODO! -- add metrics
-                println!("Got {} actors", actors.len());
-                return Ok(Response::new(MultiActorResponse { actors }));
+            match self
+                .call_datastore(DsRequest::GetActors(req.clone(), tx), "get actors", rx)
+                .await?
+            {
+                DsResponse::MultipleActors(actors, hits) => {
+                    //TODO! -- add metrics
+                    println!("Got {} actors", actors.len());
+                    Ok(Response::new(MultiActorResponse { actors, hits }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
+    }
+
+    /// Get the groups an actor belongs to and the roles they convey
+    async fn get_actor_memberships(
+        &self,
+        request: Request<GetActorMembershipsRequest>,
+    ) -> Result<Response<ActorMembershipsResponse>, Status> {
+        self.hooked("get_actor_memberships", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::GetActorMemberships(req, tx),
+                    "get actor memberships",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::ActorMemberships(memberships) => {
+                    println!(
+                        "Got {} groups and {} roles for actor",
+                        memberships.groups.len(),
+                        memberships.roles.len()
+                    );
+                    Ok(Response::new(memberships))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Lend a subset of an actor's access to another actor until it expires
+    ///
+    /// When approvers are configured, only the delegator or an approver can delegate.
+    async fn add_delegation(
+        &self,
+        request: Request<AddDelegationRequest>,
+    ) -> Result<Response<DelegationResponse>, Status> {
+        self.hooked("add_delegation", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
+            if let Some(ref delegation) = req.delegation {
+                if !self.may_delegate(&caller, delegation) {
+                    return Err(Status::permission_denied(
+                        "Only the delegator or an approver can delegate",
+                    ));
+                }
+            }
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::AddDelegation(req, tx), "add delegation", rx)
+                .await?
+            {
+                DsResponse::SingleDelegation(delegation) => {
+                    println!("Added delegation {}", delegation.name);
+                    Ok(Response::new(DelegationResponse {
+                        delegation: Some(delegation),
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Remove a delegation
+    ///
+    /// When approvers are configured, only the delegator or an approver can remove it.
+    async fn remove_delegation(
+        &self,
+        request: Request<RemoveDelegationRequest>,
+    ) -> Result<Response<DelegationResponse>, Status> {
+        self.hooked("remove_delegation", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
+            if !self.approvers.is_empty() {
+                let (tx, rx) = channel::<DsResponse>();
+                if let DsResponse::MultipleDelegations(delegations) = self
+                    .call_datastore(
+                        DsRequest::GetDelegations(GetDelegationsRequest::default(), tx),
+                        "get delegations",
+                        rx,
+                    )
+                    .await?
+                {
+                    if let Some(delegation) = delegations
+                        .iter()
+                        .find(|d| d.name.eq_ignore_ascii_case(&req.name))
+                    {
+                        if !self.may_delegate(&caller, delegation) {
+                            return Err(Status::permission_denied(
+                                "Only the delegator or an approver can remove a delegation",
+                            ));
+                        }
+                    }
+                }
+            }
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::RemoveDelegation(req, tx),
+                    "remove delegation",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::SingleDelegation(delegation) => {
+                    println!("Removed delegation {}", delegation.name);
+                    Ok(Response::new(DelegationResponse {
+                        delegation: Some(delegation),
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get delegations, or just those from or to an actor
+    async fn get_delegations(
+        &self,
+        request: Request<GetDelegationsRequest>,
+    ) -> Result<Response<MultiDelegationResponse>, Status> {
+        self.hooked("get_delegations", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetDelegations(req, tx), "get delegations", rx)
+                .await?
+            {
+                DsResponse::MultipleDelegations(delegations) => {
+                    println!("Got {} delegations", delegations.len());
+                    Ok(Response::new(MultiDelegationResponse { delegations }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     //** ROLES **//
@@ -275,21 +1007,27 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<AddRoleRequest>,
     ) -> Result<Response<RoleResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("add_role", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::AddRole(req.clone(), tx), "add role", rx)
-            .await?
-        {
-            DsResponse::SingleRole(role) => {
-                //TODO! -- add metrics
-                println!("Added role {}", role);
-                return Ok(Response::new(RoleResponse { role: Some(role) }));
+            match self
+                .call_datastore(DsRequest::AddRole(req.clone(), tx), "add role", rx)
+                .await?
+            {
+                DsResponse::SingleRole(role, affected_groups) => {
+                    //TODO! -- add metrics
+                    println!("Added role {}", role);
+                    Ok(Response::new(RoleResponse {
+                        role: Some(role),
+                        affected_groups,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     /// Modify a role
@@ -297,21 +1035,27 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<ModifyRoleRequest>,
     ) -> Result<Response<RoleResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("modify_role", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::ModifyRole(req.clone(), tx), "modify role", rx)
-            .await?
-        {
-            DsResponse::SingleRole(role) => {
-                //TODO! -- add metrics
-                println!("Modified role {}", role);
-                return Ok(Response::new(RoleResponse { role: Some(role) }));
+            match self
+                .call_datastore(DsRequest::ModifyRole(req.clone(), tx), "modify role", rx)
+                .await?
+            {
+                DsResponse::SingleRole(role, affected_groups) => {
+                    //TODO! -- add metrics
+                    println!("Modified role {}", role);
+                    Ok(Response::new(RoleResponse {
+                        role: Some(role),
+                        affected_groups,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     /// Delete a role
@@ -319,21 +1063,27 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<RemoveRoleRequest>,
     ) -> Result<Response<RoleResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("remove_role", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::RemoveRole(req.clone(), tx), "remove role", rx)
-            .await?
-        {
-            DsResponse::SingleRole(role) => {
-                //TODO! -- add metrics
-                println!("Removed role {}", role);
-                return Ok(Response::new(RoleResponse { role: Some(role) }));
+            match self
+                .call_datastore(DsRequest::RemoveRole(req.clone(), tx), "remove role", rx)
+                .await?
+            {
+                DsResponse::SingleRole(role, affected_groups) => {
+                    //TODO! -- add metrics
+                    println!("Removed role {}", role);
+                    Ok(Response::new(RoleResponse {
+                        role: Some(role),
+                        affected_groups,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     /// Get all roles (or a specific one by name)
@@ -341,21 +1091,24 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<GetRolesRequest>,
     ) -> Result<Response<MultiRoleResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("get_roles", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::GetRoles(req.clone(), tx), "get roles", rx)
-            .await?
-        {
-            DsResponse::MultipleRoles(roles) => {
-                //TODO! -- add metrics
-                println!("Get {} roles", roles.len());
-                return Ok(Response::new(MultiRoleResponse { roles }));
+            match self
+                .call_datastore(DsRequest::GetRoles(req.clone(), tx), "get roles", rx)
+                .await?
+            {
+                DsResponse::MultipleRoles(roles) => {
+                    //TODO! -- add metrics
+                    println!("Get {} roles", roles.len());
+                    Ok(Response::new(MultiRoleResponse { roles }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     //** GROUPS **//
@@ -365,21 +1118,27 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<AddGroupRequest>,
     ) -> Result<Response<GroupResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("add_group", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::AddGroup(req.clone(), tx), "add group", rx)
-            .await?
-        {
-            DsResponse::SingleGroup(group) => {
-                //TODO! -- add metrics
-                println!("Added group {}", group);
-                return Ok(Response::new(GroupResponse { group: Some(group) }));
+            match self
+                .call_datastore(DsRequest::AddGroup(req.clone(), tx), "add group", rx)
+                .await?
+            {
+                DsResponse::SingleGroup(group, affected_roles) => {
+                    //TODO! -- add metrics
+                    println!("Added group {}", group);
+                    Ok(Response::new(GroupResponse {
+                        group: Some(group),
+                        affected_roles,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
     }
 
     /// Modify a group
@@ -387,21 +1146,55 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<ModifyGroupRequest>,
     ) -> Result<Response<GroupResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("modify_group", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::ModifyGroup(req.clone(), tx), "modify group", rx)
-            .await?
-        {
-            DsResponse::SingleGroup(group) => {
-                //TODO! -- add metrics
-                println!("Modified group {}", group);
-                return Ok(Response::new(GroupResponse { group: Some(group) }));
+            match self
+                .call_datastore(DsRequest::ModifyGroup(req.clone(), tx), "modify group", rx)
+                .await?
+            {
+                DsResponse::SingleGroup(group, affected_roles) => {
+                    //TODO! -- add metrics
+                    println!("Modified group {}", group);
+                    Ok(Response::new(GroupResponse {
+                        group: Some(group),
+                        affected_roles,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
+    }
+
+    /// Add and remove members across several groups at once
+    async fn bulk_modify_memberships(
+        &self,
+        request: Request<BulkModifyMembershipsRequest>,
+    ) -> Result<Response<MultiGroupResponse>, Status> {
+        self.hooked("bulk_modify_memberships", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::BulkModifyMemberships(req, tx),
+                    "bulk modify memberships",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::MultipleGroups(groups) => {
+                    println!("Modified memberships of {} groups", groups.len());
+                    Ok(Response::new(MultiGroupResponse { groups }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     /// Remove a group
@@ -409,21 +1202,81 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<RemoveGroupRequest>,
     ) -> Result<Response<GroupResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("remove_group", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::RemoveGroup(req.clone(), tx), "remove group", rx)
-            .await?
-        {
-            DsResponse::SingleGroup(group) => {
-                //TODO! -- add metrics
-                println!("Removed group {}", group);
-                return Ok(Response::new(GroupResponse { group: Some(group) }));
+            match self
+                .call_datastore(DsRequest::RemoveGroup(req.clone(), tx), "remove group", rx)
+                .await?
+            {
+                DsResponse::SingleGroup(group, affected_roles) => {
+                    //TODO! -- add metrics
+                    println!("Removed group {}", group);
+                    Ok(Response::new(GroupResponse {
+                        group: Some(group),
+                        affected_roles,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
+    }
+
+    /// Copy a group under a new name
+    async fn clone_group(
+        &self,
+        request: Request<CloneGroupRequest>,
+    ) -> Result<Response<GroupResponse>, Status> {
+        self.hooked("clone_group", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::CloneGroup(req, tx), "clone group", rx)
+                .await?
+            {
+                DsResponse::SingleGroup(group, affected_roles) => {
+                    println!("Cloned group {}", group);
+                    Ok(Response::new(GroupResponse {
+                        group: Some(group),
+                        affected_roles,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Rename a group and the references to it
+    async fn rename_group(
+        &self,
+        request: Request<RenameGroupRequest>,
+    ) -> Result<Response<GroupResponse>, Status> {
+        self.hooked("rename_group", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::RenameGroup(req, tx), "rename group", rx)
+                .await?
+            {
+                DsResponse::SingleGroup(group, affected_roles) => {
+                    println!("Renamed group to {}", group);
+                    Ok(Response::new(GroupResponse {
+                        group: Some(group),
+                        affected_roles,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     /// Get groups (with optional filters)
@@ -431,21 +1284,48 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<GetGroupsRequest>,
     ) -> Result<Response<MultiGroupResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("get_groups", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::GetGroups(req.clone(), tx), "get groups", rx)
-            .await?
-        {
-            DsResponse::MultipleGroups(groups) => {
-                //TODO! -- add metrics
-                println!("Got {} groups", groups.len());
-                return Ok(Response::new(MultiGroupResponse { groups }));
+            match self
+                .call_datastore(DsRequest::GetGroups(req.clone(), tx), "get groups", rx)
+                .await?
+            {
+                DsResponse::MultipleGroups(groups) => {
+                    //TODO! -- add metrics
+                    println!("Got {} groups", groups.len());
+                    Ok(Response::new(MultiGroupResponse { groups }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
+    }
+
+    /// Get the effective members of a group, a page at a time
+    async fn get_group_members(
+        &self,
+        request: Request<GetGroupMembersRequest>,
+    ) -> Result<Response<GroupMembersResponse>, Status> {
+        self.hooked("get_group_members", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetGroupMembers(req, tx), "get group members", rx)
+                .await?
+            {
+                DsResponse::GroupMembers(page) => {
+                    println!("Got {} of {} group members", page.members.len(), page.total);
+                    Ok(Response::new(page))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     /// Add policy
@@ -453,21 +1333,40 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<AddPolicyRequest>,
     ) -> Result<Response<PolicyResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("add_policy", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
 
-        match self
-            .call_datastore(DsRequest::AddPolicy(req.clone(), tx), "add policy", rx)
-            .await?
-        {
-            DsResponse::SinglePolicy(rule) => {
-                //TODO! -- add metrics
-                println!("Added policy rule {}", rule);
-                return Ok(Response::new(PolicyResponse { rule: Some(*rule) }));
+            if self.needs_approval(&caller) && !req.dry_run {
+                let rule = req.rule.clone();
+                let proposal_id = self.propose(caller, ProposedChange::Add(req)).await?;
+                return Ok(Response::new(PolicyResponse {
+                    rule,
+                    proposal_id,
+                    ..Default::default()
+                }));
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::AddPolicy(req.clone(), tx), "add policy", rx)
+                .await?
+            {
+                DsResponse::SinglePolicy(rule, impact) => {
+                    //TODO! -- add metrics
+                    println!("Added policy rule {}{}", rule, describe_impact(&impact));
+                    Ok(Response::new(PolicyResponse {
+                        rule: Some(*rule),
+                        impact,
+                        ..Default::default()
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     /// Modify policy
@@ -475,25 +1374,44 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<ModifyPolicyRequest>,
     ) -> Result<Response<PolicyResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("modify_policy", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
 
-        match self
-            .call_datastore(
-                DsRequest::ModifyPolicy(req.clone(), tx),
-                "modify policy",
-                rx,
-            )
-            .await?
-        {
-            DsResponse::SinglePolicy(rule) => {
-                //TODO! -- add metrics
-                println!("Modified policy rule {}", rule);
-                return Ok(Response::new(PolicyResponse { rule: Some(*rule) }));
+            if self.needs_approval(&caller) && !req.dry_run {
+                let rule = req.rule.clone();
+                let proposal_id = self.propose(caller, ProposedChange::Modify(req)).await?;
+                return Ok(Response::new(PolicyResponse {
+                    rule,
+                    proposal_id,
+                    ..Default::default()
+                }));
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::ModifyPolicy(req.clone(), tx),
+                    "modify policy",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::SinglePolicy(rule, impact) => {
+                    //TODO! -- add metrics
+                    println!("Modified policy rule {}{}", rule, describe_impact(&impact));
+                    Ok(Response::new(PolicyResponse {
+                        rule: Some(*rule),
+                        impact,
+                        ..Default::default()
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     /// Remove policy
@@ -501,25 +1419,114 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<RemovePolicyRequest>,
     ) -> Result<Response<PolicyResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("remove_policy", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
 
-        match self
-            .call_datastore(
-                DsRequest::RemovePolicy(req.clone(), tx),
-                "remove policy",
-                rx,
-            )
-            .await?
-        {
-            DsResponse::SinglePolicy(rule) => {
-                //TODO! -- add metrics
-                println!("Removed policy rule {}", rule);
-                return Ok(Response::new(PolicyResponse { rule: Some(*rule) }));
+            if self.needs_approval(&caller) && !req.dry_run {
+                let proposal_id = self.propose(caller, ProposedChange::Remove(req)).await?;
+                return Ok(Response::new(PolicyResponse {
+                    proposal_id,
+                    ..Default::default()
+                }));
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::RemovePolicy(req.clone(), tx),
+                    "remove policy",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::SinglePolicy(rule, impact) => {
+                    //TODO! -- add metrics
+                    println!("Removed policy rule {}{}", rule, describe_impact(&impact));
+                    Ok(Response::new(PolicyResponse {
+                        rule: Some(*rule),
+                        impact,
+                        ..Default::default()
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Copy a policy under a new name
+    async fn clone_policy(
+        &self,
+        request: Request<ClonePolicyRequest>,
+    ) -> Result<Response<PolicyResponse>, Status> {
+        self.hooked("clone_policy", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
+
+            // approvals are for single adds, modifies, and removes, which this can be done with
+            if self.needs_approval(&caller) && !req.dry_run {
+                return Err(Status::permission_denied(
+                    "Policy changes need approval; use AddPolicy and RemovePolicy instead",
+                ));
+            }
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::ClonePolicy(req, tx), "clone policy", rx)
+                .await?
+            {
+                DsResponse::SinglePolicy(rule, _) => {
+                    println!("Cloned policy rule {}", rule);
+                    Ok(Response::new(PolicyResponse {
+                        rule: Some(*rule),
+                        ..Default::default()
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Rename a policy
+    async fn rename_policy(
+        &self,
+        request: Request<RenamePolicyRequest>,
+    ) -> Result<Response<PolicyResponse>, Status> {
+        self.hooked("rename_policy", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
+
+            // approvals are for single adds, modifies, and removes, which this can be done with
+            if self.needs_approval(&caller) && !req.dry_run {
+                return Err(Status::permission_denied(
+                    "Policy changes need approval; use AddPolicy and RemovePolicy instead",
+                ));
+            }
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::RenamePolicy(req, tx), "rename policy", rx)
+                .await?
+            {
+                DsResponse::SinglePolicy(rule, _) => {
+                    println!("Renamed policy rule to {}", rule);
+                    Ok(Response::new(PolicyResponse {
+                        rule: Some(*rule),
+                        ..Default::default()
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     /// Get policies based on filters
@@ -527,21 +1534,625 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<GetPoliciesRequest>,
     ) -> Result<Response<MultiPolicyResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("get_policies", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
 
-        match self
-            .call_datastore(DsRequest::GetPolicies(req.clone(), tx), "get policies", rx)
-            .await?
-        {
-            DsResponse::MultiplePolicies(rules) => {
-                //TODO! -- add metrics
-                println!("Got {} policies", rules.len());
-                return Ok(Response::new(MultiPolicyResponse { rules }));
+            match self
+                .call_datastore(DsRequest::GetPolicies(req.clone(), tx), "get policies", rx)
+                .await?
+            {
+                DsResponse::MultiplePolicies(rules) => {
+                    //TODO! -- add metrics
+                    println!("Got {} policies", rules.len());
+                    Ok(Response::new(MultiPolicyResponse { rules }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
             }
-            DsResponse::Error(status) => return Err(status),
-            _ => return Err(Status::internal("Got unexpected answer from datastore")),
-        }
+        })
+        .await
+    }
+
+    /// Compile policies written in the policy DSL, without adding them
+    async fn compile_policies(
+        &self,
+        request: Request<CompilePoliciesRequest>,
+    ) -> Result<Response<MultiPolicyResponse>, Status> {
+        self.hooked("compile_policies", request, |request| async move {
+            let rules = dsl::parse(&request.into_inner().text).map_err(Status::invalid_argument)?;
+            println!("Compiled {} policies", rules.len());
+            Ok(Response::new(MultiPolicyResponse { rules }))
+        })
+        .await
+    }
+
+    /// Get policies written in the policy DSL, and why the others can't be
+    async fn print_policies(
+        &self,
+        request: Request<GetPoliciesRequest>,
+    ) -> Result<Response<PrintPoliciesResponse>, Status> {
+        self.hooked("print_policies", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetPolicies(req, tx), "get policies", rx)
+                .await?
+            {
+                DsResponse::MultiplePolicies(mut rules) => {
+                    rules.sort_by(|a, b| a.name.cmp(&b.name));
+                    let mut printed = Vec::new();
+                    let mut unprintable = Vec::new();
+                    for rule in &rules {
+                        match dsl::print(rule) {
+                            Ok(text) => printed.push(text),
+                            Err(err) => unprintable.push(err),
+                        }
+                    }
+                    println!("Printed {} policies", printed.len());
+                    Ok(Response::new(PrintPoliciesResponse {
+                        text: printed.join("\n"),
+                        unprintable,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Add a policy set
+    async fn add_policy_set(
+        &self,
+        request: Request<AddPolicySetRequest>,
+    ) -> Result<Response<PolicySetResponse>, Status> {
+        self.hooked("add_policy_set", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
+
+            // proposals only hold policy changes, so sets can't wait for approval
+            if self.needs_approval(&caller) && !req.dry_run {
+                return Err(Status::permission_denied(
+                    "Only approvers can change policy sets",
+                ));
+            }
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::AddPolicySet(req, tx), "add policy set", rx)
+                .await?
+            {
+                DsResponse::SinglePolicySet(set) => {
+                    println!("Added policy set {}", set);
+                    Ok(Response::new(PolicySetResponse { set: Some(set) }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Modify a policy set
+    async fn modify_policy_set(
+        &self,
+        request: Request<ModifyPolicySetRequest>,
+    ) -> Result<Response<PolicySetResponse>, Status> {
+        self.hooked("modify_policy_set", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
+
+            // proposals only hold policy changes, so sets can't wait for approval
+            if self.needs_approval(&caller) && !req.dry_run {
+                return Err(Status::permission_denied(
+                    "Only approvers can change policy sets",
+                ));
+            }
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::ModifyPolicySet(req, tx), "modify policy set", rx)
+                .await?
+            {
+                DsResponse::SinglePolicySet(set) => {
+                    println!("Modified policy set {}", set);
+                    Ok(Response::new(PolicySetResponse { set: Some(set) }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Remove a policy set
+    async fn remove_policy_set(
+        &self,
+        request: Request<RemovePolicySetRequest>,
+    ) -> Result<Response<PolicySetResponse>, Status> {
+        self.hooked("remove_policy_set", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
+
+            // proposals only hold policy changes, so sets can't wait for approval
+            if self.needs_approval(&caller) && !req.dry_run {
+                return Err(Status::permission_denied(
+                    "Only approvers can change policy sets",
+                ));
+            }
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::RemovePolicySet(req, tx), "remove policy set", rx)
+                .await?
+            {
+                DsResponse::SinglePolicySet(set) => {
+                    println!("Removed policy set {}", set);
+                    Ok(Response::new(PolicySetResponse { set: Some(set) }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get policy sets, or just the one named
+    async fn get_policy_sets(
+        &self,
+        request: Request<GetPolicySetsRequest>,
+    ) -> Result<Response<MultiPolicySetResponse>, Status> {
+        self.hooked("get_policy_sets", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetPolicySets(req, tx), "get policy sets", rx)
+                .await?
+            {
+                DsResponse::MultiplePolicySets(sets) => {
+                    println!("Got {} policy sets", sets.len());
+                    Ok(Response::new(MultiPolicySetResponse { sets }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get the policy changes waiting for approval
+    async fn list_proposals(
+        &self,
+        request: Request<ListProposalsRequest>,
+    ) -> Result<Response<MultiProposalResponse>, Status> {
+        self.hooked("list_proposals", request, |_request| async move {
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::ListProposals(tx), "list proposals", rx)
+                .await?
+            {
+                DsResponse::MultipleProposals(proposals) => {
+                    println!("Got {} proposals", proposals.len());
+                    Ok(Response::new(MultiProposalResponse { proposals }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Approve a proposal, making its change
+    async fn approve_proposal(
+        &self,
+        request: Request<ApproveProposalRequest>,
+    ) -> Result<Response<ProposalResponse>, Status> {
+        self.hooked("approve_proposal", request, |request| async move {
+            let approver = self
+                .approver(&request)
+                .ok_or_else(|| Status::permission_denied("Only approvers can do this"))?;
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::ApproveProposal(req.id, approver.clone(), tx),
+                    "approve proposal",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::SingleProposal(proposal) => {
+                    println!("{} approved proposal {}", approver, proposal.id);
+                    Ok(Response::new(ProposalResponse {
+                        proposal: Some(*proposal),
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Reject a proposal, dropping its change
+    async fn reject_proposal(
+        &self,
+        request: Request<RejectProposalRequest>,
+    ) -> Result<Response<ProposalResponse>, Status> {
+        self.hooked("reject_proposal", request, |request| async move {
+            let approver = self
+                .approver(&request)
+                .ok_or_else(|| Status::permission_denied("Only approvers can do this"))?;
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::RejectProposal(req.id, tx), "reject proposal", rx)
+                .await?
+            {
+                DsResponse::SingleProposal(proposal) => {
+                    println!("{} rejected proposal {}", approver, proposal.id);
+                    Ok(Response::new(ProposalResponse {
+                        proposal: Some(*proposal),
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Make several changes at once
+    async fn apply_transaction(
+        &self,
+        request: Request<ApplyTransactionRequest>,
+    ) -> Result<Response<ApplyTransactionResponse>, Status> {
+        self.hooked("apply_transaction", request, |request| async move {
+            let caller = caller(&request);
+            let req = request.into_inner();
+
+            // policy changes that need approval can't sneak in through a transaction
+            let changes_policies = req.mutations.iter().any(|mutation| {
+                matches!(
+                    mutation.op,
+                    Some(Op::AddPolicy(_)) | Some(Op::ModifyPolicy(_)) | Some(Op::RemovePolicy(_))
+                )
+            });
+            if changes_policies && self.needs_approval(&caller) && !req.dry_run {
+                return Err(Status::permission_denied(
+                    "Policy changes need approval and can't be made in a transaction",
+                ));
+            }
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::ApplyTransaction(req, tx),
+                    "apply transaction",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::TransactionApplied(changes) => {
+                    println!("Applied transaction with {} changes", changes.len());
+                    Ok(Response::new(ApplyTransactionResponse { changes }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Make the policies those of a signed bundle
+    async fn apply_bundle(
+        &self,
+        request: Request<ApplyBundleRequest>,
+    ) -> Result<Response<ApplyTransactionResponse>, Status> {
+        self.hooked("apply_bundle", request, |request| async move {
+            let req = request.into_inner();
+
+            let key = self.bundle_key.as_ref().ok_or_else(|| {
+                Status::failed_precondition("No key is configured to verify bundles with")
+            })?;
+            let signed = req
+                .bundle
+                .ok_or_else(|| Status::invalid_argument("No bundle in request"))?;
+            let verified = bundle::verify(&signed, key).map_err(|err| {
+                eprintln!("Refused policy bundle: {err}");
+                Status::permission_denied(err)
+            })?;
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::ApplyBundle(verified, req.dry_run, tx),
+                    "apply bundle",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::TransactionApplied(changes) => {
+                    println!("Applied policy bundle with {} changes", changes.len());
+                    Ok(Response::new(ApplyTransactionResponse { changes }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Find what refers to an entity
+    async fn get_references(
+        &self,
+        request: Request<GetReferencesRequest>,
+    ) -> Result<Response<GetReferencesResponse>, Status> {
+        self.hooked("get_references", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetReferences(req, tx), "get references", rx)
+                .await?
+            {
+                DsResponse::References(references) => {
+                    println!(
+                        "Got {} policies, {} groups, and {} roles referring to entity",
+                        references.policies.len(),
+                        references.groups.len(),
+                        references.roles.len()
+                    );
+                    Ok(Response::new(references))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Find the actors, targets, and roles nothing uses, without removing them
+    async fn find_unused(
+        &self,
+        request: Request<FindUnusedRequest>,
+    ) -> Result<Response<FindUnusedResponse>, Status> {
+        self.hooked("find_unused", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::FindUnused(req, tx), "find unused entities", rx)
+                .await?
+            {
+                DsResponse::Unused(unused) => {
+                    println!("Found {} unused entities", unused.entities.len());
+                    Ok(Response::new(unused))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Add a webhook
+    async fn add_webhook(
+        &self,
+        request: Request<AddWebhookRequest>,
+    ) -> Result<Response<WebhookResponse>, Status> {
+        self.hooked("add_webhook", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::AddWebhook(req, tx), "add webhook", rx)
+                .await?
+            {
+                DsResponse::SingleWebhook(webhook) => {
+                    println!("Added webhook {}", webhook);
+                    Ok(Response::new(WebhookResponse {
+                        webhook: Some(webhook),
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Remove a webhook
+    async fn remove_webhook(
+        &self,
+        request: Request<RemoveWebhookRequest>,
+    ) -> Result<Response<WebhookResponse>, Status> {
+        self.hooked("remove_webhook", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::RemoveWebhook(req, tx), "remove webhook", rx)
+                .await?
+            {
+                DsResponse::SingleWebhook(webhook) => {
+                    println!("Removed webhook {}", webhook);
+                    Ok(Response::new(WebhookResponse {
+                        webhook: Some(webhook),
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get webhooks, optionally by name
+    async fn get_webhooks(
+        &self,
+        request: Request<GetWebhooksRequest>,
+    ) -> Result<Response<MultiWebhookResponse>, Status> {
+        self.hooked("get_webhooks", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetWebhooks(req, tx), "get webhooks", rx)
+                .await?
+            {
+                DsResponse::MultipleWebhooks(webhooks) => {
+                    println!("Got {} webhooks", webhooks.len());
+                    Ok(Response::new(MultiWebhookResponse { webhooks }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get the status of recent webhook deliveries
+    async fn get_webhook_deliveries(
+        &self,
+        request: Request<GetDeliveriesRequest>,
+    ) -> Result<Response<DeliveriesResponse>, Status> {
+        self.hooked("get_webhook_deliveries", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::GetWebhookDeliveries(req, tx),
+                    "get webhook deliveries",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::Deliveries(deliveries) => {
+                    println!("Got {} webhook deliveries", deliveries.len());
+                    Ok(Response::new(DeliveriesResponse { deliveries }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    //** API KEYS **//
+
+    /// Issue an API key
+    async fn create_api_key(
+        &self,
+        request: Request<CreateApiKeyRequest>,
+    ) -> Result<Response<CreateApiKeyResponse>, Status> {
+        self.hooked("create_api_key", request, |request| async move {
+            in_default_namespace("create API keys").map_err(|err| *err)?;
+            let req = request.into_inner();
+
+            if let Some(unknown) = req
+                .namespaces
+                .iter()
+                .find(|ns| !self.namespaces.contains_key(&ns.to_ascii_lowercase()))
+            {
+                return Err(Status::invalid_argument(format!(
+                    "Unknown namespace: {unknown}"
+                )));
+            }
+
+            let principal = req.principal.trim().to_string();
+            if principal.is_empty() {
+                return Err(Status::invalid_argument(
+                    "An API key must be bound to a principal",
+                ));
+            }
+
+            let scope = req.scope().into();
+            let (key, secret) =
+                RegisteredApiKey::issue(req.name, scope, req.namespaces, principal, now())
+                    .map_err(Status::internal)?;
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::AddApiKey(key, tx), "create API key", rx)
+                .await?
+            {
+                DsResponse::SingleApiKey(key) => {
+                    println!(
+                        "Created API key {} for {} as {}",
+                        key.id, key.name, key.principal
+                    );
+                    Ok(Response::new(CreateApiKeyResponse {
+                        key: Some(key),
+                        secret,
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Revoke an API key
+    async fn revoke_api_key(
+        &self,
+        request: Request<RevokeApiKeyRequest>,
+    ) -> Result<Response<ApiKeyResponse>, Status> {
+        self.hooked("revoke_api_key", request, |request| async move {
+            in_default_namespace("revoke API keys").map_err(|err| *err)?;
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::RevokeApiKey(req.id, tx), "revoke API key", rx)
+                .await?
+            {
+                DsResponse::SingleApiKey(key) => {
+                    println!("Revoked API key {}", key.id);
+                    Ok(Response::new(ApiKeyResponse { key: Some(key) }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get the issued API keys
+    async fn get_api_keys(
+        &self,
+        request: Request<GetApiKeysRequest>,
+    ) -> Result<Response<MultiApiKeyResponse>, Status> {
+        self.hooked("get_api_keys", request, |_request| async move {
+            in_default_namespace("get API keys").map_err(|err| *err)?;
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetApiKeys(tx), "get API keys", rx)
+                .await?
+            {
+                DsResponse::MultipleApiKeys(keys) => {
+                    println!("Got {} API keys", keys.len());
+                    Ok(Response::new(MultiApiKeyResponse { keys }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
     }
 
     /// Make a decision an actor wanting to take an action on a target
@@ -549,26 +2160,1492 @@ impl Gatehouse for GatehouseSvc {
         &self,
         request: Request<CheckRequest>,
     ) -> Result<Response<CheckResponse>, Status> {
-        let req = request.into_inner();
-        let (tx, rx) = channel::<DsResponse>();
+        self.hooked("check", request, |request| async move {
+            let req = self.enrich_check(request).await?;
+            let (tx, rx) = channel::<DsResponse>();
+
+            if req.actor.is_none() {
+                return Err(Status::invalid_argument("Actor cannot be null"));
+            }
+
+            let fallback = &self.store().fallback;
+            let answer = self
+                .call_datastore_within(
+                    DsRequest::Check(req.clone(), tx),
+                    "perform check",
+                    rx,
+                    fallback.timeout,
+                )
+                .await;
 
-        if req.actor.is_none() {
-            return Err(Status::invalid_argument("Actor cannot be null"));
+            // a datastore that can't be reached may leave the check to the fallback
+            let answer = match answer {
+                Ok(answer) => answer,
+                Err(status) => match fallback.answer(&req) {
+                    Some(resp) => {
+                        eprintln!(
+                            "Datastore could not perform check, answering {:?}: {}",
+                            resp.source(),
+                            status.message()
+                        );
+                        DsResponse::CheckResult(resp)
+                    }
+                    None => return Err(status),
+                },
+            };
+            match answer {
+                DsResponse::CheckResult(resp) => {
+                    //TODO! -- add metrics
+                    println!(
+                        "Got decision: {} ({})",
+                        resp.decision(),
+                        resp.correlation_id
+                    );
+                    fallback.remember(&req, &resp);
+
+                    let mut response = Response::new(resp);
+                    if let Ok(val) = response.get_ref().correlation_id.parse() {
+                        response
+                            .metadata_mut()
+                            .insert(CORRELATION_METADATA_KEY, val);
+                    }
+                    Ok(response)
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Make a decision and return the evaluation trace of every policy
+    async fn trace_check(
+        &self,
+        request: Request<CheckRequest>,
+    ) -> Result<Response<TraceCheckResponse>, Status> {
+        self.hooked("trace_check", request, |request| async move {
+            let req = self.enrich_check(request).await?;
+            let (tx, rx) = channel::<DsResponse>();
+
+            if req.actor.is_none() {
+                return Err(Status::invalid_argument("Actor cannot be null"));
+            }
+
+            match self
+                .call_datastore(DsRequest::TraceCheck(req.clone(), tx), "trace check", rx)
+                .await?
+            {
+                DsResponse::TraceResult(trace) => {
+                    //TODO! -- add metrics
+                    println!("Traced {} policies", trace.policies.len());
+                    Ok(Response::new(trace))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Decide whether an actor may get an SSH certificate for a host, and what it may allow
+    async fn check_ssh_cert(
+        &self,
+        request: Request<SshCertRequest>,
+    ) -> Result<Response<SshCertResponse>, Status> {
+        self.hooked("check_ssh_cert", request, |request| async move {
+            let metadata = request.metadata().clone();
+            let req = request.into_inner();
+
+            if req.actor.is_none() {
+                return Err(Status::invalid_argument("Actor cannot be null"));
+            }
+            let mut check =
+                Request::new(ssh::check_request(&req).map_err(Status::invalid_argument)?);
+            *check.metadata_mut() = metadata;
+            let check = self.enrich_check(check).await?;
+
+            // the host may limit how long certificates are valid for
+            let (tx, rx) = channel::<DsResponse>();
+            let host = GetTargetsRequest {
+                name: Some(req.host.clone()),
+                typestr: Some(ssh::host_type(&req)),
+                ..Default::default()
+            };
+            let host = match self
+                .call_datastore(DsRequest::GetTargets(host, tx), "get host", rx)
+                .await?
+            {
+                DsResponse::MultipleTargets(targets, _) => targets.into_iter().next(),
+                DsResponse::Error(status) => return Err(status),
+                _ => return Err(Status::internal("Got unexpected answer from datastore")),
+            };
+
+            let (tx, rx) = channel::<DsResponse>();
+            match self
+                .call_datastore(DsRequest::Check(check, tx), "check ssh certificate", rx)
+                .await?
+            {
+                DsResponse::CheckResult(resp) => {
+                    let resp = ssh::response(req, resp, ssh::max_ttl(host.as_ref()));
+                    //TODO! -- add metrics
+                    println!(
+                        "Got SSH certificate decision: {} ({})",
+                        resp.decision(),
+                        resp.correlation_id
+                    );
+                    Ok(Response::new(resp))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Co-sign a check that is waiting for approval, on behalf of the caller
+    ///
+    /// Only an authenticated caller who is one of the configured approvers can co-sign, so
+    /// nobody can without approvers.
+    async fn approve(
+        &self,
+        request: Request<ApproveRequest>,
+    ) -> Result<Response<ApprovalResponse>, Status> {
+        self.hooked("approve", request, |request| async move {
+            if self.approvers.is_empty() {
+                return Err(Status::failed_precondition(
+                    "No approvers are configured to co-sign checks",
+                ));
+            }
+            if caller(&request).is_none() {
+                return Err(Status::unauthenticated(
+                    "Co-signing needs an authenticated caller",
+                ));
+            }
+            let approver = self
+                .approver(&request)
+                .ok_or_else(|| Status::permission_denied("Only approvers can do this"))?;
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(
+                    DsRequest::Approve(req.id, approver.clone(), tx),
+                    "approve check",
+                    rx,
+                )
+                .await?
+            {
+                DsResponse::SingleApproval(approval) => {
+                    println!("{} approved check {}", approver, approval.id);
+                    Ok(Response::new(ApprovalResponse {
+                        approval: Some(*approval),
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get the checks waiting for approval, and the approved checks that are still allowed
+    async fn get_approvals(
+        &self,
+        request: Request<GetApprovalsRequest>,
+    ) -> Result<Response<MultiApprovalResponse>, Status> {
+        self.hooked("get_approvals", request, |_request| async move {
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetApprovals(tx), "get approvals", rx)
+                .await?
+            {
+                DsResponse::MultipleApprovals(approvals) => {
+                    println!("Got {} approvals", approvals.len());
+                    Ok(Response::new(MultiApprovalResponse { approvals }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// In an emergency, allow an actor everything or bypass a deny policy for some minutes
+    async fn break_glass(
+        &self,
+        request: Request<BreakGlassRequest>,
+    ) -> Result<Response<BreakGlassResponse>, Status> {
+        self.hooked("break_glass", request, |request| async move {
+            let caller = caller(&request).ok_or_else(|| {
+                Status::unauthenticated("Breaking the glass needs an authenticated caller")
+            })?;
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::BreakGlass(req, caller, tx), "break glass", rx)
+                .await?
+            {
+                DsResponse::SingleBreakGlass(break_glass) => {
+                    Ok(Response::new(BreakGlassResponse {
+                        break_glass: Some(*break_glass),
+                    }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get the emergency access that hasn't ended
+    async fn get_break_glass(
+        &self,
+        request: Request<GetBreakGlassRequest>,
+    ) -> Result<Response<MultiBreakGlassResponse>, Status> {
+        self.hooked("get_break_glass", request, |_request| async move {
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetBreakGlass(tx), "get break glass", rx)
+                .await?
+            {
+                DsResponse::MultipleBreakGlasses(break_glasses) => {
+                    println!("Got {} break glasses", break_glasses.len());
+                    Ok(Response::new(MultiBreakGlassResponse { break_glasses }))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Decide on a check and, if every action is allowed, issue a signed grant token for it
+    async fn request_grant(
+        &self,
+        request: Request<GrantRequest>,
+    ) -> Result<Response<GrantResponse>, Status> {
+        self.hooked("request_grant", request, |request| async move {
+            let secret = self
+                .grant_secret
+                .clone()
+                .ok_or_else(|| Status::failed_precondition("Grants are not enabled"))?;
+            let metadata = request.metadata().clone();
+            let req = request.into_inner();
+
+            let mut check = Request::new(
+                req.check
+                    .ok_or_else(|| Status::invalid_argument("Check cannot be null"))?,
+            );
+            *check.metadata_mut() = metadata;
+            let check = self.enrich_check(check).await?;
+            let actor = check
+                .actor
+                .clone()
+                .ok_or_else(|| Status::invalid_argument("Actor cannot be null"))?;
+            if check.target_action.is_empty() {
+                return Err(Status::invalid_argument(
+                    "At least one action must be asked for",
+                ));
+            }
+            let mut grant = Grant {
+                actor_type: actor.typestr,
+                actor_name: actor.name,
+                target_type: check.target_type.clone(),
+                target_name: check.target_name.clone(),
+                actions: check.target_action.clone(),
+                ..Default::default()
+            };
+
+            let (tx, rx) = channel::<DsResponse>();
+            let resp = match self
+                .call_datastore(DsRequest::Check(check, tx), "check grant", rx)
+                .await?
+            {
+                DsResponse::CheckResult(resp) => resp,
+                DsResponse::Error(status) => return Err(status),
+                _ => return Err(Status::internal("Got unexpected answer from datastore")),
+            };
+            //TODO! -- add metrics
+            println!(
+                "Got grant decision: {} ({})",
+                resp.decision(),
+                resp.correlation_id
+            );
+            if resp.decision() != Decide::Allow {
+                return Ok(Response::new(GrantResponse {
+                    decision: resp.decision,
+                    correlation_id: resp.correlation_id,
+                    approval_id: resp.approval_id,
+                    ..Default::default()
+                }));
+            }
+
+            let ttl = match req.ttl_seconds {
+                0 => self.grant_ttl,
+                ttl => ttl.min(self.grant_ttl),
+            };
+            grant.issued_at = now();
+            grant.expires_at = grant.issued_at + u64::from(ttl);
+            let token = grant::issue(&secret, &grant).map_err(Status::internal)?;
+
+            Ok(Response::new(GrantResponse {
+                decision: resp.decision,
+                token,
+                grant: Some(grant),
+                correlation_id: resp.correlation_id,
+                approval_id: 0,
+            }))
+        })
+        .await
+    }
+
+    /// Verify a grant token, and that it allows what the request names
+    async fn verify_grant(
+        &self,
+        request: Request<VerifyGrantRequest>,
+    ) -> Result<Response<VerifyGrantResponse>, Status> {
+        self.hooked("verify_grant", request, |request| async move {
+            let secret = self
+                .grant_secret
+                .as_deref()
+                .ok_or_else(|| Status::failed_precondition("Grants are not enabled"))?;
+            let req = request.into_inner();
+
+            let grant = match grant::decode(&req.token, secret) {
+                Ok(grant) => grant,
+                Err(reason) => {
+                    return Ok(Response::new(VerifyGrantResponse {
+                        valid: false,
+                        reason,
+                        grant: None,
+                    }))
+                }
+            };
+            let reason = if grant.expires_at <= now() {
+                String::from("Grant has expired")
+            } else if !grant.allows(&req.target_type, &req.target_name, &req.action) {
+                String::from("Grant does not allow this")
+            } else {
+                String::new()
+            };
+
+            Ok(Response::new(VerifyGrantResponse {
+                valid: reason.is_empty(),
+                reason,
+                grant: Some(grant),
+            }))
+        })
+        .await
+    }
+
+    async fn coverage_report(
+        &self,
+        request: Request<CoverageReportRequest>,
+    ) -> Result<Response<CoverageReportResponse>, Status> {
+        self.hooked("coverage_report", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::CoverageReport(req, tx), "coverage report", rx)
+                .await?
+            {
+                DsResponse::CoverageResult(report) => {
+                    println!(
+                        "Coverage report: {} requests replayed, {} policies unmatched",
+                        report.requests,
+                        report.unmatched_policies.len()
+                    );
+                    Ok(Response::new(report))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    async fn what_if(
+        &self,
+        request: Request<WhatIfRequest>,
+    ) -> Result<Response<WhatIfResponse>, Status> {
+        self.hooked("what_if", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::WhatIf(req, tx), "what-if", rx)
+                .await?
+            {
+                DsResponse::WhatIfResult(result) => {
+                    println!(
+                        "What-if: {} of {} requests would change decision",
+                        result.changes.len(),
+                        result.requests
+                    );
+                    Ok(Response::new(result))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    async fn test_policies(
+        &self,
+        request: Request<TestPoliciesRequest>,
+    ) -> Result<Response<TestPoliciesResponse>, Status> {
+        self.hooked("test_policies", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::TestPolicies(req, tx), "test policies", rx)
+                .await?
+            {
+                DsResponse::PolicyTestResults(result) => {
+                    println!(
+                        "Policy tests: {} passed, {} failed",
+                        result.passed, result.failed
+                    );
+                    Ok(Response::new(result))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    //** HEALTH **//
+
+    /// Report the part this server plays, so clients can send changes to the leader
+    async fn health(
+        &self,
+        request: Request<HealthRequest>,
+    ) -> Result<Response<HealthResponse>, Status> {
+        self.hooked("health", request, |_request| async move {
+            let store = self.store();
+            let leadership = store.leadership.borrow().clone();
+            let role = match self.replica {
+                true => ServingRole::Replica,
+                false => leadership.role,
+            };
+
+            Ok(Response::new(HealthResponse {
+                role: role.into(),
+                leader: leadership.leader.unwrap_or_default(),
+                storage: Some(store.storage_health.status()),
+                // only the default store is replicated to another region
+                region: match std::ptr::eq(store, &self.store) {
+                    true => self.region.as_ref().map(|region| region.status()),
+                    false => None,
+                },
+                canaries: self.canaries.clone(),
+                ready: !self.canaries_required
+                    || self
+                        .canaries
+                        .as_ref()
+                        .is_some_and(|canaries| canaries.passed),
+            }))
+        })
+        .await
+    }
+
+    /// Get statistics about the server, including problems found with the data at startup
+    async fn get_server_stats(
+        &self,
+        request: Request<GetServerStatsRequest>,
+    ) -> Result<Response<GetServerStatsResponse>, Status> {
+        self.hooked("get_server_stats", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetServerStats(req, tx), "get server stats", rx)
+                .await?
+            {
+                DsResponse::ServerStats(stats) => Ok(Response::new(stats)),
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get the version of the server, how it was built, and what it holds
+    async fn get_server_info(
+        &self,
+        request: Request<GetServerInfoRequest>,
+    ) -> Result<Response<GetServerInfoResponse>, Status> {
+        self.hooked("get_server_info", request, |_request| async move {
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetServerInfo(tx), "get server info", rx)
+                .await?
+            {
+                DsResponse::ServerInfo(info) => Ok(Response::new(info)),
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get the policies that take longest to evaluate in checks
+    async fn get_policy_stats(
+        &self,
+        request: Request<GetPolicyStatsRequest>,
+    ) -> Result<Response<GetPolicyStatsResponse>, Status> {
+        self.hooked("get_policy_stats", request, |request| async move {
+            let req = request.into_inner();
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::GetPolicyStats(req, tx), "get policy stats", rx)
+                .await?
+            {
+                DsResponse::PolicyStats(stats) => Ok(Response::new(stats)),
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Drop what is held in memory and load everything again from the storage backend
+    async fn reload_state(
+        &self,
+        request: Request<ReloadStateRequest>,
+    ) -> Result<Response<ReloadStateResponse>, Status> {
+        self.hooked("reload_state", request, |_request| async move {
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::ReloadState(tx), "reload state", rx)
+                .await?
+            {
+                DsResponse::Reconciled(changed) => Ok(Response::new(ReloadStateResponse {
+                    changed: changed as u64,
+                })),
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    /// Get JSON Schemas for the documents the server reads and writes
+    async fn get_schemas(
+        &self,
+        request: Request<GetSchemasRequest>,
+    ) -> Result<Response<GetSchemasResponse>, Status> {
+        self.hooked("get_schemas", request, |request| async move {
+            let req = request.into_inner();
+            let found = match req.names.is_empty() {
+                true => schema::schemas(),
+                false => {
+                    let mut found = Vec::new();
+                    for name in req.names {
+                        match schema::schema(&name) {
+                            Some(schema) => found.push(schema),
+                            None => {
+                                return Err(Status::not_found(format!("No schema named {name}")))
+                            }
+                        }
+                    }
+                    found
+                }
+            };
+
+            let schemas = found
+                .into_iter()
+                .map(|schema| EntitySchema {
+                    name: schema.name.to_string(),
+                    description: schema.description.to_string(),
+                    schema: schema.schema.to_string(),
+                })
+                .collect();
+            Ok(Response::new(GetSchemasResponse { schemas }))
+        })
+        .await
+    }
+
+    //** REPLICATION **//
+
+    /// Get the full state of the server so a replica can start from it
+    async fn sync(&self, request: Request<SyncRequest>) -> Result<Response<SyncResponse>, Status> {
+        self.hooked("sync", request, |_request| async move {
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self.call_datastore(DsRequest::Sync(tx), "sync", rx).await? {
+                DsResponse::SyncResult(state) => {
+                    println!("Synced state at revision {}", state.revision);
+                    Ok(Response::new(*state))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+
+    type WatchStream = ReceiverStream<Result<WatchEvent, Status>>;
+
+    /// Stream every change made from now on
+    ///
+    /// A watcher that falls too far behind gets an error and should sync again.
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        self.hooked("watch", request, |_request| async move {
+            let (tx, rx) = channel::<DsResponse>();
+
+            let mut events = match self
+                .call_datastore(DsRequest::Watch(tx), "watch", rx)
+                .await?
+            {
+                DsResponse::Watcher(events) => events,
+                DsResponse::Error(status) => return Err(status),
+                _ => return Err(Status::internal("Got unexpected answer from datastore")),
+            };
+
+            let (stream_tx, stream_rx) = mpsc::channel(16);
+            tokio::spawn(async move {
+                loop {
+                    let msg = match events.recv().await {
+                        Ok(event) => Ok(event),
+                        Err(RecvError::Lagged(missed)) => Err(Status::data_loss(format!(
+                            "Missed {missed} changes; sync again"
+                        ))),
+                        Err(RecvError::Closed) => break,
+                    };
+
+                    let lagged = msg.is_err();
+                    if stream_tx.send(msg).await.is_err() || lagged {
+                        break;
+                    }
+                }
+            });
+
+            Ok(Response::new(ReceiverStream::new(stream_rx)))
+        })
+        .await
+    }
+
+    type StreamChangesStream = ReceiverStream<Result<ChangeEvent, Status>>;
+
+    /// Stream every change after a resume token, or after the call if there is none
+    async fn stream_changes(
+        &self,
+        request: Request<StreamChangesRequest>,
+    ) -> Result<Response<Self::StreamChangesStream>, Status> {
+        self.hooked("stream_changes", request, |request| async move {
+            let token = request.into_inner().resume_token;
+            let (tx, rx) = channel::<DsResponse>();
+
+            let (epoch, after, backlog, events) = match self
+                .call_datastore(DsRequest::StreamChanges(token, tx), "stream changes", rx)
+                .await?
+            {
+                DsResponse::Changes(epoch, after, backlog, events) => {
+                    (epoch, after, backlog, events)
+                }
+                DsResponse::Error(status) => return Err(status),
+                _ => return Err(Status::internal("Got unexpected answer from datastore")),
+            };
+
+            let (stream_tx, stream_rx) = mpsc::channel(16);
+            let dstx = self.store().dstx.clone();
+            tokio::spawn(send_changes(dstx, epoch, after, backlog, events, stream_tx));
+
+            Ok(Response::new(ReceiverStream::new(stream_rx)))
+        })
+        .await
+    }
+
+    /// Make changes replicated from a server in another region
+    async fn replicate(
+        &self,
+        request: Request<ReplicateRequest>,
+    ) -> Result<Response<ReplicateResponse>, Status> {
+        self.hooked("replicate", request, |request| async move {
+            // only replication keys get here, and their changes were already approved in their
+            // region, if they needed to be
+            let req = request.into_inner();
+
+            let (tx, rx) = channel::<DsResponse>();
+
+            match self
+                .call_datastore(DsRequest::Replicate(req, tx), "replicate changes", rx)
+                .await?
+            {
+                DsResponse::Replicated(result) => {
+                    println!(
+                        "Replicated changes: {} applied, {} unchanged, {} conflicts",
+                        result.applied, result.unchanged, result.conflicts
+                    );
+                    Ok(Response::new(result))
+                }
+                DsResponse::Error(status) => Err(status),
+                _ => Err(Status::internal("Got unexpected answer from datastore")),
+            }
+        })
+        .await
+    }
+}
+
+/// Refuse a call made in a namespace; API keys are only managed in the default one
+fn in_default_namespace(what: &str) -> Result<(), Box<Status>> {
+    match NAMESPACE.try_with(Clone::clone) {
+        Ok(Some(_)) => Err(Box::new(Status::invalid_argument(format!(
+            "Can only {what} in the default namespace"
+        )))),
+        _ => Ok(()),
+    }
+}
+
+/// What a policy change can affect, to log along with it
+fn describe_impact(impact: &Option<PolicyImpact>) -> String {
+    match impact {
+        Some(impact) if !impact.unknown_actions.is_empty() => format!(
+            "; can affect {} actors and {} targets of types [{}]; no registered target has the \
+             actions [{}]",
+            impact.actors,
+            impact.targets,
+            impact.target_types.join(", "),
+            impact.unknown_actions.join(", ")
+        ),
+        Some(impact) => format!(
+            "; can affect {} actors and {} targets of types [{}]",
+            impact.actors,
+            impact.targets,
+            impact.target_types.join(", ")
+        ),
+        None => String::new(),
+    }
+}
+
+/// Who made a request, if their API key or a request hook says
+fn caller<T>(request: &Request<T>) -> Option<String> {
+    request
+        .extensions()
+        .get::<Caller>()
+        .map(|caller| caller.0.clone())
+}
+
+/// Send changes down a change stream until the caller goes away
+///
+/// A stream that falls behind the watch buffer picks up from the changelog after the last change
+/// it sent, so the caller still gets every change once.
+async fn send_changes(
+    dstx: Sender<DsRequest>,
+    epoch: u64,
+    mut last: u64,
+    mut backlog: Vec<WatchEvent>,
+    mut events: broadcast::Receiver<WatchEvent>,
+    stream_tx: mpsc::Sender<Result<ChangeEvent, Status>>,
+) {
+    loop {
+        for event in backlog.drain(..) {
+            last = event.revision;
+            let event = ChangeEvent {
+                resume_token: resume_token(epoch, event.revision),
+                event: Some(event),
+            };
+            if stream_tx.send(Ok(event)).await.is_err() {
+                return;
+            }
         }
 
-        match self
-            .call_datastore(DsRequest::Check(req.clone(), tx), "perform check", rx)
-            .await?
+        match events.recv().await {
+            Ok(event) => {
+                if event.revision > last {
+                    backlog.push(event);
+                }
+                continue;
+            }
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return,
+        }
+
+        let (tx, rx) = channel::<DsResponse>();
+        let token = resume_token(epoch, last);
+        if dstx
+            .send_async(DsRequest::StreamChanges(token, tx))
+            .await
+            .is_err()
         {
-            DsResponse::CheckResult(decision) => {
-                //TODO! -- add metrics
-                println!("Got decision: {}", decision);
-                Ok(Response::new(CheckResponse {
-                    decision: decision.into(),
-                }))
+            return;
+        }
+        match rx.await {
+            Ok(DsResponse::Changes(_, _, missed, resubscribed)) => {
+                backlog = missed;
+                events = resubscribed;
             }
-            DsResponse::Error(status) => Err(status),
-            _ => Err(Status::internal("Got unexpected answer from datastore")),
+            Ok(DsResponse::Error(status)) => {
+                let _ = stream_tx.send(Err(status)).await;
+                return;
+            }
+            _ => return,
         }
     }
 }
+
+/// A new correlation id for a check that didn't come with one
+///
+/// The time we started makes ids from different runs unlikely to clash.
+fn new_correlation_id() -> String {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis())
+        .unwrap_or_default();
+    let count = CORRELATION_IDS.fetch_add(1, Ordering::Relaxed);
+    format!("gh-{started:x}-{count:x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::test;
+
+    use crate::proto::base::watch_event::Change;
+    use crate::proto::policies::PolicyRule;
+    use crate::proto::targets::AddTargetRequest;
+    use crate::secrets::Secret;
+
+    use super::*;
+
+    /// A request from a caller who was already authenticated
+    fn as_caller<T>(req: T, caller: &str) -> Request<T> {
+        let mut req = Request::new(req);
+        req.extensions_mut().insert(Caller(caller.to_string()));
+        req
+    }
+
+    #[test]
+    async fn test_correlation_id() {
+        let svc = GatehouseSvc::new(&StorageType::Nil).await;
+        let check = || CheckRequest {
+            actor: Some(crate::proto::actors::Actor {
+                name: String::from("kaitlyn"),
+                typestr: String::from("user"),
+                ..Default::default()
+            }),
+            target_name: String::from("db"),
+            target_type: String::from("database"),
+            ..Default::default()
+        };
+
+        // the id in the metadata comes back in the response and its metadata
+        let mut req = Request::new(check());
+        req.metadata_mut()
+            .insert(CORRELATION_METADATA_KEY, "abc-123".parse().unwrap());
+        let resp = svc.check(req).await.unwrap();
+        assert_eq!(resp.get_ref().correlation_id, "abc-123");
+        assert_eq!(
+            resp.metadata().get(CORRELATION_METADATA_KEY).unwrap(),
+            "abc-123"
+        );
+
+        // otherwise every check gets its own
+        let first = svc.check(Request::new(check())).await.unwrap();
+        let second = svc.check(Request::new(check())).await.unwrap();
+        assert!(!first.get_ref().correlation_id.is_empty());
+        assert_ne!(
+            first.get_ref().correlation_id,
+            second.get_ref().correlation_id
+        );
+    }
+
+    #[test]
+    async fn test_proposals() {
+        let config = Config {
+            approvers: vec![String::from("alice")],
+            ..Default::default()
+        };
+        let svc = GatehouseSvc::with_config(&StorageType::Nil, config).await;
+
+        let policies = || async {
+            svc.get_policies(Request::new(GetPoliciesRequest::default()))
+                .await
+                .unwrap()
+                .into_inner()
+                .rules
+                .len()
+        };
+        let rule = |name: &str| PolicyRule {
+            name: String::from(name),
+            ..Default::default()
+        };
+
+        // bob's change waits for approval
+        let req = AddPolicyRequest {
+            rule: Some(rule("allow-bob")),
+            ..Default::default()
+        };
+        let resp = svc.add_policy(as_caller(req, "bob")).await.unwrap();
+        let proposal_id = resp.get_ref().proposal_id;
+        assert_ne!(proposal_id, 0);
+        assert_eq!(policies().await, 0);
+
+        let proposals = svc
+            .list_proposals(Request::new(ListProposalsRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .proposals;
+        assert_eq!(proposals.len(), 1);
+        assert_eq!(proposals[0].proposer, "bob");
+
+        // only approvers can approve it
+        let req = ApproveProposalRequest { id: proposal_id };
+        let status = svc
+            .approve_proposal(as_caller(req, "bob"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        let req = ApproveProposalRequest { id: proposal_id };
+        svc.approve_proposal(as_caller(req, "alice")).await.unwrap();
+        assert_eq!(policies().await, 1);
+
+        // an approver's change is made right away
+        let req = AddPolicyRequest {
+            rule: Some(rule("allow-alice")),
+            ..Default::default()
+        };
+        let resp = svc.add_policy(as_caller(req, "alice")).await.unwrap();
+        assert_eq!(resp.get_ref().proposal_id, 0);
+        assert_eq!(policies().await, 2);
+
+        // and a rejected change is dropped
+        let req = RemovePolicyRequest {
+            name: String::from("allow-alice"),
+            ..Default::default()
+        };
+        let resp = svc.remove_policy(Request::new(req)).await.unwrap();
+        let req = RejectProposalRequest {
+            id: resp.get_ref().proposal_id,
+        };
+        svc.reject_proposal(as_caller(req, "alice")).await.unwrap();
+        assert_eq!(policies().await, 2);
+
+        let proposals = svc
+            .list_proposals(Request::new(ListProposalsRequest {}))
+            .await
+            .unwrap();
+        assert!(proposals.get_ref().proposals.is_empty());
+    }
+
+    #[test]
+    async fn test_approve() {
+        let req = || ApproveRequest { id: 1 };
+
+        // without approvers, nobody can co-sign
+        let svc = GatehouseSvc::new(&StorageType::Nil).await;
+        let status = svc.approve(as_caller(req(), "alice")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let config = Config {
+            approvers: vec![String::from("alice")],
+            ..Default::default()
+        };
+        let svc = GatehouseSvc::with_config(&StorageType::Nil, config).await;
+        let status = svc.approve(Request::new(req())).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        let status = svc.approve(as_caller(req(), "bob")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        // an approver gets as far as finding there is nothing to co-sign
+        let status = svc.approve(as_caller(req(), "alice")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[test]
+    async fn test_may_delegate() {
+        let config = Config {
+            approvers: vec![String::from("carol")],
+            ..Default::default()
+        };
+        let svc = GatehouseSvc::with_config(&StorageType::Nil, config).await;
+        let delegation = Delegation {
+            delegator_type: String::from("user"),
+            delegator_name: String::from("alice"),
+            ..Default::default()
+        };
+        let may = |caller: &str| svc.may_delegate(&Some(caller.to_string()), &delegation);
+
+        // the delegator is named as type/name, matched exactly
+        assert!(may("user/alice"));
+        assert!(!may("alice"));
+        assert!(!may("service/alice"));
+        assert!(!may("user/Alice"));
+        // approvers can manage anyone's delegations
+        assert!(may("carol"));
+        assert!(!svc.may_delegate(&None, &delegation));
+    }
+
+    fn with_key<T>(req: T, key: &str) -> Request<T> {
+        let mut req = Request::new(req);
+        req.metadata_mut()
+            .insert(API_KEY_METADATA_KEY, key.parse().unwrap());
+        req
+    }
+
+    #[test]
+    async fn test_api_key_principals() {
+        let config = Config {
+            api_keys_required: true,
+            bootstrap_api_key: Some(Secret::from("ghk_0123_secret")),
+            bootstrap_principal: String::from("alice"),
+            approvers: vec![String::from("alice")],
+            ..Default::default()
+        };
+        let svc = GatehouseSvc::with_config(&StorageType::Nil, config).await;
+        let create = |principal: &str| CreateApiKeyRequest {
+            name: String::from("ops"),
+            scope: crate::proto::base::ApiKeyScope::Admin.into(),
+            principal: String::from(principal),
+            ..Default::default()
+        };
+
+        // nothing, not even issuing a key, can be done without one
+        let status = svc
+            .create_api_key(Request::new(create("bob")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        // every key is bound to someone
+        let status = svc
+            .create_api_key(with_key(create(""), "ghk_0123_secret"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let bobs_key = svc
+            .create_api_key(with_key(create("bob"), "ghk_0123_secret"))
+            .await
+            .unwrap()
+            .into_inner()
+            .secret;
+
+        // bob's key can't pass for alice, so the change waits for approval
+        let req = AddPolicyRequest {
+            rule: Some(PolicyRule {
+                name: String::from("allow-bob"),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut req = with_key(req, &bobs_key);
+        req.metadata_mut()
+            .insert(CALLER_METADATA_KEY, "alice".parse().unwrap());
+        let resp = svc.add_policy(req).await.unwrap();
+        assert_ne!(resp.get_ref().proposal_id, 0);
+
+        let req = AddPolicyRequest {
+            rule: Some(PolicyRule {
+                name: String::from("allow-alice"),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let resp = svc
+            .add_policy(with_key(req, "ghk_0123_secret"))
+            .await
+            .unwrap();
+        assert_eq!(resp.get_ref().proposal_id, 0);
+    }
+
+    #[test]
+    async fn test_replicate_needs_replication_key() {
+        let config = Config {
+            bootstrap_api_key: Some(Secret::from("ghk_0123_secret")),
+            ..Default::default()
+        };
+        let svc = GatehouseSvc::with_config(&StorageType::Nil, config).await;
+
+        // even where keys aren't required, and even an admin key won't do
+        let status = svc
+            .replicate(Request::new(ReplicateRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        let status = svc
+            .replicate(with_key(ReplicateRequest::default(), "ghk_0123_secret"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        let req = CreateApiKeyRequest {
+            name: String::from("eu-west"),
+            scope: crate::proto::base::ApiKeyScope::Replication.into(),
+            principal: String::from("region/eu-west"),
+            ..Default::default()
+        };
+        let key = svc
+            .create_api_key(with_key(req, "ghk_0123_secret"))
+            .await
+            .unwrap()
+            .into_inner()
+            .secret;
+        svc.replicate(with_key(ReplicateRequest::default(), &key))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    async fn test_hooks() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+
+        let svc = GatehouseSvc::new(&StorageType::Nil)
+            .await
+            .with_request_hook(|_, metadata| match metadata.get("authorization") {
+                Some(_) => Ok(()),
+                None => Err(Box::new(Status::unauthenticated("No credentials"))),
+            })
+            .with_request_hook(|_, metadata| {
+                metadata.insert(CALLER_METADATA_KEY, "kaitlyn".parse().unwrap());
+                Ok(())
+            })
+            .with_response_hook(move |method, outcome, _| {
+                if let Ok(metadata) = outcome {
+                    metadata.insert("x-served-by", "test".parse().unwrap());
+                }
+                recorded.lock().unwrap().push(method.to_string());
+            });
+
+        // the first hook rejects the call before it is handled
+        let err = svc
+            .get_targets(Request::new(GetTargetsRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        assert!(seen.lock().unwrap().is_empty());
+
+        let mut req = Request::new(GetTargetsRequest::default());
+        req.metadata_mut()
+            .insert("authorization", "Bearer token".parse().unwrap());
+        let resp = svc.get_targets(req).await.unwrap();
+        assert_eq!(resp.metadata().get("x-served-by").unwrap(), "test");
+        assert_eq!(*seen.lock().unwrap(), vec![String::from("get_targets")]);
+    }
+
+    fn in_namespace<T>(req: T, namespace: &str) -> Request<T> {
+        let mut req = Request::new(req);
+        req.metadata_mut()
+            .insert(NAMESPACE_METADATA_KEY, namespace.parse().unwrap());
+        req
+    }
+
+    #[test]
+    async fn test_namespaces() {
+        let config = Config {
+            namespaces: vec![String::from("staging")],
+            ..Default::default()
+        };
+        let svc = GatehouseSvc::with_config(&StorageType::Nil, config).await;
+
+        let target = || AddTargetRequest {
+            name: String::from("db"),
+            typestr: String::from("database"),
+            ..Default::default()
+        };
+
+        svc.add_target(in_namespace(target(), "Staging"))
+            .await
+            .unwrap();
+
+        // each namespace has its own store
+        let targets = svc
+            .get_targets(Request::new(GetTargetsRequest::default()))
+            .await
+            .unwrap();
+        assert!(targets.into_inner().targets.is_empty());
+        let targets = svc
+            .get_targets(in_namespace(GetTargetsRequest::default(), "staging"))
+            .await
+            .unwrap();
+        assert_eq!(targets.into_inner().targets.len(), 1);
+
+        let err = svc
+            .add_target(in_namespace(target(), "prod"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+    }
+
+    #[test]
+    async fn test_check_ssh_cert() {
+        use crate::proto::common::AttributeValues;
+        use crate::proto::policies::{AddPolicyRequest, Decide, Set, StringCheck, TargetCheck};
+
+        let svc = GatehouseSvc::new(&StorageType::Nil).await;
+        svc.add_target(Request::new(AddTargetRequest {
+            name: String::from("web1"),
+            typestr: String::from("host"),
+            attributes: HashMap::from([(
+                String::from("ssh-max-ttl"),
+                AttributeValues {
+                    values: vec![String::from("600")],
+                },
+            )]),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        let rule = PolicyRule {
+            name: String::from("deploy-on-hosts"),
+            target_check: Some(TargetCheck {
+                typestr: Some(StringCheck {
+                    val_cmp: Set::Has.into(),
+                    vals: vec![String::from("host")],
+                }),
+                action: Some(StringCheck {
+                    val_cmp: Set::Has.into(),
+                    vals: vec![
+                        String::from("principal:deploy"),
+                        String::from("option:permit-pty"),
+                    ],
+                }),
+                ..Default::default()
+            }),
+            decision: Decide::Allow.into(),
+            ..Default::default()
+        };
+        svc.add_policy(Request::new(AddPolicyRequest {
+            rule: Some(rule),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+
+        let ask = |principals: &[&str], ttl_seconds| SshCertRequest {
+            actor: Some(crate::proto::actors::Actor {
+                name: String::from("kaitlyn"),
+                typestr: String::from("user"),
+                ..Default::default()
+            }),
+            host: String::from("web1"),
+            principals: principals.iter().map(|p| p.to_string()).collect(),
+            options: vec![
+                String::from("permit-pty"),
+                String::from("permit-port-forwarding"),
+            ],
+            ttl_seconds,
+            ..Default::default()
+        };
+
+        // only what is allowed is granted, for no longer than the host allows
+        let resp = svc
+            .check_ssh_cert(Request::new(ask(&["deploy", "root"], 0)))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.decision(), Decide::Allow);
+        assert_eq!(resp.principals, vec!["deploy"]);
+        assert_eq!(resp.options, vec!["permit-pty"]);
+        assert_eq!(resp.ttl_seconds, 600);
+        let resp = svc
+            .check_ssh_cert(Request::new(ask(&["deploy"], 60)))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.ttl_seconds, 60);
+
+        let resp = svc
+            .check_ssh_cert(Request::new(ask(&["root"], 0)))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.decision(), Decide::Deny);
+        assert!(resp.principals.is_empty());
+        assert_eq!(resp.ttl_seconds, 0);
+
+        assert!(svc.check_ssh_cert(Request::new(ask(&[], 0))).await.is_err());
+    }
+
+    #[test]
+    async fn test_grants() {
+        use crate::proto::policies::{AddPolicyRequest, Set, StringCheck, TargetCheck};
+
+        let config = Config {
+            grant_secret: Some(Secret::from("secret")),
+            ..Default::default()
+        };
+        let svc = GatehouseSvc::with_config(&StorageType::Nil, config).await;
+        let rule = PolicyRule {
+            name: String::from("read-databases"),
+            target_check: Some(TargetCheck {
+                action: Some(StringCheck {
+                    val_cmp: Set::Has.into(),
+                    vals: vec![String::from("read")],
+                }),
+                ..Default::default()
+            }),
+            decision: Decide::Allow.into(),
+            ..Default::default()
+        };
+        svc.add_policy(Request::new(AddPolicyRequest {
+            rule: Some(rule),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+
+        let ask = |action: &str| GrantRequest {
+            check: Some(CheckRequest {
+                actor: Some(crate::proto::actors::Actor {
+                    name: String::from("kaitlyn"),
+                    typestr: String::from("user"),
+                    ..Default::default()
+                }),
+                target_name: String::from("db"),
+                target_type: String::from("database"),
+                target_action: vec![action.to_string()],
+                ..Default::default()
+            }),
+            ttl_seconds: 60,
+        };
+
+        let resp = svc
+            .request_grant(Request::new(ask("read")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.decision(), Decide::Allow);
+        let grant = resp.grant.unwrap();
+        assert_eq!(grant.expires_at, grant.issued_at + 60);
+
+        // the token can be verified here, or offline with the secret
+        let verify = |token: &str, action: &str| VerifyGrantRequest {
+            token: token.to_string(),
+            target_type: String::from("database"),
+            target_name: String::from("db"),
+            action: action.to_string(),
+        };
+        let verified = svc
+            .verify_grant(Request::new(verify(&resp.token, "read")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(verified.valid);
+        assert_eq!(verified.grant, Some(grant.clone()));
+        assert_eq!(grant::verify(&resp.token, "secret").unwrap(), grant);
+
+        let verified = svc
+            .verify_grant(Request::new(verify(&resp.token, "write")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!verified.valid);
+        let verified = svc
+            .verify_grant(Request::new(verify("not.a-grant.token", "read")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!verified.valid);
+        assert!(verified.grant.is_none());
+
+        let resp = svc
+            .request_grant(Request::new(ask("write")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.decision(), Decide::NotApplicable);
+        assert!(resp.token.is_empty());
+
+        // without a secret there are no grants
+        let svc = GatehouseSvc::new(&StorageType::Nil).await;
+        assert!(svc.request_grant(Request::new(ask("read"))).await.is_err());
+    }
+
+    #[test]
+    async fn test_stream_changes() {
+        let svc = GatehouseSvc::new(&StorageType::Nil).await;
+
+        let open = |resume_token: &str| {
+            svc.stream_changes(Request::new(StreamChangesRequest {
+                resume_token: resume_token.to_string(),
+            }))
+        };
+        let add = |name: &str| {
+            svc.add_target(Request::new(AddTargetRequest {
+                name: name.to_string(),
+                typestr: String::from("database"),
+                ..Default::default()
+            }))
+        };
+        let target_name = |event: &ChangeEvent| match event.event.as_ref().unwrap().change {
+            Some(Change::PutTarget(ref target)) => target.name.clone(),
+            _ => panic!("expected a target"),
+        };
+
+        let mut stream = open("").await.unwrap().into_inner().into_inner();
+        add("first").await.unwrap();
+        let first = stream.recv().await.unwrap().unwrap();
+        assert_eq!(target_name(&first), "first");
+
+        // a change made while disconnected is picked up after the last one received
+        drop(stream);
+        add("second").await.unwrap();
+        let mut stream = open(&first.resume_token)
+            .await
+            .unwrap()
+            .into_inner()
+            .into_inner();
+        let second = stream.recv().await.unwrap().unwrap();
+        assert_eq!(target_name(&second), "second");
+        add("third").await.unwrap();
+        let third = stream.recv().await.unwrap().unwrap();
+        assert_eq!(target_name(&third), "third");
+
+        let err = open("nonsense").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        // a token from another run of the server can't be resumed
+        let err = open("1.1").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::OutOfRange);
+    }
+
+    #[test]
+    async fn test_standby() {
+        let mut svc = GatehouseSvc::new(&StorageType::Nil).await;
+
+        let health = svc.health(Request::new(HealthRequest {})).await.unwrap();
+        assert_eq!(health.into_inner().role(), ServingRole::Standalone);
+
+        // a standby serves reads but points changes at the leader
+        let (_leadership_tx, leadership) = watch::channel(Leadership {
+            role: ServingRole::Standby,
+            leader: Some(String::from("gate-1")),
+        });
+        svc.store.leadership = leadership;
+
+        let health = svc.health(Request::new(HealthRequest {})).await.unwrap();
+        assert_eq!(health.get_ref().role(), ServingRole::Standby);
+        assert_eq!(health.get_ref().leader, "gate-1");
+
+        let req = AddTargetRequest {
+            name: String::from("db"),
+            typestr: String::from("database"),
+            ..Default::default()
+        };
+        let status = svc.add_target(Request::new(req)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert!(status.message().contains("gate-1"));
+
+        let targets = svc
+            .get_targets(Request::new(GetTargetsRequest::default()))
+            .await
+            .unwrap();
+        assert!(targets.into_inner().targets.is_empty());
+    }
+}
@@ -0,0 +1,328 @@
+#![warn(missing_docs)]
+
+//! Periodic synchronization of groups from an LDAP/Active Directory server
+//!
+//! Only what is needed to read group memberships is done: a bind followed by a subtree search
+//! for each mapped group. The bind is anonymous, a simple bind with a DN and password, or a SASL
+//! EXTERNAL bind. Credentials are only sent over TLS, either an `ldaps://` url or StartTLS.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::time::Duration;
+
+use ldap3::{ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use serde::Deserialize;
+
+use crate::group::{RegisteredGroup, RegisteredGroupMember};
+use crate::intern::intern;
+use crate::msgs::DsRequest;
+use crate::secrets::Secret;
+
+/// the source groups synced from LDAP are marked as managed by
+pub(crate) const SOURCE: &str = "ldap";
+
+/// How to connect to the LDAP server and which groups to pull from it
+#[derive(Debug, Clone, Deserialize)]
+pub struct LdapConfig {
+    /// address of the server, as `host:port`, `ldap://host:port`, `ldaps://host:port`, or
+    /// `ldapi://` and a socket path
+    pub url: String,
+    /// whether to upgrade an `ldap://` connection to TLS with StartTLS before binding
+    #[serde(default)]
+    pub starttls: bool,
+    /// DN to bind as with a simple bind; the bind is anonymous if not set
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    /// password for the bind DN, or a reference to where it is kept
+    #[serde(default)]
+    pub bind_password: Option<Secret>,
+    /// whether to make a SASL EXTERNAL bind, taking the identity the connection already has,
+    /// instead of a simple bind
+    #[serde(default)]
+    pub sasl_external: bool,
+    /// where to search for groups
+    pub base_dn: String,
+    /// attribute that names a group (default `cn`)
+    #[serde(default = "default_group_attribute")]
+    pub group_attribute: String,
+    /// attribute that lists the members of a group (default `member`)
+    #[serde(default = "default_member_attribute")]
+    pub member_attribute: String,
+    /// the type given to synced group members (default `user`)
+    #[serde(default = "default_member_type")]
+    pub member_type: String,
+    /// seconds between syncs (default 300)
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// the LDAP groups to sync
+    pub groups: Vec<LdapGroupMapping>,
+}
+
+/// Maps an LDAP group onto a Gatehouse group
+#[derive(Debug, Clone, Deserialize)]
+pub struct LdapGroupMapping {
+    /// name of the group in LDAP
+    pub ldap_group: String,
+    /// name of the Gatehouse group
+    pub group: String,
+    /// optional description of the Gatehouse group
+    #[serde(default)]
+    pub desc: Option<String>,
+    /// roles granted to members of the Gatehouse group
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+fn default_group_attribute() -> String {
+    String::from("cn")
+}
+
+fn default_member_attribute() -> String {
+    String::from("member")
+}
+
+fn default_member_type() -> String {
+    String::from("user")
+}
+
+fn default_interval_secs() -> u64 {
+    300
+}
+
+impl LdapConfig {
+    /// Load the sync configuration from a JSON file
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read LDAP config {path}: {err}"))?;
+
+        let config: Self = serde_json::from_str(&contents)
+            .map_err(|err| format!("Could not parse LDAP config {path}: {err}"))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Make sure the config never sends credentials in the clear
+    fn validate(&self) -> Result<(), String> {
+        let url = self.url();
+        if self.starttls && !url.starts_with("ldap://") {
+            return Err(String::from("starttls only applies to ldap:// urls"));
+        }
+        if self.bind_dn.is_some() != self.bind_password.is_some() {
+            return Err(String::from(
+                "bind_dn and bind_password must be set together; leave both unset to bind \
+                 anonymously",
+            ));
+        }
+        if self.sasl_external && self.bind_dn.is_some() {
+            return Err(String::from(
+                "A SASL EXTERNAL bind takes no bind_dn or bind_password",
+            ));
+        }
+
+        // a local socket never leaves the host
+        let secure = url.starts_with("ldaps://") || url.starts_with("ldapi://") || self.starttls;
+        if self.bind_dn.is_some() && !secure {
+            return Err(String::from(
+                "A simple bind would send the password in the clear; use an ldaps:// url or set \
+                 starttls",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The url to connect to, assuming plain LDAP if there is no scheme
+    fn url(&self) -> String {
+        if self.url.contains("://") {
+            self.url.clone()
+        } else {
+            format!("ldap://{}", self.url)
+        }
+    }
+}
+
+impl Display for LdapConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({} groups every {}s)",
+            self.url,
+            self.groups.len(),
+            self.interval_secs
+        )
+    }
+}
+
+/// Sync groups from LDAP in the background, forever
+pub(crate) fn spawn(config: LdapConfig, dstx: flume::Sender<DsRequest>) {
+    tokio::spawn(async move {
+        loop {
+            match sync(&config, &dstx).await {
+                Ok(changed) => println!("LDAP sync complete: {changed} groups changed"),
+                Err(err) => eprintln!("LDAP sync failed: {err}"),
+            }
+
+            tokio::time::sleep(Duration::from_secs(config.interval_secs)).await;
+        }
+    });
+}
+
+/// Pull the mapped groups and have the datastore reconcile them
+async fn sync(config: &LdapConfig, dstx: &flume::Sender<DsRequest>) -> Result<usize, String> {
+    let groups = tokio::time::timeout(Duration::from_secs(30), fetch_groups(config))
+        .await
+        .map_err(|_| String::from("Timeout talking to LDAP server"))??;
+
+    super::reconcile_groups(dstx, SOURCE, groups).await
+}
+
+/// Read the members of every mapped group from LDAP
+///
+/// Any failure aborts the whole fetch so that a partial result never removes groups.
+async fn fetch_groups(config: &LdapConfig) -> Result<Vec<RegisteredGroup>, String> {
+    let ldap_err = |op: &str, err: ldap3::LdapError| format!("LDAP {op} failed: {err}");
+
+    let settings = LdapConnSettings::new().set_starttls(config.starttls);
+    let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &config.url())
+        .await
+        .map_err(|err| format!("Could not connect to {}: {err}", config.url))?;
+    tokio::spawn(async move {
+        if let Err(err) = conn.drive().await {
+            eprintln!("LDAP connection failed: {err}");
+        }
+    });
+
+    let bound = match (&config.bind_dn, &config.bind_password) {
+        _ if config.sasl_external => ldap.sasl_external_bind().await,
+        (Some(dn), Some(password)) => ldap.simple_bind(dn, password.expose()).await,
+        _ => ldap.simple_bind("", "").await,
+    };
+    bound
+        .and_then(|res| res.success())
+        .map_err(|err| ldap_err("bind", err))?;
+
+    let member_type = config.member_type.to_ascii_lowercase();
+
+    let mut groups = Vec::new();
+    for mapping in &config.groups {
+        let (entries, _) = ldap
+            .search(
+                &config.base_dn,
+                Scope::Subtree,
+                &filter(&config.group_attribute, &mapping.ldap_group),
+                vec![config.member_attribute.as_str()],
+            )
+            .await
+            .and_then(|res| res.success())
+            .map_err(|err| ldap_err("search", err))?;
+
+        // search result references are not followed
+        let members: HashSet<RegisteredGroupMember> = entries
+            .into_iter()
+            .map(SearchEntry::construct)
+            .flat_map(|entry| member_values(entry, &config.member_attribute))
+            .map(|val| RegisteredGroupMember {
+                name: member_name(&val),
+                typestr: intern(&member_type),
+            })
+            .collect();
+
+        let mut group = RegisteredGroup::new(
+            &mapping.group,
+            mapping.desc.clone(),
+            members,
+            mapping
+                .roles
+                .iter()
+                .map(|r| r.to_ascii_lowercase())
+                .collect(),
+        );
+        group.managed_by = Some(SOURCE.to_string());
+        groups.push(group);
+    }
+
+    let _ = ldap.unbind().await;
+
+    Ok(groups)
+}
+
+/// A filter for entries where `attr` equals `val`
+fn filter(attr: &str, val: &str) -> String {
+    format!("({}={})", ldap_escape(attr), ldap_escape(val))
+}
+
+/// Every value of an attribute of a search result entry, whatever case the server names it in
+fn member_values(entry: SearchEntry, wanted: &str) -> Vec<String> {
+    entry
+        .attrs
+        .into_iter()
+        .filter(|(attr, _)| attr.eq_ignore_ascii_case(wanted))
+        .flat_map(|(_, vals)| vals)
+        .collect()
+}
+
+/// Members may be listed by DN (`uid=jdoe,ou=people,...`), in which case the value of the first
+/// RDN is used as the name
+fn member_name(val: &str) -> String {
+    let first_rdn = val.split(',').next().unwrap_or(val);
+    let name = match first_rdn.split_once('=') {
+        Some((_, name)) => name,
+        None => first_rdn,
+    };
+
+    name.trim().to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let config = |json: &str| serde_json::from_str::<LdapConfig>(json).unwrap();
+
+        let anonymous = config(r#"{"url": "ldap://ldap:389", "base_dn": "dc=x", "groups": []}"#);
+        assert!(anonymous.validate().is_ok());
+
+        let bound = |url: &str, starttls: bool| {
+            config(&format!(
+                r#"{{"url": "{url}", "starttls": {starttls}, "bind_dn": "cn=gatehouse",
+                    "bind_password": "secret", "base_dn": "dc=x", "groups": []}}"#
+            ))
+        };
+
+        // a password only goes over TLS or a local socket
+        assert!(bound("ldap://ldap:389", false).validate().is_err());
+        assert!(bound("ldap:389", false).validate().is_err());
+        assert!(bound("ldap://ldap:389", true).validate().is_ok());
+        assert!(bound("ldaps://ldap:636", false).validate().is_ok());
+        assert!(bound("ldapi://%2Fvar%2Frun%2Fslapd%2Fldapi", false)
+            .validate()
+            .is_ok());
+        assert!(bound("ldaps://ldap:636", true).validate().is_err());
+
+        let no_password = config(
+            r#"{"url": "ldaps://ldap:636", "bind_dn": "cn=gatehouse", "base_dn": "dc=x",
+                "groups": []}"#,
+        );
+        assert!(no_password.validate().is_err());
+
+        let external = config(
+            r#"{"url": "ldaps://ldap:636", "sasl_external": true, "base_dn": "dc=x",
+                "groups": []}"#,
+        );
+        assert!(external.validate().is_ok());
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(filter("cn", "admins"), "(cn=admins)");
+        assert_eq!(filter("cn", "a*)(uid=*"), "(cn=a\\2a\\29\\28uid=\\2a)");
+    }
+
+    #[test]
+    fn test_member_name() {
+        assert_eq!(member_name("uid=Kaitlyn,ou=people,dc=example"), "kaitlyn");
+        assert_eq!(member_name("CN=Hank Hill,OU=Users"), "hank hill");
+        assert_eq!(member_name("brandy"), "brandy");
+    }
+}
@@ -0,0 +1,32 @@
+#![warn(missing_docs)]
+
+//! Synchronization of data from external systems into Gatehouse
+
+use tokio::sync::oneshot::channel;
+
+use crate::group::RegisteredGroup;
+use crate::msgs::{DsRequest, DsResponse};
+
+pub mod ldap;
+
+/// Hand the full set of groups from an external source to the datastore to reconcile
+///
+/// Returns the number of groups that were added, changed, or removed.
+pub(crate) async fn reconcile_groups(
+    dstx: &flume::Sender<DsRequest>,
+    source: &str,
+    groups: Vec<RegisteredGroup>,
+) -> Result<usize, String> {
+    let (tx, rx) = channel::<DsResponse>();
+
+    dstx.send_async(DsRequest::SyncGroups(source.to_string(), groups, tx))
+        .await
+        .map_err(|err| format!("Could not reach datastore: {err}"))?;
+
+    match rx.await {
+        Ok(DsResponse::GroupsSynced(changed)) => Ok(changed),
+        Ok(DsResponse::Error(status)) => Err(status.message().to_string()),
+        Ok(_) => Err(String::from("Got unexpected answer from datastore")),
+        Err(err) => Err(err.to_string()),
+    }
+}
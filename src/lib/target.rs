@@ -6,16 +6,30 @@ use serde::{Deserialize, Serialize};
 
 use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
+use std::sync::Arc;
 
+use crate::attribute::{self, AttributeMap};
+use crate::intern;
+use crate::policy::{lowercase, TargetAction};
 use crate::proto::common::AttributeValues;
-use crate::proto::targets::Target;
+use crate::proto::targets::{ActionGroup, Target};
+use crate::timestamps::Timestamps;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct RegisteredTarget {
     pub name: String,
-    pub typestr: String,
+    /// interned, so targets of a type share one copy of it
+    #[serde(deserialize_with = "intern::deserialize")]
+    pub typestr: Arc<str>,
     pub actions: HashSet<String>,
-    pub attributes: HashMap<String, HashSet<String>>,
+    /// shared, so checks can use them without a copy
+    #[serde(deserialize_with = "attribute::deserialize")]
+    pub attributes: Arc<AttributeMap>,
+    #[serde(default)]
+    pub action_groups: HashMap<String, HashSet<String>>,
+    /// when the target was added and last changed
+    #[serde(flatten)]
+    pub timestamps: Timestamps,
 }
 
 impl From<Target> for RegisteredTarget {
@@ -25,16 +39,18 @@ impl From<Target> for RegisteredTarget {
             actions.insert(action.to_ascii_lowercase());
         }
 
-        let mut attributes = HashMap::new();
-        for kv in tgt.attributes {
-            attributes.insert(kv.0, HashSet::from_iter(kv.1.values));
-        }
+        let attributes = attribute::from_request(tgt.attributes);
 
         Self {
             name: tgt.name.to_ascii_lowercase(),
-            typestr: tgt.typestr.to_ascii_lowercase(),
+            typestr: intern::intern(&lowercase(&tgt.typestr)),
             actions,
-            attributes,
+            attributes: Arc::new(attributes),
+            action_groups: action_groups(tgt.action_groups),
+            timestamps: Timestamps {
+                created_at: tgt.created_at,
+                updated_at: tgt.updated_at,
+            },
         }
     }
 }
@@ -42,20 +58,32 @@ impl From<Target> for RegisteredTarget {
 impl From<RegisteredTarget> for Target {
     fn from(target: RegisteredTarget) -> Self {
         let mut attributes = HashMap::new();
-        for kv in target.attributes {
+        for kv in target.attributes.iter() {
             attributes.insert(
-                kv.0,
+                kv.0.to_string(),
                 AttributeValues {
                     values: kv.1.iter().map(|v| v.to_string()).collect(),
                 },
             );
         }
 
+        let action_groups = target
+            .action_groups
+            .into_iter()
+            .map(|(name, actions)| {
+                let actions = actions.into_iter().collect();
+                (name, ActionGroup { actions })
+            })
+            .collect();
+
         Self {
             name: target.name,
-            typestr: target.typestr,
+            typestr: target.typestr.to_string(),
             actions: target.actions.iter().map(|a| a.to_string()).collect(),
             attributes,
+            action_groups,
+            created_at: target.timestamps.created_at,
+            updated_at: target.timestamps.updated_at,
         }
     }
 }
@@ -77,9 +105,23 @@ impl Display for RegisteredTarget {
             })
             .collect::<Vec<String>>()
             .join("; ");
+        let groupvals = self
+            .action_groups
+            .iter()
+            .map(|kv| {
+                format!(
+                    " // {}:: {}",
+                    kv.0,
+                    kv.1.iter()
+                        .map(|v| v.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            })
+            .collect::<String>();
         write!(
             f,
-            "tgt[{}/{}]: actions:: {} // attribs:: {}",
+            "tgt[{}/{}]: actions:: {} // attribs:: {}{}",
             self.typestr,
             self.name,
             self.actions
@@ -87,7 +129,8 @@ impl Display for RegisteredTarget {
                 .map(|s| s.to_string())
                 .collect::<Vec<String>>()
                 .join(", "),
-            attribvals
+            attribvals,
+            groupvals
         )
     }
 }
@@ -97,7 +140,8 @@ impl RegisteredTarget {
         name: &str,
         typestr: &str,
         actions: Vec<String>,
-        attributes: HashMap<String, HashSet<String>>,
+        attributes: AttributeMap,
+        groups: HashMap<String, ActionGroup>,
     ) -> Self {
         let mut actions_set = HashSet::new();
 
@@ -107,9 +151,69 @@ impl RegisteredTarget {
 
         RegisteredTarget {
             name: name.to_string(),
-            typestr: typestr.to_string(),
+            typestr: intern::intern(typestr),
             actions: actions_set,
-            attributes,
+            attributes: Arc::new(attributes),
+            action_groups: action_groups(groups),
+            timestamps: Timestamps::default(),
+        }
+    }
+
+    /// Resolve an action named in a check
+    ///
+    /// `*` stands for every action of the target and the name of an action group stands for
+    /// every action in the group; anything else is a single action. Each action comes with the
+    /// names of the groups that include it.
+    pub(crate) fn resolve_action(&self, action: &str) -> Vec<TargetAction> {
+        let lowered = lowercase(action);
+
+        let mut actions: Vec<&String> = if lowered == "*" {
+            self.actions.iter().collect()
+        } else if let Some(group) = self.action_groups.get(lowered.as_ref()) {
+            group.iter().collect()
+        } else {
+            return vec![self.action(action)];
+        };
+        actions.sort();
+
+        if actions.is_empty() {
+            // nothing to expand to, so we check the action as it was given
+            return vec![self.action(action)];
         }
+
+        actions.into_iter().map(|a| self.action(a)).collect()
     }
+
+    /// Get a single action along with the groups that include it
+    fn action(&self, name: &str) -> TargetAction {
+        let lowered = lowercase(name);
+        let groups = self
+            .action_groups
+            .iter()
+            .filter(|(_, actions)| actions.contains(lowered.as_ref()))
+            .map(|(group, _)| group.clone())
+            .collect();
+
+        TargetAction {
+            name: lowered.into_owned(),
+            groups,
+        }
+    }
+}
+
+/// Normalize action groups from a request
+pub(crate) fn action_groups(
+    groups: HashMap<String, ActionGroup>,
+) -> HashMap<String, HashSet<String>> {
+    groups
+        .into_iter()
+        .map(|(name, group)| {
+            let actions = group
+                .actions
+                .iter()
+                .map(|a| a.to_ascii_lowercase())
+                .collect();
+            (name.to_ascii_lowercase(), actions)
+        })
+        .collect()
 }
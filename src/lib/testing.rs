@@ -0,0 +1,334 @@
+#![warn(missing_docs)]
+
+//! Fixtures for testing against a real Gatehouse server
+//!
+//! A [`TestHarness`] runs the server in-process on a random local port with nothing stored, so
+//! crates that embed or call Gatehouse can write integration tests without building and
+//! spawning the server binary. The builders put together the actors, targets, and checks those
+//! tests need; [`crate::model::PolicyRuleBuilder`] puts together policies.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), String> {
+//! use gatehouse::model::{PolicyRuleBuilder, StringCheck, TargetCheckBuilder};
+//! use gatehouse::testing::{ActorBuilder, CheckBuilder, TestHarness};
+//!
+//! let harness = TestHarness::start().await?;
+//! let read = StringCheck::OneOf(vec!["read".to_string()]);
+//! let rule = PolicyRuleBuilder::new("readers")
+//!     .target(TargetCheckBuilder::new().action(read).build())
+//!     .build();
+//! harness.add_policy(rule).await?;
+//!
+//! let actor = ActorBuilder::new("user", "kaitlyn").build();
+//! let check = CheckBuilder::new(actor, "database", "db").action("read").build();
+//! assert!(harness.check(check).await?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+
+use crate::config::Config;
+use crate::proto::actors::{Actor, AddActorRequest};
+use crate::proto::base::gatehouse_client::GatehouseClient;
+use crate::proto::base::gatehouse_server::GatehouseServer;
+use crate::proto::base::CheckRequest;
+use crate::proto::common::AttributeValues;
+use crate::proto::policies::{AddPolicyRequest, Decide, PolicyRule};
+use crate::proto::targets::{AddTargetRequest, Target};
+use crate::svc::GatehouseSvc;
+use crate::StorageType;
+
+/// A Gatehouse server running in-process for the length of a test
+///
+/// The server keeps nothing between runs and stops when the harness is dropped.
+pub struct TestHarness {
+    addr: SocketAddr,
+    client: GatehouseClient<Channel>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl TestHarness {
+    /// Start a server with the default configuration
+    pub async fn start() -> Result<Self, String> {
+        Self::with_config(Config::default()).await
+    }
+
+    /// Start a server with the given configuration
+    pub async fn with_config(config: Config) -> Result<Self, String> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .map_err(|err| format!("Could not bind a port: {err}"))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|err| format!("Could not get the bound address: {err}"))?;
+
+        let svc = Arc::new(GatehouseSvc::with_config(&StorageType::Nil, config).await);
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let served = Server::builder()
+                .add_service(GatehouseServer::from_arc(svc))
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                    let _ = stopped.await;
+                })
+                .await;
+            if let Err(err) = served {
+                eprintln!("Test server failed: {err}");
+            }
+        });
+
+        let client = GatehouseClient::connect(format!("http://{addr}"))
+            .await
+            .map_err(|err| format!("Could not connect to the test server: {err}"))?;
+
+        Ok(Self {
+            addr,
+            client,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    /// The address the server listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A client connected to the server
+    pub fn client(&self) -> GatehouseClient<Channel> {
+        self.client.clone()
+    }
+
+    /// Register a target
+    pub async fn add_target(&self, target: Target) -> Result<(), String> {
+        let req = AddTargetRequest {
+            name: target.name,
+            typestr: target.typestr,
+            actions: target.actions,
+            attributes: target.attributes,
+            action_groups: target.action_groups,
+            ..Default::default()
+        };
+        self.client()
+            .add_target(req)
+            .await
+            .map_err(|err| format!("Failed to add target: {err}"))?;
+        Ok(())
+    }
+
+    /// Register an actor
+    pub async fn add_actor(&self, actor: Actor) -> Result<(), String> {
+        let req = AddActorRequest {
+            name: actor.name,
+            typestr: actor.typestr,
+            attributes: actor.attributes,
+            ..Default::default()
+        };
+        self.client()
+            .add_actor(req)
+            .await
+            .map_err(|err| format!("Failed to add actor: {err}"))?;
+        Ok(())
+    }
+
+    /// Add a policy, e.g. one put together with a [`crate::model::PolicyRuleBuilder`]
+    pub async fn add_policy(&self, rule: impl Into<PolicyRule>) -> Result<(), String> {
+        let req = AddPolicyRequest {
+            rule: Some(rule.into()),
+            dry_run: false,
+        };
+        self.client()
+            .add_policy(req)
+            .await
+            .map_err(|err| format!("Failed to add policy: {err}"))?;
+        Ok(())
+    }
+
+    /// Make a check, returning whether it was allowed
+    pub async fn check(&self, req: CheckRequest) -> Result<bool, String> {
+        let resp = self
+            .client()
+            .check(req)
+            .await
+            .map_err(|err| format!("Failed to check: {err}"))?;
+        Ok(resp.get_ref().decision() == Decide::Allow)
+    }
+}
+
+impl Drop for TestHarness {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+/// Attribute values from a list of strings
+fn values(vals: &[&str]) -> AttributeValues {
+    AttributeValues {
+        values: vals.iter().map(|v| v.to_string()).collect(),
+    }
+}
+
+/// Builds an actor
+#[derive(Debug, Clone)]
+pub struct ActorBuilder {
+    actor: Actor,
+}
+
+impl ActorBuilder {
+    /// Start an actor with a type and name
+    pub fn new(typestr: &str, name: &str) -> Self {
+        Self {
+            actor: Actor {
+                name: name.to_string(),
+                typestr: typestr.to_string(),
+                attributes: HashMap::new(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Set an attribute
+    pub fn attribute(mut self, key: &str, vals: &[&str]) -> Self {
+        self.actor.attributes.insert(key.to_string(), values(vals));
+        self
+    }
+
+    /// The actor
+    pub fn build(self) -> Actor {
+        self.actor
+    }
+}
+
+/// Builds a target
+#[derive(Debug, Clone)]
+pub struct TargetBuilder {
+    target: Target,
+}
+
+impl TargetBuilder {
+    /// Start a target with a type and name
+    pub fn new(typestr: &str, name: &str) -> Self {
+        Self {
+            target: Target {
+                name: name.to_string(),
+                typestr: typestr.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Add actions
+    pub fn actions(mut self, actions: &[&str]) -> Self {
+        self.target
+            .actions
+            .extend(actions.iter().map(|a| a.to_string()));
+        self
+    }
+
+    /// Set an attribute
+    pub fn attribute(mut self, key: &str, vals: &[&str]) -> Self {
+        self.target.attributes.insert(key.to_string(), values(vals));
+        self
+    }
+
+    /// The target
+    pub fn build(self) -> Target {
+        self.target
+    }
+}
+
+/// Builds a check request
+#[derive(Debug, Clone)]
+pub struct CheckBuilder {
+    req: CheckRequest,
+}
+
+impl CheckBuilder {
+    /// Start a check of an actor on a target
+    pub fn new(actor: Actor, target_type: &str, target_name: &str) -> Self {
+        Self {
+            req: CheckRequest {
+                actor: Some(actor),
+                target_type: target_type.to_string(),
+                target_name: target_name.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Check an action; can be called again to check several
+    pub fn action(mut self, action: &str) -> Self {
+        self.req.target_action.push(action.to_string());
+        self
+    }
+
+    /// Set an environment attribute
+    pub fn env(mut self, key: &str, vals: &[&str]) -> Self {
+        self.req
+            .env_attributes
+            .insert(key.to_string(), values(vals));
+        self
+    }
+
+    /// The check request
+    pub fn build(self) -> CheckRequest {
+        self.req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::test;
+
+    use super::*;
+    use crate::model::{KvCheck, PolicyRuleBuilder, StringCheck, TargetCheckBuilder};
+
+    #[test]
+    async fn test_harness() {
+        let harness = TestHarness::start().await.unwrap();
+        let other = TestHarness::start().await.unwrap();
+        assert_ne!(harness.addr(), other.addr());
+
+        harness
+            .add_target(
+                TargetBuilder::new("database", "db")
+                    .actions(&["read", "write"])
+                    .build(),
+            )
+            .await
+            .unwrap();
+        let read = StringCheck::OneOf(vec![String::from("read")]);
+        let rule = PolicyRuleBuilder::new("readers")
+            .actor_attribute(KvCheck::Has(
+                String::from("role"),
+                vec![String::from("reader")],
+            ))
+            .target(TargetCheckBuilder::new().action(read).build())
+            .build();
+        harness.add_policy(rule).await.unwrap();
+
+        let reader = ActorBuilder::new("user", "kaitlyn")
+            .attribute("role", &["reader"])
+            .build();
+        let read = CheckBuilder::new(reader.clone(), "database", "db").action("read");
+        assert!(harness.check(read.build()).await.unwrap());
+        let write = CheckBuilder::new(reader, "database", "db").action("write");
+        assert!(!harness.check(write.build()).await.unwrap());
+
+        // each harness has its own server
+        let read = CheckBuilder::new(
+            ActorBuilder::new("user", "kaitlyn").build(),
+            "database",
+            "db",
+        );
+        assert!(!other.check(read.action("read").build()).await.unwrap());
+    }
+}
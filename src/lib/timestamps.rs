@@ -0,0 +1,60 @@
+#![warn(missing_docs)]
+
+//! When stored entities were added and last changed
+
+use serde::{Deserialize, Serialize};
+
+/// When an entity was added and last changed, in seconds since the epoch; both are 0 for
+/// entities stored before they were recorded
+///
+/// They describe the entity rather than being part of it, so two entities that differ only in
+/// when they were changed are still equal.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timestamps {
+    /// when the entity was added
+    pub created_at: u64,
+    /// when the entity last changed
+    pub updated_at: u64,
+}
+
+impl Timestamps {
+    /// Record a change made at `now`; an entity without a `created_at`, because it is new or was
+    /// stored before they were recorded, is taken to be added then
+    pub(crate) fn touch(&mut self, now: u64) {
+        if self.created_at == 0 {
+            self.created_at = now;
+        }
+        self.updated_at = now;
+    }
+}
+
+impl PartialEq for Timestamps {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Timestamps {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_touch() {
+        let mut stamps = Timestamps::default();
+        stamps.touch(100);
+        assert_eq!((stamps.created_at, stamps.updated_at), (100, 100));
+
+        stamps.touch(250);
+        assert_eq!((stamps.created_at, stamps.updated_at), (100, 250));
+    }
+
+    #[test]
+    fn test_equality_ignores_stamps() {
+        let mut later = Timestamps::default();
+        later.touch(100);
+        assert_eq!(later, Timestamps::default());
+    }
+}
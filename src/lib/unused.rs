@@ -0,0 +1,74 @@
+#![warn(missing_docs)]
+
+//! A background job that looks for actors, targets, and roles nothing uses
+//!
+//! Every hour, the job asks the datastore for entities that nothing refers to and that haven't
+//! been checked or changed in the configured number of days, and logs them. If removal is
+//! turned on, it removes them as well, so the dataset doesn't grow without bound.
+
+use flume::Sender;
+use tokio::sync::oneshot::channel;
+use tokio::time::{sleep, Duration};
+
+use crate::msgs::{DsRequest, DsResponse};
+use crate::proto::base::{FindUnusedRequest, UnusedEntity};
+use crate::replica::call;
+
+/// how often to look for unused entities
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Look for unused entities in the background until the datastore goes away
+pub(crate) fn spawn(days: u32, remove: bool, dstx: Sender<DsRequest>) {
+    tokio::spawn(async move {
+        while !dstx.is_disconnected() {
+            sleep(INTERVAL).await;
+
+            match sweep(days, remove, &dstx).await {
+                Ok(entities) if entities.is_empty() => {}
+                Ok(entities) => {
+                    let verb = match remove {
+                        true => "Removed",
+                        false => "Found",
+                    };
+                    println!(
+                        "{verb} {} entities unused for {days} days: {}",
+                        entities.len(),
+                        describe(&entities)
+                    );
+                }
+                Err(err) => eprintln!("Looking for unused entities failed: {err}"),
+            }
+        }
+    });
+}
+
+/// Find, and maybe remove, the unused entities
+async fn sweep(
+    days: u32,
+    remove: bool,
+    dstx: &Sender<DsRequest>,
+) -> Result<Vec<UnusedEntity>, String> {
+    let req = FindUnusedRequest { days };
+    let (tx, rx) = channel::<DsResponse>();
+    let req = match remove {
+        true => DsRequest::RemoveUnused(req, tx),
+        false => DsRequest::FindUnused(req, tx),
+    };
+
+    match call(dstx, req, rx).await? {
+        DsResponse::Unused(resp) => Ok(resp.entities),
+        _ => Err(String::from("Got unexpected answer from datastore")),
+    }
+}
+
+/// List entities as `kind typestr/name`
+fn describe(entities: &[UnusedEntity]) -> String {
+    entities
+        .iter()
+        .map(|entity| match entity.typestr.is_empty() {
+            true => format!("{} {}", entity.kind, entity.name),
+            false => format!("{} {}/{}", entity.kind, entity.typestr, entity.name),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
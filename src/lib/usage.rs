@@ -0,0 +1,180 @@
+#![warn(missing_docs)]
+
+//! Tracking of how often registered actors, targets, and roles are checked
+//!
+//! Only registered entities are tracked, and an entity stops being tracked when it is removed,
+//! so memory is bounded by how much is registered. With a sample rate of N, only one in every N
+//! checks is recorded, and each recorded check counts N times; timestamps can then be off by
+//! however long N checks take. Usage is kept in memory only, so it starts over with the server.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+use crate::ds::now;
+use crate::proto::common::CheckHits;
+
+/// the most entities tracked, in case far more are registered than quotas would normally allow
+const MAX_TRACKED: usize = 1_000_000;
+
+/// How an entity has been used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Hits {
+    /// when the entity was last checked or changed, in seconds since the epoch
+    pub last_used: u64,
+    /// when the entity was last checked, in seconds since the epoch; 0 if it hasn't been
+    pub last_checked: u64,
+    /// about how many checks the entity has been in
+    pub checks: u64,
+}
+
+impl From<Hits> for CheckHits {
+    fn from(hits: Hits) -> Self {
+        CheckHits {
+            checks: hits.checks,
+            last_checked: hits.last_checked,
+        }
+    }
+}
+
+/// How registered entities have been used, by usage key
+#[derive(Debug)]
+pub(crate) struct Usage {
+    sample_rate: u64,
+    /// every check made, sampled or not
+    checks: AtomicU64,
+    hits: RwLock<HashMap<String, Hits>>,
+}
+
+impl Usage {
+    /// Track usage, recording one in every `sample_rate` checks; 0 records every check
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: u64::from(sample_rate.max(1)),
+            checks: AtomicU64::new(0),
+            hits: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The key usage of an actor, target, or role is tracked under
+    pub(crate) fn key(kind: &str, typestr: &str, name: &str) -> String {
+        let mut key = String::new();
+        write_key(&mut key, kind, typestr, name);
+        key
+    }
+
+    /// Count a check, returning whether it should be recorded
+    pub(crate) fn sample(&self) -> bool {
+        self.checks
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample_rate)
+    }
+
+    /// How many checks have been made
+    pub(crate) fn checks(&self) -> u64 {
+        self.checks.load(Ordering::Relaxed)
+    }
+
+    /// How many checks each recorded one stands for
+    pub(crate) fn sample_rate(&self) -> u64 {
+        self.sample_rate
+    }
+
+    /// Record that entities, as (kind, typestr, name), were in a sampled check
+    ///
+    /// Keys are built in one buffer, so only entities seen for the first time allocate.
+    pub(crate) async fn checked(&self, entities: &[(&str, &str, &str)]) {
+        let now = now();
+        let mut hits = self.hits.write().await;
+        let mut key = String::new();
+        for (kind, typestr, name) in entities {
+            key.clear();
+            write_key(&mut key, kind, typestr, name);
+            if !hits.contains_key(&key) && entry(&mut hits, key.clone()).is_none() {
+                continue;
+            }
+            if let Some(entry) = hits.get_mut(&key) {
+                entry.last_used = now;
+                entry.last_checked = now;
+                entry.checks += self.sample_rate;
+            }
+        }
+    }
+
+    /// Record that an entity was added or changed
+    pub(crate) async fn changed(&self, key: String) {
+        if let Some(entry) = entry(&mut *self.hits.write().await, key) {
+            entry.last_used = now();
+        }
+    }
+
+    /// Stop tracking an entity that was removed
+    pub(crate) async fn removed(&self, key: &str) {
+        self.hits.write().await.remove(key);
+    }
+
+    /// The usage of every tracked entity
+    pub(crate) async fn read(&self) -> RwLockReadGuard<'_, HashMap<String, Hits>> {
+        self.hits.read().await
+    }
+
+    /// Pretend every tracked entity was last used at a time
+    #[cfg(test)]
+    pub(crate) async fn backdate(&self, to: u64) {
+        for entry in self.hits.write().await.values_mut() {
+            entry.last_used = to;
+        }
+    }
+}
+
+/// The entry for an entity, unless it is new and we already track as many as we can
+/// Append the lowercase key of an entity to a buffer
+fn write_key(key: &mut String, kind: &str, typestr: &str, name: &str) {
+    key.reserve(kind.len() + typestr.len() + name.len() + 2);
+    for part in [kind, "/", typestr, "/", name] {
+        key.extend(part.chars().map(|c| c.to_ascii_lowercase()));
+    }
+}
+
+fn entry(hits: &mut HashMap<String, Hits>, key: String) -> Option<&mut Hits> {
+    if hits.len() >= MAX_TRACKED && !hits.contains_key(&key) {
+        return None;
+    }
+    Some(hits.entry(key).or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::test;
+
+    use super::*;
+
+    #[test]
+    async fn test_sampling() {
+        let usage = Usage::new(3);
+        let key = Usage::key("actor", "User", "Alice");
+        assert_eq!(key, "actor/user/alice");
+
+        for _ in 0..6 {
+            if usage.sample() {
+                usage.checked(&[("actor", "User", "Alice")]).await;
+            }
+        }
+        assert_eq!(usage.checks(), 6);
+
+        // two checks were recorded, each standing for three
+        let hits = usage.read().await[&key];
+        assert_eq!(hits.checks, 6);
+        assert!(hits.last_checked > 0);
+
+        usage.removed(&key).await;
+        assert!(usage.read().await.is_empty());
+
+        // a change is a use, but not a check
+        usage.changed(key.clone()).await;
+        let hits = usage.read().await[&key];
+        assert!(hits.last_used > 0);
+        assert_eq!(hits.last_checked, 0);
+    }
+}
@@ -0,0 +1,386 @@
+#![warn(missing_docs)]
+
+//! Validation rules for attribute values
+//!
+//! Rules are kept per attribute key, separately for actors and targets, in a JSON file:
+//!
+//! ```json
+//! {
+//!     "actors": {
+//!         "email": {"pattern": "[a-z0-9._-]+@example\\.com"},
+//!         "region": {"one_of": ["us", "emea", "apac"]}
+//!     },
+//!     "targets": {
+//!         "tier": {"min": 0, "max": 3}
+//!     }
+//! }
+//! ```
+//!
+//! A rule can have a `pattern` every value must match as a whole, a `one_of` list of the only
+//! values allowed, and a `min` and `max` every value must be a number between. Attributes without
+//! a rule take any value. Writes of actors and targets with values that break a rule are refused
+//! with an `INVALID_ARGUMENT` error whose details are an `AttributeViolations` message listing
+//! every value that was refused and why.
+//!
+//! Actors can never be given the attributes Gatehouse sets itself, `member-of`, `has-role`, and
+//! `risk`, whether or not there are rules.
+//!
+//! Patterns are regular expressions in the syntax of the `regex` crate. They always match the
+//! whole value, so `^` and `$` are optional.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use prost::Message;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use tonic::{Code, Status};
+
+use crate::attribute;
+use crate::proto::common::{AttributeValues, AttributeViolation, AttributeViolations};
+
+/// The validation rules for attribute values, by the key of the attribute
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttributeRules {
+    /// rules for the attributes of actors
+    #[serde(default, deserialize_with = "rules")]
+    pub actors: HashMap<String, AttributeRule>,
+    /// rules for the attributes of targets
+    #[serde(default, deserialize_with = "rules")]
+    pub targets: HashMap<String, AttributeRule>,
+}
+
+/// Read rules by key, naming the key of a rule that can't be read, such as one whose pattern
+/// does not compile
+fn rules<'de, D>(deserializer: D) -> Result<HashMap<String, AttributeRule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, serde_json::Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, rule)| match AttributeRule::deserialize(rule) {
+            Ok(rule) => Ok((key, rule)),
+            Err(err) => Err(serde::de::Error::custom(format!(
+                "the rule for {key}: {err}"
+            ))),
+        })
+        .collect()
+}
+
+impl AttributeRules {
+    /// Read the rules from a JSON file
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read attribute rules {path}: {err}"))?;
+
+        let rules: Self = serde_json::from_str(&contents)
+            .map_err(|err| format!("Could not parse attribute rules {path}: {err}"))?;
+        for (key, rule) in rules.actors.iter().chain(&rules.targets) {
+            if let (Some(min), Some(max)) = (rule.min, rule.max) {
+                if min > max {
+                    return Err(format!(
+                        "Bad attribute rules {path}: the min of {key} is more than its max"
+                    ));
+                }
+            }
+        }
+
+        Ok(rules)
+    }
+
+    /// The values of actor attributes given in a field that break their rules, or that Gatehouse
+    /// sets itself
+    pub fn check_actor(
+        &self,
+        field: &str,
+        attributes: &HashMap<String, AttributeValues>,
+    ) -> Vec<AttributeViolation> {
+        let mut violations = check(&self.actors, field, attributes);
+
+        // even an empty list of values would leave the key set
+        let none = [String::new()];
+        for (key, vals) in attributes
+            .iter()
+            .filter(|(key, _)| attribute::is_system(key))
+        {
+            let values = match vals.values.is_empty() {
+                true => &none[..],
+                false => &vals.values[..],
+            };
+            violations.extend(values.iter().map(|value| AttributeViolation {
+                field: format!("{field}.{key}"),
+                value: value.clone(),
+                problem: String::from("is set by Gatehouse and can't be given"),
+            }));
+        }
+
+        violations.sort_by(|a, b| (&a.field, &a.value).cmp(&(&b.field, &b.value)));
+        violations
+    }
+
+    /// The values of target attributes given in a field that break their rules
+    pub fn check_target(
+        &self,
+        field: &str,
+        attributes: &HashMap<String, AttributeValues>,
+    ) -> Vec<AttributeViolation> {
+        check(&self.targets, field, attributes)
+    }
+}
+
+impl Display for AttributeRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} actor keys, {} target keys",
+            self.actors.len(),
+            self.targets.len()
+        )
+    }
+}
+
+fn check(
+    rules: &HashMap<String, AttributeRule>,
+    field: &str,
+    attributes: &HashMap<String, AttributeValues>,
+) -> Vec<AttributeViolation> {
+    let mut violations = Vec::new();
+    for (key, vals) in attributes {
+        let Some(rule) = rules.get(key) else {
+            continue;
+        };
+        for value in &vals.values {
+            if let Some(problem) = rule.problem(value) {
+                violations.push(AttributeViolation {
+                    field: format!("{field}.{key}"),
+                    value: value.clone(),
+                    problem,
+                });
+            }
+        }
+    }
+
+    // a stable order, so errors read the same every time
+    violations.sort_by(|a, b| (&a.field, &a.value).cmp(&(&b.field, &b.value)));
+    violations
+}
+
+/// Describe values that broke their rules in one line
+pub fn describe(violations: &[AttributeViolation]) -> String {
+    violations
+        .iter()
+        .map(|violation| {
+            format!(
+                "{}: \"{}\" {}",
+                violation.field, violation.value, violation.problem
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The error for values that broke their rules, with the violations in its details
+pub fn violations_status(violations: Vec<AttributeViolation>) -> Status {
+    let message = format!(
+        "Attributes break their validation rules: {}",
+        describe(&violations)
+    );
+    let details = AttributeViolations { violations }.encode_to_vec();
+    Status::with_details(Code::InvalidArgument, message, details.into())
+}
+
+/// What a value must be to be given for an attribute
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttributeRule {
+    /// if set, every value must match this pattern as a whole
+    #[serde(default)]
+    pub pattern: Option<Pattern>,
+    /// if not empty, the only values allowed
+    #[serde(default)]
+    pub one_of: Vec<String>,
+    /// if set, every value must be a number at least this
+    #[serde(default)]
+    pub min: Option<f64>,
+    /// if set, every value must be a number at most this
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+impl AttributeRule {
+    /// What is wrong with a value, if anything
+    pub fn problem(&self, value: &str) -> Option<String> {
+        if let Some(pattern) = &self.pattern {
+            if !pattern.matches(value) {
+                return Some(format!("does not match {pattern}"));
+            }
+        }
+
+        if !self.one_of.is_empty() && !self.one_of.iter().any(|allowed| allowed == value) {
+            return Some(format!("is not one of {}", self.one_of.join(", ")));
+        }
+
+        if self.min.is_some() || self.max.is_some() {
+            let Some(number) = value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite())
+            else {
+                return Some(String::from("is not a number"));
+            };
+            if let Some(min) = self.min.filter(|min| number < *min) {
+                return Some(format!("is less than {min}"));
+            }
+            if let Some(max) = self.max.filter(|max| number > *max) {
+                return Some(format!("is more than {max}"));
+            }
+        }
+
+        None
+    }
+}
+
+/// A pattern that values must match as a whole
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    /// Compile a pattern
+    pub fn new(source: &str) -> Result<Self, String> {
+        let regex = Regex::new(&format!("^(?:{source})$"))
+            .map_err(|err| format!("Bad pattern {source}: {err}"))?;
+
+        Ok(Self {
+            source: source.to_string(),
+            regex,
+        })
+    }
+
+    /// Whether the whole value matches the pattern
+    pub fn matches(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Self::new(&source)
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        let matches = |pattern: &str, value: &str| Pattern::new(pattern).unwrap().matches(value);
+
+        assert!(matches(
+            "[a-z0-9._-]+@example\\.com",
+            "kaitlyn.b@example.com"
+        ));
+        assert!(!matches(
+            "[a-z0-9._-]+@example\\.com",
+            "kaitlyn@exampleXcom"
+        ));
+        assert!(matches("^(dev|prod)-\\d{2,3}$", "prod-042"));
+        assert!(!matches("(dev|prod)-\\d{2,3}", "qa-04"));
+        assert!(!matches("(dev|prod)-\\d{2,3}", "dev-0421"));
+        assert!(matches("[^,]*", "a b"));
+        assert!(matches("a?b*", ""));
+        assert!(!matches("(a*)*c", "aaaa"));
+
+        // the whole value must match, not just part of it
+        assert!(!matches("us", "us-east"));
+        assert!(!matches("dev|prod", "prod-1"));
+
+        // patterns that would backtrack forever match in linear time
+        assert!(!matches("(a|a)*b", &"a".repeat(40)));
+
+        for bad in ["(a", "a)", "[a-", "*a", "a{3,1}", "\\q", "[z-a]"] {
+            assert!(Pattern::new(bad).is_err(), "{bad} should be refused");
+        }
+    }
+
+    #[test]
+    fn test_attribute_rules() {
+        let rules: AttributeRules = serde_json::from_str(
+            r#"{
+                "actors": {
+                    "region": {"one_of": ["us", "emea"]},
+                    "email": {"pattern": "\\w+@example\\.com"}
+                },
+                "targets": {"tier": {"min": 0, "max": 3}}
+            }"#,
+        )
+        .unwrap();
+
+        let attributes = |key: &str, values: &[&str]| {
+            HashMap::from([(
+                key.to_string(),
+                AttributeValues {
+                    values: values.iter().map(|v| v.to_string()).collect(),
+                },
+            )])
+        };
+
+        assert!(rules
+            .check_actor("attributes", &attributes("region", &["us", "emea"]))
+            .is_empty());
+        let violations = rules.check_actor("attributes", &attributes("region", &["us", "mars"]));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].field, "attributes.region");
+        assert_eq!(violations[0].value, "mars");
+        assert_eq!(violations[0].problem, "is not one of us, emea");
+
+        let violations =
+            rules.check_target("add_attributes", &attributes("tier", &["2", "7", "x"]));
+        assert_eq!(
+            describe(&violations),
+            "add_attributes.tier: \"7\" is more than 3; add_attributes.tier: \"x\" is not a number"
+        );
+
+        // rules are per kind of entity, and keys without a rule take anything
+        assert!(rules
+            .check_target("attributes", &attributes("region", &["mars"]))
+            .is_empty());
+
+        // the attributes Gatehouse sets can't be given to actors, even without rules
+        let violations = AttributeRules::default()
+            .check_actor("attributes", &attributes("has-role", &["admin"]));
+        assert_eq!(
+            describe(&violations),
+            "attributes.has-role: \"admin\" is set by Gatehouse and can't be given"
+        );
+
+        // patterns are compiled when the rules are read, naming the rule that is bad
+        let err = serde_json::from_str::<AttributeRules>(
+            r#"{"actors": {"email": {"pattern": "(oops"}}}"#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("the rule for email: Bad pattern (oops"));
+    }
+}
@@ -0,0 +1,163 @@
+#![warn(missing_docs)]
+
+//! Tracking of how often actors use actions, for policies with rate checks
+//!
+//! Uses are counted per actor and action in one-second buckets, so a rate check can count the
+//! uses in any window up to a day. Only allowed uses of actions some rate check counts are
+//! recorded. Counts are kept in memory and, if a file is configured, saved to it every so often
+//! and loaded again at startup, so they survive a restart give or take the last few seconds.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::actor::RegisteredActor;
+use crate::ds::now;
+
+/// the longest window a rate check can count uses over, in seconds
+pub(crate) const MAX_WINDOW: u32 = 24 * 60 * 60;
+
+/// the most actor and action pairs tracked, so a flood of actors can't take all the memory
+const MAX_TRACKED: usize = 1_000_000;
+
+/// Recent uses of actions, by actor and action, as (second, uses) buckets oldest first
+#[derive(Debug, Default)]
+pub(crate) struct Velocity {
+    uses: Mutex<HashMap<String, VecDeque<(u64, u64)>>>,
+}
+
+impl Velocity {
+    /// The key uses of an action by an actor are counted under
+    fn key(actor: &RegisteredActor, action: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            actor.typestr.to_ascii_lowercase(),
+            actor.name.to_ascii_lowercase(),
+            action.to_ascii_lowercase()
+        )
+    }
+
+    /// Record a use of an action by an actor
+    pub(crate) fn record(&self, actor: &RegisteredActor, action: &str) {
+        self.record_at(Self::key(actor, action), now());
+    }
+
+    /// Record a use at a given second
+    fn record_at(&self, key: String, at: u64) {
+        let mut uses = self.uses.lock().unwrap();
+        if uses.len() >= MAX_TRACKED && !uses.contains_key(&key) {
+            return;
+        }
+
+        let buckets = uses.entry(key).or_default();
+        match buckets.back_mut() {
+            // if the clock went back, the use counts with the latest second
+            Some((second, count)) if *second >= at => *count += 1,
+            _ => buckets.push_back((at, 1)),
+        }
+        while buckets
+            .front()
+            .is_some_and(|(second, _)| *second + u64::from(MAX_WINDOW) <= at)
+        {
+            buckets.pop_front();
+        }
+    }
+
+    /// How many times an actor used any of some actions in the last `window` seconds
+    pub(crate) fn count(&self, actor: &RegisteredActor, actions: &[&str], window: u32) -> u64 {
+        let since = now().saturating_sub(u64::from(window));
+        let uses = self.uses.lock().unwrap();
+        actions
+            .iter()
+            .filter_map(|action| uses.get(&Self::key(actor, action)))
+            .map(|buckets| {
+                buckets
+                    .iter()
+                    .rev()
+                    .take_while(|(second, _)| *second > since)
+                    .map(|(_, count)| count)
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+
+    /// Forget uses too old for any window to count
+    pub(crate) fn prune(&self) {
+        let since = now().saturating_sub(u64::from(MAX_WINDOW));
+        let mut uses = self.uses.lock().unwrap();
+        uses.retain(|_, buckets| {
+            buckets.retain(|(second, _)| *second > since);
+            !buckets.is_empty()
+        });
+    }
+
+    /// How many actor and action pairs are tracked, and how many uses they have between them
+    pub(crate) fn stats(&self) -> (u64, u64) {
+        let uses = self.uses.lock().unwrap();
+        let events = uses.values().flatten().map(|(_, count)| count).sum();
+        (uses.len() as u64, events)
+    }
+
+    /// Load the uses saved to a file; a missing file means there are none
+    pub(crate) fn load(path: &str) -> Result<Self, String> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(format!("Could not read rate counts from {path}: {err}")),
+        };
+        let uses = serde_json::from_slice(&data)
+            .map_err(|err| format!("Could not parse rate counts in {path}: {err}"))?;
+
+        let velocity = Self {
+            uses: Mutex::new(uses),
+        };
+        velocity.prune();
+        Ok(velocity)
+    }
+
+    /// Save the uses to a file, replacing it whole so a crash never leaves half of it
+    pub(crate) fn save(&self, path: &str) -> Result<(), String> {
+        let data =
+            serde_json::to_vec(&*self.uses.lock().unwrap()).map_err(|err| err.to_string())?;
+        let tmp = format!("{path}.tmp");
+        std::fs::write(&tmp, data)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|err| format!("Could not save rate counts to {path}: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_velocity() {
+        let velocity = Velocity::default();
+        let actor = RegisteredActor::new("Kaitlyn", "user", Default::default());
+        let other = RegisteredActor::new("deployer", "service", Default::default());
+
+        // a use from before the window doesn't count
+        velocity.record_at(Velocity::key(&actor, "delete"), now() - 120);
+        velocity.record(&actor, "delete");
+        velocity.record(&actor, "DELETE");
+        velocity.record(&actor, "update");
+        velocity.record(&other, "delete");
+
+        assert_eq!(velocity.count(&actor, &["delete"], 60), 2);
+        assert_eq!(velocity.count(&actor, &["delete"], 600), 3);
+        assert_eq!(velocity.count(&actor, &["delete", "update"], 60), 3);
+        assert_eq!(velocity.count(&other, &["update"], 60), 0);
+        assert_eq!(velocity.stats(), (3, 5));
+
+        // uses older than any window are forgotten
+        velocity.record_at(Velocity::key(&other, "read"), now() - u64::from(MAX_WINDOW));
+        velocity.prune();
+        assert_eq!(velocity.stats(), (3, 5));
+
+        let path = std::env::temp_dir().join(format!("gatehouse-rates-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        velocity.save(path).unwrap();
+        let loaded = Velocity::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.count(&actor, &["delete"], 600), 3);
+    }
+}
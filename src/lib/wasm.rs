@@ -0,0 +1,122 @@
+#![warn(missing_docs)]
+
+//! Custom policy conditions evaluated by WASM modules
+//!
+//! Modules are loaded from a directory at startup and named by their file stem. A module must
+//! export its `memory`, an `alloc(len: i32) -> i32` function, and an
+//! `evaluate(ptr: i32, len: i32) -> i32` function. The request is written as JSON into memory
+//! reserved with `alloc`, and `evaluate` returns 1 if the condition matches. Modules cannot
+//! import anything and each evaluation gets a fresh instance with a bounded amount of fuel.
+//!
+//! Running modules requires building with the `wasm` feature.
+
+#[cfg(feature = "wasm")]
+use std::collections::HashMap;
+
+#[cfg(feature = "wasm")]
+use wasmtime::{Engine, Instance, Module, Store};
+
+/// how much fuel a single evaluation may use before it is stopped
+#[cfg(feature = "wasm")]
+const FUEL: u64 = 10_000_000;
+
+/// The WASM modules that policies can reference
+#[derive(Default)]
+pub(crate) struct WasmModules {
+    #[cfg(feature = "wasm")]
+    engine: Engine,
+    #[cfg(feature = "wasm")]
+    modules: HashMap<String, Module>,
+}
+
+impl WasmModules {
+    /// Load every `.wasm` file in a directory
+    #[cfg(feature = "wasm")]
+    pub(crate) fn load(dir: &str) -> Result<Self, String> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|err| err.to_string())?;
+
+        let entries = std::fs::read_dir(dir)
+            .map_err(|err| format!("Could not read WASM modules from {dir}: {err}"))?;
+
+        let mut modules = HashMap::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "wasm") {
+                let name = match path.file_stem() {
+                    Some(stem) => stem.to_string_lossy().to_ascii_lowercase(),
+                    None => continue,
+                };
+                let module = Module::from_file(&engine, &path)
+                    .map_err(|err| format!("Could not load {}: {err}", path.display()))?;
+                modules.insert(name, module);
+            }
+        }
+
+        Ok(Self { engine, modules })
+    }
+
+    /// Load every `.wasm` file in a directory
+    #[cfg(not(feature = "wasm"))]
+    pub(crate) fn load(dir: &str) -> Result<Self, String> {
+        Err(format!(
+            "Cannot load WASM modules from {dir}: Gatehouse was built without the wasm feature"
+        ))
+    }
+
+    /// See if a module is loaded
+    #[cfg(feature = "wasm")]
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.modules.contains_key(name)
+    }
+
+    /// See if a module is loaded
+    #[cfg(not(feature = "wasm"))]
+    pub(crate) fn contains(&self, _name: &str) -> bool {
+        false
+    }
+
+    /// Run a module's condition against a request
+    #[cfg(feature = "wasm")]
+    pub(crate) fn evaluate(&self, name: &str, input: &serde_json::Value) -> Result<bool, String> {
+        let module = self
+            .modules
+            .get(name)
+            .ok_or_else(|| format!("Unknown WASM module {name}"))?;
+        let input = input.to_string();
+        let len = i32::try_from(input.len()).map_err(|_| String::from("Request is too large"))?;
+
+        let mut store = Store::new(&self.engine, ());
+        store.add_fuel(FUEL).map_err(|err| err.to_string())?;
+
+        let instance = Instance::new(&mut store, module, &[]).map_err(|err| err.to_string())?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| format!("WASM module {name} does not export memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32, _>(&mut store, "alloc")
+            .map_err(|err| err.to_string())?;
+        let evaluate = instance
+            .get_typed_func::<(i32, i32), i32, _>(&mut store, "evaluate")
+            .map_err(|err| err.to_string())?;
+
+        let ptr = alloc.call(&mut store, len).map_err(|err| err.to_string())?;
+        memory
+            .write(&mut store, ptr as usize, input.as_bytes())
+            .map_err(|err| err.to_string())?;
+
+        let result = evaluate
+            .call(&mut store, (ptr, len))
+            .map_err(|err| err.to_string())?;
+        Ok(result == 1)
+    }
+
+    /// Run a module's condition against a request
+    #[cfg(not(feature = "wasm"))]
+    pub(crate) fn evaluate(&self, name: &str, _input: &serde_json::Value) -> Result<bool, String> {
+        Err(format!(
+            "Cannot run WASM module {name}: Gatehouse was built without the wasm feature"
+        ))
+    }
+}
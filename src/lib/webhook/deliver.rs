@@ -0,0 +1,273 @@
+//! Delivery of events to webhooks
+//!
+//! Events are POSTed as JSON in the background, over HTTPS for `https://` urls. Failed
+//! deliveries are retried with an exponential backoff, and the status of the most recent
+//! deliveries is kept in memory.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::{Body, Method, Request};
+use serde_json::json;
+use tokio::sync::RwLock;
+use tokio::time::{sleep, timeout, Duration};
+
+use crate::http::{self, HttpsClient};
+use crate::proto::webhooks::{self as protos, Delivery};
+
+use super::sign::signature;
+use super::{Event, RegisteredWebhook};
+
+/// how many deliveries we keep the status of
+const MAX_DELIVERIES: usize = 1000;
+
+/// how many times we try to deliver an event before giving up
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Sends events to webhooks and tracks the status of each delivery
+pub(crate) struct Dispatcher {
+    client: HttpsClient,
+    next_id: AtomicU64,
+    /// recent deliveries along with the id used to update them, oldest first
+    deliveries: RwLock<VecDeque<(u64, Delivery)>>,
+}
+
+impl Dispatcher {
+    pub(crate) fn new() -> Self {
+        Self {
+            client: http::client(),
+            next_id: AtomicU64::new(0),
+            deliveries: RwLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Send an event to every webhook subscribed to it, in the background
+    pub(crate) async fn dispatch<'a>(
+        self: &Arc<Self>,
+        hooks: impl IntoIterator<Item = &'a RegisteredWebhook>,
+        event: Event,
+        data: serde_json::Value,
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let payload = json!({
+            "event": event.name(),
+            "timestamp": timestamp,
+            "data": data,
+        })
+        .to_string();
+
+        for hook in hooks.into_iter().filter(|h| h.events.contains(&event)) {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+            let mut deliveries = self.deliveries.write().await;
+            if deliveries.len() >= MAX_DELIVERIES {
+                deliveries.pop_front();
+            }
+            deliveries.push_back((
+                id,
+                Delivery {
+                    webhook: hook.name.clone(),
+                    event: protos::Event::from(event).into(),
+                    timestamp,
+                    attempts: 0,
+                    delivered: false,
+                    error: None,
+                },
+            ));
+            drop(deliveries);
+
+            let me = self.clone();
+            let hook = hook.clone();
+            let payload = payload.clone();
+            tokio::spawn(async move { me.deliver(id, hook, event, payload).await });
+        }
+    }
+
+    /// Get the status of recent deliveries, optionally only for one webhook
+    pub(crate) async fn deliveries(&self, webhook: Option<&str>) -> Vec<Delivery> {
+        self.deliveries
+            .read()
+            .await
+            .iter()
+            .filter(|(_, d)| webhook.is_none_or(|name| d.webhook == name))
+            .map(|(_, d)| d.clone())
+            .collect()
+    }
+
+    /// Keep trying to deliver an event, backing off between attempts
+    async fn deliver(&self, id: u64, hook: RegisteredWebhook, event: Event, payload: String) {
+        let mut backoff = Duration::from_secs(1);
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let result = self.post(&hook, event, &payload).await;
+            let delivered = result.is_ok();
+
+            if let Some((_, delivery)) = self
+                .deliveries
+                .write()
+                .await
+                .iter_mut()
+                .find(|(d_id, _)| *d_id == id)
+            {
+                delivery.attempts = attempt;
+                delivery.delivered = delivered;
+                delivery.error = result.err();
+            }
+
+            if delivered {
+                return;
+            }
+
+            if attempt < MAX_ATTEMPTS {
+                sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+
+        eprintln!(
+            "Giving up on delivering {} to {} after {} attempts",
+            event.name(),
+            hook,
+            MAX_ATTEMPTS
+        );
+    }
+
+    /// Make a single attempt at delivering a payload
+    async fn post(
+        &self,
+        hook: &RegisteredWebhook,
+        event: Event,
+        payload: &str,
+    ) -> Result<(), String> {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(&hook.url)
+            .header("content-type", "application/json")
+            .header("x-gatehouse-event", event.name());
+
+        if let Some(ref secret) = hook.secret {
+            req = req.header(
+                "x-gatehouse-signature",
+                signature(secret, payload.as_bytes()),
+            );
+        }
+
+        let req = req
+            .body(Body::from(payload.to_string()))
+            .map_err(|err| err.to_string())?;
+
+        let resp = timeout(Duration::from_secs(10), self.client.request(req))
+            .await
+            .map_err(|_| String::from("Timeout waiting for webhook"))?
+            .map_err(|err| err.to_string())?;
+
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Webhook responded with {}", resp.status()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::test;
+
+    use super::*;
+
+    #[test]
+    async fn test_dispatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // a webhook receiver that hands back the request it got
+        let receiver = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let len = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..len]).to_string()
+        });
+
+        let hooks = vec![
+            RegisteredWebhook {
+                name: String::from("denials"),
+                url: format!("http://{addr}/hook"),
+                events: HashSet::from([Event::DenyDecision]),
+                secret: Some(String::from("shh")),
+            },
+            RegisteredWebhook {
+                name: String::from("changes"),
+                url: format!("http://{addr}/other"),
+                events: HashSet::from([Event::EntityChanged]),
+                secret: None,
+            },
+        ];
+
+        let dispatcher = Arc::new(Dispatcher::new());
+        dispatcher
+            .dispatch(&hooks, Event::DenyDecision, json!({"target": "db"}))
+            .await;
+
+        let request = timeout(Duration::from_secs(5), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(request.starts_with("POST /hook"));
+        assert!(request.contains("x-gatehouse-signature: sha256="));
+        assert!(request.contains("\"event\":\"deny_decision\""));
+
+        // only the subscribed webhook gets the event
+        let deliveries = dispatcher.deliveries(None).await;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].webhook, "denials");
+    }
+
+    #[test]
+    async fn test_dispatch_https() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // a receiver that only reads what it is sent first
+        let receiver = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let len = stream.read(&mut buf).await.unwrap();
+            buf.truncate(len);
+            buf
+        });
+
+        let hooks = vec![RegisteredWebhook {
+            name: String::from("denials"),
+            url: format!("https://{addr}/hook"),
+            events: HashSet::from([Event::DenyDecision]),
+            secret: Some(String::from("shh")),
+        }];
+
+        let dispatcher = Arc::new(Dispatcher::new());
+        dispatcher
+            .dispatch(&hooks, Event::DenyDecision, json!({"target": "db"}))
+            .await;
+
+        // the payload is only sent after a TLS handshake
+        let sent = timeout(Duration::from_secs(5), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sent.first(), Some(&0x16));
+        assert!(!String::from_utf8_lossy(&sent).contains("deny_decision"));
+    }
+}
@@ -0,0 +1,113 @@
+#![warn(missing_docs)]
+
+//! The Webhook type and methods
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+use std::fmt::Display;
+
+use crate::proto::webhooks::{self as protos, Webhook};
+
+pub(crate) mod deliver;
+pub(crate) mod sign;
+
+/// Events that can be sent to webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) enum Event {
+    EntityChanged,
+    DenyDecision,
+    StorageHealth,
+    DelegatedDecision,
+    BreakGlass,
+    EvaluationLimit,
+    DenyStreak,
+}
+
+impl Event {
+    /// the name of the event as sent in payloads
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Event::EntityChanged => "entity_changed",
+            Event::DenyDecision => "deny_decision",
+            Event::StorageHealth => "storage_health",
+            Event::DelegatedDecision => "delegated_decision",
+            Event::BreakGlass => "break_glass",
+            Event::EvaluationLimit => "evaluation_limit",
+            Event::DenyStreak => "deny_streak",
+        }
+    }
+}
+
+impl From<protos::Event> for Event {
+    fn from(e: protos::Event) -> Self {
+        match e {
+            protos::Event::EntityChanged => Self::EntityChanged,
+            protos::Event::DenyDecision => Self::DenyDecision,
+            protos::Event::StorageHealth => Self::StorageHealth,
+            protos::Event::DelegatedDecision => Self::DelegatedDecision,
+            protos::Event::BreakGlass => Self::BreakGlass,
+            protos::Event::EvaluationLimit => Self::EvaluationLimit,
+            protos::Event::DenyStreak => Self::DenyStreak,
+        }
+    }
+}
+impl From<Event> for protos::Event {
+    fn from(e: Event) -> Self {
+        match e {
+            Event::EntityChanged => Self::EntityChanged,
+            Event::DenyDecision => Self::DenyDecision,
+            Event::StorageHealth => Self::StorageHealth,
+            Event::DelegatedDecision => Self::DelegatedDecision,
+            Event::BreakGlass => Self::BreakGlass,
+            Event::EvaluationLimit => Self::EvaluationLimit,
+            Event::DenyStreak => Self::DenyStreak,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RegisteredWebhook {
+    pub name: String,
+    pub url: String,
+    pub events: HashSet<Event>,
+    pub secret: Option<String>,
+}
+
+impl From<Webhook> for RegisteredWebhook {
+    fn from(hook: Webhook) -> Self {
+        let events = hook.events().map(Event::from).collect();
+
+        Self {
+            name: hook.name.to_ascii_lowercase(),
+            url: hook.url,
+            events,
+            secret: hook.secret,
+        }
+    }
+}
+
+/// The secret is never handed back out
+impl From<RegisteredWebhook> for Webhook {
+    fn from(hook: RegisteredWebhook) -> Self {
+        let mut events: Vec<i32> = hook
+            .events
+            .into_iter()
+            .map(|e| protos::Event::from(e).into())
+            .collect();
+        events.sort_unstable();
+
+        Self {
+            name: hook.name,
+            url: hook.url,
+            events,
+            secret: None,
+        }
+    }
+}
+
+impl Display for RegisteredWebhook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "webhook[{}]: {}", self.name, self.url)
+    }
+}
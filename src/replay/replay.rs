@@ -0,0 +1,88 @@
+#![warn(missing_docs)]
+
+//! Replays a recording of a server's requests against a fresh datastore
+
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Parser;
+
+use gatehouse::config::Config;
+use gatehouse::replay::replay;
+
+/// Command line arguments for the replay tool
+#[derive(Parser, Debug)]
+struct Arguments {
+    #[arg(help = "Recording made with GATERECORDREQUESTS")]
+    recording: PathBuf,
+    #[arg(
+        long,
+        value_name = "LINE",
+        help = "Stop after this line of the recording"
+    )]
+    until: Option<usize>,
+    #[arg(
+        long,
+        short,
+        help = "Print every request, not only those that got errors"
+    )]
+    verbose: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Arguments::parse();
+
+    // the server's settings apply, so a recording can be replayed the way it was made
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut requests = 0;
+    let mut errors = 0;
+    let result = replay(&args.recording, config, args.until, |outcome| {
+        requests += 1;
+        match outcome.error {
+            Some(ref status) => {
+                errors += 1;
+                println!(
+                    "line {} ({}ms): {}: {:?}: {}",
+                    outcome.line,
+                    outcome.at,
+                    outcome.request,
+                    status.code(),
+                    status.message()
+                );
+            }
+            None if args.verbose => println!(
+                "line {} ({}ms): {}: ok",
+                outcome.line, outcome.at, outcome.request
+            ),
+            None => {}
+        }
+    })
+    .await;
+
+    let stats = match result {
+        Ok(stats) => stats,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            exit(1);
+        }
+    };
+    println!("Replayed {requests} requests, {errors} with errors");
+    println!(
+        "{} targets, {} actors, {} roles, {} groups, {} policies, {} checks",
+        stats.targets, stats.actors, stats.roles, stats.groups, stats.policies, stats.checks
+    );
+    if stats.drift > 0 {
+        println!(
+            "{} roles and groups out of step with each other",
+            stats.drift
+        );
+    }
+}
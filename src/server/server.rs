@@ -2,31 +2,238 @@
 
 //! The main Gatehouse server binary
 
+use std::sync::Arc;
+
+use clap::Parser;
+use tonic::codec::CompressionEncoding;
 use tonic::transport::Server;
 
+use gatehouse::admin;
+use gatehouse::authzen;
+use gatehouse::config::Config;
 use gatehouse::helpers::str;
+use gatehouse::kubernetes;
+use gatehouse::limits::LimitedServer;
 use gatehouse::proto::base::gatehouse_server::GatehouseServer;
 use gatehouse::svc::GatehouseSvc;
+use gatehouse::StorageType;
+
+/// Command line arguments for the server
+#[derive(Parser, Debug)]
+struct Arguments {
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Replicate another Gatehouse server instead of using storage, and only serve checks"
+    )]
+    replica_of: Option<String>,
+}
 
-#[tokio::main]
 /// Our main function for the server
-pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Arguments::parse();
+
+    let mut config = Config::from_env()?;
+    config.replica_of = args.replica_of;
+
+    // the runtimes are built here rather than with #[tokio::main] so they can be configured
+    let runtimes = config.runtime.build()?;
+    config.storage_runtime = runtimes.storage_handle();
+
+    let served = runtimes.main.block_on(serve(config));
+    runtimes.shutdown();
+    served
+}
+
+/// Start the server and its endpoints, and serve until the server stops
+async fn serve(config: Config) -> Result<(), Box<dyn std::error::Error>> {
     let port = std::env::var("GATEPORT").unwrap_or_else(|_| str("6174"));
     let addr = format!("[::1]:{port}").parse()?;
-    let storage = std::env::var("GATESTORAGE")
-        .unwrap_or_else(|_| str("file:/tmp/gatehouse"))
-        .into();
 
-    let svc = GatehouseSvc::new(&storage).await;
+    // a replica gets everything from its primary
+    let storage = match config.replica_of {
+        Some(_) => StorageType::Nil,
+        None => std::env::var("GATESTORAGE")
+            .unwrap_or_else(|_| str("file:/tmp/gatehouse"))
+            .into(),
+    };
+
+    let authzen_port = std::env::var("GATEAUTHZENPORT").ok();
+    let admin_port = std::env::var("GATEADMINPORT").ok();
+    let kube_port = std::env::var("GATEKUBEPORT").ok();
+
+    let svc = Arc::new(GatehouseSvc::with_config(&storage, config.clone()).await);
 
     println!("Starting Gatehouse server:");
     println!("* addr: {}", addr);
     println!("* storage: {}", storage);
+    println!(
+        "* startup mode: {}",
+        config.startup_mode.as_str_name().to_ascii_lowercase()
+    );
+    println!(
+        "* migrations: {} to schema version {}",
+        config.migrations,
+        gatehouse::migrate::SCHEMA_VERSION
+    );
+    match config.replica_of {
+        Some(ref primary) => println!("* replica of: {}", primary),
+        None => println!("* replica of: none"),
+    }
+    match config.region {
+        Some(ref region) => println!("* replicate to region: {}", region),
+        None => println!("* replicate to region: none"),
+    }
+    match config.election {
+        Some(ref name) => println!("* leader election: as {}", name),
+        None => println!("* leader election: disabled"),
+    }
+    match config.namespaces.is_empty() {
+        true => println!("* namespaces: none"),
+        false => println!("* namespaces: {}", config.namespaces.join(", ")),
+    }
+    match (config.unused_days, config.remove_unused) {
+        (0, _) => println!("* unused entities: ignored"),
+        (days, true) => println!("* unused entities: removed after {}d", days),
+        (days, false) => println!("* unused entities: flagged after {}d", days),
+    }
+    println!("* quotas: {}", config.quotas);
+    let mut namespace_quotas: Vec<_> = config.namespace_quotas.iter().collect();
+    namespace_quotas.sort_by_key(|(namespace, _)| *namespace);
+    for (namespace, quotas) in namespace_quotas {
+        println!("* quotas of {namespace}: {quotas}");
+    }
+    println!("* recorded checks: {}", config.recorded_checks);
+    match config.record_requests {
+        Some(ref path) => println!("* recording requests: to {}", path),
+        None => println!("* recording requests: disabled"),
+    }
+    match config.sample_decisions {
+        Some(ref path) => println!(
+            "* decision sampling: 1 in {} to {}",
+            config.decision_sample_rate.max(1),
+            path
+        ),
+        None => println!("* decision sampling: disabled"),
+    }
+    println!("* decision cache ttl: {}s", config.decision_ttl);
+    match config.ldap {
+        Some(ref ldap) => println!("* ldap sync: {}", ldap),
+        None => println!("* ldap sync: disabled"),
+    }
+    match config.wasm_dir {
+        Some(ref dir) => println!("* wasm modules: {}", dir),
+        None => println!("* wasm modules: disabled"),
+    }
+    match config.oidc {
+        Some(ref oidc) => println!("* oidc introspection: {}", oidc),
+        None => println!("* oidc introspection: disabled"),
+    }
+    match config.risk {
+        Some(ref risk) => println!("* risk scoring: {}", risk),
+        None => println!("* risk scoring: disabled"),
+    }
+    match config.attribute_rules {
+        Some(ref rules) => println!("* attribute rules: {}", rules),
+        None => println!("* attribute rules: disabled"),
+    }
+    match config.grant_secret {
+        Some(_) => println!(
+            "* grants: enabled, up to {}s",
+            match config.grant_ttl {
+                0 => gatehouse::grant::DEFAULT_TTL,
+                ttl => ttl,
+            }
+        ),
+        None => println!("* grants: disabled"),
+    }
+    match config.break_glass_role {
+        Some(ref role) => println!("* break glass: members of {role}"),
+        None => println!("* break glass: disabled"),
+    }
+    match (config.bundle_key, config.bundle_only) {
+        (Some(_), true) => println!("* policy bundles: required"),
+        (Some(_), false) => println!("* policy bundles: accepted"),
+        (None, _) => println!("* policy bundles: disabled"),
+    }
+    match (&config.canaries, config.canaries_required) {
+        (Some(source), true) => println!("* canaries: {source}, required"),
+        (Some(source), false) => println!("* canaries: {source}"),
+        (None, _) => println!("* canaries: none"),
+    }
+    match (config.api_keys_required, &config.bootstrap_api_key) {
+        (true, Some(_)) => println!(
+            "* API keys: required, bootstrap key for {}",
+            config.bootstrap_principal
+        ),
+        (true, None) => println!("* API keys: required"),
+        (false, _) => println!("* API keys: not required"),
+    }
+    println!("* runtime: {}", config.runtime);
+    println!("* compression: {}", config.compression);
+    println!("* limits: {}", config.limits);
+    println!("* attribute merge: {}", config.attribute_merge);
+    match config.chaos {
+        Some(ref chaos) => println!("* storage faults: {} (for testing only)", chaos),
+        None => println!("* storage faults: none"),
+    }
+
+    match authzen_port {
+        Some(port) => {
+            let authzen_addr = format!("[::1]:{port}").parse()?;
+            println!("* authzen: {}", authzen_addr);
+
+            let svc = svc.clone();
+            tokio::spawn(async move {
+                if let Err(err) = authzen::serve(authzen_addr, svc).await {
+                    eprintln!("AuthZEN endpoint failed: {err}");
+                }
+            });
+        }
+        None => println!("* authzen: disabled"),
+    }
+
+    match admin_port {
+        Some(port) => {
+            let admin_addr = format!("[::1]:{port}").parse()?;
+            println!("* admin api: {}", admin_addr);
+
+            let svc = svc.clone();
+            tokio::spawn(async move {
+                if let Err(err) = admin::serve(admin_addr, svc).await {
+                    eprintln!("Admin API failed: {err}");
+                }
+            });
+        }
+        None => println!("* admin api: disabled"),
+    }
+
+    match kube_port {
+        Some(port) => {
+            let kube_addr = format!("[::1]:{port}").parse()?;
+            println!("* kubernetes authorization webhook: {}", kube_addr);
+
+            let svc = svc.clone();
+            tokio::spawn(async move {
+                if let Err(err) = kubernetes::serve(kube_addr, svc).await {
+                    eprintln!("Kubernetes authorization webhook failed: {err}");
+                }
+            });
+        }
+        None => println!("* kubernetes authorization webhook: disabled"),
+    }
+
+    // gzip-compressed messages are always taken, and sent to clients that accept them if asked
+    let mut gatehouse = GatehouseServer::from_arc(svc).accept_compressed(CompressionEncoding::Gzip);
+    if let Some(encoding) = config.compression.encoding() {
+        gatehouse = gatehouse.send_compressed(encoding);
+    }
 
-    Server::builder()
-        .accept_http1(true)
-        .add_service(tonic_web::enable(GatehouseServer::new(svc)))
-        .serve(addr)
+    let limits = &config.limits;
+    limits
+        .apply(Server::builder().accept_http1(true))
+        .add_service(tonic_web::enable(LimitedServer::new(gatehouse, limits)))
+        .serve_with_incoming(limits.incoming(addr)?)
         .await?;
 
     Ok(())
@@ -64,7 +64,7 @@ pub async fn run_server() {
         .into();
 
     match storage {
-        StorageType::FileSystem(path) => clear_dir(&path).await,
+        StorageType::FileSystem(path) | StorageType::Log(path) => clear_dir(&path).await,
         StorageType::Etcd(url) => clear_etcd(&url).await,
         _ => (),
     }
@@ -73,6 +73,8 @@ pub async fn run_server() {
         .arg("run")
         .arg("--bin")
         .arg("gatesrv")
+        .env("GATECOMPRESSION", "gzip")
+        .env("GATEMAXDECODESIZE", "1048576")
         .kill_on_drop(true)
         .stdout(Stdio::null())
         .spawn()
@@ -4,9 +4,10 @@ use std::process::exit;
 
 use tokio::time::{sleep, Duration};
 
-use gatehouse::proto::policies::{
-    ActorCheck, Decide, KvCheck, Num, NumberCheck, Set, StringCheck, TargetCheck,
+use gatehouse::model::{
+    ActorCheckBuilder, KvCheck, NumberCheck, PolicyRuleBuilder, StringCheck, TargetCheckBuilder,
 };
+use gatehouse::proto::policies::Decide;
 use tokio::test;
 
 use gatehouse::proto::base::gatehouse_client::GatehouseClient;
@@ -14,8 +15,11 @@ use gatehouse::proto::base::gatehouse_client::GatehouseClient;
 use gatehouse::helpers::{
     add_actor, add_group, add_policy, add_role, add_target, get_actors, get_groups, get_policies,
     get_roles, get_targets, modify_actor, modify_group, modify_policy, modify_target, remove_actor,
-    remove_group, remove_policy, remove_role, remove_target, str,
+    remove_group, remove_policy, remove_role, remove_target, str, AddActorRequestBuilder,
+    AddGroupRequestBuilder, AddTargetRequestBuilder, ModifyActorRequestBuilder,
+    ModifyGroupRequestBuilder, ModifyTargetRequestBuilder,
 };
+use tonic::codec::CompressionEncoding;
 use tonic::transport::Channel;
 
 #[test]
@@ -36,6 +40,8 @@ async fn test_crud() {
     sleep(Duration::from_secs(5)).await;
 
     load_data().await;
+    test_compression().await;
+    test_message_limit().await;
 }
 
 async fn create_client() -> GatehouseClient<Channel> {
@@ -55,6 +61,58 @@ async fn create_client() -> GatehouseClient<Channel> {
     }
 }
 
+/// a large target is sent and fetched with its messages compressed
+async fn test_compression() {
+    let mut client = create_client()
+        .await
+        .send_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Gzip);
+
+    let members: Vec<String> = (0..500).map(|i| format!("member-{i}")).collect();
+    add_target(
+        &mut client,
+        AddTargetRequestBuilder::new("bucket", "archive")
+            .actions(&["read"])
+            .attribute(
+                "readers",
+                &members.iter().map(String::as_str).collect::<Vec<_>>(),
+            )
+            .build(),
+    )
+    .await
+    .expect("Didn't add compressed target");
+
+    let targets = get_targets(&mut client, Some("bucket"), Some("archive"))
+        .await
+        .unwrap();
+    assert_eq!(targets.len(), 1);
+    assert_eq!(targets[0].attributes["readers"].values.len(), members.len());
+}
+
+/// a message over the server's limit is refused with an error that names the limit
+async fn test_message_limit() {
+    let mut client = create_client().await;
+
+    let members: Vec<String> = (0..100_000).map(|i| format!("member-{i}")).collect();
+    let err = add_target(
+        &mut client,
+        AddTargetRequestBuilder::new("bucket", "huge")
+            .actions(&["read"])
+            .attribute(
+                "readers",
+                &members.iter().map(String::as_str).collect::<Vec<_>>(),
+            )
+            .build(),
+    )
+    .await
+    .expect_err("Added a target over the message limit");
+    assert!(
+        err.message
+            .contains("larger than the limit of 1048576 bytes"),
+        "{err}"
+    );
+}
+
 async fn test_targets() {
     let mut client = create_client().await;
 
@@ -66,9 +124,12 @@ async fn test_targets() {
     assert_eq!(targets.len(), 0, "targets should have been 0");
 
     // add a target
-    let tgt1 = add_target(&mut client, "db1", "database", vec![], vec![])
-        .await
-        .expect("Didn't get target");
+    let tgt1 = add_target(
+        &mut client,
+        AddTargetRequestBuilder::new("database", "db1").build(),
+    )
+    .await
+    .expect("Didn't get target");
     assert_eq!(tgt1.name, "db1");
     assert_eq!(tgt1.typestr, "database");
     assert_eq!(tgt1.actions.len(), 0);
@@ -76,22 +137,31 @@ async fn test_targets() {
     // add some more targets
     let tgt2 = add_target(
         &mut client,
-        "db2",
-        "database",
-        vec!["read", "write"],
-        vec![(str("role"), vec!["prod"])],
+        AddTargetRequestBuilder::new("database", "db2")
+            .actions(&["read", "write"])
+            .attribute("role", &["prod"])
+            .build(),
+    )
+    .await
+    .expect("Didn't get target");
+    let tgt3 = add_target(
+        &mut client,
+        AddTargetRequestBuilder::new("website", "www1").build(),
+    )
+    .await
+    .expect("Didn't get target");
+    let _tgt4 = add_target(
+        &mut client,
+        AddTargetRequestBuilder::new("website", "www2").build(),
+    )
+    .await
+    .expect("Didn't get target");
+    let _tgt5 = add_target(
+        &mut client,
+        AddTargetRequestBuilder::new("website", "login").build(),
     )
     .await
     .expect("Didn't get target");
-    let tgt3 = add_target(&mut client, "www1", "website", vec![], vec![])
-        .await
-        .expect("Didn't get target");
-    let _tgt4 = add_target(&mut client, "www2", "website", vec![], vec![])
-        .await
-        .expect("Didn't get target");
-    let _tgt5 = add_target(&mut client, "login", "website", vec![], vec![])
-        .await
-        .expect("Didn't get target");
     assert_eq!(tgt2.actions.len(), 2);
     assert!(tgt2.attributes.contains_key("role"));
 
@@ -102,7 +172,7 @@ async fn test_targets() {
     assert_eq!(targets.len(), 5, "expected 5 targets");
 
     // filter targets by type
-    let targets = get_targets(&mut client, None, Some("website"))
+    let targets = get_targets(&mut client, Some("website"), None)
         .await
         .unwrap();
     assert_eq!(
@@ -112,7 +182,7 @@ async fn test_targets() {
     );
 
     // filter target type by name
-    let targets = get_targets(&mut client, Some("db2"), None).await.unwrap();
+    let targets = get_targets(&mut client, None, Some("db2")).await.unwrap();
     assert_eq!(targets.len(), 1, "expected to get a single result");
     assert_eq!(targets[0].name, "db2");
 
@@ -121,12 +191,9 @@ async fn test_targets() {
     assert_eq!(tgt3.actions.len(), 0);
     let tgt3 = modify_target(
         &mut client,
-        "www1",
-        "website",
-        vec!["login", "logout"],
-        vec![],
-        vec![],
-        vec![],
+        ModifyTargetRequestBuilder::new("website", "www1")
+            .add_actions(&["login", "logout"])
+            .build(),
     )
     .await
     .unwrap();
@@ -137,15 +204,11 @@ async fn test_targets() {
     // remove an action and add some attributes
     let tgt3 = modify_target(
         &mut client,
-        "www1",
-        "website",
-        vec![],
-        vec![
-            (str("auth"), vec!["basic", "gssapi"]),
-            (str("api"), vec!["json", "xml"]),
-        ],
-        vec!["logout"],
-        vec![],
+        ModifyTargetRequestBuilder::new("website", "www1")
+            .add_attribute("auth", &["basic", "gssapi"])
+            .add_attribute("api", &["json", "xml"])
+            .remove_actions(&["logout"])
+            .build(),
     )
     .await
     .unwrap();
@@ -173,12 +236,9 @@ async fn test_targets() {
     // remove some attributes
     let tgt3 = modify_target(
         &mut client,
-        "www1",
-        "website",
-        vec![],
-        vec![],
-        vec![],
-        vec![(str("api"), vec!["json"])],
+        ModifyTargetRequestBuilder::new("website", "www1")
+            .remove_attribute("api", &["json"])
+            .build(),
     )
     .await
     .unwrap();
@@ -206,12 +266,9 @@ async fn test_targets() {
     // remove some attributes
     let tgt3 = modify_target(
         &mut client,
-        "www1",
-        "website",
-        vec![],
-        vec![],
-        vec![],
-        vec![(str("api"), vec!["xml"])],
+        ModifyTargetRequestBuilder::new("website", "www1")
+            .remove_attribute("api", &["xml"])
+            .build(),
     )
     .await
     .unwrap();
@@ -228,7 +285,7 @@ async fn test_targets() {
         2
     );
 
-    let tgt3 = remove_target(&mut client, "www1", "website").await.unwrap();
+    let tgt3 = remove_target(&mut client, "website", "www1").await.unwrap();
     assert_eq!(tgt3.name, "www1");
     assert_eq!(tgt3.typestr, "website");
     assert_eq!(tgt3.actions.len(), 1);
@@ -246,9 +303,12 @@ async fn test_actors() {
     assert_eq!(actors.len(), 0, "actors should have been 0");
 
     // add a actor
-    let ent1 = add_actor(&mut client, "testman1", "user", vec![])
-        .await
-        .unwrap();
+    let ent1 = add_actor(
+        &mut client,
+        AddActorRequestBuilder::new("user", "testman1").build(),
+    )
+    .await
+    .unwrap();
     assert_eq!(ent1.name, "testman1");
     assert_eq!(ent1.typestr, "user");
     assert!(ent1.attributes.is_empty());
@@ -256,21 +316,30 @@ async fn test_actors() {
     // add some more actors
     let ent2 = add_actor(
         &mut client,
-        "sandytest",
-        "user",
-        vec![(str("org"), vec!["hr"])],
+        AddActorRequestBuilder::new("user", "sandytest")
+            .attribute("org", &["hr"])
+            .build(),
+    )
+    .await
+    .unwrap();
+    let _ = add_actor(
+        &mut client,
+        AddActorRequestBuilder::new("svc", "logger").build(),
+    )
+    .await
+    .unwrap();
+    let _ = add_actor(
+        &mut client,
+        AddActorRequestBuilder::new("svc", "launcher").build(),
+    )
+    .await
+    .unwrap();
+    let _ = add_actor(
+        &mut client,
+        AddActorRequestBuilder::new("svc", "printer").build(),
     )
     .await
     .unwrap();
-    let _ = add_actor(&mut client, "logger", "svc", vec![])
-        .await
-        .unwrap();
-    let _ = add_actor(&mut client, "launcher", "svc", vec![])
-        .await
-        .unwrap();
-    let _ = add_actor(&mut client, "printer", "svc", vec![])
-        .await
-        .unwrap();
     assert!(ent2.attributes.contains_key("org"));
 
     // make sure all the actors are there
@@ -280,11 +349,11 @@ async fn test_actors() {
     assert_eq!(actors.len(), 5, "expected 5 actors");
 
     // filter actors by type
-    let actors = get_actors(&mut client, None, Some("svc")).await.unwrap();
+    let actors = get_actors(&mut client, Some("svc"), None).await.unwrap();
     assert_eq!(actors.len(), 3, "expected to get 3 actors of type website");
 
     // filter target type by name
-    let actors = get_actors(&mut client, Some("sandytest"), None)
+    let actors = get_actors(&mut client, None, Some("sandytest"))
         .await
         .unwrap();
     assert_eq!(actors.len(), 1, "expected to get a single result");
@@ -293,13 +362,10 @@ async fn test_actors() {
     // add some attributes
     let ent3 = modify_actor(
         &mut client,
-        "logger",
-        "svc",
-        vec![
-            (str("org"), vec!["hr", "recruiting"]),
-            (str("office"), vec!["remote", "nyc"]),
-        ],
-        vec![],
+        ModifyActorRequestBuilder::new("svc", "logger")
+            .add_attribute("org", &["hr", "recruiting"])
+            .add_attribute("office", &["remote", "nyc"])
+            .build(),
     )
     .await
     .unwrap();
@@ -325,10 +391,9 @@ async fn test_actors() {
     // remove some attributes
     let ent3 = modify_actor(
         &mut client,
-        "logger",
-        "svc",
-        vec![],
-        vec![(str("office"), vec!["remote"])],
+        ModifyActorRequestBuilder::new("svc", "logger")
+            .remove_attribute("office", &["remote"])
+            .build(),
     )
     .await
     .unwrap();
@@ -354,10 +419,9 @@ async fn test_actors() {
     // remove some attributes
     let ent3 = modify_actor(
         &mut client,
-        "logger",
-        "svc",
-        vec![],
-        vec![(str("office"), vec!["nyc"])],
+        ModifyActorRequestBuilder::new("svc", "logger")
+            .remove_attribute("office", &["nyc"])
+            .build(),
     )
     .await
     .unwrap();
@@ -372,7 +436,7 @@ async fn test_actors() {
         2
     );
 
-    let ent3 = remove_actor(&mut client, "logger", "svc").await.unwrap();
+    let ent3 = remove_actor(&mut client, "svc", "logger").await.unwrap();
     assert_eq!(ent3.name, "logger");
     assert_eq!(ent3.typestr, "svc");
 }
@@ -422,10 +486,11 @@ async fn test_groups() {
 
     let grp1 = add_group(
         &mut client,
-        "administrators",
-        None,
-        vec![("sandytest", "authuser"), ("donnyman", "authuser")],
-        vec!["admin", "user"],
+        AddGroupRequestBuilder::new("administrators")
+            .member("authuser", "sandytest")
+            .member("authuser", "donnyman")
+            .roles(&["admin", "user"])
+            .build(),
     )
     .await
     .unwrap();
@@ -445,22 +510,24 @@ async fn test_groups() {
 
     let grp1 = modify_group(
         &mut client,
-        "administrators",
-        None,
-        vec![("testman", "authuser"), ("testdog", "authuser")],
-        vec!["manager", "guest"],
-        vec![("sandytest", "authuser")],
-        vec!["admin"],
+        ModifyGroupRequestBuilder::new("administrators")
+            .add_member("authuser", "testman")
+            .add_member("authuser", "testdog")
+            .add_roles(&["manager", "guest"])
+            .remove_member("authuser", "sandytest")
+            .remove_roles(&["admin"])
+            .build(),
     )
     .await
     .unwrap();
 
     let grp2 = add_group(
         &mut client,
-        "customers",
-        None,
-        vec![("coke", "authuser"), ("pepsi", "authuser")],
-        vec!["user", "manager"],
+        AddGroupRequestBuilder::new("customers")
+            .member("authuser", "coke")
+            .member("authuser", "pepsi")
+            .roles(&["user", "manager"])
+            .build(),
     )
     .await
     .unwrap();
@@ -480,7 +547,9 @@ async fn test_groups() {
     assert_eq!(role2[0].name, "user");
     assert_eq!(role2[0].granted_to.len(), 2);
 
-    let grps = get_groups(&mut client, None, None, None).await.unwrap();
+    let grps = get_groups(&mut client, None, None, None, false)
+        .await
+        .unwrap();
     assert_eq!(grps.len(), 2);
     let grp1 = remove_group(&mut client, "administrators").await.unwrap();
     assert_eq!(grp1.name, "administrators");
@@ -490,11 +559,13 @@ async fn test_groups() {
     let role2 = get_roles(&mut client, Some("user")).await.unwrap();
     assert_eq!(role2[0].name, "user");
     assert_eq!(role2[0].granted_to.len(), 1);
-    let grps = get_groups(&mut client, None, None, None).await.unwrap();
+    let grps = get_groups(&mut client, None, None, None, false)
+        .await
+        .unwrap();
     assert_eq!(grps.len(), 1);
 
     // we will next remove a role and make sure it gets removed from the group
-    let grp2 = get_groups(&mut client, Some("customers"), None, None)
+    let grp2 = get_groups(&mut client, Some("customers"), None, None, false)
         .await
         .unwrap();
     assert_eq!(grp2[0].name, "customers");
@@ -504,7 +575,7 @@ async fn test_groups() {
     let role2 = remove_role(&mut client, "user").await.unwrap();
     assert_eq!(role2.name, "user");
 
-    let grp2 = get_groups(&mut client, Some("customers"), None, None)
+    let grp2 = get_groups(&mut client, Some("customers"), None, None, false)
         .await
         .unwrap();
     assert_eq!(grp2[0].name, "customers");
@@ -517,24 +588,14 @@ async fn test_polices() {
 
     let pol1 = add_policy(
         &mut client,
-        "allow-admins",
-        None,
-        Some(ActorCheck {
-            name: None,
-            typestr: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("user")],
-            }),
-            attributes: vec![KvCheck {
-                key: str("role"),
-                op: Set::Has.into(),
-                vals: vec![str("admin")],
-            }],
-            bucket: None,
-        }),
-        vec![],
-        None,
-        Decide::Allow,
+        PolicyRuleBuilder::new("allow-admins")
+            .actor(
+                ActorCheckBuilder::new()
+                    .typestr(StringCheck::OneOf(vec![str("user")]))
+                    .attribute(KvCheck::Has(str("role"), vec![str("admin")]))
+                    .build(),
+            )
+            .build(),
     )
     .await
     .unwrap();
@@ -545,27 +606,16 @@ async fn test_polices() {
 
     let pol1 = modify_policy(
         &mut client,
-        "allow-admins",
-        None,
-        Some(ActorCheck {
-            name: None,
-            typestr: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("user")],
-            }),
-            attributes: vec![KvCheck {
-                key: str("role"),
-                op: Set::Has.into(),
-                vals: vec![str("admin")],
-            }],
-            bucket: Some(NumberCheck {
-                op: Num::LessThan.into(),
-                val: 50,
-            }),
-        }),
-        vec![],
-        None,
-        Decide::Deny,
+        PolicyRuleBuilder::new("allow-admins")
+            .actor(
+                ActorCheckBuilder::new()
+                    .typestr(StringCheck::OneOf(vec![str("user")]))
+                    .attribute(KvCheck::Has(str("role"), vec![str("admin")]))
+                    .bucket(NumberCheck::LessThan(50))
+                    .build(),
+            )
+            .deny()
+            .build(),
     )
     .await
     .unwrap();
@@ -584,12 +634,7 @@ async fn test_polices() {
 
     let _ = add_policy(
         &mut client,
-        "allow-everyone",
-        None,
-        None,
-        vec![],
-        None,
-        Decide::Allow,
+        PolicyRuleBuilder::new("allow-everyone").build(),
     )
     .await
     .unwrap();
@@ -612,61 +657,37 @@ async fn test_polices() {
 
     let _ = add_policy(
         &mut client,
-        "allow-teamalpha",
-        Some("Give specific access to members of Team Alpha"),
-        Some(ActorCheck {
-            name: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("brandy"), str("hank")],
-            }),
-            typestr: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("user")],
-            }),
-            attributes: vec![
-                KvCheck {
-                    key: str("role"),
-                    op: Set::Has.into(),
-                    vals: vec![str("admin")],
-                },
-                KvCheck {
-                    key: str("role"),
-                    op: Set::HasNot.into(),
-                    vals: vec![str("manager"), str("exec")],
-                },
-            ],
-            bucket: Some(NumberCheck {
-                op: Num::LessThan.into(),
-                val: 50,
-            }),
-        }),
-        vec![KvCheck {
-            key: str("env"),
-            op: Set::Has.into(),
-            vals: vec![str("prod")],
-        }],
-        Some(TargetCheck {
-            name: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("launchctl"), str("abortctl")],
-            }),
-            typestr: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("svc"), str("api"), str("ui")],
-            }),
-            attributes: vec![KvCheck {
-                key: str("release"),
-                op: Set::Has.into(),
-                vals: vec![str("stable"), str("canary")],
-            }],
-            action: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("engage"), str("check"), str("read")],
-            }),
-            match_in_actor: vec![],
-            match_in_env: vec![],
-        }),
-        Decide::Allow,
+        PolicyRuleBuilder::new("allow-teamalpha")
+            .desc("Give specific access to members of Team Alpha")
+            .actor(
+                ActorCheckBuilder::new()
+                    .name(StringCheck::OneOf(vec![str("brandy"), str("hank")]))
+                    .typestr(StringCheck::OneOf(vec![str("user")]))
+                    .attribute(KvCheck::Has(str("role"), vec![str("admin")]))
+                    .attribute(KvCheck::HasNot(
+                        str("role"),
+                        vec![str("manager"), str("exec")],
+                    ))
+                    .bucket(NumberCheck::LessThan(50))
+                    .build(),
+            )
+            .env_attribute(KvCheck::Has(str("env"), vec![str("prod")]))
+            .target(
+                TargetCheckBuilder::new()
+                    .name(StringCheck::OneOf(vec![str("launchctl"), str("abortctl")]))
+                    .typestr(StringCheck::OneOf(vec![str("svc"), str("api"), str("ui")]))
+                    .attribute(KvCheck::Has(
+                        str("release"),
+                        vec![str("stable"), str("canary")],
+                    ))
+                    .action(StringCheck::OneOf(vec![
+                        str("engage"),
+                        str("check"),
+                        str("read"),
+                    ]))
+                    .build(),
+            )
+            .build(),
     )
     .await
     .unwrap();
@@ -680,169 +701,153 @@ async fn load_data() {
 
     add_target(
         &mut client,
-        "db1",
-        "database",
-        vec!["read", "write", "update", "delete"],
-        vec![(str("role"), vec!["master"]), (str("schema"), vec!["v20"])],
+        AddTargetRequestBuilder::new("database", "db1")
+            .actions(&["read", "write", "update", "delete"])
+            .attribute("role", &["master"])
+            .attribute("schema", &["v20"])
+            .build(),
     )
     .await
     .expect("Didn't get target");
     add_target(
         &mut client,
-        "db2",
-        "database",
-        vec!["read", "write", "update", "delete"],
-        vec![(str("role"), vec!["replica"]), (str("schema"), vec!["v20"])],
+        AddTargetRequestBuilder::new("database", "db2")
+            .actions(&["read", "write", "update", "delete"])
+            .attribute("role", &["replica"])
+            .attribute("schema", &["v20"])
+            .build(),
     )
     .await
     .expect("Didn't get target");
     add_target(
         &mut client,
-        "www1",
-        "website",
-        vec!["view-users", "create-users", "read-metrics"],
-        vec![(str("region"), vec!["us-west"])],
+        AddTargetRequestBuilder::new("website", "www1")
+            .actions(&["view-users", "create-users", "read-metrics"])
+            .attribute("region", &["us-west"])
+            .build(),
     )
     .await
     .expect("Didn't get target");
     add_target(
         &mut client,
-        "www2",
-        "website",
-        vec!["view-users", "create-users", "read-metrics"],
-        vec![(str("region"), vec!["emea"])],
+        AddTargetRequestBuilder::new("website", "www2")
+            .actions(&["view-users", "create-users", "read-metrics"])
+            .attribute("region", &["emea"])
+            .build(),
     )
     .await
     .expect("Didn't get target");
     add_target(
         &mut client,
-        "login",
-        "website",
-        vec!["view-users", "create-users", "read-metrics"],
-        vec![(str("region"), vec!["anz"])],
+        AddTargetRequestBuilder::new("website", "login")
+            .actions(&["view-users", "create-users", "read-metrics"])
+            .attribute("region", &["anz"])
+            .build(),
     )
     .await
     .expect("Didn't get target");
 
     add_actor(
         &mut client,
-        "sally",
-        "user",
-        vec![
-            (str("team"), vec!["eng"]),
-            (str("office"), vec!["london", "remote"]),
-            (str("clearance"), vec!["secret"]),
-        ],
+        AddActorRequestBuilder::new("user", "sally")
+            .attribute("team", &["eng"])
+            .attribute("office", &["london", "remote"])
+            .attribute("clearance", &["secret"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "kelsey",
-        "user",
-        vec![
-            (str("team"), vec!["eng"]),
-            (str("office"), vec!["nyc"]),
-            (str("clearance"), vec!["secret"]),
-        ],
+        AddActorRequestBuilder::new("user", "kelsey")
+            .attribute("team", &["eng"])
+            .attribute("office", &["nyc"])
+            .attribute("clearance", &["secret"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "john",
-        "user",
-        vec![
-            (str("team"), vec!["eng"]),
-            (str("office"), vec!["sfo"]),
-            (str("clearance"), vec!["none"]),
-        ],
+        AddActorRequestBuilder::new("user", "john")
+            .attribute("team", &["eng"])
+            .attribute("office", &["sfo"])
+            .attribute("clearance", &["none"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "devraj",
-        "user",
-        vec![
-            (str("team"), vec!["eng"]),
-            (str("office"), vec!["sfo"]),
-            (str("clearance"), vec!["none"]),
-        ],
+        AddActorRequestBuilder::new("user", "devraj")
+            .attribute("team", &["eng"])
+            .attribute("office", &["sfo"])
+            .attribute("clearance", &["none"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "marie",
-        "user",
-        vec![
-            (str("team"), vec!["eng"]),
-            (str("office"), vec!["sfo", "remote"]),
-            (str("clearance"), vec!["none"]),
-        ],
+        AddActorRequestBuilder::new("user", "marie")
+            .attribute("team", &["eng"])
+            .attribute("office", &["sfo", "remote"])
+            .attribute("clearance", &["none"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "catie",
-        "user",
-        vec![
-            (str("team"), vec!["ceo", "exec"]),
-            (str("office"), vec!["sfo"]),
-            (str("clearance"), vec!["secret"]),
-        ],
+        AddActorRequestBuilder::new("user", "catie")
+            .attribute("team", &["ceo", "exec"])
+            .attribute("office", &["sfo"])
+            .attribute("clearance", &["secret"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "rose",
-        "user",
-        vec![
-            (str("team"), vec!["exec"]),
-            (str("office"), vec!["sfo"]),
-            (str("clearance"), vec!["none"]),
-        ],
+        AddActorRequestBuilder::new("user", "rose")
+            .attribute("team", &["exec"])
+            .attribute("office", &["sfo"])
+            .attribute("clearance", &["none"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "jack",
-        "user",
-        vec![
-            (str("team"), vec!["ceo"]),
-            (str("office"), vec!["nyc", "remote"]),
-        ],
+        AddActorRequestBuilder::new("user", "jack")
+            .attribute("team", &["ceo"])
+            .attribute("office", &["nyc", "remote"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "logger",
-        "svc",
-        vec![(str("env"), vec!["prod"])],
+        AddActorRequestBuilder::new("svc", "logger")
+            .attribute("env", &["prod"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "launcher",
-        "svc",
-        vec![
-            (str("env"), vec!["dev"]),
-            (str("clearance"), vec!["secret"]),
-        ],
+        AddActorRequestBuilder::new("svc", "launcher")
+            .attribute("env", &["dev"])
+            .attribute("clearance", &["secret"])
+            .build(),
     )
     .await
     .unwrap();
     add_actor(
         &mut client,
-        "printer",
-        "svc",
-        vec![(str("env"), vec!["dev"])],
+        AddActorRequestBuilder::new("svc", "printer")
+            .attribute("env", &["dev"])
+            .build(),
     )
     .await
     .unwrap();
@@ -891,122 +896,97 @@ async fn load_data() {
 
     add_group(
         &mut client,
-        "administrators",
-        Some("Administrators with special privileges"),
-        vec![
-            ("john", "user"),
-            ("adminapi", "svc"),
-            ("kelsey", "user"),
-            ("marie", "user"),
-        ],
-        vec!["admin", "user"],
+        AddGroupRequestBuilder::new("administrators")
+            .desc("Administrators with special privileges")
+            .member("user", "john")
+            .member("svc", "adminapi")
+            .member("user", "kelsey")
+            .member("user", "marie")
+            .roles(&["admin", "user"])
+            .build(),
     )
     .await
     .unwrap();
 
     add_group(
         &mut client,
-        "customers",
-        Some("Beta customers"),
-        vec![
-            ("coke", "authuser"),
-            ("pepsi", "authuser"),
-            ("marie", "user"),
-        ],
-        vec!["user", "guest"],
+        AddGroupRequestBuilder::new("customers")
+            .desc("Beta customers")
+            .member("authuser", "coke")
+            .member("authuser", "pepsi")
+            .member("user", "marie")
+            .roles(&["user", "guest"])
+            .build(),
     )
     .await
     .unwrap();
 
     add_group(
         &mut client,
-        "launchteam",
-        Some("Launch control team"),
-        vec![],
-        vec!["launchmaster"],
+        AddGroupRequestBuilder::new("launchteam")
+            .desc("Launch control team")
+            .roles(&["launchmaster"])
+            .build(),
     )
     .await
     .unwrap();
 
     add_group(
         &mut client,
-        "loadteam",
-        Some("Cargo loading and ramp team"),
-        vec![],
-        vec!["loadmaster"],
+        AddGroupRequestBuilder::new("loadteam")
+            .desc("Cargo loading and ramp team")
+            .roles(&["loadmaster"])
+            .build(),
     )
     .await
     .unwrap();
 
     add_policy(
         &mut client,
-        "allow-everyone",
-        None,
-        None,
-        vec![],
-        None,
-        Decide::Allow,
+        PolicyRuleBuilder::new("allow-everyone").build(),
     )
     .await
     .unwrap();
 
     add_policy(
         &mut client,
-        "allow-teamalpha",
-        Some("Give specific access to members of Team Alpha"),
-        Some(ActorCheck {
-            name: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("john"), str("kelsey"), str("sally")],
-            }),
-            typestr: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("user")],
-            }),
-            attributes: vec![
-                KvCheck {
-                    key: str("role"),
-                    op: Set::Has.into(),
-                    vals: vec![str("admin")],
-                },
-                KvCheck {
-                    key: str("role"),
-                    op: Set::HasNot.into(),
-                    vals: vec![str("manager"), str("exec")],
-                },
-            ],
-            bucket: Some(NumberCheck {
-                op: Num::LessThan.into(),
-                val: 50,
-            }),
-        }),
-        vec![KvCheck {
-            key: str("env"),
-            op: Set::Has.into(),
-            vals: vec![str("prod")],
-        }],
-        Some(TargetCheck {
-            name: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("launchctl"), str("abortctl")],
-            }),
-            typestr: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("svc"), str("api"), str("ui")],
-            }),
-            attributes: vec![KvCheck {
-                key: str("release"),
-                op: Set::Has.into(),
-                vals: vec![str("stable"), str("canary")],
-            }],
-            action: Some(StringCheck {
-                val_cmp: Set::Has.into(),
-                vals: vec![str("engage"), str("check"), str("read")],
-            }),
-            match_in_actor: vec![str("clearance")],
-            match_in_env: vec![str("env")],
-        }),
-        Decide::Allow,
+        PolicyRuleBuilder::new("allow-teamalpha")
+            .desc("Give specific access to members of Team Alpha")
+            .actor(
+                ActorCheckBuilder::new()
+                    .name(StringCheck::OneOf(vec![
+                        str("john"),
+                        str("kelsey"),
+                        str("sally"),
+                    ]))
+                    .typestr(StringCheck::OneOf(vec![str("user")]))
+                    .attribute(KvCheck::Has(str("role"), vec![str("admin")]))
+                    .attribute(KvCheck::HasNot(
+                        str("role"),
+                        vec![str("manager"), str("exec")],
+                    ))
+                    .bucket(NumberCheck::LessThan(50))
+                    .build(),
+            )
+            .env_attribute(KvCheck::Has(str("env"), vec![str("prod")]))
+            .target(
+                TargetCheckBuilder::new()
+                    .name(StringCheck::OneOf(vec![str("launchctl"), str("abortctl")]))
+                    .typestr(StringCheck::OneOf(vec![str("svc"), str("api"), str("ui")]))
+                    .attribute(KvCheck::Has(
+                        str("release"),
+                        vec![str("stable"), str("canary")],
+                    ))
+                    .action(StringCheck::OneOf(vec![
+                        str("engage"),
+                        str("check"),
+                        str("read"),
+                    ]))
+                    .match_in_actor("clearance")
+                    .match_in_env("env")
+                    .build(),
+            )
+            .build(),
     )
     .await
     .unwrap();